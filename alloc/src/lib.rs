@@ -7,10 +7,12 @@ mod chain;
 mod linear;
 mod utils;
 mod virtual_alloc;
+mod virtual_arena;
 
 pub use chain::*;
 pub use linear::*;
 pub use virtual_alloc::*;
+pub use virtual_arena::*;
 
 // Expose allocator_api2 for our users.
 pub use allocator_api2::alloc::*;
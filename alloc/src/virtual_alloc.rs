@@ -28,7 +28,7 @@ fn layout_to_page_size(layout: Layout) -> Result<usize, AllocError> {
 
 #[cfg_attr(debug_assertions, track_caller)]
 #[inline]
-fn pad_to_pow2(num: usize, pow2: usize) -> Option<usize> {
+pub(crate) fn pad_to_pow2(num: usize, pow2: usize) -> Option<usize> {
     debug_assert!(pow2.is_power_of_two());
 
     // Usually, if num is evenly divisible by the pow2, then use that without
@@ -88,6 +88,42 @@ pub mod os {
         validate_page_size!(result)
     }
 
+    /// Reserves `size` bytes of address space, which can't be accessed until
+    /// committed with [commit]. The size must be a multiple of the page size.
+    pub fn reserve(size: usize) -> Result<ptr::NonNull<u8>, AllocError> {
+        let null = ptr::null_mut();
+        let flags = libc::MAP_PRIVATE | libc::MAP_ANON;
+        // SAFETY: these args create a new inaccessible mapping, which doesn't
+        // count as committed memory.
+        let result = unsafe { libc::mmap(null, size, libc::PROT_NONE, flags, -1, 0) };
+        if result == libc::MAP_FAILED {
+            return Err(AllocError);
+        }
+        ptr::NonNull::new(result.cast()).ok_or(AllocError)
+    }
+
+    /// Makes `len` bytes from `ptr` readable and writable.
+    ///
+    /// # Safety
+    /// The range must be page aligned, and within a reservation made by
+    /// [reserve].
+    pub unsafe fn commit(ptr: ptr::NonNull<u8>, len: usize) -> Result<(), AllocError> {
+        let prot = libc::PROT_READ | libc::PROT_WRITE;
+        match libc::mprotect(ptr.as_ptr().cast(), len, prot) {
+            0 => Ok(()),
+            _ => Err(AllocError),
+        }
+    }
+
+    /// Releases a reservation, including its committed memory.
+    ///
+    /// # Safety
+    /// The `ptr` and `size` must be the ones of a reservation made by
+    /// [reserve], which must not be used anymore.
+    pub unsafe fn release(ptr: ptr::NonNull<u8>, size: usize) {
+        _ = libc::munmap(ptr.as_ptr().cast(), size);
+    }
+
     unsafe impl Allocator for VirtualAllocator {
         fn allocate(&self, layout: Layout) -> Result<ptr::NonNull<[u8]>, AllocError> {
             self.allocate_zeroed(layout)
@@ -153,6 +189,41 @@ pub mod os {
         validate_page_size!(system_info.dwPageSize)
     }
 
+    /// Reserves `size` bytes of address space, which can't be accessed until
+    /// committed with [commit]. The size must be a multiple of the page size.
+    pub fn reserve(size: usize) -> Result<ptr::NonNull<u8>, AllocError> {
+        let null = ptr::null_mut();
+        // SAFETY: these args reserve address space without committing it.
+        let result =
+            unsafe { Memory::VirtualAlloc(null, size, Memory::MEM_RESERVE, Memory::PAGE_NOACCESS) };
+        ptr::NonNull::new(result.cast::<u8>()).ok_or(AllocError)
+    }
+
+    /// Makes `len` bytes from `ptr` readable and writable.
+    ///
+    /// # Safety
+    /// The range must be page aligned, and within a reservation made by
+    /// [reserve].
+    pub unsafe fn commit(ptr: ptr::NonNull<u8>, len: usize) -> Result<(), AllocError> {
+        let alloc_type = Memory::MEM_COMMIT;
+        let protection = Memory::PAGE_READWRITE;
+        let result = Memory::VirtualAlloc(ptr.as_ptr() as *const _, len, alloc_type, protection);
+        if result.is_null() {
+            Err(AllocError)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Releases a reservation, including its committed memory.
+    ///
+    /// # Safety
+    /// The `ptr` and `size` must be the ones of a reservation made by
+    /// [reserve], which must not be used anymore.
+    pub unsafe fn release(ptr: ptr::NonNull<u8>, _size: usize) {
+        _ = Memory::VirtualFree(ptr.as_ptr() as *mut _, 0, Memory::MEM_RELEASE);
+    }
+
     unsafe impl Allocator for VirtualAllocator {
         fn allocate(&self, layout: Layout) -> Result<ptr::NonNull<[u8]>, AllocError> {
             self.allocate_zeroed(layout)
@@ -0,0 +1,218 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::virtual_alloc::{os, pad_to_pow2};
+use crate::{AllocError, Allocator};
+use core::alloc::Layout;
+use core::cell::Cell;
+use core::ptr::{slice_from_raw_parts_mut, NonNull};
+
+/// [VirtualArena] is an arena allocator like [crate::LinearAllocator], but
+/// instead of allocating its whole capacity upfront, it reserves address
+/// space for it and only commits the pages as the allocations reach them.
+/// This way, a large capacity can be configured for the worst case without
+/// costing memory to the common, small case.
+///
+/// Once the reservation is used up, allocations begin to fail.
+pub struct VirtualArena {
+    base: NonNull<u8>,
+    page_size: usize,
+    reserved: usize,
+    committed: Cell<usize>,
+    used: Cell<usize>,
+}
+
+unsafe impl Send for VirtualArena {}
+
+impl VirtualArena {
+    /// Pages are committed at least this many bytes at a time, to avoid a
+    /// system call for every page when the arena fills up.
+    const MIN_COMMIT_SIZE: usize = 64 * 1024;
+
+    /// Reserves address space for `capacity` bytes, rounded up to the page
+    /// size. Nothing is committed until the first allocation.
+    pub fn with_capacity(capacity: usize) -> Result<Self, AllocError> {
+        if capacity == 0 {
+            return Err(AllocError);
+        }
+        let page_size = os::page_size()?;
+        let reserved = pad_to_pow2(capacity, page_size).ok_or(AllocError)?;
+        let base = os::reserve(reserved)?;
+        Ok(Self {
+            base,
+            page_size,
+            reserved,
+            committed: Cell::new(0),
+            used: Cell::new(0),
+        })
+    }
+
+    /// Get the number of bytes allocated.
+    #[inline]
+    pub fn used_bytes(&self) -> usize {
+        self.used.get()
+    }
+
+    /// Get the number of bytes committed, which are backed by memory. This
+    /// number is greater than or equal to [Self::used_bytes].
+    #[inline]
+    pub fn committed_bytes(&self) -> usize {
+        self.committed.get()
+    }
+
+    /// Get the number of bytes of address space reserved, which is the most
+    /// the arena can allocate. This number is greater than or equal to
+    /// [Self::committed_bytes].
+    #[inline]
+    pub fn reserved_bytes(&self) -> usize {
+        self.reserved
+    }
+
+    /// Gets the number of bytes that can still be allocated.
+    pub fn remaining_capacity(&self) -> usize {
+        self.reserved_bytes() - self.used_bytes()
+    }
+
+    /// Commits the pages up to `end` bytes into the reservation, growing the
+    /// committed memory at least geometrically.
+    fn commit_up_to(&self, end: usize) -> Result<(), AllocError> {
+        let committed = self.committed.get();
+        if end <= committed {
+            return Ok(());
+        }
+        let target = end
+            .max(committed.saturating_mul(2))
+            .max(Self::MIN_COMMIT_SIZE);
+        let target = pad_to_pow2(target, self.page_size)
+            .ok_or(AllocError)?
+            .min(self.reserved);
+
+        // SAFETY: committed and target are page aligned and within the
+        // reservation.
+        unsafe {
+            let start = NonNull::new_unchecked(self.base.as_ptr().add(committed));
+            os::commit(start, target - committed)?;
+        }
+        self.committed.set(target);
+        Ok(())
+    }
+}
+
+impl Drop for VirtualArena {
+    fn drop(&mut self) {
+        // SAFETY: this is the reservation made in with_capacity, and the
+        // allocations made from it can't outlive the arena.
+        unsafe { os::release(self.base, self.reserved) };
+    }
+}
+
+unsafe impl Allocator for VirtualArena {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() == 0 {
+            return Err(AllocError);
+        }
+
+        // Find the needed allocation size including the necessary alignment.
+        let size = self.used_bytes();
+        // SAFETY: base + size will always be in the reserved range, or be the
+        // legally allowed one-past-the-end.
+        let align_offset = unsafe { self.base.as_ptr().add(size) }.align_offset(layout.align());
+        let needed_size = align_offset.checked_add(layout.size()).ok_or(AllocError)?;
+
+        // Fail if there isn't room.
+        if needed_size > self.remaining_capacity() {
+            return Err(AllocError);
+        }
+        self.commit_up_to(size + needed_size)?;
+
+        // SAFETY: just checked above that base + align_offset + size of the
+        // requested layout fits within the committed range.
+        let thin_ptr = unsafe { self.base.as_ptr().add(size + align_offset) };
+        debug_assert_eq!(0, thin_ptr.align_offset(layout.align()));
+        let wide_ptr = slice_from_raw_parts_mut(thin_ptr, layout.size());
+
+        self.used.set(size + needed_size);
+        // SAFETY: derived from the reservation pointer, so it is inherently
+        // not null.
+        Ok(unsafe { NonNull::new_unchecked(wide_ptr) })
+    }
+
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {
+        // This is an arena. It does batch de-allocation when dropped.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::*;
+
+    #[test]
+    fn fuzz() {
+        const MAX_SIZE: usize = 0x10000000;
+
+        use bolero::TypeGenerator;
+        let capacity = 1..=MAX_SIZE;
+        let align_bits = 0..=12;
+        let size = 0..=MAX_SIZE;
+        let idx = 0..=MAX_SIZE;
+        let val = u8::gen();
+        let allocs = Vec::<(usize, u32, usize, u8)>::gen()
+            .with()
+            .values((size, align_bits, idx, val));
+        bolero::check!()
+            .with_generator((capacity, allocs))
+            .for_each(|(capacity, size_align_vec)| {
+                let allocator = VirtualArena::with_capacity(*capacity).unwrap();
+
+                for (size, align_bits, idx, val) in size_align_vec {
+                    fuzzer_inner_loop(&allocator, *size, *align_bits, *idx, *val, MAX_SIZE)
+                }
+            })
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_commit_on_demand() {
+        const GIB: usize = 1024 * 1024 * 1024;
+        let arena = VirtualArena::with_capacity(GIB).unwrap();
+        assert_eq!(GIB, arena.reserved_bytes());
+        assert_eq!(0, arena.committed_bytes());
+
+        let layout = Layout::new::<[u64; 4]>();
+        let mut ptr = arena.allocate(layout).unwrap();
+        unsafe { ptr.as_mut().fill(1) };
+        assert_eq!(32, arena.used_bytes());
+        let committed = arena.committed_bytes();
+        assert_eq!(
+            VirtualArena::MIN_COMMIT_SIZE.max(arena.page_size),
+            committed
+        );
+
+        // allocations within the committed pages don't commit more
+        let layout = Layout::from_size_align(committed - 32, 1).unwrap();
+        let mut ptr = arena.allocate(layout).unwrap();
+        unsafe { ptr.as_mut().fill(2) };
+        assert_eq!(committed, arena.committed_bytes());
+
+        let ptr = arena.allocate(Layout::new::<u8>()).unwrap();
+        unsafe { ptr.cast::<u8>().as_ptr().write(3) };
+        assert_eq!(2 * committed, arena.committed_bytes());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_capacity_is_a_limit() {
+        let page_size = os::page_size().unwrap();
+        let arena = VirtualArena::with_capacity(1).unwrap();
+        assert_eq!(page_size, arena.reserved_bytes());
+
+        let layout = Layout::from_size_align(page_size, 1).unwrap();
+        arena.allocate(layout).unwrap();
+        assert_eq!(page_size, arena.committed_bytes());
+        assert_eq!(0, arena.remaining_capacity());
+        _ = arena.allocate(Layout::new::<u8>()).unwrap_err();
+
+        _ = VirtualArena::with_capacity(0).unwrap_err();
+    }
+}
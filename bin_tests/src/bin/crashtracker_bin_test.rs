@@ -46,6 +46,7 @@ mod unix {
             Some(ddcommon::Endpoint {
                 url: ddcommon::parse_uri(&output_url)?,
                 api_key: None,
+                ..Default::default()
             })
         };
 
@@ -107,9 +107,17 @@ pub fn generate_header(crate_dir: PathBuf, header_name: &str, output_base_dir: P
         );
     }
 
+    // All the FFI crates share the workspace version, see `ddog_version`.
+    let mut config = Config::from_root_or_default(&crate_dir);
+    let version = env::var("CARGO_PKG_VERSION").expect("CARGO_PKG_VERSION is not set");
+    config.after_includes = Some(format!(
+        "{}\n\n#define DDOG_FFI_VERSION \"{version}\"",
+        config.after_includes.unwrap_or_default()
+    ));
+
     cbindgen::Builder::new()
         .with_crate(crate_dir.clone())
-        .with_config(Config::from_root_or_default(&crate_dir))
+        .with_config(config)
         .generate()
         .expect("Unable to generate bindings")
         .write_to_file(output_path);
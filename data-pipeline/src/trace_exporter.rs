@@ -5,7 +5,7 @@ use bytes::Bytes;
 use datadog_trace_protobuf::pb;
 use datadog_trace_utils::trace_utils::{self, SendData, TracerHeaderTags};
 use datadog_trace_utils::tracer_payload::TraceEncoding;
-use ddcommon::{connector, Endpoint};
+use ddcommon::{connector, process_tags, Endpoint};
 use hyper::http::uri::PathAndQuery;
 use hyper::{Body, Client, Method, Uri};
 use log::error;
@@ -146,7 +146,10 @@ impl TraceExporter {
                     .uri(uri)
                     .header(
                         hyper::header::USER_AGENT,
-                        concat!("Tracer/", env!("CARGO_PKG_VERSION")),
+                        ddcommon::user_agent::user_agent(concat!(
+                            "Tracer/",
+                            env!("CARGO_PKG_VERSION")
+                        )),
                     )
                     .method(Method::POST);
 
@@ -155,7 +158,9 @@ impl TraceExporter {
                 for (key, value) in &headers {
                     req_builder = req_builder.header(*key, value);
                 }
-                req_builder = req_builder
+                req_builder = self
+                    .endpoint
+                    .apply_headers(req_builder)
                     .header("Content-type", "application/msgpack")
                     .header("X-Datadog-Trace-Count", trace_count.to_string().as_str());
                 let req = req_builder
@@ -193,7 +198,7 @@ impl TraceExporter {
     fn send_deser_ser(&self, data: &[u8]) -> Result<String, String> {
         let size = data.len();
         // TODO base on input format
-        let traces: Vec<Vec<pb::Span>> = match rmp_serde::from_slice(data) {
+        let mut traces: Vec<Vec<pb::Span>> = match rmp_serde::from_slice(data) {
             Ok(res) => res,
             Err(err) => {
                 error!("Error deserializing trace from request body: {err}");
@@ -208,6 +213,18 @@ impl TraceExporter {
 
         let header_tags: TracerHeaderTags<'_> = (&self.tags).into();
 
+        if let (TraceExporterOutputFormat::V04, Some(process_tags)) =
+            (&self.output_format, process_tags::get_process_tags())
+        {
+            // v0.4 payloads have no payload level tags, the first span of each chunk carries them
+            for span in traces.iter_mut().filter_map(|trace| trace.first_mut()) {
+                span.meta.insert(
+                    process_tags::PROCESS_TAGS_SPAN_META_KEY.to_string(),
+                    process_tags.to_string(),
+                );
+            }
+        }
+
         match self.output_format {
             TraceExporterOutputFormat::V04 => rmp_serde::to_vec_named(&traces).map_or_else(
                 |err| {
@@ -235,7 +252,13 @@ impl TraceExporter {
                     url: self.output_format.add_path(&self.endpoint.url),
                     ..self.endpoint.clone()
                 };
-                let send_data = SendData::new(size, tracer_payload, header_tags, &endpoint);
+                let mut send_data = SendData::new(size, tracer_payload, header_tags, &endpoint);
+                if let Some(process_tags) = process_tags::get_process_tags() {
+                    send_data.set_agent_payload_tags(HashMap::from([(
+                        process_tags::PROCESS_TAGS_KEY.to_string(),
+                        process_tags.to_string(),
+                    )]));
+                }
                 self.runtime.block_on(async {
                     match send_data.send().await.last_result {
                         Ok(response) => match hyper::body::to_bytes(response.into_body()).await {
@@ -318,6 +341,7 @@ impl TraceExporterBuilder {
                     .as_str(),
             )?,
             api_key: None,
+            ..Default::default()
         };
         let runtime = tokio::runtime::Builder::new_current_thread()
             .enable_all()
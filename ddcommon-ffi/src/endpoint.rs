@@ -10,9 +10,13 @@ use std::str::FromStr;
 #[no_mangle]
 #[must_use]
 pub extern "C" fn ddog_endpoint_from_url(url: crate::CharSlice) -> Option<Box<Endpoint>> {
-    parse_uri(url.to_utf8_lossy().as_ref())
-        .ok()
-        .map(|url| Box::new(Endpoint { url, api_key: None }))
+    parse_uri(url.to_utf8_lossy().as_ref()).ok().map(|url| {
+        Box::new(Endpoint {
+            url,
+            api_key: None,
+            ..Default::default()
+        })
+    })
 }
 
 // We'll just specify the base site here. If api key provided, different intakes need to use their
@@ -25,6 +29,7 @@ pub extern "C" fn ddog_endpoint_from_api_key(api_key: crate::CharSlice) -> Box<E
     Box::new(Endpoint {
         url: hyper::Uri::from_parts(parts).unwrap(),
         api_key: Some(api_key.to_utf8_lossy().to_string().into()),
+        ..Default::default()
     })
 }
 
@@ -45,10 +50,54 @@ pub extern "C" fn ddog_endpoint_from_api_key_and_site(
     *endpoint = Box::into_raw(Box::new(Endpoint {
         url: hyper::Uri::from_parts(parts).unwrap(),
         api_key: Some(api_key.to_utf8_lossy().to_string().into()),
+        ..Default::default()
     }));
     None
 }
 
+fn to_utf8<'a>(slice: &'a crate::CharSlice, what: &str) -> anyhow::Result<&'a str> {
+    slice
+        .try_to_utf8()
+        .map_err(|e| anyhow::anyhow!("The {what} is not valid UTF-8: {e}"))
+}
+
+/// Sends the header `name` with every request to the endpoint, replacing the previous value of
+/// the header, if any, e.g. the authorization expected by a proxy in front of the intake.
+///
+/// Fails if the name or value are not valid UTF-8, or if the header is set by the library itself,
+/// like DD-API-KEY or Content-Type.
+#[no_mangle]
+#[must_use]
+pub extern "C" fn ddog_endpoint_set_header(
+    endpoint: &mut Endpoint,
+    name: crate::CharSlice,
+    value: crate::CharSlice,
+) -> Option<Box<Error>> {
+    let result = (|| {
+        let name = to_utf8(&name, "header name")?.to_string();
+        let value = to_utf8(&value, "header value")?.to_string();
+        endpoint.set_header(name, value)
+    })();
+    match result {
+        Ok(()) => None,
+        Err(e) => Some(Box::new(Error::from(e.to_string()))),
+    }
+}
+
+/// Authenticates every request to the endpoint with an "Authorization: Bearer <token>" header.
+/// Fails if the token is not valid UTF-8.
+#[no_mangle]
+#[must_use]
+pub extern "C" fn ddog_endpoint_set_bearer_token(
+    endpoint: &mut Endpoint,
+    token: crate::CharSlice,
+) -> Option<Box<Error>> {
+    match to_utf8(&token, "token").and_then(|token| endpoint.set_bearer_token(token)) {
+        Ok(()) => None,
+        Err(e) => Some(Box::new(Error::from(e.to_string()))),
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn ddog_endpoint_drop(_: Box<Endpoint>) {}
 
@@ -56,7 +105,8 @@ pub extern "C" fn ddog_endpoint_drop(_: Box<Endpoint>) {}
 mod tests {
     use crate::CharSlice;
 
-    use super::ddog_endpoint_from_url;
+    use super::*;
+    use std::borrow::Cow;
 
     #[test]
     fn test_ddog_endpoint_from_url() {
@@ -75,4 +125,38 @@ mod tests {
             assert_eq!(actual, expected);
         }
     }
+
+    #[test]
+    fn test_ddog_endpoint_set_header() {
+        let mut endpoint =
+            ddog_endpoint_from_url(CharSlice::from("http://localhost:8126")).unwrap();
+        let error = ddog_endpoint_set_bearer_token(&mut endpoint, CharSlice::from("token"));
+        assert!(error.is_none());
+        let error = ddog_endpoint_set_header(
+            &mut endpoint,
+            CharSlice::from("invalid header"),
+            CharSlice::from("value"),
+        );
+        assert!(error.is_some());
+        let error = ddog_endpoint_set_header(
+            &mut endpoint,
+            CharSlice::from("DD-API-KEY"),
+            CharSlice::from("key"),
+        );
+        assert!(error.is_some());
+
+        let bytes = b"tok\xe9n";
+        let invalid_utf8: CharSlice =
+            unsafe { crate::Slice::from_raw_parts(bytes.as_ptr().cast(), bytes.len()) };
+        let error = ddog_endpoint_set_bearer_token(&mut endpoint, invalid_utf8);
+        assert!(error.is_some());
+        let error =
+            ddog_endpoint_set_header(&mut endpoint, CharSlice::from("x-proxy"), invalid_utf8);
+        assert!(error.is_some());
+
+        assert_eq!(
+            endpoint.headers,
+            [(Cow::from("authorization"), Cow::from("Bearer token"))]
+        );
+    }
 }
@@ -3,16 +3,23 @@
 
 mod error;
 
+#[cfg(feature = "endpoint")]
 pub mod endpoint;
 pub mod option;
 pub mod slice;
 pub mod string;
+#[cfg(feature = "endpoint")]
 pub mod tags;
+pub mod timespec;
+#[cfg(feature = "endpoint")]
+pub mod user_agent;
 pub mod vec;
+pub mod version;
 
 pub use error::*;
 pub use string::*;
 
 pub use option::Option;
 pub use slice::{CharSlice, Slice};
+pub use timespec::Timespec;
 pub use vec::Vec;
@@ -0,0 +1,24 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::slice::{AsBytes, CharSlice};
+use ddcommon::user_agent::{set_client_metadata, ClientMetadata};
+
+/// Registers the language and version of the tracer using libdatadog. They are reported in the
+/// User-Agent of the requests made by libdatadog, so this should be called once at startup,
+/// before any exporter or worker is created.
+///
+/// Only the first call has an effect, later calls return false.
+#[no_mangle]
+#[must_use]
+pub extern "C" fn ddog_set_client_metadata(
+    language: CharSlice,
+    language_version: CharSlice,
+    library_version: CharSlice,
+) -> bool {
+    set_client_metadata(ClientMetadata {
+        language: language.to_utf8_lossy().into_owned(),
+        language_version: language_version.to_utf8_lossy().into_owned(),
+        library_version: library_version.to_utf8_lossy().into_owned(),
+    })
+}
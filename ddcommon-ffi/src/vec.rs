@@ -3,7 +3,7 @@
 
 extern crate alloc;
 
-use crate::slice::Slice;
+use crate::slice::{ByteSlice, Slice};
 use core::ops::Deref;
 use std::io::Write;
 use std::marker::PhantomData;
@@ -113,6 +113,38 @@ impl<T> Default for Vec<T> {
     }
 }
 
+#[must_use]
+#[no_mangle]
+pub extern "C" fn ddog_Vec_U8_new() -> Vec<u8> {
+    Vec::default()
+}
+
+/// Copies the bytes of the `slice` into a new Vec.
+///
+/// # Safety
+/// The `slice`'s .ptr must point to a valid object at least as large as its
+/// .len property.
+#[must_use]
+#[no_mangle]
+pub unsafe extern "C" fn ddog_Vec_U8_from_slice(slice: ByteSlice) -> Vec<u8> {
+    Vec::from(slice.as_slice().to_vec())
+}
+
+#[must_use]
+#[no_mangle]
+pub extern "C" fn ddog_Vec_U8_clone(vec: &Vec<u8>) -> Vec<u8> {
+    Vec::from(vec.to_vec())
+}
+
+#[must_use]
+#[no_mangle]
+pub extern "C" fn ddog_Vec_U8_as_slice(vec: &Vec<u8>) -> ByteSlice {
+    vec.as_slice()
+}
+
+#[no_mangle]
+pub extern "C" fn ddog_Vec_U8_drop(_: Vec<u8>) {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -148,6 +180,17 @@ mod tests {
         assert_eq!(second, 2);
     }
 
+    #[test]
+    fn test_u8_ownership() {
+        let bytes = b"pprof".as_slice();
+        let vec = unsafe { ddog_Vec_U8_from_slice(ByteSlice::from(bytes)) };
+        let clone = ddog_Vec_U8_clone(&vec);
+        ddog_Vec_U8_drop(vec);
+        assert_eq!(bytes, ddog_Vec_U8_as_slice(&clone).as_slice());
+        assert_eq!(bytes, alloc::vec::Vec::from(clone));
+        assert!(ddog_Vec_U8_new().is_empty());
+    }
+
     #[test]
     fn test_iter() {
         let vec = vec![0, 2, 4, 6];
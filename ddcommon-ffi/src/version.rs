@@ -0,0 +1,26 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::slice::CharSlice;
+
+/// Returns the version of libdatadog, e.g. "10.0.0". The headers define the version they were
+/// generated for as `DDOG_FFI_VERSION`: bindings should check both are the same when loading the
+/// library, as the ABI may change between versions.
+#[no_mangle]
+pub extern "C" fn ddog_version() -> CharSlice<'static> {
+    CharSlice::from(env!("CARGO_PKG_VERSION"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slice::AsBytes;
+
+    #[test]
+    fn test_version() {
+        assert_eq!(
+            env!("CARGO_PKG_VERSION"),
+            ddog_version().try_to_utf8().unwrap()
+        );
+    }
+}
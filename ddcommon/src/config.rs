@@ -7,10 +7,32 @@ pub mod parse_env {
 
     use crate::parse_uri;
 
+    /// Parses a duration in (fractional) seconds. Values which are not positive, or too large to
+    /// be a [Duration], are ignored with a warning.
     pub fn duration(name: &str) -> Option<Duration> {
-        Some(Duration::from_secs_f32(
-            env::var(name).ok()?.parse::<f32>().ok()?,
-        ))
+        parse_duration(name, false)
+    }
+
+    /// Like [duration], for settings where 0 has a meaning of its own, e.g. disabling a timeout.
+    pub fn duration_or_zero(name: &str) -> Option<Duration> {
+        parse_duration(name, true)
+    }
+
+    fn parse_duration(name: &str, allow_zero: bool) -> Option<Duration> {
+        let value = env::var(name).ok()?;
+        let secs = value.parse::<f32>().ok()?;
+        match Duration::try_from_secs_f32(secs) {
+            Ok(duration) if allow_zero || !duration.is_zero() => Some(duration),
+            _ => {
+                let expected = if allow_zero {
+                    "non-negative"
+                } else {
+                    "positive"
+                };
+                log::warn!("Ignoring {name}={value}, expected a {expected} number of seconds");
+                None
+            }
+        }
     }
 
     pub fn int<T: FromStr>(name: &str) -> Option<T> {
@@ -29,4 +51,34 @@ pub mod parse_env {
     pub fn uri(name: &str) -> Option<Uri> {
         parse_uri(&str_not_empty(name)?).ok()
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_duration() {
+            const NAME: &str = "_DD_TEST_PARSE_ENV_DURATION";
+            assert_eq!(None, duration(NAME));
+            for (value, expected) in [
+                ("2.5", Some(Duration::from_millis(2500))),
+                ("60", Some(Duration::from_secs(60))),
+                ("0", None),
+                ("-1", None),
+                ("NaN", None),
+                ("inf", None),
+                ("1e30", None),
+                ("soon", None),
+            ] {
+                env::set_var(NAME, value);
+                assert_eq!(expected, duration(NAME), "{value}");
+            }
+
+            env::set_var(NAME, "0");
+            assert_eq!(Some(Duration::ZERO), duration_or_zero(NAME));
+            env::set_var(NAME, "-1");
+            assert_eq!(None, duration_or_zero(NAME));
+            env::remove_var(NAME);
+        }
+    }
 }
@@ -36,7 +36,8 @@ pub enum ConnStream {
 
 pub type ConnStreamError = Box<dyn std::error::Error + Send + Sync>;
 
-use hyper::{client::HttpConnector, service::Service};
+use super::TcpConnector;
+use hyper::service::Service;
 impl ConnStream {
     pub async fn from_uds_uri(uri: hyper::Uri) -> Result<ConnStream, ConnStreamError> {
         #[cfg(unix)]
@@ -69,7 +70,7 @@ impl ConnStream {
     }
 
     pub fn from_http_connector_with_uri(
-        c: &mut HttpConnector,
+        c: &mut TcpConnector,
         uri: hyper::Uri,
     ) -> impl Future<Output = Result<ConnStream, ConnStreamError>> {
         c.call(uri).map(|r| match r {
@@ -79,7 +80,7 @@ impl ConnStream {
     }
 
     pub fn from_https_connector_with_uri(
-        c: &mut HttpsConnector<HttpConnector>,
+        c: &mut HttpsConnector<TcpConnector>,
         uri: hyper::Uri,
         require_tls: bool,
     ) -> impl Future<Output = Result<ConnStream, ConnStreamError>> {
@@ -123,8 +124,13 @@ impl hyper::client::connect::Connection for ConnStream {
         match self {
             Self::Tcp { transport } => transport.connected(),
             Self::Tls { transport } => {
-                let (tcp, _) = transport.get_ref();
-                tcp.connected()
+                let (tcp, session) = transport.get_ref();
+                let connected = tcp.connected();
+                if session.alpn_protocol() == Some(b"h2") {
+                    connected.negotiated_h2()
+                } else {
+                    connected
+                }
             }
             #[cfg(unix)]
             Self::Udp { transport: _ } => hyper::client::connect::Connected::new(),
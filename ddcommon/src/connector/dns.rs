@@ -0,0 +1,209 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use futures::future::BoxFuture;
+use futures::{future, FutureExt};
+use hyper::client::connect::dns::{GaiResolver, Name};
+use hyper::service::Service;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use super::timings;
+use crate::config::parse_env;
+
+const ENV_DNS_CACHE_TTL: &str = "DD_DNS_CACHE_TTL";
+const ENV_DNS_CACHE_MAX_STALE: &str = "DD_DNS_CACHE_MAX_STALE";
+const DEFAULT_TTL: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_STALE: Duration = Duration::from_secs(300);
+
+struct CachedAddrs {
+    addrs: Vec<SocketAddr>,
+    resolved_at: Instant,
+    refreshing: bool,
+}
+
+/// A resolver caching the addresses of hosts for a fixed TTL, as the system resolver does not
+/// expose the TTL of the records.
+///
+/// Once the TTL is over, the stale addresses are still used for up to `max_stale` while they are
+/// refreshed in the background, so that uploads don't wait for the lookup. Past that, the lookup
+/// is awaited, but the stale addresses are still used if it fails, e.g. on a transient DNS outage.
+/// A TTL of zero disables the cache.
+#[derive(Clone)]
+pub struct CachingResolver {
+    inner: GaiResolver,
+    cache: Arc<Mutex<HashMap<Name, CachedAddrs>>>,
+    ttl: Duration,
+    max_stale: Duration,
+}
+
+impl Default for CachingResolver {
+    /// The TTL and stale duration can be overridden through the DD_DNS_CACHE_TTL and
+    /// DD_DNS_CACHE_MAX_STALE env vars, in seconds.
+    fn default() -> Self {
+        CachingResolver::new(
+            parse_env::duration_or_zero(ENV_DNS_CACHE_TTL).unwrap_or(DEFAULT_TTL),
+            parse_env::duration_or_zero(ENV_DNS_CACHE_MAX_STALE).unwrap_or(DEFAULT_MAX_STALE),
+        )
+    }
+}
+
+impl CachingResolver {
+    pub fn new(ttl: Duration, max_stale: Duration) -> Self {
+        CachingResolver {
+            inner: GaiResolver::new(),
+            cache: Default::default(),
+            ttl,
+            max_stale,
+        }
+    }
+
+    async fn resolve(&self, name: Name) -> io::Result<Vec<SocketAddr>> {
+        let addrs: Vec<SocketAddr> = self.inner.clone().call(name.clone()).await?.collect();
+        let mut cache = self.cache.lock().unwrap();
+        cache.insert(
+            name,
+            CachedAddrs {
+                addrs: addrs.clone(),
+                resolved_at: Instant::now(),
+                refreshing: false,
+            },
+        );
+        Ok(addrs)
+    }
+
+    fn refresh_in_background(&self, name: Name) {
+        let resolver = self.clone();
+        tokio::spawn(async move {
+            if resolver.resolve(name.clone()).await.is_err() {
+                if let Some(entry) = resolver.cache.lock().unwrap().get_mut(&name) {
+                    entry.refreshing = false;
+                }
+            }
+        });
+    }
+
+    fn lookup(
+        &mut self,
+        name: Name,
+    ) -> BoxFuture<'static, io::Result<std::vec::IntoIter<SocketAddr>>> {
+        if self.ttl.is_zero() {
+            let mut inner = self.inner.clone();
+            return async move { Ok(inner.call(name).await?.collect::<Vec<_>>().into_iter()) }
+                .boxed();
+        }
+
+        let mut cache = self.cache.lock().unwrap();
+        let stale_addrs = match cache.get_mut(&name) {
+            Some(entry) if entry.resolved_at.elapsed() < self.ttl => {
+                return future::ok(entry.addrs.clone().into_iter()).boxed();
+            }
+            Some(entry) if entry.resolved_at.elapsed() < self.ttl + self.max_stale => {
+                if !entry.refreshing {
+                    entry.refreshing = true;
+                    self.refresh_in_background(name);
+                }
+                return future::ok(entry.addrs.clone().into_iter()).boxed();
+            }
+            Some(entry) => Some(entry.addrs.clone()),
+            None => None,
+        };
+        drop(cache);
+
+        let resolver = self.clone();
+        async move {
+            match resolver.resolve(name).await {
+                Ok(addrs) => Ok(addrs.into_iter()),
+                Err(e) => stale_addrs.map(Vec::into_iter).ok_or(e),
+            }
+        }
+        .boxed()
+    }
+}
+
+impl Service<Name> for CachingResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, io::Result<Self::Response>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let lookup = self.lookup(name);
+        async move {
+            let start = Instant::now();
+            let addrs = lookup.await?;
+            let elapsed = start.elapsed();
+            timings::record(|timings| timings.dns = Some(elapsed));
+            Ok(addrs)
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn cached_addrs(resolver: &CachingResolver, name: &Name) -> Option<(Vec<SocketAddr>, bool)> {
+        let cache = resolver.cache.lock().unwrap();
+        cache
+            .get(name)
+            .map(|entry| (entry.addrs.clone(), entry.refreshing))
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_caches_addresses() {
+        let mut resolver = CachingResolver::new(Duration::from_secs(60), Duration::ZERO);
+        let name = Name::from_str("localhost").unwrap();
+
+        let addrs: Vec<_> = resolver.call(name.clone()).await.unwrap().collect();
+        assert!(!addrs.is_empty());
+        assert_eq!(Some((addrs.clone(), false)), cached_addrs(&resolver, &name));
+
+        // served from the cache, even if the entry changed behind the resolver's back
+        let fake_addrs = vec![SocketAddr::from(([10, 0, 0, 1], 0))];
+        resolver.cache.lock().unwrap().get_mut(&name).unwrap().addrs = fake_addrs.clone();
+        let addrs: Vec<_> = resolver.call(name).await.unwrap().collect();
+        assert_eq!(fake_addrs, addrs);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_serves_stale_addresses_while_refreshing() {
+        let mut resolver = CachingResolver::new(Duration::from_millis(1), Duration::from_secs(60));
+        let name = Name::from_str("localhost").unwrap();
+        let fake_addrs = vec![SocketAddr::from(([10, 0, 0, 1], 0))];
+        resolver.cache.lock().unwrap().insert(
+            name.clone(),
+            CachedAddrs {
+                addrs: fake_addrs.clone(),
+                resolved_at: Instant::now() - Duration::from_secs(1),
+                refreshing: false,
+            },
+        );
+
+        let addrs: Vec<_> = resolver.call(name.clone()).await.unwrap().collect();
+        assert_eq!(fake_addrs, addrs);
+
+        // the refresh replaces the stale entry, the lookup itself runs on a blocking thread
+        for _ in 0..100 {
+            match cached_addrs(&resolver, &name) {
+                Some((addrs, false)) if addrs != fake_addrs => return,
+                _ => {
+                    std::thread::sleep(Duration::from_millis(10));
+                    tokio::task::yield_now().await;
+                }
+            }
+        }
+        panic!("stale entry was not refreshed");
+    }
+}
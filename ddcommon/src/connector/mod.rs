@@ -7,9 +7,12 @@ use hyper::client::HttpConnector;
 
 use lazy_static::lazy_static;
 use rustls::ClientConfig;
+use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::{Mutex, PoisonError};
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
 #[cfg(unix)]
 pub mod uds;
@@ -18,17 +21,29 @@ pub mod named_pipe;
 
 pub mod errors;
 
+pub mod dns;
+use dns::CachingResolver;
+
+pub mod timings;
+use timings::TimedConnector;
+
 mod conn_stream;
 use conn_stream::{ConnStream, ConnStreamError};
 
+type TcpConnector = TimedConnector<HttpConnector<CachingResolver>>;
+
+/// The connections to the intake and agent resolve host names through a [`CachingResolver`].
 #[derive(Clone)]
 pub enum Connector {
-    Http(hyper::client::HttpConnector),
-    Https(hyper_rustls::HttpsConnector<hyper::client::HttpConnector>),
+    Http(TcpConnector),
+    Https(hyper_rustls::HttpsConnector<TcpConnector>),
 }
 
 lazy_static! {
     static ref DEFAULT_CONNECTOR: Connector = Connector::new();
+    static ref DEFAULT_HTTP2_CONNECTOR: Connector = Connector::new_with_http2();
+    static ref CONNECT_TIMEOUT_CONNECTORS: Mutex<HashMap<(bool, Duration), Connector>> =
+        Mutex::new(HashMap::new());
 }
 
 impl Default for Connector {
@@ -39,9 +54,35 @@ impl Default for Connector {
 
 impl Connector {
     pub fn new() -> Self {
-        match build_https_connector() {
+        Self::build(false, None)
+    }
+
+    /// Like [`Connector::new`], but additionally offers HTTP/2 via ALPN on TLS connections. The
+    /// client must be built with HTTP/2 support to make use of it.
+    pub fn new_with_http2() -> Self {
+        Self::build(true, None)
+    }
+
+    /// A shared connector offering HTTP/2, see [`Connector::new_with_http2`].
+    pub fn default_with_http2() -> Self {
+        DEFAULT_HTTP2_CONNECTOR.clone()
+    }
+
+    /// A shared connector giving up on TCP connections which are not established within
+    /// `timeout`. It offers HTTP/2 like [`Connector::new_with_http2`] if `enable_http2` is set.
+    pub fn with_connect_timeout(enable_http2: bool, timeout: Duration) -> Self {
+        CONNECT_TIMEOUT_CONNECTORS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry((enable_http2, timeout))
+            .or_insert_with(|| Self::build(enable_http2, Some(timeout)))
+            .clone()
+    }
+
+    fn build(enable_http2: bool, connect_timeout: Option<Duration>) -> Self {
+        match build_https_connector(enable_http2, connect_timeout) {
             Ok(connector) => Connector::Https(connector),
-            Err(_) => Connector::Http(HttpConnector::new()),
+            Err(_) => Connector::Http(build_http_connector(connect_timeout)),
         }
     }
 
@@ -68,18 +109,33 @@ impl Connector {
     }
 }
 
+fn build_http_connector(connect_timeout: Option<Duration>) -> TcpConnector {
+    let mut http_connector = HttpConnector::new_with_resolver(CachingResolver::default());
+    http_connector.set_connect_timeout(connect_timeout);
+    TimedConnector(http_connector)
+}
+
 fn build_https_connector(
-) -> anyhow::Result<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>> {
+    enable_http2: bool,
+    connect_timeout: Option<Duration>,
+) -> anyhow::Result<hyper_rustls::HttpsConnector<TcpConnector>> {
     let certs = load_root_certs()?;
     let client_config = ClientConfig::builder()
         .with_safe_defaults()
         .with_root_certificates(certs)
         .with_no_client_auth();
-    Ok(hyper_rustls::HttpsConnectorBuilder::new()
+    let mut http_connector = build_http_connector(connect_timeout);
+    // TLS is handled by the HttpsConnector
+    http_connector.0.enforce_http(false);
+    let builder = hyper_rustls::HttpsConnectorBuilder::new()
         .with_tls_config(client_config)
         .https_or_http()
-        .enable_http1()
-        .build())
+        .enable_http1();
+    Ok(if enable_http2 {
+        builder.enable_http2().wrap_connector(http_connector)
+    } else {
+        builder.wrap_connector(http_connector)
+    })
 }
 
 fn load_root_certs() -> anyhow::Result<rustls::RootCertStore> {
@@ -107,12 +163,27 @@ impl hyper::service::Service<hyper::Uri> for Connector {
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
 
     fn call(&mut self, uri: hyper::Uri) -> Self::Future {
-        match uri.scheme_str() {
+        let connecting = match uri.scheme_str() {
             Some("unix") => conn_stream::ConnStream::from_uds_uri(uri).boxed(),
             Some("windows") => conn_stream::ConnStream::from_named_pipe_uri(uri).boxed(),
             Some("https") => self.build_conn_stream(uri, true),
             _ => self.build_conn_stream(uri, false),
+        };
+        async move {
+            let start = Instant::now();
+            let stream = connecting.await?;
+            let elapsed = start.elapsed();
+            // The TCP connections are measured by the TimedConnector, the rest is the handshake
+            timings::record(|timings| match stream {
+                ConnStream::Tcp { .. } => {}
+                ConnStream::Tls { .. } => {
+                    timings.tls = Some(elapsed.saturating_sub(timings.total()));
+                }
+                _ => timings.connect = Some(elapsed),
+            });
+            Ok(stream)
         }
+        .boxed()
     }
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
@@ -136,6 +207,8 @@ mod tests {
     /// to be able to use the hyper::Client
     fn test_hyper_client_from_connector() {
         let _: hyper::Client<Connector> = hyper::Client::builder().build(Connector::new());
+        let _: hyper::Client<Connector> =
+            hyper::Client::builder().build(Connector::new_with_http2());
     }
 
     #[tokio::test]
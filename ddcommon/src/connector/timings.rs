@@ -0,0 +1,117 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use hyper::service::Service;
+use std::cell::Cell;
+use std::future::Future;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+tokio::task_local! {
+    static TIMINGS: Cell<ConnectTimings>;
+}
+
+/// The time spent establishing the connection of a request, see [`measure`]. All of them are None
+/// when the request reused a pooled connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConnectTimings {
+    /// Resolving the host name, zero when it was cached. None for IP addresses and sockets.
+    pub dns: Option<Duration>,
+    /// Establishing the TCP connection, or the connection to the unix socket or named pipe.
+    pub connect: Option<Duration>,
+    /// The TLS handshake, None for plain-text connections.
+    pub tls: Option<Duration>,
+}
+
+impl ConnectTimings {
+    /// The time spent establishing the connection in total.
+    pub fn total(&self) -> Duration {
+        [self.dns, self.connect, self.tls]
+            .into_iter()
+            .flatten()
+            .sum()
+    }
+}
+
+/// Runs `future`, returning the timings of the connection it establishes through a
+/// [`super::Connector`]. Connections established in the background, e.g. after the request got
+/// another connection from the pool, are not measured.
+pub async fn measure<F: Future>(future: F) -> (F::Output, ConnectTimings) {
+    TIMINGS
+        .scope(Cell::new(ConnectTimings::default()), async move {
+            let output = future.await;
+            (output, TIMINGS.with(Cell::get))
+        })
+        .await
+}
+
+/// Updates the timings of the current [`measure`], if any.
+pub(crate) fn record(update: impl FnOnce(&mut ConnectTimings)) {
+    _ = TIMINGS.try_with(|timings| {
+        let mut updated = timings.get();
+        update(&mut updated);
+        timings.set(updated);
+    });
+}
+
+/// Records the time its inner connector takes to establish TCP connections, without the name
+/// resolution.
+#[derive(Clone)]
+pub struct TimedConnector<C>(pub(crate) C);
+
+impl<C> Service<hyper::Uri> for TimedConnector<C>
+where
+    C: Service<hyper::Uri>,
+    C::Response: Send,
+    C::Error: Send,
+    C::Future: Send + 'static,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = BoxFuture<'static, Result<C::Response, C::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: hyper::Uri) -> Self::Future {
+        let connecting = self.0.call(uri);
+        async move {
+            let start = Instant::now();
+            let result = connecting.await;
+            let elapsed = start.elapsed();
+            if result.is_ok() {
+                record(|timings| {
+                    timings.connect = Some(elapsed.saturating_sub(timings.dns.unwrap_or_default()))
+                });
+            }
+            result
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_measure() {
+        let ((), timings) = measure(async {
+            record(|timings| timings.dns = Some(Duration::from_millis(2)));
+            record(|timings| timings.connect = Some(Duration::from_millis(3)));
+        })
+        .await;
+        assert_eq!(Some(Duration::from_millis(2)), timings.dns);
+        assert_eq!(None, timings.tls);
+        assert_eq!(Duration::from_millis(5), timings.total());
+
+        // nothing is recorded outside of measure
+        record(|timings| timings.tls = Some(Duration::from_millis(1)));
+        let ((), timings) = measure(async {}).await;
+        assert_eq!(ConnectTimings::default(), timings);
+    }
+}
@@ -4,7 +4,7 @@
 use std::{borrow::Cow, ops::Deref, path::PathBuf, str::FromStr};
 
 use hyper::{
-    header::HeaderValue,
+    header::{HeaderName, HeaderValue},
     http::uri::{self},
 };
 use serde::de::Error;
@@ -16,7 +16,10 @@ pub mod entity_id;
 #[macro_use]
 pub mod cstr;
 pub mod config;
+pub mod process_tags;
 pub mod tag;
+pub mod tasks;
+pub mod user_agent;
 
 pub mod header {
     #![allow(clippy::declare_interior_mutable_const)]
@@ -25,9 +28,23 @@ pub mod header {
     pub const DATADOG_ENTITY_ID: HeaderName = HeaderName::from_static("datadog-entity-id");
     pub const DATADOG_EXTERNAL_ENV: HeaderName = HeaderName::from_static("datadog-external-env");
     pub const DATADOG_API_KEY: HeaderName = HeaderName::from_static("dd-api-key");
+    pub const DATADOG_EVP_ORIGIN: HeaderName = HeaderName::from_static("dd-evp-origin");
+    pub const DATADOG_EVP_ORIGIN_VERSION: HeaderName =
+        HeaderName::from_static("dd-evp-origin-version");
     pub const APPLICATION_JSON: HeaderValue = HeaderValue::from_static("application/json");
 }
 
+/// The headers set by the clients of an [`Endpoint`], which [`Endpoint::set_header`] rejects.
+pub const RESERVED_HEADERS: &[&str] = &[
+    "dd-api-key",
+    "content-type",
+    "content-length",
+    "user-agent",
+    "datadog-container-id",
+    "datadog-entity-id",
+    "datadog-external-env",
+];
+
 pub type HttpClient = hyper::Client<connector::Connector, hyper::Body>;
 pub type HttpResponse = hyper::Response<hyper::Body>;
 pub type HttpRequestBuilder = hyper::http::request::Builder;
@@ -37,6 +54,10 @@ pub struct Endpoint {
     #[serde(serialize_with = "serialize_uri", deserialize_with = "deserialize_uri")]
     pub url: hyper::Uri,
     pub api_key: Option<Cow<'static, str>>,
+    /// Headers sent with every request to the endpoint, e.g. the authorization expected by a
+    /// proxy in front of the intake, see [`Endpoint::set_header`].
+    #[serde(default)]
+    pub headers: Vec<(Cow<'static, str>, Cow<'static, str>)>,
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
@@ -134,19 +155,24 @@ pub fn decode_uri_path_in_authority(uri: &hyper::Uri) -> anyhow::Result<PathBuf>
 
 impl Endpoint {
     /// Return a request builder with the following headers:
-    /// - User agent
+    /// - User agent, built from `user_agent` and the client metadata, see
+    ///   [`user_agent::user_agent`]
     /// - Api key
+    /// - The headers of the endpoint, see [`Endpoint::set_header`]
     /// - Container Id/Entity Id
     pub fn into_request_builder(&self, user_agent: &str) -> anyhow::Result<HttpRequestBuilder> {
-        let mut builder = hyper::Request::builder()
-            .uri(self.url.clone())
-            .header(hyper::header::USER_AGENT, user_agent);
+        let mut builder = hyper::Request::builder().uri(self.url.clone()).header(
+            hyper::header::USER_AGENT,
+            user_agent::user_agent(user_agent),
+        );
 
         // Add the Api key header if available
         if let Some(api_key) = &self.api_key {
             builder = builder.header(header::DATADOG_API_KEY, HeaderValue::from_str(api_key)?);
         }
 
+        builder = self.apply_headers(builder);
+
         // Add the Container Id header if available
         if let Some(container_id) = entity_id::get_container_id() {
             builder = builder.header(header::DATADOG_CONTAINER_ID, container_id);
@@ -164,4 +190,71 @@ impl Endpoint {
 
         Ok(builder)
     }
+
+    /// Adds the headers of the endpoint, see [`Endpoint::set_header`], to `builder`. Clients
+    /// which don't use [`Endpoint::into_request_builder`] must call this instead.
+    pub fn apply_headers(&self, mut builder: HttpRequestBuilder) -> HttpRequestBuilder {
+        for (name, value) in &self.headers {
+            builder = builder.header(&**name, &**value);
+        }
+        builder
+    }
+
+    /// Sends the header `name` with every request to the endpoint, replacing the previous value
+    /// of the header, if any. This is meant for static authentication, e.g. of a proxy in front of
+    /// the intake, and is applied by all clients of the endpoint.
+    ///
+    /// The headers set by the clients themselves, like the api key or the content type, can't be
+    /// overridden, see [`RESERVED_HEADERS`].
+    pub fn set_header<N, V>(&mut self, name: N, value: V) -> anyhow::Result<()>
+    where
+        N: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+    {
+        let (name, value) = (name.into(), value.into());
+        let header_name = HeaderName::from_str(&name)?;
+        anyhow::ensure!(
+            !RESERVED_HEADERS.contains(&header_name.as_str()),
+            "The {name} header is reserved and can't be set on the endpoint"
+        );
+        HeaderValue::from_str(&value)?;
+        self.headers
+            .retain(|(existing, _)| !existing.eq_ignore_ascii_case(&name));
+        self.headers.push((name, value));
+        Ok(())
+    }
+
+    /// Authenticates every request to the endpoint with an "Authorization: Bearer `token`" header.
+    pub fn set_bearer_token(&mut self, token: &str) -> anyhow::Result<()> {
+        self.set_header("authorization", format!("Bearer {token}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint_headers() {
+        let mut endpoint = Endpoint {
+            url: parse_uri("http://localhost:8126").unwrap(),
+            ..Default::default()
+        };
+        endpoint.set_header("X-Proxy-Tenant", "first").unwrap();
+        endpoint.set_header("x-proxy-tenant", "second").unwrap();
+        endpoint.set_bearer_token("token").unwrap();
+        assert!(endpoint.set_header("invalid header", "value").is_err());
+        assert!(endpoint.set_bearer_token("invalid\ntoken").is_err());
+        assert!(endpoint.set_header("DD-API-KEY", "key").is_err());
+        assert!(endpoint.set_header("Content-Type", "text/plain").is_err());
+
+        let request = endpoint
+            .into_request_builder("test")
+            .unwrap()
+            .body(())
+            .unwrap();
+        let tenants: Vec<_> = request.headers().get_all("x-proxy-tenant").iter().collect();
+        assert_eq!(tenants, ["second"]);
+        assert_eq!(request.headers()["authorization"], "Bearer token");
+    }
 }
@@ -0,0 +1,189 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tags describing the process, sent along with its profiles and traces so that the data of a
+//! fleet can be correlated by executable and namespace.
+
+use crate::config::parse_env;
+use crate::tag::Tag;
+use lazy_static::lazy_static;
+use std::path::PathBuf;
+
+/// Enables the collection of the process tags.
+pub const PROCESS_TAGS_ENABLED_ENV: &str = "DD_EXPERIMENTAL_PROPAGATE_PROCESS_TAGS_ENABLED";
+
+/// The key of the process tags in the profile event and the `AgentPayload` tags.
+pub const PROCESS_TAGS_KEY: &str = "process_tags";
+
+/// The meta key of the process tags on the first span of the trace chunks, for the payloads which
+/// have no `AgentPayload` tags, like v0.4 traces.
+pub const PROCESS_TAGS_SPAN_META_KEY: &str = "_dd.tags.process";
+
+const REDACTED: &str = "********";
+
+/// Information about a process, see [`ProcessInfo::current`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ProcessInfo {
+    pub exe_path: Option<PathBuf>,
+    /// The command line, with the values of its arguments redacted.
+    pub cmdline: Vec<String>,
+    /// The inode of the pid namespace of the process, on Linux.
+    pub pid_namespace: Option<u64>,
+}
+
+impl ProcessInfo {
+    /// Collects the information of the current process.
+    pub fn current() -> ProcessInfo {
+        ProcessInfo {
+            exe_path: std::env::current_exe().ok(),
+            cmdline: sanitize_cmdline(
+                std::env::args_os().map(|arg| arg.to_string_lossy().into_owned()),
+            ),
+            pid_namespace: pid_namespace(),
+        }
+    }
+
+    pub fn tags(&self) -> Vec<Tag> {
+        let mut tags = vec![];
+        if let Some(exe_path) = &self.exe_path {
+            if let Some(name) = exe_path.file_name() {
+                tags.extend(Tag::new("entrypoint.name", name.to_string_lossy()));
+            }
+            tags.extend(Tag::new("process.executable", exe_path.to_string_lossy()));
+        }
+        if !self.cmdline.is_empty() {
+            tags.extend(Tag::new("process.command_line", self.cmdline.join(" ")));
+        }
+        if let Some(pid_namespace) = self.pid_namespace {
+            tags.extend(Tag::new("process.pid_namespace", pid_namespace.to_string()));
+        }
+        tags
+    }
+
+    /// The tags as a single comma separated string, the format of the `process_tags` entry.
+    /// Commas within the values are replaced, as they would split the tags.
+    pub fn process_tags(&self) -> String {
+        self.tags()
+            .iter()
+            .map(|tag| tag.as_ref().replace(',', "_"))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// Returns the process tags of the current process, or None if they are not enabled with
+/// `DD_EXPERIMENTAL_PROPAGATE_PROCESS_TAGS_ENABLED`.
+pub fn get_process_tags() -> Option<&'static str> {
+    // the process doesn't change, compute the tags only once
+    lazy_static! {
+        static ref PROCESS_TAGS: Option<String> = parse_env::bool(PROCESS_TAGS_ENABLED_ENV)
+            .unwrap_or(false)
+            .then(|| ProcessInfo::current().process_tags())
+            .filter(|tags| !tags.is_empty());
+    }
+    PROCESS_TAGS.as_deref()
+}
+
+/// Redacts the values of the arguments, passed either as `name=value` or as `--name value`, as
+/// there is no telling which ones are secrets. The executable and the other arguments are kept.
+fn sanitize_cmdline(mut args: impl Iterator<Item = String>) -> Vec<String> {
+    let mut cmdline: Vec<String> = args.next().into_iter().collect();
+    let mut redact_next = false;
+    for arg in args {
+        let is_flag = arg.starts_with('-') && arg != "-" && arg != "--";
+        if std::mem::take(&mut redact_next) && !is_flag {
+            cmdline.push(REDACTED.to_string());
+            continue;
+        }
+        match arg.split_once('=') {
+            Some((name, _)) => cmdline.push(format!("{name}={REDACTED}")),
+            None => {
+                redact_next = is_flag;
+                cmdline.push(arg);
+            }
+        }
+    }
+    cmdline
+}
+
+#[cfg(target_os = "linux")]
+fn pid_namespace() -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata("/proc/self/ns/pid")
+        .ok()
+        .map(|metadata| metadata.ino())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pid_namespace() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> + '_ {
+        args.iter().map(|arg| arg.to_string())
+    }
+
+    #[test]
+    fn test_sanitize_cmdline() {
+        assert_eq!(
+            vec![
+                "/usr/bin/env=1",
+                "FOO=********",
+                "php",
+                "--api-key=********",
+                "--password",
+                "********",
+                "-v",
+                "--secret",
+                "-d",
+                "********",
+                "--",
+                "script.php"
+            ],
+            sanitize_cmdline(args(&[
+                "/usr/bin/env=1",
+                "FOO=bar",
+                "php",
+                "--api-key=1234",
+                "--password",
+                "hunter2",
+                "-v",
+                "--secret",
+                "-d",
+                "memory_limit",
+                "--",
+                "script.php"
+            ]))
+        );
+        assert!(sanitize_cmdline(args(&[])).is_empty());
+    }
+
+    #[test]
+    fn test_process_tags() {
+        let info = ProcessInfo {
+            exe_path: Some(PathBuf::from("/usr/bin/php")),
+            cmdline: vec!["php".to_string(), "a,b.php".to_string()],
+            pid_namespace: Some(4026531836),
+        };
+        assert_eq!(
+            "entrypoint.name:php,process.executable:/usr/bin/php,\
+             process.command_line:php a_b.php,process.pid_namespace:4026531836",
+            info.process_tags()
+        );
+        assert_eq!("", ProcessInfo::default().process_tags());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_current() {
+        let info = ProcessInfo::current();
+        assert!(info.exe_path.is_some());
+        assert!(!info.cmdline.is_empty());
+        #[cfg(target_os = "linux")]
+        assert!(info.pid_namespace.is_some());
+    }
+}
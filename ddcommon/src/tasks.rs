@@ -0,0 +1,251 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! Supervision of the background tasks of a process, so that they are shut down in a
+//! deterministic order when it exits.
+
+use futures::FutureExt;
+use std::any::Any;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+pub type TaskName = Cow<'static, str>;
+
+/// Handed to supervised tasks, telling them when to shut down.
+#[derive(Clone)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    pub fn is_shutting_down(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once the task is asked to shut down. It should then finish its pending work and
+    /// return.
+    pub async fn wait(&mut self) {
+        while !*self.0.borrow_and_update() {
+            if self.0.changed().await.is_err() {
+                // the supervisor is gone
+                return;
+            }
+        }
+    }
+}
+
+/// What happened to the tasks while they were shut down.
+#[derive(Debug, Default)]
+pub struct ShutdownReport {
+    /// The tasks which did not finish within the drain timeout, and were aborted.
+    pub aborted: Vec<TaskName>,
+    /// The tasks which panicked, with their panic message.
+    pub panicked: Vec<(TaskName, String)>,
+}
+
+struct SupervisedTask {
+    name: TaskName,
+    stage: u32,
+    handle: JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct State {
+    tasks: Vec<SupervisedTask>,
+    stages: BTreeMap<u32, watch::Sender<bool>>,
+    panicked: Vec<(TaskName, String)>,
+}
+
+struct Inner {
+    runtime: Handle,
+    state: Mutex<State>,
+}
+
+/// Spawns named background tasks on a shared runtime and shuts them down stage by stage.
+///
+/// Each task belongs to a shutdown stage: [`TaskSupervisor::shutdown`] signals the tasks of the
+/// lowest stage first and waits for them to finish, before moving on to the next stage. E.g.
+/// request handling can be stopped before the uploaders flush what it produced. Panics are caught
+/// and logged with the name of the task, instead of silently ending it.
+#[derive(Clone)]
+pub struct TaskSupervisor {
+    inner: Arc<Inner>,
+}
+
+impl Default for TaskSupervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+impl TaskSupervisor {
+    /// Creates a supervisor spawning the tasks on the current runtime.
+    ///
+    /// # Panics
+    /// If called outside of a tokio runtime.
+    pub fn new() -> Self {
+        Self::with_handle(Handle::current())
+    }
+
+    /// Creates a supervisor spawning the tasks on the given runtime, so that tasks can be spawned
+    /// from threads not managed by a runtime.
+    pub fn with_handle(runtime: Handle) -> Self {
+        TaskSupervisor {
+            inner: Arc::new(Inner {
+                runtime,
+                state: Mutex::new(State::default()),
+            }),
+        }
+    }
+
+    /// Spawns the future returned by `task`, which is given the signal telling it to shut down.
+    /// Tasks spawned into a stage which is already shut down are signaled right away.
+    pub fn spawn<F, Fut>(&self, name: impl Into<TaskName>, stage: u32, task: F)
+    where
+        F: FnOnce(ShutdownSignal) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let name = name.into();
+        let signal = self
+            .inner
+            .state
+            .lock()
+            .unwrap()
+            .stages
+            .entry(stage)
+            .or_insert_with(|| watch::channel(false).0)
+            .subscribe();
+        let future = AssertUnwindSafe(task(ShutdownSignal(signal))).catch_unwind();
+
+        let inner = Arc::downgrade(&self.inner);
+        let task_name = name.clone();
+        let handle = self.inner.runtime.spawn(async move {
+            if let Err(panic) = future.await {
+                let message = panic_message(&*panic);
+                log::error!("Task {task_name} panicked: {message}");
+                if let Some(inner) = Weak::upgrade(&inner) {
+                    let mut state = inner.state.lock().unwrap();
+                    state.panicked.push((task_name, message));
+                }
+            }
+        });
+
+        let mut state = self.inner.state.lock().unwrap();
+        // don't accumulate the tasks which are done
+        state.tasks.retain(|task| !task.handle.is_finished());
+        state.tasks.push(SupervisedTask {
+            name,
+            stage,
+            handle,
+        });
+    }
+
+    /// Returns the number of tasks which are still running.
+    pub fn running_tasks(&self) -> usize {
+        let state = self.inner.state.lock().unwrap();
+        state
+            .tasks
+            .iter()
+            .filter(|task| !task.handle.is_finished())
+            .count()
+    }
+
+    /// Shuts the tasks down by increasing stage. The tasks of each stage are signaled, then given
+    /// up to `drain_timeout` to finish, before the remaining ones are aborted.
+    pub async fn shutdown(&self, drain_timeout: Duration) -> ShutdownReport {
+        let mut report = ShutdownReport::default();
+        loop {
+            // tasks may be spawned while shutting down, hence picking the next stage every time
+            let tasks = {
+                let mut state = self.inner.state.lock().unwrap();
+                let Some(stage) = state.tasks.iter().map(|task| task.stage).min() else {
+                    break;
+                };
+                if let Some(shutdown) = state.stages.get(&stage) {
+                    shutdown.send_replace(true);
+                }
+                let (tasks, others) = std::mem::take(&mut state.tasks)
+                    .into_iter()
+                    .partition::<Vec<_>, _>(|task| task.stage == stage);
+                state.tasks = others;
+                tasks
+            };
+
+            let deadline = tokio::time::Instant::now() + drain_timeout;
+            for mut task in tasks {
+                if tokio::time::timeout_at(deadline, &mut task.handle)
+                    .await
+                    .is_err()
+                {
+                    log::warn!("Task {} did not shut down in time, aborting it", task.name);
+                    task.handle.abort();
+                    report.aborted.push(task.name);
+                }
+            }
+        }
+
+        let mut state = self.inner.state.lock().unwrap();
+        report.panicked = std::mem::take(&mut state.panicked);
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_shutdown_order() {
+        let supervisor = TaskSupervisor::new();
+        let order = Arc::new(Mutex::new(vec![]));
+        for (name, stage) in [("uploader", 1), ("listener", 0), ("telemetry", 2)] {
+            let order = order.clone();
+            supervisor.spawn(name, stage, move |mut shutdown| async move {
+                shutdown.wait().await;
+                order.lock().unwrap().push(name);
+            });
+        }
+        assert_eq!(3, supervisor.running_tasks());
+
+        let report = supervisor.shutdown(Duration::from_secs(5)).await;
+        assert!(report.aborted.is_empty());
+        assert!(report.panicked.is_empty());
+        assert_eq!(
+            vec!["listener", "uploader", "telemetry"],
+            *order.lock().unwrap()
+        );
+        assert_eq!(0, supervisor.running_tasks());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_drain_timeout_and_panics() {
+        let supervisor = TaskSupervisor::new();
+        supervisor.spawn("stuck", 0, |_| futures::future::pending());
+        supervisor.spawn("panicking", 0, |_| async { panic!("boom") });
+        tokio::task::yield_now().await;
+
+        let report = supervisor.shutdown(Duration::from_millis(10)).await;
+        assert_eq!(vec![TaskName::from("stuck")], report.aborted);
+        assert_eq!(
+            vec![(TaskName::from("panicking"), "boom".to_string())],
+            report.panicked
+        );
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! Standard User-Agent and client metadata headers, so that all the requests made by libdatadog
+//! identify the tracer they originate from the same way.
+
+use crate::{header, HttpRequestBuilder};
+use std::sync::OnceLock;
+
+/// Describes the tracer (or other library) using libdatadog.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClientMetadata {
+    /// The language of the tracer, e.g. "php".
+    pub language: String,
+    /// The version of the language runtime.
+    pub language_version: String,
+    /// The version of the tracer itself.
+    pub library_version: String,
+}
+
+static CLIENT_METADATA: OnceLock<ClientMetadata> = OnceLock::new();
+
+/// Registers the metadata of the tracer, once per process. Returns false if it was already set.
+pub fn set_client_metadata(metadata: ClientMetadata) -> bool {
+    CLIENT_METADATA.set(metadata).is_ok()
+}
+
+pub fn client_metadata() -> Option<&'static ClientMetadata> {
+    CLIENT_METADATA.get()
+}
+
+/// Builds the User-Agent for the libdatadog `component` (e.g.
+/// `concat!("DDProf/", env!("CARGO_PKG_VERSION"))`), followed by the client metadata if it was
+/// set, e.g. "DDProf/7.0.0 (php 8.3.1; tracer 1.0.0)".
+pub fn user_agent(component: &str) -> String {
+    format_user_agent(component, client_metadata())
+}
+
+fn format_user_agent(component: &str, metadata: Option<&ClientMetadata>) -> String {
+    match metadata {
+        Some(metadata) => format!(
+            "{component} ({} {}; tracer {})",
+            metadata.language, metadata.language_version, metadata.library_version
+        ),
+        None => component.to_string(),
+    }
+}
+
+/// Adds the DD-EVP-ORIGIN and DD-EVP-ORIGIN-VERSION headers, identifying the library sending the
+/// data to an EVP intake.
+pub fn with_evp_origin(
+    builder: HttpRequestBuilder,
+    origin: &str,
+    origin_version: &str,
+) -> HttpRequestBuilder {
+    builder
+        .header(header::DATADOG_EVP_ORIGIN, origin)
+        .header(header::DATADOG_EVP_ORIGIN_VERSION, origin_version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_agent() {
+        assert_eq!("DDProf/1.0.0", format_user_agent("DDProf/1.0.0", None));
+
+        let metadata = ClientMetadata {
+            language: "php".to_string(),
+            language_version: "8.3.1".to_string(),
+            library_version: "0.99.0".to_string(),
+        };
+        assert_eq!(
+            "DDProf/1.0.0 (php 8.3.1; tracer 0.99.0)",
+            format_user_agent("DDProf/1.0.0", Some(&metadata))
+        );
+    }
+
+    #[test]
+    fn test_evp_origin() {
+        let request = with_evp_origin(hyper::Request::builder(), "dd-trace-php", "0.99.0")
+            .body(())
+            .unwrap();
+        assert_eq!("dd-trace-php", request.headers()["dd-evp-origin"]);
+        assert_eq!("0.99.0", request.headers()["dd-evp-origin-version"]);
+    }
+}
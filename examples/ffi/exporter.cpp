@@ -98,6 +98,23 @@ int main(int argc, char *argv[]) {
 
   ddog_prof_EncodedProfile *encoded_profile = &serialize_result.ok;
 
+  ddog_ByteSlice profile_buffer;
+  ddog_Timespec start, end;
+  ddog_prof_Profile_Result get_result =
+      ddog_prof_EncodedProfile_get_buffer_slice(encoded_profile, &profile_buffer);
+  if (get_result.tag == DDOG_PROF_PROFILE_RESULT_OK) {
+    get_result = ddog_prof_EncodedProfile_get_start(encoded_profile, &start);
+  }
+  if (get_result.tag == DDOG_PROF_PROFILE_RESULT_OK) {
+    get_result = ddog_prof_EncodedProfile_get_end(encoded_profile, &end);
+  }
+  if (get_result.tag == DDOG_PROF_PROFILE_RESULT_ERR) {
+    print_error("Failed to read encoded profile: ", get_result.err);
+    ddog_Error_drop(&get_result.err);
+    ddog_prof_EncodedProfile_drop(encoded_profile);
+    return 1;
+  }
+
   ddog_prof_Endpoint endpoint =
       ddog_prof_Endpoint_agentless(DDOG_CHARSLICE_C_BARE("datad0g.com"), to_slice_c_char(api_key));
 
@@ -125,7 +142,7 @@ int main(int argc, char *argv[]) {
 
   ddog_prof_Exporter_File files_to_compress_and_export_[] = {{
       .name = DDOG_CHARSLICE_C_BARE("auto.pprof"),
-      .file = ddog_Vec_U8_as_slice(&encoded_profile->buffer),
+      .file = profile_buffer,
   }};
   ddog_prof_Exporter_Slice_File files_to_compress_and_export = {
       .ptr = files_to_compress_and_export_,
@@ -141,7 +158,7 @@ int main(int argc, char *argv[]) {
       "{\"application\": {\"start_time\": \"2024-01-24T11:17:22+0000\"}, \"platform\": {\"kernel\": \"Darwin Kernel 22.5.0\"}}");
 
   ddog_prof_Exporter_Request_BuildResult build_result = ddog_prof_Exporter_Request_build(
-      exporter, encoded_profile->start, encoded_profile->end, files_to_compress_and_export,
+      exporter, start, end, files_to_compress_and_export,
       files_to_export_unmodified, nullptr, nullptr, &internal_metadata_example, &info_example,
       30000);
   ddog_prof_EncodedProfile_drop(encoded_profile);
@@ -54,9 +54,12 @@ pub fn impl_transfer_handles(_attr: TokenStream, input: TokenStream) -> TokenStr
                                 colon_token: None,
                                 pat: Box::new(parse_quote! { #ident }),
                             });
-                            stmts_move.push(
-                                parse_quote! { __transport.move_handle(#ident.clone().into())?; },
-                            );
+                            stmts_move.push(parse_quote! {
+                                datadog_ipc::handles::TransferHandles::move_handles(
+                                    #ident,
+                                    __transport,
+                                )?;
+                            });
                             stmts_recv.push(parse_quote! { #ident.receive_handles(__transport)?; });
                         }
                     }
@@ -221,6 +221,31 @@ mod tests {
         assert_eq!(mapped.as_slice(), exp.as_slice());
     }
 
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_sealed_anon_shm() {
+        let mut mapped = ShmHandle::new(5).unwrap().map().unwrap();
+        _ = mapped.as_slice_mut().write(&[1, 2, 3, 4, 5]).unwrap();
+        let shm: ShmHandle = mapped.into();
+        shm.seal().unwrap();
+
+        let mapped = shm.clone().map().unwrap();
+        assert_eq!(&[1, 2, 3, 4, 5], &mapped.as_slice()[..5]);
+
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::prelude::AsRawFd;
+            // writing through the mapping of the sealed memory doesn't change it
+            let mut mapped = mapped;
+            mapped.as_slice_mut()[0] = 0;
+            assert_eq!(&[1, 2, 3, 4, 5], &shm.map().unwrap().as_slice()[..5]);
+
+            let fd = mapped.mem.handle.as_raw_fd();
+            assert_eq!(-1, unsafe { libc::write(fd, [0u8].as_ptr().cast(), 1) });
+            assert_eq!(-1, unsafe { libc::ftruncate(fd, 1) });
+        }
+    }
+
     #[test]
     #[cfg_attr(miri, ignore)]
     fn test_named_shm() {
@@ -8,6 +8,8 @@ mod windows;
 
 mod mem_handle;
 pub use mem_handle::*;
+mod payload;
+pub use payload::*;
 mod platform_handle;
 pub use platform_handle::*;
 
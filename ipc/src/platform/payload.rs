@@ -0,0 +1,135 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::handles::{HandlesTransport, TransferHandles};
+use crate::platform::{FileBackedHandle, MappedMem, ShmHandle};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::ops::Deref;
+
+/// Payloads at least this large are passed through shared memory.
+pub const LARGE_PAYLOAD_THRESHOLD: usize = 64 * 1024;
+
+/// Bytes to be sent over IPC, e.g. a trace blob.
+///
+/// Payloads of at least [`LARGE_PAYLOAD_THRESHOLD`] bytes are copied once into an anonymous shared
+/// memory segment, whose handle is transferred alongside the message. The receiver then maps it,
+/// instead of the data being copied through the socket and the deserializer.
+///
+/// Interface methods taking a `LargePayload` must flag it with `#[SerializedHandle]`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum LargePayload {
+    Inline(Vec<u8>),
+    Shared { handle: ShmHandle, len: usize },
+}
+
+impl LargePayload {
+    pub fn len(&self) -> usize {
+        match self {
+            LargePayload::Inline(data) => data.len(),
+            LargePayload::Shared { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Gives access to the received bytes, mapping the shared memory if needed.
+    pub fn map(self) -> io::Result<MappedPayload> {
+        Ok(match self {
+            LargePayload::Inline(data) => MappedPayload::Inline(data),
+            LargePayload::Shared { handle, len } => MappedPayload::Shared {
+                mapped: handle.map()?,
+                len,
+            },
+        })
+    }
+
+    fn share(data: &[u8]) -> anyhow::Result<LargePayload> {
+        let mut mapped = ShmHandle::new(data.len())?.map()?;
+        mapped.as_slice_mut()[..data.len()].copy_from_slice(data);
+        Ok(LargePayload::Shared {
+            handle: mapped.into(),
+            len: data.len(),
+        })
+    }
+}
+
+impl From<Vec<u8>> for LargePayload {
+    /// Moves the data into shared memory if it is large enough. Failing to allocate the shared
+    /// memory falls back to sending it inline.
+    fn from(data: Vec<u8>) -> Self {
+        if data.len() >= LARGE_PAYLOAD_THRESHOLD {
+            if let Ok(payload) = LargePayload::share(&data) {
+                return payload;
+            }
+        }
+        LargePayload::Inline(data)
+    }
+}
+
+impl TransferHandles for LargePayload {
+    fn move_handles<Transport: HandlesTransport>(
+        &self,
+        transport: Transport,
+    ) -> Result<(), Transport::Error> {
+        match self {
+            LargePayload::Inline(_) => Ok(()),
+            LargePayload::Shared { handle, .. } => handle.move_handles(transport),
+        }
+    }
+
+    fn receive_handles<Transport: HandlesTransport>(
+        &mut self,
+        transport: Transport,
+    ) -> Result<(), Transport::Error> {
+        match self {
+            LargePayload::Inline(_) => Ok(()),
+            LargePayload::Shared { handle, .. } => handle.receive_handles(transport),
+        }
+    }
+}
+
+/// The bytes of a received [`LargePayload`].
+pub enum MappedPayload {
+    Inline(Vec<u8>),
+    Shared {
+        mapped: MappedMem<ShmHandle>,
+        len: usize,
+    },
+}
+
+impl Deref for MappedPayload {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            MappedPayload::Inline(data) => data,
+            MappedPayload::Shared { mapped, len } => &mapped.as_slice()[..*len],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LargePayload, LARGE_PAYLOAD_THRESHOLD};
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_small_payload_is_inline() {
+        let payload = LargePayload::from(vec![1, 2, 3]);
+        assert!(matches!(payload, LargePayload::Inline(_)));
+        assert_eq!(&[1, 2, 3], &*payload.map().unwrap());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_large_payload_is_shared() {
+        let data: Vec<u8> = (0..LARGE_PAYLOAD_THRESHOLD + 5).map(|i| i as u8).collect();
+        let payload = LargePayload::from(data.clone());
+        assert!(matches!(payload, LargePayload::Shared { .. }));
+        assert_eq!(data.len(), payload.len());
+        assert_eq!(data.as_slice(), &*payload.map().unwrap());
+    }
+}
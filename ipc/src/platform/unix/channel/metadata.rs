@@ -80,6 +80,14 @@ impl ChannelMetadata {
     }
 
     pub(crate) fn receive_fds(&mut self, fds: &[RawFd]) {
+        // Linux receives the fds with MSG_CMSG_CLOEXEC, other platforms like macOS have no such
+        // flag, so they need to be marked explicitly to not leak into spawned processes
+        #[cfg(not(target_os = "linux"))]
+        for fd in fds {
+            unsafe {
+                libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC);
+            }
+        }
         self.fds_received.append(&mut fds.to_vec().into());
     }
 
@@ -21,13 +21,20 @@ use std::sync::atomic::{AtomicI32, Ordering};
 
 pub(crate) fn mmap_handle<T: FileBackedHandle>(handle: T) -> io::Result<MappedMem<T>> {
     let fd: RawFd = handle.get_shm().handle.as_raw_fd();
+    // A writable shared mapping of memory sealed with ShmHandle::seal is refused. Writes to a
+    // private mapping don't reach the memory, so mapping it privately keeps the seal intact.
+    let flags = if is_write_sealed(fd) {
+        MapFlags::MAP_PRIVATE
+    } else {
+        MapFlags::MAP_SHARED
+    };
     Ok(MappedMem {
         ptr: unsafe {
             mmap(
                 None,
                 NonZeroUsize::new(handle.get_shm().size).unwrap(),
                 ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
-                MapFlags::MAP_SHARED,
+                flags,
                 fd,
                 0,
             )?
@@ -36,6 +43,17 @@ pub(crate) fn mmap_handle<T: FileBackedHandle>(handle: T) -> io::Result<MappedMe
     })
 }
 
+#[cfg(target_os = "linux")]
+fn is_write_sealed(fd: RawFd) -> bool {
+    let seals = unsafe { libc::fcntl(fd, libc::F_GET_SEALS) };
+    seals != -1 && seals & libc::F_SEAL_WRITE != 0
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_write_sealed(_fd: RawFd) -> bool {
+    false
+}
+
 pub(crate) fn munmap_handle<T: MemoryHandle>(mapped: &mut MappedMem<T>) {
     unsafe {
         _ = munmap(mapped.ptr, mapped.mem.get_size());
@@ -49,6 +67,7 @@ impl ShmHandle {
     #[cfg(target_os = "linux")]
     fn open_anon_shm() -> anyhow::Result<RawFd> {
         Ok(memfd::MemfdOptions::default()
+            .allow_sealing(true)
             .create("anon-shm-handle")?
             .into_raw_fd())
     }
@@ -75,6 +94,23 @@ impl ShmHandle {
         ftruncate(fd, size as off_t)?;
         Ok(ShmHandle { handle, size })
     }
+
+    /// Seals the memory against writes and shrinking, so that the process it is passed to can
+    /// rely on its contents. It must not be mapped anymore when sealing. Later mappings are
+    /// private copies. Only memfds can be sealed, on other platforms this does nothing.
+    #[cfg(target_os = "linux")]
+    pub fn seal(&self) -> io::Result<()> {
+        let seals = libc::F_SEAL_WRITE | libc::F_SEAL_SHRINK;
+        if unsafe { libc::fcntl(self.handle.as_raw_fd(), libc::F_ADD_SEALS, seals) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn seal(&self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 impl NamedShmHandle {
@@ -86,6 +86,11 @@ impl ShmHandle {
             size: size | NOT_COMMITTED,
         })
     }
+
+    /// Sealing is only supported for memfds on linux, this does nothing.
+    pub fn seal(&self) -> io::Result<()> {
+        Ok(())
+    }
 }
 fn path_slice(path: &CString) -> &[u8] {
     assert_eq!(path.as_bytes()[0], b'/');
@@ -40,7 +40,7 @@ mod single_threaded_tests {
         os::unix::prelude::{AsRawFd, RawFd},
     };
 
-    use crate::platform::{metadata::ChannelMetadata, unix::message::MAX_FDS};
+    use crate::platform::{metadata::ChannelMetadata, unix::message::MAX_FDS, Channel};
 
     use super::super::PlatformHandle;
 
@@ -81,10 +81,36 @@ mod single_threaded_tests {
 
     #[cfg(target_os = "macos")]
     fn get_open_file_descriptors(
-        _: Option<libc::pid_t>,
+        pid: Option<libc::pid_t>,
     ) -> Result<BTreeMap<RawFd, String>, io::Error> {
-        //TODO implement this check for macos
-        Ok(BTreeMap::default())
+        use std::ffi::CStr;
+
+        if pid.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "listing the fds of other processes is not supported on macos",
+            ));
+        }
+
+        // there is no procfs on macos, but /dev/fd lists the fds of the current process
+        let fds = std::fs::read_dir("/dev/fd")?
+            .filter_map(|r| r.ok())
+            .filter_map(|r| r.file_name().into_string().ok()?.parse().ok())
+            .map(|fd: RawFd| {
+                let mut path = [0 as libc::c_char; libc::PATH_MAX as usize];
+                // fails for fds which are not backed by a file, e.g. sockets
+                let path = if unsafe { libc::fcntl(fd, libc::F_GETPATH, path.as_mut_ptr()) } == 0 {
+                    unsafe { CStr::from_ptr(path.as_ptr()) }
+                        .to_string_lossy()
+                        .into_owned()
+                } else {
+                    String::new()
+                };
+                (fd, path)
+            })
+            .collect();
+
+        Ok(fds)
     }
 
     fn assert_file_descriptors_unchanged(
@@ -145,4 +171,40 @@ mod single_threaded_tests {
 
         assert_file_descriptors_unchanged(&reference, None);
     }
+
+    #[test]
+    fn test_channel_passes_fds_over_socketpair() {
+        let reference = get_open_file_descriptors(None).unwrap();
+        let (sender, receiver) = std::os::unix::net::UnixStream::pair().unwrap();
+        let mut sender = Channel::from(sender);
+        let mut receiver = Channel::from(receiver);
+
+        let files: Vec<PlatformHandle<File>> = (0..MAX_FDS + 1)
+            .map(|_| tempfile::tempfile().unwrap().into())
+            .collect();
+        for file in &files {
+            sender.metadata.enqueue_for_sending(file.clone());
+        }
+        // the fds are sent along the data, MAX_FDS at a time
+        sender.write_all(b"first").unwrap();
+        sender.write_all(b"second").unwrap();
+
+        let mut buf = [0u8; 11];
+        receiver.read_exact(&mut buf).unwrap();
+        assert_eq!(b"firstsecond", &buf);
+
+        for file in &files {
+            let handle = receiver.metadata.find_handle(file).unwrap();
+            assert_ne!(file.as_raw_fd(), handle.as_raw_fd());
+            let flags = unsafe { libc::fcntl(handle.as_raw_fd(), libc::F_GETFD) };
+            assert_eq!(libc::FD_CLOEXEC, flags & libc::FD_CLOEXEC);
+            assert_platform_handle_is_valid_file(handle.to_untyped());
+        }
+        assert!(receiver.metadata.find_handle(&files[0]).is_none());
+
+        drop(files);
+        drop(sender);
+        drop(receiver);
+        assert_file_descriptors_unchanged(&reference, None);
+    }
 }
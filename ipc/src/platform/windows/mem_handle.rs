@@ -106,6 +106,11 @@ impl ShmHandle {
             size: size | NOT_COMMITTED,
         })
     }
+
+    /// Sealing is only supported for memfds on linux, this does nothing.
+    pub fn seal(&self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 impl NamedShmHandle {
@@ -15,14 +15,14 @@ use tarpc::{context::Context, ClientMessage, Request, Response};
 
 use tokio_serde::{Deserializer, Serializer};
 
-use tokio_util::codec::{Decoder, Encoder, LengthDelimitedCodec};
+use tokio_util::codec::{Decoder, Encoder};
 
 use crate::{
     handles::TransferHandles,
     platform::{Channel, Message},
 };
 
-use super::DefaultCodec;
+use super::{compression::CompressedFrameCodec, DefaultCodec};
 
 pub struct BlockingTransport<IncomingItem, OutgoingItem> {
     requests_id: Arc<AtomicU64>,
@@ -51,7 +51,7 @@ impl<IncomingItem, OutgoingItem> From<std::os::unix::net::UnixStream>
 }
 
 pub struct FramedBlocking<IncomingItem, OutgoingItem> {
-    codec: LengthDelimitedCodec,
+    codec: CompressedFrameCodec,
     read_buffer: BytesMut,
     channel: Channel,
     serde_codec: Pin<Box<DefaultCodec<Message<IncomingItem>, Message<OutgoingItem>>>>,
@@ -156,6 +156,11 @@ where
         )
     }
 
+    /// Sets the size from which sent frames are compressed, None disables compression.
+    pub fn set_compression_threshold(&mut self, threshold: Option<usize>) {
+        self.transport.codec.set_compression_threshold(threshold)
+    }
+
     pub fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
         self.transport.channel.set_nonblocking(nonblocking)
     }
@@ -0,0 +1,168 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! Length delimited framing, compressing the large frames with LZ4.
+//!
+//! Every frame starts with a flag byte telling whether its payload is compressed. The flag makes
+//! frames self describing: a peer decodes both kinds regardless of its own threshold, so the
+//! compression doesn't need to be agreed upon beforehand.
+
+use std::io;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder, LengthDelimitedCodec};
+
+const FRAME_RAW: u8 = 0;
+const FRAME_LZ4: u8 = 1;
+
+/// Frames of at least this size are compressed by default.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 64 * 1024;
+
+const MAX_FRAME_LENGTH: usize = 100_000_000;
+
+pub struct CompressedFrameCodec {
+    inner: LengthDelimitedCodec,
+    compression_threshold: Option<usize>,
+}
+
+impl Default for CompressedFrameCodec {
+    fn default() -> Self {
+        let mut inner = LengthDelimitedCodec::new();
+        inner.set_max_frame_length(MAX_FRAME_LENGTH);
+        CompressedFrameCodec {
+            inner,
+            compression_threshold: Some(DEFAULT_COMPRESSION_THRESHOLD),
+        }
+    }
+}
+
+impl CompressedFrameCodec {
+    /// Sets the size from which frames are compressed, None disables compression. Received
+    /// frames are decompressed either way.
+    pub fn set_compression_threshold(&mut self, threshold: Option<usize>) {
+        self.compression_threshold = threshold;
+    }
+
+    fn write_frame(&self, flag: u8, payload: &[u8], dst: &mut BytesMut) -> io::Result<()> {
+        let len = payload.len() + 1;
+        if len > self.inner.max_frame_length() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("frame of {len} bytes exceeds the maximum frame length"),
+            ));
+        }
+        // same header as the LengthDelimitedCodec defaults: a big endian u32
+        dst.reserve(4 + len);
+        dst.put_u32(len as u32);
+        dst.put_u8(flag);
+        dst.extend_from_slice(payload);
+        Ok(())
+    }
+}
+
+impl Encoder<Bytes> for CompressedFrameCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, data: Bytes, dst: &mut BytesMut) -> io::Result<()> {
+        if matches!(self.compression_threshold, Some(threshold) if data.len() >= threshold) {
+            let compressed = lz4_flex::compress_prepend_size(&data);
+            // incompressible data is sent as is
+            if compressed.len() < data.len() {
+                return self.write_frame(FRAME_LZ4, &compressed, dst);
+            }
+        }
+        self.write_frame(FRAME_RAW, &data, dst)
+    }
+}
+
+impl Decoder for CompressedFrameCodec {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<BytesMut>> {
+        let Some(mut frame) = self.inner.decode(src)? else {
+            return Ok(None);
+        };
+        if frame.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "empty frame"));
+        }
+        match frame.get_u8() {
+            FRAME_RAW => Ok(Some(frame)),
+            FRAME_LZ4 => {
+                if frame.len() < 4 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "truncated compressed frame",
+                    ));
+                }
+                let size = frame.get_u32_le() as usize;
+                // the size is read from the peer, don't let it allocate unbounded memory
+                if size > self.inner.max_frame_length() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("decompressed frame of {size} bytes exceeds the maximum length"),
+                    ));
+                }
+                let mut decompressed = BytesMut::zeroed(size);
+                let len = lz4_flex::decompress_into(&frame, &mut decompressed)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                decompressed.truncate(len);
+                Ok(Some(decompressed))
+            }
+            flag => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown frame flag {flag}"),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(codec: &mut CompressedFrameCodec, data: &[u8]) -> (usize, BytesMut) {
+        let mut buf = BytesMut::new();
+        codec
+            .encode(Bytes::copy_from_slice(data), &mut buf)
+            .unwrap();
+        let encoded_len = buf.len();
+        let frame = codec.decode(&mut buf).unwrap().unwrap();
+        assert!(buf.is_empty());
+        (encoded_len, frame)
+    }
+
+    #[test]
+    fn test_large_frames_are_compressed() {
+        let mut codec = CompressedFrameCodec::default();
+        let large = vec![b'a'; DEFAULT_COMPRESSION_THRESHOLD * 2];
+        let (encoded_len, frame) = roundtrip(&mut codec, &large);
+        assert!(encoded_len < large.len());
+        assert_eq!(large, frame);
+
+        let small = b"small frame";
+        let (encoded_len, frame) = roundtrip(&mut codec, small);
+        assert_eq!(4 + 1 + small.len(), encoded_len);
+        assert_eq!(&small[..], frame);
+
+        codec.set_compression_threshold(None);
+        let (encoded_len, frame) = roundtrip(&mut codec, &large);
+        assert_eq!(4 + 1 + large.len(), encoded_len);
+        assert_eq!(large, frame);
+    }
+
+    #[test]
+    fn test_invalid_frames() {
+        let mut codec = CompressedFrameCodec::default();
+        let mut buf = BytesMut::new();
+        buf.put_u32(5);
+        buf.put_u8(FRAME_LZ4);
+        buf.put_u32_le(u32::MAX);
+        assert!(codec.decode(&mut buf).is_err());
+
+        let mut buf = BytesMut::new();
+        buf.put_u32(1);
+        buf.put_u8(42);
+        assert!(codec.decode(&mut buf).is_err());
+    }
+}
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod blocking;
+pub mod compression;
 
 use std::{
     io,
@@ -20,8 +21,9 @@ use tokio_serde::Framed as SerdeFramed;
 use futures::{Sink, Stream};
 use serde::{Deserialize, Serialize};
 
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio_util::codec::Framed;
 
+use self::compression::CompressedFrameCodec;
 use super::{
     handles::TransferHandles,
     platform::{metadata::ChannelMetadata, AsyncChannel, Channel, Message},
@@ -30,7 +32,7 @@ use super::{
 pub type DefaultCodec<Item, SinkItem> = Bincode<Item, SinkItem>;
 
 type DefaultSerdeFramed<Item, SinkItem> = SerdeFramed<
-    Framed<AsyncChannel, LengthDelimitedCodec>,
+    Framed<AsyncChannel, CompressedFrameCodec>,
     Message<Item>,
     Message<SinkItem>,
     DefaultCodec<Message<Item>, Message<SinkItem>>,
@@ -50,6 +52,14 @@ impl<Item, SinkItem> Transport<Item, SinkItem> {
     pub fn get_ref(&self) -> &AsyncChannel {
         self.inner.get_ref().get_ref()
     }
+
+    /// Sets the size from which sent frames are compressed, None disables compression.
+    pub fn set_compression_threshold(&mut self, threshold: Option<usize>) {
+        self.inner
+            .get_mut()
+            .codec_mut()
+            .set_compression_threshold(threshold)
+    }
 }
 
 impl<CodecError, Item, SinkItem> Stream for Transport<Item, SinkItem>
@@ -129,10 +139,8 @@ where
     SinkItem: Serialize,
 {
     let channel_metadata = io.metadata.clone();
-    let mut length_delimited = LengthDelimitedCodec::new();
-    length_delimited.set_max_frame_length(100_000_000);
     Transport {
-        inner: SerdeFramed::new(Framed::new(io, length_delimited), codec),
+        inner: SerdeFramed::new(Framed::new(io, CompressedFrameCodec::default()), codec),
         channel_metadata,
     }
 }
@@ -62,6 +62,32 @@ pub unsafe extern "C" fn ddog_prof_Crashtracker_update_on_fork(
     .into()
 }
 
+#[no_mangle]
+#[must_use]
+/// Replaces the metadata reported with crashes of this process, e.g. after the
+/// service, env or version changed. Custom tags, such as the path of the
+/// tracer log file, can be passed along in the `tags` of the metadata.
+/// The metadata is serialized right away and kept in memory, so that the crash
+/// handler does not need to allocate when emitting it.
+///
+/// # Preconditions
+///     None.
+/// # Safety
+///     Crash-tracking functions are not reentrant.
+///     No other crash-handler functions should be called concurrently.
+/// # Atomicity
+///     This function uses a swap on an atomic pointer.
+pub unsafe extern "C" fn ddog_prof_Crashtracker_update_metadata(
+    metadata: CrashtrackerMetadata,
+) -> CrashtrackerResult {
+    (|| {
+        let metadata = metadata.try_into()?;
+        datadog_crashtracker::update_metadata(metadata)
+    })()
+    .context("ddog_prof_Crashtracker_update_metadata failed")
+    .into()
+}
+
 #[no_mangle]
 #[must_use]
 /// Receives data from a crash collector via a pipe on `stdin`, formats it into
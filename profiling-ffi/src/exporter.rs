@@ -4,9 +4,11 @@
 #![allow(renamed_and_removed_lints)]
 #![allow(clippy::box_vec)]
 
+use crate::profiles::ProfileResult;
 use crate::Timespec;
+use anyhow::Context;
 use datadog_profiling::exporter;
-use datadog_profiling::exporter::{ProfileExporter, Request};
+use datadog_profiling::exporter::{HttpClientConfig, HttpVersion, ProfileExporter, Request};
 use datadog_profiling::internal::ProfiledEndpointsStats;
 use ddcommon::tag::Tag;
 use ddcommon_ffi::slice::{AsBytes, ByteSlice, CharSlice, Slice};
@@ -65,6 +67,37 @@ pub struct CancellationToken(tokio_util::sync::CancellationToken);
 /// cbindgen:field-names=[code]
 pub struct HttpStatus(u16);
 
+/// Where the time of an upload went, in microseconds. The connection timings are 0 when a pooled
+/// connection was reused, see `ddog_prof_Exporter_send_with_timings`.
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct SendTimings {
+    /// Resolving the host name.
+    pub dns_us: u64,
+    /// Establishing the TCP connection, or the connection to the unix socket or named pipe.
+    pub connect_us: u64,
+    /// The TLS handshake.
+    pub tls_us: u64,
+    /// From the connection being ready to the response headers, so the upload of the profile and
+    /// its processing by the intake.
+    pub ttfb_us: u64,
+    /// From the start of the request to the response headers.
+    pub total_us: u64,
+}
+
+impl From<exporter::SendTimings> for SendTimings {
+    fn from(timings: exporter::SendTimings) -> Self {
+        let micros = |duration: std::time::Duration| duration.as_micros() as u64;
+        SendTimings {
+            dns_us: timings.connect.dns.map_or(0, micros),
+            connect_us: timings.connect.connect.map_or(0, micros),
+            tls_us: timings.connect.tls.map_or(0, micros),
+            ttfb_us: micros(timings.ttfb),
+            total_us: micros(timings.total),
+        }
+    }
+}
+
 /// Creates an endpoint that uses the agent.
 /// # Arguments
 /// * `base_url` - Contains a URL with scheme, host, and port e.g. "https://agent:8126/".
@@ -204,6 +237,62 @@ pub unsafe extern "C" fn ddog_prof_Exporter_drop(exporter: Option<&mut ProfileEx
     }
 }
 
+/// Configures the HTTP client of the exporter. By default, HTTP/1.1 is used and the connection is
+/// closed after each upload.
+/// # Arguments
+/// * `http_version` - The HTTP versions to use for uploads.
+/// * `pool_idle_timeout_ms` - How long idle connections are kept open for later uploads, 0 closes
+///   the connection after each upload.
+/// * `http2_keep_alive_interval_ms` - The interval of HTTP/2 pings keeping idle connections alive,
+///   0 disables them.
+/// * `connect_timeout_ms` - How long establishing a TCP connection may take, 0 only bounds it by
+///   the `timeout_ms` of the request.
+/// # Safety
+/// The `exporter` must point to a valid exporter made by `ddog_prof_Exporter_new`.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_Exporter_set_http_client_config(
+    exporter: Option<&mut ProfileExporter>,
+    http_version: HttpVersion,
+    pool_idle_timeout_ms: u64,
+    http2_keep_alive_interval_ms: u64,
+    connect_timeout_ms: u64,
+) -> ProfileResult {
+    (|| {
+        let exporter = exporter.ok_or_else(|| anyhow::anyhow!("exporter was null"))?;
+        let millis = |ms: u64| (ms > 0).then(|| std::time::Duration::from_millis(ms));
+        exporter.set_http_client_config(&HttpClientConfig {
+            http_version,
+            pool_idle_timeout: millis(pool_idle_timeout_ms),
+            http2_keep_alive_interval: millis(http2_keep_alive_interval_ms),
+            connect_timeout: millis(connect_timeout_ms),
+        });
+        anyhow::Ok(())
+    })()
+    .context("ddog_prof_Exporter_set_http_client_config failed")
+    .into()
+}
+
+/// Sends the header `name` with every upload of the exporter, replacing the previous value of the
+/// header, if any. This is meant for static authentication, e.g. "Authorization: Bearer <token>"
+/// for a proxy in front of the intake.
+/// # Safety
+/// The `exporter` must point to a valid exporter made by `ddog_prof_Exporter_new`.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_Exporter_set_header(
+    exporter: Option<&mut ProfileExporter>,
+    name: CharSlice,
+    value: CharSlice,
+) -> ProfileResult {
+    (|| {
+        let exporter = exporter.ok_or_else(|| anyhow::anyhow!("exporter was null"))?;
+        exporter.set_header(name.try_to_utf8()?, value.try_to_utf8()?)
+    })()
+    .context("ddog_prof_Exporter_set_header failed")
+    .into()
+}
+
 unsafe fn into_vec_files<'a>(slice: Slice<'a, File>) -> Vec<exporter::File<'a>> {
     slice
         .into_slice()
@@ -230,6 +319,9 @@ impl From<RequestBuildResult> for Result<Box<Request>, String> {
 /// If successful, builds a `ddog_prof_Exporter_Request` object based on the
 /// profile data supplied. If unsuccessful, it returns an error message.
 ///
+/// The `optional_additional_tags` are only sent with this request, and take precedence over the
+/// tags of the exporter with the same key, e.g. to update the runtime-id after a fork.
+///
 /// For details on the `optional_internal_metadata_json`, please reference the Datadog-internal
 /// "RFC: Attaching internal metadata to pprof profiles".
 /// If you use this parameter, please update the RFC with your use-case, so we can keep track of how
@@ -357,16 +449,45 @@ pub unsafe extern "C" fn ddog_prof_Exporter_send(
     request: Option<&mut Option<&mut Request>>,
     cancel: Option<&CancellationToken>,
 ) -> SendResult {
-    match ddog_prof_exporter_send_impl(exporter, request, cancel) {
+    match ddog_prof_exporter_send_impl(exporter, request, cancel, None) {
         Ok(code) => SendResult::HttpResponse(code),
         Err(err) => SendResult::Err(Error::from(err.context("failed ddog_prof_Exporter_send"))),
     }
 }
 
+/// Like `ddog_prof_Exporter_send`, additionally breaking down the time taken by the upload, e.g.
+/// to tell slow networks from large profiles.
+///
+/// # Arguments
+/// * `exporter` - Borrows the exporter for sending the request.
+/// * `request` - Takes ownership of the request, replacing it with a null pointer.
+/// * `cancel` - Borrows the cancel, if any.
+/// * `timings` - Set to the timings of the upload when it gets a response, left untouched
+///   otherwise.
+///
+/// # Safety
+/// All non-null arguments MUST have been created by created by apis in this module.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_Exporter_send_with_timings(
+    exporter: Option<&mut ProfileExporter>,
+    request: Option<&mut Option<&mut Request>>,
+    cancel: Option<&CancellationToken>,
+    timings: Option<&mut SendTimings>,
+) -> SendResult {
+    match ddog_prof_exporter_send_impl(exporter, request, cancel, timings) {
+        Ok(code) => SendResult::HttpResponse(code),
+        Err(err) => SendResult::Err(Error::from(
+            err.context("failed ddog_prof_Exporter_send_with_timings"),
+        )),
+    }
+}
+
 unsafe fn ddog_prof_exporter_send_impl(
     exporter: Option<&mut ProfileExporter>,
     request: Option<&mut Option<&mut Request>>,
     cancel: Option<&CancellationToken>,
+    timings: Option<&mut SendTimings>,
 ) -> anyhow::Result<HttpStatus> {
     // Re-box the request first, to avoid leaks on other errors.
     let request = match rebox_request(request) {
@@ -380,7 +501,10 @@ unsafe fn ddog_prof_exporter_send_impl(
     };
 
     let cancel = cancel.map(|ptr| &ptr.0);
-    let response = exporter.send(*request, cancel)?;
+    let (response, send_timings) = exporter.send_with_timings(*request, cancel)?;
+    if let Some(timings) = timings {
+        *timings = send_timings.into();
+    }
 
     Ok(HttpStatus(response.status().as_u16()))
 }
@@ -964,4 +1088,23 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn send_with_timings_fails_with_null() {
+        let mut timings = SendTimings::default();
+        unsafe {
+            match ddog_prof_Exporter_send_with_timings(None, None, None, Some(&mut timings)) {
+                SendResult::HttpResponse(http_status) => {
+                    panic!("Expected test to fail, got {http_status:?}")
+                }
+                SendResult::Err(error) => {
+                    assert_eq!(
+                        "failed ddog_prof_Exporter_send_with_timings: request was null",
+                        error.to_string()
+                    );
+                }
+            }
+        }
+        assert_eq!(0, timings.total_us);
+    }
 }
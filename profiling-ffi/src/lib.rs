@@ -4,15 +4,14 @@
 #[cfg(all(feature = "symbolizer", not(target_os = "windows")))]
 pub use symbolizer_ffi::*;
 
-use std::fmt::Debug;
-use std::time::SystemTime;
-
-use chrono::{DateTime, TimeZone, Utc};
-
+#[cfg(feature = "crashtracker")]
 mod crashtracker;
+#[cfg(feature = "exporter")]
 mod exporter;
+#[cfg(feature = "profiling")]
 mod profiles;
 
+#[cfg(feature = "crashtracker")]
 pub use crashtracker::*;
 // re-export telemetry ffi
 #[cfg(feature = "ddtelemetry-ffi")]
@@ -22,49 +21,5 @@ pub use ddtelemetry_ffi::*;
 #[allow(unused_imports)]
 pub use data_pipeline_ffi::*;
 
-/// Represents time since the Unix Epoch in seconds plus nanoseconds.
-#[repr(C)]
-#[derive(Copy, Clone, Debug)]
-pub struct Timespec {
-    pub seconds: i64,
-    pub nanoseconds: u32,
-}
-
-impl From<Timespec> for DateTime<Utc> {
-    fn from(value: Timespec) -> Self {
-        Utc.timestamp_opt(value.seconds, value.nanoseconds).unwrap()
-    }
-}
-
-impl From<Timespec> for SystemTime {
-    fn from(value: Timespec) -> Self {
-        // The DateTime API is more convenient, so let's delegate.
-        let datetime: DateTime<Utc> = value.into();
-        SystemTime::from(datetime)
-    }
-}
-
-impl<'a> From<&'a Timespec> for SystemTime {
-    fn from(value: &'a Timespec) -> Self {
-        // The DateTime API is more convenient, so let's delegate.
-        let datetime: DateTime<Utc> = (*value).into();
-        SystemTime::from(datetime)
-    }
-}
-
-impl From<DateTime<Utc>> for Timespec {
-    fn from(value: DateTime<Utc>) -> Self {
-        Self {
-            seconds: value.timestamp(),
-            nanoseconds: value.timestamp_subsec_nanos(),
-        }
-    }
-}
-
-impl From<SystemTime> for Timespec {
-    fn from(value: SystemTime) -> Self {
-        // The DateTime API is more convenient, so let's delegate again.
-        let datetime: DateTime<Utc> = value.into();
-        Self::from(datetime)
-    }
-}
+// shared with the other FFI crates, so that their headers agree on the definition
+pub use ddcommon_ffi::Timespec;
@@ -3,14 +3,20 @@
 
 use crate::Timespec;
 use anyhow::Context;
+use datadog_profiling::allocator_stats::AllocatorStats;
 use datadog_profiling::api;
 use datadog_profiling::internal;
 use datadog_profiling::internal::ProfiledEndpointsStats;
-use ddcommon_ffi::slice::{AsBytes, CharSlice, Slice};
+use ddcommon_ffi::slice::{AsBytes, ByteSlice, CharSlice, Slice};
 use ddcommon_ffi::Error;
+use std::borrow::Cow;
+use std::ffi::c_void;
 use std::num::NonZeroI64;
 use std::str::Utf8Error;
-use std::time::{Duration, SystemTime};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
 
 /// Represents a profile. Do not access its member for any reason, only use
 /// the C API functions on this struct.
@@ -103,11 +109,39 @@ impl From<anyhow::Result<internal::EncodedProfile>> for SerializeResult {
     }
 }
 
+/// How the values of a sample type are aggregated by default, see [ValueType].
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Aggregation {
+    /// No hint, the backend picks the aggregation for the sample type.
+    #[default]
+    None,
+    Sum,
+    Min,
+    Max,
+}
+
+impl From<Aggregation> for Option<api::Aggregation> {
+    fn from(aggregation: Aggregation) -> Self {
+        match aggregation {
+            Aggregation::None => None,
+            Aggregation::Sum => Some(api::Aggregation::Sum),
+            Aggregation::Min => Some(api::Aggregation::Min),
+            Aggregation::Max => Some(api::Aggregation::Max),
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct ValueType<'a> {
     pub type_: CharSlice<'a>,
     pub unit: CharSlice<'a>,
+    /// Hints for sample types the backend doesn't know about yet. Zero-initialized, the
+    /// aggregation is `None` and the display unit empty, meaning no hint.
+    pub aggregation: Aggregation,
+    /// The unit the values are displayed in, if not the `unit` they are collected in.
+    pub display_unit: CharSlice<'a>,
 }
 
 impl<'a> ValueType<'a> {
@@ -115,6 +149,8 @@ impl<'a> ValueType<'a> {
         Self {
             type_: type_.into(),
             unit: unit.into(),
+            aggregation: Aggregation::None,
+            display_unit: CharSlice::default(),
         }
     }
 }
@@ -176,6 +212,9 @@ pub struct Line<'a> {
 pub struct Location<'a> {
     /// todo: how to handle unknown mapping?
     pub mapping: Mapping<'a>,
+    /// Leave the function zeroed for locations which are only known by their mapping and
+    /// address, e.g. when native frames are symbolicated by the backend. No function is then
+    /// emitted in the pprof.
     pub function: Function<'a>,
 
     /// The instruction address for this location, if available.  It
@@ -229,6 +268,20 @@ pub struct Sample<'a> {
     pub labels: Slice<'a, Label<'a>>,
 }
 
+/// The thread a sample was taken on, see `ddog_prof_Profile_add_with_thread`.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct ThreadLabels<'a> {
+    /// The id of the thread in the runtime, e.g. the id of a Java thread, or the OS id when the
+    /// runtime has none.
+    pub id: i64,
+    /// The id of the thread in the OS, e.g. the tid on Linux, if it differs from `id`. Zero when
+    /// unknown.
+    pub native_id: Option<NonZeroI64>,
+    /// Empty when unknown.
+    pub name: CharSlice<'a>,
+}
+
 impl<'a> TryFrom<&'a Mapping<'a>> for api::Mapping<'a> {
     type Error = Utf8Error;
 
@@ -247,10 +300,13 @@ impl<'a> TryFrom<&'a Mapping<'a>> for api::Mapping<'a> {
 
 impl<'a> From<&'a ValueType<'a>> for api::ValueType<'a> {
     fn from(vt: &'a ValueType<'a>) -> Self {
-        Self::new(
-            vt.type_.try_to_utf8().unwrap_or(""),
-            vt.unit.try_to_utf8().unwrap_or(""),
-        )
+        let display_unit = vt.display_unit.try_to_utf8().unwrap_or("");
+        Self {
+            r#type: vt.type_.try_to_utf8().unwrap_or(""),
+            unit: vt.unit.try_to_utf8().unwrap_or(""),
+            aggregation: vt.aggregation.into(),
+            display_unit: (!display_unit.is_empty()).then_some(display_unit),
+        }
     }
 }
 
@@ -328,6 +384,19 @@ impl<'a> TryFrom<&'a Label<'a>> for api::Label<'a> {
     }
 }
 
+impl<'a> TryFrom<&'a ThreadLabels<'a>> for api::ThreadLabels<'a> {
+    type Error = Utf8Error;
+
+    fn try_from(thread: &'a ThreadLabels<'a>) -> Result<Self, Self::Error> {
+        let name = thread.name.try_to_utf8()?;
+        Ok(Self {
+            id: thread.id,
+            native_id: thread.native_id.map(NonZeroI64::get),
+            name: if name.is_empty() { None } else { Some(name) },
+        })
+    }
+}
+
 impl<'a> TryFrom<Sample<'a>> for api::Sample<'a> {
     type Error = Utf8Error;
 
@@ -353,6 +422,121 @@ impl<'a> TryFrom<Sample<'a>> for api::Sample<'a> {
     }
 }
 
+static STRICT_VALIDATION: AtomicBool = AtomicBool::new(cfg!(feature = "strict-validation"));
+
+/// Enables or disables the strict validation of the data passed to the profile functions, which
+/// is enabled by default when built with the `strict-validation` feature.
+///
+/// By default, strings which are not valid UTF-8 are replaced lossily (sample types, endpoints,
+/// upscaling rules), and samples are only rejected on invalid UTF-8, with a terse error. With
+/// strict validation, every string is checked, the samples are also checked for inconsistent
+/// labels, locations and mappings, and the errors tell which field is invalid, e.g.
+/// `labels[2].key is not valid UTF-8`. This is meant to catch bugs of bindings during
+/// development, at the cost of slower sampling.
+#[no_mangle]
+pub extern "C" fn ddog_prof_set_strict_validation(enabled: bool) {
+    STRICT_VALIDATION.store(enabled, Ordering::Relaxed);
+}
+
+fn strict_validation() -> bool {
+    STRICT_VALIDATION.load(Ordering::Relaxed)
+}
+
+/// Converts the string, failing on invalid UTF-8 with strict validation, or replacing the invalid
+/// sequences otherwise.
+fn char_slice_to_utf8<'a>(slice: &'a CharSlice<'a>, what: &str) -> anyhow::Result<Cow<'a, str>> {
+    if strict_validation() {
+        Ok(Cow::Borrowed(check_utf8(slice, || what.to_string())?))
+    } else {
+        Ok(slice.to_utf8_lossy())
+    }
+}
+
+fn check_utf8<'a>(
+    slice: &'a CharSlice<'a>,
+    what: impl FnOnce() -> String,
+) -> anyhow::Result<&'a str> {
+    slice
+        .try_to_utf8()
+        .map_err(|err| anyhow::anyhow!("{} is not valid UTF-8: {err}", what()))
+}
+
+fn validate_value_type(vt: &ValueType, path: &str) -> anyhow::Result<()> {
+    check_utf8(&vt.type_, || format!("{path}.type_"))?;
+    check_utf8(&vt.unit, || format!("{path}.unit"))?;
+    check_utf8(&vt.display_unit, || format!("{path}.display_unit"))?;
+    Ok(())
+}
+
+fn validate_profile_types(
+    sample_types: &[ValueType],
+    period: Option<&Period>,
+) -> anyhow::Result<()> {
+    for (i, vt) in sample_types.iter().enumerate() {
+        validate_value_type(vt, &format!("sample_types[{i}]"))?;
+    }
+    if let Some(period) = period {
+        validate_value_type(&period.type_, "period.type_")?;
+    }
+    Ok(())
+}
+
+fn validate_function(function: &Function, path: &str) -> anyhow::Result<()> {
+    check_utf8(&function.name, || format!("{path}.name"))?;
+    check_utf8(&function.system_name, || format!("{path}.system_name"))?;
+    check_utf8(&function.filename, || format!("{path}.filename"))?;
+    anyhow::ensure!(
+        function.start_line >= 0,
+        "{path}.start_line is negative: {}",
+        function.start_line
+    );
+    Ok(())
+}
+
+fn validate_locations(locations: &[Location]) -> anyhow::Result<()> {
+    for (i, location) in locations.iter().enumerate() {
+        let mapping = &location.mapping;
+        check_utf8(&mapping.filename, || {
+            format!("locations[{i}].mapping.filename")
+        })?;
+        check_utf8(&mapping.build_id, || {
+            format!("locations[{i}].mapping.build_id")
+        })?;
+        anyhow::ensure!(
+            mapping.memory_limit == 0 || mapping.memory_start <= mapping.memory_limit,
+            "locations[{i}].mapping ends before it starts: {:#x}..{:#x}",
+            mapping.memory_start,
+            mapping.memory_limit
+        );
+        validate_function(&location.function, &format!("locations[{i}].function"))?;
+        anyhow::ensure!(
+            location.line >= 0,
+            "locations[{i}].line is negative: {}",
+            location.line
+        );
+    }
+    Ok(())
+}
+
+fn validate_labels(labels: &[Label]) -> anyhow::Result<()> {
+    for (i, label) in labels.iter().enumerate() {
+        let key = check_utf8(&label.key, || format!("labels[{i}].key"))?;
+        anyhow::ensure!(!key.is_empty(), "labels[{i}].key is empty");
+        let str = check_utf8(&label.str, || format!("labels[{i}].str"))?;
+        let num_unit = check_utf8(&label.num_unit, || format!("labels[{i}].num_unit"))?;
+        anyhow::ensure!(
+            str.is_empty() || (label.num == 0 && num_unit.is_empty()),
+            "labels[{i}] ({key}) has both a string and a numeric value"
+        );
+    }
+    Ok(())
+}
+
+fn validate_sample(sample: &Sample) -> anyhow::Result<()> {
+    validate_locations(sample.locations.as_slice())?;
+    validate_labels(sample.labels.as_slice())
+}
+
 /// Create a new profile with the given sample types. Must call
 /// `ddog_prof_Profile_drop` when you are done with the profile.
 ///
@@ -372,6 +556,11 @@ pub unsafe extern "C" fn ddog_prof_Profile_new(
     period: Option<&Period>,
     start_time: Option<&Timespec>,
 ) -> ProfileNewResult {
+    if strict_validation() {
+        if let Err(err) = validate_profile_types(sample_types.as_slice(), period) {
+            return ProfileNewResult::Err(err.context("ddog_prof_Profile_new failed").into());
+        }
+    }
     let types: Vec<api::ValueType> = sample_types.into_slice().iter().map(Into::into).collect();
     let start_time = start_time.map_or_else(SystemTime::now, SystemTime::from);
     let period = period.map(Into::into);
@@ -434,6 +623,9 @@ pub unsafe extern "C" fn ddog_prof_Profile_add(
 ) -> ProfileResult {
     (|| {
         let profile = profile_ptr_to_inner(profile)?;
+        if strict_validation() {
+            validate_sample(&sample)?;
+        }
         let sample = sample.try_into()?;
         profile.add_sample(sample, timestamp)
     })()
@@ -441,233 +633,1285 @@ pub unsafe extern "C" fn ddog_prof_Profile_add(
     .into()
 }
 
-unsafe fn profile_ptr_to_inner<'a>(
-    profile_ptr: *mut Profile,
-) -> anyhow::Result<&'a mut internal::Profile> {
-    match profile_ptr.as_mut() {
-        None => anyhow::bail!("profile pointer was null"),
-        Some(inner_ptr) => match inner_ptr.inner.as_mut() {
-            Some(profile) => Ok(profile),
-            None => anyhow::bail!("profile's inner pointer was null (indicates use-after-free)"),
-        },
-    }
-}
-
-/// Associate an endpoint to a given local root span id.
-/// During the serialization of the profile, an endpoint label will be added
-/// to all samples that contain a matching local root span id label.
-///
-/// Note: calling this API causes the "trace endpoint" and "local root span id" strings
-/// to be interned, even if no matching sample is found.
-///
-/// # Arguments
-/// * `profile` - a reference to the profile that will contain the samples.
-/// * `local_root_span_id`
-/// * `endpoint` - the value of the endpoint label to add for matching samples.
+/// Same as `ddog_prof_Profile_add`, but attaches the sample to the numeric `context_id`. The
+/// labels of the context are requested from the provider registered with
+/// `ddog_prof_Profile_set_context_provider` when the profile is serialized, so that they don't
+/// need to be built at sample time.
 ///
 /// # Safety
-/// The `profile` ptr must point to a valid Profile object created by this
-/// module.
-/// This call is _NOT_ thread-safe.
-#[no_mangle]
+/// Same as `ddog_prof_Profile_add`.
 #[must_use]
-pub unsafe extern "C" fn ddog_prof_Profile_set_endpoint(
+#[no_mangle]
+pub unsafe extern "C" fn ddog_prof_Profile_add_with_context(
     profile: *mut Profile,
-    local_root_span_id: u64,
-    endpoint: CharSlice,
+    sample: Sample,
+    timestamp: Option<NonZeroI64>,
+    context_id: u64,
 ) -> ProfileResult {
     (|| {
         let profile = profile_ptr_to_inner(profile)?;
-        let endpoint = endpoint.to_utf8_lossy();
-        profile.add_endpoint(local_root_span_id, endpoint)
+        if strict_validation() {
+            validate_sample(&sample)?;
+        }
+        let sample = sample.try_into()?;
+        profile.add_sample_with_context(sample, timestamp, context_id)
     })()
-    .context("ddog_prof_Profile_set_endpoint failed")
+    .context("ddog_prof_Profile_add_with_context failed")
     .into()
 }
 
-/// Count the number of times an endpoint has been seen.
-///
-/// # Arguments
-/// * `profile` - a reference to the profile that will contain the samples.
-/// * `endpoint` - the endpoint label for which the count will be incremented
+/// Same as `ddog_prof_Profile_add`, but also labels the sample with the thread it was taken on,
+/// in the format expected by the backend: a numeric "thread id", then a numeric "thread native
+/// id" and a "thread name" when known. The `sample` must not have these labels already.
 ///
 /// # Safety
-/// The `profile` ptr must point to a valid Profile object created by this
-/// module.
-/// This call is _NOT_ thread-safe.
-#[no_mangle]
+/// Same as `ddog_prof_Profile_add`. The `thread` name must also be valid for the duration of this
+/// call.
 #[must_use]
-pub unsafe extern "C" fn ddog_prof_Profile_add_endpoint_count(
+#[no_mangle]
+pub unsafe extern "C" fn ddog_prof_Profile_add_with_thread(
     profile: *mut Profile,
-    endpoint: CharSlice,
-    value: i64,
+    sample: Sample,
+    thread: ThreadLabels,
+    timestamp: Option<NonZeroI64>,
 ) -> ProfileResult {
     (|| {
         let profile = profile_ptr_to_inner(profile)?;
-        let endpoint = endpoint.to_utf8_lossy();
-        profile.add_endpoint_count(endpoint, value)
+        if strict_validation() {
+            validate_sample(&sample)?;
+            check_utf8(&thread.name, || "thread.name".to_string())?;
+        }
+        let mut sample: api::Sample = sample.try_into()?;
+        api::ThreadLabels::try_from(&thread)?.push_labels(&mut sample.labels);
+        profile.add_sample(sample, timestamp)
     })()
-    .context("ddog_prof_Profile_set_endpoint failed")
+    .context("ddog_prof_Profile_add_with_thread failed")
     .into()
 }
 
-/// Add a poisson-based upscaling rule which will be use to adjust values and make them
-/// closer to reality.
+/// Returned by [ddog_prof_Profile_intern_stacktrace].
+#[allow(dead_code)]
+#[repr(C)]
+pub enum InternStackTraceResult {
+    Ok(internal::InternedStackTrace),
+    Err(Error),
+}
+
+impl From<anyhow::Result<internal::InternedStackTrace>> for InternStackTraceResult {
+    fn from(value: anyhow::Result<internal::InternedStackTrace>) -> Self {
+        match value {
+            Ok(stacktrace) => Self::Ok(stacktrace),
+            Err(err) => Self::Err(err.into()),
+        }
+    }
+}
+
+/// Interns the stack trace made of `locations` (the leaf is at locations[0]), for samples added
+/// with `ddog_prof_Profile_add_by_stacktrace`. Stacks which are sampled repeatedly then only need
+/// their locations to be converted once.
 ///
-/// # Arguments
-/// * `profile` - a reference to the profile that will contain the samples.
-/// * `offset_values` - offset of the values
-/// * `label_name` - name of the label used to identify sample(s)
-/// * `label_value` - value of the label used to identify sample(s)
-/// * `sum_value_offset` - offset of the value used as a sum (compute the average with
-///   `count_value_offset`)
-/// * `count_value_offset` - offset of the value used as a count (compute the average with
-///   `sum_value_offset`)
-/// * `sampling_distance` - this is the threshold for this sampling window. This value must not be
-///   equal to 0
+/// The returned stack trace is only valid for this profile, and is invalidated when the profile
+/// is reset. Using it afterwards fails, it needs to be interned again.
 ///
 /// # Safety
-/// This function must be called before serialize and must not be called after.
 /// The `profile` ptr must point to a valid Profile object created by this
-/// module.
+/// module. All pointers inside the `locations` need to be valid for the duration
+/// of this call.
 /// This call is _NOT_ thread-safe.
 #[must_use]
 #[no_mangle]
-pub unsafe extern "C" fn ddog_prof_Profile_add_upscaling_rule_poisson(
+pub unsafe extern "C" fn ddog_prof_Profile_intern_stacktrace(
     profile: *mut Profile,
-    offset_values: Slice<usize>,
-    label_name: CharSlice,
-    label_value: CharSlice,
-    sum_value_offset: usize,
-    count_value_offset: usize,
-    sampling_distance: u64,
-) -> ProfileResult {
+    locations: Slice<Location>,
+) -> InternStackTraceResult {
     (|| {
         let profile = profile_ptr_to_inner(profile)?;
-        anyhow::ensure!(sampling_distance != 0, "sampling_distance must not be 0");
-        let upscaling_info = api::UpscalingInfo::Poisson {
-            sum_value_offset,
-            count_value_offset,
-            sampling_distance,
-        };
-        add_upscaling_rule(
-            profile,
-            offset_values,
-            label_name,
-            label_value,
-            upscaling_info,
-        )
+        if strict_validation() {
+            validate_locations(locations.as_slice())?;
+        }
+        let locations = locations
+            .as_slice()
+            .iter()
+            .map(api::Location::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        profile.intern_stacktrace(&locations)
     })()
-    .context("ddog_prof_Profile_add_upscaling_rule_proportional failed")
+    .context("ddog_prof_Profile_intern_stacktrace failed")
     .into()
 }
 
-/// Add a proportional-based upscaling rule which will be use to adjust values and make them
-/// closer to reality.
-///
-/// # Arguments
-/// * `profile` - a reference to the profile that will contain the samples.
-/// * `offset_values` - offset of the values
-/// * `label_name` - name of the label used to identify sample(s)
-/// * `label_value` - value of the label used to identify sample(s)
-/// * `total_sampled` - number of sampled event (found in the pprof). This value must not be equal
-///   to 0
-/// * `total_real` - number of events the profiler actually witnessed. This value must not be equal
-///   to 0
+/// Same as `ddog_prof_Profile_add`, for a stack trace returned by
+/// `ddog_prof_Profile_intern_stacktrace`. Fails if the stack trace was interned by another
+/// profile, or before the profile was reset.
 ///
 /// # Safety
-/// This function must be called before serialize and must not be called after.
 /// The `profile` ptr must point to a valid Profile object created by this
-/// module.
+/// module. All pointers inside the `values` and `labels` need to be valid for the duration
+/// of this call.
 /// This call is _NOT_ thread-safe.
 #[must_use]
 #[no_mangle]
-pub unsafe extern "C" fn ddog_prof_Profile_add_upscaling_rule_proportional(
+pub unsafe extern "C" fn ddog_prof_Profile_add_by_stacktrace(
     profile: *mut Profile,
-    offset_values: Slice<usize>,
-    label_name: CharSlice,
-    label_value: CharSlice,
-    total_sampled: u64,
-    total_real: u64,
+    stacktrace: internal::InternedStackTrace,
+    values: Slice<i64>,
+    labels: Slice<Label>,
+    timestamp: Option<NonZeroI64>,
 ) -> ProfileResult {
     (|| {
         let profile = profile_ptr_to_inner(profile)?;
-        anyhow::ensure!(total_sampled != 0, "total_sampled must not be 0");
-        anyhow::ensure!(total_real != 0, "total_real must not be 0");
-        let upscaling_info = api::UpscalingInfo::Proportional {
-            scale: total_real as f64 / total_sampled as f64,
-        };
-        add_upscaling_rule(
-            profile,
-            offset_values,
-            label_name,
-            label_value,
-            upscaling_info,
-        )
+        if strict_validation() {
+            validate_labels(labels.as_slice())?;
+        }
+        let labels = labels
+            .as_slice()
+            .iter()
+            .map(api::Label::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        profile.add_sample_by_stacktrace(stacktrace, values.as_slice().to_vec(), &labels, timestamp)
     })()
-    .context("ddog_prof_Profile_add_upscaling_rule_proportional failed")
+    .context("ddog_prof_Profile_add_by_stacktrace failed")
     .into()
 }
 
-unsafe fn add_upscaling_rule(
-    profile: &mut internal::Profile,
-    offset_values: Slice<usize>,
-    label_name: CharSlice,
-    label_value: CharSlice,
-    upscaling_info: api::UpscalingInfo,
-) -> anyhow::Result<()> {
-    let label_name_n = label_name.to_utf8_lossy();
-    let label_value_n = label_value.to_utf8_lossy();
-    profile.add_upscaling_rule(
-        offset_values.as_slice(),
-        label_name_n.as_ref(),
-        label_value_n.as_ref(),
-        upscaling_info,
-    )
+/// The ids returned by [ddog_prof_Profile_preintern]. Do not access its member for any reason,
+/// only use the C API functions on this struct.
+#[repr(C)]
+pub struct PreinternedIds {
+    // This may be null, but if not it will point to valid PreinternedIds.
+    inner: *mut internal::PreinternedIds,
 }
 
-#[repr(C)]
-pub struct EncodedProfile {
-    start: Timespec,
-    end: Timespec,
-    buffer: ddcommon_ffi::Vec<u8>,
-    endpoints_stats: Box<ProfiledEndpointsStats>,
+impl PreinternedIds {
+    fn new(ids: internal::PreinternedIds) -> Self {
+        PreinternedIds {
+            inner: Box::into_raw(Box::new(ids)),
+        }
+    }
+
+    fn take(&mut self) -> Option<Box<internal::PreinternedIds>> {
+        let raw = std::mem::replace(&mut self.inner, std::ptr::null_mut());
+
+        if raw.is_null() {
+            None
+        } else {
+            Some(unsafe { Box::from_raw(raw) })
+        }
+    }
 }
 
-/// # Safety
-/// Only pass a reference to a valid `ddog_prof_EncodedProfile`, or null. A
-/// valid reference also means that it hasn't already been dropped (do not
-/// call this twice on the same object).
-#[no_mangle]
-pub unsafe extern "C" fn ddog_prof_EncodedProfile_drop(profile: Option<&mut EncodedProfile>) {
-    if let Some(reference) = profile {
-        // Safety: EncodedProfile's are repr(C), and not box allocated. If the
-        // user has followed the safety requirements of this function, then
-        // this is safe.
-        std::ptr::drop_in_place(reference as *mut _)
+impl Drop for PreinternedIds {
+    fn drop(&mut self) {
+        drop(self.take())
     }
 }
 
-impl From<internal::EncodedProfile> for EncodedProfile {
-    fn from(value: internal::EncodedProfile) -> Self {
-        let start = value.start.into();
-        let end = value.end.into();
-        let buffer = value.buffer.into();
-        let endpoints_stats = Box::new(value.endpoints_stats);
+/// Returned by [ddog_prof_Profile_preintern].
+#[allow(dead_code)]
+#[repr(C)]
+pub enum PreinternResult {
+    Ok(PreinternedIds),
+    Err(Error),
+}
 
-        Self {
-            start,
-            end,
-            buffer,
-            endpoints_stats,
+impl From<anyhow::Result<internal::PreinternedIds>> for PreinternResult {
+    fn from(value: anyhow::Result<internal::PreinternedIds>) -> Self {
+        match value {
+            Ok(ids) => Self::Ok(PreinternedIds::new(ids)),
+            Err(err) => Self::Err(err.into()),
         }
     }
 }
 
-/// Serialize the aggregated profile.
-/// Drains the data, and then resets the profile for future use.
+/// A location of a stack trace passed to `ddog_prof_Profile_intern_preinterned_stacktrace`.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct PreinternedLocation {
+    /// The position of the function in the `functions` passed to `ddog_prof_Profile_preintern`,
+    /// plus one. Leave it at 0 for locations which are only known by their mapping and address.
+    pub function: usize,
+    /// The position of the mapping in the `mappings` passed to `ddog_prof_Profile_preintern`,
+    /// plus one. Leave it at 0 for locations without a mapping.
+    pub mapping: usize,
+    pub address: u64,
+    pub line: i64,
+}
+
+fn preinterned_ids_ptr_to_inner<'a>(
+    ids: &'a PreinternedIds,
+) -> anyhow::Result<&'a internal::PreinternedIds> {
+    // Safety: the pointer is either null or points to ids made by ddog_prof_Profile_preintern.
+    unsafe { ids.inner.as_ref() }
+        .context("preinterned ids' inner pointer was null (indicates use-after-free)")
+}
+
+/// Resolves the 1-based `position` of a [PreinternedLocation] field, 0 meaning none.
+fn preinterned_id<T: Copy>(ids: &[T], position: usize, what: &str) -> anyhow::Result<Option<T>> {
+    match position {
+        0 => Ok(None),
+        n => ids
+            .get(n - 1)
+            .copied()
+            .map(Some)
+            .with_context(|| format!("{what} {n} was not preinterned")),
+    }
+}
+
+/// Interns the `strings`, `functions` and `mappings` a runtime knows it will use up front, so
+/// that the first samples referencing them don't pay for growing the tables. This is meant to be
+/// called right after the profile is created or reset, as resetting drops all interned items.
 ///
-/// Don't forget to clean up the ok with `ddog_prof_EncodedProfile_drop` or
+/// The returned ids are used to build stack traces with
+/// `ddog_prof_Profile_intern_preinterned_stacktrace`. They are only valid for this profile until
+/// it is reset; `ddog_prof_Profile_check_preinterned` tells whether cached ids can still be used.
+/// They must be dropped with `ddog_prof_PreinternedIds_drop`.
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module. All pointers inside the `strings`, `functions` and `mappings` need to be valid for
+/// the duration of this call.
+/// This call is _NOT_ thread-safe.
+#[must_use]
+#[no_mangle]
+pub unsafe extern "C" fn ddog_prof_Profile_preintern(
+    profile: *mut Profile,
+    strings: Slice<CharSlice>,
+    functions: Slice<Function>,
+    mappings: Slice<Mapping>,
+) -> PreinternResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        let strings = strings
+            .as_slice()
+            .iter()
+            .map(|string| string.try_to_utf8())
+            .collect::<Result<Vec<_>, _>>()?;
+        let functions = functions
+            .as_slice()
+            .iter()
+            .map(api::Function::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mappings = mappings
+            .as_slice()
+            .iter()
+            .map(api::Mapping::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        profile.preintern(&api::InternSchema {
+            strings: &strings,
+            functions: &functions,
+            mappings: &mappings,
+        })
+    })()
+    .context("ddog_prof_Profile_preintern failed")
+    .into()
+}
+
+/// # Safety
+/// The `ids` can be null, but if non-null they must point to PreinternedIds made by this module,
+/// which have not previously been dropped.
+#[no_mangle]
+pub unsafe extern "C" fn ddog_prof_PreinternedIds_drop(ids: *mut PreinternedIds) {
+    if !ids.is_null() {
+        drop((*ids).take())
+    }
+}
+
+/// Fails with an error if the `ids` were not returned by `ddog_prof_Profile_preintern` on this
+/// profile since it was last reset. Bindings caching the ids across resets use this to find out
+/// whether the schema needs to be preinterned again: stale ids would otherwise refer to whatever
+/// was interned at the same position after the reset.
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module, and `ids` to ids made by `ddog_prof_Profile_preintern`.
+/// This call is _NOT_ thread-safe.
+#[must_use]
+#[no_mangle]
+pub unsafe extern "C" fn ddog_prof_Profile_check_preinterned(
+    profile: *mut Profile,
+    ids: &PreinternedIds,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        profile.ensure_preinterned(preinterned_ids_ptr_to_inner(ids)?)
+    })()
+    .context("ddog_prof_Profile_check_preinterned failed")
+    .into()
+}
+
+/// Same as `ddog_prof_Profile_intern_stacktrace`, for locations made of the `ids` returned by
+/// `ddog_prof_Profile_preintern`, which skips looking up their functions and mappings again.
+/// Fails if the `ids` are stale, see `ddog_prof_Profile_check_preinterned`, or if a location
+/// refers to a function or mapping which was not preinterned.
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module, and `ids` to ids made by `ddog_prof_Profile_preintern`.
+/// This call is _NOT_ thread-safe.
+#[must_use]
+#[no_mangle]
+pub unsafe extern "C" fn ddog_prof_Profile_intern_preinterned_stacktrace(
+    profile: *mut Profile,
+    ids: &PreinternedIds,
+    locations: Slice<PreinternedLocation>,
+) -> InternStackTraceResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        let ids = preinterned_ids_ptr_to_inner(ids)?;
+        let locations = locations
+            .as_slice()
+            .iter()
+            .map(|location| {
+                anyhow::Ok(internal::PreinternedLocation {
+                    function: preinterned_id(&ids.functions, location.function, "function")?,
+                    mapping: preinterned_id(&ids.mappings, location.mapping, "mapping")?,
+                    address: location.address,
+                    line: location.line,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        profile.intern_preinterned_stacktrace(ids, &locations)
+    })()
+    .context("ddog_prof_Profile_intern_preinterned_stacktrace failed")
+    .into()
+}
+
+/// Returns the labels of the sample context `context_id`. The labels, and the strings they point
+/// to, must remain valid until the callback is invoked again or the serialization ends. Labels
+/// which are not valid UTF-8 are skipped.
+///
+/// It is invoked during serialization, which may happen on the serialization thread of
+/// `ddog_prof_Profile_serialize_async`.
+pub type ContextProviderCallback =
+    extern "C" fn(context_id: u64, user_data: *mut c_void) -> Slice<'static, Label<'static>>;
+
+struct ContextProviderUserData(*mut c_void);
+
+impl ContextProviderUserData {
+    fn as_ptr(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+// Safety: the user data is only handed back to the callback, the caller of
+// ddog_prof_Profile_set_context_provider is responsible for it being usable from other threads.
+unsafe impl Send for ContextProviderUserData {}
+unsafe impl Sync for ContextProviderUserData {}
+
+/// Registers the provider of the labels for samples added with
+/// `ddog_prof_Profile_add_with_context`. The provider is kept when the profile is reset.
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module. The `user_data` must remain valid as long as the profile, or any profile reset from
+/// it, may be serialized.
+/// This call is _NOT_ thread-safe.
+#[must_use]
+#[no_mangle]
+pub unsafe extern "C" fn ddog_prof_Profile_set_context_provider(
+    profile: *mut Profile,
+    callback: ContextProviderCallback,
+    user_data: *mut c_void,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        let user_data = ContextProviderUserData(user_data);
+        profile.set_context_provider(Arc::new(
+            move |context_id: u64, add_label: &mut dyn FnMut(api::Label)| {
+                let labels = callback(context_id, user_data.as_ptr());
+                for label in labels.as_slice().iter() {
+                    if let Ok(label) = api::Label::try_from(label) {
+                        add_label(label);
+                    }
+                }
+            },
+        ));
+        anyhow::Ok(())
+    })()
+    .context("ddog_prof_Profile_set_context_provider failed")
+    .into()
+}
+
+unsafe fn profile_ptr_to_inner<'a>(
+    profile_ptr: *mut Profile,
+) -> anyhow::Result<&'a mut internal::Profile> {
+    match profile_ptr.as_mut() {
+        None => anyhow::bail!("profile pointer was null"),
+        Some(inner_ptr) => match inner_ptr.inner.as_mut() {
+            Some(profile) => Ok(profile),
+            None => anyhow::bail!("profile's inner pointer was null (indicates use-after-free)"),
+        },
+    }
+}
+
+/// Associate an endpoint to a given local root span id.
+/// During the serialization of the profile, an endpoint label will be added
+/// to all samples that contain a matching local root span id label.
+///
+/// Note: calling this API causes the "trace endpoint" and "local root span id" strings
+/// to be interned, even if no matching sample is found.
+///
+/// # Arguments
+/// * `profile` - a reference to the profile that will contain the samples.
+/// * `local_root_span_id`
+/// * `endpoint` - the value of the endpoint label to add for matching samples.
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module.
+/// This call is _NOT_ thread-safe.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_Profile_set_endpoint(
+    profile: *mut Profile,
+    local_root_span_id: u64,
+    endpoint: CharSlice,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        let endpoint = char_slice_to_utf8(&endpoint, "endpoint")?;
+        profile.add_endpoint(local_root_span_id, endpoint)
+    })()
+    .context("ddog_prof_Profile_set_endpoint failed")
+    .into()
+}
+
+/// Same as `ddog_prof_Profile_set_endpoint` for many local root spans at once, to be preferred
+/// when there are many local root spans per profile. `local_root_span_ids` and `endpoints` are
+/// parallel slices: the endpoint at index i is associated with the span id at index i.
+///
+/// # Arguments
+/// * `profile` - a reference to the profile that will contain the samples.
+/// * `local_root_span_ids`
+/// * `endpoints` - the values of the endpoint labels, one per local root span id.
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module. All slices must be valid for the duration of this call.
+/// This call is _NOT_ thread-safe.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_Profile_add_endpoint_batch(
+    profile: *mut Profile,
+    local_root_span_ids: Slice<u64>,
+    endpoints: Slice<CharSlice>,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        let local_root_span_ids = local_root_span_ids.as_slice();
+        let endpoints = endpoints.as_slice();
+        anyhow::ensure!(
+            local_root_span_ids.len() == endpoints.len(),
+            "got {} local root span ids but {} endpoints",
+            local_root_span_ids.len(),
+            endpoints.len()
+        );
+        if strict_validation() {
+            for (i, endpoint) in endpoints.iter().enumerate() {
+                check_utf8(endpoint, || format!("endpoints[{i}]"))?;
+            }
+        }
+        profile.add_endpoints(
+            local_root_span_ids
+                .iter()
+                .zip(endpoints)
+                .map(|(id, endpoint)| (*id, endpoint.to_utf8_lossy())),
+        )
+    })()
+    .context("ddog_prof_Profile_add_endpoint_batch failed")
+    .into()
+}
+
+/// Registers the time a local root span was running, so that timestamped samples taken within
+/// it, but without a "local root span id" label, get the endpoint set for the span with
+/// `ddog_prof_Profile_set_endpoint`. Samples within the intervals of several spans only get an
+/// endpoint if the spans agree on it.
+///
+/// # Arguments
+/// * `profile` - a reference to the profile that will contain the samples.
+/// * `local_root_span_id`
+/// * `start` - when the span started, in nanoseconds since the epoch.
+/// * `end` - when the span ended, in nanoseconds since the epoch.
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module.
+/// This call is _NOT_ thread-safe.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_Profile_add_span_interval(
+    profile: *mut Profile,
+    local_root_span_id: u64,
+    start: NonZeroI64,
+    end: NonZeroI64,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        profile.add_span_interval(local_root_span_id, start, end)
+    })()
+    .context("ddog_prof_Profile_add_span_interval failed")
+    .into()
+}
+
+/// Computes a fingerprint of the profile state, which only changes when data is added to the
+/// profile. Comparing it to the `state_fingerprint` of the serialized profile allows detecting
+/// that the profile memory was corrupted before uploading it.
+///
+/// # Arguments
+/// * `profile` - a reference to the profile.
+/// * `fingerprint` - receives the fingerprint on success.
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module.
+/// This call is _NOT_ thread-safe.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_Profile_state_fingerprint(
+    profile: *mut Profile,
+    fingerprint: &mut u64,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        *fingerprint = profile.state_fingerprint();
+        anyhow::Ok(())
+    })()
+    .context("ddog_prof_Profile_state_fingerprint failed")
+    .into()
+}
+
+/// Renders the samples of the profile as folded stacks, the text format of flamegraph tools: one
+/// line per distinct stack, from the root frame to the leaf separated by `;`, followed by the sum
+/// of the values of the given sample type. This is meant for inspecting profiles locally, the
+/// profile is left as is. The `collapsed` text must be dropped with `ddog_Vec_U8_drop`.
+///
+/// # Arguments
+/// * `profile` - a reference to the profile.
+/// * `sample_type` - the type of the values to sum, e.g. "cpu-time".
+/// * `collapsed` - receives the text on success.
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module.
+/// This call is _NOT_ thread-safe.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_Profile_to_collapsed(
+    profile: *mut Profile,
+    sample_type: CharSlice,
+    collapsed: &mut ddcommon_ffi::Vec<u8>,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        let sample_type = char_slice_to_utf8(&sample_type, "sample_type")?;
+        *collapsed = profile.to_collapsed(&sample_type)?.into_bytes().into();
+        anyhow::Ok(())
+    })()
+    .context("ddog_prof_Profile_to_collapsed failed")
+    .into()
+}
+
+/// Returns the lifecycle state of the profile. Samples, endpoints and upscaling rules can only be
+/// added while it is `DDOG_PROF_PROFILE_STATE_OPEN`, other calls fail with an error.
+///
+/// # Arguments
+/// * `profile` - a reference to the profile.
+/// * `state` - receives the state on success.
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module.
+/// This call is _NOT_ thread-safe.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_Profile_state(
+    profile: *mut Profile,
+    state: &mut internal::ProfileState,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        *state = profile.state();
+        anyhow::Ok(())
+    })()
+    .context("ddog_prof_Profile_state failed")
+    .into()
+}
+
+/// Limits the number of frames kept per sample. Deeper stacks are truncated when the sample is
+/// added: the `max_frames` frames on the leaf side are kept and a "[truncated]" frame is appended.
+/// The limit is kept when the profile is reset.
+///
+/// # Arguments
+/// * `profile` - a reference to the profile.
+/// * `max_frames` - the maximum number of frames per sample, 0 to keep all frames (the default).
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module.
+/// This call is _NOT_ thread-safe.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_Profile_set_max_frames(
+    profile: *mut Profile,
+    max_frames: usize,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        profile.set_max_frames(std::num::NonZeroUsize::new(max_frames));
+        anyhow::Ok(())
+    })()
+    .context("ddog_prof_Profile_set_max_frames failed")
+    .into()
+}
+
+/// Makes the serialized pprofs canonical, so that profiles holding the same data are encoded to
+/// the same bytes whatever the order the data was added in, e.g. for tests comparing the output of
+/// several bindings. Serializing is slower with this enabled. The setting is kept when the profile
+/// is reset.
+///
+/// # Arguments
+/// * `profile` - a reference to the profile.
+/// * `enabled` - whether to encode deterministically, false by default.
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module.
+/// This call is _NOT_ thread-safe.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_Profile_set_deterministic_encoding(
+    profile: *mut Profile,
+    enabled: bool,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        profile.set_deterministic_encoding(enabled);
+        anyhow::Ok(())
+    })()
+    .context("ddog_prof_Profile_set_deterministic_encoding failed")
+    .into()
+}
+
+/// Keeps the upscaling rules when the profile is reset, so that they don't need to be added again
+/// for each profile. The setting is kept when the profile is reset.
+///
+/// # Arguments
+/// * `profile` - a reference to the profile.
+/// * `enabled` - whether to keep the rules, false by default.
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module.
+/// This call is _NOT_ thread-safe.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_Profile_set_preserve_upscaling_rules(
+    profile: *mut Profile,
+    enabled: bool,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        profile.set_preserve_upscaling_rules(enabled);
+        anyhow::Ok(())
+    })()
+    .context("ddog_prof_Profile_set_preserve_upscaling_rules failed")
+    .into()
+}
+
+/// Keeps the endpoints of the local root spans which may still be running when the profile is
+/// reset, see `ddog_prof_Profile_set_endpoint`. Spans with a span interval ending before the reset
+/// are done and their endpoints are dropped. The setting is kept when the profile is reset.
+///
+/// # Arguments
+/// * `profile` - a reference to the profile.
+/// * `enabled` - whether to keep the endpoints, false by default.
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module.
+/// This call is _NOT_ thread-safe.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_Profile_set_preserve_endpoints(
+    profile: *mut Profile,
+    enabled: bool,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        profile.set_preserve_endpoints(enabled);
+        anyhow::Ok(())
+    })()
+    .context("ddog_prof_Profile_set_preserve_endpoints failed")
+    .into()
+}
+
+/// Disables or re-enables a sample type, e.g. to turn off an expensive dimension at runtime. The
+/// values of disabled sample types are zeroed when adding samples and the sample type is left out
+/// of the serialized pprof, while samples are still added with all their values. The setting is
+/// kept when the profile is reset.
+///
+/// # Arguments
+/// * `profile` - a reference to the profile.
+/// * `offset` - the offset of the sample type, in the sample types of the profile.
+/// * `enabled` - whether the sample type is enabled, all of them are by default.
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module.
+/// This call is _NOT_ thread-safe.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_Profile_set_sample_type_enabled(
+    profile: *mut Profile,
+    offset: usize,
+    enabled: bool,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        profile.set_sample_type_enabled(offset, enabled)
+    })()
+    .context("ddog_prof_Profile_set_sample_type_enabled failed")
+    .into()
+}
+
+/// Records the time spent in libdatadog as samples of the profile, under a "[profiler]" root
+/// frame with a leaf frame per operation, so that the overhead of profiling shows up in the
+/// profile itself. Adding samples and `ddog_prof_Profile_serialize` are recorded automatically,
+/// other operations, e.g. uploads, with `ddog_prof_Profile_record_overhead`. The setting is kept
+/// when the profile is reset.
+///
+/// # Arguments
+/// * `profile` - a reference to the profile.
+/// * `offset` - the offset of the sample type holding the time, which should be a time in
+///   nanoseconds, e.g. "wall-time".
+/// * `enabled` - whether the time is recorded, it is not by default.
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module.
+/// This call is _NOT_ thread-safe.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_Profile_set_overhead_sample_type(
+    profile: *mut Profile,
+    offset: usize,
+    enabled: bool,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        profile.set_overhead_sample_type(enabled.then_some(offset))
+    })()
+    .context("ddog_prof_Profile_set_overhead_sample_type failed")
+    .into()
+}
+
+/// Adds the time spent in `operation` to the profile, if enabled with
+/// `ddog_prof_Profile_set_overhead_sample_type`, e.g. the time spent uploading the previous
+/// profile.
+///
+/// # Arguments
+/// * `profile` - a reference to the profile.
+/// * `operation` - the operation the time was spent in.
+/// * `duration_nanos` - the time spent.
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module.
+/// This call is _NOT_ thread-safe.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_Profile_record_overhead(
+    profile: *mut Profile,
+    operation: internal::ProfilerOperation,
+    duration_nanos: u64,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        profile.record_overhead(operation, Duration::from_nanos(duration_nanos));
+        anyhow::Ok(())
+    })()
+    .context("ddog_prof_Profile_record_overhead failed")
+    .into()
+}
+
+/// Reads the statistics of the memory allocator of the process, if it is jemalloc or mimalloc, and
+/// adds them to the profile as a sample timestamped with `timestamp`. The profile must have at
+/// least one of the "heap-allocated", "heap-resident" and "heap-fragmentation" sample types, in
+/// bytes. Meant to be called periodically, e.g. before each serialization.
+///
+/// # Arguments
+/// * `profile` - a reference to the profile.
+/// * `timestamp` - the time the statistics are read at.
+/// * `added` - receives whether the allocator is supported and the sample was added, on success.
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module.
+/// This call is _NOT_ thread-safe.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_Profile_add_allocator_stats(
+    profile: *mut Profile,
+    timestamp: NonZeroI64,
+    added: &mut bool,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        *added = AllocatorStats::sample(profile, timestamp)?;
+        anyhow::Ok(())
+    })()
+    .context("ddog_prof_Profile_add_allocator_stats failed")
+    .into()
+}
+
+/// Gets the number of samples added since the last reset whose stack was truncated, see
+/// `ddog_prof_Profile_set_max_frames`.
+///
+/// # Arguments
+/// * `profile` - a reference to the profile.
+/// * `count` - receives the number of truncated stacks on success.
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module.
+/// This call is _NOT_ thread-safe.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_Profile_truncated_stacks_count(
+    profile: *mut Profile,
+    count: &mut u64,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        *count = profile.truncated_stacks_count();
+        anyhow::Ok(())
+    })()
+    .context("ddog_prof_Profile_truncated_stacks_count failed")
+    .into()
+}
+
+/// Count the number of times an endpoint has been seen.
+///
+/// # Arguments
+/// * `profile` - a reference to the profile that will contain the samples.
+/// * `endpoint` - the endpoint label for which the count will be incremented
+///
+/// # Safety
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module.
+/// This call is _NOT_ thread-safe.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_Profile_add_endpoint_count(
+    profile: *mut Profile,
+    endpoint: CharSlice,
+    value: i64,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        let endpoint = char_slice_to_utf8(&endpoint, "endpoint")?;
+        profile.add_endpoint_count(endpoint, value)
+    })()
+    .context("ddog_prof_Profile_set_endpoint failed")
+    .into()
+}
+
+/// Add a poisson-based upscaling rule which will be use to adjust values and make them
+/// closer to reality.
+///
+/// # Arguments
+/// * `profile` - a reference to the profile that will contain the samples.
+/// * `offset_values` - offset of the values
+/// * `label_name` - name of the label used to identify sample(s)
+/// * `label_value` - value of the label used to identify sample(s)
+/// * `sum_value_offset` - offset of the value used as a sum (compute the average with
+///   `count_value_offset`)
+/// * `count_value_offset` - offset of the value used as a count (compute the average with
+///   `sum_value_offset`)
+/// * `sampling_distance` - this is the threshold for this sampling window. This value must not be
+///   equal to 0
+///
+/// # Safety
+/// This function must be called before serialize and must not be called after.
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module.
+/// This call is _NOT_ thread-safe.
+#[must_use]
+#[no_mangle]
+pub unsafe extern "C" fn ddog_prof_Profile_add_upscaling_rule_poisson(
+    profile: *mut Profile,
+    offset_values: Slice<usize>,
+    label_name: CharSlice,
+    label_value: CharSlice,
+    sum_value_offset: usize,
+    count_value_offset: usize,
+    sampling_distance: u64,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        anyhow::ensure!(sampling_distance != 0, "sampling_distance must not be 0");
+        let upscaling_info = api::UpscalingInfo::Poisson {
+            sum_value_offset,
+            count_value_offset,
+            sampling_distance,
+        };
+        add_upscaling_rule(
+            profile,
+            offset_values,
+            label_name,
+            label_value,
+            upscaling_info,
+        )
+    })()
+    .context("ddog_prof_Profile_add_upscaling_rule_proportional failed")
+    .into()
+}
+
+/// Add a proportional-based upscaling rule which will be use to adjust values and make them
+/// closer to reality.
+///
+/// # Arguments
+/// * `profile` - a reference to the profile that will contain the samples.
+/// * `offset_values` - offset of the values
+/// * `label_name` - name of the label used to identify sample(s)
+/// * `label_value` - value of the label used to identify sample(s)
+/// * `total_sampled` - number of sampled event (found in the pprof). This value must not be equal
+///   to 0
+/// * `total_real` - number of events the profiler actually witnessed. This value must not be equal
+///   to 0
+///
+/// # Safety
+/// This function must be called before serialize and must not be called after.
+/// The `profile` ptr must point to a valid Profile object created by this
+/// module.
+/// This call is _NOT_ thread-safe.
+#[must_use]
+#[no_mangle]
+pub unsafe extern "C" fn ddog_prof_Profile_add_upscaling_rule_proportional(
+    profile: *mut Profile,
+    offset_values: Slice<usize>,
+    label_name: CharSlice,
+    label_value: CharSlice,
+    total_sampled: u64,
+    total_real: u64,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        anyhow::ensure!(total_sampled != 0, "total_sampled must not be 0");
+        anyhow::ensure!(total_real != 0, "total_real must not be 0");
+        let upscaling_info = api::UpscalingInfo::Proportional {
+            scale: total_real as f64 / total_sampled as f64,
+        };
+        add_upscaling_rule(
+            profile,
+            offset_values,
+            label_name,
+            label_value,
+            upscaling_info,
+        )
+    })()
+    .context("ddog_prof_Profile_add_upscaling_rule_proportional failed")
+    .into()
+}
+
+/// Same as `ddog_prof_Profile_add_upscaling_rule_poisson`, for the samples with a numeric
+/// `label_name` label within [`range_start`, `range_end`), e.g. an allocation size.
+///
+/// # Safety
+/// Same as `ddog_prof_Profile_add_upscaling_rule_poisson`.
+#[must_use]
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn ddog_prof_Profile_add_upscaling_rule_poisson_for_range(
+    profile: *mut Profile,
+    offset_values: Slice<usize>,
+    label_name: CharSlice,
+    range_start: i64,
+    range_end: i64,
+    sum_value_offset: usize,
+    count_value_offset: usize,
+    sampling_distance: u64,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        anyhow::ensure!(sampling_distance != 0, "sampling_distance must not be 0");
+        let upscaling_info = api::UpscalingInfo::Poisson {
+            sum_value_offset,
+            count_value_offset,
+            sampling_distance,
+        };
+        let label_name = char_slice_to_utf8(&label_name, "label_name")?;
+        profile.add_upscaling_rule_for_range(
+            offset_values.as_slice(),
+            &label_name,
+            range_start..range_end,
+            upscaling_info,
+        )
+    })()
+    .context("ddog_prof_Profile_add_upscaling_rule_poisson_for_range failed")
+    .into()
+}
+
+/// Same as `ddog_prof_Profile_add_upscaling_rule_proportional`, for the samples with a numeric
+/// `label_name` label within [`range_start`, `range_end`), e.g. an allocation size.
+///
+/// # Safety
+/// Same as `ddog_prof_Profile_add_upscaling_rule_proportional`.
+#[must_use]
+#[no_mangle]
+pub unsafe extern "C" fn ddog_prof_Profile_add_upscaling_rule_proportional_for_range(
+    profile: *mut Profile,
+    offset_values: Slice<usize>,
+    label_name: CharSlice,
+    range_start: i64,
+    range_end: i64,
+    total_sampled: u64,
+    total_real: u64,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        anyhow::ensure!(total_sampled != 0, "total_sampled must not be 0");
+        anyhow::ensure!(total_real != 0, "total_real must not be 0");
+        let upscaling_info = api::UpscalingInfo::Proportional {
+            scale: total_real as f64 / total_sampled as f64,
+        };
+        let label_name = char_slice_to_utf8(&label_name, "label_name")?;
+        profile.add_upscaling_rule_for_range(
+            offset_values.as_slice(),
+            &label_name,
+            range_start..range_end,
+            upscaling_info,
+        )
+    })()
+    .context("ddog_prof_Profile_add_upscaling_rule_proportional_for_range failed")
+    .into()
+}
+
+unsafe fn add_upscaling_rule(
+    profile: &mut internal::Profile,
+    offset_values: Slice<usize>,
+    label_name: CharSlice,
+    label_value: CharSlice,
+    upscaling_info: api::UpscalingInfo,
+) -> anyhow::Result<()> {
+    let label_name_n = char_slice_to_utf8(&label_name, "label_name")?;
+    let label_value_n = char_slice_to_utf8(&label_value, "label_value")?;
+    profile.add_upscaling_rule(
+        offset_values.as_slice(),
+        label_name_n.as_ref(),
+        label_value_n.as_ref(),
+        upscaling_info,
+    )
+}
+
+/// An encoded profile, as returned by the serialization. Do not access its member for any reason,
+/// only use the `ddog_prof_EncodedProfile_*` functions on this struct.
+#[repr(C)]
+pub struct EncodedProfile {
+    // This may be null, but if not it will point to a valid EncodedProfile.
+    inner: *mut internal::EncodedProfile,
+}
+
+impl EncodedProfile {
+    fn take(&mut self) -> Option<Box<internal::EncodedProfile>> {
+        // Leaving a null will help with double-free issues that can
+        // arise in C.
+        let raw = std::mem::replace(&mut self.inner, std::ptr::null_mut());
+
+        if raw.is_null() {
+            None
+        } else {
+            Some(unsafe { Box::from_raw(raw) })
+        }
+    }
+}
+
+/// # Safety
+/// Only pass a reference to a valid `ddog_prof_EncodedProfile`, or null.
+/// Dropping it twice is harmless, but should not be relied on.
+#[no_mangle]
+pub unsafe extern "C" fn ddog_prof_EncodedProfile_drop(profile: Option<&mut EncodedProfile>) {
+    if let Some(reference) = profile {
+        drop(reference.take())
+    }
+}
+
+impl From<internal::EncodedProfile> for EncodedProfile {
+    fn from(value: internal::EncodedProfile) -> Self {
+        EncodedProfile {
+            inner: Box::into_raw(Box::new(value)),
+        }
+    }
+}
+
+unsafe fn encoded_profile_ptr_to_inner<'a>(
+    encoded_profile_ptr: *const EncodedProfile,
+) -> anyhow::Result<&'a internal::EncodedProfile> {
+    match encoded_profile_ptr.as_ref() {
+        None => anyhow::bail!("encoded profile pointer was null"),
+        Some(inner_ptr) => match inner_ptr.inner.as_ref() {
+            Some(encoded_profile) => Ok(encoded_profile),
+            None => {
+                anyhow::bail!("encoded profile's inner pointer was null (indicates use-after-free)")
+            }
+        },
+    }
+}
+
+/// Returns the pprof of the encoded profile. The slice is valid until the encoded profile is
+/// dropped.
+///
+/// # Safety
+/// The `encoded_profile` ptr must point to a valid EncodedProfile.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_EncodedProfile_get_buffer_slice<'a>(
+    encoded_profile: *const EncodedProfile,
+    buffer: &mut ByteSlice<'a>,
+) -> ProfileResult {
+    (|| {
+        let encoded_profile: &'a internal::EncodedProfile =
+            encoded_profile_ptr_to_inner(encoded_profile)?;
+        *buffer = encoded_profile.buffer.as_slice().into();
+        anyhow::Ok(())
+    })()
+    .context("ddog_prof_EncodedProfile_get_buffer_slice failed")
+    .into()
+}
+
+/// Moves the pprof out of the encoded profile, without copying it, leaving an empty buffer behind.
+/// This lets bindings hand the buffer over to their own memory management, it must eventually be
+/// dropped with `ddog_Vec_U8_drop`. The `buffer` must be a valid Vec, e.g. from `ddog_Vec_U8_new`,
+/// as it is dropped when overwritten.
+///
+/// # Safety
+/// The `encoded_profile` ptr must point to a valid EncodedProfile.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_EncodedProfile_take_buffer(
+    encoded_profile: *mut EncodedProfile,
+    buffer: &mut ddcommon_ffi::Vec<u8>,
+) -> ProfileResult {
+    (|| {
+        let encoded_profile = match encoded_profile.as_mut() {
+            None => anyhow::bail!("encoded profile pointer was null"),
+            Some(inner_ptr) => match inner_ptr.inner.as_mut() {
+                Some(encoded_profile) => encoded_profile,
+                None => anyhow::bail!(
+                    "encoded profile's inner pointer was null (indicates use-after-free)"
+                ),
+            },
+        };
+        *buffer = std::mem::take(&mut encoded_profile.buffer).into();
+        anyhow::Ok(())
+    })()
+    .context("ddog_prof_EncodedProfile_take_buffer failed")
+    .into()
+}
+
+/// Returns the start time of the encoded profile.
+///
+/// # Safety
+/// The `encoded_profile` ptr must point to a valid EncodedProfile.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_EncodedProfile_get_start(
+    encoded_profile: *const EncodedProfile,
+    start: &mut Timespec,
+) -> ProfileResult {
+    (|| {
+        let encoded_profile = encoded_profile_ptr_to_inner(encoded_profile)?;
+        *start = encoded_profile.start.into();
+        anyhow::Ok(())
+    })()
+    .context("ddog_prof_EncodedProfile_get_start failed")
+    .into()
+}
+
+/// Returns the end time of the encoded profile.
+///
+/// # Safety
+/// The `encoded_profile` ptr must point to a valid EncodedProfile.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_EncodedProfile_get_end(
+    encoded_profile: *const EncodedProfile,
+    end: &mut Timespec,
+) -> ProfileResult {
+    (|| {
+        let encoded_profile = encoded_profile_ptr_to_inner(encoded_profile)?;
+        *end = encoded_profile.end.into();
+        anyhow::Ok(())
+    })()
+    .context("ddog_prof_EncodedProfile_get_end failed")
+    .into()
+}
+
+/// Returns the endpoints stats of the encoded profile, to be passed to
+/// `ddog_prof_Exporter_Request_build`. They are valid until the encoded profile is dropped.
+///
+/// # Safety
+/// The `encoded_profile` ptr must point to a valid EncodedProfile.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_EncodedProfile_get_endpoints_stats<'a>(
+    encoded_profile: *const EncodedProfile,
+    endpoints_stats: &mut Option<&'a ProfiledEndpointsStats>,
+) -> ProfileResult {
+    (|| {
+        let encoded_profile: &'a internal::EncodedProfile =
+            encoded_profile_ptr_to_inner(encoded_profile)?;
+        *endpoints_stats = Some(&encoded_profile.endpoints_stats);
+        anyhow::Ok(())
+    })()
+    .context("ddog_prof_EncodedProfile_get_endpoints_stats failed")
+    .into()
+}
+
+/// Returns the endpoints stats of the encoded profile as JSON, mapping each endpoint to its
+/// count. The `json` must be dropped with `ddog_Vec_U8_drop`.
+///
+/// # Safety
+/// The `encoded_profile` ptr must point to a valid EncodedProfile.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_EncodedProfile_get_endpoints_stats_json(
+    encoded_profile: *const EncodedProfile,
+    json: &mut ddcommon_ffi::Vec<u8>,
+) -> ProfileResult {
+    (|| {
+        let encoded_profile = encoded_profile_ptr_to_inner(encoded_profile)?;
+        *json = serde_json::to_vec(&encoded_profile.endpoints_stats)?.into();
+        anyhow::Ok(())
+    })()
+    .context("ddog_prof_EncodedProfile_get_endpoints_stats_json failed")
+    .into()
+}
+
+/// Returns the `state_fingerprint` of the profile right before it was serialized, see
+/// `ddog_prof_Profile_state_fingerprint`.
+///
+/// # Safety
+/// The `encoded_profile` ptr must point to a valid EncodedProfile.
+#[no_mangle]
+#[must_use]
+pub unsafe extern "C" fn ddog_prof_EncodedProfile_get_state_fingerprint(
+    encoded_profile: *const EncodedProfile,
+    fingerprint: &mut u64,
+) -> ProfileResult {
+    (|| {
+        let encoded_profile = encoded_profile_ptr_to_inner(encoded_profile)?;
+        *fingerprint = encoded_profile.state_fingerprint;
+        anyhow::Ok(())
+    })()
+    .context("ddog_prof_EncodedProfile_get_state_fingerprint failed")
+    .into()
+}
+
+/// Serialize the aggregated profile.
+/// Drains the data, and then resets the profile for future use.
+///
+/// Don't forget to clean up the ok with `ddog_prof_EncodedProfile_drop` or
 /// the error variant with `ddog_Error_drop` when you are done with them.
 ///
 /// # Arguments
@@ -704,16 +1948,123 @@ pub unsafe extern "C" fn ddog_prof_Profile_serialize(
             Some(x) if *x < 0 => None,
             Some(x) => Some(Duration::from_nanos((*x) as u64)),
         };
-        old_profile.serialize_into_compressed_pprof(end_time, duration)
+        let start = Instant::now();
+        let encoded = old_profile.serialize_into_compressed_pprof(end_time, duration);
+        profile.record_overhead(internal::ProfilerOperation::Serialize, start.elapsed());
+        encoded
     })()
     .context("ddog_prof_Profile_serialize failed")
     .into()
 }
 
+/// Invoked on the libdatadog serialization thread once an asynchronous serialization finished.
+/// The callee takes ownership of the `result`, so it must clean up the ok variant with
+/// `ddog_prof_EncodedProfile_drop` or the error variant with `ddog_Error_drop`. It must not block
+/// for long, as subsequent serializations are queued behind it.
+pub type SerializeCallback = extern "C" fn(result: SerializeResult, user_data: *mut c_void);
+
+struct SerializeJob {
+    profile: internal::Profile,
+    end_time: Option<SystemTime>,
+    duration: Option<Duration>,
+    callback: SerializeCallback,
+    user_data: UserData,
+}
+
+struct UserData(*mut c_void);
+
+// Safety: the user data is only handed back to the callback, the caller of
+// ddog_prof_Profile_serialize_async is responsible for it being usable from another thread.
+unsafe impl Send for UserData {}
+
+/// Returns the sender for the serialization worker thread, spawning it on first use.
+fn serialize_worker() -> anyhow::Result<Sender<SerializeJob>> {
+    static WORKER: OnceLock<Mutex<Sender<SerializeJob>>> = OnceLock::new();
+    if let Some(sender) = WORKER.get() {
+        return Ok(sender
+            .lock()
+            .map_err(|_| anyhow::anyhow!("lock poisoned"))?
+            .clone());
+    }
+
+    let (sender, receiver) = mpsc::channel::<SerializeJob>();
+    std::thread::Builder::new()
+        .name("ddprof-serialize".to_string())
+        .spawn(move || {
+            while let Ok(job) = receiver.recv() {
+                let result = job
+                    .profile
+                    .serialize_into_compressed_pprof(job.end_time, job.duration)
+                    .context("ddog_prof_Profile_serialize_async failed");
+                (job.callback)(result.into(), job.user_data.0);
+            }
+        })?;
+
+    // If another thread raced us, our worker exits as soon as our sender is dropped.
+    let sender = WORKER.get_or_init(|| Mutex::new(sender));
+    Ok(sender
+        .lock()
+        .map_err(|_| anyhow::anyhow!("lock poisoned"))?
+        .clone())
+}
+
+/// Like `ddog_prof_Profile_serialize`, but only swaps out the aggregated profile on the calling
+/// thread. The encoding then happens on a libdatadog-managed background thread, which invokes
+/// `callback` with the result (and `user_data`) once done. Serializations are processed in the
+/// order they were submitted.
+///
+/// The profile is reset synchronously, so it can be used for new samples as soon as this
+/// returns.
+///
+/// # Arguments
+/// * `profile` - a reference to the profile being serialized.
+/// * `end_time` - optional end time of the profile. If None/null is passed, the time at which
+///   this function was called will be used.
+/// * `duration_nanos` - Optional duration of the profile, see `ddog_prof_Profile_serialize`.
+/// * `start_time` - Optional start time for the next profile.
+/// * `callback` - invoked exactly once on the background thread if this function returns Ok.
+/// * `user_data` - passed as is to the callback.
+///
+/// # Safety
+/// The `profile` must point to a valid profile object.
+/// The `end_time` must be null or otherwise point to a valid TimeSpec object.
+/// The `duration_nanos` must be null or otherwise point to a valid i64.
+/// The `user_data` must be safe to access from another thread until the callback is invoked.
 #[must_use]
 #[no_mangle]
-pub unsafe extern "C" fn ddog_Vec_U8_as_slice(vec: &ddcommon_ffi::Vec<u8>) -> Slice<u8> {
-    vec.as_slice()
+pub unsafe extern "C" fn ddog_prof_Profile_serialize_async(
+    profile: *mut Profile,
+    end_time: Option<&Timespec>,
+    duration_nanos: Option<&i64>,
+    start_time: Option<&Timespec>,
+    callback: SerializeCallback,
+    user_data: *mut c_void,
+) -> ProfileResult {
+    (|| {
+        let profile = profile_ptr_to_inner(profile)?;
+        let worker = serialize_worker()?;
+
+        let start_time = start_time.map(SystemTime::from);
+        // Fix the end time now rather than when the background thread gets to it.
+        let end_time = end_time.map_or_else(SystemTime::now, SystemTime::from);
+        let old_profile = profile.reset_and_return_previous(start_time)?;
+        let duration = match duration_nanos {
+            None => None,
+            Some(x) if *x < 0 => None,
+            Some(x) => Some(Duration::from_nanos((*x) as u64)),
+        };
+        worker
+            .send(SerializeJob {
+                profile: old_profile,
+                end_time: Some(end_time),
+                duration,
+                callback,
+                user_data: UserData(user_data),
+            })
+            .map_err(|_| anyhow::anyhow!("serialization thread is gone"))
+    })()
+    .context("ddog_prof_Profile_serialize_async failed")
+    .into()
 }
 
 /// Resets all data in `profile` except the sample types and period. Returns
@@ -787,6 +2138,97 @@ mod tests {
         }
     }
 
+    #[test]
+    fn value_type_hints() {
+        let plain = ValueType::new("wall-time", "nanoseconds");
+        assert_eq!(
+            api::ValueType::new("wall-time", "nanoseconds"),
+            api::ValueType::from(&plain)
+        );
+
+        let hinted = ValueType {
+            aggregation: Aggregation::Max,
+            display_unit: "milliseconds".into(),
+            ..plain
+        };
+        assert_eq!(
+            api::ValueType::new("wall-time", "nanoseconds")
+                .with_aggregation(api::Aggregation::Max)
+                .with_display_unit("milliseconds"),
+            api::ValueType::from(&hinted)
+        );
+    }
+
+    #[test]
+    fn strict_validation_errors() {
+        let bytes = b"caf\xe9";
+        let invalid_utf8: CharSlice =
+            unsafe { Slice::from_raw_parts(bytes.as_ptr().cast(), bytes.len()) };
+        let sample_types = [ValueType {
+            unit: invalid_utf8,
+            ..ValueType::new("samples", "")
+        }];
+        let err = validate_profile_types(&sample_types, None).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("sample_types[0].unit is not valid UTF-8"));
+
+        let locations = [
+            Location::default(),
+            Location {
+                function: Function {
+                    name: invalid_utf8,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        ];
+        let err = validate_locations(&locations).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("locations[1].function.name is not valid UTF-8"));
+
+        let mapping = Mapping {
+            memory_start: 0x2000,
+            memory_limit: 0x1000,
+            ..Default::default()
+        };
+        let err = validate_locations(&[Location {
+            mapping,
+            ..Default::default()
+        }])
+        .unwrap_err();
+        assert_eq!(
+            "locations[0].mapping ends before it starts: 0x2000..0x1000",
+            err.to_string()
+        );
+
+        let labels = [
+            Label {
+                key: "thread name".into(),
+                str: "main".into(),
+                ..Default::default()
+            },
+            Label {
+                key: "thread id".into(),
+                str: "1".into(),
+                num: 1,
+                ..Default::default()
+            },
+        ];
+        assert_eq!(
+            "labels[1] (thread id) has both a string and a numeric value",
+            validate_labels(&labels).unwrap_err().to_string()
+        );
+        assert_eq!(
+            "labels[0].key is empty",
+            validate_labels(&[Label::default()])
+                .unwrap_err()
+                .to_string()
+        );
+        validate_labels(&labels[..1]).unwrap();
+    }
+
     #[test]
     // TODO FIX
     #[cfg_attr(miri, ignore)]
@@ -931,6 +2373,325 @@ mod tests {
         profile
     }
 
+    extern "C" fn serialize_callback(result: SerializeResult, user_data: *mut c_void) {
+        let sender = unsafe { &*(user_data as *const Mutex<Sender<bool>>) };
+        // The result is owned by us and dropped at the end of the scope.
+        let ok = matches!(result, SerializeResult::Ok(_));
+        sender.lock().unwrap().send(ok).unwrap();
+    }
+
+    #[test]
+    fn serialize_resets_profile() -> Result<(), Error> {
+        unsafe {
+            let mut profile = provide_distinct_locations_ffi();
+            let mut state = internal::ProfileState::Serialized;
+            let mut encoded = match ddog_prof_Profile_serialize(&mut profile, None, None, None) {
+                SerializeResult::Ok(encoded) => encoded,
+                SerializeResult::Err(err) => return Err(err),
+            };
+            ddog_prof_EncodedProfile_drop(Some(&mut encoded));
+
+            // The profile was reset, so it can be used again right away.
+            Result::from(ddog_prof_Profile_state(&mut profile, &mut state))?;
+            assert_eq!(internal::ProfileState::Open, state);
+            let values: &[i64] = &[1];
+            let sample = Sample {
+                locations: Slice::empty(),
+                values: Slice::from(values),
+                labels: Slice::empty(),
+            };
+            Result::from(ddog_prof_Profile_add(&mut profile, sample, None))?;
+
+            ddog_prof_Profile_drop(&mut profile);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn preinterned_ids_are_checked_across_resets() -> Result<(), Error> {
+        unsafe {
+            let sample_type: *const ValueType = &ValueType::new("samples", "count");
+            let mut profile = Result::from(ddog_prof_Profile_new(
+                Slice::from_raw_parts(sample_type, 1),
+                None,
+                None,
+            ))?;
+            let functions = [Function {
+                name: "{main}".into(),
+                filename: "index.php".into(),
+                ..Default::default()
+            }];
+            let mut ids = match ddog_prof_Profile_preintern(
+                &mut profile,
+                Slice::empty(),
+                Slice::from(functions.as_slice()),
+                Slice::empty(),
+            ) {
+                PreinternResult::Ok(ids) => ids,
+                PreinternResult::Err(err) => return Err(err),
+            };
+            let locations = [PreinternedLocation {
+                function: 1,
+                line: 3,
+                ..Default::default()
+            }];
+            let stacktrace = match ddog_prof_Profile_intern_preinterned_stacktrace(
+                &mut profile,
+                &ids,
+                Slice::from(locations.as_slice()),
+            ) {
+                InternStackTraceResult::Ok(stacktrace) => stacktrace,
+                InternStackTraceResult::Err(err) => return Err(err),
+            };
+            let values: &[i64] = &[1];
+            Result::from(ddog_prof_Profile_add_by_stacktrace(
+                &mut profile,
+                stacktrace,
+                Slice::from(values),
+                Slice::empty(),
+                None,
+            ))?;
+
+            // Positions past the preinterned functions are rejected.
+            let unknown = [PreinternedLocation {
+                function: 2,
+                ..Default::default()
+            }];
+            assert!(matches!(
+                ddog_prof_Profile_intern_preinterned_stacktrace(
+                    &mut profile,
+                    &ids,
+                    Slice::from(unknown.as_slice()),
+                ),
+                InternStackTraceResult::Err(_)
+            ));
+
+            Result::from(ddog_prof_Profile_check_preinterned(&mut profile, &ids))?;
+            Result::from(ddog_prof_Profile_reset(&mut profile, None))?;
+            assert!(Result::from(ddog_prof_Profile_check_preinterned(&mut profile, &ids)).is_err());
+            assert!(matches!(
+                ddog_prof_Profile_intern_preinterned_stacktrace(
+                    &mut profile,
+                    &ids,
+                    Slice::from(locations.as_slice()),
+                ),
+                InternStackTraceResult::Err(_)
+            ));
+
+            ddog_prof_PreinternedIds_drop(&mut ids);
+            ddog_prof_Profile_drop(&mut profile);
+            Ok(())
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn serialize_async() {
+        unsafe {
+            let mut profile = provide_distinct_locations_ffi();
+            let (sender, receiver) = mpsc::channel();
+            let sender = Mutex::new(sender);
+
+            for _ in 0..2 {
+                Result::from(ddog_prof_Profile_serialize_async(
+                    &mut profile,
+                    None,
+                    None,
+                    None,
+                    serialize_callback,
+                    &sender as *const _ as *mut c_void,
+                ))
+                .unwrap();
+                // The profile was reset synchronously.
+                assert_eq!(
+                    profile
+                        .inner
+                        .as_ref()
+                        .unwrap()
+                        .only_for_testing_num_aggregated_samples(),
+                    0
+                );
+            }
+
+            assert!(receiver.recv().unwrap());
+            assert!(receiver.recv().unwrap());
+            ddog_prof_Profile_drop(&mut profile);
+        }
+    }
+
+    struct ContextProvider {
+        calls: std::sync::atomic::AtomicUsize,
+        labels: Vec<Label<'static>>,
+    }
+
+    extern "C" fn provide_context(
+        context_id: u64,
+        user_data: *mut c_void,
+    ) -> Slice<'static, Label<'static>> {
+        let provider = unsafe { &*(user_data as *const ContextProvider) };
+        provider
+            .calls
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Slice::from(&provider.labels[context_id as usize..=context_id as usize])
+    }
+
+    #[test]
+    fn add_with_context() -> Result<(), Error> {
+        unsafe {
+            let sample_type: *const ValueType = &ValueType::new("samples", "count");
+            let mut profile = Result::from(ddog_prof_Profile_new(
+                Slice::from_raw_parts(sample_type, 1),
+                None,
+                None,
+            ))?;
+
+            let provider = ContextProvider {
+                calls: Default::default(),
+                labels: ["endpoint 0", "endpoint 1"]
+                    .into_iter()
+                    .map(|endpoint| Label {
+                        key: CharSlice::from("trace endpoint"),
+                        str: CharSlice::from(endpoint),
+                        ..Default::default()
+                    })
+                    .collect(),
+            };
+            Result::from(ddog_prof_Profile_set_context_provider(
+                &mut profile,
+                provide_context,
+                &provider as *const _ as *mut c_void,
+            ))?;
+
+            let values: &[i64] = &[1];
+            for context_id in [0, 1, 1] {
+                let sample = Sample {
+                    locations: Slice::empty(),
+                    values: Slice::from(values),
+                    labels: Slice::empty(),
+                };
+                Result::from(ddog_prof_Profile_add_with_context(
+                    &mut profile,
+                    sample,
+                    None,
+                    context_id,
+                ))?;
+            }
+            assert_eq!(
+                profile
+                    .inner
+                    .as_ref()
+                    .unwrap()
+                    .only_for_testing_num_aggregated_samples(),
+                2
+            );
+
+            match ddog_prof_Profile_serialize(&mut profile, None, None, None) {
+                SerializeResult::Ok(_) => {}
+                SerializeResult::Err(err) => return Err(err),
+            }
+            assert_eq!(provider.calls.load(std::sync::atomic::Ordering::Relaxed), 2);
+            ddog_prof_Profile_drop(&mut profile);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn add_with_thread() -> Result<(), Error> {
+        unsafe {
+            let sample_type: *const ValueType = &ValueType::new("samples", "count");
+            let mut profile = Result::from(ddog_prof_Profile_new(
+                Slice::from_raw_parts(sample_type, 1),
+                None,
+                None,
+            ))?;
+
+            let main = ThreadLabels {
+                id: 1,
+                native_id: NonZeroI64::new(4242),
+                name: CharSlice::from("main"),
+            };
+            let worker = ThreadLabels {
+                id: 2,
+                ..Default::default()
+            };
+            let values: &[i64] = &[1];
+            for thread in [main, worker, main] {
+                let sample = Sample {
+                    locations: Slice::empty(),
+                    values: Slice::from(values),
+                    labels: Slice::empty(),
+                };
+                Result::from(ddog_prof_Profile_add_with_thread(
+                    &mut profile,
+                    sample,
+                    thread,
+                    None,
+                ))?;
+            }
+            assert_eq!(
+                profile
+                    .inner
+                    .as_ref()
+                    .unwrap()
+                    .only_for_testing_num_aggregated_samples(),
+                2
+            );
+
+            // The thread labels must not be given twice.
+            let labels = [Label {
+                key: CharSlice::from("thread id"),
+                num: 1,
+                ..Default::default()
+            }];
+            let sample = Sample {
+                locations: Slice::empty(),
+                values: Slice::from(values),
+                labels: Slice::from(&labels[..]),
+            };
+            Result::from(ddog_prof_Profile_add_with_thread(
+                &mut profile,
+                sample,
+                main,
+                None,
+            ))
+            .unwrap_err();
+
+            ddog_prof_Profile_drop(&mut profile);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn encoded_profile_take_buffer() -> Result<(), Error> {
+        unsafe {
+            let mut profile = provide_distinct_locations_ffi();
+            let mut encoded = match ddog_prof_Profile_serialize(&mut profile, None, None, None) {
+                SerializeResult::Ok(encoded) => encoded,
+                SerializeResult::Err(err) => return Err(err),
+            };
+            ddog_prof_Profile_drop(&mut profile);
+
+            let mut buffer = ddcommon_ffi::vec::ddog_Vec_U8_new();
+            Result::from(ddog_prof_EncodedProfile_take_buffer(
+                &mut encoded,
+                &mut buffer,
+            ))?;
+            assert!(!buffer.is_empty());
+
+            let mut left_behind = ddcommon_ffi::vec::ddog_Vec_U8_new();
+            Result::from(ddog_prof_EncodedProfile_take_buffer(
+                &mut encoded,
+                &mut left_behind,
+            ))?;
+            assert!(left_behind.is_empty());
+
+            ddog_prof_EncodedProfile_drop(Some(&mut encoded));
+            // the buffer outlives the encoded profile
+            assert!(!buffer.is_empty());
+            Ok(())
+        }
+    }
+
     #[test]
     fn distinct_locations_ffi() {
         unsafe {
@@ -65,4 +65,23 @@ pub fn small_wordpress_profile(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, small_wordpress_profile);
+/// Interning strings which are already present, the most frequent case once a profile has seen
+/// its first samples.
+pub fn wordpress_lookups(c: &mut Criterion) {
+    let mut table = StringTable::new();
+    for string in WORDPRESS_STRINGS {
+        table.intern(string);
+    }
+    let n_strings = table.len();
+
+    c.bench_function("benching string lookups on wordpress profile", |b| {
+        b.iter(|| {
+            for string in WORDPRESS_STRINGS {
+                black_box(table.intern(black_box(string)));
+            }
+        })
+    });
+    assert_eq!(n_strings, table.len());
+}
+
+criterion_group!(benches, small_wordpress_profile, wordpress_lookups);
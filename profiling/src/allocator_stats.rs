@@ -0,0 +1,313 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! Statistics of the memory allocator of the process, added to profiles as synthetic samples so
+//! that the allocator health shows up next to the other profiles.
+//!
+//! jemalloc and mimalloc are supported. They are looked up among the symbols loaded in the
+//! process, nothing is linked in: runtimes get the statistics of the allocator they already use.
+
+use crate::api;
+use crate::internal::{Profile, Timestamp};
+
+/// Bytes allocated by the application.
+pub const HEAP_ALLOCATED: &str = "heap-allocated";
+/// Bytes of physical memory held by the allocator.
+pub const HEAP_RESIDENT: &str = "heap-resident";
+/// Bytes of the pages in use by the allocator which don't hold allocations.
+pub const HEAP_FRAGMENTATION: &str = "heap-fragmentation";
+
+/// The sample types of the allocator samples, to include in the sample types of the profile, see
+/// [`AllocatorStats::add_to_profile`].
+pub fn sample_types() -> [api::ValueType<'static>; 3] {
+    [
+        api::ValueType::new(HEAP_ALLOCATED, "bytes"),
+        api::ValueType::new(HEAP_RESIDENT, "bytes"),
+        api::ValueType::new(HEAP_FRAGMENTATION, "bytes"),
+    ]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AllocatorStats {
+    /// The name of the allocator, e.g. "jemalloc".
+    pub allocator: &'static str,
+    /// Bytes allocated by the application, if the allocator tracks them.
+    pub allocated: Option<u64>,
+    /// Bytes of the pages in use by the allocator, including fragmentation.
+    pub active: u64,
+    /// Bytes of physical memory held by the allocator.
+    pub resident: u64,
+}
+
+impl AllocatorStats {
+    /// Reads the statistics of the allocator of the process. Returns None if it's neither jemalloc
+    /// nor mimalloc, or if it was built without statistics.
+    pub fn read() -> Option<Self> {
+        #[cfg(unix)]
+        {
+            os::read(os::find_symbol)
+        }
+        #[cfg(not(unix))]
+        {
+            None
+        }
+    }
+
+    /// Reads the statistics of the allocator of the process and adds them to the profile, see
+    /// [`AllocatorStats::read`] and [`AllocatorStats::add_to_profile`]. Returns false, without
+    /// adding anything, if the allocator is not supported.
+    pub fn sample(profile: &mut Profile, timestamp: Timestamp) -> anyhow::Result<bool> {
+        match Self::read() {
+            Some(stats) => stats.add_to_profile(profile, timestamp).map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Bytes of the pages in use by the allocator which don't hold allocations.
+    pub fn fragmentation(&self) -> Option<u64> {
+        self.allocated
+            .map(|allocated| self.active.saturating_sub(allocated))
+    }
+
+    /// Adds a sample with the statistics to the profile, under a frame named after the allocator.
+    /// Each statistic goes to the sample type of [`sample_types`] of the same name, those the
+    /// profile doesn't have are left out. Fails if the profile has none of them.
+    ///
+    /// The statistics are gauges, so the sample is timestamped: aggregated with the previous
+    /// ones, they would be summed up.
+    pub fn add_to_profile(
+        &self,
+        profile: &mut Profile,
+        timestamp: Timestamp,
+    ) -> anyhow::Result<()> {
+        let mut values = vec![0; profile.sample_types_len()];
+        let mut found = false;
+        for (sample_type, value) in [
+            (HEAP_ALLOCATED, self.allocated),
+            (HEAP_RESIDENT, Some(self.resident)),
+            (HEAP_FRAGMENTATION, self.fragmentation()),
+        ] {
+            if let Some(index) = profile.sample_type_index(sample_type) {
+                values[index] = value.map_or(0, |value| value.min(i64::MAX as u64) as i64);
+                found = true;
+            }
+        }
+        anyhow::ensure!(found, "the profile has no allocator sample type");
+
+        let frame = format!("[{}]", self.allocator);
+        let sample = api::Sample {
+            locations: vec![api::Location {
+                function: api::Function {
+                    name: &frame,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }],
+            values,
+            labels: vec![api::Label {
+                key: "allocator",
+                str: Some(self.allocator),
+                ..Default::default()
+            }],
+        };
+        profile.add_sample(sample, Some(timestamp))
+    }
+}
+
+#[cfg(unix)]
+mod os {
+    use super::AllocatorStats;
+    use std::ffi::{c_char, c_int, c_void};
+    use std::{mem, ptr};
+
+    type Mallctl =
+        unsafe extern "C" fn(*const c_char, *mut c_void, *mut usize, *mut c_void, usize) -> c_int;
+
+    type MiProcessInfo = unsafe extern "C" fn(
+        *mut usize,
+        *mut usize,
+        *mut usize,
+        *mut usize,
+        *mut usize,
+        *mut usize,
+        *mut usize,
+        *mut usize,
+    );
+
+    /// jemalloc may be built with a prefix, e.g. by the tikv-jemallocator crate.
+    const MALLCTL_SYMBOLS: &[&[u8]] = &[b"mallctl\0", b"je_mallctl\0", b"_rjem_mallctl\0"];
+
+    /// Reads the statistics of the allocator, with `find_symbol` looking up its functions.
+    pub fn read(find_symbol: impl Fn(&[&[u8]]) -> Option<*mut c_void>) -> Option<AllocatorStats> {
+        read_jemalloc(&find_symbol).or_else(|| read_mimalloc(&find_symbol))
+    }
+
+    pub fn find_symbol(names: &[&[u8]]) -> Option<*mut c_void> {
+        names.iter().find_map(|name| {
+            // SAFETY: the names are nul terminated.
+            let symbol = unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr().cast()) };
+            (!symbol.is_null()).then_some(symbol)
+        })
+    }
+
+    fn read_jemalloc(
+        find_symbol: &impl Fn(&[&[u8]]) -> Option<*mut c_void>,
+    ) -> Option<AllocatorStats> {
+        let symbol = find_symbol(MALLCTL_SYMBOLS)?;
+        // SAFETY: mallctl has this signature in all jemalloc versions.
+        let mallctl: Mallctl = unsafe { mem::transmute(symbol) };
+
+        // The statistics are cached, writing the epoch refreshes them.
+        let mut epoch: u64 = 1;
+        let mut epoch_len = mem::size_of::<u64>();
+        let epoch_ptr: *mut u64 = &mut epoch;
+        // SAFETY: "epoch" takes and returns a u64.
+        unsafe {
+            mallctl(
+                b"epoch\0".as_ptr().cast(),
+                epoch_ptr.cast(),
+                &mut epoch_len,
+                epoch_ptr.cast(),
+                epoch_len,
+            )
+        };
+
+        let read = |name: &[u8]| {
+            let mut value: usize = 0;
+            let mut len = mem::size_of::<usize>();
+            let value_ptr: *mut usize = &mut value;
+            // SAFETY: the statistics are nul terminated names of size_t values.
+            let result = unsafe {
+                mallctl(
+                    name.as_ptr().cast(),
+                    value_ptr.cast(),
+                    &mut len,
+                    ptr::null_mut(),
+                    0,
+                )
+            };
+            (result == 0).then_some(value as u64)
+        };
+        Some(AllocatorStats {
+            allocator: "jemalloc",
+            allocated: Some(read(b"stats.allocated\0")?),
+            active: read(b"stats.active\0")?,
+            resident: read(b"stats.resident\0")?,
+        })
+    }
+
+    fn read_mimalloc(
+        find_symbol: &impl Fn(&[&[u8]]) -> Option<*mut c_void>,
+    ) -> Option<AllocatorStats> {
+        let symbol = find_symbol(&[b"mi_process_info\0"])?;
+        // SAFETY: mi_process_info has this signature since mimalloc 1.7.
+        let process_info: MiProcessInfo = unsafe { mem::transmute(symbol) };
+
+        let mut values = [0usize; 8];
+        let [elapsed, user, system, current_rss, peak_rss, current_commit, peak_commit, faults] =
+            &mut values;
+        // SAFETY: all the pointers are valid for writes.
+        unsafe {
+            process_info(
+                elapsed,
+                user,
+                system,
+                current_rss,
+                peak_rss,
+                current_commit,
+                peak_commit,
+                faults,
+            )
+        };
+        Some(AllocatorStats {
+            allocator: "mimalloc",
+            // mimalloc only tracks the allocated bytes in its debug statistics
+            allocated: None,
+            active: *current_commit as u64,
+            resident: *current_rss as u64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pprof;
+    use std::time::SystemTime;
+
+    fn timestamp() -> Timestamp {
+        Timestamp::new(1).unwrap()
+    }
+
+    const STATS: AllocatorStats = AllocatorStats {
+        allocator: "jemalloc",
+        allocated: Some(600),
+        active: 1000,
+        resident: 1200,
+    };
+
+    #[test]
+    fn add_to_profile() {
+        let types = [
+            api::ValueType::new("samples", "count"),
+            api::ValueType::new(HEAP_RESIDENT, "bytes"),
+            api::ValueType::new(HEAP_FRAGMENTATION, "bytes"),
+        ];
+        let mut profile = Profile::new(SystemTime::now(), &types, None);
+        STATS.add_to_profile(&mut profile, timestamp()).unwrap();
+        // gauges are not summed up
+        STATS.add_to_profile(&mut profile, timestamp()).unwrap();
+
+        let pprof = pprof::roundtrip_to_pprof(profile).unwrap();
+        assert_eq!(2, pprof.samples.len());
+        assert_eq!(vec![0, 1200, 400], pprof.samples[0].values);
+        assert_eq!(vec![0, 1200, 400], pprof.samples[1].values);
+
+        let mut profile = Profile::new(SystemTime::now(), &types[..1], None);
+        STATS.add_to_profile(&mut profile, timestamp()).unwrap_err();
+
+        let mut profile = Profile::new(SystemTime::now(), &sample_types(), None);
+        let mimalloc = AllocatorStats {
+            allocator: "mimalloc",
+            allocated: None,
+            ..STATS
+        };
+        assert_eq!(None, mimalloc.fragmentation());
+        mimalloc.add_to_profile(&mut profile, timestamp()).unwrap();
+        let pprof = pprof::roundtrip_to_pprof(profile).unwrap();
+        assert_eq!(vec![0, 1200, 0], pprof.samples[0].values);
+    }
+
+    #[cfg(unix)]
+    unsafe extern "C" fn fake_mallctl(
+        name: *const std::ffi::c_char,
+        old: *mut std::ffi::c_void,
+        _old_len: *mut usize,
+        _new: *mut std::ffi::c_void,
+        _new_len: usize,
+    ) -> std::ffi::c_int {
+        let value: usize = match std::ffi::CStr::from_ptr(name).to_bytes() {
+            b"epoch" => return 0,
+            b"stats.allocated" => 600,
+            b"stats.active" => 1000,
+            b"stats.resident" => 1200,
+            _ => return 1,
+        };
+        *old.cast::<usize>() = value;
+        0
+    }
+
+    #[test]
+    #[cfg(unix)]
+    #[cfg_attr(miri, ignore)]
+    fn read() {
+        assert_eq!(None, os::read(|_| None));
+
+        let find_mallctl = |names: &[&[u8]]| {
+            names
+                .contains(&&b"mallctl\0"[..])
+                .then_some(fake_mallctl as *mut std::ffi::c_void)
+        };
+        assert_eq!(Some(STATS), os::read(find_mallctl));
+    }
+}
@@ -5,16 +5,59 @@ use crate::pprof;
 use std::ops::{Add, Sub};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+/// How the values of a sample type are aggregated by default, e.g. when merging profiles.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(test, derive(bolero_generator::TypeGenerator))]
+pub enum Aggregation {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Aggregation::Sum => "sum",
+            Aggregation::Min => "min",
+            Aggregation::Max => "max",
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct ValueType<'a> {
     pub r#type: &'a str,
     pub unit: &'a str,
+    /// Hints for sample types the backend doesn't know about yet. They are serialized as internal
+    /// labels of the samples, see [`crate::internal::Profile::serialize_into_compressed_pprof`].
+    pub aggregation: Option<Aggregation>,
+    /// The unit the values are displayed in, if not the `unit` they are collected in.
+    pub display_unit: Option<&'a str>,
 }
 
 impl<'a> ValueType<'a> {
     #[inline(always)]
     pub fn new(r#type: &'a str, unit: &'a str) -> Self {
-        Self { r#type, unit }
+        Self {
+            r#type,
+            unit,
+            aggregation: None,
+            display_unit: None,
+        }
+    }
+
+    pub fn with_aggregation(self, aggregation: Aggregation) -> Self {
+        Self {
+            aggregation: Some(aggregation),
+            ..self
+        }
+    }
+
+    pub fn with_display_unit(self, display_unit: &'a str) -> Self {
+        Self {
+            display_unit: Some(display_unit),
+            ..self
+        }
     }
 }
 
@@ -74,6 +117,9 @@ pub struct Line<'a> {
 #[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
 pub struct Location<'a> {
     pub mapping: Mapping<'a>,
+    /// Leave the function empty (the default) for locations which are only known by their mapping
+    /// and address, e.g. when native frames are symbolicated by the backend. No function is then
+    /// emitted in the pprof.
     pub function: Function<'a>,
 
     /// The instruction address for this location, if available.  It
@@ -85,6 +131,15 @@ pub struct Location<'a> {
     pub line: i64,
 }
 
+/// The strings, functions and mappings a runtime knows it will use, to be interned up front with
+/// [`crate::internal::Profile::preintern`].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct InternSchema<'a> {
+    pub strings: &'a [&'a str],
+    pub functions: &'a [Function<'a>],
+    pub mappings: &'a [Mapping<'a>],
+}
+
 #[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
 pub struct Label<'a> {
     pub key: &'a str,
@@ -109,6 +164,49 @@ impl<'a> Label<'a> {
     }
 }
 
+/// The thread a sample was taken on. Profilers should label their samples with
+/// [`ThreadLabels::push_labels`] rather than building the labels themselves, so that threads are
+/// labeled the same way the backend expects them in every language.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ThreadLabels<'a> {
+    /// The id of the thread in the runtime, e.g. the id of a Java thread, or the OS id when the
+    /// runtime has none.
+    pub id: i64,
+    /// The id of the thread in the OS, e.g. the tid on Linux, if it differs from `id`.
+    pub native_id: Option<i64>,
+    pub name: Option<&'a str>,
+}
+
+impl<'a> ThreadLabels<'a> {
+    pub const ID_KEY: &'static str = "thread id";
+    pub const NATIVE_ID_KEY: &'static str = "thread native id";
+    pub const NAME_KEY: &'static str = "thread name";
+
+    /// Appends the labels of the thread: a numeric "thread id", then a numeric "thread native
+    /// id" and a "thread name" when known. An empty name is the same as no name.
+    pub fn push_labels(&self, labels: &mut Vec<Label<'a>>) {
+        labels.push(Label {
+            key: Self::ID_KEY,
+            num: self.id,
+            ..Default::default()
+        });
+        if let Some(native_id) = self.native_id {
+            labels.push(Label {
+                key: Self::NATIVE_ID_KEY,
+                num: native_id,
+                ..Default::default()
+            });
+        }
+        if let Some(name) = self.name.filter(|name| !name.is_empty()) {
+            labels.push(Label {
+                key: Self::NAME_KEY,
+                str: Some(name),
+                ..Default::default()
+            });
+        }
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Sample<'a> {
     /// The leaf is at locations[0].
@@ -127,7 +225,7 @@ pub struct Sample<'a> {
     pub labels: Vec<Label<'a>>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 #[cfg_attr(test, derive(bolero_generator::TypeGenerator))]
 pub enum UpscalingInfo {
     Poisson {
@@ -244,18 +342,20 @@ fn location_fetch(pprof: &pprof::Profile, id: u64) -> anyhow::Result<Location> {
         Some(location) => {
             anyhow::ensure!(!location.is_folded, "expected Location to not be folded");
             anyhow::ensure!(
-                location.lines.len() == 1,
-                "expected Location to have exactly 1 Line"
+                location.lines.len() <= 1,
+                "expected Location to have at most 1 Line"
             );
-            // Safety: guarded by len check above.
-            let line = unsafe { location.lines.get_unchecked(0) };
-            let function = function_fetch(pprof, line.function_id)?;
+            // Address-only locations have no line.
+            let (function, line) = match location.lines.first() {
+                Some(line) => (function_fetch(pprof, line.function_id)?, line.line),
+                None => (Function::default(), 0),
+            };
 
             Ok(Location {
                 mapping: mapping_fetch(pprof, location.mapping_id)?,
                 function,
                 address: location.address,
-                line: line.line,
+                line,
             })
         }
         None => anyhow::bail!("Location {id} was not found."),
@@ -398,4 +498,44 @@ mod tests {
         };
         assert!(label.uses_at_most_one_of_str_and_num());
     }
+
+    #[test]
+    fn thread_labels() {
+        let mut labels = vec![];
+        ThreadLabels {
+            id: 7,
+            native_id: None,
+            name: Some(""),
+        }
+        .push_labels(&mut labels);
+        assert_eq!(
+            vec![Label {
+                key: "thread id",
+                str: None,
+                num: 7,
+                num_unit: None,
+            }],
+            labels
+        );
+
+        labels.clear();
+        ThreadLabels {
+            id: 7,
+            native_id: Some(4242),
+            name: Some("worker"),
+        }
+        .push_labels(&mut labels);
+        assert_eq!(
+            vec![
+                ("thread id", None, 7),
+                ("thread native id", None, 4242),
+                ("thread name", Some("worker"), 0)
+            ],
+            labels
+                .iter()
+                .map(|label| (label.key, label.str, label.num))
+                .collect::<Vec<_>>()
+        );
+        assert!(labels.iter().all(Label::uses_at_most_one_of_str_and_num));
+    }
 }
@@ -84,6 +84,16 @@ pub fn into_pprof_iter<T: PprofItem>(
         .map(|(index, item)| item.to_pprof(<T as Item>::Id::from_offset(index)))
 }
 
+/// Like [`into_pprof_iter`], without consuming the collection.
+pub fn pprof_iter<T: PprofItem>(
+    collection: &FxIndexSet<T>,
+) -> impl Iterator<Item = T::PprofMessage> + '_ {
+    collection
+        .iter()
+        .enumerate()
+        .map(|(index, item)| item.to_pprof(<T as Item>::Id::from_offset(index)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
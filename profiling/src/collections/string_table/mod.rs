@@ -6,7 +6,8 @@ pub mod wordpress_test_data;
 
 use crate::collections::identifiable::{Id, StringId};
 use crate::iter::{IntoLendingIterator, LendingIterator};
-use datadog_alloc::{AllocError, Allocator, ChainAllocator, VirtualAllocator};
+use datadog_alloc::{AllocError, Allocator, ChainAllocator, VirtualArena};
+use hashbrown::hash_map::RawEntryMut;
 use std::alloc::Layout;
 
 /// A trait that indicates an allocator is arena allocator, meaning it doesn't
@@ -48,23 +49,122 @@ pub trait ArenaAllocator: Allocator {
 
 impl<A: Allocator + Clone> ArenaAllocator for ChainAllocator<A> {}
 
-type Hasher = core::hash::BuildHasherDefault<rustc_hash::FxHasher>;
-type HashSet<K> = indexmap::IndexSet<K, Hasher>;
+impl ArenaAllocator for VirtualArena {}
+
+/// Maps the strings to their [StringId]. The map has no hasher of its own, the hashes are always
+/// computed by [hash_str] and passed to the raw entry API. This way, a string is hashed once per
+/// [StringTable::intern], whether it was present or not.
+type Index = hashbrown::HashMap<&'static str, StringId, ()>;
+
+/// Hashes the strings of a [StringTable]. When SSE 4.2 is enabled at compile time, this uses the
+/// CRC32 instructions, which process 8 bytes per instruction. Otherwise, this falls back to
+/// FxHash.
+#[inline]
+fn hash_str(str: &str) -> u64 {
+    #[cfg(all(target_arch = "x86_64", target_feature = "sse4.2"))]
+    {
+        crc32_hash(str.as_bytes())
+    }
+    #[cfg(not(all(target_arch = "x86_64", target_feature = "sse4.2")))]
+    {
+        use core::hash::BuildHasher;
+        core::hash::BuildHasherDefault::<rustc_hash::FxHasher>::default().hash_one(str)
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", target_feature = "sse4.2"))]
+#[inline]
+fn crc32_hash(bytes: &[u8]) -> u64 {
+    use core::arch::x86_64::{_mm_crc32_u64, _mm_crc32_u8};
+
+    // Seeding with the length distinguishes strings which only differ by trailing zero bytes.
+    let mut crc = bytes.len() as u64;
+    let mut chunks = bytes.chunks_exact(8);
+    // SAFETY: the instructions are available, the sse4.2 target feature is enabled.
+    unsafe {
+        for chunk in &mut chunks {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(chunk);
+            crc = _mm_crc32_u64(crc, u64::from_le_bytes(word));
+        }
+        for byte in chunks.remainder() {
+            crc = _mm_crc32_u8(crc as u32, *byte) as u64;
+        }
+    }
+    // The CRC only has 32 bits. Spread them to the high bits too, as the map uses the top 7 bits
+    // to filter the candidates of a lookup.
+    crc.wrapping_mul(0x9E37_79B9_7F4A_7C15)
+}
+
+/// The memory statistics of the arena of a [StringTable].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ArenaStats {
+    /// The bytes taken by the strings.
+    pub used_bytes: usize,
+    /// The bytes backed by memory, at least `used_bytes`.
+    pub committed_bytes: usize,
+    /// The bytes of address space reserved, which is the capacity of the
+    /// arena.
+    pub reserved_bytes: usize,
+}
+
+/// The arenas the strings of a [StringTable] are allocated in. Only the last
+/// one is allocated from. Once it is full, a new arena twice as large is
+/// reserved, so the strings never move and small tables only reserve a little
+/// address space.
+struct StringArenas {
+    arenas: Vec<VirtualArena>,
+}
+
+impl StringArenas {
+    fn with_capacity(capacity: usize) -> Result<Self, AllocError> {
+        Ok(Self {
+            arenas: vec![VirtualArena::with_capacity(capacity)?],
+        })
+    }
+
+    fn last(&self) -> &VirtualArena {
+        // There is always at least the arena made by with_capacity.
+        &self.arenas[self.arenas.len() - 1]
+    }
+
+    fn allocate(&mut self, str: &str) -> Result<&str, AllocError> {
+        if self.last().remaining_capacity() < str.len() {
+            let capacity = self.last().reserved_bytes().saturating_mul(2);
+            self.arenas
+                .push(VirtualArena::with_capacity(capacity.max(str.len()))?);
+        }
+        ArenaAllocator::allocate(self.last(), str)
+    }
+
+    fn stats(&self) -> ArenaStats {
+        self.arenas
+            .iter()
+            .fold(ArenaStats::default(), |stats, arena| ArenaStats {
+                used_bytes: stats.used_bytes + arena.used_bytes(),
+                committed_bytes: stats.committed_bytes + arena.committed_bytes(),
+                reserved_bytes: stats.reserved_bytes + arena.reserved_bytes(),
+            })
+    }
+}
 
 /// Holds unique strings and provides [StringId]s that correspond to the order
 /// that the strings were inserted.
 pub struct StringTable {
     /// The bytes of each string stored in `strings` are allocated here.
-    bytes: ChainAllocator<VirtualAllocator>,
+    bytes: StringArenas,
 
-    /// The ordered hash set of unique strings. The order becomes the StringId.
+    /// The unique strings, in insertion order. The order becomes the StringId.
     /// The static lifetime is a lie, it is tied to the `bytes`, which is only
     /// moved if the string table is moved e.g.
     /// [StringTable::into_lending_iterator].
     /// References to the underlying strings should generally not be handed,
     /// but if they are, they should be bound to the string table's lifetime
     /// or the lending iterator's lifetime.
-    strings: HashSet<&'static str>,
+    strings: Vec<&'static str>,
+
+    /// Looks up the StringId of the `strings`, with the same lifetime caveat.
+    index: Index,
 }
 
 impl Default for StringTable {
@@ -80,12 +180,24 @@ impl StringTable {
         // Keep in mind 32-bit .NET. There is only 2 GiB of virtual memory
         // total available to an application, and we're not the application,
         // we're just a piece inside it. Additionally, there may be 2 or more
-        // string tables in memory at a given time. Talk to .NET profiling
-        // engineers before making this any bigger.
-        const SIZE_HINT: usize = 4 * 1024 * 1024;
-        let bytes = ChainAllocator::new_in(SIZE_HINT, VirtualAllocator {});
+        // string tables in memory at a given time. The arenas grow as needed,
+        // so don't make the initial reservation any bigger.
+        const CAPACITY: usize = 4 * 1024 * 1024;
+        Self::with_arena_capacity(CAPACITY)
+    }
+
+    /// Creates a new string table whose arena initially reserves `capacity`
+    /// bytes, which are only committed as needed. Once they are used up,
+    /// another arena twice as large is reserved.
+    ///
+    /// # Panics
+    /// This panics if the address space can't be reserved.
+    pub fn with_arena_capacity(capacity: usize) -> Self {
+        // PANIC: like interning, creating a table doesn't allow for failure.
+        // Reserving address space only fails if the process runs out of it.
+        let bytes = StringArenas::with_capacity(capacity)
+            .expect("address space for the StringTable arena to be reserved");
 
-        let mut strings = HashSet::with_hasher(Hasher::default());
         // It varies by implementation, but frequently I've noticed that the
         // capacity after the first insertion is quite small, as in 3. This is
         // a bit too small and there are frequent reallocations. For one sample
@@ -100,14 +212,24 @@ impl StringTable {
         // So with a capacity like 3, we end up reallocating a bunch on or
         // before the very first sample. The number here is not fine-tuned,
         // just skipping some obviously bad, tiny sizes.
-        strings.reserve(32);
+        const STRINGS_CAPACITY: usize = 32;
+        let mut strings = Vec::with_capacity(STRINGS_CAPACITY);
+        let mut index = Index::with_capacity_and_hasher(STRINGS_CAPACITY, ());
 
         // Always hold the empty string as item 0. Do not insert it via intern
         // because that will try to allocate zero-bytes from the storage,
         // which is sketchy.
-        strings.insert("");
+        strings.push("");
+        let hash = hash_str("");
+        if let RawEntryMut::Vacant(entry) = index.raw_entry_mut().from_hash(hash, |_| false) {
+            entry.insert_with_hasher(hash, "", StringId::ZERO, |s| hash_str(s));
+        }
 
-        Self { bytes, strings }
+        Self {
+            bytes,
+            strings,
+            index,
+        }
     }
 
     /// Returns the number of strings currently held in the string table.
@@ -117,6 +239,28 @@ impl StringTable {
         self.strings.len()
     }
 
+    /// Iterates over the strings in the order they were inserted, without consuming the table.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.strings.iter().copied()
+    }
+
+    /// Returns the string with the given id, if it was interned into this table.
+    pub fn get(&self, id: StringId) -> Option<&str> {
+        self.strings.get(id.to_offset()).copied()
+    }
+
+    /// Returns the number of bytes used by the strings in the arena.
+    #[inline]
+    pub fn arena_used_bytes(&self) -> usize {
+        self.bytes.arenas.iter().map(VirtualArena::used_bytes).sum()
+    }
+
+    /// Returns how much memory the arenas of the strings use, commit and
+    /// reserve.
+    pub fn arena_stats(&self) -> ArenaStats {
+        self.bytes.stats()
+    }
+
     /// Adds the string to the string table if it isn't present already, and
     /// returns a [StringId] that corresponds to the order that this string
     /// was originally inserted.
@@ -124,13 +268,13 @@ impl StringTable {
     /// # Panics
     /// This panics if the allocator fails to allocate a new chunk/node.
     pub fn intern(&mut self, str: &str) -> StringId {
-        let set = &mut self.strings;
-        match set.get_index_of(str) {
-            Some(offset) => StringId::from_offset(offset),
-            None => {
+        let hash = hash_str(str);
+        match self.index.raw_entry_mut().from_hash(hash, |s| *s == str) {
+            RawEntryMut::Occupied(entry) => *entry.get(),
+            RawEntryMut::Vacant(entry) => {
                 // No match. Get the current size of the table, which
                 // corresponds to the StringId it will have when inserted.
-                let string_id = StringId::from_offset(set.len());
+                let string_id = StringId::from_offset(self.strings.len());
 
                 // Make a new string in the arena, and fudge its lifetime
                 // to appease the borrow checker.
@@ -140,7 +284,9 @@ impl StringTable {
                     // implementation of `ChainAllocator` will fail if the
                     // underlying allocator fails when asking for a new chunk.
                     // This is expected to be rare.
-                    let s = ArenaAllocator::allocate(&self.bytes, str)
+                    let s = self
+                        .bytes
+                        .allocate(str)
                         .expect("allocator for StringTable::intern to succeed");
 
                     // SAFETY: all references to this value get re-narrowed to
@@ -150,8 +296,10 @@ impl StringTable {
                     unsafe { core::mem::transmute::<&str, &'static str>(s) }
                 };
 
-                // Add it to the set.
-                self.strings.insert(new_str);
+                // Add it to the table. The index may need to grow, which
+                // rehashes the strings it holds with the same function.
+                self.strings.push(new_str);
+                entry.insert_with_hasher(hash, new_str, string_id, |s| hash_str(s));
 
                 string_id
             }
@@ -165,13 +313,13 @@ pub struct StringTableIter {
     /// This is actually used, the compiler doesn't know that the static
     /// references in `iter` actually point in here.
     #[allow(unused)]
-    bytes: ChainAllocator<VirtualAllocator>,
+    bytes: StringArenas,
 
     /// The strings of the string table, in order of insertion.
     /// The static lifetimes are a lie, they are tied to the `bytes`. When
     /// handing out references, bind the lifetime to the iterator's lifetime,
     /// which is a [LendingIterator] is needed.
-    iter: <HashSet<&'static str> as IntoIterator>::IntoIter,
+    iter: std::vec::IntoIter<&'static str>,
 }
 
 impl StringTableIter {
@@ -184,7 +332,10 @@ impl StringTableIter {
 }
 
 impl LendingIterator for StringTableIter {
-    type Item<'a> = &'a str where Self: 'a;
+    type Item<'a>
+        = &'a str
+    where
+        Self: 'a;
 
     fn next(&mut self) -> Option<Self::Item<'_>> {
         self.iter.next()
@@ -206,6 +357,7 @@ impl IntoLendingIterator for StringTable {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use datadog_alloc::VirtualAllocator;
 
     #[test]
     fn fuzz_arena_allocator() {
@@ -279,6 +431,49 @@ mod tests {
             })
     }
 
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_arena_stats() {
+        const CAPACITY: usize = 64 * 1024 * 1024;
+        let mut table = StringTable::with_arena_capacity(CAPACITY);
+        let stats = table.arena_stats();
+        assert_eq!(0, stats.used_bytes);
+        assert_eq!(0, stats.committed_bytes);
+        assert_eq!(CAPACITY, stats.reserved_bytes);
+
+        table.intern("datadog");
+        let stats = table.arena_stats();
+        assert_eq!(7, stats.used_bytes);
+        assert!(stats.committed_bytes >= stats.used_bytes);
+        // Only the pages reached by the strings are committed.
+        assert!(stats.committed_bytes < CAPACITY);
+        assert_eq!(CAPACITY, stats.reserved_bytes);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_arena_grows() {
+        let page_size = 4096;
+        let mut table = StringTable::with_arena_capacity(page_size);
+        let reserved = table.arena_stats().reserved_bytes;
+
+        // Fill the first arena, the strings must stay valid after growing.
+        let strings: Vec<String> = (0..reserved / 8 + 1).map(|i| format!("{i:08}")).collect();
+        let ids: Vec<StringId> = strings.iter().map(|s| table.intern(s)).collect();
+        let stats = table.arena_stats();
+        assert_eq!(8 * strings.len(), stats.used_bytes);
+        assert_eq!(3 * reserved, stats.reserved_bytes);
+        for (s, id) in strings.iter().zip(ids) {
+            assert_eq!(Some(s.as_str()), table.get(id));
+        }
+
+        // Strings larger than twice the last arena get an arena of their own.
+        let large = "x".repeat(8 * reserved);
+        let id = table.intern(&large);
+        assert_eq!(Some(large.as_str()), table.get(id));
+        assert!(table.arena_stats().reserved_bytes >= 11 * reserved);
+    }
+
     #[test]
     fn test_basics() {
         let mut table = StringTable::new();
@@ -344,6 +539,32 @@ mod tests {
         test_from_src(cases);
     }
 
+    /// Strings which only differ after their last full 8-byte chunk, or by trailing zero bytes,
+    /// must not be mistaken for one another.
+    #[test]
+    fn test_hash_aliasing() {
+        let mut strings = vec!["\0".to_string(), "\0\0".to_string()];
+        for len in 1..=24 {
+            let prefix = "a".repeat(len - 1);
+            strings.push(format!("{prefix}a"));
+            strings.push(format!("{prefix}b"));
+            strings.push(format!("{prefix}a\0"));
+        }
+        assert_ne!(hash_str("aaaaaaaab"), hash_str("aaaaaaaac"));
+        assert_ne!(hash_str("aaaaaaaa"), hash_str("aaaaaaaa\0"));
+
+        let mut table = StringTable::new();
+        let ids: Vec<_> = strings.iter().map(|string| table.intern(string)).collect();
+        assert_eq!(strings.len() + 1, table.len());
+
+        for (string, id) in strings.iter().zip(ids) {
+            // A copy at another address is the same string.
+            assert_eq!(id, table.intern(&string.clone()));
+            assert_eq!(Some(string.as_str()), table.get(id));
+        }
+        assert_eq!(strings.len() + 1, table.len());
+    }
+
     /// Test inserting strings from a WordPress profile.
     /// Here we're checking that we don't panic or otherwise fail, and that
     /// the total number of strings and the bytes of those strings match.
@@ -15,7 +15,9 @@ use serde_json::json;
 use tokio::runtime::Runtime;
 use tokio_util::sync::CancellationToken;
 
-use ddcommon::{azure_app_services, connector, Endpoint, HttpClient, HttpResponse};
+use ddcommon::{
+    azure_app_services, connector, process_tags, user_agent, Endpoint, HttpClient, HttpResponse,
+};
 
 pub mod config;
 mod errors;
@@ -30,9 +32,72 @@ use crate::internal::ProfiledEndpointsStats;
 
 const DURATION_ZERO: std::time::Duration = std::time::Duration::from_millis(0);
 
+/// The HTTP versions the exporter may use for uploads.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HttpVersion {
+    /// HTTP/1.1 only.
+    #[default]
+    Http1,
+    /// HTTP/2 if the server offers it via ALPN during the TLS handshake, HTTP/1.1 otherwise.
+    Http2,
+    /// HTTP/2 without negotiation ("prior knowledge"), also on plain-text connections. Uploads
+    /// fail if the server does not speak HTTP/2.
+    Http2PriorKnowledge,
+}
+
+/// The configuration of the HTTP client of an [`Exporter`].
+///
+/// The client is shared by all uploads of the exporter, so with an idle timeout, frequent uploads
+/// reuse the connection instead of going through the TCP and TLS handshakes each time. Note that
+/// idle connections are only serviced while an upload is in progress.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HttpClientConfig {
+    pub http_version: HttpVersion,
+    /// How long idle connections are kept open for later uploads. With `None`, the default, the
+    /// connection is closed after each upload.
+    pub pool_idle_timeout: Option<std::time::Duration>,
+    /// The interval of the HTTP/2 pings keeping idle connections alive. `None` disables them.
+    pub http2_keep_alive_interval: Option<std::time::Duration>,
+    /// How long establishing a TCP connection may take. `None`, the default, only bounds it by the
+    /// timeout of the request, which also covers the transfer of the profile.
+    pub connect_timeout: Option<std::time::Duration>,
+}
+
+impl HttpClientConfig {
+    fn closes_connections(&self) -> bool {
+        self.http_version == HttpVersion::Http1 && self.pool_idle_timeout.is_none()
+    }
+
+    fn build_client(&self) -> HttpClient {
+        let mut builder = hyper::Client::builder();
+        match self.pool_idle_timeout {
+            // Set idle to 0, which prevents the pipe being broken every 2nd request
+            None => builder.pool_max_idle_per_host(0),
+            Some(timeout) => builder.pool_idle_timeout(timeout),
+        };
+        if let Some(interval) = self.http2_keep_alive_interval {
+            builder
+                .http2_keep_alive_interval(interval)
+                .http2_keep_alive_while_idle(true);
+        }
+        if self.http_version == HttpVersion::Http2PriorKnowledge {
+            builder.http2_only(true);
+        }
+        let enable_http2 = self.http_version != HttpVersion::Http1;
+        let connector = match self.connect_timeout {
+            Some(timeout) => connector::Connector::with_connect_timeout(enable_http2, timeout),
+            None if enable_http2 => connector::Connector::default_with_http2(),
+            None => connector::Connector::default(),
+        };
+        builder.build(connector)
+    }
+}
+
 pub struct Exporter {
     client: HttpClient,
     runtime: Runtime,
+    close_connections: bool,
 }
 
 pub struct Fields {
@@ -54,6 +119,18 @@ pub struct File<'a> {
     pub bytes: &'a [u8],
 }
 
+/// Where the time of an upload went, see [`ProfileExporter::send_with_timings`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SendTimings {
+    /// Establishing the connection, all None when a pooled connection was reused.
+    pub connect: connector::timings::ConnectTimings,
+    /// From the connection being ready to the response headers, so the upload of the profile and
+    /// its processing by the intake.
+    pub ttfb: std::time::Duration,
+    /// From the start of the request to the response headers.
+    pub total: std::time::Duration,
+}
+
 #[derive(Debug)]
 pub struct Request {
     timeout: Option<std::time::Duration>,
@@ -114,6 +191,27 @@ impl Request {
             => result,
         }
     }
+
+    async fn send_with_timings(
+        self,
+        client: &HttpClient,
+        cancel: Option<&CancellationToken>,
+    ) -> anyhow::Result<(hyper::Response<hyper::Body>, SendTimings)> {
+        let start = std::time::Instant::now();
+        let (response, connect) = connector::timings::measure(self.send(client, cancel)).await;
+        let total = start.elapsed();
+        let timings = SendTimings {
+            connect,
+            ttfb: total.saturating_sub(connect.total()),
+            total,
+        };
+        Ok((response?, timings))
+    }
+}
+
+fn tag_key(tag: &Tag) -> &str {
+    let tag = tag.as_ref();
+    tag.split_once(':').map_or(tag, |(key, _)| key)
 }
 
 impl ProfileExporter {
@@ -126,7 +224,8 @@ impl ProfileExporter {
     ///   package manager
     /// * `family` - Profile family, e.g. "ruby"
     /// * `tags` - Tags to include with every profile reported by this exporter. It's also possible
-    ///   to include profile-specific tags, see `additional_tags` on `build`.
+    ///   to include profile-specific tags, see `additional_tags` on `build`, which override the
+    ///   tags with the same key given here.
     /// * `endpoint` - Configuration for reporting data
     pub fn new<F, N, V>(
         profiling_library_name: N,
@@ -174,10 +273,17 @@ impl ProfileExporter {
     ) -> anyhow::Result<Request> {
         let mut form = multipart::Form::default();
 
-        // combine tags and additional_tags
+        // combine tags and additional_tags, the latter overriding tags of the exporter with the
+        // same key, e.g. a runtime-id changed after a fork
         let mut tags_profiler = String::new();
-        let other_tags = additional_tags.into_iter();
-        for tag in self.tags.iter().chain(other_tags).flatten() {
+        let additional_tags = additional_tags.map(Vec::as_slice).unwrap_or_default();
+        let is_overridden = |tag: &&Tag| {
+            additional_tags
+                .iter()
+                .any(|other| tag_key(other) == tag_key(tag))
+        };
+        let exporter_tags = self.tags.iter().flatten().filter(|tag| !is_overridden(tag));
+        for tag in exporter_tags.chain(additional_tags) {
             tags_profiler.push_str(tag.as_ref());
             tags_profiler.push(',');
         }
@@ -223,7 +329,7 @@ impl ProfileExporter {
             .map(|file| file.name.to_owned())
             .collect();
 
-        let event = json!({
+        let mut event = json!({
             "attachments": attachments,
             "tags_profiler": tags_profiler,
             "start": start.format("%Y-%m-%dT%H:%M:%S%.9fZ").to_string(),
@@ -233,8 +339,11 @@ impl ProfileExporter {
             "endpoint_counts" : endpoint_counts,
             "internal": internal_metadata.unwrap_or_else(|| json!({})),
             "info": info.unwrap_or_else(|| json!({})),
-        })
-        .to_string();
+        });
+        if let Some(process_tags) = process_tags::get_process_tags() {
+            event[process_tags::PROCESS_TAGS_KEY] = json!(process_tags);
+        }
+        let event = event.to_string();
 
         form.add_reader_file_with_mime(
             // Intake does not look for filename=event.json, it looks for name=event.
@@ -275,16 +384,18 @@ impl ProfileExporter {
             form.add_reader_file(file.name, Cursor::new(encoded), file.name)
         }
 
-        let builder = self
+        let mut builder = self
             .endpoint
             .into_request_builder(concat!("DDProf/", env!("CARGO_PKG_VERSION")))?
-            .method(http::Method::POST)
-            .header("Connection", "close")
-            .header("DD-EVP-ORIGIN", self.profiling_library_name.as_ref())
-            .header(
-                "DD-EVP-ORIGIN-VERSION",
-                self.profiling_library_version.as_ref(),
-            );
+            .method(http::Method::POST);
+        if self.exporter.close_connections {
+            builder = builder.header("Connection", "close");
+        }
+        let builder = user_agent::with_evp_origin(
+            builder,
+            self.profiling_library_name.as_ref(),
+            self.profiling_library_version.as_ref(),
+        );
 
         Ok(
             Request::from(form.set_body_convert::<hyper::Body, multipart::Body>(builder)?)
@@ -292,28 +403,60 @@ impl ProfileExporter {
         )
     }
 
+    /// Replaces the HTTP client used for uploads, see [`HttpClientConfig`].
+    pub fn set_http_client_config(&mut self, config: &HttpClientConfig) {
+        self.exporter.set_http_client_config(config);
+    }
+
+    /// Sends the header `name` with every upload, e.g. the authorization expected by a proxy in
+    /// front of the intake, see [`Endpoint::set_header`].
+    pub fn set_header(&mut self, name: &str, value: &str) -> anyhow::Result<()> {
+        self.endpoint.set_header(name.to_owned(), value.to_owned())
+    }
+
     pub fn send(
         &self,
         request: Request,
         cancel: Option<&CancellationToken>,
     ) -> anyhow::Result<HttpResponse> {
+        Ok(self.send_with_timings(request, cancel)?.0)
+    }
+
+    /// Like [`ProfileExporter::send`], also returning where the time of the upload went, e.g. to
+    /// tell slow networks from large profiles.
+    pub fn send_with_timings(
+        &self,
+        request: Request,
+        cancel: Option<&CancellationToken>,
+    ) -> anyhow::Result<(HttpResponse, SendTimings)> {
         self.exporter
             .runtime
-            .block_on(request.send(&self.exporter.client, cancel))
+            .block_on(request.send_with_timings(&self.exporter.client, cancel))
     }
 }
 
 impl Exporter {
     /// Creates a new Exporter, initializing the TLS stack.
     pub fn new() -> anyhow::Result<Self> {
-        // Set idle to 0, which prevents the pipe being broken every 2nd request
-        let client = hyper::Client::builder()
-            .pool_max_idle_per_host(0)
-            .build(connector::Connector::default());
+        Self::with_http_client_config(&HttpClientConfig::default())
+    }
+
+    /// Creates a new Exporter whose HTTP client is configured by `config`.
+    pub fn with_http_client_config(config: &HttpClientConfig) -> anyhow::Result<Self> {
         let runtime = tokio::runtime::Builder::new_current_thread()
             .enable_all()
             .build()?;
-        Ok(Self { client, runtime })
+        Ok(Self {
+            client: config.build_client(),
+            runtime,
+            close_connections: config.closes_connections(),
+        })
+    }
+
+    /// Replaces the HTTP client, dropping the connections of the previous one.
+    pub fn set_http_client_config(&mut self, config: &HttpClientConfig) {
+        self.client = config.build_client();
+        self.close_connections = config.closes_connections();
     }
 
     pub fn send(
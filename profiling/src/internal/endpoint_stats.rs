@@ -3,9 +3,9 @@
 
 use std::collections::HashMap;
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Default, PartialEq, Eq, Debug, Clone, Serialize)]
+#[derive(Default, PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct ProfiledEndpointsStats {
     count: HashMap<String, i64>,
@@ -2,12 +2,35 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::*;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+/// The time a local root span was running, in nanoseconds since the epoch, see
+/// [`Profile::add_span_interval`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpanInterval {
+    pub local_root_span_id: u64,
+    pub start: i64,
+    pub end: i64,
+}
+
+/// The endpoint of the samples taken from `start` on, until the start of the next segment of the
+/// [`Endpoints`] timeline.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct TimelineSegment {
+    start: i64,
+    endpoint: Option<StringId>,
+}
 
 pub struct Endpoints {
     pub endpoint_label: StringId,
     pub local_root_span_id_label: StringId,
     pub mappings: FxIndexMap<u64, StringId>,
+    pub span_intervals: Vec<SpanInterval>,
     pub stats: ProfiledEndpointsStats,
+    /// The span intervals resolved to their endpoints and sorted by time, built on the first
+    /// lookup after a change so that each sample is attributed in O(log n).
+    timeline: OnceLock<Vec<TimelineSegment>>,
 }
 
 impl Endpoints {
@@ -16,8 +39,70 @@ impl Endpoints {
             mappings: Default::default(),
             local_root_span_id_label: Default::default(),
             endpoint_label: Default::default(),
+            span_intervals: Default::default(),
             stats: Default::default(),
+            timeline: Default::default(),
+        }
+    }
+
+    pub fn add_mapping(&mut self, local_root_span_id: u64, endpoint: StringId) {
+        self.mappings.insert(local_root_span_id, endpoint);
+        self.timeline.take();
+    }
+
+    pub fn add_span_interval(&mut self, interval: SpanInterval) {
+        self.span_intervals.push(interval);
+        self.timeline.take();
+    }
+
+    /// Returns the endpoint of the span intervals containing the timestamp, or None if there is
+    /// none, or if they don't agree on a single endpoint, e.g. for concurrent requests.
+    pub fn endpoint_at(&self, timestamp: i64) -> Option<StringId> {
+        let timeline = self.timeline.get_or_init(|| self.build_timeline());
+        let segments = timeline.partition_point(|segment| segment.start <= timestamp);
+        timeline[..segments].last()?.endpoint
+    }
+
+    /// Sweeps over the starts and ends of the intervals whose span has an endpoint, keeping count
+    /// of the endpoints of the intervals containing each point in time.
+    fn build_timeline(&self) -> Vec<TimelineSegment> {
+        let mut events = Vec::with_capacity(self.span_intervals.len() * 2);
+        for interval in &self.span_intervals {
+            let Some(&endpoint) = self.mappings.get(&interval.local_root_span_id) else {
+                continue;
+            };
+            events.push((interval.start, true, endpoint));
+            // The end is inclusive, an interval ending at i64::MAX never ends.
+            if let Some(end) = interval.end.checked_add(1) {
+                events.push((end, false, endpoint));
+            }
+        }
+        events.sort_unstable_by_key(|&(time, _, _)| time);
+
+        let mut running = BTreeMap::<StringId, usize>::new();
+        let mut timeline: Vec<TimelineSegment> = Vec::new();
+        let mut events = events.into_iter().peekable();
+        while let Some((start, is_start, endpoint)) = events.next() {
+            if is_start {
+                *running.entry(endpoint).or_default() += 1;
+            } else if let Some(count) = running.get_mut(&endpoint) {
+                *count -= 1;
+                if *count == 0 {
+                    running.remove(&endpoint);
+                }
+            }
+            if events.peek().is_some_and(|&(next, _, _)| next == start) {
+                continue;
+            }
+            let endpoint = match running.len() {
+                1 => running.keys().next().copied(),
+                _ => None,
+            };
+            if timeline.last().map(|segment| segment.endpoint) != Some(endpoint) {
+                timeline.push(TimelineSegment { start, endpoint });
+            }
         }
+        timeline
     }
 }
 
@@ -6,12 +6,14 @@ use super::*;
 /// Represents a [pprof::Location] with some space-saving changes:
 ///  - The id is not stored on the struct. It's stored in the container that holds the struct.
 ///  - ids for linked objects use 32-bit numbers instead of 64 bit ones.
-///  - in libdatadog, we always use 1 Line per Location, so this is directly inlined into the
-///    struct.
+///  - in libdatadog, we always use at most 1 Line per Location, so this is directly inlined into
+///    the struct.
+///  - the function is optional, for locations which are only known by their address and are
+///    symbolicated later on.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub struct Location {
     pub mapping_id: MappingId,
-    pub function_id: FunctionId,
+    pub function_id: Option<FunctionId>,
     pub address: u64,
     pub line: i64,
 }
@@ -24,14 +26,21 @@ impl PprofItem for Location {
     type PprofMessage = pprof::Location;
 
     fn to_pprof(&self, id: Self::Id) -> Self::PprofMessage {
+        // Address-only locations have no line at all, instead of one pointing to an empty
+        // function.
+        let lines = if self.function_id.is_none() && self.line == 0 {
+            vec![]
+        } else {
+            vec![pprof::Line {
+                function_id: self.function_id.map_or(0, |id| id.to_raw_id()),
+                line: self.line,
+            }]
+        };
         pprof::Location {
             id: id.to_raw_id(),
             mapping_id: self.mapping_id.to_raw_id(),
             address: self.address,
-            lines: vec![pprof::Line {
-                function_id: self.function_id.to_raw_id(),
-                line: self.line,
-            }],
+            lines,
             is_folded: false,
         }
     }
@@ -8,6 +8,7 @@ mod label;
 mod location;
 mod mapping;
 mod observation;
+mod overhead;
 mod owned_types;
 mod profile;
 mod sample;
@@ -23,6 +24,7 @@ pub use label::*;
 pub use location::*;
 pub use mapping::*;
 pub use observation::*;
+pub use overhead::*;
 pub use profile::*;
 pub use sample::*;
 pub use stack_trace::*;
@@ -8,6 +8,7 @@ use super::timestamped_observations::TimestampedObservations;
 use super::trimmed_observation::{ObservationLength, TrimmedObservation};
 use crate::internal::Timestamp;
 use std::collections::HashMap;
+use std::ops::Range;
 
 struct NonEmptyObservations {
     // Samples with no timestamps are aggregated in-place as each observation is added
@@ -86,6 +87,89 @@ impl Observations {
             .map(|o| o.timestamped_samples_count)
             .unwrap_or(0)
     }
+
+    /// Returns the observations ordered by stack trace id, then label set id, then timestamp
+    /// (aggregated observations, which have none, come first), skipping the first `offset` ones
+    /// and returning at most `limit` of them. The order only changes when observations are added,
+    /// so consecutive pages neither overlap nor miss observations.
+    ///
+    /// Unlike [Observations::into_iter], the observations are kept. All timestamped observations
+    /// are decompressed to sort them, so prefer consuming them when all of them are needed.
+    pub fn sorted_page(
+        &mut self,
+        offset: usize,
+        limit: usize,
+    ) -> anyhow::Result<Vec<(Sample, Option<Timestamp>, Vec<i64>)>> {
+        let Some(observations) = self.inner.as_mut() else {
+            return Ok(Vec::new());
+        };
+
+        let mut entries: Vec<(Sample, Option<Timestamp>, Option<Vec<i64>>)> = observations
+            .aggregated_data
+            .data
+            .keys()
+            .map(|sample| (*sample, None, None))
+            .collect();
+        entries.extend(
+            observations
+                .timestamped_data
+                .iter()?
+                .map(|(sample, ts, values)| (sample, Some(ts), Some(values))),
+        );
+        // The sort is stable, so identical timestamped observations keep the order they were
+        // added in.
+        entries.sort_by_key(|(sample, ts, _)| {
+            (u32::from(sample.stacktrace), u32::from(sample.labels), *ts)
+        });
+
+        let aggregated_data = &mut observations.aggregated_data;
+        Ok(entries
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .filter_map(|(sample, ts, values)| {
+                let values = values.or_else(|| aggregated_data.get(&sample))?;
+                Some((sample, ts, values))
+            })
+            .collect())
+    }
+
+    /// Returns the aggregated observations, and the timestamped ones whose timestamp is in
+    /// `range`, in nanoseconds since the epoch.
+    ///
+    /// Like [Observations::sorted_page], the observations are kept.
+    pub fn in_range(
+        &mut self,
+        range: Range<i64>,
+    ) -> anyhow::Result<Vec<(Sample, Option<Timestamp>, Vec<i64>)>> {
+        let Some(observations) = self.inner.as_mut() else {
+            return Ok(Vec::new());
+        };
+
+        let obs_len = observations.obs_len;
+        let mut entries: Vec<_> = observations
+            .aggregated_data
+            .data
+            .iter_mut()
+            // SAFETY: The only way to build one of these is through [AggregatedObservations::add],
+            // which already checked that the length was correct.
+            .map(|(sample, values)| {
+                (
+                    *sample,
+                    None,
+                    unsafe { values.as_mut_slice(obs_len) }.to_vec(),
+                )
+            })
+            .collect();
+        entries.extend(
+            observations
+                .timestamped_data
+                .iter()?
+                .filter(|(_, ts, _)| range.contains(&ts.get()))
+                .map(|(sample, ts, values)| (sample, Some(ts), values)),
+        );
+        Ok(entries)
+    }
 }
 
 #[derive(Default)]
@@ -129,6 +213,15 @@ impl AggregatedObservations {
         self.data.len()
     }
 
+    fn get(&mut self, sample: &Sample) -> Option<Vec<i64>> {
+        let obs_len = self.obs_len;
+        self.data
+            .get_mut(sample)
+            // SAFETY: The only way to build one of these is through [Self::add], which already
+            // checked that the length was correct.
+            .map(|v| unsafe { v.as_mut_slice(obs_len) }.to_vec())
+    }
+
     #[allow(dead_code)]
     fn is_empty(&self) -> bool {
         self.data.is_empty()
@@ -251,6 +344,74 @@ mod tests {
         });
     }
 
+    #[test]
+    fn sorted_page_test() {
+        let mut o = Observations::new(1);
+        let s1 = Sample {
+            labels: LabelSetId::from_offset(2),
+            stacktrace: StackTraceId::from_offset(1),
+        };
+        let s2 = Sample {
+            labels: LabelSetId::from_offset(1),
+            stacktrace: StackTraceId::from_offset(2),
+        };
+        let s3 = Sample {
+            labels: LabelSetId::from_offset(1),
+            stacktrace: StackTraceId::from_offset(1),
+        };
+        let t1 = Some(Timestamp::new(1).unwrap());
+        let t2 = Some(Timestamp::new(2).unwrap());
+
+        o.add(s2, t2, vec![1]).unwrap();
+        o.add(s1, None, vec![2]).unwrap();
+        o.add(s2, t1, vec![3]).unwrap();
+        o.add(s3, None, vec![4]).unwrap();
+        o.add(s2, None, vec![5]).unwrap();
+        o.add(s1, None, vec![6]).unwrap();
+
+        let expected = vec![
+            (s3, None, vec![4]),
+            (s1, None, vec![8]),
+            (s2, None, vec![5]),
+            (s2, t1, vec![3]),
+            (s2, t2, vec![1]),
+        ];
+        assert_eq!(expected, o.sorted_page(0, usize::MAX).unwrap());
+        assert_eq!(expected[1..3], o.sorted_page(1, 2).unwrap());
+        assert_eq!(expected[4..], o.sorted_page(4, 2).unwrap());
+        assert!(o.sorted_page(5, 2).unwrap().is_empty());
+
+        // The observations are kept
+        assert_eq!(5, o.into_iter().count());
+    }
+
+    #[test]
+    fn in_range_test() {
+        let mut o = Observations::new(1);
+        let s1 = Sample {
+            labels: LabelSetId::from_offset(1),
+            stacktrace: StackTraceId::from_offset(1),
+        };
+        let t1 = Some(Timestamp::new(100).unwrap());
+        let t2 = Some(Timestamp::new(200).unwrap());
+        let t3 = Some(Timestamp::new(300).unwrap());
+
+        o.add(s1, t1, vec![1]).unwrap();
+        o.add(s1, t2, vec![2]).unwrap();
+        o.add(s1, t3, vec![3]).unwrap();
+        o.add(s1, None, vec![4]).unwrap();
+        o.add(s1, None, vec![5]).unwrap();
+
+        assert_eq!(
+            vec![(s1, None, vec![9]), (s1, t2, vec![2])],
+            o.in_range(150..300).unwrap()
+        );
+        assert_eq!(vec![(s1, None, vec![9])], o.in_range(400..500).unwrap());
+
+        // The observations are kept
+        assert_eq!(4, o.into_iter().count());
+    }
+
     #[test]
     fn different_lengths_panic_different_key_no_ts() {
         // These are only for test purposes. The only thing that matters is that
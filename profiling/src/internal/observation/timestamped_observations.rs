@@ -71,6 +71,18 @@ impl TimestampedObservations {
         Ok(())
     }
 
+    /// Iterates over the observations added so far, without consuming them. This flushes the
+    /// compressor, which compresses a bit worse, so it's meant for occasional use.
+    pub fn iter(&mut self) -> anyhow::Result<TimestampedObservationsIter> {
+        self.compressed_timestamped_data.flush()?;
+        Ok(TimestampedObservationsIter {
+            decoder: FrameDecoder::new(Cursor::new(
+                self.compressed_timestamped_data.get_ref().clone(),
+            )),
+            sample_types_len: self.sample_types_len,
+        })
+    }
+
     pub fn into_iter(self) -> TimestampedObservationsIter {
         TimestampedObservationsIter {
             decoder: FrameDecoder::new(Cursor::new(
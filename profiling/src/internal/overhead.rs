@@ -0,0 +1,71 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use std::time::Duration;
+
+/// The root frame of the samples holding the time spent in libdatadog, see
+/// [`super::Profile::set_overhead_sample_type`].
+pub const PROFILER_FRAME_NAME: &str = "[profiler]";
+
+/// The operations of libdatadog whose time can be recorded in the profile.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProfilerOperation {
+    /// Adding samples.
+    Add,
+    /// Serializing the previous profile.
+    Serialize,
+    /// Uploading the previous profile.
+    Upload,
+}
+
+impl ProfilerOperation {
+    pub const ALL: [ProfilerOperation; 3] = [
+        ProfilerOperation::Add,
+        ProfilerOperation::Serialize,
+        ProfilerOperation::Upload,
+    ];
+
+    /// The name of the leaf frame of the samples of the operation.
+    pub fn frame_name(self) -> &'static str {
+        match self {
+            ProfilerOperation::Add => "add",
+            ProfilerOperation::Serialize => "serialize",
+            ProfilerOperation::Upload => "upload",
+        }
+    }
+}
+
+/// The time spent in each operation since the profile was created or reset.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Overhead {
+    /// The offset of the sample type holding the time.
+    pub offset: usize,
+    nanos: [i64; ProfilerOperation::ALL.len()],
+}
+
+impl Overhead {
+    pub fn new(offset: usize) -> Self {
+        Overhead {
+            offset,
+            nanos: Default::default(),
+        }
+    }
+
+    pub fn record(&mut self, operation: ProfilerOperation, duration: Duration) {
+        let nanos = duration.as_nanos().min(i64::MAX as u128) as i64;
+        let total = &mut self.nanos[operation as usize];
+        *total = total.saturating_add(nanos);
+    }
+
+    /// Returns the time recorded for each operation, skipping the ones without time, and starts
+    /// over.
+    pub fn take(&mut self) -> Vec<(ProfilerOperation, i64)> {
+        let nanos = std::mem::take(&mut self.nanos);
+        ProfilerOperation::ALL
+            .into_iter()
+            .zip(nanos)
+            .filter(|(_, nanos)| *nanos > 0)
+            .collect()
+    }
+}
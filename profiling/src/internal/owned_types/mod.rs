@@ -8,6 +8,8 @@ use crate::api;
 pub struct ValueType {
     pub typ: Box<str>,
     pub unit: Box<str>,
+    pub aggregation: Option<api::Aggregation>,
+    pub display_unit: Option<Box<str>>,
 }
 
 impl<'a> From<&'a api::ValueType<'a>> for ValueType {
@@ -16,13 +18,46 @@ impl<'a> From<&'a api::ValueType<'a>> for ValueType {
         Self {
             typ: Box::from(value_type.r#type),
             unit: Box::from(value_type.unit),
+            aggregation: value_type.aggregation,
+            display_unit: value_type.display_unit.map(Box::from),
         }
     }
 }
 
 impl<'a> From<&'a ValueType> for api::ValueType<'a> {
     fn from(value: &'a ValueType) -> Self {
-        Self::new(&value.typ, &value.unit)
+        api::ValueType {
+            r#type: &value.typ,
+            unit: &value.unit,
+            aggregation: value.aggregation,
+            display_unit: value.display_unit.as_deref(),
+        }
+    }
+}
+
+/// The prefix of the keys of the internal labels holding the hints of the sample types.
+pub const SAMPLE_TYPE_HINT_LABEL_PREFIX: &str = "_dd.sample_type_hint.";
+
+impl ValueType {
+    /// The hints as `_dd.sample_type_hint.<type>.<key>` label keys and their values, empty if there
+    /// are none.
+    pub fn hints(&self) -> impl Iterator<Item = (String, &str)> + '_ {
+        let aggregation = self
+            .aggregation
+            .map(|aggregation| ("aggregation", aggregation.as_str()));
+        let display_unit = self
+            .display_unit
+            .as_deref()
+            .map(|display_unit| ("display_unit", display_unit));
+        aggregation
+            .into_iter()
+            .chain(display_unit)
+            .map(|(key, value)| {
+                (
+                    format!("{SAMPLE_TYPE_HINT_LABEL_PREFIX}{}.{key}", self.typ),
+                    value,
+                )
+            })
     }
 }
 
@@ -278,10 +278,10 @@ fn assert_samples_eq(
             // is +1 of the index in the vector of Locations in internal::Profile.
             let location = &profile.locations[*loc_id as usize - 1];
             let mapping = &profile.mappings[location.mapping_id as usize - 1];
-            // internal::Location::to_pprof() always creates a single line.
-            assert!(location.lines.len() == 1);
-            let line = location.lines[0];
-            let function = profile.functions[line.function_id as usize - 1];
+            // internal::Location::to_pprof() creates a single line, unless the location has
+            // neither a function nor a line.
+            assert!(location.lines.len() <= 1);
+            let line = location.lines.first().copied().unwrap_or_default();
             assert!(!location.is_folded);
 
             // TODO: Consider using &str from the string table and make an `api::` mapping
@@ -293,15 +293,22 @@ fn assert_samples_eq(
                 profile.string_table_fetch_owned(mapping.filename),
                 profile.string_table_fetch_owned(mapping.build_id),
             );
-            let owned_function = Function::new(
-                profile
-                    .string_table_fetch(function.name)
-                    .clone()
-                    .into_boxed_str(),
-                profile.string_table_fetch_owned(function.system_name),
-                profile.string_table_fetch_owned(function.filename),
-                function.start_line,
-            );
+            // Locations with an empty function have none in the pprof.
+            let owned_function = match line.function_id {
+                0 => Function::default(),
+                function_id => {
+                    let function = profile.functions[function_id as usize - 1];
+                    Function::new(
+                        profile
+                            .string_table_fetch(function.name)
+                            .clone()
+                            .into_boxed_str(),
+                        profile.string_table_fetch_owned(function.system_name),
+                        profile.string_table_fetch_owned(function.filename),
+                        function.start_line,
+                    )
+                }
+            };
             let owned_location =
                 Location::new(owned_mapping, owned_function, location.address, line.line);
 
@@ -8,14 +8,68 @@ use self::api::UpscalingInfo;
 use super::*;
 use crate::api;
 use crate::collections::identifiable::*;
-use crate::collections::string_table::StringTable;
+use crate::collections::string_table::{ArenaStats, StringTable};
 use crate::iter::{IntoLendingIterator, LendingIterator};
 use crate::pprof::sliced_proto::*;
 use crate::serializer::CompressedProtobufSerializer;
 use anyhow::Context;
 use std::borrow::Cow;
-use std::collections::HashMap;
-use std::time::{Duration, SystemTime};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Provides the labels of a sample context, see [`Profile::set_context_provider`].
+/// The labels are handed to the second argument one by one.
+pub type SampleContextProvider = Arc<dyn Fn(u64, &mut dyn FnMut(api::Label)) + Send + Sync>;
+
+/// Key of the label holding the context id of samples added with
+/// [`Profile::add_sample_with_context`]. It is replaced by the context labels when serializing.
+const CONTEXT_ID_LABEL_KEY: &str = "_dd.sample_context_id";
+
+/// Every profile, including each reset of a profile, gets a distinct generation, so that
+/// [`InternedStackTrace`]s and [`PreinternedIds`] can't be used with another profile than the one
+/// they come from. It starts at 1, so that default [`PreinternedIds`] never match a profile.
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+/// The name of the frame replacing the frames dropped by [`Profile::set_max_frames`].
+pub const TRUNCATED_FRAME_NAME: &str = "[truncated]";
+
+/// The lifecycle of a [`Profile`]. Samples, endpoints and upscaling rules can only be added while
+/// it is `Open`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProfileState {
+    Open,
+    /// The aggregated data is being encoded.
+    Serializing,
+    /// The data was serialized with [`Profile::serialize`], the profile needs to be reset before
+    /// it can be used again.
+    Serialized,
+}
+
+/// Returned when an operation is not allowed in the current [`ProfileState`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct ProfileStateError {
+    pub operation: &'static str,
+    pub state: ProfileState,
+}
+
+impl std::fmt::Display for ProfileStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} is not allowed while the profile is {:?}",
+            self.operation, self.state
+        )
+    }
+}
+
+impl std::error::Error for ProfileStateError {}
 
 pub struct Profile {
     /// When profiles are reset, the sample-types need to be preserved. This
@@ -26,8 +80,31 @@ pub struct Profile {
     /// When profiles are reset, the period needs to be preserved. This
     /// stores it in a way that does not depend on the string table.
     owned_period: Option<owned_types::Period>,
+    /// Preserved across resets, like the period and sample types.
+    context_provider: Option<SampleContextProvider>,
+    /// Only interned once a sample with a context is added.
+    context_id_key: Option<StringId>,
+    /// Preserved across resets, like the period and sample types.
+    max_frames: Option<NonZeroUsize>,
+    /// Preserved across resets, like the period and sample types.
+    deterministic_encoding: bool,
+    /// Preserved across resets, like the period and sample types.
+    preserve_upscaling_rules: bool,
+    /// Preserved across resets, like the period and sample types.
+    preserve_endpoints: bool,
+    /// The offsets of the sample types whose values are zeroed and left out of the pprof, see
+    /// [`Profile::set_sample_type_enabled`]. Preserved across resets, like the period and sample
+    /// types.
+    disabled_sample_types: Box<[bool]>,
+    /// The time spent in libdatadog, see [`Profile::set_overhead_sample_type`]. The sample type is
+    /// preserved across resets, like the period and sample types, but not the time.
+    overhead: Option<Overhead>,
+    /// Number of samples whose stack was truncated to `max_frames`.
+    truncated_stacks: u64,
     endpoints: Endpoints,
     functions: FxIndexSet<Function>,
+    /// Identifies the profile and its resets, see [`InternedStackTrace`].
+    generation: u64,
     labels: FxIndexSet<Label>,
     label_sets: FxIndexSet<LabelSet>,
     locations: FxIndexSet<Location>,
@@ -37,16 +114,42 @@ pub struct Profile {
     sample_types: Box<[ValueType]>,
     stack_traces: FxIndexSet<StackTrace>,
     start_time: SystemTime,
+    state: ProfileState,
     strings: StringTable,
     timestamp_key: StringId,
     upscaling_rules: UpscalingRules,
 }
 
+/// The ids of the functions and mappings interned by [`Profile::preintern`], in the order of the
+/// schema. They are used to build the [`PreinternedLocation`]s of stack traces.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PreinternedIds {
+    /// Identifies the profile, and its reset, the ids were interned into. See
+    /// [`Profile::ensure_preinterned`].
+    pub generation: u64,
+    pub functions: Vec<FunctionId>,
+    pub mappings: Vec<MappingId>,
+}
+
+/// A location made of the ids returned by [`Profile::preintern`], see
+/// [`Profile::intern_preinterned_stacktrace`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PreinternedLocation {
+    /// `None` for locations which are only known by their mapping and address.
+    pub function: Option<FunctionId>,
+    /// `None` for locations without a mapping.
+    pub mapping: Option<MappingId>,
+    pub address: u64,
+    pub line: i64,
+}
+
 pub struct EncodedProfile {
     pub start: SystemTime,
     pub end: SystemTime,
     pub buffer: Vec<u8>,
     pub endpoints_stats: ProfiledEndpointsStats,
+    /// The [`Profile::state_fingerprint`] of the profile right before it was serialized.
+    pub state_fingerprint: u64,
 }
 
 /// Public API
@@ -58,15 +161,62 @@ impl Profile {
         local_root_span_id: u64,
         endpoint: Cow<str>,
     ) -> anyhow::Result<()> {
+        self.ensure_open("add_endpoint")?;
         let interned_endpoint = self.intern(endpoint.as_ref());
 
         self.endpoints
-            .mappings
-            .insert(local_root_span_id, interned_endpoint);
+            .add_mapping(local_root_span_id, interned_endpoint);
+        Ok(())
+    }
+
+    /// Same as [`Profile::add_endpoint`] for many local root spans at once. An endpoint repeated
+    /// for consecutive spans is only interned once.
+    pub fn add_endpoints<'a, I>(&mut self, endpoints: I) -> anyhow::Result<()>
+    where
+        I: IntoIterator<Item = (u64, Cow<'a, str>)>,
+    {
+        self.ensure_open("add_endpoints")?;
+        let mut previous: Option<(Cow<str>, StringId)> = None;
+        for (local_root_span_id, endpoint) in endpoints {
+            let interned_endpoint = match previous {
+                Some((ref previous, id)) if *previous == endpoint => id,
+                _ => self.intern(endpoint.as_ref()),
+            };
+            self.endpoints
+                .add_mapping(local_root_span_id, interned_endpoint);
+            previous = Some((endpoint, interned_endpoint));
+        }
+        Ok(())
+    }
+
+    /// Registers the time the local root span was running. Timestamped samples without a "local
+    /// root span id" label get the endpoint of the span, as set with [`Profile::add_endpoint`], if
+    /// they were taken within the interval. This improves the endpoint attribution of the timeline
+    /// for samples which can't be tied to a span when they are taken.
+    ///
+    /// Samples within the intervals of several spans only get an endpoint if the spans agree on
+    /// it. Without any interval, only the samples with the label get an endpoint.
+    pub fn add_span_interval(
+        &mut self,
+        local_root_span_id: u64,
+        start: Timestamp,
+        end: Timestamp,
+    ) -> anyhow::Result<()> {
+        self.ensure_open("add_span_interval")?;
+        anyhow::ensure!(
+            start <= end,
+            "the span interval ends before it starts: {start}..{end}"
+        );
+        self.endpoints.add_span_interval(SpanInterval {
+            local_root_span_id,
+            start: start.get(),
+            end: end.get(),
+        });
         Ok(())
     }
 
     pub fn add_endpoint_count(&mut self, endpoint: Cow<str>, value: i64) -> anyhow::Result<()> {
+        self.ensure_open("add_endpoint_count")?;
         self.endpoints
             .stats
             .add_endpoint_count(endpoint.into_owned(), value);
@@ -78,42 +228,61 @@ impl Profile {
         sample: api::Sample,
         timestamp: Option<Timestamp>,
     ) -> anyhow::Result<()> {
-        anyhow::ensure!(
-            sample.values.len() == self.sample_types.len(),
-            "expected {} sample types, but sample had {} sample types",
-            self.sample_types.len(),
-            sample.values.len(),
-        );
-
-        self.validate_sample_labels(&sample)?;
-        let labels: Vec<_> = sample
-            .labels
-            .iter()
-            .map(|label| {
-                let key = self.intern(label.key);
-                let internal_label = if let Some(s) = label.str {
-                    let str = self.intern(s);
-                    Label::str(key, str)
-                } else {
-                    let num = label.num;
-                    let num_unit = label.num_unit.map(|s| self.intern(s));
-                    Label::num(key, num, num_unit)
-                };
+        self.add_sample_internal(sample, timestamp, None)
+    }
 
-                self.labels.dedup(internal_label)
-            })
-            .collect();
-        let labels = self.label_sets.dedup(LabelSet::new(labels));
+    /// Adds a sample attached to the numeric `context_id`. Instead of passing labels that are
+    /// common to many samples (and having to build their strings at sample time), the caller
+    /// registers a context provider with [`Profile::set_context_provider`], which is asked for
+    /// the labels of each context when the profile is serialized.
+    pub fn add_sample_with_context(
+        &mut self,
+        sample: api::Sample,
+        timestamp: Option<Timestamp>,
+        context_id: u64,
+    ) -> anyhow::Result<()> {
+        self.add_sample_internal(sample, timestamp, Some(context_id))
+    }
 
-        let locations = sample
-            .locations
-            .iter()
-            .map(|l| self.add_location(l))
-            .collect();
+    /// Interns the stack trace made of `locations` once, so that samples of a stack which is
+    /// sampled repeatedly can be added with [`Profile::add_sample_by_stacktrace`] without
+    /// converting its locations each time. The frames are truncated like the ones of
+    /// [`Profile::add_sample`].
+    ///
+    /// The returned stack trace is only valid for this profile, until it is reset.
+    pub fn intern_stacktrace(
+        &mut self,
+        locations: &[api::Location],
+    ) -> anyhow::Result<InternedStackTrace> {
+        self.ensure_open("intern_stacktrace")?;
+        Ok(InternedStackTrace {
+            generation: self.generation,
+            id: self.add_locations(locations),
+        })
+    }
 
-        let stacktrace = self.add_stacktrace(locations);
+    /// Same as [`Profile::add_sample`], for the stack trace returned by
+    /// [`Profile::intern_stacktrace`]. Fails if the stack trace was interned by another profile
+    /// or before this profile was reset.
+    pub fn add_sample_by_stacktrace(
+        &mut self,
+        stacktrace: InternedStackTrace,
+        values: Vec<i64>,
+        labels: &[api::Label],
+        timestamp: Option<Timestamp>,
+    ) -> anyhow::Result<()> {
+        self.ensure_open("add_sample_by_stacktrace")?;
+        let start = self.overhead.map(|_| Instant::now());
+        anyhow::ensure!(
+            stacktrace.generation == self.generation,
+            "the stack trace was interned by another profile, or before the profile was reset"
+        );
+        self.ensure_sample_values(&values)?;
+        let labels = self.add_sample_labels(labels, None)?;
+        let values = self.zero_disabled_values(values);
         self.observations
-            .add(Sample::new(labels, stacktrace), timestamp, sample.values)?;
+            .add(Sample::new(labels, stacktrace.id), timestamp, values)?;
+        self.record_overhead_since(ProfilerOperation::Add, start);
         Ok(())
     }
 
@@ -124,6 +293,7 @@ impl Profile {
         label_value: &str,
         upscaling_info: UpscalingInfo,
     ) -> anyhow::Result<()> {
+        self.ensure_open("add_upscaling_rule")?;
         let label_name_id = self.intern(label_name);
         let label_value_id = self.intern(label_value);
         self.upscaling_rules.add(
@@ -137,6 +307,237 @@ impl Profile {
         Ok(())
     }
 
+    /// Adds an upscaling rule for the samples with a numeric `label_name` label within `range`.
+    pub fn add_upscaling_rule_for_range(
+        &mut self,
+        offset_values: &[usize],
+        label_name: &str,
+        range: Range<i64>,
+        upscaling_info: UpscalingInfo,
+    ) -> anyhow::Result<()> {
+        self.ensure_open("add_upscaling_rule_for_range")?;
+        let label_name_id = self.intern(label_name);
+        self.upscaling_rules.add_for_range(
+            offset_values,
+            (label_name, label_name_id),
+            range,
+            upscaling_info,
+            self.sample_types.len(),
+        )
+    }
+
+    /// Sets the provider of the labels for the context ids of samples added with
+    /// [`Profile::add_sample_with_context`]. It is called once per distinct context id during
+    /// serialization, possibly from another thread. Without a provider, the context ids are
+    /// dropped from the serialized samples.
+    pub fn set_context_provider(&mut self, provider: SampleContextProvider) {
+        self.context_provider = Some(provider);
+    }
+
+    /// Limits the number of frames kept per sample. Deeper stacks are truncated when the sample
+    /// is added: the `max_frames` frames on the leaf side are kept and a frame named
+    /// [`TRUNCATED_FRAME_NAME`] is appended in place of the dropped ones. `None` keeps all frames,
+    /// which is the default.
+    pub fn set_max_frames(&mut self, max_frames: Option<NonZeroUsize>) {
+        self.max_frames = max_frames;
+    }
+
+    /// Makes the serialized pprofs canonical: the string table is sorted, the mappings, functions
+    /// and locations are sorted and renumbered, and so are the samples and their labels. Profiles
+    /// holding the same data are then encoded to the same bytes, whatever the order the data was
+    /// added in, e.g. for tests comparing the output of several bindings. This costs decoding and
+    /// encoding the pprof again, so it is off by default.
+    pub fn set_deterministic_encoding(&mut self, enabled: bool) {
+        self.deterministic_encoding = enabled;
+    }
+
+    /// Keeps the upscaling rules when the profile is reset, instead of having to add them again
+    /// for each profile. Off by default.
+    pub fn set_preserve_upscaling_rules(&mut self, enabled: bool) {
+        self.preserve_upscaling_rules = enabled;
+    }
+
+    /// Keeps the endpoints of the local root spans which may still be running when the profile is
+    /// reset, so that their samples in the next profile get an endpoint without adding it again.
+    /// Spans with a span interval ending before the reset are done, and left behind. Off by
+    /// default.
+    pub fn set_preserve_endpoints(&mut self, enabled: bool) {
+        self.preserve_endpoints = enabled;
+    }
+
+    /// Disables or re-enables the sample type at `offset`, e.g. to turn off an expensive
+    /// dimension at runtime. The values of disabled sample types are zeroed when adding samples,
+    /// and the sample type is left out of the serialized pprof, while the samples keep the same
+    /// number of values. The setting is kept when the profile is reset. All sample types are
+    /// enabled by default.
+    pub fn set_sample_type_enabled(&mut self, offset: usize, enabled: bool) -> anyhow::Result<()> {
+        let disabled = self
+            .disabled_sample_types
+            .get_mut(offset)
+            .with_context(|| {
+                format!(
+                    "sample type offset {offset} is out of range, the profile has {} sample types",
+                    self.sample_types.len()
+                )
+            })?;
+        *disabled = !enabled;
+        Ok(())
+    }
+
+    /// Records the time spent in libdatadog as samples of the profile, so that users can see the
+    /// overhead of profiling in the profile itself. The time is added to the values of the
+    /// sample type at `offset`, which should be a time in nanoseconds, e.g. "wall-time", under a
+    /// [`PROFILER_FRAME_NAME`] root frame, with a leaf frame per [`ProfilerOperation`]. `None`
+    /// disables it, which is the default. The setting is kept when the profile is reset.
+    ///
+    /// Adding samples and serializing with [`Profile::serialize`] are recorded automatically.
+    /// Other operations, e.g. uploads, must be recorded by the caller with
+    /// [`Profile::record_overhead`].
+    pub fn set_overhead_sample_type(&mut self, offset: Option<usize>) -> anyhow::Result<()> {
+        if let Some(offset) = offset {
+            anyhow::ensure!(
+                offset < self.sample_types.len(),
+                "sample type offset {offset} is out of range, the profile has {} sample types",
+                self.sample_types.len()
+            );
+        }
+        self.overhead = offset.map(Overhead::new);
+        Ok(())
+    }
+
+    /// Adds `duration` to the time spent in `operation`, if enabled with
+    /// [`Profile::set_overhead_sample_type`]. It shows up in the profile when it is serialized.
+    pub fn record_overhead(&mut self, operation: ProfilerOperation, duration: Duration) {
+        if let Some(overhead) = &mut self.overhead {
+            overhead.record(operation, duration);
+        }
+    }
+
+    /// Returns whether the sample type at `offset` exists and is enabled, see
+    /// [`Profile::set_sample_type_enabled`].
+    pub fn is_sample_type_enabled(&self, offset: usize) -> bool {
+        self.disabled_sample_types.get(offset) == Some(&false)
+    }
+
+    /// Returns the number of sample types, which is the number of values of each sample.
+    pub fn sample_types_len(&self) -> usize {
+        self.sample_types.len()
+    }
+
+    /// Returns the offset of the values of the `sample_type` type in the samples, if the profile
+    /// has it.
+    pub fn sample_type_index(&self, sample_type: &str) -> Option<usize> {
+        self.sample_types
+            .iter()
+            .position(|value_type| self.strings.get(value_type.r#type) == Some(sample_type))
+    }
+
+    /// Returns the number of samples added since the last reset whose stack was truncated, see
+    /// [`Profile::set_max_frames`].
+    pub fn truncated_stacks_count(&self) -> u64 {
+        self.truncated_stacks
+    }
+
+    /// Interns the items of the `schema` up front, so that the first samples referencing them
+    /// don't pay for growing and hashing into the tables. This is meant to be called right after
+    /// the profile is created or reset, as resetting drops all interned items.
+    ///
+    /// The returned ids of the functions and mappings make up the stack traces passed to
+    /// [`Profile::intern_preinterned_stacktrace`], which skips looking them up again. The strings
+    /// of the schema are only interned, e.g. for the labels of the samples.
+    ///
+    /// Interning is deterministic: preinterning the same schema right after each reset yields the
+    /// same ids, so the returned ids can be cached by the caller. Their `generation` changes with
+    /// each reset though, which lets [`Profile::ensure_preinterned`] catch ids that were cached
+    /// across a reset without preinterning the schema again.
+    pub fn preintern(&mut self, schema: &api::InternSchema) -> anyhow::Result<PreinternedIds> {
+        self.ensure_open("preintern")?;
+        self.functions.reserve(schema.functions.len());
+        self.mappings.reserve(schema.mappings.len());
+
+        for string in schema.strings {
+            self.intern(string);
+        }
+        let functions = schema
+            .functions
+            .iter()
+            .map(|function| self.add_function(function))
+            .collect();
+        let mappings = schema
+            .mappings
+            .iter()
+            .map(|mapping| self.add_mapping(mapping))
+            .collect();
+        Ok(PreinternedIds {
+            generation: self.generation,
+            functions,
+            mappings,
+        })
+    }
+
+    /// Same as [`Profile::intern_stacktrace`], for locations made of the `ids` returned by
+    /// [`Profile::preintern`]. Fails if the `ids` are stale, see [`Profile::ensure_preinterned`],
+    /// or if a location refers to a function or mapping this profile doesn't know.
+    pub fn intern_preinterned_stacktrace(
+        &mut self,
+        ids: &PreinternedIds,
+        locations: &[PreinternedLocation],
+    ) -> anyhow::Result<InternedStackTrace> {
+        self.ensure_open("intern_preinterned_stacktrace")?;
+        self.ensure_preinterned(ids)?;
+        let max_frames = self.max_frames.map_or(usize::MAX, NonZeroUsize::get);
+        let mut location_ids = Vec::with_capacity(locations.len().min(max_frames) + 1);
+        for location in locations.iter().take(max_frames) {
+            if let Some(function_id) = location.function {
+                anyhow::ensure!(
+                    function_id.to_raw_id() <= self.functions.len() as u64,
+                    "unknown function id {function_id:?}"
+                );
+            }
+            let mapping_id = match location.mapping {
+                Some(mapping_id) => {
+                    anyhow::ensure!(
+                        mapping_id.to_raw_id() <= self.mappings.len() as u64,
+                        "unknown mapping id {mapping_id:?}"
+                    );
+                    mapping_id
+                }
+                None => self.add_mapping(&api::Mapping::default()),
+            };
+            location_ids.push(self.locations.dedup(Location {
+                mapping_id,
+                function_id: location.function,
+                address: location.address,
+                line: location.line,
+            }));
+        }
+        if locations.len() > max_frames {
+            self.truncated_stacks += 1;
+            location_ids.push(self.add_location(&api::Location {
+                function: api::Function {
+                    name: TRUNCATED_FRAME_NAME,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }));
+        }
+        Ok(InternedStackTrace {
+            generation: self.generation,
+            id: self.add_stacktrace(location_ids),
+        })
+    }
+
+    /// Fails if the `ids` were not returned by [`Profile::preintern`] on this profile since it was
+    /// last reset. Ids from before a reset would otherwise silently refer to whatever was interned
+    /// at the same position afterwards.
+    pub fn ensure_preinterned(&self, ids: &PreinternedIds) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            ids.generation == self.generation,
+            "the ids were interned by another profile, or before the profile was reset"
+        );
+        Ok(())
+    }
+
     /// Creates a profile with `start_time`.
     /// Initializes the string table to hold:
     ///  - "" (the empty string)
@@ -168,11 +569,61 @@ impl Profile {
             self.owned_sample_types.take(),
             start_time.unwrap_or_else(SystemTime::now),
         );
+        profile.context_provider = self.context_provider.clone();
+        profile.max_frames = self.max_frames;
+        profile.deterministic_encoding = self.deterministic_encoding;
+        profile.preserve_upscaling_rules = self.preserve_upscaling_rules;
+        profile.preserve_endpoints = self.preserve_endpoints;
+        profile
+            .disabled_sample_types
+            .clone_from(&self.disabled_sample_types);
+        profile.overhead = self.overhead.map(|overhead| match self.state {
+            // Holds the time spent serializing the previous profile, which belongs to the next.
+            ProfileState::Serialized => overhead,
+            _ => Overhead::new(overhead.offset),
+        });
+
+        // Carried over before the swap, so that a failure leaves this profile as it was.
+        let carried_over = (|| {
+            if self.preserve_upscaling_rules {
+                self.carry_over_upscaling_rules(&mut profile)?;
+            }
+            if self.preserve_endpoints {
+                self.carry_over_endpoints(&mut profile)?;
+            }
+            anyhow::Ok(())
+        })();
+        if let Err(e) = carried_over {
+            self.owned_period = profile.owned_period.take();
+            self.owned_sample_types = profile.owned_sample_types.take();
+            return Err(e);
+        }
 
         std::mem::swap(&mut *self, &mut profile);
         Ok(profile)
     }
 
+    /// Serializes the aggregated profile like [`Profile::serialize_into_compressed_pprof`], but
+    /// keeps the (now empty) profile around in the [`ProfileState::Serialized`] state. Adding data
+    /// to it fails until it is reset with [`Profile::reset_and_return_previous`].
+    pub fn serialize(
+        &mut self,
+        end_time: Option<SystemTime>,
+        duration: Option<Duration>,
+    ) -> anyhow::Result<EncodedProfile> {
+        self.ensure_open("serialize")?;
+        let profile = self.reset_and_return_previous(None)?;
+        self.state = ProfileState::Serialized;
+        let start = self.overhead.map(|_| Instant::now());
+        let encoded = profile.serialize_into_compressed_pprof(end_time, duration);
+        self.record_overhead_since(ProfilerOperation::Serialize, start);
+        encoded
+    }
+
+    pub fn state(&self) -> ProfileState {
+        self.state
+    }
+
     /// Serialize the aggregated profile, adding the end time and duration.
     /// # Arguments
     /// * `end_time` - Optional end time of the profile. Passing None will use the current time.
@@ -185,8 +636,12 @@ impl Profile {
         end_time: Option<SystemTime>,
         duration: Option<Duration>,
     ) -> anyhow::Result<EncodedProfile> {
+        self.ensure_open("serialize")?;
+        self.add_overhead_samples()?;
+        self.state = ProfileState::Serializing;
         let end = end_time.unwrap_or_else(SystemTime::now);
         let start = self.start_time;
+        let state_fingerprint = self.state_fingerprint();
         let endpoints_stats = std::mem::take(&mut self.endpoints.stats);
         let duration_nanos = duration
             .unwrap_or_else(|| {
@@ -198,11 +653,190 @@ impl Profile {
             })
             .as_nanos()
             .min(i64::MAX as u128) as i64;
-        let (period, period_type) = match self.period {
-            Some(tuple) => (tuple.0, Some(tuple.1.into())),
-            None => (0, None),
+        let mut encoder = Self::pprof_encoder();
+        let observations = std::mem::take(&mut self.observations);
+        let header = self.encode_samples(&mut encoder, observations, start, duration_nanos)?;
+        let deterministic_encoding = self.deterministic_encoding;
+
+        // The tables are consumed while they are encoded, like the observations. This allows Rust
+        // to release memory faster, reducing our peak RSS.
+        for item in into_pprof_iter(self.mappings) {
+            encoder.encode(ProfileMappingsEntry::from(item))?;
+        }
+
+        for item in into_pprof_iter(self.locations) {
+            encoder.encode(ProfileLocationsEntry::from(item))?;
+        }
+
+        for item in into_pprof_iter(self.functions) {
+            encoder.encode(ProfileFunctionsEntry::from(item))?;
+        }
+
+        let mut lender = self.strings.into_lending_iter();
+        while let Some(item) = lender.next() {
+            encoder.encode_string_table_entry(item)?;
+        }
+
+        Ok(EncodedProfile {
+            start,
+            end,
+            buffer: Self::finish_pprof(encoder, header, deterministic_encoding)?,
+            endpoints_stats,
+            state_fingerprint,
+        })
+    }
+
+    /// Serializes the samples whose timestamp is in `start..end`, along with all the aggregated
+    /// samples, which have no timestamp. Unlike [`Profile::serialize`], the profile is left as is,
+    /// so that e.g. the timeline around an incident can be uploaded on demand while the profile
+    /// keeps collecting samples.
+    ///
+    /// The serialized profile starts at `start` and lasts until `end`. It holds no endpoint stats,
+    /// these are only reported by the full profile.
+    ///
+    /// Strings of the sample contexts and hints may get interned, like when serializing the full
+    /// profile, so the [`Profile::state_fingerprint`] can change.
+    pub fn serialize_range(
+        &mut self,
+        start: SystemTime,
+        end: SystemTime,
+    ) -> anyhow::Result<EncodedProfile> {
+        self.ensure_open("serialize_range")?;
+        let timestamp_nanos = |time: SystemTime| {
+            time.duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |duration| {
+                    duration.as_nanos().min(i64::MAX as u128) as i64
+                })
         };
+        let range = timestamp_nanos(start)..timestamp_nanos(end);
+        let duration_nanos = range.end.saturating_sub(range.start).max(0);
+        let state_fingerprint = self.state_fingerprint();
+        let observations = self.observations.in_range(range)?;
+
+        let mut encoder = Self::pprof_encoder();
+        let header = self.encode_samples(&mut encoder, observations, start, duration_nanos)?;
+
+        // Unlike for the full profile, the tables are only borrowed, as the profile is kept.
+        for item in pprof_iter(&self.mappings) {
+            encoder.encode(ProfileMappingsEntry::from(item))?;
+        }
+
+        for item in pprof_iter(&self.locations) {
+            encoder.encode(ProfileLocationsEntry::from(item))?;
+        }
+
+        for item in pprof_iter(&self.functions) {
+            encoder.encode(ProfileFunctionsEntry::from(item))?;
+        }
+
+        for item in self.strings.iter() {
+            encoder.encode_string_table_entry(item)?;
+        }
+
+        Ok(EncodedProfile {
+            start,
+            end,
+            buffer: Self::finish_pprof(encoder, header, self.deterministic_encoding)?,
+            endpoints_stats: ProfiledEndpointsStats::default(),
+            state_fingerprint,
+        })
+    }
+
+    /// Returns how much memory the string table's arenas use, commit and reserve.
+    pub fn string_arena_stats(&self) -> ArenaStats {
+        self.strings.arena_stats()
+    }
+
+    /// Returns a hash over the number of items in each of the profile's collections and over the
+    /// bytes used in the string arena. These only grow while adding samples, so a runtime can
+    /// remember the fingerprint and compare it with the one of the [`EncodedProfile`] to detect
+    /// that the profile was modified behind its back (e.g. memory corruption caused by a
+    /// misbehaving native extension) before uploading it.
+    pub fn state_fingerprint(&self) -> u64 {
+        let mut hasher = rustc_hash::FxHasher::default();
+        (
+            self.strings.len(),
+            self.strings.arena_used_bytes(),
+            self.functions.len(),
+            self.labels.len(),
+            self.label_sets.len(),
+            self.locations.len(),
+            self.mappings.len(),
+            self.stack_traces.len(),
+            self.observations.aggregated_samples_count(),
+            self.observations.timestamped_samples_count(),
+        )
+            .hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Renders the samples as folded stacks, the text format of Brendan Gregg's flamegraph
+    /// scripts, for a quick local look at the profile: one line per distinct stack, from the root
+    /// frame to the leaf separated by `;`, followed by the sum of the `sample_type` values of the
+    /// samples with that stack. Stacks summing to 0 are left out. The values are upscaled, and
+    /// the lines are sorted by stack.
+    ///
+    /// Like [`Profile::serialize_range`], the profile is left as is.
+    pub fn to_collapsed(&mut self, sample_type: &str) -> anyhow::Result<String> {
+        let value_index = self
+            .sample_type_index(sample_type)
+            .with_context(|| format!("sample type {sample_type} is not in the profile"))?;
+
+        let mut stacks = BTreeMap::<String, i64>::new();
+        for (sample, timestamp, mut values) in self.observations.sorted_page(0, usize::MAX)? {
+            let labels = self.enrich_sample_labels(sample, timestamp)?;
+            self.upscaling_rules.upscale_values(&mut values, &labels)?;
+            let frames = self
+                .get_stacktrace(sample.stacktrace)?
+                .locations
+                .iter()
+                .rev()
+                .map(|id| self.collapsed_frame_name(*id))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let value = stacks.entry(frames.join(";")).or_default();
+            *value = value.saturating_add(values[value_index]);
+        }
 
+        let mut collapsed = String::new();
+        for (stack, value) in stacks.into_iter().filter(|(_, value)| *value != 0) {
+            writeln!(collapsed, "{stack} {value}")?;
+        }
+        Ok(collapsed)
+    }
+}
+
+/// Private helper functions
+impl Profile {
+    /// The function name of the location, or its address for address-only locations. The
+    /// separators of the folded stacks format are replaced.
+    fn collapsed_frame_name(&self, id: LocationId) -> anyhow::Result<String> {
+        let location = self
+            .locations
+            .get_index(id.to_raw_id() as usize - 1)
+            .with_context(|| format!("LocationId {id:?} to exist in profile"))?;
+        let name = match location.function_id {
+            Some(function_id) => {
+                let function = self
+                    .functions
+                    .get_index(function_id.to_raw_id() as usize - 1)
+                    .with_context(|| format!("FunctionId {function_id:?} to exist in profile"))?;
+                let name = self.strings.get(function.name).unwrap_or_default();
+                if name.is_empty() {
+                    self.strings.get(function.system_name).unwrap_or_default()
+                } else {
+                    name
+                }
+            }
+            None => "",
+        };
+        Ok(if name.is_empty() {
+            format!("0x{:x}", location.address)
+        } else {
+            name.replace([';', '\n'], "_")
+        })
+    }
+
+    fn pprof_encoder() -> CompressedProtobufSerializer {
         // On 2023-08-23, we analyzed the uploaded tarball size per language.
         // These tarballs include 1 or more profiles, but for most languages
         // using libdatadog (all?) there is only 1 profile, so this is a good
@@ -214,10 +848,33 @@ impl Profile {
         // size of 32KiB should definitely out-perform starting at zero for
         // time consumed, allocator pressure, and allocator fragmentation.
         const INITIAL_PPROF_BUFFER_SIZE: usize = 32 * 1024;
-        let mut encoder = CompressedProtobufSerializer::with_capacity(INITIAL_PPROF_BUFFER_SIZE);
+        CompressedProtobufSerializer::with_capacity(INITIAL_PPROF_BUFFER_SIZE)
+    }
 
-        for (sample, timestamp, mut values) in std::mem::take(&mut self.observations).into_iter() {
-            let labels = self.enrich_sample_labels(sample, timestamp)?;
+    /// Encodes the observations and the sample types. The mappings, locations, functions and
+    /// strings are encoded next by the caller, then the returned remaining fields of the profile
+    /// with [`Profile::finish_pprof`].
+    fn encode_samples(
+        &mut self,
+        encoder: &mut CompressedProtobufSerializer,
+        observations: impl IntoIterator<Item = (Sample, Option<Timestamp>, Vec<i64>)>,
+        start: SystemTime,
+        duration_nanos: i64,
+    ) -> anyhow::Result<ProfileSimpler> {
+        let (period, period_type) = match self.period {
+            Some(tuple) => (tuple.0, Some(tuple.1.into())),
+            None => (0, None),
+        };
+
+        let hint_labels: Vec<Label> = self
+            .sample_type_hints()
+            .iter()
+            .map(|(key, value)| Label::str(self.intern(key), self.intern(value)))
+            .collect();
+        let mut context_labels = HashMap::new();
+        for (sample, timestamp, mut values) in observations {
+            let mut labels = self.enrich_sample_labels(sample, timestamp)?;
+            self.expand_sample_context(&mut labels, &mut context_labels);
             let location_ids: Vec<_> = self
                 .get_stacktrace(sample.stacktrace)?
                 .locations
@@ -225,6 +882,10 @@ impl Profile {
                 .map(Id::to_raw_id)
                 .collect();
             self.upscaling_rules.upscale_values(&mut values, &labels)?;
+            labels.extend_from_slice(&hint_labels);
+            if self.disabled_sample_types.contains(&true) {
+                values = self.enabled_values(values);
+            }
 
             let labels = labels.into_iter().map(pprof::Label::from).collect();
             let item = pprof::Sample {
@@ -236,61 +897,179 @@ impl Profile {
             encoder.encode(ProfileSamplesEntry::from(item))?;
         }
 
-        // `Sample`s must be emitted before `SampleTypes` since we consume
-        // fields as we convert (using `into_iter`).  This allows Rust to
-        // release memory faster, reducing our peak RSS, but means that we
-        // must process fields in dependency order, regardless of the numeric
-        // field index in the `pprof` protobuf.
+        // `Sample`s are emitted first, as the observations are consumed while
+        // they are converted. This allows Rust to release memory faster,
+        // reducing our peak RSS, regardless of the numeric field index in the
+        // `pprof` protobuf.
         // It is valid to emit protobuf fields out of order. See example in:
         // https://protobuf.dev/programming-guides/encoding/#optional
-        //
-        // In this case, we use `sample_types` during upscaling of `samples`,
-        // so we must serialize `Sample` before `SampleType`.
-        for sample_type in self.sample_types.iter() {
+        for sample_type in self.enabled_values(self.sample_types.to_vec()) {
             let item: pprof::ValueType = sample_type.into();
             encoder.encode(ProfileSampleTypesEntry::from(item))?;
         }
 
-        for item in into_pprof_iter(self.mappings) {
-            encoder.encode(ProfileMappingsEntry::from(item))?;
+        Ok(ProfileSimpler {
+            time_nanos: start
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |duration| {
+                    duration.as_nanos().min(i64::MAX as u128) as i64
+                }),
+            duration_nanos,
+            period_type,
+            period,
+        })
+    }
+
+    fn finish_pprof(
+        mut encoder: CompressedProtobufSerializer,
+        header: ProfileSimpler,
+        deterministic_encoding: bool,
+    ) -> anyhow::Result<Vec<u8>> {
+        encoder.encode(header)?;
+        let mut buffer = encoder.finish()?;
+        if deterministic_encoding {
+            buffer = pprof::canonicalize_compressed_pprof(&buffer)?;
         }
+        Ok(buffer)
+    }
 
-        for item in into_pprof_iter(self.locations) {
-            encoder.encode(ProfileLocationsEntry::from(item))?;
+    fn ensure_open(&self, operation: &'static str) -> Result<(), ProfileStateError> {
+        match self.state {
+            ProfileState::Open => Ok(()),
+            state => Err(ProfileStateError { operation, state }),
         }
+    }
 
-        for item in into_pprof_iter(self.functions) {
-            encoder.encode(ProfileFunctionsEntry::from(item))?;
+    fn add_sample_internal(
+        &mut self,
+        sample: api::Sample,
+        timestamp: Option<Timestamp>,
+        context_id: Option<u64>,
+    ) -> anyhow::Result<()> {
+        self.ensure_open("add_sample")?;
+        let start = self.overhead.map(|_| Instant::now());
+        self.ensure_sample_values(&sample.values)?;
+        let labels = self.add_sample_labels(&sample.labels, context_id)?;
+        let stacktrace = self.add_locations(&sample.locations);
+        let values = self.zero_disabled_values(sample.values);
+        self.observations
+            .add(Sample::new(labels, stacktrace), timestamp, values)?;
+        self.record_overhead_since(ProfilerOperation::Add, start);
+        Ok(())
+    }
+
+    fn record_overhead_since(&mut self, operation: ProfilerOperation, start: Option<Instant>) {
+        if let Some(start) = start {
+            self.record_overhead(operation, start.elapsed());
+        }
+    }
+
+    /// Adds the time recorded in libdatadog as samples, see [`Profile::set_overhead_sample_type`].
+    fn add_overhead_samples(&mut self) -> anyhow::Result<()> {
+        let Some(overhead) = &mut self.overhead else {
+            return Ok(());
+        };
+        let offset = overhead.offset;
+        for (operation, nanos) in overhead.take() {
+            let locations =
+                [operation.frame_name(), PROFILER_FRAME_NAME].map(|name| api::Location {
+                    function: api::Function {
+                        name,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                });
+            let mut values = vec![0; self.sample_types.len()];
+            values[offset] = nanos;
+            // Not through add_sample, which would record this as overhead too.
+            let labels = self.add_sample_labels(&[], None)?;
+            let stacktrace = self.add_locations(&locations);
+            let values = self.zero_disabled_values(values);
+            self.observations
+                .add(Sample::new(labels, stacktrace), None, values)?;
+        }
+        Ok(())
+    }
+
+    fn zero_disabled_values(&self, mut values: Vec<i64>) -> Vec<i64> {
+        for (value, disabled) in values.iter_mut().zip(self.disabled_sample_types.iter()) {
+            if *disabled {
+                *value = 0;
+            }
+        }
+        values
+    }
+
+    /// Drops the items of the disabled sample types, from the values of a sample or from the
+    /// sample types themselves.
+    fn enabled_values<T>(&self, values: Vec<T>) -> Vec<T> {
+        values
+            .into_iter()
+            .zip(self.disabled_sample_types.iter())
+            .filter_map(|(value, disabled)| (!disabled).then_some(value))
+            .collect()
+    }
+
+    fn ensure_sample_values(&self, values: &[i64]) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            values.len() == self.sample_types.len(),
+            "expected {} sample types, but sample had {} sample types",
+            self.sample_types.len(),
+            values.len(),
+        );
+        Ok(())
+    }
+
+    fn add_sample_labels(
+        &mut self,
+        labels: &[api::Label],
+        context_id: Option<u64>,
+    ) -> anyhow::Result<LabelSetId> {
+        self.validate_sample_labels(labels)?;
+        let mut labels: Vec<_> = labels
+            .iter()
+            .map(|label| {
+                let internal_label = self.intern_label(label);
+                self.labels.dedup(internal_label)
+            })
+            .collect();
+        if let Some(context_id) = context_id {
+            let key = match self.context_id_key {
+                Some(key) => key,
+                None => {
+                    let key = self.intern(CONTEXT_ID_LABEL_KEY);
+                    self.context_id_key = Some(key);
+                    key
+                }
+            };
+            // The context id is stored as a regular label, so that samples with different
+            // contexts are not aggregated together.
+            labels.push(self.labels.dedup(Label::num(key, context_id as i64, None)));
         }
+        Ok(self.label_sets.dedup(LabelSet::new(labels)))
+    }
 
-        let mut lender = self.strings.into_lending_iter();
-        while let Some(item) = lender.next() {
-            encoder.encode_string_table_entry(item)?;
+    /// Interns the stack trace of the locations, truncated to `max_frames`.
+    fn add_locations(&mut self, locations: &[api::Location]) -> StackTraceId {
+        let max_frames = self.max_frames.map_or(usize::MAX, NonZeroUsize::get);
+        let mut location_ids: Vec<_> = locations
+            .iter()
+            .take(max_frames)
+            .map(|l| self.add_location(l))
+            .collect();
+        if locations.len() > max_frames {
+            self.truncated_stacks += 1;
+            location_ids.push(self.add_location(&api::Location {
+                function: api::Function {
+                    name: TRUNCATED_FRAME_NAME,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }));
         }
-
-        encoder.encode(ProfileSimpler {
-            time_nanos: self
-                .start_time
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .map_or(0, |duration| {
-                    duration.as_nanos().min(i64::MAX as u128) as i64
-                }),
-            duration_nanos,
-            period_type,
-            period,
-        })?;
-
-        Ok(EncodedProfile {
-            start,
-            end,
-            buffer: encoder.finish()?,
-            endpoints_stats,
-        })
+        self.add_stacktrace(location_ids)
     }
-}
 
-/// Private helper functions
-impl Profile {
     fn add_function(&mut self, function: &api::Function) -> FunctionId {
         let name = self.intern(function.name);
         let system_name = self.intern(function.system_name);
@@ -307,7 +1086,10 @@ impl Profile {
 
     fn add_location(&mut self, location: &api::Location) -> LocationId {
         let mapping_id = self.add_mapping(&location.mapping);
-        let function_id = self.add_function(&location.function);
+        // An empty function means the location is only known by its address, don't emit a
+        // function for it.
+        let function_id = (location.function != api::Function::default())
+            .then(|| self.add_function(&location.function));
         self.locations.dedup(Location {
             mapping_id,
             function_id,
@@ -338,6 +1120,23 @@ impl Profile {
         src.as_ref().map(owned_types::Period::from)
     }
 
+    /// The hints of the sample types and of the period type as label keys and values, without
+    /// duplicates.
+    fn sample_type_hints(&self) -> Vec<(String, String)> {
+        let mut hints = Vec::new();
+        let value_types = self
+            .owned_sample_types
+            .iter()
+            .flat_map(|sample_types| sample_types.iter())
+            .chain(self.owned_period.as_ref().map(|period| &period.typ));
+        for (key, value) in value_types.flat_map(owned_types::ValueType::hints) {
+            if !hints.iter().any(|(hint, _)| *hint == key) {
+                hints.push((key, value.to_string()));
+            }
+        }
+        hints
+    }
+
     #[inline]
     fn backup_sample_types(src: &[api::ValueType]) -> Option<Box<[owned_types::ValueType]>> {
         Some(src.iter().map(owned_types::ValueType::from).collect())
@@ -374,7 +1173,11 @@ impl Profile {
             .map(|v| Label::str(self.endpoints.endpoint_label, *v)))
     }
 
-    fn get_endpoint_for_labels(&self, label_set_id: LabelSetId) -> anyhow::Result<Option<Label>> {
+    fn get_endpoint_for_labels(
+        &self,
+        label_set_id: LabelSetId,
+        timestamp: Option<Timestamp>,
+    ) -> anyhow::Result<Option<Label>> {
         let label = self.get_label_set(label_set_id)?.iter().find_map(|id| {
             if let Ok(label) = self.get_label(*id) {
                 if label.get_key() == self.endpoints.local_root_span_id_label {
@@ -386,7 +1189,9 @@ impl Profile {
         if let Some(label) = label {
             self.get_endpoint_for_label(label)
         } else {
-            Ok(None)
+            Ok(timestamp
+                .and_then(|timestamp| self.endpoints.endpoint_at(timestamp.get()))
+                .map(|endpoint| Label::str(self.endpoints.endpoint_label, endpoint)))
         }
     }
 
@@ -408,6 +1213,51 @@ impl Profile {
             .with_context(|| format!("StackTraceId {:?} to exist in profile", st))
     }
 
+    fn intern_label(&mut self, label: &api::Label) -> Label {
+        let key = self.intern(label.key);
+        if let Some(s) = label.str {
+            let str = self.intern(s);
+            Label::str(key, str)
+        } else {
+            let num = label.num;
+            let num_unit = label.num_unit.map(|s| self.intern(s));
+            Label::num(key, num, num_unit)
+        }
+    }
+
+    /// Replaces the context id label of a sample by the labels from the context provider. The
+    /// labels of each context are cached in `context_labels`, so that the provider is only called
+    /// once per context.
+    fn expand_sample_context(
+        &mut self,
+        labels: &mut Vec<Label>,
+        context_labels: &mut HashMap<i64, Vec<Label>>,
+    ) {
+        let Some(key) = self.context_id_key else {
+            return;
+        };
+        let Some(pos) = labels.iter().position(|l| l.get_key() == key) else {
+            return;
+        };
+        let LabelValue::Num {
+            num: context_id, ..
+        } = *labels.remove(pos).get_value()
+        else {
+            return;
+        };
+        let Some(provider) = self.context_provider.clone() else {
+            return;
+        };
+        let context_labels = context_labels.entry(context_id).or_insert_with(|| {
+            let mut context_labels = Vec::new();
+            provider(context_id as u64, &mut |label: api::Label| {
+                context_labels.push(self.intern_label(&label))
+            });
+            context_labels
+        });
+        labels.extend_from_slice(context_labels);
+    }
+
     /// Interns the `str` as a string, returning the id in the string table.
     /// The empty string is guaranteed to have an id of [StringId::ZERO].
     #[inline]
@@ -415,6 +1265,49 @@ impl Profile {
         self.strings.intern(item)
     }
 
+    /// Adds the upscaling rules of this profile to the `next` one, see
+    /// [`Profile::set_preserve_upscaling_rules`].
+    fn carry_over_upscaling_rules(&self, next: &mut Profile) -> anyhow::Result<()> {
+        let resolve = |id| {
+            self.strings
+                .get(id)
+                .context("upscaling rule string not found")
+        };
+        for (name, value, offsets, info) in self.upscaling_rules.by_label_rules() {
+            next.add_upscaling_rule(offsets, resolve(name)?, resolve(value)?, info)?;
+        }
+        for (name, range, offsets, info) in self.upscaling_rules.by_range_rules() {
+            next.add_upscaling_rule_for_range(offsets, resolve(name)?, range, info)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the endpoints of the spans of this profile which may still be running to the `next`
+    /// one, see [`Profile::set_preserve_endpoints`].
+    fn carry_over_endpoints(&self, next: &mut Profile) -> anyhow::Result<()> {
+        let next_start = next
+            .start_time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |start| start.as_nanos() as i64);
+        let ended: HashSet<u64> = self
+            .endpoints
+            .span_intervals
+            .iter()
+            .filter(|interval| interval.end < next_start)
+            .map(|interval| interval.local_root_span_id)
+            .collect();
+        let endpoints = self
+            .endpoints
+            .mappings
+            .iter()
+            .filter(|(local_root_span_id, _)| !ended.contains(local_root_span_id))
+            .filter_map(|(&local_root_span_id, &endpoint)| {
+                let endpoint = self.strings.get(endpoint)?;
+                Some((local_root_span_id, Cow::Borrowed(endpoint)))
+            });
+        next.add_endpoints(endpoints)
+    }
+
     /// Creates a profile from the period, sample types, and start time using
     /// the owned values.
     #[inline(never)]
@@ -426,8 +1319,18 @@ impl Profile {
         let mut profile = Self {
             owned_period,
             owned_sample_types,
+            context_provider: None,
+            context_id_key: None,
+            max_frames: None,
+            deterministic_encoding: false,
+            preserve_upscaling_rules: false,
+            preserve_endpoints: false,
+            disabled_sample_types: Box::new([]),
+            overhead: None,
+            truncated_stacks: 0,
             endpoints: Default::default(),
             functions: Default::default(),
+            generation: NEXT_GENERATION.fetch_add(1, Ordering::Relaxed),
             labels: Default::default(),
             label_sets: Default::default(),
             locations: Default::default(),
@@ -437,6 +1340,7 @@ impl Profile {
             sample_types: Box::new([]),
             stack_traces: Default::default(),
             start_time,
+            state: ProfileState::Open,
             strings: Default::default(),
             timestamp_key: Default::default(),
             upscaling_rules: Default::default(),
@@ -479,6 +1383,7 @@ impl Profile {
         profile.owned_period = owned_period;
 
         profile.observations = Observations::new(profile.sample_types.len());
+        profile.disabled_sample_types = vec![false; profile.sample_types.len()].into();
         profile
     }
 
@@ -490,16 +1395,19 @@ impl Profile {
         self.get_label_set(sample.labels)?
             .iter()
             .map(|l| self.get_label(*l).copied())
-            .chain(self.get_endpoint_for_labels(sample.labels).transpose())
+            .chain(
+                self.get_endpoint_for_labels(sample.labels, timestamp)
+                    .transpose(),
+            )
             .chain(timestamp.map(|ts| Ok(Label::num(self.timestamp_key, ts.get(), None))))
             .collect()
     }
 
     /// Validates labels
-    fn validate_sample_labels(&mut self, sample: &api::Sample) -> anyhow::Result<()> {
+    fn validate_sample_labels(&mut self, labels: &[api::Label]) -> anyhow::Result<()> {
         let mut seen: HashMap<&str, &api::Label> = HashMap::new();
 
-        for label in sample.labels.iter() {
+        for label in labels.iter() {
             if let Some(duplicate) = seen.insert(label.key, label) {
                 anyhow::bail!("Duplicate label on sample: {:?} {:?}", duplicate, label);
             }
@@ -517,6 +1425,20 @@ impl Profile {
                 "Timestamp should not be passed as a label {:?}",
                 label
             );
+
+            anyhow::ensure!(
+                label.key != CONTEXT_ID_LABEL_KEY,
+                "Context ids should be passed with add_sample_with_context {:?}",
+                label
+            );
+
+            anyhow::ensure!(
+                !label
+                    .key
+                    .starts_with(owned_types::SAMPLE_TYPE_HINT_LABEL_PREFIX),
+                "Sample type hints should be passed with the sample types {:?}",
+                label
+            );
         }
         Ok(())
     }
@@ -539,6 +1461,15 @@ impl Profile {
     pub fn only_for_testing_num_timestamped_samples(&self) -> usize {
         self.observations.timestamped_samples_count()
     }
+
+    /// Returns a page of the samples in a stable order, see [Observations::sorted_page].
+    pub fn sorted_observations(
+        &mut self,
+        offset: usize,
+        limit: usize,
+    ) -> anyhow::Result<Vec<(Sample, Option<Timestamp>, Vec<i64>)>> {
+        self.observations.sorted_page(offset, limit)
+    }
 }
 
 #[cfg(test)]
@@ -560,6 +1491,149 @@ mod api_tests {
         assert_eq!(id1, expected_id);
     }
 
+    #[test]
+    fn preintern() {
+        let sample_types = [api::ValueType::new("samples", "count")];
+        let mut profile = Profile::new(SystemTime::now(), &sample_types, None);
+        let mapping = api::Mapping {
+            filename: "php",
+            ..Default::default()
+        };
+        let function = api::Function {
+            name: "{main}",
+            filename: "index.php",
+            ..Default::default()
+        };
+        let schema = api::InternSchema {
+            strings: &["thread id", "thread name", "{main}"],
+            functions: &[function],
+            mappings: &[mapping],
+        };
+
+        let ids = profile.preintern(&schema).unwrap();
+        let strings_count = profile.interned_strings_count();
+        profile.intern("thread name");
+        assert_eq!(strings_count, profile.interned_strings_count());
+        assert_eq!(profile.add_function(&function), ids.functions[0]);
+        assert_eq!(profile.add_mapping(&mapping), ids.mappings[0]);
+        let strings_count = profile.interned_strings_count();
+
+        // samples referencing the schema don't intern anything new
+        profile
+            .add_sample(
+                api::Sample {
+                    locations: vec![api::Location {
+                        mapping,
+                        function,
+                        ..Default::default()
+                    }],
+                    values: vec![1],
+                    labels: vec![],
+                },
+                None,
+            )
+            .unwrap();
+        assert_eq!(strings_count, profile.interned_strings_count());
+
+        // the stack trace made of the ids is the one of the sample above
+        let location = PreinternedLocation {
+            function: Some(ids.functions[0]),
+            mapping: Some(ids.mappings[0]),
+            address: 0,
+            line: 0,
+        };
+        let stacktrace = profile
+            .intern_preinterned_stacktrace(&ids, &[location])
+            .unwrap();
+        profile
+            .add_sample_by_stacktrace(stacktrace, vec![2], &[], None)
+            .unwrap();
+        assert_eq!(1, profile.only_for_testing_num_aggregated_samples());
+        let unknown = PreinternedLocation {
+            function: Some(FunctionId::from_offset(ids.functions.len() + 100)),
+            ..location
+        };
+        profile
+            .intern_preinterned_stacktrace(&ids, &[unknown])
+            .unwrap_err();
+
+        profile.ensure_preinterned(&ids).unwrap();
+        profile
+            .ensure_preinterned(&PreinternedIds::default())
+            .unwrap_err();
+
+        // the ids are stable across resets, but those from before the reset are stale
+        profile.reset_and_return_previous(None).unwrap();
+        profile.ensure_preinterned(&ids).unwrap_err();
+        profile
+            .intern_preinterned_stacktrace(&ids, &[location])
+            .unwrap_err();
+        let new_ids = profile.preintern(&schema).unwrap();
+        profile.ensure_preinterned(&new_ids).unwrap();
+        assert_ne!(ids.generation, new_ids.generation);
+        assert_eq!(ids.functions, new_ids.functions);
+        assert_eq!(ids.mappings, new_ids.mappings);
+    }
+
+    #[test]
+    fn add_sample_by_stacktrace() {
+        let sample_types = [api::ValueType::new("samples", "count")];
+        let mut profile = Profile::new(SystemTime::now(), &sample_types, None);
+        let locations = [api::Location {
+            function: api::Function {
+                name: "{main}",
+                filename: "index.php",
+                ..Default::default()
+            },
+            ..Default::default()
+        }];
+        let labels = [api::Label {
+            key: "thread id",
+            num: 1,
+            ..Default::default()
+        }];
+
+        let stacktrace = profile.intern_stacktrace(&locations).unwrap();
+        profile
+            .add_sample_by_stacktrace(stacktrace, vec![1], &labels, None)
+            .unwrap();
+        profile
+            .add_sample_by_stacktrace(stacktrace, vec![2], &labels, None)
+            .unwrap();
+        // aggregated with a sample of the same stack added the regular way
+        profile
+            .add_sample(
+                api::Sample {
+                    locations: locations.to_vec(),
+                    values: vec![4],
+                    labels: labels.to_vec(),
+                },
+                None,
+            )
+            .unwrap();
+        assert_eq!(1, profile.only_for_testing_num_aggregated_samples());
+        assert!(profile
+            .add_sample_by_stacktrace(stacktrace, vec![1, 2], &labels, None)
+            .is_err());
+
+        let pprof = profile.reset_and_return_previous(None).unwrap();
+        let pprof = pprof::roundtrip_to_pprof(pprof).unwrap();
+        assert_eq!(vec![7], pprof.samples[0].values);
+
+        // the stack trace was invalidated by the reset, and can't be used with another profile
+        assert!(profile
+            .add_sample_by_stacktrace(stacktrace, vec![1], &labels, None)
+            .is_err());
+        let mut other = Profile::new(SystemTime::now(), &sample_types, None);
+        assert!(other
+            .add_sample_by_stacktrace(stacktrace, vec![1], &labels, None)
+            .is_err());
+        let stacktrace = profile.intern_stacktrace(&locations).unwrap();
+        profile
+            .add_sample_by_stacktrace(stacktrace, vec![1], &labels, None)
+            .unwrap();
+    }
+
     #[test]
     fn api() {
         let sample_types = [
@@ -671,31 +1745,289 @@ mod api_tests {
             labels: labels.clone(),
         };
 
-        let timestamp_sample = api::Sample {
-            locations: timestamp_locations,
-            values,
-            labels,
+        let timestamp_sample = api::Sample {
+            locations: timestamp_locations,
+            values,
+            labels,
+        };
+
+        let mut profile = Profile::new(SystemTime::now(), &sample_types, None);
+        assert_eq!(profile.only_for_testing_num_aggregated_samples(), 0);
+
+        profile
+            .add_sample(main_sample, None)
+            .expect("profile to not be full");
+        assert_eq!(profile.only_for_testing_num_aggregated_samples(), 1);
+
+        profile
+            .add_sample(test_sample, None)
+            .expect("profile to not be full");
+        assert_eq!(profile.only_for_testing_num_aggregated_samples(), 2);
+
+        assert_eq!(profile.only_for_testing_num_timestamped_samples(), 0);
+        profile
+            .add_sample(timestamp_sample, Timestamp::new(42))
+            .expect("profile to not be full");
+        assert_eq!(profile.only_for_testing_num_timestamped_samples(), 1);
+        profile
+    }
+
+    #[test]
+    fn to_collapsed() {
+        let sample_types = [
+            api::ValueType::new("samples", "count"),
+            api::ValueType::new("wall-time", "nanoseconds"),
+        ];
+        let mut profile = Profile::new(SystemTime::now(), &sample_types, None);
+        let location = |name| api::Location {
+            function: api::Function {
+                name,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let address = api::Location {
+            address: 0x1234,
+            ..Default::default()
+        };
+        let pid = |num| api::Label {
+            key: "pid",
+            num,
+            ..Default::default()
+        };
+
+        // same stack with different labels, and with a timestamp
+        for (pid_label, timestamp) in [(101, None), (102, None), (101, Timestamp::new(42))] {
+            let sample = api::Sample {
+                locations: vec![location("foo"), location("main")],
+                values: vec![1, 10],
+                labels: vec![pid(pid_label)],
+            };
+            profile.add_sample(sample, timestamp).unwrap();
+        }
+        let sample = api::Sample {
+            locations: vec![address, location("a;b"), location("main")],
+            values: vec![2, 0],
+            labels: vec![],
+        };
+        profile.add_sample(sample, None).unwrap();
+
+        assert_eq!(
+            "main;a_b;0x1234 2\nmain;foo 3\n",
+            profile.to_collapsed("samples").unwrap()
+        );
+        assert_eq!("main;foo 30\n", profile.to_collapsed("wall-time").unwrap());
+        assert!(profile.to_collapsed("cpu-time").is_err());
+        // the samples are kept
+        assert_eq!(2, profile.only_for_testing_num_aggregated_samples());
+    }
+
+    #[test]
+    fn address_only_locations() {
+        let sample_types = [api::ValueType::new("samples", "count")];
+        let mapping = api::Mapping {
+            memory_start: 0x1000,
+            memory_limit: 0x2000,
+            filename: "/usr/lib/libc.so.6",
+            build_id: "abcdef",
+            ..Default::default()
+        };
+        let locations = vec![
+            api::Location {
+                mapping,
+                address: 0x1234,
+                ..Default::default()
+            },
+            api::Location {
+                mapping,
+                function: api::Function {
+                    name: "main",
+                    ..Default::default()
+                },
+                address: 0x1500,
+                ..Default::default()
+            },
+        ];
+
+        let mut profile = Profile::new(SystemTime::now(), &sample_types, None);
+        profile
+            .add_sample(
+                api::Sample {
+                    locations,
+                    values: vec![1],
+                    labels: vec![],
+                },
+                None,
+            )
+            .expect("add to succeed");
+
+        let pprof = pprof::roundtrip_to_pprof(profile).unwrap();
+        assert_eq!(pprof.locations.len(), 2);
+        assert_eq!(pprof.functions.len(), 1);
+
+        let address_only = &pprof.locations[0];
+        assert_eq!(address_only.address, 0x1234);
+        assert_eq!(address_only.mapping_id, 1);
+        assert!(address_only.lines.is_empty());
+
+        let symbolized = &pprof.locations[1];
+        assert_eq!(symbolized.lines.len(), 1);
+        assert_eq!(symbolized.lines[0].function_id, pprof.functions[0].id);
+    }
+
+    #[test]
+    fn deterministic_encoding() {
+        let sample_types = [api::ValueType::new("samples", "count")];
+        let sample = |name, thread| api::Sample {
+            locations: vec![api::Location {
+                function: api::Function {
+                    name,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }],
+            values: vec![1],
+            labels: vec![api::Label {
+                key: "thread name",
+                str: Some(thread),
+                num: 0,
+                num_unit: None,
+            }],
+        };
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let encode = |samples: &[(&'static str, &'static str)], deterministic| {
+            let mut profile = Profile::new(start, &sample_types, None);
+            profile.set_deterministic_encoding(deterministic);
+            // the setting survives resets
+            profile.reset_and_return_previous(Some(start)).unwrap();
+            for &(name, thread) in samples {
+                profile.add_sample(sample(name, thread), None).unwrap();
+            }
+            profile
+                .serialize_into_compressed_pprof(None, Some(Duration::from_secs(60)))
+                .unwrap()
+                .buffer
+        };
+
+        let samples = [("foo", "main"), ("bar", "worker"), ("foo", "worker")];
+        let reversed = [("foo", "worker"), ("bar", "worker"), ("foo", "main")];
+        assert_eq!(encode(&samples, true), encode(&reversed, true));
+        assert_ne!(encode(&samples, false), encode(&reversed, false));
+    }
+
+    #[test]
+    fn max_frames_truncation() {
+        let sample_types = [api::ValueType::new("samples", "count")];
+        let location = |name| api::Location {
+            function: api::Function {
+                name,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let sample = |locations| api::Sample {
+            locations,
+            values: vec![1],
+            labels: vec![],
+        };
+        let deep = || sample(vec![location("leaf"), location("middle"), location("root")]);
+
+        let mut profile = Profile::new(SystemTime::now(), &sample_types, None);
+        profile.set_max_frames(NonZeroUsize::new(2));
+        profile.add_sample(deep(), None).expect("add to succeed");
+        profile
+            .add_sample(sample(vec![location("leaf"), location("root")]), None)
+            .expect("add to succeed");
+        assert_eq!(1, profile.truncated_stacks_count());
+
+        // the limit is kept across resets, the count is not
+        let previous = profile.reset_and_return_previous(None).unwrap();
+        assert_eq!(0, profile.truncated_stacks_count());
+        profile.add_sample(deep(), None).expect("add to succeed");
+        assert_eq!(1, profile.truncated_stacks_count());
+
+        let pprof = pprof::roundtrip_to_pprof(previous).unwrap();
+        let mut stacks: Vec<Vec<&str>> = pprof
+            .samples
+            .iter()
+            .map(|sample| {
+                sample
+                    .location_ids
+                    .iter()
+                    .map(|id| {
+                        let location = pprof.locations.iter().find(|l| l.id == *id).unwrap();
+                        let function_id = location.lines[0].function_id;
+                        let function = pprof.functions.iter().find(|f| f.id == function_id);
+                        pprof.string_table[function.unwrap().name as usize].as_str()
+                    })
+                    .collect()
+            })
+            .collect();
+        stacks.sort();
+        assert_eq!(
+            vec![
+                vec!["leaf", "middle", TRUNCATED_FRAME_NAME],
+                vec!["leaf", "root"]
+            ],
+            stacks
+        );
+    }
+
+    #[test]
+    fn sample_type_hints() {
+        let sample_types = [
+            api::ValueType::new("samples", "count"),
+            api::ValueType::new("gc-time", "nanoseconds")
+                .with_aggregation(api::Aggregation::Max)
+                .with_display_unit("milliseconds"),
+        ];
+        let period = api::Period {
+            r#type: sample_types[1],
+            value: 10,
+        };
+
+        // the hints are kept across resets, and not duplicated by the period
+        let mut profile = Profile::new(SystemTime::now(), &sample_types, Some(period));
+        profile.reset_and_return_previous(None).unwrap();
+        let sample = api::Sample {
+            locations: vec![],
+            values: vec![1, 10],
+            labels: vec![],
         };
+        profile.add_sample(sample, None).unwrap();
+        let pprof = pprof::roundtrip_to_pprof(profile).unwrap();
+        assert!(pprof.comment.is_empty());
+        assert_eq!(1, pprof.samples.len());
+        let labels: Vec<(&str, &str)> = pprof.samples[0]
+            .labels
+            .iter()
+            .map(|label| {
+                (
+                    pprof.string_table_fetch(label.key).as_str(),
+                    pprof.string_table_fetch(label.str).as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("_dd.sample_type_hint.gc-time.aggregation", "max"),
+                ("_dd.sample_type_hint.gc-time.display_unit", "milliseconds")
+            ],
+            labels
+        );
 
+        // the keys are reserved
         let mut profile = Profile::new(SystemTime::now(), &sample_types, None);
-        assert_eq!(profile.only_for_testing_num_aggregated_samples(), 0);
-
-        profile
-            .add_sample(main_sample, None)
-            .expect("profile to not be full");
-        assert_eq!(profile.only_for_testing_num_aggregated_samples(), 1);
-
-        profile
-            .add_sample(test_sample, None)
-            .expect("profile to not be full");
-        assert_eq!(profile.only_for_testing_num_aggregated_samples(), 2);
-
-        assert_eq!(profile.only_for_testing_num_timestamped_samples(), 0);
-        profile
-            .add_sample(timestamp_sample, Timestamp::new(42))
-            .expect("profile to not be full");
-        assert_eq!(profile.only_for_testing_num_timestamped_samples(), 1);
-        profile
+        let sample = api::Sample {
+            locations: vec![],
+            values: vec![1, 10],
+            labels: vec![api::Label {
+                key: "_dd.sample_type_hint.gc-time.aggregation",
+                str: Some("sum"),
+                ..Default::default()
+            }],
+        };
+        profile.add_sample(sample, None).unwrap_err();
     }
 
     #[test]
@@ -838,6 +2170,151 @@ mod api_tests {
         assert!(profile.strings.len() > 0);
     }
 
+    #[test]
+    fn state_fingerprint() {
+        let mut profile = provide_distinct_locations();
+        let fingerprint = profile.state_fingerprint();
+        assert_eq!(fingerprint, profile.state_fingerprint());
+        assert_eq!(
+            fingerprint,
+            provide_distinct_locations().state_fingerprint()
+        );
+
+        profile.intern("a new string");
+        assert_ne!(fingerprint, profile.state_fingerprint());
+
+        let fingerprint = profile.state_fingerprint();
+        let encoded = profile
+            .serialize_into_compressed_pprof(None, None)
+            .expect("serialization to succeed");
+        assert_eq!(fingerprint, encoded.state_fingerprint);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn serialize_range() {
+        let sample_types = [api::ValueType::new("samples", "count")];
+        let mut profile = Profile::new(SystemTime::now(), &sample_types, None);
+        let sample = api::Sample {
+            locations: vec![],
+            values: vec![1],
+            labels: vec![],
+        };
+        for nanos in [100, 200, 300] {
+            profile
+                .add_sample(sample.clone(), Timestamp::new(nanos))
+                .unwrap();
+        }
+        profile.add_sample(sample, None).unwrap();
+
+        let epoch = SystemTime::UNIX_EPOCH;
+        let start = epoch + Duration::from_nanos(150);
+        let end = epoch + Duration::from_nanos(300);
+        let encoded = profile.serialize_range(start, end).unwrap();
+        assert_eq!((start, end), (encoded.start, encoded.end));
+        let pprof = pprof::deserialize_compressed_pprof(&encoded.buffer).unwrap();
+        assert_eq!(150, pprof.duration_nanos);
+        let timestamps: Vec<i64> = pprof
+            .sorted_samples()
+            .iter()
+            .map(|sample| sample.labels.first().map_or(0, |label| label.num))
+            .collect();
+        assert_eq!(vec![0, 200], timestamps);
+
+        // the profile is left untouched
+        assert_eq!(ProfileState::Open, profile.state());
+        assert_eq!(1, profile.only_for_testing_num_aggregated_samples());
+        assert_eq!(3, profile.only_for_testing_num_timestamped_samples());
+    }
+
+    #[test]
+    fn serialize_in_place() {
+        let mut profile = provide_distinct_locations();
+        assert_eq!(ProfileState::Open, profile.state());
+
+        let encoded = profile
+            .serialize(None, None)
+            .expect("serialization to succeed");
+        assert!(!encoded.buffer.is_empty());
+        assert_eq!(ProfileState::Serialized, profile.state());
+        assert_eq!(0, profile.only_for_testing_num_aggregated_samples());
+
+        let sample = api::Sample {
+            locations: vec![],
+            values: vec![1],
+            labels: vec![],
+        };
+        let err = profile.add_sample(sample.clone(), None).unwrap_err();
+        assert_eq!(
+            Some(&ProfileStateError {
+                operation: "add_sample",
+                state: ProfileState::Serialized
+            }),
+            err.downcast_ref()
+        );
+        let upscaling_info = UpscalingInfo::Proportional { scale: 2.0 };
+        assert!(profile
+            .add_upscaling_rule(&[0], "", "", upscaling_info)
+            .is_err());
+        assert!(profile.serialize(None, None).is_err());
+
+        profile
+            .reset_and_return_previous(None)
+            .expect("reset to succeed");
+        assert_eq!(ProfileState::Open, profile.state());
+        profile.add_sample(sample, None).expect("add to succeed");
+    }
+
+    #[test]
+    fn overhead_samples() {
+        let sample_types = [
+            api::ValueType::new("samples", "count"),
+            api::ValueType::new("wall-time", "nanoseconds"),
+        ];
+        let mut profile = Profile::new(SystemTime::now(), &sample_types, None);
+        assert!(profile.set_overhead_sample_type(Some(2)).is_err());
+        profile.set_overhead_sample_type(Some(1)).unwrap();
+
+        let sample = api::Sample {
+            locations: vec![],
+            values: vec![1, 10],
+            labels: vec![],
+        };
+        profile.add_sample(sample, None).unwrap();
+        profile.record_overhead(ProfilerOperation::Upload, Duration::from_millis(3));
+
+        let encoded = profile.serialize(None, None).unwrap();
+        let pprof = pprof::deserialize_compressed_pprof(&encoded.buffer).unwrap();
+        let frame_name = |location_id: &u64| {
+            let location = &pprof.locations[*location_id as usize - 1];
+            let function = &pprof.functions[location.lines[0].function_id as usize - 1];
+            pprof.string_table[function.name as usize].as_str()
+        };
+        let overhead: HashMap<Vec<&str>, &[i64]> = pprof
+            .samples
+            .iter()
+            .map(|sample| {
+                let frames = sample.location_ids.iter().map(frame_name).collect();
+                (frames, sample.values.as_slice())
+            })
+            .collect();
+        assert_eq!(
+            Some(&&[0, 3_000_000][..]),
+            overhead.get(&vec!["upload", PROFILER_FRAME_NAME])
+        );
+        assert!(!overhead.contains_key(&vec!["serialize", PROFILER_FRAME_NAME]));
+
+        // the time spent serializing goes to the next profile
+        profile.reset_and_return_previous(None).unwrap();
+        let overhead = profile.overhead.unwrap().take();
+        assert_eq!(1, overhead.len());
+        assert_eq!(ProfilerOperation::Serialize, overhead[0].0);
+
+        profile.set_overhead_sample_type(None).unwrap();
+        let pprof = pprof::roundtrip_to_pprof(profile).unwrap();
+        assert!(pprof.samples.is_empty());
+    }
+
     #[test]
     fn reset_period() {
         /* The previous test (reset) checked quite a few properties already, so
@@ -1003,6 +2480,114 @@ mod api_tests {
         Ok(())
     }
 
+    #[test]
+    fn add_endpoints() {
+        let sample_types = [api::ValueType::new("samples", "count")];
+        let mut profile = Profile::new(SystemTime::now(), &sample_types, None);
+        let strings = profile.interned_strings_count();
+
+        profile
+            .add_endpoints([
+                (1, Cow::from("GET /users")),
+                (2, Cow::from("GET /users")),
+                (3, Cow::from("POST /users")),
+                (1, Cow::from("GET /")),
+            ])
+            .expect("add to succeed");
+
+        assert_eq!(strings + 3, profile.interned_strings_count());
+        let users = profile.intern("GET /users");
+        let root = profile.intern("GET /");
+        assert_eq!(3, profile.endpoints.mappings.len());
+        assert_eq!(users, profile.endpoints.mappings[&2]);
+        assert_eq!(root, profile.endpoints.mappings[&1]);
+    }
+
+    #[test]
+    fn span_interval_endpoints() {
+        let sample_types = [api::ValueType::new("samples", "count")];
+        let mut profile = Profile::new(SystemTime::now(), &sample_types, None);
+        let ts = |nanos: i64| Timestamp::new(nanos).unwrap();
+        let sample = |thread: &'static str| api::Sample {
+            locations: vec![],
+            values: vec![1],
+            labels: vec![api::Label {
+                key: "thread name",
+                str: Some(thread),
+                ..Default::default()
+            }],
+        };
+
+        profile.add_endpoint(1, Cow::from("GET /users")).unwrap();
+        profile.add_endpoint(2, Cow::from("POST /users")).unwrap();
+        profile.add_span_interval(1, ts(100), ts(200)).unwrap();
+        profile.add_span_interval(2, ts(150), ts(300)).unwrap();
+        profile.add_span_interval(1, ts(1), ts(0)).unwrap_err();
+
+        profile.add_sample(sample("only 1"), Some(ts(120))).unwrap();
+        profile.add_sample(sample("both"), Some(ts(170))).unwrap();
+        profile.add_sample(sample("only 2"), Some(ts(250))).unwrap();
+        profile.add_sample(sample("none"), Some(ts(400))).unwrap();
+        profile.add_sample(sample("untimed"), None).unwrap();
+
+        let pprof = pprof::roundtrip_to_pprof(profile).unwrap();
+        let endpoints: HashMap<&str, Option<&str>> = pprof
+            .samples
+            .iter()
+            .map(|sample| {
+                let string = |id: i64| pprof.string_table[id as usize].as_str();
+                let label = |key: &str| {
+                    sample
+                        .labels
+                        .iter()
+                        .find(|label| string(label.key) == key)
+                        .map(|label| string(label.str))
+                };
+                (label("thread name").unwrap(), label("trace endpoint"))
+            })
+            .collect();
+
+        assert_eq!(Some("GET /users"), endpoints["only 1"]);
+        assert_eq!(None, endpoints["both"]);
+        assert_eq!(Some("POST /users"), endpoints["only 2"]);
+        assert_eq!(None, endpoints["none"]);
+        assert_eq!(None, endpoints["untimed"]);
+    }
+
+    #[test]
+    fn span_interval_timeline() {
+        let mut endpoints = Endpoints::new();
+        let users = StringId::from_offset(1);
+        let orders = StringId::from_offset(2);
+        let interval = |local_root_span_id, start, end| SpanInterval {
+            local_root_span_id,
+            start,
+            end,
+        };
+        endpoints.add_mapping(1, users);
+        endpoints.add_mapping(2, users);
+        endpoints.add_span_interval(interval(1, 100, 200));
+        endpoints.add_span_interval(interval(2, 150, 300));
+        // no endpoint for this span, so it doesn't conflict with the others
+        endpoints.add_span_interval(interval(4, 0, i64::MAX));
+
+        assert_eq!(None, endpoints.endpoint_at(99));
+        assert_eq!(Some(users), endpoints.endpoint_at(100));
+        assert_eq!(Some(users), endpoints.endpoint_at(170));
+        assert_eq!(Some(users), endpoints.endpoint_at(300));
+        assert_eq!(None, endpoints.endpoint_at(301));
+
+        // the timeline is rebuilt after a change
+        endpoints.add_mapping(3, orders);
+        endpoints.add_span_interval(interval(3, 250, i64::MAX));
+        assert_eq!(Some(users), endpoints.endpoint_at(200));
+        assert_eq!(None, endpoints.endpoint_at(250));
+        assert_eq!(Some(orders), endpoints.endpoint_at(i64::MAX));
+        endpoints.add_mapping(4, orders);
+        assert_eq!(None, endpoints.endpoint_at(200));
+        assert_eq!(Some(orders), endpoints.endpoint_at(10));
+    }
+
     #[test]
     fn endpoint_counts_empty_test() {
         let sample_types = [
@@ -1188,6 +2773,105 @@ mod api_tests {
         assert_eq!(first.values, vec![3, 10000, 42]);
     }
 
+    #[test]
+    fn reset_preserving_upscaling_rules_and_endpoints() {
+        let sample_types = create_samples_types();
+        let mut profile: Profile = Profile::new(SystemTime::now(), &sample_types, None);
+        profile.set_preserve_upscaling_rules(true);
+        profile.set_preserve_endpoints(true);
+
+        let upscaling_info = UpscalingInfo::Proportional { scale: 2.0 };
+        profile
+            .add_upscaling_rule(&[0], "", "", upscaling_info)
+            .expect("Rule added");
+        profile
+            .add_upscaling_rule_for_range(&[1], "allocation size", 0..4096, upscaling_info)
+            .expect("Rule added");
+        profile.add_endpoint(1, Cow::from("GET /done")).unwrap();
+        profile.add_endpoint(2, Cow::from("GET /running")).unwrap();
+        let ts = |nanos: i64| Timestamp::new(nanos).unwrap();
+        profile.add_span_interval(1, ts(100), ts(200)).unwrap();
+
+        let prev = profile.reset_and_return_previous(None).unwrap();
+        assert!(!prev.upscaling_rules.is_empty());
+
+        assert_eq!(1, profile.upscaling_rules.by_label_rules().count());
+        assert_eq!(1, profile.upscaling_rules.by_range_rules().count());
+        // the rules were interned again in the new string table
+        let (name, range, offsets, _) = profile.upscaling_rules.by_range_rules().next().unwrap();
+        assert_eq!(Some("allocation size"), profile.strings.get(name));
+        assert_eq!((0..4096, &[1][..]), (range, offsets));
+        let running = profile.intern("GET /running");
+        assert_eq!(1, profile.endpoints.mappings.len());
+        assert_eq!(running, profile.endpoints.mappings[&2]);
+        assert!(profile.endpoints.span_intervals.is_empty());
+
+        let sample = api::Sample {
+            locations: vec![],
+            values: vec![1, 10000, 42],
+            labels: vec![],
+        };
+        profile.add_sample(sample, None).expect("add to success");
+        let serialized_profile = pprof::roundtrip_to_pprof(profile).unwrap();
+        assert_eq!(vec![2, 10000, 42], serialized_profile.samples[0].values);
+
+        // both settings are off by default
+        let mut profile: Profile = Profile::new(SystemTime::now(), &sample_types, None);
+        profile
+            .add_upscaling_rule(&[0], "", "", upscaling_info)
+            .expect("Rule added");
+        profile.add_endpoint(2, Cow::from("GET /running")).unwrap();
+        profile.reset_and_return_previous(None).unwrap();
+        assert!(profile.upscaling_rules.is_empty());
+        assert!(profile.endpoints.mappings.is_empty());
+    }
+
+    #[test]
+    fn disabled_sample_types() {
+        let sample_types = create_samples_types();
+        let mut profile: Profile = Profile::new(SystemTime::now(), &sample_types, None);
+        profile.set_sample_type_enabled(1, false).unwrap();
+        profile.set_sample_type_enabled(3, false).unwrap_err();
+        assert!(profile.is_sample_type_enabled(0));
+        assert!(!profile.is_sample_type_enabled(1));
+        assert!(!profile.is_sample_type_enabled(3));
+
+        let sample = api::Sample {
+            locations: vec![],
+            values: vec![1, 10000, 42],
+            labels: vec![],
+        };
+        profile.add_sample(sample.clone(), None).unwrap();
+        // samples keep all their values
+        profile
+            .add_sample(
+                api::Sample {
+                    values: vec![1, 2],
+                    ..sample.clone()
+                },
+                None,
+            )
+            .unwrap_err();
+
+        profile.reset_and_return_previous(None).unwrap();
+        assert!(!profile.is_sample_type_enabled(1));
+        profile.add_sample(sample.clone(), None).unwrap();
+        // values added while the sample type was disabled stay zero
+        profile.set_sample_type_enabled(1, true).unwrap();
+        profile.add_sample(sample, None).unwrap();
+        profile.set_sample_type_enabled(2, false).unwrap();
+
+        let pprof = pprof::roundtrip_to_pprof(profile).unwrap();
+        let sample_types: Vec<_> = pprof
+            .sample_types
+            .iter()
+            .map(|sample_type| pprof.string_table[sample_type.r#type as usize].as_str())
+            .collect();
+        assert_eq!(vec!["samples", "wall-time"], sample_types);
+        assert_eq!(1, pprof.samples.len());
+        assert_eq!(vec![2, 10000], pprof.samples[0].values);
+    }
+
     #[test]
     fn test_upscaling_by_value_on_one_value_with_poisson() {
         let sample_types = create_samples_types();
@@ -1421,6 +3105,98 @@ mod api_tests {
         assert_eq!(second.values, vec![10, 24, 495]);
     }
 
+    #[test]
+    fn test_upscaling_by_range() {
+        let sample_types = create_samples_types();
+        let mut profile: Profile = Profile::new(SystemTime::now(), &sample_types, None);
+
+        for size in [512, 4096, 65535, 65536] {
+            let sample = api::Sample {
+                locations: vec![],
+                values: vec![1, 10000, size],
+                labels: vec![api::Label {
+                    key: "allocation size",
+                    str: None,
+                    num: size,
+                    num_unit: Some("bytes"),
+                }],
+            };
+            profile.add_sample(sample, None).expect("add to success");
+        }
+
+        profile
+            .add_upscaling_rule_for_range(
+                &[0],
+                "allocation size",
+                4096..65536,
+                UpscalingInfo::Proportional { scale: 2.0 },
+            )
+            .expect("Rule added");
+        profile
+            .add_upscaling_rule_for_range(
+                &[0],
+                "allocation size",
+                0..1024,
+                UpscalingInfo::Proportional { scale: 3.0 },
+            )
+            .expect("Rule added");
+        // overlapping ranges on the same value
+        profile
+            .add_upscaling_rule_for_range(
+                &[0],
+                "allocation size",
+                1000..5000,
+                UpscalingInfo::Proportional { scale: 2.0 },
+            )
+            .unwrap_err();
+        profile
+            .add_upscaling_rule_for_range(
+                &[1],
+                "allocation size",
+                10..10,
+                UpscalingInfo::Proportional { scale: 2.0 },
+            )
+            .unwrap_err();
+        // a sample matching both a by-label and a by-range rule would be upscaled twice
+        let error = profile
+            .add_upscaling_rule(
+                &[0],
+                "allocation class",
+                "large",
+                UpscalingInfo::Proportional { scale: 2.0 },
+            )
+            .unwrap_err();
+        assert!(error.to_string().contains("by-range rule"));
+        profile
+            .add_upscaling_rule(
+                &[1],
+                "allocation class",
+                "large",
+                UpscalingInfo::Proportional { scale: 2.0 },
+            )
+            .expect("Rule added");
+        let error = profile
+            .add_upscaling_rule_for_range(
+                &[1],
+                "allocation size",
+                0..1024,
+                UpscalingInfo::Proportional { scale: 2.0 },
+            )
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("The by-range rule (label name allocation size, range 0..1024)"));
+
+        let serialized_profile = pprof::roundtrip_to_pprof(profile).unwrap();
+        let mut samples: Vec<_> = serialized_profile
+            .samples
+            .iter()
+            .map(|sample| (sample.values[2], sample.values[0]))
+            .collect();
+        samples.sort_unstable();
+        assert_eq!(vec![(512, 3), (4096, 2), (65535, 2), (65536, 1)], samples);
+    }
+
     #[test]
     fn test_no_upscaling_by_label_if_no_match() {
         let sample_types = create_samples_types();
@@ -2006,14 +3782,8 @@ mod api_tests {
     #[test]
     fn local_root_span_id_label_as_i64() -> anyhow::Result<()> {
         let sample_types = vec![
-            api::ValueType {
-                r#type: "samples",
-                unit: "count",
-            },
-            api::ValueType {
-                r#type: "wall-time",
-                unit: "nanoseconds",
-            },
+            api::ValueType::new("samples", "count"),
+            api::ValueType::new("wall-time", "nanoseconds"),
         ];
 
         let mut profile = Profile::new(SystemTime::now(), &sample_types, None);
@@ -2109,4 +3879,106 @@ mod api_tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn sample_context_labels_are_provided_at_serialization() -> anyhow::Result<()> {
+        let sample_types = [api::ValueType::new("samples", "count")];
+        let mut profile = Profile::new(SystemTime::now(), &sample_types, None);
+
+        let thread_label = api::Label {
+            key: "thread id",
+            str: None,
+            num: 7,
+            num_unit: None,
+        };
+        for context_id in [1, 2, 2] {
+            let sample = api::Sample {
+                locations: vec![],
+                values: vec![1],
+                labels: vec![thread_label],
+            };
+            profile.add_sample_with_context(sample, None, context_id)?;
+        }
+        // samples with different contexts are not aggregated together
+        assert_eq!(profile.only_for_testing_num_aggregated_samples(), 2);
+
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let provider_calls = calls.clone();
+        profile.set_context_provider(Arc::new(
+            move |context_id: u64, add_label: &mut dyn FnMut(api::Label)| {
+                provider_calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let endpoint = format!("endpoint {context_id}");
+                add_label(api::Label {
+                    key: "trace endpoint",
+                    str: Some(&endpoint),
+                    num: 0,
+                    num_unit: None,
+                });
+            },
+        ));
+
+        // the previous profile is serialized with the provider, and the new one keeps it
+        let previous = profile.reset_and_return_previous(None)?;
+        let serialized_profile = pprof::roundtrip_to_pprof(previous)?;
+        assert_eq!(calls.load(std::sync::atomic::Ordering::Relaxed), 2);
+
+        let samples = serialized_profile.sorted_samples();
+        assert_eq!(samples.len(), 2);
+        let mut endpoints: Vec<_> = samples
+            .iter()
+            .map(|sample| {
+                assert_eq!(sample.labels.len(), 2);
+                assert_eq!(
+                    serialized_profile.string_table_fetch(sample.labels[0].key),
+                    "thread id"
+                );
+                let label = &sample.labels[1];
+                assert_eq!(
+                    serialized_profile.string_table_fetch(label.key),
+                    "trace endpoint"
+                );
+                (
+                    serialized_profile.string_table_fetch(label.str).clone(),
+                    sample.values[0],
+                )
+            })
+            .collect();
+        endpoints.sort();
+        assert_eq!(
+            endpoints,
+            vec![("endpoint 1".to_string(), 1), ("endpoint 2".to_string(), 2)]
+        );
+
+        let sample = api::Sample {
+            locations: vec![],
+            values: vec![1],
+            labels: vec![],
+        };
+        profile.add_sample_with_context(sample, None, 3)?;
+        let serialized_profile = pprof::roundtrip_to_pprof(profile)?;
+        assert_eq!(calls.load(std::sync::atomic::Ordering::Relaxed), 3);
+        let label = &serialized_profile.samples[0].labels[0];
+        assert_eq!(
+            serialized_profile.string_table_fetch(label.str),
+            "endpoint 3"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn context_id_label_is_reserved() {
+        let sample_types = [api::ValueType::new("samples", "count")];
+        let mut profile = Profile::new(SystemTime::now(), &sample_types, None);
+        let sample = api::Sample {
+            locations: vec![],
+            values: vec![1],
+            labels: vec![api::Label {
+                key: "_dd.sample_context_id",
+                str: None,
+                num: 1,
+                num_unit: None,
+            }],
+        };
+        assert!(profile.add_sample(sample, None).is_err());
+    }
 }
@@ -32,6 +32,16 @@ impl Id for StackTraceId {
     }
 }
 
+/// A stack trace interned with [`Profile::intern_stacktrace`]. It can be passed to
+/// [`Profile::add_sample_by_stacktrace`] of the same profile until that profile is reset, after
+/// which it is rejected and the stack trace needs to be interned again.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct InternedStackTrace {
+    pub(crate) generation: u64,
+    pub(crate) id: StackTraceId,
+}
+
 impl From<StackTraceId> for u32 {
     fn from(value: StackTraceId) -> Self {
         value.0
@@ -4,6 +4,11 @@
 use super::*;
 use crate::api::UpscalingInfo;
 use anyhow::Context;
+use std::ops::Range;
+
+fn is_overlapping(v1: &[usize], v2: &[usize]) -> bool {
+    v1.iter().any(|x| v2.contains(x))
+}
 
 #[derive(Debug)]
 pub struct UpscalingRule {
@@ -40,13 +45,24 @@ impl UpscalingRule {
     }
 }
 
+/// A rule applying to the samples with a numeric label within `range`.
+#[derive(Debug)]
+struct RangeUpscalingRule {
+    range: Range<i64>,
+    rule: UpscalingRule,
+}
+
 #[derive(Default)]
 pub struct UpscalingRules {
     rules: FxIndexMap<(StringId, StringId), Vec<UpscalingRule>>,
+    // by-range rules, by label name
+    range_rules: FxIndexMap<StringId, Vec<RangeUpscalingRule>>,
     // this is just an optimization in the case where we check collisions (when adding
     // a by-value rule) against by-label rules
     // 32 should be enough for the size of the bitmap
     offset_modified_by_bylabel_rule: bitmaps::Bitmap<32>,
+    // same for the by-range rules only, checked when adding a by-label rule
+    offset_modified_by_range_rule: bitmaps::Bitmap<32>,
 }
 
 impl UpscalingRules {
@@ -92,6 +108,75 @@ impl UpscalingRules {
         Ok(())
     }
 
+    /// Adds a rule applying to the samples with a numeric `label_name` label within `range`, e.g.
+    /// an allocation size within [4096, 65536).
+    pub fn add_for_range(
+        &mut self,
+        values_offset: &[usize],
+        label_name: (&str, StringId),
+        range: Range<i64>,
+        upscaling_info: UpscalingInfo,
+        max_offset: usize,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            values_offset.iter().all(|x| *x < max_offset),
+            "Invalid offset. Highest expected offset: {max_offset}",
+        );
+        anyhow::ensure!(
+            !label_name.1.is_zero(),
+            "By-range rules require a label name"
+        );
+        anyhow::ensure!(!range.is_empty(), "Empty range {range:?}");
+
+        let mut new_values_offset = values_offset.to_vec();
+        new_values_offset.sort_unstable();
+
+        let (label_name_str, label_name_id) = label_name;
+        let colliding_rule = self.range_rules.get(&label_name_id).and_then(|rules| {
+            rules.iter().find(|existing| {
+                existing.range.start < range.end
+                    && range.start < existing.range.end
+                    && is_overlapping(&existing.rule.values_offset, &new_values_offset)
+            })
+        });
+        anyhow::ensure!(
+            colliding_rule.is_none(),
+            "There are overlapping by-range rules for the same label name: {label_name_str} with at least one value offset in common.\n\
+            Existing rule {colliding_rule:?}\n\
+            New rule {label_name_str} {range:?} {new_values_offset:?} {upscaling_info:?}"
+        );
+        // a sample matching both a by-label and a by-range rule would be upscaled twice
+        let colliding_rule = self
+            .rules
+            .iter()
+            .filter(|((name, value), _)| !name.is_zero() || !value.is_zero())
+            .flat_map(|(_, rules)| rules)
+            .find(|rule| is_overlapping(&rule.values_offset, &new_values_offset));
+        anyhow::ensure!(
+            colliding_rule.is_none(),
+            "The by-range rule (label name {label_name_str}, range {range:?}) is colliding with a by-label rule on values offsets\n\
+            Existing rule {colliding_rule:?}, new rule values offset(s) {new_values_offset:?}"
+        );
+        self.check_byvalue_collisions(
+            &new_values_offset,
+            &format!("by-range rule (label name {label_name_str}, range {range:?})"),
+        )?;
+        upscaling_info.check_validity(max_offset)?;
+
+        new_values_offset.iter().for_each(|offset| {
+            self.offset_modified_by_bylabel_rule.set(*offset, true);
+            self.offset_modified_by_range_rule.set(*offset, true);
+        });
+        self.range_rules
+            .entry(label_name_id)
+            .or_default()
+            .push(RangeUpscalingRule {
+                range,
+                rule: UpscalingRule::new(new_values_offset, upscaling_info),
+            });
+        Ok(())
+    }
+
     fn check_collisions(
         &self,
         values_offset: &[usize],
@@ -100,9 +185,6 @@ impl UpscalingRules {
         upscaling_info: &UpscalingInfo,
     ) -> anyhow::Result<()> {
         // Check for duplicates
-        fn is_overlapping(v1: &[usize], v2: &[usize]) -> bool {
-            v1.iter().any(|x| v2.contains(x))
-        }
         let (label_name_str, label_name_id) = label_name;
         let (label_value_str, label_value_id) = label_value;
 
@@ -132,23 +214,77 @@ impl UpscalingRules {
                 "The by-value rule is colliding with at least one by-label rule at offset {collision_offset:?}\n\
                 by-value rule values offset(s) {values_offset:?}",
             )
-        } else if let Some(rules) = self.rules.get(&(StringId::ZERO, StringId::ZERO)) {
+        } else {
+            let collision_offset = values_offset
+                .iter()
+                .find(|offset| self.offset_modified_by_range_rule.get(**offset));
+            anyhow::ensure!(
+                collision_offset.is_none(),
+                "The by-label rule (label name {label_name_str}, label value {label_value_str}) is colliding with at least one by-range rule at offset {collision_offset:?}\n\
+                by-label rule values offset(s) {values_offset:?}",
+            );
+            self.check_byvalue_collisions(
+                values_offset,
+                &format!(
+                    "by-label rule (label name {label_name_str}, label value {label_value_str})"
+                ),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Checks the by-label or by-range rule described by `rule` against the by-value rules.
+    fn check_byvalue_collisions(&self, values_offset: &[usize], rule: &str) -> anyhow::Result<()> {
+        if let Some(rules) = self.rules.get(&(StringId::ZERO, StringId::ZERO)) {
             let collide_with_byvalue_rule = rules
                 .iter()
                 .find(|rule| is_overlapping(&rule.values_offset, values_offset));
             anyhow::ensure!(collide_with_byvalue_rule.is_none(),
-                "The by-label rule (label name {label_name_str}, label value {label_value_str}) is colliding with a by-value rule on values offsets\n\
+                "The {rule} is colliding with a by-value rule on values offsets\n\
                 Existing values offset(s) {collide_with_byvalue_rule:?}, new rule values offset(s) {values_offset:?}");
         }
         Ok(())
     }
 
+    /// Iterates over the by-value and by-label rules, with the ids of their label name and value
+    /// (both zero for by-value rules), e.g. to add them to another profile.
+    pub fn by_label_rules(
+        &self,
+    ) -> impl Iterator<Item = (StringId, StringId, &[usize], UpscalingInfo)> {
+        self.rules.iter().flat_map(|(&(name, value), rules)| {
+            rules.iter().map(move |rule| {
+                (
+                    name,
+                    value,
+                    rule.values_offset.as_slice(),
+                    rule.upscaling_info,
+                )
+            })
+        })
+    }
+
+    /// Iterates over the by-range rules, with the id of their label name.
+    pub fn by_range_rules(
+        &self,
+    ) -> impl Iterator<Item = (StringId, Range<i64>, &[usize], UpscalingInfo)> {
+        self.range_rules.iter().flat_map(|(&name, rules)| {
+            rules.iter().map(move |range_rule| {
+                (
+                    name,
+                    range_rule.range.clone(),
+                    range_rule.rule.values_offset.as_slice(),
+                    range_rule.rule.upscaling_info,
+                )
+            })
+        })
+    }
+
     pub fn get(&self, k: &(StringId, StringId)) -> Option<&Vec<UpscalingRule>> {
         self.rules.get(k)
     }
 
     pub fn is_empty(&self) -> bool {
-        self.rules.is_empty()
+        self.rules.is_empty() && self.range_rules.is_empty()
     }
 
     pub fn upscale_values(&self, values: &mut [i64], labels: &[Label]) -> anyhow::Result<()> {
@@ -165,19 +301,34 @@ impl UpscalingRules {
                         },
                     ))
                 })
-                .collect::<Vec<&Vec<UpscalingRule>>>();
+                .flatten()
+                .collect::<Vec<&UpscalingRule>>();
+
+            // then byrange rules matching numeric labels (if any)
+            if !self.range_rules.is_empty() {
+                for label in labels {
+                    if let (LabelValue::Num { num, .. }, Some(rules)) =
+                        (label.get_value(), self.range_rules.get(&label.get_key()))
+                    {
+                        group_of_rules.extend(
+                            rules
+                                .iter()
+                                .filter(|rule| rule.range.contains(num))
+                                .map(|rule| &rule.rule),
+                        );
+                    }
+                }
+            }
 
             // get byvalue rules if any
             if let Some(byvalue_rules) = self.get(&(StringId::ZERO, StringId::ZERO)) {
-                group_of_rules.push(byvalue_rules);
+                group_of_rules.extend(byvalue_rules);
             }
 
-            group_of_rules.iter().for_each(|rules| {
-                rules.iter().for_each(|rule| {
-                    let scale = rule.compute_scale(values);
-                    rule.values_offset.iter().for_each(|offset| {
-                        values[*offset] = (values[*offset] as f64 * scale).round() as i64
-                    })
+            group_of_rules.iter().for_each(|rule| {
+                let scale = rule.compute_scale(values);
+                rule.values_offset.iter().for_each(|offset| {
+                    values[*offset] = (values[*offset] as f64 * scale).round() as i64
                 })
             });
         }
@@ -1,8 +1,10 @@
 // Copyright 2021-Present Datadog, Inc. https://www.datadoghq.com/
 // SPDX-License-Identifier: Apache-2.0
 
+pub mod allocator_stats;
 pub mod api;
 pub mod collections;
+#[cfg(feature = "exporter")]
 pub mod exporter;
 pub mod internal;
 pub mod iter;
@@ -0,0 +1,336 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! Helpers to compare pprofs independently of the order in which their data was added, e.g. for
+//! snapshot tests.
+
+use super::{Function, Label, Line, Location, Mapping, Profile, Sample, ValueType};
+use crate::serializer::CompressedProtobufSerializer;
+use prost::Message;
+use std::collections::HashMap;
+use std::io::Read;
+
+impl Profile {
+    /// Returns the samples, sorted by their location ids, values and labels.
+    pub fn sorted_samples(&self) -> Vec<Sample> {
+        let mut samples = self.samples.clone();
+        samples.sort_unstable();
+        samples
+    }
+
+    /// Returns the string at index `id` of the string table.
+    ///
+    /// # Panics
+    /// If the id is not in the string table.
+    pub fn string_table_fetch(&self, id: i64) -> &String {
+        self.string_table
+            .get(id as usize)
+            .unwrap_or_else(|| panic!("String {id} not found"))
+    }
+
+    pub fn string_table_fetch_owned(&self, id: i64) -> Box<str> {
+        self.string_table_fetch(id).clone().into_boxed_str()
+    }
+
+    /// Returns an equivalent profile in a canonical form: the string table is sorted, the
+    /// mappings, functions and locations are sorted by content and renumbered from 1, and the
+    /// samples and their labels are sorted. Two profiles holding the same data are equal once
+    /// canonicalized, whatever the order their data was added in.
+    ///
+    /// Fails if the profile references a string, mapping, function or location which doesn't
+    /// exist.
+    pub fn canonicalize(&self) -> anyhow::Result<Profile> {
+        let strings = StringRemap::new(&self.string_table);
+        let string = |id: i64| strings.get(id);
+        let value_type = |value_type: &ValueType| -> anyhow::Result<ValueType> {
+            Ok(ValueType {
+                r#type: string(value_type.r#type)?,
+                unit: string(value_type.unit)?,
+            })
+        };
+
+        let mut mappings = self
+            .mappings
+            .iter()
+            .map(|mapping| {
+                Ok(Mapping {
+                    filename: string(mapping.filename)?,
+                    build_id: string(mapping.build_id)?,
+                    ..*mapping
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        mappings.sort_by_key(|mapping| {
+            (
+                mapping.memory_start,
+                mapping.memory_limit,
+                mapping.file_offset,
+                mapping.filename,
+                mapping.build_id,
+            )
+        });
+        let mapping_ids = renumber(&mut mappings, |mapping| &mut mapping.id);
+
+        let mut functions = self
+            .functions
+            .iter()
+            .map(|function| {
+                Ok(Function {
+                    name: string(function.name)?,
+                    system_name: string(function.system_name)?,
+                    filename: string(function.filename)?,
+                    ..*function
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        functions.sort_by_key(|function| {
+            (
+                function.name,
+                function.system_name,
+                function.filename,
+                function.start_line,
+            )
+        });
+        let function_ids = renumber(&mut functions, |function| &mut function.id);
+
+        let mut locations = self
+            .locations
+            .iter()
+            .map(|location| {
+                let lines = location
+                    .lines
+                    .iter()
+                    .map(|line| {
+                        Ok(Line {
+                            function_id: remap_id(&function_ids, line.function_id, "function")?,
+                            line: line.line,
+                        })
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+                Ok(Location {
+                    id: location.id,
+                    mapping_id: remap_id(&mapping_ids, location.mapping_id, "mapping")?,
+                    address: location.address,
+                    lines,
+                    is_folded: location.is_folded,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        locations.sort_by_cached_key(|location| {
+            let lines: Vec<_> = location
+                .lines
+                .iter()
+                .map(|line| (line.function_id, line.line))
+                .collect();
+            (
+                location.mapping_id,
+                location.address,
+                lines,
+                location.is_folded,
+            )
+        });
+        let location_ids = renumber(&mut locations, |location| &mut location.id);
+
+        let mut samples = self
+            .samples
+            .iter()
+            .map(|sample| {
+                let location_ids = sample
+                    .location_ids
+                    .iter()
+                    .map(|id| remap_id(&location_ids, *id, "location"))
+                    .collect::<anyhow::Result<_>>()?;
+                let mut labels = sample
+                    .labels
+                    .iter()
+                    .map(|label| {
+                        Ok(Label {
+                            key: string(label.key)?,
+                            str: string(label.str)?,
+                            num: label.num,
+                            num_unit: string(label.num_unit)?,
+                        })
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                labels.sort_unstable();
+                Ok(Sample {
+                    location_ids,
+                    values: sample.values.clone(),
+                    labels,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        samples.sort_unstable();
+
+        Ok(Profile {
+            sample_types: self
+                .sample_types
+                .iter()
+                .map(value_type)
+                .collect::<anyhow::Result<_>>()?,
+            samples,
+            mappings,
+            locations,
+            functions,
+            drop_frames: string(self.drop_frames)?,
+            keep_frames: string(self.keep_frames)?,
+            time_nanos: self.time_nanos,
+            duration_nanos: self.duration_nanos,
+            period_type: self.period_type.as_ref().map(value_type).transpose()?,
+            period: self.period,
+            comment: self
+                .comment
+                .iter()
+                .map(|id| string(*id))
+                .collect::<anyhow::Result<_>>()?,
+            default_sample_type: string(self.default_sample_type)?,
+            // last, as the other fields borrow the remapping
+            string_table: strings.sorted,
+        })
+    }
+}
+
+/// Decodes an lz4 compressed pprof, canonicalizes it and compresses it again, so that profiles
+/// holding the same data are encoded to the same bytes, see [`Profile::canonicalize`].
+pub(crate) fn canonicalize_compressed_pprof(encoded: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    lz4_flex::frame::FrameDecoder::new(encoded).read_to_end(&mut buffer)?;
+    let profile = Profile::decode(buffer.as_slice())?.canonicalize()?;
+    let mut encoder = CompressedProtobufSerializer::with_capacity(encoded.len());
+    encoder.encode(profile)?;
+    encoder.finish()
+}
+
+/// Maps the indices of a string table to the indices of the same strings in the sorted and
+/// deduplicated table. The empty string stays at index 0.
+struct StringRemap {
+    sorted: Vec<String>,
+    indices: Vec<i64>,
+}
+
+impl StringRemap {
+    fn new(string_table: &[String]) -> Self {
+        let mut sorted: Vec<String> = string_table.to_vec();
+        sorted.push(String::new());
+        sorted.sort_unstable();
+        sorted.dedup();
+        let indices = string_table
+            .iter()
+            .map(|string| sorted.binary_search(string).unwrap_or_default() as i64)
+            .collect();
+        StringRemap { sorted, indices }
+    }
+
+    fn get(&self, id: i64) -> anyhow::Result<i64> {
+        usize::try_from(id)
+            .ok()
+            .and_then(|index| self.indices.get(index).copied())
+            .ok_or_else(|| anyhow::anyhow!("String {id} not found"))
+    }
+}
+
+/// Assigns the ids 1..=n to the items in their current order, returning the old ids mapped to the
+/// new ones.
+fn renumber<T>(items: &mut [T], id: impl Fn(&mut T) -> &mut u64) -> HashMap<u64, u64> {
+    items
+        .iter_mut()
+        .zip(1..)
+        .map(|(item, new_id)| (std::mem::replace(id(item), new_id), new_id))
+        .collect()
+}
+
+fn remap_id(ids: &HashMap<u64, u64>, id: u64, kind: &str) -> anyhow::Result<u64> {
+    ids.get(&id)
+        .copied()
+        .ok_or_else(|| anyhow::anyhow!("{kind} {id} not found"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api;
+    use crate::internal;
+    use crate::pprof::roundtrip_to_pprof;
+    use std::time::SystemTime;
+
+    fn location<'a>(name: &'a str, filename: &'a str) -> api::Location<'a> {
+        api::Location {
+            function: api::Function {
+                name,
+                filename,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn sample<'a>(names: &[&'a str], thread: &'a str, value: i64) -> api::Sample<'a> {
+        api::Sample {
+            locations: names
+                .iter()
+                .map(|name| location(name, "index.php"))
+                .collect(),
+            values: vec![value],
+            labels: vec![api::Label {
+                key: "thread name",
+                str: Some(thread),
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn canonicalize_ignores_insertion_order() {
+        let sample_types = [api::ValueType::new("samples", "count")];
+        let samples = [
+            sample(&["foo", "{main}"], "main", 1),
+            sample(&["bar", "foo", "{main}"], "worker", 2),
+            sample(&["baz"], "main", 3),
+        ];
+        let now = SystemTime::now();
+
+        let mut profile = internal::Profile::new(now, &sample_types, None);
+        for sample in samples.iter().cloned() {
+            profile.add_sample(sample, None).unwrap();
+        }
+        let mut reversed = internal::Profile::new(now, &sample_types, None);
+        for sample in samples.iter().rev().cloned() {
+            reversed.add_sample(sample, None).unwrap();
+        }
+
+        let profile = roundtrip_to_pprof(profile).unwrap();
+        let reversed = roundtrip_to_pprof(reversed).unwrap();
+        assert_ne!(profile.string_table, reversed.string_table);
+
+        let mut canonical = profile.canonicalize().unwrap();
+        let mut canonical_reversed = reversed.canonicalize().unwrap();
+        // the durations depend on when the profiles were serialized
+        canonical.duration_nanos = 0;
+        canonical_reversed.duration_nanos = 0;
+        assert_eq!(canonical, canonical_reversed);
+        assert_eq!("", canonical.string_table[0]);
+
+        // the ids, which are ignored by the equality, are renumbered too
+        let ids = |profile: &super::Profile| -> Vec<Vec<u64>> {
+            vec![
+                profile.mappings.iter().map(|m| m.id).collect(),
+                profile.functions.iter().map(|f| f.id).collect(),
+                profile.locations.iter().map(|l| l.id).collect(),
+            ]
+        };
+        assert_eq!(ids(&canonical), ids(&canonical_reversed));
+    }
+
+    #[test]
+    fn canonicalize_rejects_dangling_references() {
+        let profile = super::Profile {
+            string_table: vec!["".to_string()],
+            samples: vec![super::Sample {
+                location_ids: vec![1],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(profile.canonicalize().is_err());
+    }
+}
@@ -1,9 +1,11 @@
 // Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
 // SPDX-License-Identifier: Apache-2.0
 
+mod canonical;
 mod proto;
 
 pub mod sliced_proto;
+pub(crate) use canonical::canonicalize_compressed_pprof;
 pub use proto::*;
 
 #[cfg(test)]
@@ -168,25 +168,6 @@ pub struct Function {
     pub start_line: i64, // Index into string table
 }
 
-#[cfg(test)]
-impl Profile {
-    pub fn sorted_samples(&self) -> Vec<Sample> {
-        let mut samples = self.samples.clone();
-        samples.sort_unstable();
-        samples
-    }
-
-    pub fn string_table_fetch(&self, id: i64) -> &String {
-        self.string_table
-            .get(id as usize)
-            .expect("String {id} not found")
-    }
-
-    pub fn string_table_fetch_owned(&self, id: i64) -> Box<str> {
-        self.string_table_fetch(id).clone().into_boxed_str()
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -62,6 +62,7 @@ mod tests {
     use datadog_profiling::exporter::*;
     use ddcommon::tag;
     use serde_json::json;
+    use std::time::Duration;
 
     fn default_tags() -> Vec<Tag> {
         vec![tag!("service", "php"), tag!("host", "bits")]
@@ -135,6 +136,73 @@ mod tests {
         assert_eq!(parsed_event_json["version"], json!("4"));
     }
 
+    #[test]
+    // This test invokes an external function SecTrustSettingsCopyCertificates
+    // which Miri cannot evaluate.
+    #[cfg_attr(miri, ignore)]
+    fn connection_reuse() {
+        let base_url = "http://localhost:8126".parse().expect("url to parse");
+        let endpoint = config::agent(base_url).expect("endpoint to construct");
+        let mut exporter = ProfileExporter::new(
+            "dd-trace-foo",
+            "1.2.3",
+            "php",
+            Some(default_tags()),
+            endpoint,
+        )
+        .expect("exporter to construct");
+
+        let request = multipart(&exporter, None, None);
+        assert_eq!(request.headers().get("Connection").unwrap(), "close");
+
+        exporter.set_http_client_config(&HttpClientConfig {
+            http_version: HttpVersion::Http2,
+            pool_idle_timeout: Some(Duration::from_secs(90)),
+            http2_keep_alive_interval: Some(Duration::from_secs(30)),
+            connect_timeout: Some(Duration::from_secs(3)),
+        });
+        let request = multipart(&exporter, None, None);
+        assert!(!request.headers().contains_key("Connection"));
+    }
+
+    #[test]
+    // This test invokes an external function SecTrustSettingsCopyCertificates
+    // which Miri cannot evaluate.
+    #[cfg_attr(miri, ignore)]
+    fn additional_tags_override_exporter_tags() {
+        let base_url = "http://localhost:8126".parse().expect("url to parse");
+        let endpoint = config::agent(base_url).expect("endpoint to construct");
+        let exporter = ProfileExporter::new(
+            "dd-trace-foo",
+            "1.2.3",
+            "php",
+            Some(default_tags()),
+            endpoint,
+        )
+        .expect("exporter to construct");
+
+        let now = chrono::Utc::now();
+        let additional_tags = vec![tag!("host", "other"), tag!("profile_seq", "2")];
+        let request = exporter
+            .build(
+                now,
+                now,
+                &[],
+                &[],
+                Some(&additional_tags),
+                None,
+                None,
+                None,
+                Duration::from_secs(10),
+            )
+            .expect("request to be built");
+
+        assert_eq!(
+            parsed_event_json(request)["tags_profiler"],
+            json!("service:php,host:other,profile_seq:2")
+        );
+    }
+
     #[test]
     // This test invokes an external function SecTrustSettingsCopyCertificates
     // which Miri cannot evaluate.
@@ -42,27 +42,15 @@ fn wordpress() {
     assert_eq!(Duration::from_nanos(67000138417_u64), api.duration);
 
     let expected_sample_types = vec![
-        api::ValueType {
-            r#type: "sample",
-            unit: "count",
-        },
-        api::ValueType {
-            r#type: "wall-time",
-            unit: "nanoseconds",
-        },
-        api::ValueType {
-            r#type: "cpu-time",
-            unit: "nanoseconds",
-        },
+        api::ValueType::new("sample", "count"),
+        api::ValueType::new("wall-time", "nanoseconds"),
+        api::ValueType::new("cpu-time", "nanoseconds"),
     ];
     assert_eq!(expected_sample_types, api.sample_types);
 
     let expected_period = Some((
         10000000_i64,
-        api::ValueType {
-            r#type: "wall-time",
-            unit: "nanoseconds",
-        },
+        api::ValueType::new("wall-time", "nanoseconds"),
     ));
     assert_eq!(expected_period, api.period);
 
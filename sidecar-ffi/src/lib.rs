@@ -11,10 +11,14 @@ use datadog_sidecar::config;
 use datadog_sidecar::config::LogMethod;
 use datadog_sidecar::dogstatsd::DogStatsDAction;
 use datadog_sidecar::one_way_shared_memory::{OneWayShmReader, ReaderOpener};
+use datadog_sidecar::service::profile_upload::ProfileExporterConfig;
+use datadog_sidecar::service::proxy_upload::{IntakeKind, ProxyUpload};
 use datadog_sidecar::service::{
     blocking::{self, SidecarTransport},
-    InstanceId, QueueId, RuntimeMetadata, SerializedTracerHeaderTags, SessionConfig, SidecarAction,
+    InstanceId, QueueId, RuntimeMetadata, SerializedTracerHeaderTags, SessionConfig, SessionTags,
+    SessionTagsUpdate, SidecarAction, SidecarError,
 };
+use datadog_sidecar::startup_diagnostics::last_startup_diagnostics;
 use ddcommon::tag::Tag;
 use ddcommon::Endpoint;
 use ddcommon_ffi as ffi;
@@ -218,6 +222,113 @@ pub extern "C" fn ddog_sidecar_flush_traces(transport: &mut Box<SidecarTransport
     MaybeError::None
 }
 
+/// The kind of data a result reported by `ddog_sidecar_flush_all` is about.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FlushedData {
+    /// The traces, the target is the url of the endpoint.
+    Traces,
+    /// The telemetry of an app, the target is its service name and env name, separated by a
+    /// colon.
+    Telemetry,
+    /// A profile or proxied payload, without target.
+    Upload,
+}
+
+/// Flushes the traces, and the telemetry and profiles of the instance, blocking until the data was
+/// sent or the timeout expired. Meant to drain everything before e.g. a serverless platform freezes
+/// the process. The read timeout of the transport must be longer than `timeout_ms`.
+///
+/// If `callback` is set, it is called with the result of each destination data was sent to: the
+/// HTTP status if known (0 otherwise) and the error message, which is empty on success. The
+/// slices are only valid during the call. The first failure is also returned.
+#[no_mangle]
+pub extern "C" fn ddog_sidecar_flush_all(
+    transport: &mut Box<SidecarTransport>,
+    instance_id: &InstanceId,
+    timeout_ms: u64,
+    callback: Option<
+        extern "C" fn(
+            ctx: *mut c_void,
+            data: FlushedData,
+            target: ffi::CharSlice,
+            status: u16,
+            error: ffi::CharSlice,
+        ),
+    >,
+    ctx: *mut c_void,
+) -> MaybeError {
+    let timeout = Duration::from_millis(timeout_ms);
+    let result = match blocking::flush_all(transport, instance_id, timeout) {
+        Ok(result) => result,
+        Err(e) => return MaybeError::Some(ffi::Error::from(e.to_string())),
+    };
+
+    if let Some(callback) = callback {
+        let report = |data, target: &str, status: Option<u16>, error: Option<&SidecarError>| {
+            let status = match error {
+                Some(SidecarError::UploadFailed { status, .. }) => *status,
+                _ => status,
+            };
+            let error = error.map(ToString::to_string).unwrap_or_default();
+            callback(
+                ctx,
+                data,
+                ffi::CharSlice::from(target),
+                status.unwrap_or(0),
+                ffi::CharSlice::from(error.as_str()),
+            );
+        };
+        for (url, traces) in &result.traces {
+            report(
+                FlushedData::Traces,
+                url,
+                traces.as_ref().ok().copied(),
+                traces.as_ref().err(),
+            );
+        }
+        for ((service, env), telemetry) in &result.telemetry {
+            let target = format!("{service}:{env}");
+            report(
+                FlushedData::Telemetry,
+                &target,
+                None,
+                telemetry.as_ref().err(),
+            );
+        }
+        for upload in &result.uploads {
+            report(FlushedData::Upload, "", None, upload.as_ref().err());
+        }
+    }
+
+    if let Some(error) = result.first_error() {
+        return MaybeError::Some(ffi::Error::from(error.to_string()));
+    }
+    MaybeError::None
+}
+
+/// Takes the endpoints of the trace chunks the instance sent so far, calling `callback` with the
+/// local root span id and the endpoint of each, e.g. to pass them on to
+/// `ddog_prof_Profile_add_endpoint`. The endpoint is only valid during the call.
+#[no_mangle]
+pub extern "C" fn ddog_sidecar_take_trace_endpoints(
+    transport: &mut Box<SidecarTransport>,
+    instance_id: &InstanceId,
+    callback: extern "C" fn(ctx: *mut c_void, local_root_span_id: u64, endpoint: ffi::CharSlice),
+    ctx: *mut c_void,
+) -> MaybeError {
+    let endpoints = try_c!(blocking::take_trace_endpoints(transport, instance_id));
+    for (local_root_span_id, endpoint) in endpoints {
+        callback(
+            ctx,
+            local_root_span_id,
+            ffi::CharSlice::from(endpoint.as_str()),
+        );
+    }
+
+    MaybeError::None
+}
+
 #[no_mangle]
 #[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn ddog_sidecar_instanceId_build(
@@ -421,7 +532,30 @@ pub extern "C" fn ddog_sidecar_is_closed(transport: &mut Box<SidecarTransport>)
     transport.is_closed()
 }
 
-/// Sets the configuration for a session.
+/// The settings of a session beyond its endpoints, flushing and logging, see
+/// [ddog_sidecar_session_set_config_with_options]. The slices must be valid, but may be empty.
+#[repr(C)]
+#[derive(Default)]
+pub struct SessionOptions<'a> {
+    /// The intake traces are submitted to directly instead of the agent if `agentless` is set and
+    /// the endpoint has an API key. May be null.
+    pub agentless_endpoint: Option<&'a Endpoint>,
+    pub agentless: bool,
+    /// Span tag replacement rules in the JSON format of DD_APM_REPLACE_TAGS. May be empty.
+    pub replace_tags: ffi::CharSlice<'a>,
+    /// A JSON array of rules adding tags to the spans matching a service, env and name regex, see
+    /// `TaggingRules`. May be empty.
+    pub tagging_rules: ffi::CharSlice<'a>,
+    /// The `apm_config` section of the agent configuration as JSON, whose `obfuscation` settings
+    /// are applied to the traces submitted agentlessly. May be empty.
+    pub obfuscation_config: ffi::CharSlice<'a>,
+    /// The host, container and runtime tags of the session. May be null.
+    pub host_tags: Option<&'a ddcommon_ffi::Vec<Tag>>,
+    pub container_tags: Option<&'a ddcommon_ffi::Vec<Tag>>,
+    pub runtime_tags: Option<&'a ddcommon_ffi::Vec<Tag>>,
+}
+
+/// Sets the configuration for a session, with the default [SessionOptions].
 #[no_mangle]
 #[allow(clippy::missing_safety_doc)]
 pub unsafe extern "C" fn ddog_sidecar_session_set_config(
@@ -434,6 +568,39 @@ pub unsafe extern "C" fn ddog_sidecar_session_set_config(
     force_drop_size: usize,
     log_level: ffi::CharSlice,
     log_path: ffi::CharSlice,
+) -> MaybeError {
+    ddog_sidecar_session_set_config_with_options(
+        transport,
+        session_id,
+        agent_endpoint,
+        dogstatsd_endpoint,
+        flush_interval_milliseconds,
+        force_flush_size,
+        force_drop_size,
+        log_level,
+        log_path,
+        &SessionOptions::default(),
+    )
+}
+
+/// Like [ddog_sidecar_session_set_config], but also sets the settings of the session beyond its
+/// endpoints, flushing and logging.
+///
+/// Calling this again reconfigures the session, e.g. switches between agentless and agent
+/// submission without restarting the sidecar.
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ddog_sidecar_session_set_config_with_options(
+    transport: &mut Box<SidecarTransport>,
+    session_id: ffi::CharSlice,
+    agent_endpoint: &Endpoint,
+    dogstatsd_endpoint: &Endpoint,
+    flush_interval_milliseconds: u64,
+    force_flush_size: usize,
+    force_drop_size: usize,
+    log_level: ffi::CharSlice,
+    log_path: ffi::CharSlice,
+    options: &SessionOptions,
 ) -> MaybeError {
     try_c!(blocking::set_session_config(
         transport,
@@ -449,7 +616,45 @@ pub unsafe extern "C" fn ddog_sidecar_session_set_config(
                 config::FromEnv::log_method()
             } else {
                 LogMethod::File(String::from(log_path.to_utf8_lossy()).into())
-            }
+            },
+            replace_tags: options.replace_tags.to_utf8_lossy().into(),
+            tagging_rules: options.tagging_rules.to_utf8_lossy().into(),
+            obfuscation_config: options.obfuscation_config.to_utf8_lossy().into(),
+            tags: SessionTags {
+                host: collect_tags(options.host_tags).unwrap_or_default(),
+                container: collect_tags(options.container_tags).unwrap_or_default(),
+                runtime: collect_tags(options.runtime_tags).unwrap_or_default(),
+            },
+            agentless_endpoint: options.agentless_endpoint.cloned(),
+            agentless: options.agentless,
+        },
+    ));
+
+    MaybeError::None
+}
+
+fn collect_tags(tags: Option<&ddcommon_ffi::Vec<Tag>>) -> Option<Vec<Tag>> {
+    tags.map(|tags| tags.iter().cloned().collect())
+}
+
+/// Updates the tags of a session, without reconfiguring it. The kinds of tags passed as null are
+/// kept as they are.
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ddog_sidecar_session_update_tags(
+    transport: &mut Box<SidecarTransport>,
+    session_id: ffi::CharSlice,
+    host_tags: Option<&ddcommon_ffi::Vec<Tag>>,
+    container_tags: Option<&ddcommon_ffi::Vec<Tag>>,
+    runtime_tags: Option<&ddcommon_ffi::Vec<Tag>>,
+) -> MaybeError {
+    try_c!(blocking::update_session_tags(
+        transport,
+        session_id.to_utf8_lossy().into(),
+        SessionTagsUpdate {
+            host: collect_tags(host_tags),
+            container: collect_tags(container_tags),
+            runtime: collect_tags(runtime_tags),
         },
     ));
 
@@ -515,6 +720,83 @@ pub unsafe extern "C" fn ddog_sidecar_send_trace_v04_shm(
     MaybeError::None
 }
 
+/// Forwards a payload held in shared memory to an intake, with the endpoint and credentials of the
+/// session. An empty `content_type` sends no Content-Type header.
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ddog_sidecar_proxy_upload(
+    transport: &mut Box<SidecarTransport>,
+    instance_id: &InstanceId,
+    intake: IntakeKind,
+    shm_handle: Box<ShmHandle>,
+    len: usize,
+    content_type: ffi::CharSlice,
+    timeout_ms: u64,
+) -> MaybeError {
+    try_c!(blocking::proxy_upload(
+        transport,
+        instance_id,
+        *shm_handle,
+        len,
+        ProxyUpload {
+            intake,
+            content_type: content_type.to_utf8_lossy().into_owned(),
+            headers: vec![],
+            timeout: Duration::from_millis(timeout_ms),
+        },
+    ));
+
+    MaybeError::None
+}
+
+/// The `ddog_prof_EncodedProfile` returned by `ddog_prof_Profile_serialize`, only ever accessed
+/// through the pointer to the encoded profile it holds.
+#[repr(C)]
+pub struct EncodedProfile {
+    // This may be null, but if not it will point to a valid EncodedProfile.
+    inner: *mut datadog_profiling::internal::EncodedProfile,
+}
+
+/// Uploads a profile through the sidecar. The `encoded_profile` is consumed: its pprof is moved
+/// into sealed shared memory and freed, the `encoded_profile` is left empty, so dropping it with
+/// `ddog_prof_EncodedProfile_drop` afterwards is harmless.
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn ddog_sidecar_send_profile(
+    transport: &mut Box<SidecarTransport>,
+    instance_id: &InstanceId,
+    endpoint: &Endpoint,
+    profiling_library_name: ffi::CharSlice,
+    profiling_library_version: ffi::CharSlice,
+    family: ffi::CharSlice,
+    tags: Option<&ddcommon_ffi::Vec<Tag>>,
+    timeout_ms: u64,
+    encoded_profile: &mut EncodedProfile,
+) -> MaybeError {
+    let inner = std::mem::replace(&mut encoded_profile.inner, std::ptr::null_mut());
+    if inner.is_null() {
+        return MaybeError::Some(ffi::Error::from(
+            "encoded profile's inner pointer was null (indicates use-after-free)".to_string(),
+        ));
+    }
+    try_c!(blocking::send_profile(
+        transport,
+        instance_id,
+        ProfileExporterConfig {
+            endpoint: endpoint.clone(),
+            profiling_library_name: profiling_library_name.to_utf8_lossy().into_owned(),
+            profiling_library_version: profiling_library_version.to_utf8_lossy().into_owned(),
+            family: family.to_utf8_lossy().into_owned(),
+            tags: collect_tags(tags).unwrap_or_default(),
+            timeout: Duration::from_millis(timeout_ms),
+        },
+        *Box::from_raw(inner),
+    ));
+
+    MaybeError::None
+}
+
 /// Sends a trace as bytes to the sidecar.
 #[no_mangle]
 #[allow(clippy::missing_safety_doc)]
@@ -570,6 +852,46 @@ pub unsafe extern "C" fn ddog_sidecar_stats(
     ffi::CharSlice::from_raw_parts(malloced as *mut c_char, size)
 }
 
+/// Returns what the sidecar learned from the agent of the session as JSON: the agent /info
+/// response as `info` and the sampling rates by service as `rate_by_service`, each null if not
+/// known (yet). These are shared by all sessions talking to the same agent, so new runtimes start
+/// with a warm state. The returned string must be freed with `free()`.
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ddog_sidecar_get_agent_state(
+    transport: &mut Box<SidecarTransport>,
+    session_id: ffi::CharSlice,
+) -> ffi::CharSlice {
+    let str = match blocking::get_agent_state(transport, session_id.to_utf8_lossy().into()) {
+        Ok(state) => state,
+        Err(e) => format!("{:?}", e),
+    };
+    let size = str.len();
+    let malloced = libc::malloc(size) as *mut u8;
+    let buf = slice::from_raw_parts_mut(malloced, size);
+    buf.copy_from_slice(str.as_bytes());
+    ffi::CharSlice::from_raw_parts(malloced as *mut c_char, size)
+}
+
+/// Returns the diagnostics of the last attempt of this process to start or connect to the
+/// sidecar, with `ddog_sidecar_connect`, as JSON. It is empty if there was no attempt. The
+/// returned string must be freed with `free()`.
+///
+/// Setting `_DD_SIDECAR_STARTUP_DIAGNOSTICS` to a file path also appends them to that file.
+#[no_mangle]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe extern "C" fn ddog_sidecar_startup_diagnostics() -> ffi::CharSlice {
+    let str = last_startup_diagnostics()
+        .map(|diagnostics| diagnostics.to_json())
+        .unwrap_or_default();
+    let size = str.len();
+    // malloc(0) may return null, which is not a valid slice pointer
+    let malloced = libc::malloc(size.max(1)) as *mut u8;
+    let buf = slice::from_raw_parts_mut(malloced, size);
+    buf.copy_from_slice(str.as_bytes());
+    ffi::CharSlice::from_raw_parts(malloced as *mut c_char, size)
+}
+
 /// Send a DogStatsD "count" metric.
 #[no_mangle]
 #[allow(clippy::missing_safety_doc)]
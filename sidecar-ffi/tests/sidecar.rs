@@ -87,6 +87,7 @@ fn test_ddog_sidecar_register_app() {
             &Endpoint {
                 api_key: None,
                 url: hyper::Uri::from_static("http://localhost:8082/"),
+                ..Default::default()
             },
             &Endpoint::default(),
             1000,
@@ -125,12 +126,13 @@ fn test_ddog_sidecar_register_app() {
             "env_name".into()
         ));
         // reset session config - and cause shutdown of all existing instances
-        ddog_sidecar_session_set_config(
+        ddog_sidecar_session_set_config_with_options(
             &mut transport,
             "session_id".into(),
             &Endpoint {
                 api_key: None,
                 url: hyper::Uri::from_static("http://localhost:8083/"),
+                ..Default::default()
             },
             &Endpoint::default(),
             1000,
@@ -138,6 +140,7 @@ fn test_ddog_sidecar_register_app() {
             10000000,
             "".into(),
             "".into(),
+            &SessionOptions::default(),
         );
 
         //TODO: Shutdown the service
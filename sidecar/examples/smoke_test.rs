@@ -0,0 +1,73 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! Checks the submission of traces through the sidecar end to end: starts or connects to the
+//! sidecar, submits a synthetic span to the agent and reports the status it was answered with.
+//!
+//! Usage: `cargo run --example smoke_test [agent url]`, the agent url defaults to
+//! `http://localhost:8126`. Exits with a non-zero status if the span was not accepted.
+
+use std::process::ExitCode;
+use std::time::Duration;
+
+use datadog_sidecar::config::{self, LogMethod};
+use datadog_sidecar::service::synthetic_span::{SyntheticSpan, SyntheticSpanKind};
+use datadog_sidecar::service::{blocking, InstanceId, SessionConfig, SessionTags};
+use ddcommon::{parse_uri, Endpoint};
+
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn run(agent_url: &str) -> anyhow::Result<Option<u16>> {
+    let mut transport = datadog_sidecar::start_or_connect_to_sidecar(config::Config::get())?;
+    // Leave the sidecar time to answer once the flush timed out
+    transport.set_read_timeout(Some(FLUSH_TIMEOUT + Duration::from_secs(5)))?;
+
+    let session_id = format!("smoke-test-{}", std::process::id());
+    let endpoint = Endpoint {
+        url: parse_uri(agent_url)?,
+        ..Default::default()
+    };
+    blocking::set_session_config(
+        &mut transport,
+        session_id.clone(),
+        &SessionConfig {
+            endpoint,
+            dogstatsd_endpoint: Endpoint::default(),
+            flush_interval: Duration::from_secs(1),
+            force_flush_size: 1_000_000,
+            force_drop_size: 10_000_000,
+            log_level: String::new(),
+            log_file: LogMethod::Disabled,
+            replace_tags: String::new(),
+            tagging_rules: String::new(),
+            obfuscation_config: String::new(),
+            tags: SessionTags::default(),
+            agentless_endpoint: None,
+            agentless: false,
+        },
+    )?;
+
+    let instance_id = InstanceId::new(session_id.as_str(), "smoke-test");
+    let span = SyntheticSpan::new(SyntheticSpanKind::ProcessStart, "smoke-test".to_string());
+    blocking::send_synthetic_span_acked(&mut transport, &instance_id, span, Some(FLUSH_TIMEOUT))
+}
+
+fn main() -> ExitCode {
+    let agent_url = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "http://localhost:8126".to_string());
+    match run(&agent_url) {
+        Ok(Some(status)) => {
+            println!("The span was accepted by {agent_url} with status {status}");
+            ExitCode::SUCCESS
+        }
+        Ok(None) => {
+            eprintln!("The span was queued, but not flushed to {agent_url}");
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("Failed submitting the span to {agent_url}: {e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! Administration of a running sidecar with signals, so that it can be managed without
+//! restarting the applications using it:
+//! - SIGHUP reloads the configuration file of the sidecar, see [`Config::reload`], and reopens the
+//!   log files, e.g. after they were rotated.
+//! - SIGUSR1 dumps the state of the sidecar (sessions, queues, tasks) to a file, see
+//!   [`dump_path`].
+//! - SIGUSR2 restarts the sidecar gracefully: a new sidecar, with the current configuration, is
+//!   spawned on the same listening socket, so that no connection is refused in between. This one
+//!   stops accepting connections and shuts down like on SIGTERM.
+//! - SIGTERM shuts the sidecar down gracefully, flushing the pending data first. The applications
+//!   spawn a new sidecar when reconnecting.
+//!
+//! The sessions configure everything else, which is reloaded whenever they update it.
+
+use crate::config::Config;
+use crate::service::SidecarServer;
+use ddcommon::tasks::ShutdownSignal;
+use lazy_static::lazy_static;
+use std::io;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tokio::io::AsyncWriteExt;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+use tracing::{error, info};
+
+/// The socket the sidecar accepts connections on, handed over to the new sidecar on restart.
+static LISTENER_FD: OnceLock<RawFd> = OnceLock::new();
+
+lazy_static! {
+    /// Notified once the listening socket was handed over to a new sidecar. The connections are no
+    /// longer accepted, but the socket must not be shut down, as the new sidecar shares it.
+    pub(crate) static ref STOP_ACCEPTING: Notify = Notify::new();
+}
+
+/// Registers the socket the sidecar accepts connections on, see [`STOP_ACCEPTING`].
+pub(crate) fn set_listener_fd(fd: RawFd) {
+    _ = LISTENER_FD.set(fd);
+}
+
+/// Spawns a new sidecar on a duplicate of the listening socket.
+fn spawn_successor() -> anyhow::Result<()> {
+    let fd = *LISTENER_FD
+        .get()
+        .ok_or_else(|| anyhow::anyhow!("The listening socket is unknown"))?;
+    let listener = unsafe { UnixListener::from_raw_fd(nix::unistd::dup(fd)?) };
+    crate::entry::daemonize(listener, Config::get())
+}
+
+/// The file the state of the sidecar is dumped to, in the temporary directory.
+pub fn dump_path() -> PathBuf {
+    std::env::temp_dir().join(format!("dd-sidecar-{}.dump", std::process::id()))
+}
+
+/// Creates the dump file, readable by the user of the sidecar only. The temporary directory is
+/// shared and the path predictable, so a file or symlink planted there by someone else is never
+/// written through: it is unlinked and the file created anew, failing if it shows up again.
+async fn create_dump_file(path: &Path) -> io::Result<tokio::fs::File> {
+    let mut options = tokio::fs::OpenOptions::new();
+    options
+        .write(true)
+        .create_new(true)
+        .custom_flags(nix::libc::O_NOFOLLOW)
+        .mode(0o600);
+    match options.open(path).await {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            // Removes a symlink itself, not its target
+            tokio::fs::remove_file(path).await?;
+            options.open(path).await
+        }
+        result => result,
+    }
+}
+
+async fn dump_state(server: &SidecarServer) -> io::Result<PathBuf> {
+    let stats = simd_json::serde::to_string_pretty(&server.compute_stats().await)
+        .unwrap_or_else(|e| format!("unable to serialize stats: {e}"));
+    let contents = format!("Stats:\n{stats}\n\n{}", crate::dump::dump().await);
+    let path = dump_path();
+    let mut file = create_dump_file(&path).await?;
+    file.write_all(contents.as_bytes()).await?;
+    file.flush().await?;
+    Ok(path)
+}
+
+/// Handles the administrative signals until the sidecar shuts down. `cancel` stops accepting
+/// connections, starting the graceful shutdown.
+pub(crate) async fn handle_admin_signals(
+    server: SidecarServer,
+    cancel: impl Fn(),
+    mut shutdown: ShutdownSignal,
+) {
+    let (mut hangup, mut user_defined1, mut user_defined2, mut terminate) = match (
+        signal(SignalKind::hangup()),
+        signal(SignalKind::user_defined1()),
+        signal(SignalKind::user_defined2()),
+        signal(SignalKind::terminate()),
+    ) {
+        (Ok(hangup), Ok(user_defined1), Ok(user_defined2), Ok(terminate)) => {
+            (hangup, user_defined1, user_defined2, terminate)
+        }
+        (Err(e), ..) | (_, Err(e), ..) | (_, _, Err(e), _) | (.., Err(e)) => {
+            error!("Error setting up the administrative signal handlers: {e}");
+            return;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => {
+                info!("Received SIGHUP, reloading the configuration and reopening the log files");
+                if let Err(e) = Config::get().reload() {
+                    error!("Failed to reload the sidecar configuration: {e}");
+                }
+                #[cfg(feature = "tracing")]
+                crate::log::MULTI_LOG_WRITER.refresh();
+            }
+            _ = user_defined1.recv() => match dump_state(&server).await {
+                Ok(path) => info!("Received SIGUSR1, dumped the sidecar state to {}", path.display()),
+                Err(e) => error!("Failed to dump the sidecar state: {e}"),
+            },
+            _ = user_defined2.recv() => match spawn_successor() {
+                Ok(()) => {
+                    info!("Received SIGUSR2, handed the connections over to a new sidecar, shutting down");
+                    STOP_ACCEPTING.notify_one();
+                    break;
+                }
+                Err(e) => error!("Failed to restart the sidecar, keeping it running: {e:?}"),
+            },
+            _ = terminate.recv() => {
+                info!("Received SIGTERM, shutting down");
+                cancel();
+                break;
+            }
+            _ = shutdown.wait() => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_create_dump_file_does_not_follow_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        std::fs::write(&target, "untouched").unwrap();
+        let path = dir.path().join("sidecar.dump");
+        std::os::unix::fs::symlink(&target, &path).unwrap();
+
+        let mut file = create_dump_file(&path).await.unwrap();
+        file.write_all(b"dump").await.unwrap();
+        file.flush().await.unwrap();
+
+        assert_eq!("untouched", std::fs::read_to_string(&target).unwrap());
+        let metadata = std::fs::symlink_metadata(&path).unwrap();
+        assert!(metadata.is_file());
+        assert_eq!(0o600, metadata.permissions().mode() & 0o777);
+        assert_eq!("dump", std::fs::read_to_string(&path).unwrap());
+    }
+}
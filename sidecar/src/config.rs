@@ -3,9 +3,11 @@
 
 use http::uri::{PathAndQuery, Scheme};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, path::PathBuf, time::Duration};
+use std::sync::RwLock;
+use std::{collections::HashMap, io, path::PathBuf, time::Duration};
 
 use ddcommon::{parse_uri, Endpoint};
+use ddtelemetry::data;
 use spawn_worker::LibDependency;
 
 const ENV_SIDECAR_IPC_MODE: &str = "_DD_DEBUG_SIDECAR_IPC_MODE";
@@ -24,6 +26,13 @@ const DEFAULT_IDLE_LINGER_TIME: Duration = Duration::from_secs(60);
 
 const ENV_SIDECAR_SELF_TELEMETRY: &str = "_DD_SIDECAR_SELF_TELEMETRY";
 
+const ENV_SIDECAR_SESSION_SNAPSHOT: &str = "_DD_SIDECAR_SESSION_SNAPSHOT";
+
+const ENV_SIDECAR_CONFIG_FILE: &str = "_DD_SIDECAR_CONFIG_FILE";
+
+/// The settings read from the [`Config::config_file`], which take precedence over the environment.
+static CONFIG_FILE_SETTINGS: RwLock<Vec<(String, String)>> = RwLock::new(Vec::new());
+
 #[derive(Debug, Copy, Clone)]
 pub enum IpcMode {
     Shared,
@@ -78,6 +87,12 @@ pub struct Config {
     pub self_telemetry: bool,
     pub library_dependencies: Vec<LibDependency>,
     pub child_env: HashMap<std::ffi::OsString, std::ffi::OsString>,
+    /// Where the sessions are persisted, for a respawned sidecar to restore them. Disabled if
+    /// None.
+    pub session_snapshot: Option<PathBuf>,
+    /// A file of `NAME=VALUE` lines, with the names of the environment variables above, overriding
+    /// the environment. It is read again when the sidecar is asked to, see [`Config::reload`].
+    pub config_file: Option<PathBuf>,
 }
 
 impl Config {
@@ -86,7 +101,7 @@ impl Config {
     }
 
     pub fn to_env(&self) -> HashMap<&'static str, String> {
-        HashMap::from([
+        let mut env = HashMap::from([
             (ENV_SIDECAR_IPC_MODE, self.ipc_mode.to_string()),
             (ENV_SIDECAR_LOG_METHOD, self.log_method.to_string()),
             (
@@ -94,15 +109,81 @@ impl Config {
                 self.idle_linger_time.as_secs().to_string(),
             ),
             (ENV_SIDECAR_SELF_TELEMETRY, self.self_telemetry.to_string()),
-        ])
+        ]);
+        if let Some(path) = &self.session_snapshot {
+            env.insert(
+                ENV_SIDECAR_SESSION_SNAPSHOT,
+                path.to_string_lossy().into_owned(),
+            );
+        }
+        if let Some(path) = &self.config_file {
+            env.insert(ENV_SIDECAR_CONFIG_FILE, path.to_string_lossy().into_owned());
+        }
+        env
     }
+
+    /// Reads the [`Config::config_file`] again, if any. Its settings take precedence over the
+    /// environment from then on: the ones read whenever they are used, like the idle linger time,
+    /// apply right away, the others once the sidecar is restarted.
+    pub fn reload(&self) -> io::Result<()> {
+        let Some(path) = &self.config_file else {
+            return Ok(());
+        };
+        let settings = parse_config_file(&std::fs::read_to_string(path)?);
+        *CONFIG_FILE_SETTINGS
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = settings;
+        Ok(())
+    }
+
+    /// The effective configuration, as reported to telemetry. Settings which were not explicitly
+    /// set in the environment are reported with their default value.
+    pub fn telemetry_configuration(&self) -> Vec<data::Configuration> {
+        let mut configuration: Vec<_> = self
+            .to_env()
+            .into_iter()
+            .map(|(name, value)| data::Configuration {
+                name: name.to_string(),
+                value,
+                origin: if std::env::var_os(name).is_some() {
+                    data::ConfigurationOrigin::EnvVar
+                } else {
+                    data::ConfigurationOrigin::Default
+                },
+            })
+            .collect();
+        configuration.sort_by(|a, b| a.name.cmp(&b.name));
+        configuration
+    }
+}
+
+/// Parses the `NAME=VALUE` lines of a configuration file, skipping empty lines and `#` comments.
+fn parse_config_file(contents: &str) -> Vec<(String, String)> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect()
 }
 
 pub struct FromEnv {}
 
 impl FromEnv {
+    /// The setting from the configuration file, or else from the environment.
+    fn var(name: &str) -> Option<String> {
+        let settings = CONFIG_FILE_SETTINGS
+            .read()
+            .unwrap_or_else(|e| e.into_inner());
+        match settings.iter().rev().find(|(setting, _)| setting == name) {
+            Some((_, value)) => Some(value.clone()),
+            None => std::env::var(name).ok(),
+        }
+    }
+
     fn ipc_mode() -> IpcMode {
-        let mode = std::env::var(ENV_SIDECAR_IPC_MODE).unwrap_or_default();
+        let mode = Self::var(ENV_SIDECAR_IPC_MODE).unwrap_or_default();
 
         match mode.as_str() {
             SIDECAR_IPC_MODE_SHARED => IpcMode::Shared,
@@ -116,7 +197,7 @@ impl FromEnv {
     }
 
     pub fn log_method() -> LogMethod {
-        let method = std::env::var(ENV_SIDECAR_LOG_METHOD).unwrap_or_default();
+        let method = Self::var(ENV_SIDECAR_LOG_METHOD).unwrap_or_default();
 
         match method.as_str() {
             SIDECAR_LOG_METHOD_DISABLED => LogMethod::Disabled,
@@ -139,8 +220,8 @@ impl FromEnv {
         }
     }
 
-    fn idle_linger_time() -> Duration {
-        std::env::var(ENV_IDLE_LINGER_TIME_SECS)
+    pub fn idle_linger_time() -> Duration {
+        Self::var(ENV_IDLE_LINGER_TIME_SECS)
             .unwrap_or_default()
             .parse()
             .ok()
@@ -150,11 +231,23 @@ impl FromEnv {
 
     fn self_telemetry() -> bool {
         matches!(
-            std::env::var(ENV_SIDECAR_SELF_TELEMETRY).as_deref(),
-            Ok("true" | "1")
+            Self::var(ENV_SIDECAR_SELF_TELEMETRY).as_deref(),
+            Some("true" | "1")
         )
     }
 
+    fn session_snapshot() -> Option<PathBuf> {
+        Self::var(ENV_SIDECAR_SESSION_SNAPSHOT)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    }
+
+    fn config_file() -> Option<PathBuf> {
+        std::env::var_os(ENV_SIDECAR_CONFIG_FILE)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    }
+
     pub fn config() -> Config {
         Config {
             ipc_mode: Self::ipc_mode(),
@@ -163,12 +256,14 @@ impl FromEnv {
             self_telemetry: Self::self_telemetry(),
             library_dependencies: vec![],
             child_env: std::env::vars_os().collect(),
+            session_snapshot: Self::session_snapshot(),
+            config_file: Self::config_file(),
         }
     }
 }
 
 pub fn get_product_endpoint(subdomain: &str, endpoint: &Endpoint) -> Endpoint {
-    if let Some(ref api_key) = endpoint.api_key {
+    if endpoint.api_key.is_some() {
         let mut parts = endpoint.url.clone().into_parts();
         if parts.scheme.is_none() {
             parts.scheme = Some(Scheme::HTTPS);
@@ -181,9 +276,35 @@ pub fn get_product_endpoint(subdomain: &str, endpoint: &Endpoint) -> Endpoint {
         parts.path_and_query = Some(PathAndQuery::from_static("/"));
         Endpoint {
             url: hyper::Uri::from_parts(parts).unwrap(),
-            api_key: Some(api_key.clone()),
+            ..endpoint.clone()
         }
     } else {
         endpoint.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config_file() {
+        let settings = parse_config_file(
+            "# restart the sidecar to apply\n\
+            _DD_DEBUG_SIDECAR_IDLE_LINGER_TIME_SECS = 300\n\
+            \n\
+            _DD_DEBUG_SIDECAR_LOG_METHOD=file:///var/log/sidecar.log\n\
+            not a setting\n",
+        );
+        assert_eq!(
+            vec![
+                (ENV_IDLE_LINGER_TIME_SECS.to_string(), "300".to_string()),
+                (
+                    ENV_SIDECAR_LOG_METHOD.to_string(),
+                    "file:///var/log/sidecar.log".to_string()
+                ),
+            ],
+            settings
+        );
+    }
+}
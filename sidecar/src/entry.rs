@@ -18,37 +18,56 @@ use tokio::sync::mpsc;
 use crate::service::blocking::SidecarTransport;
 use crate::service::SidecarServer;
 use datadog_ipc::platform::AsyncChannel;
+use ddcommon::tasks::TaskSupervisor;
 
 use crate::setup::{self, IpcClient, IpcServer, Liaison};
 
 use crate::config::{self, Config};
 use crate::self_telemetry::self_telemetry;
+use crate::startup_diagnostics::{ListenOutcome, StartupDiagnostics};
 use crate::watchdog::Watchdog;
 use crate::{ddog_daemon_entry_point, setup_daemon_process};
 
+/// The tasks watching for the conditions to stop accepting connections are shut down first.
+const LISTENER_SHUTDOWN_STAGE: u32 = 0;
+/// The self telemetry is sent once the connections are closed.
+const TELEMETRY_SHUTDOWN_STAGE: u32 = 1;
+/// The traces are flushed last, so that nothing submitted before is lost.
+const TRACE_FLUSHER_SHUTDOWN_STAGE: u32 = 2;
+/// How long supervised tasks get to finish once asked to shut down.
+const TASK_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
 async fn main_loop<L, C, Fut>(listener: L, cancel: Arc<C>) -> io::Result<()>
 where
     L: FnOnce(Box<dyn Fn(IpcClient)>) -> Fut,
     Fut: Future<Output = io::Result<()>>,
     C: Fn() + Sync + Send + 'static,
 {
+    if let Err(e) = Config::get().reload() {
+        tracing::error!("Failed to read the sidecar configuration file: {e}");
+    }
+
     let counter = Arc::new(AtomicI32::new(0));
     let cloned_counter = Arc::clone(&counter);
+    let supervisor = TaskSupervisor::new();
 
-    tokio::spawn({
+    supervisor.spawn("idle-linger", LISTENER_SHUTDOWN_STAGE, {
         let cancel = cancel.clone();
-        async move {
+        move |mut shutdown| async move {
             let mut last_seen_connection_time = Instant::now();
-            let max_idle_linger_time = Config::get().idle_linger_time;
 
             loop {
-                tokio::time::sleep(Duration::from_millis(500)).await;
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_millis(500)) => {}
+                    _ = shutdown.wait() => break,
+                }
 
                 if cloned_counter.load(Ordering::Acquire) > 0 {
                     last_seen_connection_time = Instant::now();
                 }
 
-                if last_seen_connection_time.elapsed() > max_idle_linger_time {
+                // read every time, as it may be reloaded, see crate::admin
+                if last_seen_connection_time.elapsed() > config::FromEnv::idle_linger_time() {
                     cancel();
                     tracing::info!("No active connections - shutting down");
                     break;
@@ -57,19 +76,53 @@ where
         }
     });
 
-    tokio::spawn(async move {
-        if let Err(err) = tokio::signal::ctrl_c().await {
-            tracing::error!("Error setting up signal handler {}", err);
+    supervisor.spawn("ctrl-c", LISTENER_SHUTDOWN_STAGE, {
+        let cancel = cancel.clone();
+        move |mut shutdown| async move {
+            tokio::select! {
+                result = tokio::signal::ctrl_c() => {
+                    if let Err(err) = result {
+                        tracing::error!("Error setting up signal handler {}", err);
+                    }
+                    tracing::info!("Received Ctrl-C Signal, shutting down");
+                    cancel();
+                }
+                _ = shutdown.wait() => {}
+            }
         }
-        tracing::info!("Received Ctrl-C Signal, shutting down");
-        cancel();
     });
 
-    let server = SidecarServer::default();
+    let server = match Config::get().session_snapshot {
+        Some(path) => SidecarServer::with_session_snapshot(path).await,
+        None => SidecarServer::default(),
+    };
+
+    #[cfg(unix)]
+    supervisor.spawn("admin-signals", LISTENER_SHUTDOWN_STAGE, {
+        let server = server.clone();
+        move |shutdown| crate::admin::handle_admin_signals(server, move || cancel(), shutdown)
+    });
+
     let (shutdown_complete_tx, shutdown_complete_rx) = mpsc::channel::<()>(1);
 
     let watchdog_handle = Watchdog::from_receiver(shutdown_complete_rx).spawn_watchdog();
-    let telemetry_handle = self_telemetry(server.clone(), watchdog_handle);
+    let telemetry = self_telemetry(server.clone(), watchdog_handle);
+    // Ends by itself once the watchdog sees the connections closed
+    supervisor.spawn("self-telemetry", TELEMETRY_SHUTDOWN_STAGE, move |_| {
+        telemetry
+    });
+
+    supervisor.spawn("trace-flusher", TRACE_FLUSHER_SHUTDOWN_STAGE, {
+        let server = server.clone();
+        move |mut shutdown| async move {
+            shutdown.wait().await;
+            // Don't wait for unresponsive agents
+            server.trace_flusher.agent_state.shutdown();
+            if let Err(e) = server.trace_flusher.join().await {
+                tracing::error!("Error flushing the remaining traces: {e}");
+            }
+        }
+    });
 
     listener(Box::new({
         let shutdown_complete_tx = shutdown_complete_tx.clone();
@@ -96,9 +149,10 @@ where
     // Shutdown final sender so the receiver can complete
     drop(shutdown_complete_tx);
 
-    // Await everything else to completion
-    _ = telemetry_handle.await;
-    _ = server.trace_flusher.join().await;
+    let report = supervisor.shutdown(TASK_DRAIN_TIMEOUT).await;
+    for (task, message) in report.panicked {
+        tracing::error!("Sidecar task {task} panicked: {message}");
+    }
 
     Ok(())
 }
@@ -181,17 +235,34 @@ pub fn start_or_connect_to_sidecar(cfg: Config) -> anyhow::Result<SidecarTranspo
         config::IpcMode::InstancePerProcess => setup::DefaultLiason::ipc_per_process(),
     };
 
+    let mut diagnostics = StartupDiagnostics::new(&cfg, liaison.socket_path());
     let err = match liaison.attempt_listen() {
         Ok(Some(listener)) => {
-            daemonize(listener, cfg)?;
+            diagnostics.listen = ListenOutcome::Spawned;
+            if let Err(e) = daemonize(listener, cfg) {
+                diagnostics.spawn_error = Some(format!("{e:?}"));
+                diagnostics.record();
+                return Err(e);
+            }
+            None
+        }
+        Ok(None) => {
+            diagnostics.listen = ListenOutcome::AlreadyListening;
             None
         }
-        Ok(None) => None,
-        err => err.context("Error starting sidecar").err(),
+        err => {
+            let err = err.context("Error starting sidecar").err();
+            diagnostics.listen = ListenOutcome::Failed(format!("{err:?}"));
+            err
+        }
     };
 
-    Ok(liaison
+    let result = liaison
         .connect_to_server()
-        .map_err(|e| err.unwrap_or(e.into()))?
-        .into())
+        .map_err(|e| err.unwrap_or(e.into()));
+    if let Err(e) = &result {
+        diagnostics.connect_error = Some(format!("{e:?}"));
+    }
+    diagnostics.record();
+    Ok(result?.into())
 }
@@ -1,5 +1,7 @@
 // Copyright 2021-Present Datadog, Inc. https://www.datadoghq.com/
 // SPDX-License-Identifier: Apache-2.0
+#[cfg(unix)]
+pub mod admin;
 pub mod agent_remote_config;
 pub mod config;
 pub mod dogstatsd;
@@ -10,6 +12,7 @@ pub mod log;
 pub mod one_way_shared_memory;
 mod self_telemetry;
 pub mod setup;
+pub mod startup_diagnostics;
 mod tracer;
 mod watchdog;
 
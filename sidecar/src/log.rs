@@ -105,6 +105,13 @@ where
         TemporarilyRetainedMapGuard { key, map: self }
     }
 
+    /// Recomputes all the values, e.g. to reopen the log files after they were rotated.
+    pub fn refresh(&self) {
+        for (key, value) in self.maps.write().unwrap().iter_mut() {
+            *value = key.parse();
+        }
+    }
+
     pub fn stats(&self) -> TemporarilyRetainedMapStats {
         TemporarilyRetainedMapStats {
             elements: self.maps.read().unwrap().len() as u32,
@@ -453,6 +460,16 @@ mod tests {
         assert_eq!(2, ENABLED.load(Ordering::SeqCst));
     }
 
+    #[test]
+    fn test_refresh_temporarily_retained_map() {
+        let map = TemporarilyRetainedMap::<_, i32>::default();
+        let _guard = map.add("3".to_string());
+        map.maps.write().unwrap().insert("3".to_string(), 0);
+
+        map.refresh();
+        assert_eq!(3, *map.maps.read().unwrap().get("3").unwrap());
+    }
+
     #[test]
     fn test_logs_created_counter() {
         enable_logging().ok();
@@ -12,10 +12,10 @@ use ddtelemetry::worker::{
     LifecycleAction, TelemetryActions, TelemetryWorkerBuilder, TelemetryWorkerHandle,
 };
 use manual_future::ManualFuture;
+use std::future::Future;
 use std::sync::atomic::Ordering;
 use std::time::Duration;
 use tokio::select;
-use tokio::task::JoinHandle;
 
 struct MetricData<'a> {
     worker: &'a TelemetryWorkerHandle,
@@ -31,6 +31,8 @@ struct MetricData<'a> {
     trace_api_bytes: ContextKey,
     trace_chunks_sent: ContextKey,
     trace_chunks_dropped: ContextKey,
+    normalizer_spans_malformed: ContextKey,
+    normalizer_traces_dropped: ContextKey,
 }
 impl<'a> MetricData<'a> {
     async fn send(&self, key: ContextKey, value: f64, tags: Vec<Tag>) {
@@ -127,6 +129,30 @@ impl<'a> MetricData<'a> {
                 vec![tag!("src_library", "libdatadog")],
             ));
         }
+        for (reason, count) in trace_metrics.normalization.spans_malformed() {
+            if count > 0 {
+                futures.push(self.send(
+                    self.normalizer_spans_malformed,
+                    count as f64,
+                    vec![
+                        Tag::new("reason", reason).unwrap(),
+                        tag!("src_library", "libdatadog"),
+                    ],
+                ));
+            }
+        }
+        for (reason, count) in trace_metrics.normalization.traces_dropped() {
+            if count > 0 {
+                futures.push(self.send(
+                    self.normalizer_traces_dropped,
+                    count as f64,
+                    vec![
+                        Tag::new("reason", reason).unwrap(),
+                        tag!("src_library", "libdatadog"),
+                    ],
+                ));
+            }
+        }
         for (status_code, count) in &trace_metrics.api_responses_count_per_code {
             futures.push(self.send(
                 self.trace_api_responses,
@@ -142,21 +168,27 @@ impl<'a> MetricData<'a> {
     }
 }
 
-pub fn self_telemetry(server: SidecarServer, watchdog_handle: WatchdogHandle) -> JoinHandle<()> {
-    if !Config::get().self_telemetry {
-        return tokio::spawn(async move {
-            watchdog_handle.wait_for_shutdown().await;
-        });
-    }
+/// The self telemetry of the sidecar, until the watchdog shuts it down. The configuration is
+/// registered right away, before the returned future is first polled.
+pub fn self_telemetry(
+    server: SidecarServer,
+    watchdog_handle: WatchdogHandle,
+) -> impl Future<Output = ()> + Send {
+    let future = Config::get().self_telemetry.then(|| {
+        let (future, completer) = ManualFuture::new();
+        server
+            .self_telemetry_config
+            .lock()
+            .unwrap()
+            .replace(completer);
+        future
+    });
 
-    let (future, completer) = ManualFuture::new();
-    server
-        .self_telemetry_config
-        .lock()
-        .unwrap()
-        .replace(completer);
-
-    tokio::spawn(async move {
+    async move {
+        let Some(future) = future else {
+            watchdog_handle.wait_for_shutdown().await;
+            return;
+        };
         let submission_interval = tokio::time::interval(Duration::from_secs(60));
 
         select! {
@@ -166,7 +198,7 @@ pub fn self_telemetry(server: SidecarServer, watchdog_handle: WatchdogHandle) ->
                 worker_cfg.spawn_worker().await
             },
         }
-    })
+    }
 }
 
 pub struct SelfTelemetry {
@@ -272,11 +304,31 @@ impl SelfTelemetry {
                 true,
                 MetricNamespace::Tracers,
             ),
+            normalizer_spans_malformed: worker.register_metric_context(
+                "normalizer.spans_malformed".to_string(),
+                vec![],
+                MetricType::Count,
+                true,
+                MetricNamespace::Tracers,
+            ),
+            normalizer_traces_dropped: worker.register_metric_context(
+                "normalizer.traces_dropped".to_string(),
+                vec![],
+                MetricType::Count,
+                true,
+                MetricNamespace::Tracers,
+            ),
         };
 
         let _ = worker
             .send_msg(TelemetryActions::Lifecycle(LifecycleAction::Start))
             .await;
+        // sent as app-client-configuration-change with the next flush
+        for configuration in Config::get().telemetry_configuration() {
+            let _ = worker
+                .send_msg(TelemetryActions::AddConfig(configuration))
+                .await;
+        }
         loop {
             select! {
                 _ = self.submission_interval.tick() => {
@@ -0,0 +1,361 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use ddcommon::connector::Connector;
+use ddcommon::Endpoint;
+use http::uri::PathAndQuery;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+const DEFAULT_AGENT_STATE_TTL: Duration = Duration::from_secs(60);
+const DEFAULT_FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sample rates per service, as returned by the agent in the trace submission responses.
+pub type RatesByService = HashMap<String, f64>;
+
+struct Cached<T> {
+    value: Arc<T>,
+    updated: Instant,
+}
+
+impl<T> Cached<T> {
+    fn new(value: T) -> Self {
+        Cached {
+            value: Arc::new(value),
+            updated: Instant::now(),
+        }
+    }
+
+    fn get(&self, ttl: Duration) -> Option<Arc<T>> {
+        if self.updated.elapsed() < ttl {
+            Some(self.value.clone())
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Default)]
+struct AgentState {
+    info: Option<Cached<serde_json::Value>>,
+    rates_by_service: Option<Cached<RatesByService>>,
+}
+
+impl AgentState {
+    fn is_expired(&self, ttl: Duration) -> bool {
+        let expired = |updated: Option<Instant>| updated.map_or(true, |u| u.elapsed() >= ttl);
+        expired(self.info.as_ref().map(|c| c.updated))
+            && expired(self.rates_by_service.as_ref().map(|c| c.updated))
+    }
+}
+
+#[derive(Deserialize)]
+struct TraceResponse {
+    rate_by_service: RatesByService,
+}
+
+/// `AgentStateCache` holds the state learned from an agent (its /info response and the latest
+/// rates by service) so that it can be shared between all sessions talking to the same agent.
+/// This way new runtimes start with a warm state instead of each issuing their own requests.
+///
+/// Entries are keyed by the agent (scheme and authority of the endpoint) and are considered stale
+/// once they are older than the configured TTL.
+pub struct AgentStateCache {
+    ttl: Duration,
+    /// How long a request to the agent may take, connection included.
+    fetch_timeout: Duration,
+    /// Aborts the requests in flight on shutdown, see [`AgentStateCache::shutdown`].
+    shutdown: CancellationToken,
+    agents: Mutex<HashMap<String, AgentState>>,
+}
+
+impl Default for AgentStateCache {
+    fn default() -> Self {
+        AgentStateCache::new(DEFAULT_AGENT_STATE_TTL)
+    }
+}
+
+fn agent_key(endpoint: &Endpoint) -> String {
+    format!(
+        "{}://{}",
+        endpoint.url.scheme_str().unwrap_or_default(),
+        endpoint
+            .url
+            .authority()
+            .map(|a| a.as_str())
+            .unwrap_or_default()
+    )
+}
+
+impl AgentStateCache {
+    pub fn new(ttl: Duration) -> Self {
+        AgentStateCache {
+            ttl,
+            fetch_timeout: DEFAULT_FETCH_TIMEOUT,
+            shutdown: CancellationToken::new(),
+            agents: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_fetch_timeout(self, fetch_timeout: Duration) -> Self {
+        AgentStateCache {
+            fetch_timeout,
+            ..self
+        }
+    }
+
+    /// Aborts the requests to the agents in flight, and fails the later ones, so that the sidecar
+    /// doesn't wait for unresponsive agents when shutting down. The cached state remains readable.
+    pub fn shutdown(&self) {
+        self.shutdown.cancel();
+    }
+
+    fn modify<F: FnOnce(&mut AgentState)>(&self, endpoint: &Endpoint, f: F) {
+        let mut agents = self.agents.lock().unwrap();
+        f(agents.entry(agent_key(endpoint)).or_default());
+        let ttl = self.ttl;
+        agents.retain(|_, state| !state.is_expired(ttl));
+    }
+
+    /// Returns the cached /info response of the agent, unless missing or stale.
+    pub fn get_info(&self, endpoint: &Endpoint) -> Option<Arc<serde_json::Value>> {
+        let agents = self.agents.lock().unwrap();
+        agents
+            .get(&agent_key(endpoint))?
+            .info
+            .as_ref()?
+            .get(self.ttl)
+    }
+
+    pub fn set_info(&self, endpoint: &Endpoint, info: serde_json::Value) {
+        self.modify(endpoint, |state| state.info = Some(Cached::new(info)));
+    }
+
+    /// Returns the last rates by service received from the agent, unless missing or stale.
+    pub fn get_rates_by_service(&self, endpoint: &Endpoint) -> Option<Arc<RatesByService>> {
+        let agents = self.agents.lock().unwrap();
+        agents
+            .get(&agent_key(endpoint))?
+            .rates_by_service
+            .as_ref()?
+            .get(self.ttl)
+    }
+
+    pub fn set_rates_by_service(&self, endpoint: &Endpoint, rates: RatesByService) {
+        self.modify(endpoint, |state| {
+            state.rates_by_service = Some(Cached::new(rates))
+        });
+    }
+
+    /// Returns the cached state of the agent as JSON, with its /info response as `info` and its
+    /// rates by service as `rate_by_service`, each null if missing or stale.
+    pub fn to_json(&self, endpoint: &Endpoint) -> String {
+        let info = self.get_info(endpoint);
+        let rates = self.get_rates_by_service(endpoint);
+        serde_json::json!({
+            "info": info.as_deref(),
+            "rate_by_service": rates.as_deref(),
+        })
+        .to_string()
+    }
+
+    /// Records the rates by service contained in the body of an agent trace submission response.
+    /// Bodies without rates are ignored.
+    pub fn update_from_trace_response(&self, endpoint: &Endpoint, body: &[u8]) {
+        match serde_json::from_slice::<TraceResponse>(body) {
+            Ok(response) => self.set_rates_by_service(endpoint, response.rate_by_service),
+            Err(e) => debug!("No rates by service in agent response: {e}"),
+        }
+    }
+
+    /// Returns the /info response of the agent, requesting it only if it is not cached yet. The
+    /// request fails after the fetch timeout, or when the cache is shut down.
+    pub async fn fetch_info(&self, endpoint: &Endpoint) -> anyhow::Result<Arc<serde_json::Value>> {
+        if let Some(info) = self.get_info(endpoint) {
+            return Ok(info);
+        }
+
+        // Dropping the request on timeout or shutdown is fine: the agents lock is never held
+        // across an await, only the cache is updated once the response is complete.
+        let info = tokio::select! {
+            info = tokio::time::timeout(self.fetch_timeout, Self::request_info(endpoint)) => {
+                info.map_err(|_| {
+                    anyhow::anyhow!(
+                        "Agent /info request timed out after {:?}",
+                        self.fetch_timeout
+                    )
+                })??
+            }
+            _ = self.shutdown.cancelled() => anyhow::bail!("Agent /info request aborted by shutdown"),
+        };
+
+        self.set_info(endpoint, info);
+        self.get_info(endpoint)
+            .ok_or_else(|| anyhow::anyhow!("Agent info expired immediately"))
+    }
+
+    async fn request_info(endpoint: &Endpoint) -> anyhow::Result<serde_json::Value> {
+        let mut parts = endpoint.url.clone().into_parts();
+        parts.path_and_query = Some(PathAndQuery::from_static("/info"));
+        let info_endpoint = Endpoint {
+            url: hyper::Uri::from_parts(parts)?,
+            ..endpoint.clone()
+        };
+        let req = info_endpoint
+            .into_request_builder(concat!("Sidecar/", env!("CARGO_PKG_VERSION")))?
+            .method(http::Method::GET)
+            .body(hyper::Body::empty())?;
+
+        let client = hyper::Client::builder().build::<_, hyper::Body>(Connector::default());
+        let response = client.request(req).await?;
+        if !response.status().is_success() {
+            anyhow::bail!(
+                "Agent /info request failed with status {}",
+                response.status()
+            );
+        }
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Fetches the agent /info in the background, so that it is readily available to sessions.
+    pub fn prefetch_info(self: &Arc<Self>, endpoint: Endpoint) {
+        if endpoint.api_key.is_some()
+            || self.get_info(&endpoint).is_some()
+            || self.shutdown.is_cancelled()
+        {
+            // agentless, already warm or shutting down
+            return;
+        }
+        let cache = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = cache.fetch_info(&endpoint).await {
+                warn!("Failed fetching agent info from {}: {e:?}", endpoint.url);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httpmock::MockServer;
+
+    fn endpoint(url: &str) -> Endpoint {
+        Endpoint {
+            url: url.parse().unwrap(),
+            api_key: None,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_shared_between_agent_paths() {
+        let cache = AgentStateCache::default();
+        let traces = endpoint("http://localhost:8126/v0.4/traces");
+        let other_agent = endpoint("http://localhost:8127/v0.4/traces");
+
+        cache.update_from_trace_response(
+            &traces,
+            br#"{"rate_by_service":{"service:,env:":1,"service:foo,env:prod":0.5}}"#,
+        );
+        let rates = cache
+            .get_rates_by_service(&endpoint("http://localhost:8126/"))
+            .unwrap();
+        assert_eq!(Some(&0.5), rates.get("service:foo,env:prod"));
+        assert!(cache.get_rates_by_service(&other_agent).is_none());
+
+        // responses without rates keep the previous ones
+        cache.update_from_trace_response(&traces, br#"{"status":"ok"}"#);
+        assert!(cache.get_rates_by_service(&traces).is_some());
+    }
+
+    #[test]
+    fn test_to_json() {
+        let cache = AgentStateCache::default();
+        let agent = endpoint("http://localhost:8126/");
+        assert_eq!(
+            r#"{"info":null,"rate_by_service":null}"#,
+            cache.to_json(&agent)
+        );
+
+        cache.set_info(&agent, serde_json::json!({"version": "7.50.0"}));
+        cache.update_from_trace_response(&agent, br#"{"rate_by_service":{"service:,env:":1.0}}"#);
+        assert_eq!(
+            r#"{"info":{"version":"7.50.0"},"rate_by_service":{"service:,env:":1.0}}"#,
+            cache.to_json(&agent)
+        );
+    }
+
+    #[test]
+    fn test_expiry() {
+        let cache = AgentStateCache::new(Duration::from_secs(0));
+        let agent = endpoint("http://localhost:8126/");
+        cache.set_info(&agent, serde_json::json!({"version": "7.50.0"}));
+        assert!(cache.get_info(&agent).is_none());
+        assert!(cache.agents.lock().unwrap().is_empty());
+    }
+
+    #[cfg_attr(miri, ignore)]
+    #[tokio::test]
+    async fn test_fetch_info_is_cached() {
+        let server = MockServer::start();
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::GET).path("/info");
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .body(r#"{"version":"7.50.0","endpoints":["/v0.4/traces"]}"#);
+            })
+            .await;
+
+        let cache = AgentStateCache::default();
+        let agent = endpoint(&server.url("/v0.4/traces"));
+        let info = cache.fetch_info(&agent).await.unwrap();
+        assert_eq!("7.50.0", info["version"]);
+
+        let info = cache.fetch_info(&agent).await.unwrap();
+        assert_eq!("7.50.0", info["version"]);
+        mock.assert_hits_async(1).await;
+    }
+
+    #[cfg_attr(miri, ignore)]
+    #[tokio::test]
+    async fn test_fetch_info_deadline_and_shutdown() {
+        let server = MockServer::start();
+        server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::GET).path("/info");
+                then.status(200)
+                    .delay(Duration::from_secs(10))
+                    .body(r#"{"version":"7.50.0"}"#);
+            })
+            .await;
+        let agent = endpoint(&server.url("/"));
+
+        let cache = AgentStateCache::default().with_fetch_timeout(Duration::from_millis(50));
+        let err = cache.fetch_info(&agent).await.unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{err}");
+
+        let cache = Arc::new(AgentStateCache::default());
+        let fetch = tokio::spawn({
+            let cache = cache.clone();
+            async move { cache.fetch_info(&agent).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let start = Instant::now();
+        cache.shutdown();
+        let err = fetch.await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("shutdown"), "{err}");
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // the cache remains usable
+        let agent = endpoint(&server.url("/"));
+        cache.set_info(&agent, serde_json::json!({"version": "7.50.0"}));
+        assert!(cache.fetch_info(&agent).await.is_ok());
+    }
+}
@@ -1,13 +1,19 @@
 // Copyright 2021-Present Datadog, Inc. https://www.datadoghq.com/
 // SPDX-License-Identifier: Apache-2.0
 
+use super::manual_span::{SpanFinish, SpanStart};
+use super::profile_upload::{self, ProfileExporterConfig, ProfileUpload};
+use super::proxy_upload::ProxyUpload;
+use super::synthetic_span::SyntheticSpan;
 use super::{
-    InstanceId, QueueId, RuntimeMetadata, SerializedTracerHeaderTags, SessionConfig, SidecarAction,
-    SidecarInterfaceRequest, SidecarInterfaceResponse,
+    FlushAllResult, InstanceId, QueueId, RuntimeMetadata, SerializedTracerHeaderTags,
+    SessionConfig, SessionTagsUpdate, SidecarAction, SidecarInterfaceRequest,
+    SidecarInterfaceResponse,
 };
 use crate::dogstatsd::DogStatsDAction;
 use datadog_ipc::platform::{Channel, ShmHandle};
 use datadog_ipc::transport::blocking::BlockingTransport;
+use datadog_profiling::internal::EncodedProfile;
 use std::sync::Mutex;
 use std::{
     borrow::Cow,
@@ -208,6 +214,25 @@ pub fn set_session_config(
     })
 }
 
+/// Updates the tags of a session, without reconfiguring it.
+///
+/// # Arguments
+///
+/// * `transport` - The transport used for communication.
+/// * `session_id` - The ID of the session.
+/// * `tags` - The kinds of tags to replace, the others are kept.
+///
+/// # Returns
+///
+/// An `io::Result<()>` indicating the result of the operation.
+pub fn update_session_tags(
+    transport: &mut SidecarTransport,
+    session_id: String,
+    tags: SessionTagsUpdate,
+) -> io::Result<()> {
+    transport.send(SidecarInterfaceRequest::UpdateSessionTags { session_id, tags })
+}
+
 /// Sends a trace as bytes.
 ///
 /// # Arguments
@@ -228,7 +253,7 @@ pub fn send_trace_v04_bytes(
 ) -> io::Result<()> {
     transport.send(SidecarInterfaceRequest::SendTraceV04Bytes {
         instance_id: instance_id.clone(),
-        data,
+        data: data.into(),
         headers,
     })
 }
@@ -261,6 +286,277 @@ pub fn send_trace_v04_shm(
     })
 }
 
+/// Sends a synthetic span, see [`SyntheticSpan`].
+///
+/// # Arguments
+///
+/// * `transport` - The transport used for communication.
+/// * `instance_id` - The ID of the instance.
+/// * `span` - The span to submit.
+///
+/// # Returns
+///
+/// An `io::Result<()>` indicating the result of the operation.
+pub fn send_synthetic_span(
+    transport: &mut SidecarTransport,
+    instance_id: &InstanceId,
+    span: SyntheticSpan,
+) -> io::Result<()> {
+    transport.send(SidecarInterfaceRequest::SendSyntheticSpan {
+        instance_id: instance_id.clone(),
+        span,
+    })
+}
+
+/// Submits a synthetic span like [`send_synthetic_span`], waiting for the sidecar to accept it.
+/// The read timeout of the transport must be longer than `flush_timeout`.
+///
+/// # Arguments
+///
+/// * `transport` - The transport used for communication.
+/// * `instance_id` - The ID of the instance.
+/// * `span` - The span to submit.
+/// * `flush_timeout` - If set, also waits for the traces to be flushed, for this long at most.
+///
+/// # Returns
+///
+/// The HTTP status the flushed traces were answered with, if they were flushed. Failures reported
+/// by the sidecar, like a full queue or a rejected payload, are a [`super::SidecarError`] which
+/// can be retrieved with `anyhow::Error::downcast_ref`.
+pub fn send_synthetic_span_acked(
+    transport: &mut SidecarTransport,
+    instance_id: &InstanceId,
+    span: SyntheticSpan,
+    flush_timeout: Option<Duration>,
+) -> anyhow::Result<Option<u16>> {
+    let res = transport.call(SidecarInterfaceRequest::SendSyntheticSpanAcked {
+        instance_id: instance_id.clone(),
+        span,
+        flush_timeout,
+    })?;
+    match res {
+        SidecarInterfaceResponse::SendSyntheticSpanAcked(result) => Ok(result?),
+        _ => anyhow::bail!("Unexpected response to send_synthetic_span_acked"),
+    }
+}
+
+/// Starts a span, see [`SpanStart`].
+///
+/// # Arguments
+///
+/// * `transport` - The transport used for communication.
+/// * `instance_id` - The ID of the instance.
+/// * `queue_id` - The unique identifier of the client, which the span ids are scoped to.
+/// * `span` - The span to start.
+///
+/// # Returns
+///
+/// An `io::Result<()>` indicating the result of the operation.
+pub fn start_span(
+    transport: &mut SidecarTransport,
+    instance_id: &InstanceId,
+    queue_id: &QueueId,
+    span: SpanStart,
+) -> io::Result<()> {
+    transport.send(SidecarInterfaceRequest::StartSpan {
+        instance_id: instance_id.clone(),
+        queue_id: *queue_id,
+        span,
+    })
+}
+
+/// Finishes a span started with [`start_span`].
+///
+/// # Arguments
+///
+/// * `transport` - The transport used for communication.
+/// * `instance_id` - The ID of the instance.
+/// * `queue_id` - The unique identifier of the client the span was started by.
+/// * `span` - The span to finish, and the tags to add to it.
+///
+/// # Returns
+///
+/// An `io::Result<()>` indicating the result of the operation.
+pub fn finish_span(
+    transport: &mut SidecarTransport,
+    instance_id: &InstanceId,
+    queue_id: &QueueId,
+    span: SpanFinish,
+) -> io::Result<()> {
+    transport.send(SidecarInterfaceRequest::FinishSpan {
+        instance_id: instance_id.clone(),
+        queue_id: *queue_id,
+        span,
+    })
+}
+
+/// A span finished by the exit hook registered by [`finish_span_at_exit`].
+struct ExitSpan {
+    transport: Box<SidecarTransport>,
+    instance_id: InstanceId,
+    queue_id: QueueId,
+    span_id: u64,
+}
+
+static EXIT_SPANS: Mutex<Vec<ExitSpan>> = Mutex::new(Vec::new());
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+extern "C" {
+    // glibc only, unlike atexit it passes the exit status along
+    fn on_exit(
+        function: extern "C" fn(libc::c_int, *mut libc::c_void),
+        arg: *mut libc::c_void,
+    ) -> libc::c_int;
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+extern "C" fn finish_exit_spans_with_status(status: libc::c_int, _: *mut libc::c_void) {
+    finish_exit_spans(Some(status));
+}
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+extern "C" fn finish_exit_spans_without_status() {
+    finish_exit_spans(None);
+}
+
+fn register_exit_hook() -> io::Result<()> {
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    let registered = unsafe { on_exit(finish_exit_spans_with_status, std::ptr::null_mut()) };
+    #[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+    let registered = unsafe { libc::atexit(finish_exit_spans_without_status) };
+    if registered != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "failed registering the exit hook",
+        ));
+    }
+    Ok(())
+}
+
+fn finish_exit_spans(status: Option<libc::c_int>) {
+    let spans = match EXIT_SPANS.lock() {
+        Ok(mut spans) => std::mem::take(&mut *spans),
+        Err(_) => return,
+    };
+    for mut span in spans {
+        let mut finish = SpanFinish {
+            span_id: span.span_id,
+            error: status.map_or(false, |status| status != 0),
+            ..Default::default()
+        };
+        if let Some(status) = status {
+            finish
+                .metrics
+                .insert("process.exit_code".to_string(), status.into());
+        }
+        // Nobody is left to report a failure to
+        let _ = finish_span(
+            &mut span.transport,
+            &span.instance_id,
+            &span.queue_id,
+            finish,
+        );
+    }
+}
+
+/// Finishes the span `span_id`, started with [`start_span`], when the process exits, e.g. the
+/// root span of a command run by a shell wrapper or a preload entry point. Its duration runs
+/// until the exit. On glibc the exit code is added as the `process.exit_code` metric and a
+/// non-zero exit code flags the span as an error, other libcs don't pass it to exit hooks.
+///
+/// The hook runs when `exit` is called or `main` returns, not when the process is killed by a
+/// signal or calls `_exit`.
+///
+/// # Arguments
+///
+/// * `transport` - The transport the span is finished over, owned by the exit hook.
+/// * `instance_id` - The ID of the instance.
+/// * `queue_id` - The unique identifier of the client the span was started by.
+/// * `span_id` - The span to finish.
+///
+/// # Returns
+///
+/// An `io::Result<()>` indicating whether the exit hook could be registered.
+pub fn finish_span_at_exit(
+    transport: Box<SidecarTransport>,
+    instance_id: InstanceId,
+    queue_id: QueueId,
+    span_id: u64,
+) -> io::Result<()> {
+    let mut spans = EXIT_SPANS
+        .lock()
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "exit spans lock poisoned"))?;
+    if spans.is_empty() {
+        register_exit_hook()?;
+    }
+    spans.push(ExitSpan {
+        transport,
+        instance_id,
+        queue_id,
+        span_id,
+    });
+    Ok(())
+}
+
+/// Uploads an encoded profile through the sidecar. The pprof is moved into shared memory, whose
+/// file descriptor is passed to the sidecar instead of copying the bytes into the message.
+///
+/// # Arguments
+///
+/// * `transport` - The transport used for communication.
+/// * `instance_id` - The ID of the instance.
+/// * `exporter` - The configuration of the exporter uploading the profile.
+/// * `profile` - The encoded profile, whose buffer is freed once copied into the shared memory.
+///
+/// # Returns
+///
+/// An `anyhow::Result<()>` indicating the result of the operation.
+pub fn send_profile(
+    transport: &mut SidecarTransport,
+    instance_id: &InstanceId,
+    exporter: ProfileExporterConfig,
+    profile: EncodedProfile,
+) -> anyhow::Result<()> {
+    let (upload, pprof) = ProfileUpload::new(exporter, profile);
+    let (handle, len) = profile_upload::pprof_into_shm(pprof)?;
+    transport.send(SidecarInterfaceRequest::SendProfileShm {
+        instance_id: instance_id.clone(),
+        handle,
+        len,
+        upload,
+    })?;
+    Ok(())
+}
+
+/// Forwards a payload to an intake with the endpoint and credentials of the session, see
+/// [`ProxyUpload`].
+///
+/// # Arguments
+///
+/// * `transport` - The transport used for communication.
+/// * `instance_id` - The ID of the instance.
+/// * `handle` - The handle to the shared memory holding the body.
+/// * `len` - The size of the body in the shared memory.
+/// * `upload` - The intake and the headers of the payload.
+///
+/// # Returns
+///
+/// An `io::Result<()>` indicating the result of the operation.
+pub fn proxy_upload(
+    transport: &mut SidecarTransport,
+    instance_id: &InstanceId,
+    handle: ShmHandle,
+    len: usize,
+    upload: ProxyUpload,
+) -> io::Result<()> {
+    transport.send(SidecarInterfaceRequest::ProxyUpload {
+        instance_id: instance_id.clone(),
+        handle,
+        len,
+        upload,
+    })
+}
+
 /// Sends DogStatsD actions.
 ///
 /// # Arguments
@@ -333,6 +629,79 @@ pub fn flush_traces(transport: &mut SidecarTransport) -> io::Result<()> {
     Ok(())
 }
 
+/// Flushes the traces, and the telemetry and profiles of the instance, waiting for the data to be
+/// sent. The read timeout of the transport must be longer than `timeout`.
+///
+/// # Arguments
+///
+/// * `transport` - The transport used for communication.
+/// * `instance_id` - The ID of the instance.
+/// * `timeout` - How long the sidecar waits at most.
+///
+/// # Returns
+///
+/// The result of sending the traces, the telemetry and the profiles, for each destination.
+/// Failures reported by the sidecar, like a timeout, are a [`super::SidecarError`] which can be
+/// retrieved with `anyhow::Error::downcast_ref`.
+pub fn flush_all(
+    transport: &mut SidecarTransport,
+    instance_id: &InstanceId,
+    timeout: Duration,
+) -> anyhow::Result<FlushAllResult> {
+    let res = transport.call(SidecarInterfaceRequest::FlushAll {
+        instance_id: instance_id.clone(),
+        timeout,
+    })?;
+    match res {
+        SidecarInterfaceResponse::FlushAll(result) => Ok(result?),
+        _ => anyhow::bail!("Unexpected response to flush_all"),
+    }
+}
+
+/// Takes the endpoints of the trace chunks sent by the instance so far.
+///
+/// # Arguments
+///
+/// * `transport` - The transport used for communication.
+/// * `instance_id` - The ID of the instance.
+///
+/// # Returns
+///
+/// An `io::Result` holding the local root span ids and their endpoints, oldest first.
+pub fn take_trace_endpoints(
+    transport: &mut SidecarTransport,
+    instance_id: &InstanceId,
+) -> io::Result<Vec<(u64, String)>> {
+    let res = transport.call(SidecarInterfaceRequest::TakeTraceEndpoints {
+        instance_id: instance_id.clone(),
+    })?;
+    if let SidecarInterfaceResponse::TakeTraceEndpoints(endpoints) = res {
+        Ok(endpoints)
+    } else {
+        Ok(Vec::default())
+    }
+}
+
+/// Retrieves the agent /info response and rates by service the sidecar cached for the agent of
+/// the session.
+///
+/// # Arguments
+///
+/// * `transport` - The transport used for communication.
+/// * `session_id` - The ID of the session.
+///
+/// # Returns
+///
+/// An `io::Result<String>` holding the agent state as JSON.
+pub fn get_agent_state(transport: &mut SidecarTransport, session_id: String) -> io::Result<String> {
+    let res = transport.call(SidecarInterfaceRequest::GetAgentState { session_id })?;
+    if let SidecarInterfaceResponse::GetAgentState(state) = res {
+        Ok(state)
+    } else {
+        Ok(String::default())
+    }
+}
+
 /// Sends a ping to the service.
 ///
 /// # Arguments
@@ -0,0 +1,99 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::service::InstanceId;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+/// The failures the sidecar reports back over IPC, so that clients can tell them apart without
+/// parsing messages.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SidecarError {
+    /// The data was dropped, as the sidecar already holds too much data waiting to be sent.
+    QueueFull,
+    /// The session of the instance is not known to the sidecar, e.g. because it was shut down.
+    UnknownInstance(InstanceId),
+    /// Sending data to the intake failed. The status is missing if no response was received.
+    UploadFailed {
+        status: Option<u16>,
+        message: String,
+    },
+    /// The component handling the request is shutting down.
+    ShuttingDown,
+    /// The operation did not complete in time.
+    TimedOut(Duration),
+}
+
+impl fmt::Display for SidecarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SidecarError::QueueFull => write!(f, "the sidecar queue is full"),
+            SidecarError::UnknownInstance(instance_id) => write!(
+                f,
+                "unknown instance {} of session {}",
+                instance_id.runtime_id, instance_id.session_id
+            ),
+            SidecarError::UploadFailed {
+                status: Some(status),
+                message,
+            } => write!(f, "upload failed with status {status}: {message}"),
+            SidecarError::UploadFailed {
+                status: None,
+                message,
+            } => write!(f, "upload failed: {message}"),
+            SidecarError::ShuttingDown => write!(f, "the sidecar is shutting down"),
+            SidecarError::TimedOut(timeout) => write!(f, "timed out after {timeout:?}"),
+        }
+    }
+}
+
+impl std::error::Error for SidecarError {}
+
+impl From<anyhow::Error> for SidecarError {
+    /// Failures which are not already a `SidecarError` are considered failed uploads.
+    fn from(error: anyhow::Error) -> Self {
+        error
+            .downcast()
+            .unwrap_or_else(|error| SidecarError::UploadFailed {
+                status: None,
+                message: format!("{error:#}"),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_anyhow() {
+        let error = anyhow::Error::from(SidecarError::UploadFailed {
+            status: Some(413),
+            message: "Payload Too Large".to_string(),
+        });
+        assert_eq!(
+            SidecarError::UploadFailed {
+                status: Some(413),
+                message: "Payload Too Large".to_string(),
+            },
+            SidecarError::from(error)
+        );
+
+        let error = anyhow::anyhow!("connection refused").context("sending profile");
+        assert_eq!(
+            SidecarError::UploadFailed {
+                status: None,
+                message: "sending profile: connection refused".to_string(),
+            },
+            SidecarError::from(error)
+        );
+    }
+
+    #[test]
+    fn test_serialization_roundtrip() {
+        let error = SidecarError::UnknownInstance(InstanceId::new("session", "runtime"));
+        let serialized = bincode::serialize(&error).unwrap();
+        assert_eq!(error, bincode::deserialize(&serialized).unwrap());
+    }
+}
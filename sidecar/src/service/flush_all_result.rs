@@ -0,0 +1,50 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::service::SidecarError;
+use serde::{Deserialize, Serialize};
+
+/// What `flush_all` sent, for each destination separately, so that the failure to send one kind of
+/// data doesn't hide whether the others were sent.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlushAllResult {
+    /// The status each trace endpoint answered with, by endpoint url.
+    pub traces: Vec<(String, Result<u16, SidecarError>)>,
+    /// The telemetry of each app of the instance, by service and env name.
+    pub telemetry: Vec<((String, String), Result<(), SidecarError>)>,
+    /// The profiles and proxied payloads which were still being uploaded, in submission order.
+    pub uploads: Vec<Result<(), SidecarError>>,
+}
+
+impl FlushAllResult {
+    /// The first failure, looking at the traces, then the telemetry, then the uploads.
+    pub fn first_error(&self) -> Option<&SidecarError> {
+        let traces = self.traces.iter().map(|(_, result)| result.as_ref().err());
+        let telemetry = self
+            .telemetry
+            .iter()
+            .map(|(_, result)| result.as_ref().err());
+        let uploads = self.uploads.iter().map(|result| result.as_ref().err());
+        traces.chain(telemetry).chain(uploads).flatten().next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_error() {
+        let mut result = FlushAllResult {
+            traces: vec![("http://localhost:8126/v0.4/traces".to_string(), Ok(200))],
+            telemetry: vec![(("service".to_string(), "prod".to_string()), Ok(()))],
+            uploads: vec![Ok(()), Err(SidecarError::QueueFull)],
+        };
+        assert_eq!(Some(&SidecarError::QueueFull), result.first_error());
+
+        result.telemetry[0].1 = Err(SidecarError::ShuttingDown);
+        assert_eq!(Some(&SidecarError::ShuttingDown), result.first_error());
+
+        assert_eq!(None, FlushAllResult::default().first_error());
+    }
+}
@@ -0,0 +1,224 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! Spans started and finished one call at a time by thin clients which don't embed a tracer, like
+//! a shell wrapper. The sidecar keeps the spans until all the spans of their trace chunk are
+//! finished, then submits the chunk like a tracer would.
+
+use crate::service::synthetic_span::random_id;
+use anyhow::Context;
+use datadog_trace_protobuf::pb;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
+
+/// Bounds the spans kept per queue, so that a client never finishing its spans can't make the
+/// sidecar grow unbounded.
+const MAX_OPEN_SPANS: usize = 10_000;
+
+/// A span to start with `start_span`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SpanStart {
+    /// Chosen by the client, it must be unique within the queue. It identifies the span when
+    /// finishing it, and as parent of other spans.
+    pub span_id: u64,
+    /// The parent span, None for a root span. Parents which were not started in the same queue
+    /// require the `trace_id`, e.g. when continuing a distributed trace.
+    pub parent_id: Option<u64>,
+    /// Defaults to the trace of the parent, or to a new trace for root spans.
+    pub trace_id: Option<u64>,
+    pub service: String,
+    pub name: String,
+    /// Defaults to the name of the span.
+    pub resource: Option<String>,
+    pub r#type: String,
+    pub start: SystemTime,
+    pub meta: HashMap<String, String>,
+    pub metrics: HashMap<String, f64>,
+}
+
+/// Finishes a span started with `start_span`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SpanFinish {
+    pub span_id: u64,
+    /// Defaults to the time the sidecar receives the call.
+    pub end: Option<SystemTime>,
+    pub error: bool,
+    /// Added to the tags the span was started with.
+    pub meta: HashMap<String, String>,
+    pub metrics: HashMap<String, f64>,
+}
+
+fn unix_nanos(time: SystemTime) -> i64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as i64)
+}
+
+struct OpenChunk {
+    spans: Vec<pb::Span>,
+    unfinished: HashSet<u64>,
+}
+
+/// The spans of a queue whose trace chunk is not finished yet.
+#[derive(Default)]
+pub(crate) struct OpenSpans {
+    /// The trace id of each kept span, finished or not.
+    span_traces: HashMap<u64, u64>,
+    chunks: HashMap<u64, OpenChunk>,
+}
+
+impl OpenSpans {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub(crate) fn start(&mut self, span: SpanStart) -> anyhow::Result<()> {
+        anyhow::ensure!(span.span_id != 0, "0 is not a valid span id");
+        anyhow::ensure!(
+            !self.span_traces.contains_key(&span.span_id),
+            "span {} was already started",
+            span.span_id
+        );
+        anyhow::ensure!(
+            self.span_traces.len() < MAX_OPEN_SPANS,
+            "too many open spans, at most {MAX_OPEN_SPANS} are kept"
+        );
+        let trace_id = match (span.trace_id, span.parent_id) {
+            (Some(trace_id), _) => trace_id,
+            (None, Some(parent_id)) => *self
+                .span_traces
+                .get(&parent_id)
+                .with_context(|| format!("unknown parent span {parent_id}, without a trace id"))?,
+            (None, None) => random_id(),
+        };
+
+        let chunk = self.chunks.entry(trace_id).or_insert_with(|| OpenChunk {
+            spans: vec![],
+            unfinished: HashSet::new(),
+        });
+        chunk.unfinished.insert(span.span_id);
+        chunk.spans.push(pb::Span {
+            service: span.service,
+            resource: span.resource.unwrap_or_else(|| span.name.clone()),
+            name: span.name,
+            trace_id,
+            span_id: span.span_id,
+            parent_id: span.parent_id.unwrap_or(0),
+            start: unix_nanos(span.start),
+            meta: span.meta,
+            metrics: span.metrics,
+            r#type: span.r#type,
+            ..Default::default()
+        });
+        self.span_traces.insert(span.span_id, trace_id);
+        Ok(())
+    }
+
+    /// Finishes the span, returning the spans of its chunk if it was the last unfinished one.
+    pub(crate) fn finish(&mut self, finish: SpanFinish) -> anyhow::Result<Option<Vec<pb::Span>>> {
+        let trace_id = *self
+            .span_traces
+            .get(&finish.span_id)
+            .with_context(|| format!("unknown span {}", finish.span_id))?;
+        let chunk = self
+            .chunks
+            .get_mut(&trace_id)
+            .context("the chunk of the span is gone")?;
+        anyhow::ensure!(
+            chunk.unfinished.remove(&finish.span_id),
+            "span {} was already finished",
+            finish.span_id
+        );
+
+        if let Some(span) = chunk
+            .spans
+            .iter_mut()
+            .find(|span| span.span_id == finish.span_id)
+        {
+            let end = unix_nanos(finish.end.unwrap_or_else(SystemTime::now));
+            span.duration = (end - span.start).max(0);
+            span.error = finish.error as i32;
+            span.meta.extend(finish.meta);
+            span.metrics.extend(finish.metrics);
+        }
+
+        if !chunk.unfinished.is_empty() {
+            return Ok(None);
+        }
+        let chunk = self.chunks.remove(&trace_id).map(|chunk| chunk.spans);
+        for span in chunk.iter().flatten() {
+            self.span_traces.remove(&span.span_id);
+        }
+        Ok(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn span_start(span_id: u64, parent_id: Option<u64>) -> SpanStart {
+        SpanStart {
+            span_id,
+            parent_id,
+            trace_id: None,
+            service: "wrapper".to_string(),
+            name: "command".to_string(),
+            resource: None,
+            r#type: "".to_string(),
+            start: SystemTime::UNIX_EPOCH + Duration::from_secs(1),
+            meta: HashMap::new(),
+            metrics: HashMap::new(),
+        }
+    }
+
+    fn span_finish(span_id: u64) -> SpanFinish {
+        SpanFinish {
+            span_id,
+            end: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(2)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_chunk_is_returned_once_finished() {
+        let mut spans = OpenSpans::default();
+        spans.start(span_start(1, None)).unwrap();
+        spans.start(span_start(2, Some(1))).unwrap();
+        spans.start(span_start(2, Some(1))).unwrap_err();
+        spans.start(span_start(3, Some(42))).unwrap_err();
+
+        let mut finish = span_finish(1);
+        finish.error = true;
+        finish.meta.insert("exit_code".to_string(), "1".to_string());
+        assert!(spans.finish(finish).unwrap().is_none());
+        spans.finish(span_finish(1)).unwrap_err();
+        // children of finished spans still join their chunk
+        spans.start(span_start(3, Some(1))).unwrap();
+        assert!(spans.finish(span_finish(2)).unwrap().is_none());
+
+        let chunk = spans.finish(span_finish(3)).unwrap().unwrap();
+        assert!(spans.is_empty());
+        assert_eq!(3, chunk.len());
+        assert!(chunk.iter().all(|span| span.trace_id == chunk[0].trace_id));
+        assert_eq!(0, chunk[0].parent_id);
+        assert_eq!(1, chunk[1].parent_id);
+        assert_eq!(1_000_000_000, chunk[0].duration);
+        assert_eq!(1, chunk[0].error);
+        assert_eq!("1", chunk[0].meta["exit_code"]);
+        assert_eq!("command", chunk[0].resource);
+
+        spans.finish(span_finish(1)).unwrap_err();
+    }
+
+    #[test]
+    fn test_remote_parent() {
+        let mut spans = OpenSpans::default();
+        let mut start = span_start(1, Some(42));
+        start.trace_id = Some(7);
+        spans.start(start).unwrap();
+        let chunk = spans.finish(span_finish(1)).unwrap().unwrap();
+        assert_eq!((7, 42), (chunk[0].trace_id, chunk[0].parent_id));
+    }
+}
@@ -13,10 +13,13 @@ use std::path::PathBuf;
 use std::time::Duration;
 
 // public types we want to bring up to top level of service:: scope
+pub use error::SidecarError;
+pub use flush_all_result::FlushAllResult;
 pub use instance_id::InstanceId;
 pub use queue_id::QueueId;
 pub use runtime_metadata::RuntimeMetadata;
 pub use serialized_tracer_header_tags::SerializedTracerHeaderTags;
+pub use session_tags::{SessionTags, SessionTagsUpdate};
 
 // public to crate types we want to bring up to top level of service:: scope
 pub(crate) use request_identification::{RequestIdentification, RequestIdentifier};
@@ -26,16 +29,27 @@ use runtime_info::RuntimeInfo;
 use session_info::SessionInfo;
 use sidecar_interface::{SidecarInterface, SidecarInterfaceRequest, SidecarInterfaceResponse};
 
+pub mod agent_state;
 pub mod blocking;
+mod error;
+mod flush_all_result;
 mod instance_id;
+pub mod manual_span;
+pub mod profile_upload;
+pub mod proxy_upload;
 mod queue_id;
+pub mod replace_rules;
 mod request_identification;
 mod runtime_info;
 mod runtime_metadata;
 mod serialized_tracer_header_tags;
 mod session_info;
+pub(crate) mod session_snapshot;
+mod session_tags;
 mod sidecar_interface;
 pub(crate) mod sidecar_server;
+pub mod synthetic_span;
+pub mod tagging_rules;
 mod telemetry;
 pub(crate) mod tracing;
 
@@ -48,6 +62,33 @@ pub struct SessionConfig {
     pub force_drop_size: usize,
     pub log_level: String,
     pub log_file: config::LogMethod,
+    /// Span tag replacement rules in the JSON format of DD_APM_REPLACE_TAGS, applied to the traces
+    /// before sending them. Empty if there are none.
+    pub replace_tags: String,
+    /// Rules adding tags to the spans of the session depending on their service, env and name, in
+    /// the JSON format described by [`tagging_rules::TaggingRules`]. Empty if there are none.
+    pub tagging_rules: String,
+    /// The `apm_config` of the agent configuration, as JSON, with its `obfuscation` settings
+    /// applied to the traces submitted agentlessly. Empty to not obfuscate them.
+    pub obfuscation_config: String,
+    /// Host, container and runtime tags applied to the traces, profiles and telemetry of the
+    /// session. They can be updated later on with `update_session_tags`.
+    pub tags: SessionTags,
+    /// The intake endpoint, with the site URL and the API key, traces are submitted to directly
+    /// when `agentless` is set. They are submitted to `endpoint` otherwise.
+    pub agentless_endpoint: Option<Endpoint>,
+    pub agentless: bool,
+}
+
+impl SessionConfig {
+    /// The endpoint traces are submitted to. Agentless submission requires an API key, without one
+    /// traces are still submitted to `endpoint`.
+    pub fn trace_endpoint(&self) -> &Endpoint {
+        match &self.agentless_endpoint {
+            Some(endpoint) if self.agentless && endpoint.api_key.is_some() => endpoint,
+            _ => &self.endpoint,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -57,3 +98,49 @@ pub enum SidecarAction {
     AddTelemetryMetricPoint((String, f64, Vec<Tag>)),
     PhpComposerTelemetryFile(PathBuf),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session_config(agentless: bool, api_key: Option<&'static str>) -> SessionConfig {
+        SessionConfig {
+            endpoint: Endpoint {
+                url: hyper::Uri::from_static("http://localhost:8126/"),
+                api_key: None,
+                ..Default::default()
+            },
+            dogstatsd_endpoint: Endpoint::default(),
+            flush_interval: Duration::from_secs(1),
+            force_flush_size: 0,
+            force_drop_size: 0,
+            log_level: String::new(),
+            log_file: config::LogMethod::Disabled,
+            replace_tags: String::new(),
+            tagging_rules: String::new(),
+            obfuscation_config: String::new(),
+            tags: SessionTags::default(),
+            agentless_endpoint: Some(Endpoint {
+                url: hyper::Uri::from_static("datadoghq.com"),
+                api_key: api_key.map(Into::into),
+                ..Default::default()
+            }),
+            agentless,
+        }
+    }
+
+    #[test]
+    fn test_trace_endpoint() {
+        let config = session_config(false, Some("api-key"));
+        assert_eq!(&config.endpoint, config.trace_endpoint());
+
+        let config = session_config(true, Some("api-key"));
+        assert_eq!(
+            config.agentless_endpoint.as_ref(),
+            Some(config.trace_endpoint())
+        );
+
+        let config = session_config(true, None);
+        assert_eq!(&config.endpoint, config.trace_endpoint());
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::service::SidecarError;
+use datadog_ipc::platform::{FileBackedHandle, ShmHandle};
+use datadog_profiling::exporter::{File, ProfileExporter};
+use datadog_profiling::internal::{EncodedProfile, ProfiledEndpointsStats};
+use ddcommon::tag::Tag;
+use ddcommon::Endpoint;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+
+/// The configuration of the profile exporter, like passed to [`ProfileExporter::new`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProfileExporterConfig {
+    pub endpoint: Endpoint,
+    pub profiling_library_name: String,
+    pub profiling_library_version: String,
+    pub family: String,
+    pub tags: Vec<Tag>,
+    pub timeout: Duration,
+}
+
+/// Everything needed by the sidecar to upload an encoded profile, besides the pprof itself which
+/// is passed through shared memory.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProfileUpload {
+    pub exporter: ProfileExporterConfig,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub endpoints_stats: ProfiledEndpointsStats,
+    /// JSON, see `internal_metadata` of [`ProfileExporter::build`].
+    pub internal_metadata: Option<String>,
+    /// JSON, see `info` of [`ProfileExporter::build`].
+    pub info: Option<String>,
+}
+
+impl ProfileUpload {
+    /// Splits the encoded profile into the metadata of its upload and its pprof, which is moved
+    /// out as is.
+    pub fn new(exporter: ProfileExporterConfig, profile: EncodedProfile) -> (Self, Vec<u8>) {
+        let upload = ProfileUpload {
+            exporter,
+            start: profile.start,
+            end: profile.end,
+            endpoints_stats: profile.endpoints_stats,
+            internal_metadata: None,
+            info: None,
+        };
+        (upload, profile.buffer)
+    }
+}
+
+/// Moves the pprof into anonymous shared memory (a memfd on linux) and frees the buffer, so that
+/// the profile is not held in memory twice while the sidecar uploads it.
+///
+/// The memory is sealed, see [`ShmHandle::seal`]: the process handing it over can't change the
+/// profile while the sidecar uploads it.
+pub fn pprof_into_shm(pprof: Vec<u8>) -> anyhow::Result<(ShmHandle, usize)> {
+    let len = pprof.len();
+    let mut mapped = ShmHandle::new(len.max(1))?.map()?;
+    mapped.as_slice_mut()[..len].copy_from_slice(&pprof);
+    drop(pprof);
+    // sealing fails while the memory is still mapped writable
+    let handle: ShmHandle = mapped.into();
+    handle.seal()?;
+    Ok((handle, len))
+}
+
+/// Uploads the pprof stored in the first `len` bytes of the shared memory.
+///
+/// This blocks on the exporter's own runtime, so it must not be called from an async context.
+pub(crate) fn upload_profile(
+    handle: ShmHandle,
+    len: usize,
+    upload: ProfileUpload,
+) -> anyhow::Result<()> {
+    let mapped = handle.map()?;
+    let pprof = mapped
+        .as_slice()
+        .get(..len)
+        .ok_or_else(|| anyhow::anyhow!("Profile length {len} exceeds the shared memory"))?;
+
+    let config = upload.exporter;
+    let exporter = ProfileExporter::new(
+        config.profiling_library_name,
+        config.profiling_library_version,
+        config.family,
+        Some(config.tags),
+        config.endpoint,
+    )?;
+    let internal_metadata = upload
+        .internal_metadata
+        .as_deref()
+        .map(serde_json::from_str)
+        .transpose()?;
+    let info = upload
+        .info
+        .as_deref()
+        .map(serde_json::from_str)
+        .transpose()?;
+
+    let request = exporter.build(
+        upload.start.into(),
+        upload.end.into(),
+        &[File {
+            name: "profile.pprof",
+            bytes: pprof,
+        }],
+        &[],
+        None,
+        Some(&upload.endpoints_stats),
+        internal_metadata,
+        info,
+        config.timeout,
+    )?;
+    let response = exporter.send(request, None)?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(SidecarError::UploadFailed {
+            status: Some(status.as_u16()),
+            message: status.canonical_reason().unwrap_or_default().to_string(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httpmock::MockServer;
+
+    fn upload(url: String) -> ProfileUpload {
+        let now = SystemTime::now();
+        ProfileUpload {
+            exporter: ProfileExporterConfig {
+                endpoint: Endpoint {
+                    url: url.parse().unwrap(),
+                    api_key: None,
+                    ..Default::default()
+                },
+                profiling_library_name: "dd-trace-php".to_string(),
+                profiling_library_version: "1.0.0".to_string(),
+                family: "php".to_string(),
+                tags: vec![],
+                timeout: Duration::from_secs(5),
+            },
+            start: now,
+            end: now,
+            endpoints_stats: ProfiledEndpointsStats::default(),
+            internal_metadata: Some(r#"{"no_signals_workaround_enabled": "true"}"#.to_string()),
+            info: None,
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_upload_profile_from_shm() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(httpmock::Method::POST)
+                .path("/profiling/v1/input");
+            then.status(200);
+        });
+
+        let pprof = b"not quite a pprof".to_vec();
+        let (handle, len) = pprof_into_shm(pprof).unwrap();
+        let upload = upload(server.url("/profiling/v1/input"));
+
+        upload_profile(handle, len, upload).unwrap();
+        mock.assert();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_upload_profile_reports_status() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(httpmock::Method::POST)
+                .path("/profiling/v1/input");
+            then.status(413);
+        });
+
+        let pprof = b"not quite a pprof".to_vec();
+        let (handle, len) = pprof_into_shm(pprof).unwrap();
+        let upload = upload(server.url("/profiling/v1/input"));
+
+        let error = upload_profile(handle, len, upload).unwrap_err();
+        assert_eq!(
+            SidecarError::UploadFailed {
+                status: Some(413),
+                message: "Payload Too Large".to_string(),
+            },
+            SidecarError::from(error)
+        );
+    }
+}
@@ -0,0 +1,257 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! Payloads which the sidecar forwards as is to an intake, with the endpoint and credentials of
+//! the session. This lets products reuse the egress path of the sidecar without a specific call.
+
+use crate::config::get_product_endpoint;
+use crate::service::SidecarError;
+use datadog_ipc::platform::{FileBackedHandle, ShmHandle};
+use ddcommon::connector::Connector;
+use ddcommon::Endpoint;
+use http::uri::PathAndQuery;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Headers set by the sidecar, which the payloads cannot override.
+const RESERVED_HEADERS: [&str; 8] = [
+    "content-type",
+    "content-length",
+    "host",
+    "user-agent",
+    "dd-api-key",
+    "datadog-container-id",
+    "datadog-entity-id",
+    "datadog-external-env",
+];
+
+/// The intakes payloads can be forwarded to.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IntakeKind {
+    /// v0.4 msgpack traces, only accepted by the agent: the agentless intake expects protobuf.
+    Traces,
+    Profiles,
+    /// Dynamic instrumentation snapshots and probe statuses.
+    Debugger,
+    /// Symbol database uploads, only accepted by the agent.
+    Symbols,
+}
+
+impl IntakeKind {
+    /// The path the agent receives the payloads on.
+    fn agent_path(self) -> &'static str {
+        match self {
+            IntakeKind::Traces => "/v0.4/traces",
+            IntakeKind::Profiles => "/profiling/v1/input",
+            IntakeKind::Debugger => "/debugger/v1/input",
+            IntakeKind::Symbols => "/symdb/v1/input",
+        }
+    }
+
+    /// The subdomain of the site and the path of the intake, when submitting agentlessly. None if
+    /// the payloads can only be submitted to the agent.
+    fn agentless_intake(self) -> Option<(&'static str, &'static str)> {
+        match self {
+            IntakeKind::Traces | IntakeKind::Symbols => None,
+            IntakeKind::Profiles => Some(("intake.profile", "/api/v2/profile")),
+            IntakeKind::Debugger => Some(("http-intake.logs", "/api/v2/logs")),
+        }
+    }
+
+    /// The endpoint of this intake, for a session submitting to `endpoint`: the agent, or the site
+    /// when it has an API key.
+    pub fn endpoint(self, endpoint: &Endpoint) -> anyhow::Result<Endpoint> {
+        let (endpoint, path) = if endpoint.api_key.is_some() {
+            let Some((subdomain, path)) = self.agentless_intake() else {
+                anyhow::bail!("{self:?} payloads can only be proxied to an agent");
+            };
+            (get_product_endpoint(subdomain, endpoint), path)
+        } else {
+            (endpoint.clone(), self.agent_path())
+        };
+        let mut parts = endpoint.url.into_parts();
+        parts.path_and_query = Some(PathAndQuery::from_static(path));
+        Ok(Endpoint {
+            url: hyper::Uri::from_parts(parts)?,
+            ..endpoint
+        })
+    }
+}
+
+/// A payload to forward with `proxy_upload`, besides the body which is passed through shared
+/// memory.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProxyUpload {
+    pub intake: IntakeKind,
+    /// The Content-Type of the body, none is sent if empty.
+    pub content_type: String,
+    /// Sent along with the body. The content type, the user agent, the API key and the container
+    /// headers are set by the sidecar and rejected here.
+    pub headers: Vec<(String, String)>,
+    pub timeout: Duration,
+}
+
+/// Forwards payloads with a single client, so that the connections to the intakes are reused.
+#[derive(Clone)]
+pub(crate) struct ProxyUploader {
+    client: hyper::Client<Connector, hyper::Body>,
+}
+
+impl Default for ProxyUploader {
+    fn default() -> Self {
+        ProxyUploader {
+            client: hyper::Client::builder().build(Connector::default()),
+        }
+    }
+}
+
+impl ProxyUploader {
+    /// Forwards the first `len` bytes of the shared memory to the intake, for a session submitting
+    /// to `endpoint`.
+    pub(crate) async fn upload(
+        &self,
+        endpoint: &Endpoint,
+        handle: ShmHandle,
+        len: usize,
+        upload: ProxyUpload,
+    ) -> anyhow::Result<()> {
+        if let Some((name, _)) = upload.headers.iter().find(|(name, _)| {
+            RESERVED_HEADERS
+                .iter()
+                .any(|reserved| name.eq_ignore_ascii_case(reserved))
+        }) {
+            anyhow::bail!("The {name} header is set by the sidecar");
+        }
+
+        let mapped = handle.map()?;
+        let body = mapped
+            .as_slice()
+            .get(..len)
+            .ok_or_else(|| anyhow::anyhow!("Payload length {len} exceeds the shared memory"))?
+            .to_vec();
+        drop(mapped);
+
+        let mut builder = upload
+            .intake
+            .endpoint(endpoint)?
+            .into_request_builder(concat!("Sidecar/", env!("CARGO_PKG_VERSION")))?
+            .method(http::Method::POST);
+        if !upload.content_type.is_empty() {
+            builder = builder.header(http::header::CONTENT_TYPE, &upload.content_type);
+        }
+        for (name, value) in &upload.headers {
+            builder = builder.header(name, value);
+        }
+        let req = builder.body(hyper::Body::from(body))?;
+
+        let response = tokio::time::timeout(upload.timeout, self.client.request(req))
+            .await
+            .map_err(|_| SidecarError::TimedOut(upload.timeout))??;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(SidecarError::UploadFailed {
+                status: Some(status.as_u16()),
+                message: status.canonical_reason().unwrap_or_default().to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::profile_upload::pprof_into_shm;
+    use httpmock::MockServer;
+
+    fn upload(intake: IntakeKind) -> ProxyUpload {
+        ProxyUpload {
+            intake,
+            content_type: "application/json".to_string(),
+            headers: vec![],
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn test_intake_endpoint() {
+        let agent = Endpoint {
+            url: hyper::Uri::from_static("http://localhost:8126/"),
+            api_key: None,
+            ..Default::default()
+        };
+        let endpoint = IntakeKind::Debugger.endpoint(&agent).unwrap();
+        assert_eq!("http://localhost:8126/debugger/v1/input", endpoint.url);
+
+        let site = Endpoint {
+            url: hyper::Uri::from_static("datadoghq.com"),
+            api_key: Some("api-key".into()),
+            ..Default::default()
+        };
+        let endpoint = IntakeKind::Profiles.endpoint(&site).unwrap();
+        assert_eq!(
+            "https://intake.profile.datadoghq.com/api/v2/profile",
+            endpoint.url
+        );
+        assert_eq!(site.api_key, endpoint.api_key);
+
+        // agentless traces are protobuf rather than msgpack, and symbols have no site intake
+        IntakeKind::Traces.endpoint(&site).unwrap_err();
+        IntakeKind::Symbols.endpoint(&site).unwrap_err();
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_proxy_upload() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(httpmock::Method::POST)
+                    .path("/symdb/v1/input")
+                    .header("Content-Type", "application/json")
+                    .body("{}");
+                then.status(202);
+            })
+            .await;
+        let agent = Endpoint {
+            url: server.url("/").parse().unwrap(),
+            api_key: None,
+            ..Default::default()
+        };
+
+        let uploader = ProxyUploader::default();
+        let (handle, len) = pprof_into_shm(b"{}".to_vec()).unwrap();
+        uploader
+            .upload(&agent, handle, len, upload(IntakeKind::Symbols))
+            .await
+            .unwrap();
+        mock.assert_async().await;
+
+        let (handle, len) = pprof_into_shm(b"{}".to_vec()).unwrap();
+        let mut reserved = upload(IntakeKind::Symbols);
+        reserved
+            .headers
+            .push(("DD-API-KEY".to_string(), "api-key".to_string()));
+        let error = uploader
+            .upload(&agent, handle, len, reserved)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("DD-API-KEY"), "{error}");
+        mock.assert_async().await;
+
+        let (handle, len) = pprof_into_shm(b"{}".to_vec()).unwrap();
+        let error = uploader
+            .upload(&agent, handle, len, upload(IntakeKind::Debugger))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            SidecarError::UploadFailed {
+                status: Some(404),
+                message: "Not Found".to_string(),
+            },
+            SidecarError::from(error)
+        );
+    }
+}
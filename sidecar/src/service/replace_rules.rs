@@ -0,0 +1,92 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use datadog_trace_obfuscation::replacer::{self, ReplaceRule};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+
+/// Compiled span tag replacement rules, as configured through DD_APM_REPLACE_TAGS.
+pub type ReplaceRules = Arc<Vec<ReplaceRule>>;
+
+/// `ReplaceRulesCache` compiles DD_APM_REPLACE_TAGS style rules, sharing the compiled regexes
+/// between all sessions configured with the same rules. Entries are only kept alive as long as a
+/// session is using them.
+#[derive(Default)]
+pub struct ReplaceRulesCache {
+    rules: Mutex<HashMap<String, Weak<Vec<ReplaceRule>>>>,
+}
+
+impl ReplaceRulesCache {
+    /// Returns the compiled rules for the JSON representation of DD_APM_REPLACE_TAGS, or None if
+    /// there are no rules.
+    pub fn get(&self, rules_json: &str) -> anyhow::Result<Option<ReplaceRules>> {
+        if rules_json.trim().is_empty() {
+            return Ok(None);
+        }
+        let mut cache = self.rules.lock().unwrap();
+        if let Some(rules) = cache.get(rules_json).and_then(Weak::upgrade) {
+            return Ok(Some(rules));
+        }
+        let rules = Arc::new(replacer::parse_rules_from_string(rules_json)?);
+        cache.retain(|_, rules| rules.strong_count() > 0);
+        cache.insert(rules_json.to_string(), Arc::downgrade(&rules));
+        Ok(Some(rules))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datadog_trace_protobuf::pb;
+
+    const RULES: &str = r#"[
+        {"name": "http.url", "pattern": "(token/)([^/]*)", "repl": "${1}?"},
+        {"name": "http.url", "pattern": "guid", "repl": "[REDACTED]"},
+        {"name": "custom.tag", "pattern": "(/foo/bar/).*", "repl": "${1}extra"},
+        {"name": "*", "pattern": "secret", "repl": "?"},
+        {"name": "resource.name", "pattern": "prod", "repl": "[env]"}
+    ]"#;
+
+    #[test]
+    fn test_rules_are_applied_like_the_agent() {
+        let cache = ReplaceRulesCache::default();
+        let rules = cache.get(RULES).unwrap().unwrap();
+
+        let mut trace = vec![pb::Span {
+            resource: "GET /prod/secret".to_string(),
+            meta: HashMap::from([
+                (
+                    "http.url".to_string(),
+                    "some/guid/token/abcdef/abc".to_string(),
+                ),
+                ("custom.tag".to_string(), "/foo/bar/foo".to_string()),
+                ("other".to_string(), "a secret value".to_string()),
+            ]),
+            ..Default::default()
+        }];
+        replacer::replace_trace_tags(&mut trace, &rules);
+
+        let span = &trace[0];
+        assert_eq!("GET /[env]/secret", span.resource);
+        assert_eq!("some/[REDACTED]/token/?/abc", span.meta["http.url"]);
+        assert_eq!("/foo/bar/extra", span.meta["custom.tag"]);
+        assert_eq!("a ? value", span.meta["other"]);
+    }
+
+    #[test]
+    fn test_compiled_rules_are_shared() {
+        let cache = ReplaceRulesCache::default();
+        assert!(cache.get("").unwrap().is_none());
+        assert!(cache
+            .get(r#"[{"name": "*", "pattern": "(", "repl": ""}]"#)
+            .is_err());
+
+        let rules = cache.get(RULES).unwrap().unwrap();
+        assert!(Arc::ptr_eq(&rules, &cache.get(RULES).unwrap().unwrap()));
+
+        drop(rules);
+        let other = cache.get(r#"[{"name": "*", "pattern": "a", "repl": "b"}]"#);
+        assert!(other.unwrap().is_some());
+        assert_eq!(1, cache.rules.lock().unwrap().len());
+    }
+}
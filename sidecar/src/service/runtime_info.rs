@@ -2,18 +2,24 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::service::{
+    manual_span::OpenSpans,
     telemetry::{AppInstance, AppOrQueue},
-    InstanceId, QueueId,
+    FlushAllResult, InstanceId, QueueId, SidecarError,
 };
+use ddtelemetry::worker::{LifecycleAction, TelemetryActions};
 use futures::{
     future::{self, join_all, Shared},
     FutureExt,
 };
 use manual_future::{ManualFuture, ManualFutureCompleter};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::task::JoinHandle;
 use tracing::{debug, info};
 
+/// How many trace endpoints are kept until the profiler takes them, the oldest are dropped first.
+const MAX_TRACE_ENDPOINTS: usize = 1000;
+
 type AppMap = HashMap<(String, String), Shared<ManualFuture<Option<AppInstance>>>>;
 
 /// `SharedAppManualFut` is a struct that contains a shared future of an `AppInstance` and its
@@ -33,6 +39,9 @@ pub(crate) struct SharedAppManualFut {
 pub(crate) struct RuntimeInfo {
     pub(crate) apps: Arc<Mutex<AppMap>>,
     app_or_actions: Arc<Mutex<HashMap<QueueId, AppOrQueue>>>,
+    profile_uploads: Arc<Mutex<Vec<JoinHandle<Result<(), SidecarError>>>>>,
+    open_spans: Arc<Mutex<HashMap<QueueId, OpenSpans>>>,
+    trace_endpoints: Arc<Mutex<VecDeque<(u64, String)>>>,
     #[cfg(feature = "tracing")]
     pub(crate) instance_id: InstanceId,
 }
@@ -67,6 +76,77 @@ impl RuntimeInfo {
             }
         }
     }
+
+    /// Keeps track of an upload of a profile or of a proxied payload, so that it can be awaited
+    /// when flushing.
+    pub(crate) fn add_profile_upload(&self, upload: JoinHandle<Result<(), SidecarError>>) {
+        let mut uploads = self.profile_uploads.lock().unwrap();
+        uploads.retain(|upload| !upload.is_finished());
+        uploads.push(upload);
+    }
+
+    /// Records the endpoint of a trace chunk sent by the runtime, by local root span id, for the
+    /// profiler to label its samples with.
+    pub(crate) fn add_trace_endpoint(&self, local_root_span_id: u64, endpoint: String) {
+        let mut endpoints = self.trace_endpoints.lock().unwrap();
+        if endpoints.len() == MAX_TRACE_ENDPOINTS {
+            endpoints.pop_front();
+        }
+        endpoints.push_back((local_root_span_id, endpoint));
+    }
+
+    /// Returns the trace endpoints recorded since the last call, oldest first.
+    pub(crate) fn take_trace_endpoints(&self) -> Vec<(u64, String)> {
+        self.trace_endpoints.lock().unwrap().drain(..).collect()
+    }
+
+    /// Flushes the telemetry of all apps of the runtime and waits for its pending profile
+    /// uploads, reporting the result of each. The telemetry of an app fails with
+    /// `SidecarError::ShuttingDown` if its worker is already gone.
+    pub(crate) async fn flush(&self) -> FlushAllResult {
+        let apps: Vec<_> = self
+            .lock_apps()
+            .iter()
+            .map(|(key, app)| (key.clone(), app.clone()))
+            .collect();
+        let telemetry_flushes = apps.into_iter().map(|(key, app)| async move {
+            let instance = app.await?;
+            let telemetry = &instance.telemetry;
+            let actions = [
+                TelemetryActions::Lifecycle(LifecycleAction::FlushMetricAggr),
+                TelemetryActions::Lifecycle(LifecycleAction::FlushData),
+            ];
+            if telemetry.send_msgs(actions).await.is_err() {
+                return Some((key, Err(SidecarError::ShuttingDown)));
+            }
+            // The worker handles its messages in order, so the stats are only returned once the
+            // data has been sent.
+            if let Ok(stats) = telemetry.stats() {
+                _ = stats.await;
+            }
+            Some((key, Ok(())))
+        });
+        let profile_uploads = std::mem::take(&mut *self.profile_uploads.lock().unwrap());
+        let (telemetry_results, upload_results) =
+            future::join(join_all(telemetry_flushes), join_all(profile_uploads)).await;
+        FlushAllResult {
+            traces: vec![],
+            telemetry: telemetry_results.into_iter().flatten().collect(),
+            uploads: upload_results
+                .into_iter()
+                .map(|result| {
+                    // A panicking upload is reported like any other failed upload
+                    result.unwrap_or_else(|e| {
+                        Err(SidecarError::UploadFailed {
+                            status: None,
+                            message: e.to_string(),
+                        })
+                    })
+                })
+                .collect(),
+        }
+    }
+
     /// Shuts down the runtime.
     /// This involves shutting down all the instances in the runtime.
     pub(crate) async fn shutdown(self) {
@@ -122,6 +202,11 @@ impl RuntimeInfo {
     pub(crate) fn lock_app_or_actions(&self) -> MutexGuard<HashMap<QueueId, AppOrQueue>> {
         self.app_or_actions.lock().unwrap()
     }
+
+    /// Locks the spans started with `start_span` whose trace chunk is not finished yet, by queue.
+    pub(crate) fn lock_open_spans(&self) -> MutexGuard<HashMap<QueueId, OpenSpans>> {
+        self.open_spans.lock().unwrap()
+    }
 }
 
 // TODO: APM-1079 - Add unit tests for RuntimeInfo
@@ -13,7 +13,8 @@ use tracing::{enabled, info, Level};
 use crate::log::{MultiEnvFilterGuard, MultiWriterGuard};
 use crate::{dogstatsd, tracer};
 
-use crate::service::{InstanceId, RuntimeInfo};
+use crate::service::agent_state::AgentStateCache;
+use crate::service::{InstanceId, RuntimeInfo, SessionTags};
 /// `SessionInfo` holds information about a session.
 ///
 /// It contains a list of runtimes, session configuration, tracer configuration, and log guards.
@@ -24,6 +25,12 @@ pub(crate) struct SessionInfo {
     pub(crate) session_config: Arc<Mutex<Option<ddtelemetry::config::Config>>>,
     tracer_config: Arc<Mutex<tracer::Config>>,
     dogstatsd: Arc<Mutex<dogstatsd::Flusher>>,
+    tags: Arc<Mutex<SessionTags>>,
+    /// The agent, or the site for agentless sessions, which proxied uploads are forwarded to. None
+    /// until the session is configured.
+    intake_endpoint: Arc<Mutex<Option<ddcommon::Endpoint>>>,
+    /// Agent state shared by all sessions of the sidecar.
+    pub(crate) agent_state: Arc<AgentStateCache>,
     pub(crate) log_guard:
         Arc<Mutex<Option<(MultiEnvFilterGuard<'static>, MultiWriterGuard<'static>)>>>,
     #[cfg(feature = "tracing")]
@@ -145,6 +152,33 @@ impl SessionInfo {
         f(&mut self.get_trace_config());
     }
 
+    /// Warms up the shared agent state for the agent the session is sending traces to.
+    pub(crate) fn prefetch_agent_state(&self, endpoint: &ddcommon::Endpoint) {
+        self.agent_state.prefetch_info(endpoint.clone());
+    }
+
+    /// Returns the shared state of the agent the session is sending traces to as JSON, see
+    /// [`AgentStateCache::to_json`].
+    pub(crate) fn get_agent_state_json(&self) -> String {
+        let endpoint = self.get_trace_config().endpoint.clone();
+        match endpoint {
+            Some(endpoint) => self.agent_state.to_json(&endpoint),
+            None => "{}".to_string(),
+        }
+    }
+
+    pub(crate) fn get_intake_endpoint(&self) -> Option<ddcommon::Endpoint> {
+        self.intake_endpoint.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_intake_endpoint(&self, endpoint: ddcommon::Endpoint) {
+        *self.intake_endpoint.lock().unwrap() = Some(endpoint);
+    }
+
+    pub(crate) fn get_tags(&self) -> MutexGuard<SessionTags> {
+        self.tags.lock().unwrap()
+    }
+
     pub(crate) fn get_dogstatsd(&self) -> MutexGuard<dogstatsd::Flusher> {
         self.dogstatsd.lock().unwrap()
     }
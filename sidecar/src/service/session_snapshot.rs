@@ -0,0 +1,282 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! A snapshot of the sessions registered with the sidecar, kept on disk so that a sidecar respawned
+//! after a crash can serve the existing clients without them registering again.
+//!
+//! Only what clients send once is kept: the session configs and the services registered by the
+//! runtimes. Everything else is sent again by the clients anyway.
+//!
+//! The session configs hold credentials, e.g. the API keys of the endpoints, so the file is only
+//! accessible by the user running the sidecar, and a snapshot accessible by others is ignored.
+
+use crate::service::{InstanceId, QueueId, RuntimeMetadata, SessionConfig};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use std::{fs, io};
+use tracing::{debug, warn};
+
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct Snapshot {
+    pub sessions: HashMap<String, SessionSnapshot>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct SessionSnapshot {
+    pub config: Option<SessionConfig>,
+    /// The services registered by the runtimes of the session, by runtime id.
+    pub runtimes: HashMap<String, Vec<ServiceRegistration>>,
+}
+
+/// The arguments of a `register_service_and_flush_queued_actions` call.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct ServiceRegistration {
+    pub queue_id: QueueId,
+    pub runtime_meta: RuntimeMetadata,
+    pub service_name: String,
+    pub env_name: String,
+}
+
+/// How long the sessions and runtimes restored from a snapshot are kept if no client uses them,
+/// e.g. because the process of a runtime exited while there was no sidecar.
+pub(crate) const UNCLAIMED_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// The sessions and runtimes restored from a snapshot which no client used since.
+#[derive(Default)]
+pub(crate) struct Unclaimed {
+    pub sessions: HashSet<String>,
+    pub instances: HashSet<InstanceId>,
+}
+
+impl Snapshot {
+    /// Reads the snapshot left at `path`. A missing or unreadable snapshot is empty.
+    pub fn load(path: &Path) -> Snapshot {
+        match read_private(path) {
+            Ok(data) => bincode::deserialize(&data).unwrap_or_else(|e| {
+                warn!("Ignoring the invalid session snapshot {path:?}: {e}");
+                Snapshot::default()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Snapshot::default(),
+            Err(e) => {
+                warn!("Failed reading the session snapshot {path:?}: {e}");
+                Snapshot::default()
+            }
+        }
+    }
+}
+
+/// Reads the file at `path`, which must be owned by the current user and not be accessible by
+/// others.
+fn read_private(path: &Path) -> io::Result<Vec<u8>> {
+    let mut options = fs::OpenOptions::new();
+    options.read(true);
+    #[cfg(unix)]
+    options.custom_flags(libc::O_NOFOLLOW);
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    {
+        let metadata = file.metadata()?;
+        if metadata.uid() != unsafe { libc::geteuid() } || metadata.mode() & 0o077 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the snapshot must only be accessible by the user running the sidecar",
+            ));
+        }
+    }
+    let mut data = vec![];
+    file.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Keeps the snapshot at `path` up to date with the sessions of the sidecar.
+pub(crate) struct SnapshotFile {
+    path: PathBuf,
+    snapshot: Mutex<Snapshot>,
+}
+
+impl SnapshotFile {
+    /// Starts from an empty snapshot: the file is only overwritten on the next update.
+    pub fn new(path: PathBuf) -> Self {
+        SnapshotFile {
+            path,
+            snapshot: Mutex::new(Snapshot::default()),
+        }
+    }
+
+    pub fn set_session_config(&self, session_id: &str, config: &SessionConfig) {
+        self.update(|snapshot| {
+            snapshot
+                .sessions
+                .entry(session_id.to_string())
+                .or_default()
+                .config = Some(config.clone());
+            true
+        });
+    }
+
+    pub fn register_service(&self, instance_id: &InstanceId, registration: &ServiceRegistration) {
+        self.update(|snapshot| {
+            let services = snapshot
+                .sessions
+                .entry(instance_id.session_id.clone())
+                .or_default()
+                .runtimes
+                .entry(instance_id.runtime_id.clone())
+                .or_default();
+            services.retain(|service| service.queue_id != registration.queue_id);
+            services.push(registration.clone());
+            true
+        });
+    }
+
+    pub fn remove_runtime(&self, instance_id: &InstanceId) {
+        self.update(|snapshot| {
+            snapshot
+                .sessions
+                .get_mut(&instance_id.session_id)
+                .and_then(|session| session.runtimes.remove(&instance_id.runtime_id))
+                .is_some()
+        });
+    }
+
+    pub fn remove_session(&self, session_id: &str) {
+        self.update(|snapshot| snapshot.sessions.remove(session_id).is_some());
+    }
+
+    /// Applies `update`, and writes the snapshot if it returns true, i.e. if it changed anything.
+    fn update(&self, update: impl FnOnce(&mut Snapshot) -> bool) {
+        let mut snapshot = self.snapshot.lock().unwrap();
+        if update(&mut snapshot) {
+            // Written while holding the lock, so that the writes are not reordered.
+            if let Err(e) = self.write(&snapshot) {
+                warn!("Failed writing the session snapshot {:?}: {e}", self.path);
+            }
+        }
+    }
+
+    /// Replaces the file atomically, a crash must not leave a truncated snapshot behind.
+    fn write(&self, snapshot: &Snapshot) -> io::Result<()> {
+        let data =
+            bincode::serialize(snapshot).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        // A leftover of a crash is replaced, creating a new file doesn't follow symlinks.
+        match fs::remove_file(&tmp_path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        options.open(&tmp_path)?.write_all(&data)?;
+        fs::rename(&tmp_path, &self.path)?;
+        debug!("Wrote the session snapshot {:?}", self.path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LogMethod;
+    use crate::service::SessionTags;
+    use ddcommon::Endpoint;
+    use std::time::Duration;
+
+    fn config() -> SessionConfig {
+        SessionConfig {
+            endpoint: Endpoint {
+                url: hyper::Uri::from_static("http://localhost:8126/"),
+                api_key: None,
+                ..Default::default()
+            },
+            dogstatsd_endpoint: Endpoint {
+                url: hyper::Uri::from_static("http://localhost:8125/"),
+                api_key: None,
+                ..Default::default()
+            },
+            flush_interval: Duration::from_secs(1),
+            force_flush_size: 1000,
+            force_drop_size: 2000,
+            log_level: "info".to_string(),
+            log_file: LogMethod::Disabled,
+            replace_tags: String::new(),
+            tagging_rules: String::new(),
+            obfuscation_config: String::new(),
+            tags: SessionTags::default(),
+            agentless_endpoint: None,
+            agentless: false,
+        }
+    }
+
+    fn registration(queue_id: QueueId, service_name: &str) -> ServiceRegistration {
+        ServiceRegistration {
+            queue_id,
+            runtime_meta: RuntimeMetadata::default(),
+            service_name: service_name.to_string(),
+            env_name: "prod".to_string(),
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_snapshot_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sessions");
+        assert!(Snapshot::load(&path).sessions.is_empty());
+
+        let file = SnapshotFile::new(path.clone());
+        let instance_id = InstanceId::new("session", "runtime");
+        let queue_id = QueueId::new_unique();
+        file.set_session_config("session", &config());
+        file.register_service(&instance_id, &registration(queue_id, "old"));
+        file.register_service(&instance_id, &registration(queue_id, "new"));
+        file.register_service(
+            &InstanceId::new("session", "other runtime"),
+            &registration(QueueId::new_unique(), "other"),
+        );
+        file.remove_runtime(&InstanceId::new("session", "other runtime"));
+        file.set_session_config("other session", &config());
+        file.remove_session("other session");
+
+        let snapshot = Snapshot::load(&path);
+        assert_eq!(1, snapshot.sessions.len());
+        let session = &snapshot.sessions["session"];
+        assert_eq!(
+            "http://localhost:8126/",
+            session.config.as_ref().unwrap().endpoint.url
+        );
+        assert_eq!(1, session.runtimes.len());
+        let services = &session.runtimes["runtime"];
+        assert_eq!(1, services.len());
+        assert_eq!(queue_id, services[0].queue_id);
+        assert_eq!("new", services[0].service_name);
+
+        fs::write(&path, b"garbage").unwrap();
+        assert!(Snapshot::load(&path).sessions.is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    #[cfg_attr(miri, ignore)]
+    fn test_snapshot_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sessions");
+        let file = SnapshotFile::new(path.clone());
+        file.set_session_config("session", &config());
+        assert_eq!(0o600, fs::metadata(&path).unwrap().mode() & 0o777);
+        assert_eq!(1, Snapshot::load(&path).sessions.len());
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(Snapshot::load(&path).sessions.is_empty());
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::service::SidecarAction;
+use datadog_trace_protobuf::pb;
+use ddcommon::tag::Tag;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The root span meta the container tags are joined into, like the agent does.
+const CONTAINER_TAGS_META_KEY: &str = "_dd.tags.container";
+
+/// The tags of a session, by what they describe.
+///
+/// They are applied to the data sent on behalf of the session:
+///  - traces: host tags become the `AgentPayload` tags, container tags are joined into the
+///    `_dd.tags.container` meta of root spans, and runtime tags are added to root spans.
+///  - profiles: all tags are added to the exporter tags.
+///  - telemetry: runtime tags are added to metric points. The host and container are already
+///    described by the telemetry host data.
+///
+/// Tags already present on the data are never overwritten.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionTags {
+    pub host: Vec<Tag>,
+    pub container: Vec<Tag>,
+    pub runtime: Vec<Tag>,
+}
+
+/// A partial update of the [`SessionTags`]: only the kinds which are `Some` are replaced.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SessionTagsUpdate {
+    pub host: Option<Vec<Tag>>,
+    pub container: Option<Vec<Tag>>,
+    pub runtime: Option<Vec<Tag>>,
+}
+
+fn split_tag(tag: &Tag) -> (&str, &str) {
+    let tag = tag.as_ref();
+    tag.split_once(':').unwrap_or((tag, ""))
+}
+
+impl SessionTags {
+    pub fn is_empty(&self) -> bool {
+        self.host.is_empty() && self.container.is_empty() && self.runtime.is_empty()
+    }
+
+    pub fn update(&mut self, update: SessionTagsUpdate) {
+        if let Some(host) = update.host {
+            self.host = host;
+        }
+        if let Some(container) = update.container {
+            self.container = container;
+        }
+        if let Some(runtime) = update.runtime {
+            self.runtime = runtime;
+        }
+    }
+
+    pub(crate) fn agent_payload_tags(&self) -> HashMap<String, String> {
+        self.host
+            .iter()
+            .map(split_tag)
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    pub(crate) fn apply_to_root_span(&self, span: &mut pb::Span) {
+        if !self.container.is_empty() && !span.meta.contains_key(CONTAINER_TAGS_META_KEY) {
+            let container_tags = self
+                .container
+                .iter()
+                .map(|tag| tag.as_ref())
+                .collect::<Vec<_>>()
+                .join(",");
+            span.meta
+                .insert(CONTAINER_TAGS_META_KEY.to_string(), container_tags);
+        }
+        for (key, value) in self.runtime.iter().map(split_tag) {
+            if !span.meta.contains_key(key) {
+                span.meta.insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+
+    pub(crate) fn apply_to_exporter_tags(&self, tags: &mut Vec<Tag>) {
+        add_missing_tags(
+            tags,
+            self.host.iter().chain(&self.container).chain(&self.runtime),
+        );
+    }
+
+    pub(crate) fn apply_to_actions(&self, actions: &mut [SidecarAction]) {
+        for action in actions.iter_mut() {
+            if let SidecarAction::AddTelemetryMetricPoint((_, _, tags)) = action {
+                add_missing_tags(tags, self.runtime.iter());
+            }
+        }
+    }
+}
+
+fn add_missing_tags<'a>(tags: &mut Vec<Tag>, session_tags: impl Iterator<Item = &'a Tag>) {
+    for tag in session_tags {
+        let (key, _) = split_tag(tag);
+        if !tags.iter().any(|existing| split_tag(existing).0 == key) {
+            tags.push(tag.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ddcommon::tag;
+
+    fn session_tags() -> SessionTags {
+        SessionTags {
+            host: vec![tag!("host_group", "web")],
+            container: vec![tag!("pod_name", "web-1"), tag!("kube_namespace", "prod")],
+            runtime: vec![tag!("runtime_kind", "fpm"), tag!("service", "ignored")],
+        }
+    }
+
+    #[test]
+    fn test_apply_to_root_span() {
+        let mut span = pb::Span::default();
+        span.meta.insert("service".to_string(), "web".to_string());
+        session_tags().apply_to_root_span(&mut span);
+
+        assert_eq!(
+            "pod_name:web-1,kube_namespace:prod",
+            span.meta["_dd.tags.container"]
+        );
+        assert_eq!("fpm", span.meta["runtime_kind"]);
+        assert_eq!("web", span.meta["service"]);
+        assert!(!span.meta.contains_key("host_group"));
+    }
+
+    #[test]
+    fn test_apply_to_exporter_tags() {
+        let mut tags = vec![tag!("service", "web")];
+        session_tags().apply_to_exporter_tags(&mut tags);
+
+        let tags: Vec<&str> = tags.iter().map(|tag| tag.as_ref()).collect();
+        assert_eq!(
+            vec![
+                "service:web",
+                "host_group:web",
+                "pod_name:web-1",
+                "kube_namespace:prod",
+                "runtime_kind:fpm"
+            ],
+            tags
+        );
+    }
+
+    #[test]
+    fn test_update() {
+        let mut tags = session_tags();
+        tags.update(SessionTagsUpdate {
+            container: Some(vec![]),
+            ..Default::default()
+        });
+
+        assert!(tags.container.is_empty());
+        assert_eq!(session_tags().host, tags.host);
+        assert_eq!(session_tags().runtime, tags.runtime);
+    }
+}
@@ -2,13 +2,18 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::dogstatsd::DogStatsDAction;
+use crate::service::manual_span::{SpanFinish, SpanStart};
+use crate::service::profile_upload::ProfileUpload;
+use crate::service::proxy_upload::ProxyUpload;
+use crate::service::synthetic_span::SyntheticSpan;
 use crate::service::{
-    InstanceId, QueueId, RequestIdentification, RequestIdentifier, RuntimeMetadata,
-    SerializedTracerHeaderTags, SessionConfig, SidecarAction,
+    FlushAllResult, InstanceId, QueueId, RequestIdentification, RequestIdentifier, RuntimeMetadata,
+    SerializedTracerHeaderTags, SessionConfig, SessionTagsUpdate, SidecarAction, SidecarError,
 };
 use anyhow::Result;
-use datadog_ipc::platform::ShmHandle;
+use datadog_ipc::platform::{LargePayload, ShmHandle};
 use datadog_ipc::tarpc;
+use std::time::Duration;
 
 /// The `SidecarInterface` trait defines the necessary methods for the sidecar service.
 ///
@@ -56,6 +61,14 @@ pub trait SidecarInterface {
     /// * `config` - The configuration to be set.
     async fn set_session_config(session_id: String, config: SessionConfig);
 
+    /// Updates the tags of a session, without reconfiguring it.
+    ///
+    /// # Arguments
+    ///
+    /// * `session_id` - The ID of the session.
+    /// * `tags` - The kinds of tags to replace.
+    async fn update_session_tags(session_id: String, tags: SessionTagsUpdate);
+
     /// Shuts down a runtime.
     ///
     /// # Arguments
@@ -90,14 +103,96 @@ pub trait SidecarInterface {
     /// # Arguments
     ///
     /// * `instance_id` - The ID of the instance.
-    /// * `data` - The trace data serialized as bytes.
+    /// * `data` - The trace data serialized as bytes, passed through shared memory if large.
     /// * `headers` - The serialized headers from the tracer.
     async fn send_trace_v04_bytes(
         instance_id: InstanceId,
-        data: Vec<u8>,
+        #[SerializedHandle] data: LargePayload,
         headers: SerializedTracerHeaderTags,
     );
 
+    /// Sends a span constructed on behalf of the tracer, marking an infrastructure event like the
+    /// start of the process or a crash.
+    ///
+    /// # Arguments
+    ///
+    /// * `instance_id` - The ID of the instance.
+    /// * `span` - The span, attached to the trace it refers to, if any.
+    async fn send_synthetic_span(instance_id: InstanceId, span: SyntheticSpan);
+
+    /// Like `send_synthetic_span`, but acknowledged, for verifying the submission end to end, e.g.
+    /// when debugging.
+    ///
+    /// # Arguments
+    ///
+    /// * `instance_id` - The ID of the instance.
+    /// * `span` - The span, attached to the trace it refers to, if any.
+    /// * `flush_timeout` - If set, the traces are flushed and waited for this long at most.
+    ///
+    /// # Returns
+    ///
+    /// The HTTP status the agent or intake answered the flush with, or `None` if the traces were
+    /// not flushed, e.g. without `flush_timeout` or as another flush was underway.
+    /// `SidecarError::UnknownInstance` if the session of the instance is not known or not
+    /// configured, `SidecarError::QueueFull` if the span was dropped,
+    /// `SidecarError::UploadFailed` if the flushed traces were not accepted and
+    /// `SidecarError::TimedOut` if the flush did not complete in time.
+    async fn send_synthetic_span_acked(
+        instance_id: InstanceId,
+        span: SyntheticSpan,
+        flush_timeout: Option<Duration>,
+    ) -> Result<Option<u16>, SidecarError>;
+
+    /// Starts a span, for clients creating spans without embedding a tracer. The span is sent
+    /// along with the other spans of its trace chunk once they are all finished.
+    ///
+    /// # Arguments
+    ///
+    /// * `instance_id` - The ID of the instance.
+    /// * `queue_id` - The unique identifier of the client, which the span ids are scoped to.
+    /// * `span` - The span to start.
+    async fn start_span(instance_id: InstanceId, queue_id: QueueId, span: SpanStart);
+
+    /// Finishes a span started with `start_span`.
+    ///
+    /// # Arguments
+    ///
+    /// * `instance_id` - The ID of the instance.
+    /// * `queue_id` - The unique identifier of the client the span was started by.
+    /// * `span` - The span to finish, and the tags to add to it.
+    async fn finish_span(instance_id: InstanceId, queue_id: QueueId, span: SpanFinish);
+
+    /// Uploads an encoded profile via shared memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `instance_id` - The ID of the instance.
+    /// * `handle` - The handle to the shared memory holding the pprof.
+    /// * `len` - The size of the pprof in the shared memory.
+    /// * `upload` - The exporter configuration and the profile metadata.
+    async fn send_profile_shm(
+        instance_id: InstanceId,
+        #[SerializedHandle] handle: ShmHandle,
+        len: usize,
+        upload: ProfileUpload,
+    );
+
+    /// Forwards a payload as is to an intake, with the endpoint and credentials of the session, so
+    /// that products without a specific call can reuse the egress path of the sidecar.
+    ///
+    /// # Arguments
+    ///
+    /// * `instance_id` - The ID of the instance.
+    /// * `handle` - The handle to the shared memory holding the body.
+    /// * `len` - The size of the body in the shared memory.
+    /// * `upload` - The intake and the headers of the payload.
+    async fn proxy_upload(
+        instance_id: InstanceId,
+        #[SerializedHandle] handle: ShmHandle,
+        len: usize,
+        upload: ProxyUpload,
+    );
+
     /// Sends DogStatsD actions.
     ///
     /// # Arguments
@@ -109,6 +204,49 @@ pub trait SidecarInterface {
     /// Flushes any outstanding traces queued for sending.
     async fn flush_traces();
 
+    /// Flushes the traces, and the telemetry and profiles of the instance, waiting for the data to
+    /// be sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `instance_id` - The ID of the instance.
+    /// * `timeout` - How long to wait at most.
+    ///
+    /// # Returns
+    ///
+    /// The result of sending the traces, the telemetry and the profiles, for each destination.
+    /// `SidecarError::TimedOut` if not everything was flushed before the timeout expired and
+    /// `SidecarError::UnknownInstance` if the session of the instance is not known.
+    async fn flush_all(
+        instance_id: InstanceId,
+        timeout: Duration,
+    ) -> Result<FlushAllResult, SidecarError>;
+
+    /// Takes the endpoints of the trace chunks sent by the instance so far, for the profiler to
+    /// label its samples with.
+    ///
+    /// # Arguments
+    ///
+    /// * `instance_id` - The ID of the instance.
+    ///
+    /// # Returns
+    ///
+    /// The local root span ids and their endpoints, oldest first.
+    async fn take_trace_endpoints(instance_id: InstanceId) -> Vec<(u64, String)>;
+
+    /// Retrieves what the sidecar learned from the agent of the session, which is shared by all
+    /// sessions talking to that agent.
+    ///
+    /// # Arguments
+    ///
+    /// * `session_id` - The ID of the session.
+    ///
+    /// # Returns
+    ///
+    /// The agent /info response and the rates by service as JSON, an empty object if the session
+    /// is not configured.
+    async fn get_agent_state(session_id: String) -> String;
+
     /// Sends a ping to the service.
     async fn ping();
 
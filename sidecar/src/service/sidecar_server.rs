@@ -5,22 +5,34 @@ use crate::config::get_product_endpoint;
 use crate::log;
 use crate::log::{TemporarilyRetainedMapStats, MULTI_LOG_FILTER, MULTI_LOG_WRITER};
 use crate::service::{
+    manual_span::{SpanFinish, SpanStart},
+    profile_upload::{self, ProfileUpload},
+    proxy_upload::{ProxyUpload, ProxyUploader},
+    replace_rules::ReplaceRulesCache,
+    session_snapshot::{ServiceRegistration, Snapshot, SnapshotFile, Unclaimed, UNCLAIMED_TTL},
     sidecar_interface::ServeSidecarInterface,
-    telemetry::{AppInstance, AppOrQueue},
+    synthetic_span::SyntheticSpan,
+    tagging_rules::TaggingRules,
+    telemetry::{enqueued_telemetry_data::ActionPriority, AppInstance, AppOrQueue},
     tracing::TraceFlusher,
-    EnqueuedTelemetryData, InstanceId, QueueId, RequestIdentification, RequestIdentifier,
-    RuntimeInfo, RuntimeMetadata, SerializedTracerHeaderTags, SessionConfig, SessionInfo,
-    SidecarAction, SidecarInterface, SidecarInterfaceRequest, SidecarInterfaceResponse,
+    EnqueuedTelemetryData, FlushAllResult, InstanceId, QueueId, RequestIdentification,
+    RequestIdentifier, RuntimeInfo, RuntimeMetadata, SerializedTracerHeaderTags, SessionConfig,
+    SessionInfo, SessionTags, SessionTagsUpdate, SidecarAction, SidecarError, SidecarInterface,
+    SidecarInterfaceRequest, SidecarInterfaceResponse,
 };
-use datadog_ipc::platform::{AsyncChannel, ShmHandle};
+use crate::tracer;
+use datadog_ipc::platform::{AsyncChannel, LargePayload, ShmHandle};
 use datadog_ipc::tarpc;
 use datadog_ipc::tarpc::context::Context;
 use datadog_ipc::transport::Transport;
+use datadog_trace_normalization::normalizer::{self, NormalizationStats};
+use datadog_trace_obfuscation::obfuscate::{self, obfuscate_span};
+use datadog_trace_obfuscation::obfuscation_config::ObfuscationConfig;
+use datadog_trace_obfuscation::replacer;
 use datadog_trace_protobuf::pb;
 use datadog_trace_utils::trace_utils;
-use datadog_trace_utils::trace_utils::SendData;
+use datadog_trace_utils::trace_utils::{SendData, TracerHeaderTags};
 use datadog_trace_utils::tracer_payload::TraceEncoding;
-use ddcommon::Endpoint;
 use ddtelemetry::worker::{
     LifecycleAction, TelemetryActions, TelemetryWorkerBuilder, TelemetryWorkerStats,
 };
@@ -30,9 +42,11 @@ use manual_future::{ManualFuture, ManualFutureCompleter};
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 use tracing::{debug, enabled, error, info, warn, Level};
 
 use futures::FutureExt;
@@ -52,7 +66,7 @@ fn no_response() -> NoResponse {
 }
 
 #[derive(Serialize, Deserialize)]
-struct SidecarStats {
+pub(crate) struct SidecarStats {
     trace_flusher: TraceFlusherStats,
     sessions: u32,
     session_counter_size: u32,
@@ -86,9 +100,107 @@ pub struct SidecarServer {
         Arc<Mutex<Option<ManualFutureCompleter<ddtelemetry::config::Config>>>>,
     /// Keeps track of the number of submitted payloads.
     pub submitted_payloads: Arc<AtomicU64>,
+    /// Compiled span tag replacement rules, shared between the sessions.
+    replace_rules: Arc<ReplaceRulesCache>,
+    /// Persists the session registrations, if enabled.
+    session_snapshot: Option<Arc<SnapshotFile>>,
+    /// The sessions and runtimes restored from the snapshot which no client used yet.
+    unclaimed: Arc<Mutex<Unclaimed>>,
+    /// Forwards the payloads of `proxy_upload`.
+    proxy_uploader: ProxyUploader,
 }
 
 impl SidecarServer {
+    /// Creates a server keeping its sessions in a snapshot at `path`. The sessions of the snapshot
+    /// left by a previous sidecar, e.g. one which crashed, are registered again, so that their
+    /// clients can keep using them.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The path of the snapshot file.
+    pub async fn with_session_snapshot(path: PathBuf) -> Self {
+        let previous = Snapshot::load(&path);
+        let server = SidecarServer {
+            session_snapshot: Some(Arc::new(SnapshotFile::new(path))),
+            ..Default::default()
+        };
+        for (session_id, session) in previous.sessions {
+            info!("Restoring session {session_id} from the snapshot");
+            {
+                let mut unclaimed = server.unclaimed.lock().unwrap();
+                unclaimed.sessions.insert(session_id.clone());
+                unclaimed.instances.extend(
+                    session
+                        .runtimes
+                        .keys()
+                        .map(|runtime_id| InstanceId::new(session_id.clone(), runtime_id.clone())),
+                );
+            }
+            if let Some(config) = session.config {
+                server
+                    .clone()
+                    .set_session_config(tarpc::context::current(), session_id.clone(), config)
+                    .await;
+            }
+            for (runtime_id, services) in session.runtimes {
+                let instance_id = InstanceId::new(session_id.clone(), runtime_id);
+                for service in services {
+                    server
+                        .clone()
+                        .register_service_and_flush_queued_actions(
+                            tarpc::context::current(),
+                            instance_id.clone(),
+                            service.queue_id,
+                            service.runtime_meta,
+                            service.service_name,
+                            service.env_name,
+                        )
+                        .await;
+                }
+            }
+        }
+        tokio::spawn({
+            let server = server.clone();
+            async move {
+                tokio::time::sleep(UNCLAIMED_TTL).await;
+                server.remove_unclaimed().await;
+            }
+        });
+        server
+    }
+
+    /// Removes the sessions and runtimes restored from the snapshot which no client used, they
+    /// would be kept forever otherwise, as there is no connection to close.
+    async fn remove_unclaimed(&self) {
+        let unclaimed = std::mem::take(&mut *self.unclaimed.lock().unwrap());
+        for instance_id in unclaimed.instances {
+            info!(
+                "Removing the runtime {} of session {} restored from the snapshot, it was not used",
+                instance_id.runtime_id, instance_id.session_id
+            );
+            if let Some(snapshot) = &self.session_snapshot {
+                snapshot.remove_runtime(&instance_id);
+            }
+            let maybe_session = self.lock_sessions().get(&instance_id.session_id).cloned();
+            if let Some(session) = maybe_session {
+                session.shutdown_runtime(&instance_id.runtime_id).await;
+            }
+        }
+        for session_id in unclaimed.sessions {
+            let claimed = self
+                .session_counter
+                .lock()
+                .expect("Unable to acquire lock on session_counter")
+                .contains_key(&session_id);
+            if !claimed {
+                info!(
+                    "Removing the session {session_id} restored from the snapshot, it was not used"
+                );
+                self.stop_session(&session_id).await;
+            }
+        }
+    }
+
     /// Accepts a new connection and starts processing requests.
     ///
     /// This function creates a new `tarpc` server with the provided `async_channel` and starts
@@ -116,6 +228,7 @@ impl SidecarServer {
         let session_interceptor = tokio::spawn(session_interceptor(
             self.session_counter.clone(),
             self.submitted_payloads.clone(),
+            self.unclaimed.clone(),
             rx,
             tx,
         ));
@@ -168,6 +281,9 @@ impl SidecarServer {
                     }
                 }
                 for instance_id in instances {
+                    if let Some(snapshot) = &self.session_snapshot {
+                        snapshot.remove_runtime(&instance_id);
+                    }
                     let maybe_session = self.lock_sessions().get(&instance_id.session_id).cloned();
                     if let Some(session) = maybe_session {
                         session.shutdown_runtime(&instance_id.runtime_id).await;
@@ -186,7 +302,10 @@ impl SidecarServer {
         match sessions.get(session_id) {
             Some(session) => session.clone(),
             None => {
-                let mut session = SessionInfo::default();
+                let mut session = SessionInfo {
+                    agent_state: self.trace_flusher.agent_state.clone(),
+                    ..SessionInfo::default()
+                };
                 #[cfg(feature = "tracing")]
                 if enabled!(Level::INFO) {
                     session.session_id.clone_from(session_id);
@@ -204,6 +323,9 @@ impl SidecarServer {
     }
 
     async fn stop_session(&self, session_id: &String) {
+        if let Some(snapshot) = &self.session_snapshot {
+            snapshot.remove_session(session_id);
+        }
         let session = match self.lock_sessions().remove(session_id) {
             Some(session) => session,
             None => return,
@@ -287,7 +409,14 @@ impl SidecarServer {
         manual_app_future.app_future.await
     }
 
-    fn send_trace_v04(&self, headers: &SerializedTracerHeaderTags, data: &[u8], target: &Endpoint) {
+    fn send_trace_v04(
+        &self,
+        runtime: &RuntimeInfo,
+        headers: &SerializedTracerHeaderTags,
+        data: &[u8],
+        trace_config: &tracer::Config,
+        tags: &SessionTags,
+    ) {
         let headers = match headers.try_into() {
             Ok(headers) => headers,
             Err(e) => {
@@ -310,6 +439,102 @@ impl SidecarServer {
             return;
         }
 
+        _ = self.send_traces(headers, size, traces, Some(runtime), trace_config, tags);
+    }
+
+    /// Sends traces constructed by the sidecar rather than by a tracer, like synthetic spans. Those
+    /// referring to the trace of a tracer are merged with its chunks by the agent.
+    fn send_sidecar_traces(
+        &self,
+        traces: Vec<Vec<pb::Span>>,
+        trace_config: &tracer::Config,
+        tags: &SessionTags,
+    ) -> Result<(), SidecarError> {
+        let headers = TracerHeaderTags {
+            lang: "rust",
+            tracer_version: env!("CARGO_PKG_VERSION"),
+            ..Default::default()
+        };
+        // the size the payload would have had, if submitted by a tracer
+        let size = rmp_serde::to_vec(&traces).map_or(0, |data| data.len());
+        self.send_traces(headers, size, traces, None, trace_config, tags)
+    }
+
+    /// Returns an error if the traces could not be queued for sending. Traces dropped because
+    /// there is no endpoint or because they cannot be normalized are not an error.
+    ///
+    /// The endpoints of the traces are recorded in the `runtime` which sent them, if any.
+    fn send_traces(
+        &self,
+        headers: TracerHeaderTags,
+        size: usize,
+        mut traces: Vec<Vec<pb::Span>>,
+        runtime: Option<&RuntimeInfo>,
+        trace_config: &tracer::Config,
+        tags: &SessionTags,
+    ) -> Result<(), SidecarError> {
+        let Some(target) = &trace_config.endpoint else {
+            return Ok(());
+        };
+
+        if let Some(rules) = &trace_config.replace_rules {
+            for trace in traces.iter_mut() {
+                replacer::replace_trace_tags(trace, rules);
+            }
+        }
+
+        if let Some(rules) = &trace_config.tagging_rules {
+            for trace in traces.iter_mut() {
+                rules.apply(trace);
+            }
+        }
+
+        if let Some(runtime) = runtime {
+            // Recorded before the obfuscation, which would replace the "?" of the query strings
+            for trace in traces.iter() {
+                let endpoint = match &trace_config.obfuscation_config {
+                    Some(config) => obfuscate::get_trace_endpoint(trace, config),
+                    None => trace_utils::get_trace_endpoint(trace)
+                        .map(|(id, endpoint)| (id, endpoint.to_string())),
+                };
+                if let Some((local_root_span_id, endpoint)) = endpoint {
+                    runtime.add_trace_endpoint(local_root_span_id, endpoint);
+                }
+            }
+        }
+
+        if target.api_key.is_some() {
+            // Without an agent in between, nobody else is going to normalize the traces
+            let mut stats = NormalizationStats::default();
+            traces.retain_mut(|trace| {
+                match normalizer::normalize_trace_with_stats(trace, &mut stats) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        debug!("Dropping trace which cannot be normalized: {e}");
+                        false
+                    }
+                }
+            });
+            self.trace_flusher.record_normalization_stats(&stats);
+            if traces.is_empty() {
+                return Ok(());
+            }
+            // Neither is anybody going to obfuscate them
+            if let Some(obfuscation_config) = &trace_config.obfuscation_config {
+                for span in traces.iter_mut().flatten() {
+                    obfuscate_span(span, obfuscation_config);
+                }
+            }
+        }
+
+        if !tags.is_empty() {
+            for trace in traces.iter_mut() {
+                if let Ok(root_span_index) = trace_utils::get_root_span_index(trace) {
+                    tags.apply_to_root_span(&mut trace[root_span_index]);
+                }
+            }
+        }
+
         let payload = trace_utils::collect_trace_chunks(
             traces,
             &headers,
@@ -319,11 +544,16 @@ impl SidecarServer {
         );
 
         // send trace payload to our trace flusher
-        let data = SendData::new(size, payload, headers, target);
-        self.trace_flusher.enqueue(data);
+        let mut data = SendData::new(size, payload, headers, target);
+        data.set_agent_payload_tags(tags.agent_payload_tags());
+        let result = self.trace_flusher.enqueue(data);
+        if let Err(e) = &result {
+            debug!("Dropping traces: {e}");
+        }
+        result
     }
 
-    async fn compute_stats(&self) -> SidecarStats {
+    pub(crate) async fn compute_stats(&self) -> SidecarStats {
         let mut telemetry_stats_errors = 0;
         let telemetry_stats = join_all({
             let sessions = self.lock_sessions();
@@ -445,8 +675,11 @@ impl SidecarInterface for SidecarServer {
         _context: Context,
         instance_id: InstanceId,
         queue_id: QueueId,
-        actions: Vec<SidecarAction>,
+        mut actions: Vec<SidecarAction>,
     ) -> Self::EnqueueActionsFut {
+        self.get_session(&instance_id.session_id)
+            .get_tags()
+            .apply_to_actions(&mut actions);
         let rt_info = self.get_runtime(&instance_id);
         let mut queue = rt_info.lock_app_or_actions();
         match queue.entry(queue_id) {
@@ -454,8 +687,9 @@ impl SidecarInterface for SidecarServer {
                 AppOrQueue::Queue(ref mut data) => {
                     data.process(actions);
                 }
-                AppOrQueue::App(service_future) => {
+                AppOrQueue::App(service_future, pending) => {
                     let service_future = service_future.clone();
+                    let pending = pending.clone();
                     // drop on stop
                     if actions.iter().any(|action| {
                         matches!(
@@ -467,24 +701,34 @@ impl SidecarInterface for SidecarServer {
                     }) {
                         entry.remove();
                     }
-                    let apps = rt_info.apps.clone();
-                    tokio::spawn(async move {
-                        let service = service_future.await;
-                        let app_future = if let Some(fut) = apps
-                            .lock()
-                            .expect("Unable to acquire lock on apps")
-                            .get(&service)
-                        {
-                            fut.clone()
-                        } else {
-                            return;
-                        };
-                        if let Some(mut app) = app_future.await {
-                            let actions =
-                                EnqueuedTelemetryData::process_immediately(actions, &mut app).await;
-                            app.telemetry.send_msgs(actions).await.ok();
-                        }
-                    });
+                    // While a batch is being sent, the actions are coalesced into the next one
+                    if pending.push(actions) {
+                        let apps = rt_info.apps.clone();
+                        tokio::spawn(async move {
+                            let service = service_future.await;
+                            let app_future = if let Some(fut) = apps
+                                .lock()
+                                .expect("Unable to acquire lock on apps")
+                                .get(&service)
+                            {
+                                fut.clone()
+                            } else {
+                                pending.abandon();
+                                return;
+                            };
+                            if let Some(mut app) = app_future.await {
+                                while let Some(actions) = pending.take() {
+                                    let actions = EnqueuedTelemetryData::process_immediately(
+                                        actions, &mut app,
+                                    )
+                                    .await;
+                                    app.telemetry.send_msgs(actions).await.ok();
+                                }
+                            } else {
+                                pending.abandon();
+                            }
+                        });
+                    }
                 }
             },
             Entry::Vacant(entry) => {
@@ -505,15 +749,27 @@ impl SidecarInterface for SidecarServer {
         service_name: String,
         env_name: String,
     ) -> Self::RegisterServiceAndFlushQueuedActionsFut {
+        if let Some(snapshot) = &self.session_snapshot {
+            snapshot.register_service(
+                &instance_id,
+                &ServiceRegistration {
+                    queue_id,
+                    runtime_meta: runtime_meta.clone(),
+                    service_name: service_name.clone(),
+                    env_name: env_name.clone(),
+                },
+            );
+        }
         // We need a channel to have enqueuing code await
         let (future, completer) = ManualFuture::new();
         let app_or_queue = {
             let rt_info = self.get_runtime(&instance_id);
             let mut app_or_actions = rt_info.lock_app_or_actions();
             match app_or_actions.get(&queue_id) {
-                Some(AppOrQueue::Queue(_)) => {
-                    app_or_actions.insert(queue_id, AppOrQueue::App(future.shared()))
-                }
+                Some(AppOrQueue::Queue(_)) => app_or_actions.insert(
+                    queue_id,
+                    AppOrQueue::App(future.shared(), Default::default()),
+                ),
                 None => Some(AppOrQueue::Queue(EnqueuedTelemetryData::default())),
                 _ => None,
             }
@@ -544,6 +800,7 @@ impl SidecarInterface for SidecarServer {
                     for point in std::mem::take(&mut enqueued_data.points) {
                         actions.push(app.to_telemetry_point(point));
                     }
+                    actions.sort_by_key(ActionPriority::of);
 
                     // drop on stop
                     if actions.iter().any(|action| {
@@ -572,22 +829,60 @@ impl SidecarInterface for SidecarServer {
         session_id: String,
         config: SessionConfig,
     ) -> Self::SetSessionConfigFut {
+        if let Some(snapshot) = &self.session_snapshot {
+            snapshot.set_session_config(&session_id, &config);
+        }
         let session = self.get_session(&session_id);
+        let replace_rules = match self.replace_rules.get(&config.replace_tags) {
+            Ok(rules) => rules,
+            Err(e) => {
+                error!("Failed to parse span tag replacement rules: {e}");
+                None
+            }
+        };
+        let tagging_rules = match TaggingRules::parse(&config.tagging_rules) {
+            Ok(rules) => rules,
+            Err(e) => {
+                error!("Failed to parse span tagging rules: {e}");
+                None
+            }
+        };
+        let obfuscation_config = if config.obfuscation_config.trim().is_empty() {
+            None
+        } else {
+            match ObfuscationConfig::from_agent_config_json(&config.obfuscation_config) {
+                Ok(obfuscation_config) => Some(Arc::new(obfuscation_config)),
+                Err(e) => {
+                    error!("Failed to parse the obfuscation config: {e}");
+                    None
+                }
+            }
+        };
         session.modify_telemetry_config(|cfg| {
             let endpoint =
                 get_product_endpoint(ddtelemetry::config::PROD_INTAKE_SUBDOMAIN, &config.endpoint);
             cfg.set_endpoint(endpoint).ok();
         });
+        if config.agentless && !matches!(&config.agentless_endpoint, Some(e) if e.api_key.is_some())
+        {
+            warn!("Agentless trace submission requires an API key, submitting traces to the agent");
+        }
         session.modify_trace_config(|cfg| {
             let endpoint = get_product_endpoint(
                 datadog_trace_utils::config_utils::PROD_INTAKE_SUBDOMAIN,
-                &config.endpoint,
+                config.trace_endpoint(),
             );
             cfg.set_endpoint(endpoint).ok();
+            cfg.replace_rules.clone_from(&replace_rules);
+            cfg.tagging_rules.clone_from(&tagging_rules);
+            cfg.obfuscation_config.clone_from(&obfuscation_config);
         });
+        session.set_intake_endpoint(config.trace_endpoint().clone());
+        session.get_tags().clone_from(&config.tags);
         session.configure_dogstatsd(|dogstatsd| {
             dogstatsd.set_endpoint(config.dogstatsd_endpoint.clone());
         });
+        session.prefetch_agent_state(&config.endpoint);
         self.trace_flusher
             .interval_ms
             .store(config.flush_interval.as_millis() as u64, Ordering::Relaxed);
@@ -631,9 +926,25 @@ impl SidecarInterface for SidecarServer {
         })
     }
 
+    type UpdateSessionTagsFut = NoResponse;
+
+    fn update_session_tags(
+        self,
+        _: Context,
+        session_id: String,
+        tags: SessionTagsUpdate,
+    ) -> Self::UpdateSessionTagsFut {
+        self.get_session(&session_id).get_tags().update(tags);
+
+        no_response()
+    }
+
     type ShutdownRuntimeFut = NoResponse;
 
     fn shutdown_runtime(self, _: Context, instance_id: InstanceId) -> Self::ShutdownRuntimeFut {
+        if let Some(snapshot) = &self.session_snapshot {
+            snapshot.remove_runtime(&instance_id);
+        }
         let session = self.get_session(&instance_id.session_id);
         tokio::spawn(async move { session.shutdown_runtime(&instance_id.runtime_id).await });
 
@@ -657,16 +968,21 @@ impl SidecarInterface for SidecarServer {
         len: usize,
         headers: SerializedTracerHeaderTags,
     ) -> Self::SendTraceV04ShmFut {
-        if let Some(endpoint) = self
-            .get_session(&instance_id.session_id)
-            .get_trace_config()
-            .endpoint
-            .clone()
-        {
+        let session = self.get_session(&instance_id.session_id);
+        let trace_config = session.get_trace_config().clone();
+        if trace_config.endpoint.is_some() {
+            let tags = session.get_tags().clone();
+            let runtime = self.get_runtime(&instance_id);
             tokio::spawn(async move {
                 match handle.map() {
                     Ok(mapped) => {
-                        self.send_trace_v04(&headers, &mapped.as_slice()[..len], &endpoint);
+                        self.send_trace_v04(
+                            &runtime,
+                            &headers,
+                            &mapped.as_slice()[..len],
+                            &trace_config,
+                            &tags,
+                        );
                     }
                     Err(e) => error!("Failed mapping shared trace data memory: {}", e),
                 }
@@ -682,23 +998,206 @@ impl SidecarInterface for SidecarServer {
         self,
         _: Context,
         instance_id: InstanceId,
-        data: Vec<u8>,
+        data: LargePayload,
         headers: SerializedTracerHeaderTags,
     ) -> Self::SendTraceV04BytesFut {
-        if let Some(endpoint) = self
-            .get_session(&instance_id.session_id)
-            .get_trace_config()
-            .endpoint
-            .clone()
-        {
+        let session = self.get_session(&instance_id.session_id);
+        let trace_config = session.get_trace_config().clone();
+        if trace_config.endpoint.is_some() {
+            let tags = session.get_tags().clone();
+            let runtime = self.get_runtime(&instance_id);
             tokio::spawn(async move {
-                self.send_trace_v04(&headers, data.as_slice(), &endpoint);
+                match data.map() {
+                    Ok(data) => {
+                        self.send_trace_v04(&runtime, &headers, &data, &trace_config, &tags);
+                    }
+                    Err(e) => error!("Failed mapping shared trace data memory: {}", e),
+                }
             });
         }
 
         no_response()
     }
 
+    type SendSyntheticSpanFut = NoResponse;
+
+    fn send_synthetic_span(
+        self,
+        _: Context,
+        instance_id: InstanceId,
+        span: SyntheticSpan,
+    ) -> Self::SendSyntheticSpanFut {
+        let session = self.get_session(&instance_id.session_id);
+        let trace_config = session.get_trace_config().clone();
+        if trace_config.endpoint.is_some() {
+            let tags = session.get_tags().clone();
+            _ = self.send_sidecar_traces(vec![vec![span.into_span()]], &trace_config, &tags);
+        }
+
+        no_response()
+    }
+
+    type SendSyntheticSpanAckedFut =
+        Pin<Box<dyn Send + futures::Future<Output = Result<Option<u16>, SidecarError>>>>;
+
+    fn send_synthetic_span_acked(
+        self,
+        _: Context,
+        instance_id: InstanceId,
+        span: SyntheticSpan,
+        flush_timeout: Option<Duration>,
+    ) -> Self::SendSyntheticSpanAckedFut {
+        let Some(session) = self.lock_sessions().get(&instance_id.session_id).cloned() else {
+            return Box::pin(future::ready(Err(SidecarError::UnknownInstance(
+                instance_id,
+            ))));
+        };
+        let trace_config = session.get_trace_config().clone();
+        // The session was never configured
+        if trace_config.endpoint.is_none() {
+            return Box::pin(future::ready(Err(SidecarError::UnknownInstance(
+                instance_id,
+            ))));
+        }
+        let tags = session.get_tags().clone();
+        if let Err(e) = self.send_sidecar_traces(vec![vec![span.into_span()]], &trace_config, &tags)
+        {
+            return Box::pin(future::ready(Err(e)));
+        }
+
+        let Some(timeout) = flush_timeout else {
+            return Box::pin(future::ready(Ok(None)));
+        };
+        let flusher = self.trace_flusher.clone();
+        Box::pin(async move {
+            let results = tokio::time::timeout(timeout, flusher.flush())
+                .await
+                .map_err(|_| SidecarError::TimedOut(timeout))?;
+            // Only the payload sent to the endpoint of this session tells about the span
+            let endpoint = trace_config.endpoint.as_ref().map(|e| &e.url);
+            results
+                .into_iter()
+                .find(|(target, _)| Some(&target.url) == endpoint)
+                .map(|(_, result)| result)
+                .transpose()
+        })
+    }
+
+    type StartSpanFut = NoResponse;
+
+    fn start_span(
+        self,
+        _: Context,
+        instance_id: InstanceId,
+        queue_id: QueueId,
+        span: SpanStart,
+    ) -> Self::StartSpanFut {
+        let runtime = self.get_runtime(&instance_id);
+        let mut open_spans = runtime.lock_open_spans();
+        if let Err(e) = open_spans.entry(queue_id).or_default().start(span) {
+            warn!("Failed to start span: {e}");
+        }
+
+        no_response()
+    }
+
+    type FinishSpanFut = NoResponse;
+
+    fn finish_span(
+        self,
+        _: Context,
+        instance_id: InstanceId,
+        queue_id: QueueId,
+        span: SpanFinish,
+    ) -> Self::FinishSpanFut {
+        let runtime = self.get_runtime(&instance_id);
+        let finished = {
+            let mut open_spans = runtime.lock_open_spans();
+            match open_spans.get_mut(&queue_id) {
+                Some(queue_spans) => {
+                    let finished = queue_spans.finish(span);
+                    if queue_spans.is_empty() {
+                        open_spans.remove(&queue_id);
+                    }
+                    finished
+                }
+                None => Err(anyhow::anyhow!("no span was started by the queue")),
+            }
+        };
+
+        match finished {
+            Ok(Some(chunk)) => {
+                let session = self.get_session(&instance_id.session_id);
+                let trace_config = session.get_trace_config().clone();
+                if trace_config.endpoint.is_some() {
+                    let tags = session.get_tags().clone();
+                    _ = self.send_sidecar_traces(vec![chunk], &trace_config, &tags);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to finish span: {e}"),
+        }
+
+        no_response()
+    }
+
+    type SendProfileShmFut = NoResponse;
+
+    fn send_profile_shm(
+        self,
+        _: Context,
+        instance_id: InstanceId,
+        handle: ShmHandle,
+        len: usize,
+        mut upload: ProfileUpload,
+    ) -> Self::SendProfileShmFut {
+        self.get_session(&instance_id.session_id)
+            .get_tags()
+            .apply_to_exporter_tags(&mut upload.exporter.tags);
+        // the exporter blocks on its own runtime
+        let task = tokio::task::spawn_blocking(move || {
+            profile_upload::upload_profile(handle, len, upload).map_err(|e| {
+                error!("Failed uploading profile: {e:?}");
+                SidecarError::from(e)
+            })
+        });
+        self.get_runtime(&instance_id).add_profile_upload(task);
+
+        no_response()
+    }
+
+    type ProxyUploadFut = NoResponse;
+
+    fn proxy_upload(
+        self,
+        _: Context,
+        instance_id: InstanceId,
+        handle: ShmHandle,
+        len: usize,
+        upload: ProxyUpload,
+    ) -> Self::ProxyUploadFut {
+        let Some(endpoint) = self
+            .get_session(&instance_id.session_id)
+            .get_intake_endpoint()
+        else {
+            warn!("Dropping the proxied upload of an unconfigured session");
+            return no_response();
+        };
+        let uploader = self.proxy_uploader.clone();
+        let task = tokio::spawn(async move {
+            uploader
+                .upload(&endpoint, handle, len, upload)
+                .await
+                .map_err(|e| {
+                    error!("Failed proxying upload: {e:?}");
+                    SidecarError::from(e)
+                })
+        });
+        self.get_runtime(&instance_id).add_profile_upload(task);
+
+        no_response()
+    }
+
     type SendDogstatsdActionsFut = NoResponse;
 
     fn send_dogstatsd_actions(
@@ -725,7 +1224,73 @@ impl SidecarInterface for SidecarServer {
                 error!("Failed flushing traces: {e:?}");
             }
         }
-        tokio::spawn(async move { flusher.flush().await }).map(report_result)
+        tokio::spawn(async move {
+            flusher.flush().await;
+        })
+        .map(report_result)
+    }
+
+    type FlushAllFut =
+        Pin<Box<dyn Send + futures::Future<Output = Result<FlushAllResult, SidecarError>>>>;
+
+    fn flush_all(
+        self,
+        _: Context,
+        instance_id: InstanceId,
+        timeout: Duration,
+    ) -> Self::FlushAllFut {
+        let Some(session) = self.lock_sessions().get(&instance_id.session_id).cloned() else {
+            return Box::pin(future::ready(Err(SidecarError::UnknownInstance(
+                instance_id,
+            ))));
+        };
+        // An unknown runtime has not sent anything, there is nothing else to flush then.
+        let runtime = session
+            .lock_runtimes()
+            .get(&instance_id.runtime_id)
+            .cloned();
+        let flusher = self.trace_flusher.clone();
+        Box::pin(async move {
+            let runtime_flush = async move {
+                match runtime {
+                    Some(runtime) => runtime.flush().await,
+                    None => FlushAllResult::default(),
+                }
+            };
+            let flush = future::join(flusher.flush(), runtime_flush);
+            match tokio::time::timeout(timeout, flush).await {
+                Ok((traces, mut result)) => {
+                    result.traces = traces
+                        .into_iter()
+                        .map(|(endpoint, result)| (endpoint.url.to_string(), result))
+                        .collect();
+                    Ok(result)
+                }
+                Err(_) => {
+                    warn!("Timed out flushing all data of {instance_id:?} after {timeout:?}");
+                    Err(SidecarError::TimedOut(timeout))
+                }
+            }
+        })
+    }
+
+    type TakeTraceEndpointsFut = Ready<Vec<(u64, String)>>;
+
+    fn take_trace_endpoints(
+        self,
+        _: Context,
+        instance_id: InstanceId,
+    ) -> Self::TakeTraceEndpointsFut {
+        let session = self.lock_sessions().get(&instance_id.session_id).cloned();
+        let runtime = session.and_then(|s| s.lock_runtimes().get(&instance_id.runtime_id).cloned());
+        future::ready(runtime.map_or_else(Vec::new, |r| r.take_trace_endpoints()))
+    }
+
+    type GetAgentStateFut = Ready<String>;
+
+    fn get_agent_state(self, _: Context, session_id: String) -> Self::GetAgentStateFut {
+        let session = self.lock_sessions().get(&session_id).cloned();
+        future::ready(session.map_or_else(|| "{}".to_string(), |s| s.get_agent_state_json()))
     }
 
     type PingFut = Ready<()>;
@@ -756,6 +1321,7 @@ impl SidecarInterface for SidecarServer {
 async fn session_interceptor(
     session_counter: Arc<Mutex<HashMap<String, u32>>>,
     submitted_payload_count: Arc<AtomicU64>,
+    unclaimed: Arc<Mutex<Unclaimed>>,
     mut rx: tokio::sync::mpsc::Receiver<(
         ServeSidecarInterface<SidecarServer>,
         InFlightRequest<SidecarInterfaceRequest, SidecarInterfaceResponse>,
@@ -778,7 +1344,9 @@ async fn session_interceptor(
         let instance: RequestIdentifier = req.get().extract_identifier();
         if tx.send((serve, req)).await.is_ok() {
             if let RequestIdentifier::InstanceId(ref instance_id) = instance {
-                instances.insert(instance_id.clone());
+                if instances.insert(instance_id.clone()) {
+                    unclaimed.lock().unwrap().instances.remove(instance_id);
+                }
             }
             if let RequestIdentifier::SessionId(session)
             | RequestIdentifier::InstanceId(InstanceId {
@@ -787,6 +1355,7 @@ async fn session_interceptor(
             }) = instance
             {
                 if sessions.insert(session.clone()) {
+                    unclaimed.lock().unwrap().sessions.remove(&session);
                     match session_counter
                         .lock()
                         .expect("Unable to obtain lock on session counter")
@@ -0,0 +1,137 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! Spans constructed by the sidecar itself, rather than by a tracer, so that infrastructure events
+//! like the start of a process or a crash show up in the traces.
+
+use datadog_trace_protobuf::pb;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+/// Marks the spans which were not created by a tracer.
+pub const SYNTHETIC_SPAN_META_KEY: &str = "_dd.synthetic";
+
+/// The infrastructure event a synthetic span represents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyntheticSpanKind {
+    ProcessStart,
+    ProcessStop,
+    /// The (cold) start of a serverless instance.
+    ColdStart,
+    MiniAgentStart,
+    MiniAgentStop,
+    /// Marks the point a process crashed, the span is flagged as an error.
+    Crash,
+}
+
+impl SyntheticSpanKind {
+    /// The name of the spans of this kind.
+    pub fn span_name(self) -> &'static str {
+        match self {
+            SyntheticSpanKind::ProcessStart => "process.start",
+            SyntheticSpanKind::ProcessStop => "process.stop",
+            SyntheticSpanKind::ColdStart => "cold_start",
+            SyntheticSpanKind::MiniAgentStart => "mini_agent.start",
+            SyntheticSpanKind::MiniAgentStop => "mini_agent.stop",
+            SyntheticSpanKind::Crash => "crash",
+        }
+    }
+}
+
+/// A standalone span submitted with `send_synthetic_span`.
+///
+/// The span is attached to the trace of `trace_id` as child of `parent_id`, when set. Otherwise it
+/// is the root span of a new trace of its own.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SyntheticSpan {
+    pub kind: SyntheticSpanKind,
+    pub service: String,
+    /// Defaults to the name of the span.
+    pub resource: Option<String>,
+    pub trace_id: Option<u64>,
+    pub parent_id: Option<u64>,
+    pub start: SystemTime,
+    pub duration: Duration,
+    pub meta: HashMap<String, String>,
+    pub metrics: HashMap<String, f64>,
+}
+
+impl SyntheticSpan {
+    /// A span of the given kind starting now, lasting no time.
+    pub fn new(kind: SyntheticSpanKind, service: String) -> Self {
+        SyntheticSpan {
+            kind,
+            service,
+            resource: None,
+            trace_id: None,
+            parent_id: None,
+            start: SystemTime::now(),
+            duration: Duration::ZERO,
+            meta: HashMap::new(),
+            metrics: HashMap::new(),
+        }
+    }
+
+    /// Builds the span, generating the ids which were not provided.
+    pub fn into_span(self) -> pb::Span {
+        let name = self.kind.span_name();
+        let span_id = random_id();
+        let mut meta = self.meta;
+        meta.insert(SYNTHETIC_SPAN_META_KEY.to_string(), "true".to_string());
+        let start = self
+            .start
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |start| start.as_nanos() as i64);
+        pb::Span {
+            service: self.service,
+            name: name.to_string(),
+            resource: self.resource.unwrap_or_else(|| name.to_string()),
+            trace_id: self.trace_id.unwrap_or(span_id),
+            span_id,
+            parent_id: self.parent_id.unwrap_or(0),
+            start,
+            duration: self.duration.as_nanos() as i64,
+            error: (self.kind == SyntheticSpanKind::Crash) as i32,
+            meta,
+            metrics: self.metrics,
+            r#type: "custom".to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+pub(crate) fn random_id() -> u64 {
+    // 0 is not a valid id, and tracers stick to 63 bits
+    rand::thread_rng().gen_range(1..=i64::MAX as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_into_span() {
+        let mut synthetic = SyntheticSpan::new(SyntheticSpanKind::Crash, "app".to_string());
+        synthetic.trace_id = Some(42);
+        synthetic.parent_id = Some(7);
+        synthetic.start = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        let span = synthetic.into_span();
+        assert_eq!("crash", span.name);
+        assert_eq!("crash", span.resource);
+        assert_eq!("app", span.service);
+        assert_eq!(42, span.trace_id);
+        assert_eq!(7, span.parent_id);
+        assert_ne!(0, span.span_id);
+        assert_eq!(1_000_000_000, span.start);
+        assert_eq!(1, span.error);
+        assert_eq!("true", span.meta[SYNTHETIC_SPAN_META_KEY]);
+
+        let span =
+            SyntheticSpan::new(SyntheticSpanKind::ProcessStart, "app".to_string()).into_span();
+        assert_eq!(span.span_id, span.trace_id);
+        assert_eq!(0, span.parent_id);
+        assert_eq!(0, span.error);
+    }
+}
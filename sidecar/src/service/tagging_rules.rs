@@ -0,0 +1,148 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use datadog_trace_protobuf::pb;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A rule adding tags to the spans it matches. Each matcher is optional, a rule without matchers
+/// applies to all spans.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TaggingRule {
+    /// The service of the span.
+    service: Option<String>,
+    /// The `env` meta of the span.
+    env: Option<String>,
+    /// A regex the name of the span must match.
+    name: Option<String>,
+    tags: HashMap<String, String>,
+    /// Whether tags already present on the span are overwritten.
+    #[serde(default, rename = "override")]
+    override_existing: bool,
+}
+
+struct CompiledRule {
+    service: Option<String>,
+    env: Option<String>,
+    name: Option<Regex>,
+    tags: Vec<(String, String)>,
+    override_existing: bool,
+}
+
+impl CompiledRule {
+    fn matches(&self, span: &pb::Span) -> bool {
+        if matches!(&self.service, Some(service) if *service != span.service) {
+            return false;
+        }
+        if matches!(&self.env, Some(env) if span.meta.get("env") != Some(env)) {
+            return false;
+        }
+        !matches!(&self.name, Some(name) if !name.is_match(&span.name))
+    }
+}
+
+/// Compiled span tagging rules, letting operators retag the traffic of a session centrally. They
+/// are configured as a JSON array:
+///
+/// ```json
+/// [{"service": "web", "env": "prod", "name": "^http\\.", "tags": {"team": "checkout"}}]
+/// ```
+///
+/// `service` and `env` match the service and the `env` meta of the span, `name` is a regex its
+/// name must match. Tags already present on the span are kept, unless `"override": true` is set.
+pub struct TaggingRules {
+    rules: Vec<CompiledRule>,
+}
+
+impl TaggingRules {
+    /// Compiles the JSON representation of the rules, or returns None if there are no rules.
+    pub fn parse(rules_json: &str) -> anyhow::Result<Option<Arc<TaggingRules>>> {
+        if rules_json.trim().is_empty() {
+            return Ok(None);
+        }
+        let rules: Vec<TaggingRule> = serde_json::from_str(rules_json)?;
+        if rules.is_empty() {
+            return Ok(None);
+        }
+        let rules = rules
+            .into_iter()
+            .map(|rule| {
+                Ok(CompiledRule {
+                    service: rule.service,
+                    env: rule.env,
+                    name: rule.name.as_deref().map(Regex::new).transpose()?,
+                    tags: rule.tags.into_iter().collect(),
+                    override_existing: rule.override_existing,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Some(Arc::new(TaggingRules { rules })))
+    }
+
+    /// Applies the rules to every span of the trace, in order: with `override` set, tags of later
+    /// rules win over earlier ones.
+    pub fn apply(&self, trace: &mut [pb::Span]) {
+        for span in trace.iter_mut() {
+            for rule in self.rules.iter().filter(|rule| rule.matches(span)) {
+                for (key, value) in rule.tags.iter() {
+                    if rule.override_existing || !span.meta.contains_key(key) {
+                        span.meta.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"[
+        {"service": "web", "tags": {"team": "checkout", "tier": "1"}},
+        {"service": "web", "env": "prod", "name": "^http\\.", "tags": {"tier": "0"}, "override": true},
+        {"env": "staging", "tags": {"team": "qa"}}
+    ]"#;
+
+    fn span(service: &str, env: &str, name: &str) -> pb::Span {
+        pb::Span {
+            service: service.to_string(),
+            name: name.to_string(),
+            meta: HashMap::from([
+                ("env".to_string(), env.to_string()),
+                ("team".to_string(), "existing".to_string()),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rules_are_applied_in_order() {
+        let rules = TaggingRules::parse(RULES).unwrap().unwrap();
+        let mut trace = vec![
+            span("web", "prod", "http.request"),
+            span("web", "prod", "db.query"),
+            span("web", "staging", "http.request"),
+            span("worker", "prod", "http.request"),
+        ];
+        rules.apply(&mut trace);
+
+        assert_eq!("existing", trace[0].meta["team"]);
+        assert_eq!("0", trace[0].meta["tier"]);
+        assert_eq!("1", trace[1].meta["tier"]);
+        assert_eq!("1", trace[2].meta["tier"]);
+        assert_eq!("existing", trace[2].meta["team"]);
+        assert!(!trace[3].meta.contains_key("tier"));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(TaggingRules::parse("").unwrap().is_none());
+        assert!(TaggingRules::parse("[]").unwrap().is_none());
+        assert!(TaggingRules::parse(r#"[{"name": "(", "tags": {}}]"#).is_err());
+        assert!(TaggingRules::parse(r#"[{"services": "web", "tags": {}}]"#).is_err());
+    }
+}
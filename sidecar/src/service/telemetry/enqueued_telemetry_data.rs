@@ -4,20 +4,20 @@
 use ddcommon::tag::Tag;
 use ddtelemetry::data;
 use ddtelemetry::metrics::MetricContext;
-use ddtelemetry::worker::store::Store;
-use ddtelemetry::worker::{TelemetryActions, MAX_ITEMS};
+use ddtelemetry::worker::store::{QueueHashMap, Store};
+use ddtelemetry::worker::{LifecycleAction, TelemetryActions, MAX_ITEMS};
 use futures::future::Shared;
 use futures::FutureExt;
 use lazy_static::lazy_static;
 use manual_future::ManualFuture;
 use serde::Deserialize;
 use serde_with::{serde_as, VecSkipError};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::Sub;
 use std::path::PathBuf;
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant, SystemTime};
 use tracing::warn;
 
@@ -39,10 +39,115 @@ struct ComposerPackages {
     packages: Vec<data::Dependency>,
 }
 
+/// The order in which queued actions are sent once the app is registered: the lifecycle start
+/// comes first, then the data, then the flushes and the lifecycle stop last.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum ActionPriority {
+    Start,
+    Data,
+    Flush,
+    Stop,
+}
+
+impl ActionPriority {
+    pub(crate) fn of(action: &TelemetryActions) -> Self {
+        match action {
+            TelemetryActions::Lifecycle(LifecycleAction::Start) => ActionPriority::Start,
+            TelemetryActions::Lifecycle(LifecycleAction::Stop) => ActionPriority::Stop,
+            TelemetryActions::Lifecycle(_) => ActionPriority::Flush,
+            _ => ActionPriority::Data,
+        }
+    }
+}
+
+/// Drops the actions superseded within a batch: configurations collapse to their latest value per
+/// name and repeated lifecycle actions are only kept once. The remaining actions are sorted by
+/// their [`ActionPriority`].
+pub(crate) fn coalesce_actions(actions: Vec<TelemetryActions>) -> Vec<TelemetryActions> {
+    let mut configurations = HashMap::new();
+    let mut lifecycles = vec![];
+    let mut coalesced = Vec::with_capacity(actions.len());
+    for action in actions {
+        match &action {
+            TelemetryActions::AddConfig(configuration) => {
+                if let Some(previous) =
+                    configurations.insert(configuration.name.clone(), coalesced.len())
+                {
+                    coalesced[previous] = None;
+                }
+            }
+            TelemetryActions::Lifecycle(lifecycle) => {
+                if lifecycles.contains(lifecycle) {
+                    continue;
+                }
+                lifecycles.push(*lifecycle);
+            }
+            _ => {}
+        }
+        coalesced.push(Some(action));
+    }
+    let mut actions: Vec<_> = coalesced.into_iter().flatten().collect();
+    actions.sort_by_key(ActionPriority::of);
+    actions
+}
+
+#[derive(Default)]
+struct PendingBatch {
+    actions: Vec<SidecarAction>,
+    sending: bool,
+}
+
+/// The actions for an already registered app. They pile up while a previous batch is being sent
+/// to the app, so that a burst of actions is coalesced into a single batch instead of being sent
+/// one by one.
+#[derive(Default)]
+pub(crate) struct PendingActions(Mutex<PendingBatch>);
+
+impl PendingActions {
+    /// Adds the actions to the next batch. Returns whether the caller has to send it, i.e. whether
+    /// no batch is being sent yet.
+    pub(crate) fn push(&self, actions: Vec<SidecarAction>) -> bool {
+        let mut batch = self
+            .0
+            .lock()
+            .expect("Unable to acquire lock on pending actions");
+        batch.actions.extend(actions);
+        !std::mem::replace(&mut batch.sending, true)
+    }
+
+    /// Takes the next batch to send, or returns None, once everything was sent.
+    pub(crate) fn take(&self) -> Option<Vec<SidecarAction>> {
+        let mut batch = self
+            .0
+            .lock()
+            .expect("Unable to acquire lock on pending actions");
+        if batch.actions.is_empty() {
+            batch.sending = false;
+            return None;
+        }
+        Some(std::mem::take(&mut batch.actions))
+    }
+
+    /// Drops the pending actions, when the app they were meant for is gone.
+    pub(crate) fn abandon(&self) {
+        let mut batch = self
+            .0
+            .lock()
+            .expect("Unable to acquire lock on pending actions");
+        batch.actions.clear();
+        batch.sending = false;
+    }
+}
+
 /// `EnqueuedTelemetryData` is a structure that holds telemetry data that is queued for processing.
+///
+/// Redundant actions are coalesced while queued: configurations collapse to the latest value per
+/// name and repeated lifecycle actions (e.g. flush requests) are only kept once.
 pub(crate) struct EnqueuedTelemetryData {
     dependencies: Store<data::Dependency>,
-    configurations: Store<data::Configuration>,
+    configurations: QueueHashMap<String, data::Configuration>,
+    /// The names of the configurations changed since they were last extracted.
+    unflushed_configurations: HashSet<String>,
     integrations: Store<data::Integration>,
     pub(crate) metrics: Vec<MetricContext>,
     pub(crate) points: Vec<(String, f64, Vec<Tag>)>,
@@ -54,7 +159,8 @@ impl Default for EnqueuedTelemetryData {
     fn default() -> Self {
         Self {
             dependencies: Store::new(MAX_ITEMS),
-            configurations: Store::new(MAX_ITEMS),
+            configurations: QueueHashMap::default(),
+            unflushed_configurations: HashSet::new(),
             integrations: Store::new(MAX_ITEMS),
             metrics: Vec::new(),
             points: Vec::new(),
@@ -74,7 +180,7 @@ impl EnqueuedTelemetryData {
         for action in actions {
             match action {
                 SidecarAction::Telemetry(TelemetryActions::AddConfig(c)) => {
-                    self.configurations.insert(c)
+                    self.add_configuration(c)
                 }
                 SidecarAction::Telemetry(TelemetryActions::AddDependecy(d)) => {
                     self.dependencies.insert(d)
@@ -82,7 +188,7 @@ impl EnqueuedTelemetryData {
                 SidecarAction::Telemetry(TelemetryActions::AddIntegration(i)) => {
                     self.integrations.insert(i)
                 }
-                SidecarAction::Telemetry(other) => self.actions.push(other),
+                SidecarAction::Telemetry(other) => self.add_action(other),
                 SidecarAction::PhpComposerTelemetryFile(composer_path) => self
                     .computed_dependencies
                     .push(Self::extract_composer_telemetry(composer_path).shared()),
@@ -93,6 +199,38 @@ impl EnqueuedTelemetryData {
         }
     }
 
+    fn add_configuration(&mut self, configuration: data::Configuration) {
+        match self.configurations.get(&configuration.name) {
+            Some(stored) if *stored == configuration => return,
+            Some(_) => {}
+            None => {
+                if self.configurations.len() == MAX_ITEMS {
+                    if let Some((name, _)) = self.configurations.pop_front() {
+                        self.unflushed_configurations.remove(&name);
+                    }
+                }
+            }
+        }
+        self.unflushed_configurations
+            .insert(configuration.name.clone());
+        self.configurations
+            .insert(configuration.name.clone(), configuration);
+    }
+
+    fn add_action(&mut self, action: TelemetryActions) {
+        // a lifecycle action, like a flush, is only needed once per queue
+        if let TelemetryActions::Lifecycle(lifecycle) = &action {
+            if self
+                .actions
+                .iter()
+                .any(|queued| matches!(queued, TelemetryActions::Lifecycle(l) if l == lifecycle))
+            {
+                return;
+            }
+        }
+        self.actions.push(action);
+    }
+
     /// Creates a new `EnqueuedTelemetryData` instance and processes a vector of `SidecarAction`.
     ///
     /// # Arguments
@@ -124,16 +262,19 @@ impl EnqueuedTelemetryData {
         for d in self.dependencies.unflushed() {
             actions.push(TelemetryActions::AddDependecy(d.clone()));
         }
-        for c in self.configurations.unflushed() {
-            actions.push(TelemetryActions::AddConfig(c.clone()));
+        for (name, c) in self.configurations.iter() {
+            if self.unflushed_configurations.contains(name) {
+                actions.push(TelemetryActions::AddConfig(c.clone()));
+            }
         }
+        self.unflushed_configurations.clear();
         for i in self.integrations.unflushed() {
             actions.push(TelemetryActions::AddIntegration(i.clone()));
         }
     }
 
     /// Processes a vector of `SidecarAction` immediately and returns a vector of
-    /// `TelemetryActions`.
+    /// `TelemetryActions`, coalesced like [`coalesce_actions`].
     ///
     /// # Arguments
     ///
@@ -162,7 +303,7 @@ impl EnqueuedTelemetryData {
                 }
             }
         }
-        actions
+        coalesce_actions(actions)
     }
 
     /// Parses and extracts telemetry data from a vendor/composer/installed.json file and returns a
@@ -242,8 +383,8 @@ impl EnqueuedTelemetryData {
         EnqueuedTelemetryStats {
             dependencies_stored: self.dependencies.len_stored() as u32,
             dependencies_unflushed: self.dependencies.len_unflushed() as u32,
-            configurations_stored: self.configurations.len_stored() as u32,
-            configurations_unflushed: self.configurations.len_unflushed() as u32,
+            configurations_stored: self.configurations.len() as u32,
+            configurations_unflushed: self.unflushed_configurations.len() as u32,
             integrations_stored: self.integrations.len_stored() as u32,
             integrations_unflushed: self.integrations.len_unflushed() as u32,
             metrics: self.metrics.len() as u32,
@@ -280,6 +421,118 @@ mod tests {
             .into()
         );
     }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_queued_actions_are_coalesced() {
+        let config = |value: &str| {
+            SidecarAction::Telemetry(TelemetryActions::AddConfig(data::Configuration {
+                name: "DD_TRACE_SAMPLE_RATE".to_string(),
+                value: value.to_string(),
+                origin: data::ConfigurationOrigin::Code,
+            }))
+        };
+        let lifecycle = |action| SidecarAction::Telemetry(TelemetryActions::Lifecycle(action));
+
+        let mut data = EnqueuedTelemetryData::processed(vec![
+            config("0.5"),
+            lifecycle(LifecycleAction::FlushData),
+            config("1"),
+        ]);
+        data.process(vec![
+            lifecycle(LifecycleAction::FlushData),
+            config("0.5"),
+            lifecycle(LifecycleAction::Stop),
+        ]);
+
+        assert_eq!(1, data.stats().configurations_unflushed);
+
+        let mut actions = vec![];
+        data.extract_telemetry_actions(&mut actions).await;
+        assert!(matches!(
+            &actions[..],
+            [TelemetryActions::AddConfig(data::Configuration { value, .. })] if value == "0.5"
+        ));
+        assert_eq!(2, data.actions.len());
+        assert_eq!(1, data.stats().configurations_stored);
+        assert_eq!(0, data.stats().configurations_unflushed);
+
+        // an unchanged configuration is not sent again
+        data.process(vec![config("0.5")]);
+        assert_eq!(0, data.stats().configurations_unflushed);
+        data.process(vec![config("0.25")]);
+        assert_eq!(1, data.stats().configurations_unflushed);
+    }
+
+    #[test]
+    fn test_coalesce_actions() {
+        let config = |value: &str| {
+            TelemetryActions::AddConfig(data::Configuration {
+                name: "DD_TRACE_SAMPLE_RATE".to_string(),
+                value: value.to_string(),
+                origin: data::ConfigurationOrigin::Code,
+            })
+        };
+        let actions = coalesce_actions(vec![
+            TelemetryActions::Lifecycle(LifecycleAction::FlushData),
+            config("0.5"),
+            TelemetryActions::Lifecycle(LifecycleAction::FlushData),
+            config("1"),
+        ]);
+        assert_eq!(2, actions.len());
+        assert!(matches!(
+            &actions[0],
+            TelemetryActions::AddConfig(data::Configuration { value, .. }) if value == "1"
+        ));
+        assert!(matches!(
+            &actions[1],
+            TelemetryActions::Lifecycle(LifecycleAction::FlushData)
+        ));
+    }
+
+    #[test]
+    fn test_pending_actions() {
+        let flush = || {
+            vec![SidecarAction::Telemetry(TelemetryActions::Lifecycle(
+                LifecycleAction::FlushData,
+            ))]
+        };
+        let pending = PendingActions::default();
+        assert!(pending.push(flush()));
+        // a batch is being sent, the next actions are picked up by the sender
+        assert!(!pending.push(flush()));
+        assert_eq!(2, pending.take().unwrap().len());
+        assert!(pending.take().is_none());
+        // everything was sent, the next caller sends again
+        assert!(pending.push(flush()));
+    }
+
+    #[test]
+    fn test_action_priority() {
+        let mut actions = vec![
+            TelemetryActions::Lifecycle(LifecycleAction::Stop),
+            TelemetryActions::Lifecycle(LifecycleAction::FlushData),
+            TelemetryActions::AddIntegration(data::Integration {
+                name: "redis".to_string(),
+                enabled: true,
+                version: None,
+                compatible: None,
+                auto_enabled: None,
+            }),
+            TelemetryActions::Lifecycle(LifecycleAction::Start),
+        ];
+        actions.sort_by_key(ActionPriority::of);
+        let priorities: Vec<_> = actions.iter().map(ActionPriority::of).collect();
+        assert_eq!(
+            vec![
+                ActionPriority::Start,
+                ActionPriority::Data,
+                ActionPriority::Flush,
+                ActionPriority::Stop
+            ],
+            priorities
+        );
+    }
 }
 
 //TODO: APMSP-1079 - Add more comprehensive tests for EnqueuedTelemetryData
@@ -1,10 +1,11 @@
 // Copyright 2021-Present Datadog, Inc. https://www.datadoghq.com/
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::service::telemetry::enqueued_telemetry_data::EnqueuedTelemetryData;
+use crate::service::telemetry::enqueued_telemetry_data::{EnqueuedTelemetryData, PendingActions};
 pub use app_instance::AppInstance;
 use futures::future::Shared;
 use manual_future::ManualFuture;
+use std::sync::Arc;
 
 mod app_instance;
 pub mod enqueued_telemetry_data;
@@ -12,6 +13,6 @@ pub mod enqueued_telemetry_stats;
 
 #[allow(clippy::large_enum_variant)]
 pub(crate) enum AppOrQueue {
-    App(Shared<ManualFuture<(String, String)>>),
+    App(Shared<ManualFuture<(String, String)>>, Arc<PendingActions>),
     Queue(EnqueuedTelemetryData),
 }
@@ -4,5 +4,6 @@
 pub(crate) use trace_flusher::TraceFlusher;
 use trace_send_data::TraceSendData;
 
+mod payload_size;
 pub(crate) mod trace_flusher;
 mod trace_send_data;
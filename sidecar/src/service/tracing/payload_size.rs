@@ -0,0 +1,154 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use datadog_trace_utils::trace_utils::SendDataResult;
+
+/// Payloads are never coalesced beyond this size, like `trace_utils::coalesce_send_data` does.
+pub(crate) const MAX_PAYLOAD_SIZE_BYTES: usize = 25 * 1024 * 1024;
+pub(crate) const MIN_PAYLOAD_SIZE_BYTES: usize = 256 * 1024;
+/// Number of consecutive successful sends before the maximum size is raised again.
+const SUCCESSES_BEFORE_INCREASE: u32 = 10;
+
+/// How the intake reacted to a payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PayloadFeedback {
+    Accepted,
+    /// 413 Payload Too Large
+    TooLarge,
+    /// 408 Request Timeout or 429 Too Many Requests: the intake is struggling with the load
+    Overloaded,
+    /// Failures unrelated to the payload size, e.g. network errors
+    Other,
+}
+
+impl From<&SendDataResult> for PayloadFeedback {
+    fn from(result: &SendDataResult) -> Self {
+        let responses = &result.responses_count_per_code;
+        if responses.contains_key(&413) {
+            PayloadFeedback::TooLarge
+        } else if responses.contains_key(&408) || responses.contains_key(&429) {
+            PayloadFeedback::Overloaded
+        } else if result.last_result.is_ok() {
+            PayloadFeedback::Accepted
+        } else {
+            PayloadFeedback::Other
+        }
+    }
+}
+
+/// Adapts the maximum size of the payloads sent to the intake to its responses: the size is halved
+/// when a payload is rejected as too large, reduced by a quarter when the intake is overloaded, and
+/// raised again by a quarter after a streak of accepted payloads.
+#[derive(Debug)]
+pub(crate) struct PayloadSizeController {
+    max_size: usize,
+    successes: u32,
+}
+
+impl Default for PayloadSizeController {
+    fn default() -> Self {
+        PayloadSizeController {
+            max_size: MAX_PAYLOAD_SIZE_BYTES,
+            successes: 0,
+        }
+    }
+}
+
+impl PayloadSizeController {
+    pub(crate) fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    pub(crate) fn record(&mut self, feedback: PayloadFeedback) {
+        match feedback {
+            PayloadFeedback::Accepted => {
+                self.successes += 1;
+                if self.successes >= SUCCESSES_BEFORE_INCREASE {
+                    self.successes = 0;
+                    self.set_max_size(self.max_size + self.max_size / 4);
+                }
+            }
+            PayloadFeedback::TooLarge => {
+                self.successes = 0;
+                self.set_max_size(self.max_size / 2);
+            }
+            PayloadFeedback::Overloaded => {
+                self.successes = 0;
+                self.set_max_size(self.max_size - self.max_size / 4);
+            }
+            PayloadFeedback::Other => {}
+        }
+    }
+
+    fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size.clamp(MIN_PAYLOAD_SIZE_BYTES, MAX_PAYLOAD_SIZE_BYTES);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_too_large_halves_down_to_the_minimum() {
+        let mut controller = PayloadSizeController::default();
+        controller.record(PayloadFeedback::TooLarge);
+        assert_eq!(MAX_PAYLOAD_SIZE_BYTES / 2, controller.max_size());
+
+        for _ in 0..20 {
+            controller.record(PayloadFeedback::TooLarge);
+        }
+        assert_eq!(MIN_PAYLOAD_SIZE_BYTES, controller.max_size());
+    }
+
+    #[test]
+    fn test_overloaded_reduces_by_a_quarter() {
+        let mut controller = PayloadSizeController::default();
+        controller.record(PayloadFeedback::Overloaded);
+        assert_eq!(MAX_PAYLOAD_SIZE_BYTES * 3 / 4, controller.max_size());
+    }
+
+    #[test]
+    fn test_recovers_after_successes() {
+        let mut controller = PayloadSizeController::default();
+        controller.record(PayloadFeedback::TooLarge);
+        let reduced = controller.max_size();
+
+        for _ in 0..SUCCESSES_BEFORE_INCREASE - 1 {
+            controller.record(PayloadFeedback::Accepted);
+        }
+        // unrelated failures neither reset the streak nor change the size
+        controller.record(PayloadFeedback::Other);
+        assert_eq!(reduced, controller.max_size());
+
+        controller.record(PayloadFeedback::Accepted);
+        assert_eq!(reduced + reduced / 4, controller.max_size());
+
+        // a rejection resets the streak
+        for _ in 0..SUCCESSES_BEFORE_INCREASE - 1 {
+            controller.record(PayloadFeedback::Accepted);
+        }
+        controller.record(PayloadFeedback::Overloaded);
+        let reduced = controller.max_size();
+        controller.record(PayloadFeedback::Accepted);
+        assert_eq!(reduced, controller.max_size());
+
+        // never grows beyond the maximum
+        for _ in 0..1000 {
+            controller.record(PayloadFeedback::Accepted);
+        }
+        assert_eq!(MAX_PAYLOAD_SIZE_BYTES, controller.max_size());
+    }
+
+    #[test]
+    fn test_feedback_from_send_data_result() {
+        let mut result = SendDataResult::default();
+        assert_eq!(PayloadFeedback::Other, PayloadFeedback::from(&result));
+
+        result.responses_count_per_code.insert(429, 1);
+        assert_eq!(PayloadFeedback::Overloaded, PayloadFeedback::from(&result));
+
+        result.responses_count_per_code.insert(413, 1);
+        assert_eq!(PayloadFeedback::TooLarge, PayloadFeedback::from(&result));
+    }
+}
@@ -1,9 +1,14 @@
 // Copyright 2021-Present Datadog, Inc. https://www.datadoghq.com/
 // SPDX-License-Identifier: Apache-2.0
 
+use super::payload_size::{PayloadFeedback, PayloadSizeController};
 use super::TraceSendData;
 use crate::agent_remote_config::AgentRemoteConfigWriter;
+use crate::service::agent_state::AgentStateCache;
+use crate::service::SidecarError;
 use datadog_ipc::platform::NamedShmHandle;
+use datadog_trace_normalization::normalizer::NormalizationStats;
+use datadog_trace_utils::send_data::SentPayloads;
 use datadog_trace_utils::trace_utils;
 use datadog_trace_utils::trace_utils::SendData;
 use datadog_trace_utils::trace_utils::SendDataResult;
@@ -37,6 +42,10 @@ pub(crate) struct TraceFlusherStats {
     pub(crate) send_data_size: u32,
 }
 
+/// The payloads sent by a flush: the status each endpoint answered with, or why the payload was
+/// not accepted.
+pub(crate) type FlushResults = Vec<(Endpoint, Result<u16, SidecarError>)>;
+
 struct AgentRemoteConfig {
     writer: AgentRemoteConfigWriter<NamedShmHandle>,
     last_write: Instant,
@@ -64,6 +73,7 @@ pub struct TraceFlusherMetrics {
     pub bytes_sent: u64,
     pub chunks_sent: u64,
     pub chunks_dropped: u64,
+    pub normalization: NormalizationStats,
 }
 
 impl TraceFlusherMetrics {
@@ -94,7 +104,13 @@ pub(crate) struct TraceFlusher {
     pub(crate) min_force_flush_size_bytes: AtomicU32,
     pub(crate) min_force_drop_size_bytes: AtomicU32, // put a limit on memory usage
     remote_config: Mutex<AgentRemoteConfigs>,
+    /// State learned from the agents, shared with the sessions.
+    pub(crate) agent_state: Arc<AgentStateCache>,
     pub metrics: Mutex<TraceFlusherMetrics>,
+    /// Limits the size of coalesced payloads, adapting to the intake responses.
+    payload_size: Mutex<PayloadSizeController>,
+    /// The payloads recently accepted, so that those submitted again are not sent twice.
+    sent_payloads: Arc<SentPayloads>,
 }
 impl Default for TraceFlusher {
     fn default() -> Self {
@@ -104,7 +120,10 @@ impl Default for TraceFlusher {
             min_force_flush_size_bytes: AtomicU32::new(DEFAULT_MIN_FORCE_FLUSH_SIZE_BYTES),
             min_force_drop_size_bytes: AtomicU32::new(DEFAULT_MIN_FORCE_DROP_SIZE_BYTES),
             remote_config: Mutex::new(Default::default()),
+            agent_state: Arc::new(AgentStateCache::default()),
             metrics: Mutex::new(Default::default()),
+            payload_size: Mutex::new(Default::default()),
+            sent_payloads: Arc::new(SentPayloads::default()),
         }
     }
 }
@@ -115,7 +134,12 @@ impl TraceFlusher {
     /// # Arguments
     ///
     /// * `data` - A `SendData` instance that needs to be added to the traces.
-    pub(crate) fn enqueue(self: &Arc<Self>, data: SendData) {
+    ///
+    /// # Returns
+    ///
+    /// * `SidecarError::QueueFull` if the data was dropped as the queued traces exceed the minimum
+    ///   force drop size.
+    pub(crate) fn enqueue(self: &Arc<Self>, data: SendData) -> Result<(), SidecarError> {
         let mut flush_data = self.inner.lock().unwrap();
         let flush_data = flush_data.deref_mut();
 
@@ -124,7 +148,7 @@ impl TraceFlusher {
         if flush_data.traces.send_data_size
             > self.min_force_drop_size_bytes.load(Ordering::Relaxed) as usize
         {
-            return;
+            return Err(SidecarError::QueueFull);
         }
 
         flush_data.traces.send_data.push(data);
@@ -138,6 +162,7 @@ impl TraceFlusher {
         {
             flush_data.traces.flush();
         }
+        Ok(())
     }
 
     /// Join the flusher task and flush the remaining traces.
@@ -180,6 +205,12 @@ impl TraceFlusher {
         }
     }
 
+    pub fn record_normalization_stats(&self, stats: &NormalizationStats) {
+        if !stats.is_empty() {
+            self.metrics.lock().unwrap().normalization.merge(stats);
+        }
+    }
+
     pub fn collect_metrics(&self) -> TraceFlusherMetrics {
         std::mem::take(&mut self.metrics.lock().unwrap())
     }
@@ -222,7 +253,7 @@ impl TraceFlusher {
 
     fn replace_trace_send_data(
         &self,
-        completer: ManualFutureCompleter<Option<mpsc::Sender<()>>>,
+        completer: ManualFutureCompleter<Option<mpsc::Sender<FlushResults>>>,
     ) -> Vec<SendData> {
         let trace_buffer = std::mem::replace(
             &mut self.inner.lock().unwrap().traces,
@@ -233,37 +264,55 @@ impl TraceFlusher {
             },
         )
         .send_data;
-        trace_utils::coalesce_send_data(trace_buffer)
+        let max_size = self.payload_size.lock().unwrap().max_size();
+        trace_utils::coalesce_send_data_with_max_size(trace_buffer, max_size)
             .into_iter()
             .collect()
     }
 
-    async fn send_and_handle_trace(&self, send_data: SendData) {
+    async fn send_and_handle_trace(
+        &self,
+        mut send_data: SendData,
+    ) -> (Endpoint, Result<u16, SidecarError>) {
+        send_data.set_sent_payloads(self.sent_payloads.clone());
         let endpoint = send_data.get_target().clone();
         let response = send_data.send().await;
         self.metrics.lock().unwrap().update(&response);
-        match response.last_result {
+        self.payload_size
+            .lock()
+            .unwrap()
+            .record(PayloadFeedback::from(&response));
+        let result = match response.last_result {
             Ok(response) => {
+                let status = response.status().as_u16();
                 if endpoint.api_key.is_none() {
                     // not when intake
                     match hyper::body::to_bytes(response.into_body()).await {
                         Ok(body_bytes) => {
+                            self.agent_state
+                                .update_from_trace_response(&endpoint, &body_bytes);
                             self.write_remote_configs(endpoint.clone(), body_bytes.to_vec())
                         }
                         Err(e) => error!("Error receiving agent configuration: {e:?}"),
                     }
                 }
                 info!("Successfully flushed traces to {}", endpoint.url);
+                Ok(status)
             }
             Err(e) => {
                 error!("Error sending trace: {e:?}");
+                Err(SidecarError::UploadFailed {
+                    status: response.last_status,
+                    message: format!("{e:#}"),
+                })
             }
-        }
+        };
+        (endpoint, result)
     }
 
     fn start_trace_flusher(
         self: Arc<Self>,
-        mut force_flush: ManualFuture<Option<mpsc::Sender<()>>>,
+        mut force_flush: ManualFuture<Option<mpsc::Sender<FlushResults>>>,
     ) -> JoinHandle<()> {
         tokio::spawn(async move {
             loop {
@@ -284,9 +333,12 @@ impl TraceFlusher {
                 force_flush = new_force_flush;
 
                 let send_data = self.replace_trace_send_data(completer);
-                join_all(send_data.into_iter().map(|d| self.send_and_handle_trace(d))).await;
+                let results =
+                    join_all(send_data.into_iter().map(|d| self.send_and_handle_trace(d))).await;
 
-                drop(flush_done_sender);
+                if let Some(flush_done_sender) = flush_done_sender {
+                    _ = flush_done_sender.try_send(results);
+                }
 
                 let mut data = self.inner.lock().unwrap();
                 let data = data.deref_mut();
@@ -298,8 +350,9 @@ impl TraceFlusher {
         })
     }
 
-    /// Flushes immediately without delay.
-    pub async fn flush(&self) {
+    /// Flushes immediately without delay. Returns what was sent, which is nothing if a flush was
+    /// already underway.
+    pub(crate) async fn flush(&self) -> FlushResults {
         let flush_done = self.inner.lock().unwrap().traces.await_flush();
         flush_done.await
     }
@@ -338,6 +391,7 @@ mod tests {
         let target_endpoint = Endpoint {
             url: server.url("").to_owned().parse().unwrap(),
             api_key: Some("test-key".into()),
+            ..Default::default()
         };
 
         let send_data_1 = create_send_data(size, &target_endpoint);
@@ -345,13 +399,13 @@ mod tests {
         let send_data_2 = send_data_1.clone();
         let send_data_3 = send_data_1.clone();
 
-        trace_flusher.enqueue(send_data_1);
-        trace_flusher.enqueue(send_data_2);
+        trace_flusher.enqueue(send_data_1).unwrap();
+        trace_flusher.enqueue(send_data_2).unwrap();
 
         assert!(poll_for_mock_hit(&mut mock, 10, 150, 0, false).await);
 
         // enqueue a trace that exceeds the min force flush size
-        trace_flusher.enqueue(send_data_3);
+        trace_flusher.enqueue(send_data_3).unwrap();
 
         assert!(poll_for_mock_hit(&mut mock, 25, 100, 1, true).await);
     }
@@ -379,10 +433,11 @@ mod tests {
         let target_endpoint = Endpoint {
             url: server.url("").to_owned().parse().unwrap(),
             api_key: Some("test-key".into()),
+            ..Default::default()
         };
         let send_data_1 = create_send_data(size, &target_endpoint);
 
-        trace_flusher.enqueue(send_data_1);
+        trace_flusher.enqueue(send_data_1).unwrap();
 
         // Sleep for a duration longer than the flush interval
         tokio::time::sleep(Duration::from_millis(
@@ -415,11 +470,15 @@ mod tests {
         let target_endpoint = Endpoint {
             url: server.url("").to_owned().parse().unwrap(),
             api_key: Some("test-key".into()),
+            ..Default::default()
         };
 
         let send_data_1 = create_send_data(size, &target_endpoint);
 
-        trace_flusher.enqueue(send_data_1);
+        assert_eq!(
+            Err(SidecarError::QueueFull),
+            trace_flusher.enqueue(send_data_1)
+        );
 
         assert!(poll_for_mock_hit(&mut mock, 5, 250, 0, true).await);
     }
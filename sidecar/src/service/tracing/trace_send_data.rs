@@ -1,6 +1,7 @@
 // Copyright 2021-Present Datadog, Inc. https://www.datadoghq.com/
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::service::tracing::trace_flusher::FlushResults;
 use datadog_trace_utils::trace_utils::SendData;
 use futures::future::Map;
 use futures::FutureExt;
@@ -13,7 +14,7 @@ use tracing::debug;
 pub(crate) struct TraceSendData {
     pub send_data: Vec<SendData>,
     pub send_data_size: usize,
-    pub force_flush: Option<ManualFutureCompleter<Option<Sender<()>>>>,
+    pub force_flush: Option<ManualFutureCompleter<Option<Sender<FlushResults>>>>,
 }
 
 impl TraceSendData {
@@ -24,18 +25,18 @@ impl TraceSendData {
     }
 
     /// Flush the traces. It returns a future which can be awaited to determine when data has
-    /// actually been sent.
+    /// actually been sent, resolving to the results of sending it.
     #[allow(clippy::type_complexity)]
-    pub(crate) fn await_flush(&mut self) -> Map<JoinHandle<()>, fn(Result<(), JoinError>)> {
+    pub(crate) fn await_flush(
+        &mut self,
+    ) -> Map<JoinHandle<FlushResults>, fn(Result<FlushResults, JoinError>) -> FlushResults> {
         let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
         self.do_flush(Some(sender));
-        tokio::spawn(async move {
-            receiver.recv().await;
-        })
-        .map(|_| ())
+        tokio::spawn(async move { receiver.recv().await.unwrap_or_default() })
+            .map(|results| results.unwrap_or_default())
     }
 
-    fn do_flush(&mut self, sender: Option<Sender<()>>) {
+    fn do_flush(&mut self, sender: Option<Sender<FlushResults>>) {
         if let Some(force_flush) = self.force_flush.take() {
             debug!(
                 "Emitted flush for traces with {} bytes in send_data buffer",
@@ -23,4 +23,6 @@ pub trait Liaison: Sized {
     fn attempt_listen(&self) -> io::Result<Option<IpcServer>>;
     fn ipc_shared() -> Self;
     fn ipc_per_process() -> Self;
+    /// Where the server listens, for diagnostics.
+    fn socket_path(&self) -> String;
 }
@@ -85,6 +85,10 @@ impl Liaison for SharedDirLiaison {
         //TODO: implement per pid handling
         Self::new_default_location()
     }
+
+    fn socket_path(&self) -> String {
+        self.socket_path.display().to_string()
+    }
 }
 
 impl SharedDirLiaison {
@@ -164,6 +168,11 @@ mod linux {
             ));
             Self { path }
         }
+
+        fn socket_path(&self) -> String {
+            // abstract sockets are conventionally displayed with a leading @
+            format!("@{}", self.path.display())
+        }
     }
 
     impl Default for AbstractUnixSocketLiaison {
@@ -154,6 +154,10 @@ impl Liaison for NamedPipeLiaison {
     fn ipc_per_process() -> Self {
         Self::new(format!("libdatadog_{}_", unsafe { getpid() }))
     }
+
+    fn socket_path(&self) -> String {
+        self.socket_path.to_string_lossy().into_owned()
+    }
 }
 
 impl NamedPipeLiaison {
@@ -0,0 +1,124 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! What happened when the process last started, or connected to, the sidecar. Failures there only
+//! disable tracing, so this is what tells users why tracing silently didn't activate.
+
+use crate::config::Config;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::warn;
+
+/// Appends the diagnostics of every start of the sidecar, as a JSON line, to the file it names.
+const ENV_SIDECAR_STARTUP_DIAGNOSTICS: &str = "_DD_SIDECAR_STARTUP_DIAGNOSTICS";
+
+static LAST_STARTUP: Mutex<Option<StartupDiagnostics>> = Mutex::new(None);
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListenOutcome {
+    /// This process listened on the socket, and spawned the sidecar with it.
+    Spawned,
+    /// A sidecar was already listening on the socket.
+    AlreadyListening,
+    /// Listening on the socket failed, with the given error.
+    Failed(String),
+    #[default]
+    NotAttempted,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct StartupDiagnostics {
+    pub ipc_mode: String,
+    /// Where the sidecar listens, e.g. the path of its socket.
+    pub socket_path: String,
+    pub listen: ListenOutcome,
+    /// Why the sidecar could not be spawned, if this process spawned it.
+    pub spawn_error: Option<String>,
+    /// Why the connection to the sidecar failed, if it did.
+    pub connect_error: Option<String>,
+    /// The environment the sidecar is spawned with, derived from the configuration of this
+    /// process.
+    pub env: BTreeMap<&'static str, String>,
+}
+
+impl StartupDiagnostics {
+    pub(crate) fn new(cfg: &Config, socket_path: String) -> Self {
+        StartupDiagnostics {
+            ipc_mode: cfg.ipc_mode.to_string(),
+            socket_path,
+            env: cfg.to_env().into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether the process is connected to a sidecar.
+    pub fn connected(&self) -> bool {
+        self.connect_error.is_none() && self.spawn_error.is_none()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// Keeps the diagnostics for [`last_startup_diagnostics`], and dumps them to the file named by
+    /// `_DD_SIDECAR_STARTUP_DIAGNOSTICS`, if set.
+    pub(crate) fn record(self) {
+        if let Some(path) = std::env::var_os(ENV_SIDECAR_STARTUP_DIAGNOSTICS) {
+            if let Err(e) = self.dump(Path::new(&path)) {
+                warn!("Failed writing the sidecar startup diagnostics to {path:?}: {e}");
+            }
+        }
+        *LAST_STARTUP.lock().unwrap() = Some(self);
+    }
+
+    fn dump(&self, path: &Path) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        // a single write, so that the lines of concurrent processes are not interleaved
+        file.write_all(format!("{}\n", self.to_json()).as_bytes())
+    }
+}
+
+/// The diagnostics of the last attempt of this process to start or connect to the sidecar, if any.
+pub fn last_startup_diagnostics() -> Option<StartupDiagnostics> {
+    LAST_STARTUP.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_dump() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("diagnostics");
+        let mut diagnostics = StartupDiagnostics {
+            ipc_mode: "shared".to_string(),
+            socket_path: "/tmp/libdatadog/sidecar.sock".to_string(),
+            env: BTreeMap::from([("_DD_DEBUG_SIDECAR_LOG_METHOD", "disabled".to_string())]),
+            ..Default::default()
+        };
+        diagnostics.dump(&path).unwrap();
+        diagnostics.listen = ListenOutcome::Failed("Permission denied".to_string());
+        diagnostics.connect_error = Some("Connection refused".to_string());
+        assert!(!diagnostics.connected());
+        diagnostics.dump(&path).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(2, lines.len());
+        assert_eq!("not_attempted", lines[0]["listen"]);
+        assert_eq!(serde_json::Value::Null, lines[0]["connect_error"]);
+        assert_eq!("disabled", lines[0]["env"]["_DD_DEBUG_SIDECAR_LOG_METHOD"]);
+        assert_eq!("Permission denied", lines[1]["listen"]["failed"]);
+        assert_eq!("Connection refused", lines[1]["connect_error"]);
+    }
+}
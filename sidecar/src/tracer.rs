@@ -1,14 +1,21 @@
 // Copyright 2021-Present Datadog, Inc. https://www.datadoghq.com/
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::service::replace_rules::ReplaceRules;
+use crate::service::tagging_rules::TaggingRules;
+use datadog_trace_obfuscation::obfuscation_config::ObfuscationConfig;
 use datadog_trace_utils::config_utils::trace_intake_url_prefixed;
 use ddcommon::Endpoint;
 use http::uri::PathAndQuery;
 use std::str::FromStr;
+use std::sync::Arc;
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Config {
     pub endpoint: Option<Endpoint>,
+    pub replace_rules: Option<ReplaceRules>,
+    pub tagging_rules: Option<Arc<TaggingRules>>,
+    pub obfuscation_config: Option<Arc<ObfuscationConfig>>,
 }
 
 impl Config {
@@ -22,7 +29,7 @@ impl Config {
         };
         self.endpoint = Some(Endpoint {
             url: uri,
-            api_key: endpoint.api_key,
+            ..endpoint
         });
         Ok(())
     }
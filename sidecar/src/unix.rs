@@ -1,7 +1,7 @@
 // Copyright 2021-Present Datadog, Inc. https://www.datadoghq.com/
 // SPDX-License-Identifier: Apache-2.0
 
-use spawn_worker::{getpid, SpawnWorker, Stdio};
+use spawn_worker::{getpid, SpawnMethod, SpawnWorker, Stdio};
 
 use std::os::unix::net::UnixListener as StdUnixListener;
 
@@ -10,10 +10,22 @@ use nix::fcntl::{fcntl, OFlag, F_GETFL, F_SETFL};
 use nix::sys::socket::{shutdown, Shutdown};
 use std::io;
 use std::os::unix::prelude::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Instant;
 use tokio::net::{UnixListener, UnixStream};
 use tracing::{error, info};
 
+/// Whether [`run_self_exec_sidecar`] was called, i.e. re-executing the program runs the sidecar.
+static SELF_EXEC_WIRED: AtomicBool = AtomicBool::new(false);
+
+/// Runs the sidecar if this process was spawned to be it. Statically linked programs spawn the
+/// sidecar by re-executing themselves, and must call this first thing in main. Only once it was
+/// called, the sidecar is spawned that way, when its entrypoint isn't in a shared object.
+pub fn run_self_exec_sidecar() {
+    spawn_worker::run_self_exec_entrypoint(&[spawn_worker::entrypoint!(ddog_daemon_entry_point)]);
+    SELF_EXEC_WIRED.store(true, Ordering::Relaxed);
+}
+
 #[no_mangle]
 pub extern "C" fn ddog_daemon_entry_point() {
     #[cfg(feature = "tracing")]
@@ -34,6 +46,7 @@ pub extern "C" fn ddog_daemon_entry_point() {
         let acquire_listener = move || {
             listener.set_nonblocking(true)?;
             let listener = UnixListener::from_std(listener)?;
+            crate::admin::set_listener_fd(listener.as_raw_fd());
 
             // shutdown to gracefully dequeue, and immediately relinquish ownership of the socket
             // while shutting down
@@ -67,8 +80,15 @@ async fn accept_socket_loop(
     listener: UnixListener,
     handler: Box<dyn Fn(UnixStream)>,
 ) -> io::Result<()> {
-    while let Ok((socket, _)) = listener.accept().await {
-        handler(socket);
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((socket, _)) => handler(socket),
+                Err(_) => break,
+            },
+            // the socket was handed over to a new sidecar, see crate::admin
+            _ = crate::admin::STOP_ACCEPTING.notified() => break,
+        }
     }
     Ok(())
 }
@@ -83,6 +103,14 @@ pub fn setup_daemon_process(
         .pass_fd(unsafe { OwnedFd::from_raw_fd(listener.into_raw_fd()) })
         .stdin(Stdio::Null);
 
+    if SELF_EXEC_WIRED.load(Ordering::Relaxed)
+        && spawn_worker::entrypoint!(ddog_daemon_entry_point)
+            .get_fs_path()
+            .is_none()
+    {
+        spawn_cfg.spawn_method(SpawnMethod::SelfExec);
+    }
+
     Ok(())
 }
 
@@ -7,7 +7,9 @@
 #define UNUSED(x) (void)(x)
 #include <dlfcn.h>
 #include <stdio.h>
+#include <stdlib.h>
 #include <string.h>
+#include <unistd.h>
 
 int main_override(int argc, char **argv) {
   if (argc > 2) {
@@ -16,6 +18,7 @@ int main_override(int argc, char **argv) {
 
     // Last entry is always the symbol name
     const char *symbol_name = argv[argc-1];
+    dlerror(); // clear any previous error, to not misreport it
     void (*fn)() = dlsym(RTLD_DEFAULT, symbol_name);
     char *error = NULL;
 
@@ -23,6 +26,10 @@ int main_override(int argc, char **argv) {
       fputs(error, stderr);
       return 31;
     }
+    if (!fn) {
+      fprintf(stderr, "ld_preload_trampoline: symbol %s resolved to NULL\n", symbol_name);
+      return 31;
+    }
 
     (*fn)();
   }
@@ -37,6 +44,38 @@ int __libc_start_main(int (*main)(int, char **), int argc, char **argv,
                       void (*rtld_fini)(void), void *stack_end) {
   UNUSED(main);
   typeof(&__libc_start_main) libc_start_main = dlsym(RTLD_NEXT, "__libc_start_main");
+  // dlerror() only reports the most recent failure, so it's read right after each failing call
+  const char *error = libc_start_main ? NULL : dlerror();
+
+  // e.g. if the trampoline was loaded in an unusual order, look libc up directly: libc.so.6 is
+  // glibc, libc.so is musl
+  const char *libc_names[] = {"libc.so.6", "libc.so"};
+  for (size_t i = 0; !libc_start_main && i < sizeof(libc_names) / sizeof(*libc_names); ++i) {
+    void *libc = dlopen(libc_names[i], RTLD_LAZY | RTLD_NOLOAD);
+    if (!libc) {
+      error = dlerror();
+      continue;
+    }
+    libc_start_main = dlsym(libc, "__libc_start_main");
+    if (!libc_start_main) {
+      error = dlerror();
+    }
+  }
+
+  if (!libc_start_main) {
+    // Never crash the process: run the entry point without libc's startup sequence. The
+    // constructors of the executable and libc itself are still initialized by the dynamic loader.
+    fprintf(stderr, "ld_preload_trampoline: failed resolving __libc_start_main: %s\n",
+            error ? error : "symbol not found");
+    if (init) {
+      init(argc, argv, environ);
+    }
+    int result = main_override(argc, argv);
+    if (fini) {
+      fini();
+    }
+    exit(result);
+  }
 
   return libc_start_main(main_override, argc, argv, init, fini, rtld_fini, stack_end);
 }
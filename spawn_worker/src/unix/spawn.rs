@@ -28,7 +28,11 @@ mod linux {
 
 mod helper {
     use nix::libc;
-    use std::{ffi::CString, ptr};
+    use std::{
+        ffi::{CString, OsString},
+        os::unix::prelude::OsStringExt,
+        ptr,
+    };
 
     pub struct ExecVec {
         items: Vec<CString>,
@@ -42,10 +46,38 @@ mod helper {
         }
 
         pub fn empty() -> Self {
+            Self::with_capacity(0)
+        }
+
+        /// Preallocates storage for `capacity` items (plus the trailing NULL pointer), so that
+        /// up to `capacity` calls to [`ExecVec::try_push`] are guaranteed to not allocate.
+        pub fn with_capacity(capacity: usize) -> Self {
+            let mut ptrs = Vec::with_capacity(capacity + 1);
+            ptrs.push(ptr::null());
             Self {
-                items: vec![],
-                ptrs: vec![ptr::null()],
+                items: Vec::with_capacity(capacity),
+                ptrs,
+            }
+        }
+
+        pub fn len(&self) -> usize {
+            self.items.len()
+        }
+
+        /// Number of items which can be held without reallocating.
+        pub fn capacity(&self) -> usize {
+            self.items.capacity().min(self.ptrs.capacity() - 1)
+        }
+
+        /// Pushes an item without ever allocating. When the preallocated capacity is
+        /// exhausted, the item is handed back to the caller, which may then decide to
+        /// [`ExecVec::push`] it anyway, if allocating is acceptable at that point.
+        pub fn try_push(&mut self, item: CString) -> Result<(), CString> {
+            if self.len() >= self.capacity() {
+                return Err(item);
             }
+            self.push(item);
+            Ok(())
         }
 
         pub fn push(&mut self, item: CString) {
@@ -60,6 +92,96 @@ mod helper {
             self.ptrs[index] = item.as_ptr();
             self.items[index] = item;
         }
+
+        /// Builds an environment vector of `key=value` entries, reserving space for
+        /// `additional` entries to be pushed later on without reallocating.
+        /// Entries containing interior NUL bytes are skipped.
+        pub fn from_env(env: &[(OsString, OsString)], additional: usize) -> Self {
+            let mut envp = Self::with_capacity(env.len() + additional);
+            for (k, v) in env {
+                // reserve space for '=' and final null
+                let mut env_entry = OsString::with_capacity(k.len() + v.len() + 2);
+                env_entry.push(k);
+                env_entry.push("=");
+                env_entry.push(v);
+
+                if let Ok(env_entry) = CString::new(env_entry.into_vec()) {
+                    envp.push(env_entry);
+                }
+            }
+            envp
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::ExecVec;
+        use std::ffi::{CStr, CString, OsString};
+
+        fn collect_ptrs(vec: &ExecVec) -> Vec<CString> {
+            let mut collected = vec![];
+            let mut ptr = vec.as_ptr();
+            unsafe {
+                while !(*ptr).is_null() {
+                    collected.push(CStr::from_ptr(*ptr).to_owned());
+                    ptr = ptr.add(1);
+                }
+            }
+            collected
+        }
+
+        fn cstring(s: &str) -> CString {
+            CString::new(s).unwrap()
+        }
+
+        #[test]
+        fn test_try_push_does_not_grow() {
+            let mut vec = ExecVec::with_capacity(2);
+            assert_eq!(2, vec.capacity());
+            vec.try_push(cstring("a")).unwrap();
+            vec.try_push(cstring("b")).unwrap();
+            let rejected = vec.try_push(cstring("c")).unwrap_err();
+            assert_eq!(cstring("c"), rejected);
+            assert_eq!(2, vec.len());
+
+            vec.push(rejected);
+            assert_eq!(
+                vec![cstring("a"), cstring("b"), cstring("c")],
+                collect_ptrs(&vec)
+            );
+        }
+
+        #[test]
+        fn test_env_rewriting_with_many_injected_variables() {
+            let env: Vec<(OsString, OsString)> = (0..500)
+                .map(|i| (format!("KEY_{i}").into(), format!("value {i}").into()))
+                .chain([
+                    ("INVALID\0KEY".into(), "skipped".into()),
+                    ("EMPTY".into(), "".into()),
+                ])
+                .collect();
+
+            let mut envp = ExecVec::from_env(&env, 2);
+            assert_eq!(501, envp.len());
+            assert!(envp.capacity() >= envp.len() + 2);
+
+            // injected entries must fit into the preallocated space
+            envp.try_push(cstring("LD_PRELOAD=/tmp/lib.so")).unwrap();
+            envp.try_push(cstring("__DD_INTERNAL_PASSED_FD=3")).unwrap();
+
+            // growing past the preallocated space still works via the allocating path
+            for i in 0..100 {
+                envp.push(CString::new(format!("EXTRA_{i}=1")).unwrap());
+            }
+
+            let entries = collect_ptrs(&envp);
+            assert_eq!(603, entries.len());
+            assert_eq!(cstring("KEY_0=value 0"), entries[0]);
+            assert_eq!(cstring("KEY_499=value 499"), entries[499]);
+            assert_eq!(cstring("EMPTY="), entries[500]);
+            assert_eq!(cstring("LD_PRELOAD=/tmp/lib.so"), entries[501]);
+            assert_eq!(cstring("EXTRA_99=1"), entries[602]);
+        }
     }
 }
 
@@ -84,7 +206,7 @@ use std::{
     ffi::{self, CString, OsString},
     fs::Permissions,
     io::{Seek, Write},
-    os::unix::prelude::{AsRawFd, OsStringExt, PermissionsExt},
+    os::unix::prelude::{AsRawFd, OsStrExt, OsStringExt, PermissionsExt},
 };
 
 use io_lifetimes::OwnedFd;
@@ -101,10 +223,47 @@ pub enum SpawnMethod {
     #[cfg(not(target_os = "macos"))]
     LdPreload,
     Exec,
+    /// Re-executes the current executable with [`SELF_EXEC_ARG`], for statically linked programs,
+    /// whose entrypoints can't be loaded from a shared object. The program must call
+    /// [`run_self_exec_entrypoint`] first thing in main to reach the entrypoint, otherwise the
+    /// whole program runs a second time. It is therefore never detected, but must be set with
+    /// [`SpawnWorker::spawn_method`].
+    SelfExec,
+}
+
+/// The argument telling a re-executed program which entrypoint to run, see
+/// [`SpawnMethod::SelfExec`].
+pub const SELF_EXEC_ARG: &str = "__dd_spawn_worker_self_exec";
+
+/// Runs the entrypoint requested by a parent which spawned this executable with
+/// [`SpawnMethod::SelfExec`], then exits. Returns right away if the process was not spawned that
+/// way.
+///
+/// `entrypoints` lists all the entrypoints the program may spawn, only these can be run.
+pub fn run_self_exec_entrypoint(entrypoints: &[Entrypoint]) {
+    let mut args = env::args_os().skip(1);
+    if args.next().as_deref() != Some(ffi::OsStr::new(SELF_EXEC_ARG)) {
+        return;
+    }
+    let symbol_name = args.next().unwrap_or_default();
+    match entrypoints
+        .iter()
+        .find(|entrypoint| entrypoint.symbol_name.as_bytes() == symbol_name.as_bytes())
+    {
+        Some(entrypoint) => {
+            (entrypoint.ptr)();
+            std::process::exit(0);
+        }
+        None => {
+            // same exit code as the trampoline failing to find the symbol
+            eprintln!("unknown entrypoint: {}", symbol_name.to_string_lossy());
+            std::process::exit(11);
+        }
+    }
 }
 
 use crate::unix::spawn::helper::ExecVec;
-use crate::{LibDependency, Target};
+use crate::{Entrypoint, LibDependency, Target};
 
 impl Target {
     /// TODO: ld_preload type trampoline is not yet supported on osx
@@ -135,7 +294,8 @@ impl Target {
             Target::Entrypoint(e) => e.get_fs_path().ok_or_else(|| {
                 std::io::Error::new(
                     std::io::ErrorKind::Other,
-                    "can't find the entrypoint's target path",
+                    "can't find the entrypoint's target path, statically linked programs must \
+                     opt into SpawnMethod::SelfExec",
                 )
             }),
             Target::ManualTrampoline(p, _) => Ok(std::path::PathBuf::from(p)),
@@ -200,6 +360,39 @@ impl Stdio {
     }
 }
 
+/// A resource limit of the spawned process, applied with setrlimit(2) to both the soft and the hard
+/// limit, so that the process cannot raise it again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResourceLimit {
+    /// Maximum size of the address space, in bytes (RLIMIT_AS)
+    AddressSpace(u64),
+    /// Maximum CPU time, in seconds (RLIMIT_CPU)
+    CpuTime(u64),
+    /// Maximum number of open file descriptors (RLIMIT_NOFILE)
+    OpenFiles(u64),
+}
+
+impl ResourceLimit {
+    /// Must not allocate, it's called between fork and exec.
+    fn apply(&self) -> libc::c_int {
+        let rlimit = |limit: u64| libc::rlimit {
+            rlim_cur: limit as libc::rlim_t,
+            rlim_max: limit as libc::rlim_t,
+        };
+        unsafe {
+            match *self {
+                ResourceLimit::AddressSpace(limit) => {
+                    libc::setrlimit(libc::RLIMIT_AS, &rlimit(limit))
+                }
+                ResourceLimit::CpuTime(limit) => libc::setrlimit(libc::RLIMIT_CPU, &rlimit(limit)),
+                ResourceLimit::OpenFiles(limit) => {
+                    libc::setrlimit(libc::RLIMIT_NOFILE, &rlimit(limit))
+                }
+            }
+        }
+    }
+}
+
 impl From<&File> for Stdio {
     fn from(val: &File) -> Self {
         Stdio::Fd(val.try_clone().unwrap().into())
@@ -217,6 +410,8 @@ pub struct SpawnWorker {
     shared_lib_dependencies: Vec<LibDependency>,
     env: Vec<(ffi::OsString, ffi::OsString)>,
     process_name: Option<String>,
+    resource_limits: Vec<ResourceLimit>,
+    new_session: bool,
 }
 
 impl SpawnWorker {
@@ -232,6 +427,8 @@ impl SpawnWorker {
             env: env.into_iter().collect(),
             process_name: None,
             shared_lib_dependencies: vec![],
+            resource_limits: vec![],
+            new_session: false,
         }
     }
 
@@ -298,6 +495,35 @@ impl SpawnWorker {
         self
     }
 
+    /// Drops the environment variables collected so far, except those named in `allowlist`, so
+    /// that secrets of the application are not leaked to the spawned process. Variables appended
+    /// afterwards are kept.
+    pub fn env_allowlist<I, K>(&mut self, allowlist: I) -> &mut Self
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<OsString>,
+    {
+        let allowlist: Vec<OsString> = allowlist.into_iter().map(Into::into).collect();
+        self.env.retain(|(key, _)| allowlist.contains(key));
+        self
+    }
+
+    /// Limits the resources the spawned process may use. Limits of the same kind replace each
+    /// other.
+    pub fn resource_limit(&mut self, limit: ResourceLimit) -> &mut Self {
+        self.resource_limits
+            .retain(|existing| std::mem::discriminant(existing) != std::mem::discriminant(&limit));
+        self.resource_limits.push(limit);
+        self
+    }
+
+    /// Starts the spawned process in a new session (and thus process group), detaching it from
+    /// the controlling terminal and from signals sent to the application's process group.
+    pub fn new_session(&mut self, new_session: bool) -> &mut Self {
+        self.new_session = new_session;
+        self
+    }
+
     fn wait_pid(pid: Option<libc::pid_t>) -> anyhow::Result<()> {
         let pid = match pid {
             Some(pid) => Pid::from_raw(pid),
@@ -320,13 +546,22 @@ impl SpawnWorker {
     }
 
     fn do_spawn(&self) -> anyhow::Result<Option<libc::pid_t>> {
-        let mut argv = ExecVec::empty();
+        // process name, trampoline path, target path, dependencies and symbol name
+        let mut argv = ExecVec::with_capacity(4 + 2 * self.shared_lib_dependencies.len());
         // set argv[0] and process name shown eg in `ps`
         let process_name = CString::new(self.process_name.as_deref().unwrap_or("spawned_worker"))?;
         argv.push(process_name);
         argv.push(CString::new("")?);
 
+        let spawn_method = match &self.spawn_method {
+            Some(m) => m.clone(),
+            None => self.target.detect_spawn_method()?,
+        };
+        let self_exec = matches!(spawn_method, SpawnMethod::SelfExec);
+
         let entrypoint_symbol_name = match &self.target {
+            // the re-executed program resolves the symbol itself
+            Target::Entrypoint(entrypoint) if self_exec => entrypoint.symbol_name.clone(),
             Target::Entrypoint(entrypoint) => {
                 let path = match unsafe {
                     crate::get_dl_path_raw(entrypoint.ptr as *const libc::c_void)
@@ -338,6 +573,11 @@ impl SpawnWorker {
                 argv.push(path);
                 entrypoint.symbol_name.clone()
             }
+            Target::ManualTrampoline(..) if self_exec => {
+                return Err(anyhow::format_err!(
+                    "can't spawn a manual trampoline by re-executing the current executable"
+                ))
+            }
             Target::ManualTrampoline(path, symbol_name) => {
                 argv.push(CString::new(path.as_str())?);
                 CString::new(symbol_name.as_str())?
@@ -345,23 +585,13 @@ impl SpawnWorker {
             Target::Noop => return Ok(None),
         };
 
-        let mut envp = ExecVec::empty();
-        for (k, v) in &self.env {
-            // reserve space for '=' and final null
-            let mut env_entry = OsString::with_capacity(k.len() + v.len() + 2);
-            env_entry.push(k);
-            env_entry.reserve(v.len() + 2);
-            env_entry.push("=");
-            env_entry.push(v);
-
-            if let Ok(env_entry) = CString::new(env_entry.into_vec()) {
-                envp.push(env_entry);
-            }
-        }
+        // reserve space for the passed fd and LD_PRELOAD entries
+        let mut envp = ExecVec::from_env(&self.env, 2);
 
         let fd_to_pass = if let Some(src_fd) = &self.fd_to_pass {
             // FD to pass is always 4
-            envp.push(CString::new(format!("{}={}", crate::ENV_PASS_FD_KEY, 3))?);
+            envp.try_push(CString::new(format!("{}={}", crate::ENV_PASS_FD_KEY, 3))?)
+                .map_err(|_| anyhow::format_err!("no space reserved for passed fd env"))?;
 
             let fd = src_fd.try_clone()?;
 
@@ -383,15 +613,16 @@ impl SpawnWorker {
 
         // setup final spawn
 
-        let spawn_method = match &self.spawn_method {
-            Some(m) => m.clone(),
-            None => self.target.detect_spawn_method()?,
-        };
-
         let mut temp_files = vec![];
         #[cfg(target_os = "linux")]
         let mut temp_memfds = vec![];
-        for dep in &self.shared_lib_dependencies {
+        // a statically linked executable has no use for shared libraries
+        let shared_lib_dependencies = if self_exec {
+            &[][..]
+        } else {
+            &self.shared_lib_dependencies[..]
+        };
+        for dep in shared_lib_dependencies {
             match dep {
                 LibDependency::Path(path) => {
                     argv.push(CString::new(path.to_string_lossy().to_string())?)
@@ -467,7 +698,8 @@ impl SpawnWorker {
 
                 ld_env.push(env_prefix);
                 ld_env.push(lib_path);
-                envp.push(CString::new(ld_env.into_vec())?);
+                envp.try_push(CString::new(ld_env.into_vec())?)
+                    .map_err(|_| anyhow::format_err!("no space reserved for LD_PRELOAD env"))?;
 
                 let path = CString::new(env::current_exe()?.to_str().ok_or_else(|| {
                     anyhow::format_err!("can't convert current executable file to correct path")
@@ -509,6 +741,21 @@ impl SpawnWorker {
                     panic!("{}", std::io::Error::last_os_error());
                 })
             }
+            SpawnMethod::SelfExec => {
+                // not affected by the executable having been moved or argv[0] being a relative path
+                #[cfg(target_os = "linux")]
+                let path = CString::new("/proc/self/exe")?;
+                #[cfg(not(target_os = "linux"))]
+                let path = CString::new(env::current_exe()?.into_os_string().into_vec())?;
+
+                argv.set(1, CString::new(SELF_EXEC_ARG)?);
+
+                Box::new(move || unsafe {
+                    libc::execve(path.as_ptr(), argv.as_ptr(), envp.as_ptr());
+                    // if we're here then exec has failed
+                    panic!("{}", std::io::Error::last_os_error());
+                })
+            }
         };
         let stdin = self.stdin.as_child_stdio()?;
         let stdout = self.stdout.as_child_stdio()?;
@@ -567,6 +814,19 @@ impl SpawnWorker {
             // /proc might not be mounted?
         }
 
+        // done before daemonizing, the daemonized process is then no session leader and can't
+        // acquire a controlling terminal
+        if self.new_session {
+            unsafe { libc::setsid() };
+        }
+
+        for limit in &self.resource_limits {
+            if limit.apply() != 0 {
+                // better not to run at all than to run without the requested limits
+                std::process::exit(1);
+            }
+        }
+
         if self.daemonize {
             if let Fork::Parent(_) = do_fork()? {
                 // musl will try to "correct" offsets in an atexit handler (lseek a FILE* to the
@@ -646,3 +906,49 @@ fn close_fd_range(range: RangeInclusive<i32>, skip_close_fd: i32) -> std::io::Re
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_allowlist() {
+        let mut worker = SpawnWorker::from_env([
+            ("PATH".into(), "/usr/bin".into()),
+            ("DD_API_KEY".into(), "secret".into()),
+            ("DD_TRACE_AGENT_URL".into(), "http://localhost:8126".into()),
+        ]);
+        worker
+            .env_allowlist(["PATH", "DD_TRACE_AGENT_URL"])
+            .append_env("DD_API_KEY", "passed");
+
+        let env: Vec<(OsString, OsString)> = vec![
+            ("PATH".into(), "/usr/bin".into()),
+            ("DD_TRACE_AGENT_URL".into(), "http://localhost:8126".into()),
+            ("DD_API_KEY".into(), "passed".into()),
+        ];
+        assert_eq!(env, worker.env);
+    }
+
+    #[test]
+    fn test_run_self_exec_entrypoint_returns_when_not_spawned() {
+        extern "C" fn entrypoint() {
+            panic!("must not be called");
+        }
+        run_self_exec_entrypoint(&[crate::entrypoint!(entrypoint)]);
+    }
+
+    #[test]
+    fn test_resource_limits_replace_each_other() {
+        let mut worker = SpawnWorker::from_env([]);
+        worker
+            .resource_limit(ResourceLimit::OpenFiles(64))
+            .resource_limit(ResourceLimit::CpuTime(10))
+            .resource_limit(ResourceLimit::OpenFiles(128));
+
+        assert_eq!(
+            vec![ResourceLimit::CpuTime(10), ResourceLimit::OpenFiles(128)],
+            worker.resource_limits
+        );
+    }
+}
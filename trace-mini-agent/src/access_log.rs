@@ -0,0 +1,184 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::http_utils::ResponseMessage;
+use hyper::{header, http, Body, Method, Request, Response, StatusCode};
+use log::{info, warn};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+pub const DEFAULT_ACCESS_LOG_SAMPLE_RATE: u64 = 10;
+
+/// A request served by the Mini Agent.
+#[derive(Debug)]
+pub struct AccessLogRecord {
+    pub method: Method,
+    pub path: String,
+    /// the Content-Length of the request, if any
+    pub size: Option<u64>,
+    /// time taken to decode, process and answer the request
+    pub duration: Duration,
+    /// None if no response could be built
+    pub status: Option<StatusCode>,
+    /// the message the response was created with, see [`ResponseMessage`]
+    pub message: Option<String>,
+}
+
+impl AccessLogRecord {
+    /// Starts a record for the request, the duration and status are filled in once it is served.
+    pub fn new(req: &Request<Body>) -> AccessLogRecord {
+        AccessLogRecord {
+            method: req.method().clone(),
+            path: req.uri().path().to_string(),
+            size: req
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|size| size.to_str().ok()?.parse().ok()),
+            duration: Duration::ZERO,
+            status: None,
+            message: None,
+        }
+    }
+
+    /// Fills in the status and message of the response the request was served with.
+    pub fn set_response(&mut self, response: &http::Result<Response<Body>>) {
+        let response = response.as_ref().ok();
+        self.status = response.map(Response::status);
+        self.message = response
+            .and_then(|response| response.extensions().get::<ResponseMessage>())
+            .map(|message| message.0.clone());
+    }
+
+    fn is_success(&self) -> bool {
+        matches!(self.status, Some(status) if status.is_success())
+    }
+}
+
+impl fmt::Display for AccessLogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "method={} path={}", self.method, self.path)?;
+        if let Some(size) = self.size {
+            write!(f, " size={size}")?;
+        }
+        write!(f, " duration_ms={}", self.duration.as_millis())?;
+        match self.status {
+            Some(status) => write!(f, " status={}", status.as_u16())?,
+            None => write!(f, " status=none")?,
+        }
+        if let Some(message) = &self.message {
+            write!(f, " message={message:?}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Logs the requests served by the Mini Agent and counts them. This is the only log line of a
+/// request, including the message of the response, e.g. why it failed. Failed requests are always
+/// logged, successful ones are sampled: only one out of `sample_rate` is logged, none if it is 0.
+#[derive(Debug)]
+pub struct AccessLog {
+    sample_rate: u64,
+    requests: AtomicU64,
+    failed_requests: AtomicU64,
+}
+
+impl Default for AccessLog {
+    fn default() -> Self {
+        AccessLog::new(DEFAULT_ACCESS_LOG_SAMPLE_RATE)
+    }
+}
+
+impl AccessLog {
+    pub fn new(sample_rate: u64) -> AccessLog {
+        AccessLog {
+            sample_rate,
+            requests: AtomicU64::new(0),
+            failed_requests: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts the request, returning whether it was logged.
+    pub fn record(&self, record: &AccessLogRecord) -> bool {
+        let count = self.requests.fetch_add(1, Ordering::Relaxed);
+        if !record.is_success() {
+            self.failed_requests.fetch_add(1, Ordering::Relaxed);
+            warn!("{record}");
+            true
+        } else if self.sample_rate != 0 && count % self.sample_rate == 0 {
+            info!("{record}");
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn requests_count(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    pub fn failed_requests_count(&self) -> u64 {
+        self.failed_requests.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_utils::create_http_response;
+
+    fn record(status: Option<StatusCode>) -> AccessLogRecord {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("http://localhost:8126/v0.4/traces")
+            .header(header::CONTENT_LENGTH, "1024")
+            .body(Body::empty())
+            .unwrap();
+        AccessLogRecord {
+            duration: Duration::from_millis(3),
+            status,
+            ..AccessLogRecord::new(&req)
+        }
+    }
+
+    #[test]
+    fn test_access_log_record_display() {
+        assert_eq!(
+            "method=POST path=/v0.4/traces size=1024 duration_ms=3 status=200",
+            record(Some(StatusCode::OK)).to_string()
+        );
+        assert_eq!(
+            "method=POST path=/v0.4/traces size=1024 duration_ms=3 status=none",
+            record(None).to_string()
+        );
+
+        let mut record = record(None);
+        record.set_response(&create_http_response(
+            "Payload too large",
+            StatusCode::PAYLOAD_TOO_LARGE,
+        ));
+        assert_eq!(
+            "method=POST path=/v0.4/traces size=1024 duration_ms=3 status=413 \
+             message=\"Payload too large\"",
+            record.to_string()
+        );
+    }
+
+    #[test]
+    fn test_access_log_sampling() {
+        let access_log = AccessLog::new(2);
+        let logged: Vec<bool> = (0..4)
+            .map(|_| access_log.record(&record(Some(StatusCode::OK))))
+            .collect();
+        assert_eq!(vec![true, false, true, false], logged);
+
+        // failures are always logged
+        assert!(access_log.record(&record(Some(StatusCode::BAD_REQUEST))));
+        assert!(access_log.record(&record(None)));
+        assert_eq!(6, access_log.requests_count());
+        assert_eq!(2, access_log.failed_requests_count());
+
+        let access_log = AccessLog::new(0);
+        assert!(!access_log.record(&record(Some(StatusCode::OK))));
+    }
+}
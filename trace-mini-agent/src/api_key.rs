@@ -0,0 +1,172 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use log::{debug, error};
+use std::borrow::Cow;
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Where the API key is read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiKeySource {
+    /// The DD_API_KEY env var
+    Env,
+    /// A file holding the key, e.g. a mounted secret (DD_API_KEY_FILE)
+    File(PathBuf),
+    /// An executable printing the key on stdout, e.g. a secrets manager client
+    /// (DD_API_KEY_SECRET_COMMAND)
+    Command(PathBuf),
+}
+
+impl ApiKeySource {
+    /// Picks the source from the environment, preferring DD_API_KEY over DD_API_KEY_FILE over
+    /// DD_API_KEY_SECRET_COMMAND.
+    pub fn from_env() -> anyhow::Result<ApiKeySource> {
+        if env::var("DD_API_KEY").is_ok() {
+            Ok(ApiKeySource::Env)
+        } else if let Ok(path) = env::var("DD_API_KEY_FILE") {
+            Ok(ApiKeySource::File(path.into()))
+        } else if let Ok(command) = env::var("DD_API_KEY_SECRET_COMMAND") {
+            Ok(ApiKeySource::Command(command.into()))
+        } else {
+            anyhow::bail!("DD_API_KEY environment variable is not set")
+        }
+    }
+
+    fn read(&self) -> anyhow::Result<String> {
+        let key = match self {
+            ApiKeySource::Env => env::var("DD_API_KEY")?,
+            ApiKeySource::File(path) => std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Failed reading API key from {path:?}: {e}"))?,
+            ApiKeySource::Command(command) => {
+                let output = Command::new(command).output()?;
+                anyhow::ensure!(
+                    output.status.success(),
+                    "API key command {command:?} failed with {}",
+                    output.status
+                );
+                String::from_utf8(output.stdout)?
+            }
+        };
+        let key = key.trim();
+        anyhow::ensure!(!key.is_empty(), "API key from {self:?} is empty");
+        Ok(key.to_string())
+    }
+}
+
+/// Holds the current API key. Keys from a file or a command can be refreshed, so that rotated
+/// secrets are picked up without restarting the Mini Agent.
+pub struct ApiKey {
+    /// None for fixed keys
+    source: Option<ApiKeySource>,
+    key: RwLock<Cow<'static, str>>,
+}
+
+impl std::fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ApiKey")
+            .field("source", &self.source)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ApiKey {
+    /// Reads the key from `source`, failing if it cannot be read or is empty.
+    pub fn new(source: ApiKeySource) -> anyhow::Result<ApiKey> {
+        let key = source.read()?;
+        Ok(ApiKey {
+            source: Some(source),
+            key: RwLock::new(key.into()),
+        })
+    }
+
+    /// A key which never changes.
+    pub fn fixed(key: impl Into<Cow<'static, str>>) -> ApiKey {
+        ApiKey {
+            source: None,
+            key: RwLock::new(key.into()),
+        }
+    }
+
+    pub fn get(&self) -> Cow<'static, str> {
+        self.key.read().unwrap().clone()
+    }
+
+    pub fn is_refreshable(&self) -> bool {
+        matches!(
+            self.source,
+            Some(ApiKeySource::File(_) | ApiKeySource::Command(_))
+        )
+    }
+
+    /// Re-reads the key from its source. On failure, the previous key is kept.
+    pub fn refresh(&self) -> anyhow::Result<()> {
+        let Some(source) = &self.source else {
+            return Ok(());
+        };
+        let key = source.read()?;
+        let mut current = self.key.write().unwrap();
+        if *current != key {
+            debug!("API key changed, using the new one");
+            *current = key.into();
+        }
+        Ok(())
+    }
+
+    /// Refreshes the key every `interval`. The source is read on a blocking thread, as running
+    /// the secret command may take a while.
+    pub async fn refresh_periodically(self: Arc<Self>, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+            let api_key = self.clone();
+            match tokio::task::spawn_blocking(move || api_key.refresh()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!("Failed refreshing the API key: {e}"),
+                Err(e) => error!("Failed refreshing the API key: {e}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_api_key_from_file_is_refreshed() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "first_key").unwrap();
+
+        let api_key = ApiKey::new(ApiKeySource::File(file.path().into())).unwrap();
+        assert!(api_key.is_refreshable());
+        assert_eq!("first_key", api_key.get());
+
+        std::fs::write(file.path(), "second_key\n").unwrap();
+        api_key.refresh().unwrap();
+        assert_eq!("second_key", api_key.get());
+
+        // a broken secret keeps the previous key
+        std::fs::write(file.path(), "").unwrap();
+        assert!(api_key.refresh().is_err());
+        assert_eq!("second_key", api_key.get());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_api_key_from_command() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut script = tempfile::NamedTempFile::new().unwrap();
+        writeln!(script, "#!/bin/sh\necho command_key").unwrap();
+        let path = script.into_temp_path();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o700)).unwrap();
+
+        let api_key = ApiKey::new(ApiKeySource::Command(path.to_path_buf())).unwrap();
+        assert_eq!("command_key", api_key.get());
+    }
+}
@@ -1,10 +1,13 @@
 // Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
 // SPDX-License-Identifier: Apache-2.0
 
+use ddcommon::config::parse_env;
 use ddcommon::Endpoint;
-use std::borrow::Cow;
 use std::env;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
 use datadog_trace_obfuscation::obfuscation_config;
 use datadog_trace_utils::config_utils::{
@@ -13,15 +16,69 @@ use datadog_trace_utils::config_utils::{
 };
 use datadog_trace_utils::trace_utils;
 
+use crate::access_log::DEFAULT_ACCESS_LOG_SAMPLE_RATE;
+use crate::api_key::{ApiKey, ApiKeySource};
+use crate::remote_config_proxy::RemoteConfigLimits;
+
+const DEFAULT_RECEIVER_PORT: u16 = 8126;
+const DEFAULT_API_KEY_REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+const DEFAULT_MAX_REQUEST_CONTENT_LENGTH: usize = 10 * 1024 * 1024; // 10MB in Bytes
+
+const DEFAULT_RECEIVER_SOCKET_MAX_CONNECTIONS: usize = 256;
+const DEFAULT_RECEIVER_SOCKET_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Where the unix socket receiver listens, who may connect to it, and how many connections it
+/// serves.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnixSocketConfig {
+    /// path of the socket file. On Linux, a path starting with `@` names a socket in the abstract
+    /// namespace, which has no file and is thus not subject to the other settings.
+    pub path: String,
+    /// permissions of the socket file, e.g. 0o660 to restrict it to a group
+    pub mode: Option<u32>,
+    /// owning user of the socket file
+    pub uid: Option<u32>,
+    /// owning group of the socket file
+    pub gid: Option<u32>,
+    /// maximum number of connections served at once, None if unlimited
+    pub max_connections: Option<usize>,
+    /// connections without any traffic for this long are closed, None to keep them open
+    pub idle_timeout: Option<Duration>,
+}
+
 #[derive(Debug)]
 pub struct Config {
+    /// one out of this many successful requests is logged, 0 disables the access log
+    pub access_log_sample_rate: u64,
+    pub api_key: Arc<ApiKey>,
+    /// how often to re-read an API key from a file or secret command
+    pub api_key_refresh_interval: Duration,
     pub dd_site: String,
     pub env_type: trace_utils::EnvironmentType,
     pub function_name: Option<String>,
+    /// time budget for decoding a trace payload, None if unlimited
+    pub max_decode_time: Option<Duration>,
     pub max_request_content_length: usize,
+    /// maximum number of spans in a trace payload, None if unlimited
+    pub max_spans_per_payload: Option<usize>,
     pub mini_agent_version: String,
     pub obfuscation_config: obfuscation_config::ObfuscationConfig,
     pub os: String,
+    /// localhost TCP port to receive traces and stats on, 0 picks a free port
+    pub receiver_port: u16,
+    /// file the receiver port is written to once listening, so that the tracer can discover an
+    /// auto-selected port
+    pub receiver_port_file: Option<String>,
+    /// unix socket to additionally receive traces and stats on
+    pub receiver_socket: Option<UnixSocketConfig>,
+    /// the /v0.7/config endpoint of the agent the remote configuration requests of tracers are
+    /// forwarded to, None if the proxy is disabled
+    pub remote_config_url: Option<hyper::Uri>,
+    /// bounds on the remote configuration responses passed back to the tracers
+    pub remote_config_limits: RemoteConfigLimits,
+    /// file the last known remote configuration is saved to, and served from at startup until
+    /// the agent answers
+    pub remote_config_snapshot_path: Option<PathBuf>,
     /// how often to flush stats, in seconds
     pub stats_flush_interval: u64,
     /// how often to flush traces, in seconds
@@ -32,11 +89,79 @@ pub struct Config {
     pub verify_env_timeout: u64,
 }
 
+/// Reads the unix socket receiver settings. A relative DD_APM_RECEIVER_SOCKET is placed in
+/// DD_APM_RECEIVER_SOCKET_DIR, the mode is octal and the owner is given as `uid`, `uid:gid` or
+/// `:gid`. A DD_APM_RECEIVER_SOCKET_MAX_CONNECTIONS or DD_APM_RECEIVER_SOCKET_IDLE_TIMEOUT of 0
+/// lifts the respective limit.
+fn receiver_socket_config() -> anyhow::Result<Option<UnixSocketConfig>> {
+    let Ok(mut path) = env::var("DD_APM_RECEIVER_SOCKET") else {
+        return Ok(None);
+    };
+    if let Ok(dir) = env::var("DD_APM_RECEIVER_SOCKET_DIR") {
+        if !path.starts_with('@') && std::path::Path::new(&path).is_relative() {
+            path = std::path::Path::new(&dir)
+                .join(path)
+                .to_string_lossy()
+                .into_owned();
+        }
+    }
+
+    let mode = match env::var("DD_APM_RECEIVER_SOCKET_MODE") {
+        Ok(mode) => Some(
+            u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+                .ok()
+                .filter(|mode| *mode <= 0o777)
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Invalid DD_APM_RECEIVER_SOCKET_MODE: {mode}. Shutting down Mini Agent."
+                    )
+                })?,
+        ),
+        Err(_) => None,
+    };
+
+    let (uid, gid) = match env::var("DD_APM_RECEIVER_SOCKET_OWNER") {
+        Ok(owner) => {
+            let invalid = || {
+                anyhow::anyhow!(
+                    "Invalid DD_APM_RECEIVER_SOCKET_OWNER: {owner}. Shutting down Mini Agent."
+                )
+            };
+            let parse_id = |id: &str| {
+                (!id.is_empty())
+                    .then(|| id.parse::<u32>().map_err(|_| invalid()))
+                    .transpose()
+            };
+            let (uid, gid) = owner.split_once(':').unwrap_or((&owner, ""));
+            let (uid, gid) = (parse_id(uid)?, parse_id(gid)?);
+            if uid.is_none() && gid.is_none() {
+                return Err(invalid());
+            }
+            (uid, gid)
+        }
+        Err(_) => (None, None),
+    };
+
+    let max_connections = parse_env::int("DD_APM_RECEIVER_SOCKET_MAX_CONNECTIONS")
+        .unwrap_or(DEFAULT_RECEIVER_SOCKET_MAX_CONNECTIONS);
+    let idle_timeout = parse_env::duration_or_zero("DD_APM_RECEIVER_SOCKET_IDLE_TIMEOUT")
+        .unwrap_or(DEFAULT_RECEIVER_SOCKET_IDLE_TIMEOUT);
+
+    Ok(Some(UnixSocketConfig {
+        path,
+        mode,
+        uid,
+        gid,
+        max_connections: (max_connections > 0).then_some(max_connections),
+        idle_timeout: (!idle_timeout.is_zero()).then_some(idle_timeout),
+    }))
+}
+
 impl Config {
     pub fn new() -> Result<Config, Box<dyn std::error::Error>> {
-        let api_key: Cow<str> = env::var("DD_API_KEY")
-            .map_err(|_| anyhow::anyhow!("DD_API_KEY environment variable is not set"))?
-            .into();
+        let api_key = Arc::new(ApiKey::new(ApiKeySource::from_env()?)?);
+        let api_key_refresh_interval = parse_env::duration("DD_API_KEY_REFRESH_INTERVAL")
+            .unwrap_or(DEFAULT_API_KEY_REFRESH_INTERVAL);
 
         let (function_name, env_type) = read_cloud_env().ok_or_else(|| {
             anyhow::anyhow!("Unable to identify environment. Shutting down Mini Agent.")
@@ -62,29 +187,98 @@ impl Config {
             )
         })?;
 
+        let receiver_port = match env::var("DD_APM_RECEIVER_PORT") {
+            Ok(port) => port.parse::<u16>().map_err(|_| {
+                anyhow::anyhow!("Invalid DD_APM_RECEIVER_PORT: {port}. Shutting down Mini Agent.")
+            })?,
+            Err(_) => DEFAULT_RECEIVER_PORT,
+        };
+
+        // forwarding tracers' remote configuration requests is opt-in, as it lets them reach the
+        // backend through the Mini Agent. The backend has no public remote configuration API, the
+        // requests go to an agent.
+        let remote_config_url = match env::var("DD_REMOTE_CONFIGURATION_URL") {
+            Ok(url) => Some(hyper::Uri::from_str(&url).map_err(|_| {
+                anyhow::anyhow!(
+                    "Invalid DD_REMOTE_CONFIGURATION_URL: {url}. Shutting down Mini Agent."
+                )
+            })?),
+            Err(_) if parse_env::bool("DD_REMOTE_CONFIGURATION_ENABLED").unwrap_or(false) => {
+                anyhow::bail!(
+                    "DD_REMOTE_CONFIGURATION_ENABLED requires DD_REMOTE_CONFIGURATION_URL, the \
+                     /v0.7/config endpoint of a Datadog Agent. Shutting down Mini Agent."
+                );
+            }
+            Err(_) => None,
+        };
+
+        let default_limits = RemoteConfigLimits::default();
+        let remote_config_limits = RemoteConfigLimits {
+            max_file_size: parse_env::int("DD_REMOTE_CONFIGURATION_MAX_FILE_SIZE")
+                .unwrap_or(default_limits.max_file_size),
+            max_target_files: parse_env::int("DD_REMOTE_CONFIGURATION_MAX_TARGET_FILES")
+                .unwrap_or(default_limits.max_target_files),
+            max_total_size: parse_env::int("DD_REMOTE_CONFIGURATION_MAX_RESPONSE_SIZE")
+                .unwrap_or(default_limits.max_total_size),
+        };
+
         let mini_agent_version: String = env!("CARGO_PKG_VERSION").to_string();
 
         Ok(Config {
+            access_log_sample_rate: parse_env::int("DD_APM_ACCESS_LOG_SAMPLE_RATE")
+                .unwrap_or(DEFAULT_ACCESS_LOG_SAMPLE_RATE),
             function_name: Some(function_name),
             env_type,
             os: env::consts::OS.to_string(),
-            max_request_content_length: 10 * 1024 * 1024, // 10MB in Bytes
+            max_decode_time: parse_env::duration("DD_APM_MAX_DECODE_TIME"),
+            max_request_content_length: parse_env::int("DD_APM_MAX_PAYLOAD_SIZE")
+                .unwrap_or(DEFAULT_MAX_REQUEST_CONTENT_LENGTH),
+            max_spans_per_payload: parse_env::int("DD_APM_MAX_SPANS_PER_PAYLOAD"),
             trace_flush_interval: 3,
             stats_flush_interval: 3,
             verify_env_timeout: 100,
             dd_site,
             trace_intake: Endpoint {
                 url: hyper::Uri::from_str(&trace_intake_url).unwrap(),
-                api_key: Some(api_key.clone()),
+                api_key: Some(api_key.get()),
+                ..Default::default()
             },
             trace_stats_intake: Endpoint {
                 url: hyper::Uri::from_str(&trace_stats_intake_url).unwrap(),
-                api_key: Some(api_key),
+                api_key: Some(api_key.get()),
+                ..Default::default()
             },
+            api_key,
+            api_key_refresh_interval,
             obfuscation_config,
             mini_agent_version,
+            receiver_port,
+            receiver_port_file: env::var("DD_APM_RECEIVER_PORT_FILE").ok(),
+            receiver_socket: receiver_socket_config()?,
+            remote_config_url,
+            remote_config_limits,
+            remote_config_snapshot_path: env::var_os("DD_REMOTE_CONFIGURATION_SNAPSHOT_PATH")
+                .map(PathBuf::from),
         })
     }
+
+    /// The trace intake, with the current API key.
+    pub fn trace_intake_endpoint(&self) -> Endpoint {
+        Endpoint {
+            url: self.trace_intake.url.clone(),
+            api_key: Some(self.api_key.get()),
+            ..Default::default()
+        }
+    }
+
+    /// The trace stats intake, with the current API key.
+    pub fn trace_stats_intake_endpoint(&self) -> Endpoint {
+        Endpoint {
+            url: self.trace_stats_intake.url.clone(),
+            api_key: Some(self.api_key.get()),
+            ..Default::default()
+        }
+    }
 }
 
 #[cfg(test)]
@@ -92,6 +286,8 @@ mod tests {
     use duplicate::duplicate_item;
     use serial_test::serial;
     use std::env;
+    use std::path::PathBuf;
+    use std::time::Duration;
 
     use crate::config;
 
@@ -209,4 +405,148 @@ mod tests {
         env::remove_var("DD_APM_DD_URL");
         env::remove_var("K_SERVICE");
     }
+
+    #[test]
+    #[serial]
+    fn test_api_key_file() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), "_not_a_real_key_\n").unwrap();
+        env::set_var("DD_API_KEY_FILE", file.path());
+        env::set_var("K_SERVICE", "function_name");
+
+        let config = config::Config::new().unwrap();
+        assert_eq!(
+            Some("_not_a_real_key_"),
+            config.trace_intake_endpoint().api_key.as_deref()
+        );
+
+        std::fs::write(file.path(), "_rotated_key_").unwrap();
+        config.api_key.refresh().unwrap();
+        assert_eq!(
+            Some("_rotated_key_"),
+            config.trace_stats_intake_endpoint().api_key.as_deref()
+        );
+
+        env::remove_var("DD_API_KEY_FILE");
+        env::remove_var("K_SERVICE");
+    }
+
+    #[test]
+    #[serial]
+    fn test_receiver_port() {
+        env::set_var("DD_API_KEY", "_not_a_real_key_");
+        env::set_var("K_SERVICE", "function_name");
+        let config = config::Config::new().unwrap();
+        assert_eq!(config.receiver_port, 8126);
+
+        env::set_var("DD_APM_RECEIVER_PORT", "0");
+        let config = config::Config::new().unwrap();
+        assert_eq!(config.receiver_port, 0);
+
+        env::set_var("DD_APM_RECEIVER_PORT", "not_a_port");
+        assert!(config::Config::new().is_err());
+
+        env::remove_var("DD_API_KEY");
+        env::remove_var("K_SERVICE");
+        env::remove_var("DD_APM_RECEIVER_PORT");
+    }
+
+    #[test]
+    #[serial]
+    fn test_receiver_socket() {
+        env::set_var("DD_API_KEY", "_not_a_real_key_");
+        env::set_var("K_SERVICE", "function_name");
+        let config = config::Config::new().unwrap();
+        assert!(config.receiver_socket.is_none());
+
+        env::set_var("DD_APM_RECEIVER_SOCKET", "/tmp/mini-agent.sock");
+        let socket = config::Config::new().unwrap().receiver_socket.unwrap();
+        assert_eq!(socket.path, "/tmp/mini-agent.sock");
+        assert_eq!((socket.mode, socket.uid, socket.gid), (None, None, None));
+        assert_eq!(socket.max_connections, Some(256));
+        assert_eq!(socket.idle_timeout, Some(Duration::from_secs(60)));
+
+        env::set_var("DD_APM_RECEIVER_SOCKET_MAX_CONNECTIONS", "0");
+        env::set_var("DD_APM_RECEIVER_SOCKET_IDLE_TIMEOUT", "2.5");
+        let socket = config::Config::new().unwrap().receiver_socket.unwrap();
+        assert_eq!(socket.max_connections, None);
+        assert_eq!(socket.idle_timeout, Some(Duration::from_millis(2500)));
+
+        env::set_var("DD_APM_RECEIVER_SOCKET_IDLE_TIMEOUT", "0");
+        let socket = config::Config::new().unwrap().receiver_socket.unwrap();
+        assert_eq!(socket.idle_timeout, None);
+        env::set_var("DD_APM_RECEIVER_SOCKET_IDLE_TIMEOUT", "-1");
+        let socket = config::Config::new().unwrap().receiver_socket.unwrap();
+        assert_eq!(socket.idle_timeout, Some(Duration::from_secs(60)));
+
+        env::set_var("DD_APM_RECEIVER_SOCKET", "mini-agent.sock");
+        env::set_var("DD_APM_RECEIVER_SOCKET_DIR", "/run/datadog");
+        env::set_var("DD_APM_RECEIVER_SOCKET_MODE", "0660");
+        env::set_var("DD_APM_RECEIVER_SOCKET_OWNER", ":1001");
+        let socket = config::Config::new().unwrap().receiver_socket.unwrap();
+        assert_eq!(socket.path, "/run/datadog/mini-agent.sock");
+        assert_eq!(
+            (socket.mode, socket.uid, socket.gid),
+            (Some(0o660), None, Some(1001))
+        );
+
+        env::set_var("DD_APM_RECEIVER_SOCKET_OWNER", "1000:1001");
+        let socket = config::Config::new().unwrap().receiver_socket.unwrap();
+        assert_eq!((socket.uid, socket.gid), (Some(1000), Some(1001)));
+
+        env::set_var("DD_APM_RECEIVER_SOCKET_OWNER", "root");
+        assert!(config::Config::new().is_err());
+        env::set_var("DD_APM_RECEIVER_SOCKET_OWNER", "1000");
+        env::set_var("DD_APM_RECEIVER_SOCKET_MODE", "0999");
+        assert!(config::Config::new().is_err());
+
+        env::remove_var("DD_API_KEY");
+        env::remove_var("K_SERVICE");
+        env::remove_var("DD_APM_RECEIVER_SOCKET");
+        env::remove_var("DD_APM_RECEIVER_SOCKET_DIR");
+        env::remove_var("DD_APM_RECEIVER_SOCKET_MODE");
+        env::remove_var("DD_APM_RECEIVER_SOCKET_OWNER");
+        env::remove_var("DD_APM_RECEIVER_SOCKET_MAX_CONNECTIONS");
+        env::remove_var("DD_APM_RECEIVER_SOCKET_IDLE_TIMEOUT");
+    }
+
+    #[test]
+    #[serial]
+    fn test_remote_config_url() {
+        env::set_var("DD_API_KEY", "_not_a_real_key_");
+        env::set_var("K_SERVICE", "function_name");
+        let config = config::Config::new().unwrap();
+        assert!(config.remote_config_url.is_none());
+
+        // there is no remote configuration endpoint on the site to default to
+        env::set_var("DD_REMOTE_CONFIGURATION_ENABLED", "true");
+        assert!(config::Config::new().is_err());
+
+        env::set_var(
+            "DD_REMOTE_CONFIGURATION_URL",
+            "http://127.0.0.1:5000/v0.7/config",
+        );
+        let config = config::Config::new().unwrap();
+        assert_eq!(
+            config.remote_config_url.unwrap(),
+            "http://127.0.0.1:5000/v0.7/config"
+        );
+        assert!(config.remote_config_snapshot_path.is_none());
+
+        env::set_var(
+            "DD_REMOTE_CONFIGURATION_SNAPSHOT_PATH",
+            "/tmp/remote_config.json",
+        );
+        let config = config::Config::new().unwrap();
+        assert_eq!(
+            Some(PathBuf::from("/tmp/remote_config.json")),
+            config.remote_config_snapshot_path
+        );
+
+        env::remove_var("DD_API_KEY");
+        env::remove_var("K_SERVICE");
+        env::remove_var("DD_REMOTE_CONFIGURATION_ENABLED");
+        env::remove_var("DD_REMOTE_CONFIGURATION_URL");
+        env::remove_var("DD_REMOTE_CONFIGURATION_SNAPSHOT_PATH");
+    }
 }
@@ -0,0 +1,270 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use hyper::{http, Body, Response, StatusCode};
+use serde_json::json;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::access_log::AccessLog;
+
+/// Outcome of the most recent flush to the Datadog intake, used as a proxy for uploader
+/// connectivity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UploaderConnectivity {
+    /// Nothing was flushed yet.
+    Unknown,
+    Connected,
+    Failing,
+}
+
+impl UploaderConnectivity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            UploaderConnectivity::Unknown => "unknown",
+            UploaderConnectivity::Connected => "connected",
+            UploaderConnectivity::Failing => "failing",
+        }
+    }
+}
+
+/// The flushers reporting their outcomes to the [`HealthState`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flusher {
+    Traces,
+    Stats,
+}
+
+#[derive(Debug)]
+struct FlushState {
+    connectivity: UploaderConnectivity,
+    last_flush_error: Option<String>,
+    last_successful_flush: Option<SystemTime>,
+}
+
+impl FlushState {
+    fn new() -> Self {
+        FlushState {
+            connectivity: UploaderConnectivity::Unknown,
+            last_flush_error: None,
+            last_successful_flush: None,
+        }
+    }
+
+    fn status_json(&self) -> serde_json::Value {
+        let last_successful_flush = self
+            .last_successful_flush
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+        json!({
+            "uploader_connectivity": self.connectivity.as_str(),
+            "last_flush_error": self.last_flush_error,
+            "last_successful_flush": last_successful_flush,
+        })
+    }
+}
+
+/// Shared state reported by the /health and /ready endpoints. The flushers update it after every
+/// flush, so that orchestrators (and the spawning library) can tell whether the mini agent is
+/// actually able to deliver data. Each flusher has its own flush state: the stats being flushed
+/// doesn't hide that the traces can't be, and the other way around.
+#[derive(Debug)]
+pub struct HealthState {
+    ready: AtomicBool,
+    trace_queue_depth: AtomicUsize,
+    stats_queue_depth: AtomicUsize,
+    rejected_connections: AtomicU64,
+    trace_flush: Mutex<FlushState>,
+    stats_flush: Mutex<FlushState>,
+    pub access_log: AccessLog,
+}
+
+impl Default for HealthState {
+    fn default() -> Self {
+        HealthState::new(AccessLog::default())
+    }
+}
+
+impl HealthState {
+    pub fn new(access_log: AccessLog) -> Self {
+        HealthState {
+            ready: AtomicBool::new(false),
+            trace_queue_depth: AtomicUsize::new(0),
+            stats_queue_depth: AtomicUsize::new(0),
+            rejected_connections: AtomicU64::new(0),
+            trace_flush: Mutex::new(FlushState::new()),
+            stats_flush: Mutex::new(FlushState::new()),
+            access_log,
+        }
+    }
+
+    /// Marks the mini agent as accepting requests.
+    pub fn set_ready(&self) {
+        self.ready.store(true, Ordering::Relaxed);
+    }
+
+    pub fn set_trace_queue_depth(&self, depth: usize) {
+        self.trace_queue_depth.store(depth, Ordering::Relaxed);
+    }
+
+    pub fn set_stats_queue_depth(&self, depth: usize) {
+        self.stats_queue_depth.store(depth, Ordering::Relaxed);
+    }
+
+    /// Counts a unix socket connection closed because of the connection limit.
+    pub fn record_rejected_connection(&self) {
+        self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn rejected_connections(&self) -> u64 {
+        self.rejected_connections.load(Ordering::Relaxed)
+    }
+
+    fn flush_state(&self, flusher: Flusher) -> &Mutex<FlushState> {
+        match flusher {
+            Flusher::Traces => &self.trace_flush,
+            Flusher::Stats => &self.stats_flush,
+        }
+    }
+
+    pub fn record_flush_result<E: std::fmt::Display>(
+        &self,
+        flusher: Flusher,
+        result: &Result<(), E>,
+    ) {
+        let mut flush = self.flush_state(flusher).lock().unwrap();
+        match result {
+            Ok(()) => {
+                flush.connectivity = UploaderConnectivity::Connected;
+                flush.last_successful_flush = Some(SystemTime::now());
+            }
+            Err(e) => {
+                flush.connectivity = UploaderConnectivity::Failing;
+                flush.last_flush_error = Some(e.to_string());
+            }
+        }
+    }
+
+    pub fn flusher_connectivity(&self, flusher: Flusher) -> UploaderConnectivity {
+        self.flush_state(flusher).lock().unwrap().connectivity
+    }
+
+    /// Failing if any flusher is failing, connected once a flusher succeeded otherwise.
+    pub fn connectivity(&self) -> UploaderConnectivity {
+        let traces = self.flusher_connectivity(Flusher::Traces);
+        let stats = self.flusher_connectivity(Flusher::Stats);
+        match (traces, stats) {
+            (UploaderConnectivity::Failing, _) | (_, UploaderConnectivity::Failing) => {
+                UploaderConnectivity::Failing
+            }
+            (UploaderConnectivity::Connected, _) | (_, UploaderConnectivity::Connected) => {
+                UploaderConnectivity::Connected
+            }
+            _ => UploaderConnectivity::Unknown,
+        }
+    }
+
+    /// The mini agent is ready once it accepts requests, as long as the most recent flush of
+    /// neither flusher failed.
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed) && self.connectivity() != UploaderConnectivity::Failing
+    }
+
+    fn status_json(&self) -> serde_json::Value {
+        json!({
+            "ready": self.ready.load(Ordering::Relaxed),
+            "uploader_connectivity": self.connectivity().as_str(),
+            "trace_queue_depth": self.trace_queue_depth.load(Ordering::Relaxed),
+            "stats_queue_depth": self.stats_queue_depth.load(Ordering::Relaxed),
+            "traces": self.trace_flush.lock().unwrap().status_json(),
+            "stats": self.stats_flush.lock().unwrap().status_json(),
+            "requests": self.access_log.requests_count(),
+            "failed_requests": self.access_log.failed_requests_count(),
+            "rejected_connections": self.rejected_connections(),
+        })
+    }
+
+    /// Liveness probe: always succeeds while the server is able to answer, reporting the current
+    /// state in the body.
+    pub fn health_response(&self) -> http::Result<Response<Body>> {
+        Response::builder()
+            .status(StatusCode::OK)
+            .body(Body::from(self.status_json().to_string()))
+    }
+
+    /// Readiness probe: returns 503 while the mini agent is not functional.
+    pub fn ready_response(&self) -> http::Result<Response<Body>> {
+        let status = if self.is_ready() {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        Response::builder()
+            .status(status)
+            .body(Body::from(self.status_json().to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Flusher, HealthState, UploaderConnectivity};
+    use hyper::{Body, Response, StatusCode};
+
+    async fn get_response_body_as_json(response: Response<Body>) -> serde_json::Value {
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_not_ready_before_start() {
+        let health = HealthState::default();
+        assert_eq!(UploaderConnectivity::Unknown, health.connectivity());
+
+        let response = health.ready_response().unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+
+        let response = health.health_response().unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        let body = get_response_body_as_json(response).await;
+        assert!(!body["ready"].as_bool().unwrap());
+        assert_eq!("unknown", body["uploader_connectivity"]);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_ready_follows_flush_results() {
+        let health = HealthState::default();
+        health.set_ready();
+        health.set_trace_queue_depth(3);
+        assert!(health.is_ready());
+
+        health
+            .record_flush_result::<String>(Flusher::Traces, &Err("connection refused".to_string()));
+        let response = health.ready_response().unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+        let body = get_response_body_as_json(response).await;
+        assert_eq!("failing", body["uploader_connectivity"]);
+        assert_eq!("connection refused", body["traces"]["last_flush_error"]);
+        assert_eq!(3, body["trace_queue_depth"]);
+
+        // the stats being flushed doesn't hide that the traces are not
+        health.record_flush_result::<String>(Flusher::Stats, &Ok(()));
+        assert!(!health.is_ready());
+        assert_eq!(
+            UploaderConnectivity::Connected,
+            health.flusher_connectivity(Flusher::Stats)
+        );
+
+        health.record_flush_result::<String>(Flusher::Traces, &Ok(()));
+        let response = health.ready_response().unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        let body = get_response_body_as_json(response).await;
+        assert_eq!("connected", body["uploader_connectivity"]);
+        // the last error is kept around for diagnosis
+        assert_eq!("connection refused", body["traces"]["last_flush_error"]);
+        assert!(body["traces"]["last_successful_flush"].is_u64());
+        assert!(body["stats"]["last_flush_error"].is_null());
+    }
+}
@@ -2,41 +2,39 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use hyper::{
+    body::HttpBody,
     header,
     http::{self, HeaderMap},
     Body, Response, StatusCode,
 };
-use log::{error, info};
 use serde_json::json;
 
-/// Does two things:
-/// 1. Logs the given message. A success status code (within 200-299) will cause an info log to be
-///    written,
-/// otherwise error will be written.
-/// 2. Returns the given message in the body of JSON response with the given status code.
+/// The message a response was created with by [`create_http_response`], kept in the response
+/// extensions so that the access log of the request includes it.
+#[derive(Clone, Debug)]
+pub struct ResponseMessage(pub String);
+
+/// Returns the given message in the body of JSON response with the given status code. The message
+/// is not logged here: it ends up in the access log line of the request, see
+/// [`crate::access_log::AccessLog`].
 ///
 /// Response body format:
 /// {
 ///     "message": message
 /// }
-pub fn log_and_create_http_response(
-    message: &str,
-    status: StatusCode,
-) -> http::Result<Response<Body>> {
-    if status.is_success() {
-        info!("{message}");
-    } else {
-        error!("{message}");
-    }
+pub fn create_http_response(message: &str, status: StatusCode) -> http::Result<Response<Body>> {
     let body = json!({ "message": message }).to_string();
-    Response::builder().status(status).body(Body::from(body))
+    Response::builder()
+        .status(status)
+        .extension(ResponseMessage(message.to_string()))
+        .body(Body::from(body))
 }
 
 /// Takes a request's header map, and verifies that the "content-length" header is present, valid,
 /// and less than the given max_content_length.
 ///
-/// Will return None if no issues are found. Otherwise logs an error (with the given prefix) and
-/// returns and HTTP Response with the appropriate error status code.
+/// Will return None if no issues are found. Otherwise returns an HTTP Response with an error
+/// message (with the given prefix) and the appropriate error status code.
 pub fn verify_request_content_length(
     header_map: &HeaderMap,
     max_content_length: usize,
@@ -45,7 +43,7 @@ pub fn verify_request_content_length(
     let content_length_header = match header_map.get(header::CONTENT_LENGTH) {
         Some(res) => res,
         None => {
-            return Some(log_and_create_http_response(
+            return Some(create_http_response(
                 &format!("{error_message_prefix}: Missing Content-Length header"),
                 StatusCode::LENGTH_REQUIRED,
             ));
@@ -54,7 +52,7 @@ pub fn verify_request_content_length(
     let header_as_string = match content_length_header.to_str() {
         Ok(res) => res,
         Err(_) => {
-            return Some(log_and_create_http_response(
+            return Some(create_http_response(
                 &format!("{error_message_prefix}: Invalid Content-Length header"),
                 StatusCode::BAD_REQUEST,
             ));
@@ -63,14 +61,14 @@ pub fn verify_request_content_length(
     let content_length = match header_as_string.to_string().parse::<usize>() {
         Ok(res) => res,
         Err(_) => {
-            return Some(log_and_create_http_response(
+            return Some(create_http_response(
                 &format!("{error_message_prefix}: Invalid Content-Length header"),
                 StatusCode::BAD_REQUEST,
             ));
         }
     };
     if content_length > max_content_length {
-        return Some(log_and_create_http_response(
+        return Some(create_http_response(
             &format!("{error_message_prefix}: Payload too large"),
             StatusCode::PAYLOAD_TOO_LARGE,
         ));
@@ -78,6 +76,39 @@ pub fn verify_request_content_length(
     None
 }
 
+/// Reads the whole request body, up to max_content_length bytes. The Content-Length header may
+/// not match the body, so reading stops as soon as the limit is exceeded, instead of buffering a
+/// runaway body.
+///
+/// Returns an HTTP Response with an error message (with the given prefix) and the appropriate error
+/// status code if the body can't be read or is too large.
+pub async fn read_request_body(
+    mut body: Body,
+    max_content_length: usize,
+    error_message_prefix: &str,
+) -> Result<Vec<u8>, http::Result<Response<Body>>> {
+    let mut buffer = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                return Err(create_http_response(
+                    &format!("{error_message_prefix}: Error reading request body: {err}"),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                ));
+            }
+        };
+        if buffer.len() + chunk.len() > max_content_length {
+            return Err(create_http_response(
+                &format!("{error_message_prefix}: Payload too large"),
+                StatusCode::PAYLOAD_TOO_LARGE,
+            ));
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+    Ok(buffer)
+}
+
 #[cfg(test)]
 mod tests {
     use hyper::header;
@@ -86,7 +117,7 @@ mod tests {
     use hyper::Response;
     use hyper::StatusCode;
 
-    use super::verify_request_content_length;
+    use super::{read_request_body, verify_request_content_length};
 
     fn create_test_headers_with_content_length(val: &str) -> HeaderMap {
         let mut map = HeaderMap::new();
@@ -168,4 +199,31 @@ mod tests {
             "{\"message\":\"Test Prefix: Payload too large\"}".to_string()
         );
     }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_read_request_body() {
+        let body = read_request_body(Body::from("0123456789"), 10, "Test Prefix").await;
+        assert_eq!(b"0123456789".to_vec(), body.unwrap());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_read_request_body_too_long() {
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            // more than announced by any Content-Length header, and never ending
+            while sender.send_data(vec![0; 1024].into()).await.is_ok() {}
+        });
+
+        let response = read_request_body(body, 10 * 1024, "Test Prefix")
+            .await
+            .unwrap_err()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            get_response_body_as_string(response).await,
+            "{\"message\":\"Test Prefix: Payload too large\"}".to_string()
+        );
+    }
 }
@@ -1,11 +1,19 @@
 // Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
 // SPDX-License-Identifier: Apache-2.0
 
+pub mod access_log;
+pub mod api_key;
 pub mod config;
 pub mod env_verifier;
+pub mod health;
 pub mod http_utils;
 pub mod mini_agent;
+pub mod otlp;
+pub mod remote_config_proxy;
+pub mod remote_config_snapshot;
 pub mod stats_flusher;
 pub mod stats_processor;
 pub mod trace_flusher;
 pub mod trace_processor;
+#[cfg(unix)]
+pub mod unix_socket;
@@ -1,6 +1,7 @@
 // Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
 // SPDX-License-Identifier: Apache-2.0
 
+use hyper::server::conn::AddrIncoming;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{http, Body, Method, Request, Response, Server, StatusCode};
 use log::{debug, error, info};
@@ -11,16 +12,25 @@ use std::sync::Arc;
 use std::time::Instant;
 use tokio::sync::mpsc::{self, Receiver, Sender};
 
-use crate::http_utils::log_and_create_http_response;
-use crate::{config, env_verifier, stats_flusher, stats_processor, trace_flusher, trace_processor};
+use crate::access_log::{AccessLog, AccessLogRecord};
+use crate::http_utils::create_http_response;
+#[cfg(unix)]
+use crate::unix_socket;
+use crate::{
+    config, env_verifier, health, remote_config_proxy, stats_flusher, stats_processor,
+    trace_flusher, trace_processor,
+};
 use datadog_trace_protobuf::pb;
 use datadog_trace_utils::trace_utils;
 use datadog_trace_utils::trace_utils::SendData;
 
-const MINI_AGENT_PORT: usize = 8126;
 const TRACE_ENDPOINT_PATH: &str = "/v0.4/traces";
 const STATS_ENDPOINT_PATH: &str = "/v0.6/stats";
+const OTLP_TRACE_ENDPOINT_PATH: &str = "/v1/traces";
+const REMOTE_CONFIG_ENDPOINT_PATH: &str = "/v0.7/config";
 const INFO_ENDPOINT_PATH: &str = "/info";
+const HEALTH_ENDPOINT_PATH: &str = "/health";
+const READY_ENDPOINT_PATH: &str = "/ready";
 const TRACER_PAYLOAD_CHANNEL_BUFFER_SIZE: usize = 10;
 const STATS_PAYLOAD_CHANNEL_BUFFER_SIZE: usize = 10;
 
@@ -37,6 +47,9 @@ impl MiniAgent {
     #[tokio::main]
     pub async fn start_mini_agent(&self) -> Result<(), Box<dyn std::error::Error>> {
         let now = Instant::now();
+        let health = Arc::new(health::HealthState::new(AccessLog::new(
+            self.config.access_log_sample_rate,
+        )));
 
         // verify we are in a google cloud funtion environment. if not, shut down the mini agent.
         let mini_agent_metadata = Arc::new(
@@ -54,6 +67,15 @@ impl MiniAgent {
             now.elapsed().as_millis()
         );
 
+        if self.config.api_key.is_refreshable() {
+            tokio::spawn(
+                self.config
+                    .api_key
+                    .clone()
+                    .refresh_periodically(self.config.api_key_refresh_interval),
+            );
+        }
+
         // setup a channel to send processed traces to our flusher. tx is passed through each
         // endpoint_handler to the trace processor, which uses it to send de-serialized
         // processed trace payloads to our trace flusher.
@@ -64,10 +86,11 @@ impl MiniAgent {
         // flush to backend.
         let trace_flusher = self.trace_flusher.clone();
         let trace_config = self.config.clone();
+        let trace_health = health.clone();
         tokio::spawn(async move {
             let trace_flusher = trace_flusher.clone();
             trace_flusher
-                .start_trace_flusher(trace_config.clone(), trace_rx)
+                .start_trace_flusher(trace_config.clone(), trace_rx, trace_health)
                 .await;
         });
 
@@ -80,10 +103,11 @@ impl MiniAgent {
         // start our stats flusher.
         let stats_flusher = self.stats_flusher.clone();
         let stats_config = self.config.clone();
+        let stats_health = health.clone();
         tokio::spawn(async move {
             let stats_flusher = stats_flusher.clone();
             stats_flusher
-                .start_stats_flusher(stats_config, stats_rx)
+                .start_stats_flusher(stats_config, stats_rx, stats_health)
                 .await;
         });
 
@@ -91,38 +115,68 @@ impl MiniAgent {
         let trace_processor = self.trace_processor.clone();
         let stats_processor = self.stats_processor.clone();
         let endpoint_config = self.config.clone();
+        let endpoint_health = health.clone();
+        let mut remote_config_proxy = remote_config_proxy::RemoteConfigProxy::default();
+        if let Some(path) = &self.config.remote_config_snapshot_path {
+            remote_config_proxy = remote_config_proxy.with_snapshot_path(path.clone());
+        }
+        let remote_config_proxy = Arc::new(remote_config_proxy);
 
-        let make_svc = make_service_fn(move |_| {
-            let trace_processor = trace_processor.clone();
-            let trace_tx = trace_tx.clone();
-
-            let stats_processor = stats_processor.clone();
-            let stats_tx = stats_tx.clone();
-
-            let endpoint_config = endpoint_config.clone();
-            let mini_agent_metadata = Arc::clone(&mini_agent_metadata);
-
-            let service = service_fn(move |req| {
-                MiniAgent::trace_endpoint_handler(
-                    endpoint_config.clone(),
-                    req,
-                    trace_processor.clone(),
-                    trace_tx.clone(),
-                    stats_processor.clone(),
-                    stats_tx.clone(),
-                    Arc::clone(&mini_agent_metadata),
-                )
+        // the same handler serves the TCP port and the optional unix socket
+        let service = move |req: Request<Body>| {
+            let mut record = AccessLogRecord::new(&req);
+            let health = endpoint_health.clone();
+            let response = MiniAgent::trace_endpoint_handler(
+                endpoint_config.clone(),
+                req,
+                trace_processor.clone(),
+                trace_tx.clone(),
+                stats_processor.clone(),
+                stats_tx.clone(),
+                Arc::clone(&mini_agent_metadata),
+                endpoint_health.clone(),
+                remote_config_proxy.clone(),
+            );
+            async move {
+                let start = Instant::now();
+                let response = response.await;
+                record.duration = start.elapsed();
+                record.set_response(&response);
+                health.access_log.record(&record);
+                response
+            }
+        };
+
+        #[cfg(unix)]
+        if let Some(socket) = &self.config.receiver_socket {
+            let listener = unix_socket::bind(socket)?;
+            let listener = unix_socket::UnixListenerTracked::new(listener, socket, health.clone());
+            let uds_server = listener.serve(service.clone());
+            info!("Mini Agent listening on unix socket {}", socket.path);
+            tokio::spawn(async move {
+                if let Err(e) = uds_server.await {
+                    error!("Unix socket server error: {e}");
+                }
             });
+        }
 
+        let make_svc = make_service_fn(move |_| {
+            let service = service_fn(service.clone());
             async move { Ok::<_, Infallible>(service) }
         });
 
-        let addr = SocketAddr::from(([127, 0, 0, 1], MINI_AGENT_PORT as u16));
-        let server_builder = Server::try_bind(&addr)?;
+        let addr = SocketAddr::from(([127, 0, 0, 1], self.config.receiver_port));
+        let incoming = AddrIncoming::bind(&addr)?;
+        // the configured port may be 0, in which case the OS picked one
+        let port = incoming.local_addr().port();
+        if let Some(port_file) = &self.config.receiver_port_file {
+            std::fs::write(port_file, port.to_string())?;
+        }
 
-        let server = server_builder.serve(make_svc);
+        let server = Server::builder(incoming).serve(make_svc);
+        health.set_ready();
 
-        info!("Mini Agent started: listening on port {MINI_AGENT_PORT}");
+        info!("Mini Agent started: listening on port {port}");
         debug!(
             "Time taken start the Mini Agent: {} ms",
             now.elapsed().as_millis()
@@ -137,6 +191,7 @@ impl MiniAgent {
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn trace_endpoint_handler(
         config: Arc<config::Config>,
         req: Request<Body>,
@@ -145,6 +200,8 @@ impl MiniAgent {
         stats_processor: Arc<dyn stats_processor::StatsProcessor + Send + Sync>,
         stats_tx: Sender<pb::ClientStatsPayload>,
         mini_agent_metadata: Arc<trace_utils::MiniAgentMetadata>,
+        health: Arc<health::HealthState>,
+        remote_config_proxy: Arc<remote_config_proxy::RemoteConfigProxy>,
     ) -> http::Result<Response<Body>> {
         match (req.method(), req.uri().path()) {
             (&Method::PUT | &Method::POST, TRACE_ENDPOINT_PATH) => {
@@ -153,28 +210,59 @@ impl MiniAgent {
                     .await
                 {
                     Ok(res) => Ok(res),
-                    Err(err) => log_and_create_http_response(
+                    Err(err) => create_http_response(
                         &format!("Error processing traces: {err}"),
                         StatusCode::INTERNAL_SERVER_ERROR,
                     ),
                 }
             }
+            (&Method::POST, OTLP_TRACE_ENDPOINT_PATH) => {
+                match trace_processor
+                    .process_otlp_traces(config, req, trace_tx, mini_agent_metadata)
+                    .await
+                {
+                    Ok(res) => Ok(res),
+                    Err(err) => create_http_response(
+                        &format!("Error processing OTLP traces: {err}"),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    ),
+                }
+            }
             (&Method::PUT | &Method::POST, STATS_ENDPOINT_PATH) => {
                 match stats_processor.process_stats(config, req, stats_tx).await {
                     Ok(res) => Ok(res),
-                    Err(err) => log_and_create_http_response(
+                    Err(err) => create_http_response(
                         &format!("Error processing trace stats: {err}"),
                         StatusCode::INTERNAL_SERVER_ERROR,
                     ),
                 }
             }
-            (_, INFO_ENDPOINT_PATH) => match Self::info_handler() {
+            (&Method::POST, REMOTE_CONFIG_ENDPOINT_PATH) => {
+                remote_config_proxy
+                    .proxy(&config, req, &mini_agent_metadata)
+                    .await
+            }
+            (_, INFO_ENDPOINT_PATH) => match Self::info_handler(&config) {
                 Ok(res) => Ok(res),
-                Err(err) => log_and_create_http_response(
+                Err(err) => create_http_response(
                     &format!("Info endpoint error: {err}"),
                     StatusCode::INTERNAL_SERVER_ERROR,
                 ),
             },
+            (&Method::GET, HEALTH_ENDPOINT_PATH) => match health.health_response() {
+                Ok(res) => Ok(res),
+                Err(err) => create_http_response(
+                    &format!("Health endpoint error: {err}"),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                ),
+            },
+            (&Method::GET, READY_ENDPOINT_PATH) => match health.ready_response() {
+                Ok(res) => Ok(res),
+                Err(err) => create_http_response(
+                    &format!("Ready endpoint error: {err}"),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                ),
+            },
             _ => {
                 let mut not_found = Response::default();
                 *not_found.status_mut() = StatusCode::NOT_FOUND;
@@ -183,14 +271,22 @@ impl MiniAgent {
         }
     }
 
-    fn info_handler() -> http::Result<Response<Body>> {
+    fn info_handler(config: &config::Config) -> http::Result<Response<Body>> {
+        let mut endpoints = vec![
+            TRACE_ENDPOINT_PATH,
+            STATS_ENDPOINT_PATH,
+            OTLP_TRACE_ENDPOINT_PATH,
+            INFO_ENDPOINT_PATH,
+            HEALTH_ENDPOINT_PATH,
+            READY_ENDPOINT_PATH,
+        ];
+        // tracers only poll for remote configuration if the endpoint is advertised
+        if config.remote_config_url.is_some() {
+            endpoints.push(REMOTE_CONFIG_ENDPOINT_PATH);
+        }
         let response_json = json!(
             {
-                "endpoints": [
-                    TRACE_ENDPOINT_PATH,
-                    STATS_ENDPOINT_PATH,
-                    INFO_ENDPOINT_PATH
-                ],
+                "endpoints": endpoints,
                 "client_drop_p0s": true,
             }
         );
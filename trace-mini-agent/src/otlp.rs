@@ -0,0 +1,434 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! Conversion of OTLP traces, as sent by OpenTelemetry SDKs over OTLP/HTTP (protobuf), into
+//! Datadog spans. The messages below are the subset of opentelemetry-proto's
+//! `ExportTraceServiceRequest` needed for the conversion; other fields are skipped when decoding.
+
+use std::collections::HashMap;
+
+use datadog_trace_protobuf::pb;
+use hyper::{header, http, Body, Response, StatusCode};
+use prost::Message;
+
+const DEFAULT_SERVICE_NAME: &str = "otlpresourcenoservicename";
+const STATUS_CODE_ERROR: i32 = 2;
+/// Holds the high 64 bits of 128 bit trace ids, as hex, like Datadog tracers propagate them.
+const TRACE_ID_HIGH_BITS_KEY: &str = "_dd.p.tid";
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ExportTraceServiceRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub resource_spans: Vec<ResourceSpans>,
+}
+
+/// The response to a successful export. The spans are accepted as a whole, so there is never a
+/// partial success to report.
+#[derive(Clone, PartialEq, Message)]
+pub struct ExportTraceServiceResponse {
+    #[prost(message, optional, tag = "1")]
+    pub partial_success: Option<ExportTracePartialSuccess>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ExportTracePartialSuccess {
+    #[prost(int64, tag = "1")]
+    pub rejected_spans: i64,
+    #[prost(string, tag = "2")]
+    pub error_message: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ResourceSpans {
+    #[prost(message, optional, tag = "1")]
+    pub resource: Option<Resource>,
+    #[prost(message, repeated, tag = "2")]
+    pub scope_spans: Vec<ScopeSpans>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Resource {
+    #[prost(message, repeated, tag = "1")]
+    pub attributes: Vec<KeyValue>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ScopeSpans {
+    #[prost(message, optional, tag = "1")]
+    pub scope: Option<InstrumentationScope>,
+    #[prost(message, repeated, tag = "2")]
+    pub spans: Vec<Span>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct InstrumentationScope {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub version: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Span {
+    #[prost(bytes = "vec", tag = "1")]
+    pub trace_id: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub span_id: Vec<u8>,
+    #[prost(bytes = "vec", tag = "4")]
+    pub parent_span_id: Vec<u8>,
+    #[prost(string, tag = "5")]
+    pub name: String,
+    #[prost(int32, tag = "6")]
+    pub kind: i32,
+    #[prost(fixed64, tag = "7")]
+    pub start_time_unix_nano: u64,
+    #[prost(fixed64, tag = "8")]
+    pub end_time_unix_nano: u64,
+    #[prost(message, repeated, tag = "9")]
+    pub attributes: Vec<KeyValue>,
+    #[prost(message, optional, tag = "15")]
+    pub status: Option<Status>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Status {
+    #[prost(string, tag = "2")]
+    pub message: String,
+    #[prost(int32, tag = "3")]
+    pub code: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct KeyValue {
+    #[prost(string, tag = "1")]
+    pub key: String,
+    #[prost(message, optional, tag = "2")]
+    pub value: Option<AnyValue>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct AnyValue {
+    #[prost(oneof = "any_value::Value", tags = "1, 2, 3, 4")]
+    pub value: Option<any_value::Value>,
+}
+
+pub mod any_value {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Value {
+        #[prost(string, tag = "1")]
+        StringValue(String),
+        #[prost(bool, tag = "2")]
+        BoolValue(bool),
+        #[prost(int64, tag = "3")]
+        IntValue(i64),
+        #[prost(double, tag = "4")]
+        DoubleValue(f64),
+    }
+}
+
+/// Decodes an OTLP/HTTP protobuf trace export request.
+pub fn decode_request(body: &[u8]) -> anyhow::Result<ExportTraceServiceRequest> {
+    Ok(ExportTraceServiceRequest::decode(body)?)
+}
+
+/// The OTLP/HTTP response to a successful export: a 200 with a protobuf
+/// `ExportTraceServiceResponse`, which the SDKs decode.
+pub fn export_response() -> http::Result<Response<Body>> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/x-protobuf")
+        .body(Body::from(
+            ExportTraceServiceResponse::default().encode_to_vec(),
+        ))
+}
+
+/// Converts the spans of an OTLP export request into Datadog traces, grouping the spans by trace
+/// id. Attributes of the resource and the span become meta (strings and booleans) or metrics
+/// (numbers) of the span.
+pub fn otlp_to_traces(request: ExportTraceServiceRequest) -> Vec<Vec<pb::Span>> {
+    let mut traces: Vec<Vec<pb::Span>> = Vec::new();
+    let mut trace_indices: HashMap<u64, usize> = HashMap::new();
+
+    for resource_spans in request.resource_spans {
+        let resource_attributes = resource_spans
+            .resource
+            .map(|resource| resource.attributes)
+            .unwrap_or_default();
+        for scope_spans in resource_spans.scope_spans {
+            let scope = scope_spans.scope.unwrap_or_default();
+            for span in scope_spans.spans {
+                let span = convert_span(span, &resource_attributes, &scope);
+                let index = *trace_indices.entry(span.trace_id).or_insert_with(|| {
+                    traces.push(Vec::new());
+                    traces.len() - 1
+                });
+                traces[index].push(span);
+            }
+        }
+    }
+
+    traces
+}
+
+fn convert_span(
+    span: Span,
+    resource_attributes: &[KeyValue],
+    scope: &InstrumentationScope,
+) -> pb::Span {
+    let mut meta = HashMap::new();
+    let mut metrics = HashMap::new();
+    for attribute in resource_attributes.iter().chain(span.attributes.iter()) {
+        match attribute.value.as_ref().and_then(|v| v.value.as_ref()) {
+            Some(any_value::Value::StringValue(v)) => {
+                meta.insert(attribute.key.clone(), v.clone());
+            }
+            Some(any_value::Value::BoolValue(v)) => {
+                meta.insert(attribute.key.clone(), v.to_string());
+            }
+            Some(any_value::Value::IntValue(v)) => {
+                metrics.insert(attribute.key.clone(), *v as f64);
+            }
+            Some(any_value::Value::DoubleValue(v)) => {
+                metrics.insert(attribute.key.clone(), *v);
+            }
+            None => {}
+        }
+    }
+
+    let service = meta
+        .get("service.name")
+        .cloned()
+        .unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_string());
+    if let Some(env) = meta.get("deployment.environment").cloned() {
+        meta.insert("env".to_string(), env);
+    }
+    if let Some(version) = meta.get("service.version").cloned() {
+        meta.insert("version".to_string(), version);
+    }
+
+    let kind = span_kind_name(span.kind);
+    meta.insert("span.kind".to_string(), kind.to_string());
+    if !scope.name.is_empty() {
+        meta.insert("otel.library.name".to_string(), scope.name.clone());
+    }
+    if !scope.version.is_empty() {
+        meta.insert("otel.library.version".to_string(), scope.version.clone());
+    }
+
+    let trace_id_high_bits = trace_id_high_bits(&span.trace_id);
+    if trace_id_high_bits != 0 {
+        meta.insert(
+            TRACE_ID_HIGH_BITS_KEY.to_string(),
+            format!("{trace_id_high_bits:016x}"),
+        );
+    }
+
+    let mut error = 0;
+    if let Some(status) = span.status {
+        if status.code == STATUS_CODE_ERROR {
+            error = 1;
+            meta.insert("otel.status_code".to_string(), "Error".to_string());
+            if !status.message.is_empty() {
+                meta.insert("error.msg".to_string(), status.message);
+            }
+        }
+    }
+
+    let name = match scope.name.as_str() {
+        "" => format!("opentelemetry.{kind}"),
+        library => format!("{library}.{kind}"),
+    };
+    let r#type = match (kind, http_method(&meta)) {
+        ("server", _) => "web",
+        ("client", Some(_)) => "http",
+        _ => "custom",
+    };
+
+    pb::Span {
+        service,
+        name,
+        resource: resource_name(&span.name, &meta),
+        trace_id: id_from_bytes(&span.trace_id),
+        span_id: id_from_bytes(&span.span_id),
+        parent_id: id_from_bytes(&span.parent_span_id),
+        start: span.start_time_unix_nano as i64,
+        duration: span
+            .end_time_unix_nano
+            .saturating_sub(span.start_time_unix_nano) as i64,
+        error,
+        r#type: r#type.to_string(),
+        meta,
+        metrics,
+        ..Default::default()
+    }
+}
+
+fn span_kind_name(kind: i32) -> &'static str {
+    match kind {
+        1 => "internal",
+        2 => "server",
+        3 => "client",
+        4 => "producer",
+        5 => "consumer",
+        _ => "unspecified",
+    }
+}
+
+fn http_method(meta: &HashMap<String, String>) -> Option<&String> {
+    meta.get("http.request.method")
+        .or_else(|| meta.get("http.method"))
+}
+
+/// HTTP spans are named after their method and route, like Datadog tracers do, other spans keep
+/// their OTLP name.
+fn resource_name(span_name: &str, meta: &HashMap<String, String>) -> String {
+    match (http_method(meta), meta.get("http.route")) {
+        (Some(method), Some(route)) => format!("{method} {route}"),
+        (Some(method), None) => method.clone(),
+        _ => span_name.to_string(),
+    }
+}
+
+/// Datadog ids are 64 bits, OTLP trace ids are 128 bits of which the lower 64 bits are the
+/// trace id, see [`trace_id_high_bits`] for the others.
+fn id_from_bytes(id: &[u8]) -> u64 {
+    let start = id.len().saturating_sub(8);
+    id[start..]
+        .iter()
+        .fold(0, |acc, byte| (acc << 8) | u64::from(*byte))
+}
+
+/// The high 64 bits of a 128 bit OTLP trace id, 0 for shorter ids.
+fn trace_id_high_bits(id: &[u8]) -> u64 {
+    let end = id.len().saturating_sub(8);
+    id_from_bytes(&id[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string_attribute(key: &str, value: &str) -> KeyValue {
+        KeyValue {
+            key: key.to_string(),
+            value: Some(AnyValue {
+                value: Some(any_value::Value::StringValue(value.to_string())),
+            }),
+        }
+    }
+
+    fn request() -> ExportTraceServiceRequest {
+        let trace_id = [0x5b8efff798038103u64.to_be_bytes(), 42u64.to_be_bytes()].concat();
+        let server = Span {
+            trace_id: trace_id.clone(),
+            span_id: 1u64.to_be_bytes().to_vec(),
+            parent_span_id: vec![],
+            name: "GET /users/{id}".to_string(),
+            kind: 2,
+            start_time_unix_nano: 1_000,
+            end_time_unix_nano: 3_000,
+            attributes: vec![
+                string_attribute("http.request.method", "GET"),
+                string_attribute("http.route", "/users/{id}"),
+                KeyValue {
+                    key: "http.response.status_code".to_string(),
+                    value: Some(AnyValue {
+                        value: Some(any_value::Value::IntValue(500)),
+                    }),
+                },
+            ],
+            status: Some(Status {
+                message: "boom".to_string(),
+                code: STATUS_CODE_ERROR,
+            }),
+        };
+        let internal = Span {
+            trace_id,
+            span_id: 2u64.to_be_bytes().to_vec(),
+            parent_span_id: 1u64.to_be_bytes().to_vec(),
+            name: "load user".to_string(),
+            kind: 1,
+            start_time_unix_nano: 1_500,
+            end_time_unix_nano: 2_000,
+            attributes: vec![],
+            status: None,
+        };
+        ExportTraceServiceRequest {
+            resource_spans: vec![ResourceSpans {
+                resource: Some(Resource {
+                    attributes: vec![
+                        string_attribute("service.name", "users"),
+                        string_attribute("deployment.environment", "prod"),
+                    ],
+                }),
+                scope_spans: vec![ScopeSpans {
+                    scope: Some(InstrumentationScope {
+                        name: "opentelemetry.instrumentation.flask".to_string(),
+                        version: "0.45b0".to_string(),
+                    }),
+                    spans: vec![server, internal],
+                }],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_otlp_to_traces() {
+        let body = request().encode_to_vec();
+        let traces = otlp_to_traces(decode_request(&body).unwrap());
+
+        assert_eq!(1, traces.len());
+        let [server, internal] = &traces[0][..] else {
+            panic!("expected two spans, got {:?}", traces[0]);
+        };
+
+        assert_eq!("users", server.service);
+        assert_eq!("opentelemetry.instrumentation.flask.server", server.name);
+        assert_eq!("GET /users/{id}", server.resource);
+        assert_eq!("web", server.r#type);
+        assert_eq!(42, server.trace_id);
+        assert_eq!("5b8efff798038103", server.meta["_dd.p.tid"]);
+        assert_eq!(1, server.span_id);
+        assert_eq!(0, server.parent_id);
+        assert_eq!(1_000, server.start);
+        assert_eq!(2_000, server.duration);
+        assert_eq!(1, server.error);
+        assert_eq!("boom", server.meta["error.msg"]);
+        assert_eq!("prod", server.meta["env"]);
+        assert_eq!(500.0, server.metrics["http.response.status_code"]);
+
+        assert_eq!("load user", internal.resource);
+        assert_eq!("custom", internal.r#type);
+        assert_eq!(1, internal.parent_id);
+        assert_eq!(0, internal.error);
+    }
+
+    #[test]
+    fn test_trace_id_high_bits() {
+        assert_eq!(0, trace_id_high_bits(&42u64.to_be_bytes()));
+        let trace_id = [1u64.to_be_bytes(), 42u64.to_be_bytes()].concat();
+        assert_eq!(1, trace_id_high_bits(&trace_id));
+        assert_eq!(42, id_from_bytes(&trace_id));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_export_response() {
+        let response = export_response().unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            "application/x-protobuf",
+            response.headers()[header::CONTENT_TYPE]
+        );
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(
+            ExportTraceServiceResponse::default(),
+            ExportTraceServiceResponse::decode(body).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_invalid_request() {
+        assert!(decode_request(b"\xff\xff\xff").is_err());
+    }
+}
@@ -0,0 +1,383 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::body::HttpBody;
+use hyper::{header, http, Body, Method, Request, Response, StatusCode};
+use log::{debug, error};
+
+use crate::config::Config;
+use crate::http_utils::{create_http_response, verify_request_content_length};
+use crate::remote_config_snapshot::RemoteConfigSnapshot;
+use datadog_trace_utils::trace_utils::MiniAgentMetadata;
+use ddcommon::connector::Connector;
+use ddcommon::HttpClient;
+
+const CONTAINER_TAGS_HEADER: &str = "X-Datadog-Container-Tags";
+/// How long forwarding a request may take, reading the response included. Tracers poll again
+/// every few seconds, so a stuck agent must not pile up requests.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Bounds on the remote configuration responses passed back to the tracers, so that a
+/// misconfigured or malicious backend can't make the Mini Agent buffer unbounded data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemoteConfigLimits {
+    /// Responses with a target file larger than this, once base64 decoded, are rejected.
+    pub max_file_size: usize,
+    /// Responses with more target files are rejected.
+    pub max_target_files: usize,
+    /// Responses larger than this are rejected.
+    pub max_total_size: usize,
+}
+
+impl Default for RemoteConfigLimits {
+    fn default() -> Self {
+        RemoteConfigLimits {
+            max_file_size: 2 * 1024 * 1024,
+            max_target_files: 500,
+            max_total_size: 20 * 1024 * 1024,
+        }
+    }
+}
+
+/// Forwards the remote configuration requests of the tracers to the configured agent, with a
+/// single client so that the connection to the agent is reused.
+pub struct RemoteConfigProxy {
+    client: HttpClient,
+    timeout: Duration,
+    snapshot: Arc<SnapshotState>,
+}
+
+/// The snapshot of the configuration, see [`RemoteConfigProxy::with_snapshot_path`].
+#[derive(Default)]
+struct SnapshotState {
+    path: Option<PathBuf>,
+    /// The snapshot loaded at startup, or taken from the last complete response since.
+    snapshot: Mutex<Option<RemoteConfigSnapshot>>,
+    /// Set once the agent answered, the snapshot is no longer served then.
+    fetched: AtomicBool,
+}
+
+impl SnapshotState {
+    /// The snapshot to answer with, as long as the agent didn't answer yet.
+    fn pending_response(&self) -> Option<Vec<u8>> {
+        if self.fetched.load(Ordering::Acquire) {
+            return None;
+        }
+        let snapshot = self.snapshot.lock().unwrap();
+        snapshot.as_ref().map(RemoteConfigSnapshot::to_response)
+    }
+
+    /// Takes the snapshot of a response of the agent, saving it if it changed.
+    fn record(&self, status: StatusCode, body: &[u8]) {
+        if !status.is_success() {
+            return;
+        }
+        self.fetched.store(true, Ordering::Release);
+        let Some(path) = &self.path else {
+            return;
+        };
+        let snapshot = match RemoteConfigSnapshot::from_response(body) {
+            Ok(Some(snapshot)) => snapshot,
+            Ok(None) => return,
+            Err(e) => {
+                debug!("Not taking a snapshot of the remote configuration response: {e}");
+                return;
+            }
+        };
+        let mut current = self.snapshot.lock().unwrap();
+        if current.as_ref() == Some(&snapshot) {
+            return;
+        }
+        if let Err(e) = snapshot.save(path) {
+            error!(
+                "Error saving the remote configuration snapshot to {}: {e}",
+                path.display()
+            );
+        }
+        *current = Some(snapshot);
+    }
+}
+
+impl Default for RemoteConfigProxy {
+    fn default() -> Self {
+        RemoteConfigProxy::new(DEFAULT_TIMEOUT)
+    }
+}
+
+impl RemoteConfigProxy {
+    pub fn new(timeout: Duration) -> Self {
+        RemoteConfigProxy {
+            client: hyper::Client::builder().build(Connector::default()),
+            timeout,
+            snapshot: Arc::default(),
+        }
+    }
+
+    /// Keeps a snapshot of the configuration at `path`, see [`RemoteConfigSnapshot`]. The
+    /// snapshot saved there by a previous Mini Agent is passed to the tracers right away, until
+    /// the agent first answers.
+    pub fn with_snapshot_path(mut self, path: PathBuf) -> Self {
+        let snapshot = match RemoteConfigSnapshot::load(&path) {
+            Ok(snapshot) => Some(snapshot),
+            Err(e) => {
+                debug!(
+                    "No remote configuration snapshot loaded from {}: {e}",
+                    path.display()
+                );
+                None
+            }
+        };
+        self.snapshot = Arc::new(SnapshotState {
+            path: Some(path),
+            snapshot: Mutex::new(snapshot),
+            fetched: AtomicBool::new(false),
+        });
+        self
+    }
+
+    /// Forwards the remote configuration request of a tracer, adding the API key and the container
+    /// tags of the Mini Agent's environment, and passes the response back.
+    pub async fn proxy(
+        &self,
+        config: &Config,
+        req: Request<Body>,
+        mini_agent_metadata: &MiniAgentMetadata,
+    ) -> http::Result<Response<Body>> {
+        let Some(url) = &config.remote_config_url else {
+            return create_http_response(
+                "Remote configuration is not enabled in the Mini Agent",
+                StatusCode::NOT_FOUND,
+            );
+        };
+
+        if let Some(response) = verify_request_content_length(
+            req.headers(),
+            config.max_request_content_length,
+            "Error proxying remote configuration request",
+        ) {
+            return response;
+        }
+
+        let content_type = req.headers().get(header::CONTENT_TYPE).cloned();
+        let body = match hyper::body::to_bytes(req.into_body()).await {
+            Ok(body) => body,
+            Err(e) => {
+                return create_http_response(
+                    &format!("Error reading remote configuration request: {e}"),
+                    StatusCode::BAD_REQUEST,
+                );
+            }
+        };
+
+        let mut builder = Request::builder()
+            .method(Method::POST)
+            .uri(url.clone())
+            .header("DD-API-KEY", config.api_key.get().as_ref());
+        if let Some(content_type) = content_type {
+            builder = builder.header(header::CONTENT_TYPE, content_type);
+        }
+        let container_tags = container_tags(mini_agent_metadata);
+        if !container_tags.is_empty() {
+            builder = builder.header(CONTAINER_TAGS_HEADER, container_tags);
+        }
+        let backend_req = builder.body(Body::from(body))?;
+
+        let forward = forward(
+            self.client.clone(),
+            backend_req,
+            config.remote_config_limits,
+        );
+
+        if let Some(body) = self.snapshot.pending_response() {
+            // Answer right away with the last known configuration, the response of the agent
+            // only refreshes the snapshot.
+            let snapshot = self.snapshot.clone();
+            let timeout = self.timeout;
+            tokio::spawn(async move {
+                match tokio::time::timeout(timeout, forward).await {
+                    Ok(Ok((parts, body))) => snapshot.record(parts.status, &body),
+                    Ok(Err(e)) => debug!("Error refreshing the remote configuration: {e}"),
+                    Err(_) => debug!("Refreshing the remote configuration timed out"),
+                }
+            });
+            return Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body));
+        }
+
+        let (parts, body) = match tokio::time::timeout(self.timeout, forward).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(e)) => {
+                return create_http_response(
+                    &format!("Error forwarding remote configuration request: {e}"),
+                    StatusCode::BAD_GATEWAY,
+                );
+            }
+            Err(_) => {
+                return create_http_response(
+                    &format!(
+                        "Remote configuration request timed out after {:?}",
+                        self.timeout
+                    ),
+                    StatusCode::GATEWAY_TIMEOUT,
+                );
+            }
+        };
+        self.snapshot.record(parts.status, &body);
+        let mut response = Response::builder().status(parts.status);
+        if let Some(content_type) = parts.headers.get(header::CONTENT_TYPE) {
+            response = response.header(header::CONTENT_TYPE, content_type);
+        }
+        response.body(Body::from(body))
+    }
+}
+
+/// Sends the request to the agent, and reads its response within the `limits`.
+async fn forward(
+    client: HttpClient,
+    req: Request<Body>,
+    limits: RemoteConfigLimits,
+) -> anyhow::Result<(http::response::Parts, Vec<u8>)> {
+    let backend_response = client.request(req).await?;
+    debug!(
+        "Remote configuration agent responded with {}",
+        backend_response.status()
+    );
+    let (parts, body) = backend_response.into_parts();
+    let body = read_response_body(body, limits.max_total_size)
+        .await
+        .and_then(|body| enforce_limits(body, &limits))
+        .map_err(|e| anyhow::anyhow!("Rejected remote configuration response: {e}"))?;
+    Ok((parts, body))
+}
+
+async fn read_response_body(mut body: Body, max_size: usize) -> anyhow::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        anyhow::ensure!(
+            buffer.len() + chunk.len() <= max_size,
+            "response larger than {max_size} bytes"
+        );
+        buffer.extend_from_slice(&chunk);
+    }
+    Ok(buffer)
+}
+
+/// Rejects responses with too many target files, or with a target file which is too large: the
+/// tracers must not apply a partial configuration, whose target files don't match its targets.
+/// Bodies which are not a JSON object, e.g. errors, are passed as is.
+fn enforce_limits(body: Vec<u8>, limits: &RemoteConfigLimits) -> anyhow::Result<Vec<u8>> {
+    let Ok(serde_json::Value::Object(response)) = serde_json::from_slice(&body) else {
+        return Ok(body);
+    };
+    let Some(serde_json::Value::Array(target_files)) = response.get("target_files") else {
+        return Ok(body);
+    };
+    anyhow::ensure!(
+        target_files.len() <= limits.max_target_files,
+        "{} target files, more than the maximum of {}",
+        target_files.len(),
+        limits.max_target_files
+    );
+
+    for file in target_files {
+        let raw_len = file["raw"].as_str().map_or(0, str::len);
+        // the raw content is base64 encoded
+        let size = raw_len / 4 * 3;
+        anyhow::ensure!(
+            size <= limits.max_file_size,
+            "target file {} of {size} bytes, larger than the maximum of {} bytes",
+            file["path"],
+            limits.max_file_size
+        );
+    }
+    Ok(body)
+}
+
+/// The tags describing the environment the Mini Agent runs in, in the same form as the container
+/// tags the agent adds, e.g. `project_id:my-project,location:us-east1`.
+fn container_tags(mini_agent_metadata: &MiniAgentMetadata) -> String {
+    [
+        ("project_id", &mini_agent_metadata.gcp_project_id),
+        ("location", &mini_agent_metadata.gcp_region),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| Some(format!("{key}:{}", value.as_ref()?)))
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enforce_limits() {
+        let limits = RemoteConfigLimits {
+            max_file_size: 6,
+            max_target_files: 2,
+            max_total_size: 1024,
+        };
+        let response = |files: &[(&str, &str)]| {
+            let target_files: Vec<_> = files
+                .iter()
+                .map(|(path, raw)| serde_json::json!({"path": path, "raw": raw}))
+                .collect();
+            serde_json::to_vec(&serde_json::json!({
+                "targets": "e30=",
+                "target_files": target_files,
+                "client_configs": ["datadog/2/APM_TRACING/config/config"],
+            }))
+            .unwrap()
+        };
+
+        // within the limits, the body is untouched
+        let body = response(&[("a", "YWJjZGVm")]);
+        assert_eq!(body, enforce_limits(body.clone(), &limits).unwrap());
+
+        // a single file too large rejects the whole response
+        let error =
+            enforce_limits(response(&[("a", "YWJj"), ("b", "YWJjZGVmZ2hp")]), &limits).unwrap_err();
+        assert!(error.to_string().contains("\"b\""), "{error}");
+
+        assert!(enforce_limits(response(&[("a", ""), ("b", ""), ("c", "")]), &limits).is_err());
+
+        let error = b"not found".to_vec();
+        assert_eq!(error, enforce_limits(error.clone(), &limits).unwrap());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_read_response_body() {
+        let body = read_response_body(Body::from("0123456789"), 10).await;
+        assert_eq!(b"0123456789".to_vec(), body.unwrap());
+        assert!(read_response_body(Body::from("0123456789"), 9)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_container_tags() {
+        let metadata = MiniAgentMetadata {
+            gcp_project_id: Some("my-project".to_string()),
+            gcp_region: Some("us-east1".to_string()),
+        };
+        assert_eq!(
+            "project_id:my-project,location:us-east1",
+            container_tags(&metadata)
+        );
+
+        let metadata = MiniAgentMetadata {
+            gcp_project_id: None,
+            gcp_region: Some("us-east1".to_string()),
+        };
+        assert_eq!("location:us-east1", container_tags(&metadata));
+    }
+}
@@ -0,0 +1,233 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! Snapshots of the remote configuration passed to the tracers, so that a Mini Agent started
+//! again, e.g. for the next invocation of a function, serves the last known configuration right
+//! away instead of making the tracers wait for the first response of the agent.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+/// A remote configuration file of a [`RemoteConfigSnapshot`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotFile {
+    pub path: String,
+    /// The version of the file, from the targets metadata.
+    pub version: u64,
+    /// The hashes of the file contents by algorithm, e.g. `sha256`, from the targets metadata.
+    pub hashes: BTreeMap<String, String>,
+    /// The contents of the file, base64 encoded like in the responses.
+    pub raw: String,
+}
+
+/// The active set of remote configuration files, with the signed targets metadata they were
+/// delivered with, so that the response of the agent can be rebuilt from it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteConfigSnapshot {
+    /// The signed targets metadata, base64 encoded like in the responses.
+    pub targets: String,
+    /// The paths of the files applying to the tracers.
+    pub client_configs: Vec<String>,
+    pub files: Vec<SnapshotFile>,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    #[serde(default)]
+    targets: String,
+    #[serde(default)]
+    target_files: Vec<TargetFile>,
+    #[serde(default)]
+    client_configs: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TargetFile {
+    path: String,
+    raw: String,
+}
+
+#[derive(Deserialize)]
+struct Targets {
+    signed: SignedTargets,
+}
+
+#[derive(Deserialize)]
+struct SignedTargets {
+    targets: BTreeMap<String, TargetMeta>,
+}
+
+#[derive(Deserialize)]
+struct TargetMeta {
+    #[serde(default)]
+    hashes: BTreeMap<String, String>,
+    #[serde(default)]
+    custom: TargetCustom,
+}
+
+#[derive(Default, Deserialize)]
+struct TargetCustom {
+    #[serde(default)]
+    v: u64,
+}
+
+impl RemoteConfigSnapshot {
+    /// Takes the snapshot of a response of the agent. Returns None if the response doesn't hold
+    /// the whole configuration: the agent leaves out the targets and the files the tracer
+    /// already has, and a snapshot missing them would be served as an incomplete configuration.
+    pub fn from_response(body: &[u8]) -> anyhow::Result<Option<RemoteConfigSnapshot>> {
+        let response: Response = serde_json::from_slice(body)?;
+        if response.targets.is_empty() {
+            return Ok(None);
+        }
+        let targets = base64::engine::general_purpose::STANDARD.decode(&response.targets)?;
+        let mut targets: Targets = serde_json::from_slice(&targets)?;
+
+        let mut files = Vec::with_capacity(response.client_configs.len());
+        for path in &response.client_configs {
+            let Some(file) = response.target_files.iter().find(|file| &file.path == path) else {
+                return Ok(None);
+            };
+            let Some(meta) = targets.signed.targets.remove(path) else {
+                anyhow::bail!("no targets metadata for {path}");
+            };
+            files.push(SnapshotFile {
+                path: path.clone(),
+                version: meta.custom.v,
+                hashes: meta.hashes,
+                raw: file.raw.clone(),
+            });
+        }
+        Ok(Some(RemoteConfigSnapshot {
+            targets: response.targets,
+            client_configs: response.client_configs,
+            files,
+        }))
+    }
+
+    /// The response of the agent the snapshot was taken from, to pass to the tracers.
+    pub fn to_response(&self) -> Vec<u8> {
+        let target_files: Vec<_> = self
+            .files
+            .iter()
+            .map(|file| TargetFile {
+                path: file.path.clone(),
+                raw: file.raw.clone(),
+            })
+            .collect();
+        serde_json::json!({
+            "targets": self.targets,
+            "target_files": target_files,
+            "client_configs": self.client_configs,
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    /// Loads a snapshot saved with [`RemoteConfigSnapshot::save`].
+    pub fn load(path: &Path) -> anyhow::Result<RemoteConfigSnapshot> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    /// Saves the snapshot to `path`, replacing the file atomically so that a Mini Agent starting
+    /// concurrently never loads a partial snapshot.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let mut tmp_path = path.as_os_str().to_os_string();
+        tmp_path.push(format!(".{}.tmp", std::process::id()));
+        std::fs::write(&tmp_path, serde_json::to_vec(self)?)?;
+        if let Err(e) = std::fs::rename(&tmp_path, path) {
+            _ = std::fs::remove_file(&tmp_path);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(client_configs: &[&str], target_files: &[&str]) -> Vec<u8> {
+        let targets = serde_json::json!({
+            "signed": {
+                "targets": {
+                    "datadog/2/APM_TRACING/a/config": {
+                        "custom": {"v": 3},
+                        "hashes": {"sha256": "abc"},
+                        "length": 2,
+                    },
+                    "datadog/2/APM_TRACING/b/config": {
+                        "custom": {"v": 7},
+                        "hashes": {"sha256": "def"},
+                        "length": 2,
+                    },
+                },
+                "version": 12,
+            },
+        });
+        let target_files: Vec<_> = target_files
+            .iter()
+            .map(|path| serde_json::json!({"path": path, "raw": "e30="}))
+            .collect();
+        serde_json::to_vec(&serde_json::json!({
+            "targets": base64::engine::general_purpose::STANDARD.encode(targets.to_string()),
+            "target_files": target_files,
+            "client_configs": client_configs,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_from_response() {
+        let a = "datadog/2/APM_TRACING/a/config";
+        let b = "datadog/2/APM_TRACING/b/config";
+        let snapshot = RemoteConfigSnapshot::from_response(&response(&[a], &[a, b]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            vec![SnapshotFile {
+                path: a.to_string(),
+                version: 3,
+                hashes: BTreeMap::from([("sha256".to_string(), "abc".to_string())]),
+                raw: "e30=".to_string(),
+            }],
+            snapshot.files
+        );
+
+        // the snapshot gives back the configuration of the response
+        let rebuilt = snapshot.to_response();
+        assert_eq!(
+            snapshot,
+            RemoteConfigSnapshot::from_response(&rebuilt)
+                .unwrap()
+                .unwrap()
+        );
+
+        // responses to tracers which are up to date, or missing files, are not snapshots
+        assert_eq!(None, RemoteConfigSnapshot::from_response(b"{}").unwrap());
+        assert_eq!(
+            None,
+            RemoteConfigSnapshot::from_response(&response(&[a, b], &[a])).unwrap()
+        );
+        assert!(RemoteConfigSnapshot::from_response(b"not found").is_err());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_save_and_load() {
+        let path = "datadog/2/APM_TRACING/a/config";
+        let snapshot = RemoteConfigSnapshot::from_response(&response(&[path], &[path]))
+            .unwrap()
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("remote_config.json");
+        snapshot.save(&file).unwrap();
+        assert_eq!(snapshot, RemoteConfigSnapshot::load(&file).unwrap());
+
+        std::fs::write(&file, "{").unwrap();
+        assert!(RemoteConfigSnapshot::load(&file).is_err());
+    }
+}
@@ -10,18 +10,25 @@ use datadog_trace_protobuf::pb;
 use datadog_trace_utils::stats_utils;
 
 use crate::config::Config;
+use crate::health::{Flusher, HealthState};
 
 #[async_trait]
 pub trait StatsFlusher {
     /// Starts a stats flusher that listens for stats payloads sent to the tokio mpsc Receiver,
-    /// implementing flushing logic that calls flush_stats.
+    /// implementing flushing logic that calls flush_stats. The buffer depth and flush outcomes
+    /// are reported to the given HealthState.
     async fn start_stats_flusher(
         &self,
         config: Arc<Config>,
         mut rx: Receiver<pb::ClientStatsPayload>,
+        health: Arc<HealthState>,
     );
     /// Flushes stats to the Datadog trace stats intake.
-    async fn flush_stats(&self, config: Arc<Config>, traces: Vec<pb::ClientStatsPayload>);
+    async fn flush_stats(
+        &self,
+        config: Arc<Config>,
+        traces: Vec<pb::ClientStatsPayload>,
+    ) -> anyhow::Result<()>;
 }
 
 #[derive(Clone)]
@@ -33,16 +40,19 @@ impl StatsFlusher for ServerlessStatsFlusher {
         &self,
         config: Arc<Config>,
         mut rx: Receiver<pb::ClientStatsPayload>,
+        health: Arc<HealthState>,
     ) {
         let buffer: Arc<Mutex<Vec<pb::ClientStatsPayload>>> = Arc::new(Mutex::new(Vec::new()));
 
         let buffer_producer = buffer.clone();
         let buffer_consumer = buffer.clone();
+        let producer_health = health.clone();
 
         tokio::spawn(async move {
             while let Some(stats_payload) = rx.recv().await {
                 let mut buffer = buffer_producer.lock().await;
                 buffer.push(stats_payload);
+                producer_health.set_stats_queue_depth(buffer.len());
             }
         });
 
@@ -51,15 +61,21 @@ impl StatsFlusher for ServerlessStatsFlusher {
 
             let mut buffer = buffer_consumer.lock().await;
             if !buffer.is_empty() {
-                self.flush_stats(config.clone(), buffer.to_vec()).await;
+                let result = self.flush_stats(config.clone(), buffer.to_vec()).await;
+                health.record_flush_result(Flusher::Stats, &result);
                 buffer.clear();
+                health.set_stats_queue_depth(0);
             }
         }
     }
 
-    async fn flush_stats(&self, config: Arc<Config>, stats: Vec<pb::ClientStatsPayload>) {
+    async fn flush_stats(
+        &self,
+        config: Arc<Config>,
+        stats: Vec<pb::ClientStatsPayload>,
+    ) -> anyhow::Result<()> {
         if stats.is_empty() {
-            return;
+            return Ok(());
         }
         info!("Flushing {} stats", stats.len());
 
@@ -71,20 +87,25 @@ impl StatsFlusher for ServerlessStatsFlusher {
             Ok(res) => res,
             Err(err) => {
                 error!("Failed to serialize stats payload, dropping stats: {err}");
-                return;
+                return Err(err.context("Failed to serialize stats payload"));
             }
         };
 
+        let endpoint = config.trace_stats_intake_endpoint();
         match stats_utils::send_stats_payload(
             serialized_stats_payload,
-            &config.trace_stats_intake,
-            config.trace_stats_intake.api_key.as_ref().unwrap(),
+            &endpoint,
+            endpoint.api_key.as_ref().unwrap(),
         )
         .await
         {
-            Ok(_) => info!("Successfully flushed stats"),
+            Ok(_) => {
+                info!("Successfully flushed stats");
+                Ok(())
+            }
             Err(e) => {
-                error!("Error sending stats: {e:?}")
+                error!("Error sending stats: {e:?}");
+                Err(e)
             }
         }
     }
@@ -6,14 +6,13 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
 use hyper::{http, Body, Request, Response, StatusCode};
-use log::info;
 use tokio::sync::mpsc::Sender;
 
 use datadog_trace_protobuf::pb;
 use datadog_trace_utils::stats_utils;
 
 use crate::config::Config;
-use crate::http_utils::{self, log_and_create_http_response};
+use crate::http_utils::{self, create_http_response};
 
 #[async_trait]
 pub trait StatsProcessor {
@@ -38,7 +37,6 @@ impl StatsProcessor for ServerlessStatsProcessor {
         req: Request<Body>,
         tx: Sender<pb::ClientStatsPayload>,
     ) -> http::Result<Response<Body>> {
-        info!("Recieved trace stats to process");
         let (parts, body) = req.into_parts();
 
         if let Some(response) = http_utils::verify_request_content_length(
@@ -55,7 +53,7 @@ impl StatsProcessor for ServerlessStatsProcessor {
             match stats_utils::get_stats_from_request_body(body).await {
                 Ok(res) => res,
                 Err(err) => {
-                    return log_and_create_http_response(
+                    return create_http_response(
                         &format!("Error deserializing trace stats from request body: {err}"),
                         StatusCode::INTERNAL_SERVER_ERROR,
                     );
@@ -72,13 +70,13 @@ impl StatsProcessor for ServerlessStatsProcessor {
         // send trace payload to our trace flusher
         match tx.send(stats).await {
             Ok(_) => {
-                return log_and_create_http_response(
+                return create_http_response(
                     "Successfully buffered stats to be flushed.",
                     StatusCode::ACCEPTED,
                 );
             }
             Err(err) => {
-                return log_and_create_http_response(
+                return create_http_response(
                     &format!("Error sending stats to the stats flusher: {err}"),
                     StatusCode::INTERNAL_SERVER_ERROR,
                 );
@@ -10,14 +10,21 @@ use datadog_trace_utils::trace_utils;
 use datadog_trace_utils::trace_utils::SendData;
 
 use crate::config::Config;
+use crate::health::{Flusher, HealthState};
 
 #[async_trait]
 pub trait TraceFlusher {
     /// Starts a trace flusher that listens for trace payloads sent to the tokio mpsc Receiver,
-    /// implementing flushing logic that calls flush_traces.
-    async fn start_trace_flusher(&self, config: Arc<Config>, mut rx: Receiver<SendData>);
-    /// Flushes traces to the Datadog trace intake.
-    async fn flush_traces(&self, traces: Vec<SendData>);
+    /// implementing flushing logic that calls flush_traces. The buffer depth and flush outcomes
+    /// are reported to the given HealthState.
+    async fn start_trace_flusher(
+        &self,
+        config: Arc<Config>,
+        mut rx: Receiver<SendData>,
+        health: Arc<HealthState>,
+    );
+    /// Flushes traces to the Datadog trace intake, returning the last error encountered.
+    async fn flush_traces(&self, traces: Vec<SendData>) -> anyhow::Result<()>;
 }
 
 #[derive(Clone)]
@@ -25,16 +32,23 @@ pub struct ServerlessTraceFlusher {}
 
 #[async_trait]
 impl TraceFlusher for ServerlessTraceFlusher {
-    async fn start_trace_flusher(&self, config: Arc<Config>, mut rx: Receiver<SendData>) {
+    async fn start_trace_flusher(
+        &self,
+        config: Arc<Config>,
+        mut rx: Receiver<SendData>,
+        health: Arc<HealthState>,
+    ) {
         let buffer: Arc<Mutex<Vec<SendData>>> = Arc::new(Mutex::new(Vec::new()));
 
         let buffer_producer = buffer.clone();
         let buffer_consumer = buffer.clone();
+        let producer_health = health.clone();
 
         tokio::spawn(async move {
             while let Some(tracer_payload) = rx.recv().await {
                 let mut buffer = buffer_producer.lock().await;
                 buffer.push(tracer_payload);
+                producer_health.set_trace_queue_depth(buffer.len());
             }
         });
 
@@ -43,26 +57,31 @@ impl TraceFlusher for ServerlessTraceFlusher {
 
             let mut buffer = buffer_consumer.lock().await;
             if !buffer.is_empty() {
-                self.flush_traces(buffer.to_vec()).await;
+                let result = self.flush_traces(buffer.to_vec()).await;
+                health.record_flush_result(Flusher::Traces, &result);
                 buffer.clear();
+                health.set_trace_queue_depth(0);
             }
         }
     }
 
-    async fn flush_traces(&self, traces: Vec<SendData>) {
+    async fn flush_traces(&self, traces: Vec<SendData>) -> anyhow::Result<()> {
         if traces.is_empty() {
-            return;
+            return Ok(());
         }
         info!("Flushing {} traces", traces.len());
 
+        let mut result = Ok(());
         for traces in trace_utils::coalesce_send_data(traces) {
             match traces.send().await.last_result {
                 Ok(_) => info!("Successfully flushed traces"),
                 Err(e) => {
-                    error!("Error sending trace: {e:?}")
+                    error!("Error sending trace: {e:?}");
                     // TODO: Retries
+                    result = Err(e);
                 }
             }
         }
+        result
     }
 }
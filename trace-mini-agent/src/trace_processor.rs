@@ -1,21 +1,27 @@
 // Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
 // SPDX-License-Identifier: Apache-2.0
 
+use std::cell::Cell;
+use std::fmt;
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use hyper::{http, Body, Request, Response, StatusCode};
-use log::info;
+use serde::de::{self, DeserializeSeed, Deserializer, SeqAccess, Visitor};
 use tokio::sync::mpsc::Sender;
 
 use datadog_trace_obfuscation::obfuscate::obfuscate_span;
+use datadog_trace_protobuf::pb;
 use datadog_trace_utils::trace_utils::SendData;
 use datadog_trace_utils::trace_utils::{self};
+use datadog_trace_utils::tracer_header_tags::TracerHeaderTags;
 use datadog_trace_utils::tracer_payload::TraceEncoding;
 
 use crate::{
     config::Config,
-    http_utils::{self, log_and_create_http_response},
+    http_utils::{self, create_http_response},
+    otlp,
 };
 
 #[async_trait]
@@ -29,6 +35,15 @@ pub trait TraceProcessor {
         tx: Sender<trace_utils::SendData>,
         mini_agent_metadata: Arc<trace_utils::MiniAgentMetadata>,
     ) -> http::Result<Response<Body>>;
+    /// Decodes OTLP/HTTP protobuf traces from a hyper request body, converts them to Datadog
+    /// spans and sends them through the provided tokio mpsc Sender.
+    async fn process_otlp_traces(
+        &self,
+        config: Arc<Config>,
+        req: Request<Body>,
+        tx: Sender<trace_utils::SendData>,
+        mini_agent_metadata: Arc<trace_utils::MiniAgentMetadata>,
+    ) -> http::Result<Response<Body>>;
 }
 
 #[derive(Clone)]
@@ -43,7 +58,6 @@ impl TraceProcessor for ServerlessTraceProcessor {
         tx: Sender<trace_utils::SendData>,
         mini_agent_metadata: Arc<trace_utils::MiniAgentMetadata>,
     ) -> http::Result<Response<Body>> {
-        info!("Recieved traces to process");
         let (parts, body) = req.into_parts();
 
         if let Some(response) = http_utils::verify_request_content_length(
@@ -56,72 +70,311 @@ impl TraceProcessor for ServerlessTraceProcessor {
 
         let tracer_header_tags = (&parts.headers).into();
 
+        let body = match http_utils::read_request_body(
+            body,
+            config.max_request_content_length,
+            "Error processing traces",
+        )
+        .await
+        {
+            Ok(body) => body,
+            Err(response) => return response,
+        };
+        let body_size = body.len();
+
         // deserialize traces from the request body, convert to protobuf structs (see trace-protobuf
         // crate)
-        let (body_size, traces) = match trace_utils::get_traces_from_request_body(body).await {
-            Ok(res) => res,
+        let traces =
+            match decode_traces(body, config.max_spans_per_payload, config.max_decode_time).await {
+                Ok(traces) => traces,
+                Err(DecodeError::TooManySpans(max_spans)) => {
+                    return create_http_response(
+                        &format!("Error processing traces: Too many spans (more than {max_spans})"),
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                    );
+                }
+                // The payload may well be valid, the tracer can retry it once the load is lower
+                Err(DecodeError::TimedOut(max_decode_time)) => {
+                    return create_http_response(
+                        &format!(
+                            "Error processing traces: Decoding took longer than {max_decode_time:?}"
+                        ),
+                        StatusCode::SERVICE_UNAVAILABLE,
+                    );
+                }
+                Err(DecodeError::Invalid(err)) => {
+                    return create_http_response(
+                        &format!("Error deserializing trace from request body: {err}"),
+                        StatusCode::BAD_REQUEST,
+                    );
+                }
+            };
+
+        if let Err(response) = buffer_traces(
+            &config,
+            traces,
+            body_size,
+            tracer_header_tags,
+            &mini_agent_metadata,
+            tx,
+        )
+        .await
+        {
+            return response;
+        }
+        create_http_response(
+            "Successfully buffered traces to be flushed.",
+            StatusCode::ACCEPTED,
+        )
+    }
+
+    async fn process_otlp_traces(
+        &self,
+        config: Arc<Config>,
+        req: Request<Body>,
+        tx: Sender<trace_utils::SendData>,
+        mini_agent_metadata: Arc<trace_utils::MiniAgentMetadata>,
+    ) -> http::Result<Response<Body>> {
+        let (parts, body) = req.into_parts();
+
+        if let Some(response) = http_utils::verify_request_content_length(
+            &parts.headers,
+            config.max_request_content_length,
+            "Error processing OTLP traces",
+        ) {
+            return response;
+        }
+
+        let tracer_header_tags = (&parts.headers).into();
+
+        let body = match http_utils::read_request_body(
+            body,
+            config.max_request_content_length,
+            "Error processing OTLP traces",
+        )
+        .await
+        {
+            Ok(body) => body,
+            Err(response) => return response,
+        };
+        let traces = match otlp::decode_request(&body) {
+            Ok(request) => otlp::otlp_to_traces(request),
             Err(err) => {
-                return log_and_create_http_response(
-                    &format!("Error deserializing trace from request body: {err}"),
-                    StatusCode::INTERNAL_SERVER_ERROR,
+                return create_http_response(
+                    &format!("Error decoding OTLP traces from request body: {err}"),
+                    StatusCode::BAD_REQUEST,
                 );
             }
         };
+        if traces.is_empty() {
+            return otlp::export_response();
+        }
 
-        let payload = trace_utils::collect_trace_chunks(
+        if let Err(response) = buffer_traces(
+            &config,
             traces,
-            &tracer_header_tags,
-            |chunk, root_span_index| {
-                trace_utils::set_serverless_root_span_tags(
-                    &mut chunk.spans[root_span_index],
-                    config.function_name.clone(),
-                    &config.env_type,
-                );
-                for span in chunk.spans.iter_mut() {
-                    trace_utils::enrich_span_with_mini_agent_metadata(span, &mini_agent_metadata);
-                    trace_utils::enrich_span_with_azure_metadata(
-                        span,
-                        config.mini_agent_version.as_str(),
-                    );
-                    obfuscate_span(span, &config.obfuscation_config);
-                }
-            },
-            true, // In mini agent, we always send agentless
-            TraceEncoding::V07,
-        );
+            body.len(),
+            tracer_header_tags,
+            &mini_agent_metadata,
+            tx,
+        )
+        .await
+        {
+            return response;
+        }
+        otlp::export_response()
+    }
+}
 
-        let send_data = SendData::new(body_size, payload, tracer_header_tags, &config.trace_intake);
+/// Why a trace payload couldn't be decoded.
+enum DecodeError {
+    /// The traces hold more spans than the given maximum.
+    TooManySpans(usize),
+    /// The decoding took longer than the given maximum.
+    TimedOut(Duration),
+    Invalid(anyhow::Error),
+}
 
-        // send trace payload to our trace flusher
-        match tx.send(send_data).await {
-            Ok(_) => {
-                return log_and_create_http_response(
-                    "Successfully buffered traces to be flushed.",
-                    StatusCode::ACCEPTED,
-                );
-            }
-            Err(err) => {
-                return log_and_create_http_response(
-                    &format!("Error sending traces to the trace flusher: {err}"),
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                );
+/// Deserializes msgpack encoded traces on the blocking thread pool, so that large payloads don't
+/// stall the other connections. The decoding gives up as soon as the traces hold more than
+/// `max_spans` spans, which bounds its work and memory whatever the payload. The request is
+/// answered once `max_decode_time` is exhausted.
+async fn decode_traces(
+    body: Vec<u8>,
+    max_spans: Option<usize>,
+    max_decode_time: Option<Duration>,
+) -> Result<Vec<Vec<pb::Span>>, DecodeError> {
+    let decode = tokio::task::spawn_blocking(move || {
+        let budget = SpanBudget::new(max_spans);
+        let mut deserializer = rmp_serde::Deserializer::from_read_ref(&body);
+        let traces = TracesSeed(&budget)
+            .deserialize(&mut deserializer)
+            .map_err(|e| match (budget.exceeded.get(), max_spans) {
+                (true, Some(max_spans)) => DecodeError::TooManySpans(max_spans),
+                _ => DecodeError::Invalid(e.into()),
+            })?;
+        if traces.is_empty() {
+            return Err(DecodeError::Invalid(anyhow::anyhow!(
+                "No traces deserialized from the request body."
+            )));
+        }
+        Ok(traces)
+    });
+    let decoded = match max_decode_time {
+        None => decode.await,
+        Some(max_decode_time) => tokio::time::timeout(max_decode_time, decode)
+            .await
+            .map_err(|_| DecodeError::TimedOut(max_decode_time))?,
+    };
+    decoded.map_err(|e| DecodeError::Invalid(e.into()))?
+}
+
+/// The number of spans which may still be decoded.
+struct SpanBudget {
+    left: Cell<usize>,
+    exceeded: Cell<bool>,
+}
+
+impl SpanBudget {
+    fn new(max_spans: Option<usize>) -> Self {
+        SpanBudget {
+            left: Cell::new(max_spans.unwrap_or(usize::MAX)),
+            exceeded: Cell::new(false),
+        }
+    }
+
+    fn take(&self) -> bool {
+        match self.left.get().checked_sub(1) {
+            Some(left) => self.left.set(left),
+            None => self.exceeded.set(true),
+        }
+        !self.exceeded.get()
+    }
+}
+
+/// Deserializes an array of traces within a [SpanBudget]. The array lengths announced by the
+/// payload are not trusted to preallocate.
+#[derive(Clone, Copy)]
+struct TracesSeed<'a>(&'a SpanBudget);
+
+impl<'de> DeserializeSeed<'de> for TracesSeed<'_> {
+    type Value = Vec<Vec<pb::Span>>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for TracesSeed<'_> {
+    type Value = Vec<Vec<pb::Span>>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of traces")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut traces = Vec::new();
+        while let Some(trace) = seq.next_element_seed(TraceSeed(self.0))? {
+            traces.push(trace);
+        }
+        Ok(traces)
+    }
+}
+
+/// Deserializes the array of spans of a trace within a [SpanBudget].
+#[derive(Clone, Copy)]
+struct TraceSeed<'a>(&'a SpanBudget);
+
+impl<'de> DeserializeSeed<'de> for TraceSeed<'_> {
+    type Value = Vec<pb::Span>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for TraceSeed<'_> {
+    type Value = Vec<pb::Span>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of spans")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut trace = Vec::new();
+        while let Some(span) = seq.next_element::<pb::Span>()? {
+            if !self.0.take() {
+                return Err(de::Error::custom("too many spans"));
             }
+            trace.push(span);
         }
+        Ok(trace)
     }
 }
 
+/// Enriches and obfuscates the traces and sends them through the provided tokio mpsc Sender to
+/// the trace flusher. Returns the error response if they could not be sent, the success response
+/// depends on the protocol.
+async fn buffer_traces(
+    config: &Config,
+    traces: Vec<Vec<pb::Span>>,
+    body_size: usize,
+    tracer_header_tags: TracerHeaderTags<'_>,
+    mini_agent_metadata: &trace_utils::MiniAgentMetadata,
+    tx: Sender<trace_utils::SendData>,
+) -> Result<(), http::Result<Response<Body>>> {
+    let payload = trace_utils::collect_trace_chunks(
+        traces,
+        &tracer_header_tags,
+        |chunk, root_span_index| {
+            trace_utils::set_serverless_root_span_tags(
+                &mut chunk.spans[root_span_index],
+                config.function_name.clone(),
+                &config.env_type,
+            );
+            for span in chunk.spans.iter_mut() {
+                trace_utils::enrich_span_with_mini_agent_metadata(span, mini_agent_metadata);
+                trace_utils::enrich_span_with_azure_metadata(
+                    span,
+                    config.mini_agent_version.as_str(),
+                );
+                obfuscate_span(span, &config.obfuscation_config);
+            }
+            trace_utils::set_chunk_dropped_trace(chunk);
+        },
+        true, // In mini agent, we always send agentless
+        TraceEncoding::V07,
+    );
+
+    let send_data = SendData::new(
+        body_size,
+        payload,
+        tracer_header_tags,
+        &config.trace_intake_endpoint(),
+    );
+
+    // send trace payload to our trace flusher
+    tx.send(send_data).await.map_err(|err| {
+        create_http_response(
+            &format!("Error sending traces to the trace flusher: {err}"),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use datadog_trace_obfuscation::obfuscation_config::ObfuscationConfig;
-    use hyper::Request;
+    use hyper::{Request, StatusCode};
     use std::{
         collections::HashMap,
         sync::Arc,
-        time::{SystemTime, UNIX_EPOCH},
+        time::{Duration, SystemTime, UNIX_EPOCH},
     };
     use tokio::sync::mpsc::{self, Receiver, Sender};
 
     use crate::{
+        api_key::ApiKey,
         config::Config,
         trace_processor::{self, TraceProcessor},
     };
@@ -142,24 +395,37 @@ mod tests {
 
     fn create_test_config() -> Config {
         Config {
+            access_log_sample_rate: 0,
+            api_key: Arc::new(ApiKey::fixed("dummy_api_key")),
+            api_key_refresh_interval: Duration::from_secs(300),
             function_name: Some("dummy_function_name".to_string()),
+            max_decode_time: None,
             max_request_content_length: 10 * 1024 * 1024,
+            max_spans_per_payload: None,
             trace_flush_interval: 3,
             stats_flush_interval: 3,
             verify_env_timeout: 100,
             trace_intake: Endpoint {
                 url: hyper::Uri::from_static("https://trace.agent.notdog.com/traces"),
                 api_key: Some("dummy_api_key".into()),
+                ..Default::default()
             },
             trace_stats_intake: Endpoint {
                 url: hyper::Uri::from_static("https://trace.agent.notdog.com/stats"),
                 api_key: Some("dummy_api_key".into()),
+                ..Default::default()
             },
             dd_site: "datadoghq.com".to_string(),
             env_type: trace_utils::EnvironmentType::CloudFunction,
             os: "linux".to_string(),
             obfuscation_config: ObfuscationConfig::new().unwrap(),
             mini_agent_version: "0.1.0".to_string(),
+            receiver_port: 8126,
+            receiver_port_file: None,
+            receiver_socket: None,
+            remote_config_url: None,
+            remote_config_limits: Default::default(),
+            remote_config_snapshot_path: None,
         }
     }
 
@@ -304,4 +570,100 @@ mod tests {
 
         assert_eq!(expected_tracer_payload, received_payload.unwrap());
     }
+
+    async fn process_trace_with_config(config: Config, bytes: Vec<u8>) -> StatusCode {
+        let (tx, _rx): (
+            Sender<trace_utils::SendData>,
+            Receiver<trace_utils::SendData>,
+        ) = mpsc::channel(1);
+        // the body may be larger than announced by the content-length header
+        let request = Request::builder()
+            .header("content-length", "1")
+            .body(hyper::body::Body::from(bytes))
+            .unwrap();
+
+        let trace_processor = trace_processor::ServerlessTraceProcessor {};
+        trace_processor
+            .process_traces(
+                Arc::new(config),
+                request,
+                tx,
+                Arc::new(trace_utils::MiniAgentMetadata::default()),
+            )
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_process_trace_limits() {
+        let start = get_current_timestamp_nanos();
+        let json_trace = vec![
+            create_test_json_span(11, 222, 0, start),
+            create_test_json_span(11, 333, 222, start),
+        ];
+        let bytes = rmp_serde::to_vec(&vec![json_trace]).unwrap();
+
+        let config = Config {
+            max_request_content_length: bytes.len() - 1,
+            ..create_test_config()
+        };
+        assert_eq!(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            process_trace_with_config(config, bytes.clone()).await
+        );
+
+        let config = Config {
+            max_spans_per_payload: Some(1),
+            ..create_test_config()
+        };
+        assert_eq!(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            process_trace_with_config(config, bytes.clone()).await
+        );
+
+        // the spans of all the traces count
+        let split_traces = rmp_serde::to_vec(&vec![
+            vec![create_test_json_span(11, 222, 0, start)],
+            vec![create_test_json_span(12, 444, 0, start)],
+        ])
+        .unwrap();
+        let config = Config {
+            max_spans_per_payload: Some(1),
+            ..create_test_config()
+        };
+        assert_eq!(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            process_trace_with_config(config, split_traces).await
+        );
+
+        let config = Config {
+            max_spans_per_payload: Some(2),
+            max_decode_time: Some(Duration::from_secs(5)),
+            ..create_test_config()
+        };
+        assert_eq!(
+            StatusCode::ACCEPTED,
+            process_trace_with_config(config, bytes).await
+        );
+
+        let many_spans = rmp_serde::to_vec(&vec![(1..=10_000)
+            .map(|span_id| create_test_json_span(11, span_id, 0, start))
+            .collect::<Vec<_>>()])
+        .unwrap();
+        let config = Config {
+            max_decode_time: Some(Duration::ZERO),
+            ..create_test_config()
+        };
+        assert_eq!(
+            StatusCode::SERVICE_UNAVAILABLE,
+            process_trace_with_config(config, many_spans).await
+        );
+
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            process_trace_with_config(create_test_config(), b"not msgpack".to_vec()).await
+        );
+    }
 }
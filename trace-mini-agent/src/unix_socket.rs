@@ -0,0 +1,431 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! Creation of the unix socket the Mini Agent receives traces and stats on, and the serving of
+//! its connections.
+
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{http, Body, Request, Response};
+use log::{debug, error, warn};
+use std::ffi::CString;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::Semaphore;
+
+use crate::config::UnixSocketConfig;
+use crate::health::HealthState;
+
+/// How long clients wait for a connection slot before the pending ones are turned away.
+const ACCEPT_BACKPRESSURE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Pause after failing to accept a connection, e.g. when out of file descriptors, instead of
+/// spinning on the error.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Binds the socket, creating its directory if needed, replacing a stale socket file left behind
+/// by a previous run, then applying the configured permissions and ownership.
+///
+/// Must be called from within a tokio runtime.
+pub fn bind(config: &UnixSocketConfig) -> io::Result<UnixListener> {
+    #[cfg(target_os = "linux")]
+    if let Some(name) = config.path.strip_prefix('@') {
+        use std::os::linux::net::SocketAddrExt;
+
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        let listener = std::os::unix::net::UnixListener::bind_addr(&addr)?;
+        listener.set_nonblocking(true)?;
+        return UnixListener::from_std(listener);
+    }
+
+    let path = Path::new(&config.path);
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    remove_stale_socket(path)?;
+
+    let listener = UnixListener::bind(path)?;
+    if let Some(mode) = config.mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    if config.uid.is_some() || config.gid.is_some() {
+        chown(path, config.uid, config.gid)?;
+    }
+    Ok(listener)
+}
+
+/// Removes a socket file nobody is listening on anymore. Anything else at the path is left alone,
+/// so that a misconfiguration can't delete an unrelated file or steal the socket of a running
+/// agent.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("another process is listening on {}", path.display()),
+        ));
+    }
+    fs::remove_file(path)
+}
+
+fn chown(path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // -1 leaves the id unchanged
+    let uid = uid.map_or(libc::uid_t::MAX, |uid| uid as libc::uid_t);
+    let gid = gid.map_or(libc::gid_t::MAX, |gid| gid as libc::gid_t);
+    if unsafe { libc::chown(path.as_ptr(), uid, gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A unix socket listener bounding the connections it serves.
+///
+/// Once `max_connections` connections are open, no more are accepted, leaving the clients queued
+/// in the listen backlog. Should no slot free up within [`ACCEPT_BACKPRESSURE_TIMEOUT`], pending
+/// connections are accepted and closed right away until one does, so that a client flooding the
+/// socket with connections can't exhaust the file descriptors of the Mini Agent nor make the
+/// other clients hang. Closed connections are counted in the rejected connections of the health
+/// state. Connections without traffic for `idle_timeout` are closed, unless a request is still
+/// being handled.
+pub struct UnixListenerTracked {
+    listener: UnixListener,
+    connections: Option<Arc<Semaphore>>,
+    idle_timeout: Option<Duration>,
+    health: Arc<HealthState>,
+}
+
+impl UnixListenerTracked {
+    pub fn new(
+        listener: UnixListener,
+        config: &UnixSocketConfig,
+        health: Arc<HealthState>,
+    ) -> Self {
+        UnixListenerTracked {
+            listener,
+            connections: config
+                .max_connections
+                .map(|max| Arc::new(Semaphore::new(max))),
+            idle_timeout: config.idle_timeout,
+            health,
+        }
+    }
+
+    /// Serves the connections with the given request handler, until the listener fails.
+    pub async fn serve<S, F>(self, service: S) -> io::Result<()>
+    where
+        S: Fn(Request<Body>) -> F + Clone + Send + 'static,
+        F: Future<Output = http::Result<Response<Body>>> + Send + 'static,
+    {
+        loop {
+            let permit = match &self.connections {
+                None => None,
+                Some(connections) => {
+                    let acquire = connections.clone().acquire_owned();
+                    let permit =
+                        match tokio::time::timeout(ACCEPT_BACKPRESSURE_TIMEOUT, acquire).await {
+                            Ok(permit) => permit,
+                            Err(_) => tokio::select! {
+                                permit = connections.clone().acquire_owned() => permit,
+                                accepted = self.listener.accept() => {
+                                    if accepted.is_ok() {
+                                        warn!("Unix socket connection limit reached, rejecting");
+                                        self.health.record_rejected_connection();
+                                    }
+                                    continue;
+                                }
+                            },
+                        };
+                    Some(permit.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?)
+                }
+            };
+
+            let stream = match self.listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    error!("Failed to accept a unix socket connection: {e}");
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            };
+
+            let service = service.clone();
+            let idle_timeout = self.idle_timeout;
+            tokio::spawn(async move {
+                // the slot is held for the lifetime of the connection
+                let _permit = permit;
+                if let Err(e) = serve_connection(stream, idle_timeout, service).await {
+                    debug!("Unix socket connection error: {e}");
+                }
+            });
+        }
+    }
+}
+
+async fn serve_connection<S, F>(
+    stream: UnixStream,
+    idle_timeout: Option<Duration>,
+    service: S,
+) -> hyper::Result<()>
+where
+    S: Fn(Request<Body>) -> F + Send + 'static,
+    F: Future<Output = http::Result<Response<Body>>> + Send + 'static,
+{
+    let activity = Arc::new(Activity::new());
+    let stream = ActivityTrackedStream {
+        stream,
+        activity: activity.clone(),
+    };
+    let service = {
+        let activity = activity.clone();
+        service_fn(move |req| {
+            let in_flight = InFlight::new(activity.clone());
+            let response = service(req);
+            async move {
+                let _in_flight = in_flight;
+                response.await
+            }
+        })
+    };
+    let connection = Http::new().serve_connection(stream, service);
+    let Some(idle_timeout) = idle_timeout else {
+        return connection.await;
+    };
+
+    tokio::pin!(connection);
+    loop {
+        let idle = activity.idle_for();
+        if idle.is_some_and(|idle| idle >= idle_timeout) {
+            debug!("Closing unix socket connection idle for {idle_timeout:?}");
+            return Ok(());
+        }
+        tokio::select! {
+            result = &mut connection => return result,
+            _ = tokio::time::sleep(idle_timeout - idle.unwrap_or_default()) => {}
+        }
+    }
+}
+
+/// The activity of a connection: when data last went through it, and how many of its requests
+/// are being handled. A connection is not idle while handling a request, however long it takes.
+struct Activity {
+    last: Mutex<Instant>,
+    in_flight: AtomicUsize,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Activity {
+            last: Mutex::new(Instant::now()),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        *self.last.lock().unwrap() = Instant::now();
+    }
+
+    /// How long the connection has been idle, None while a request is in flight.
+    fn idle_for(&self) -> Option<Duration> {
+        if self.in_flight.load(Ordering::SeqCst) > 0 {
+            return None;
+        }
+        Some(self.last.lock().unwrap().elapsed())
+    }
+}
+
+/// Counts a request as in flight until dropped, along with its response future.
+struct InFlight(Arc<Activity>);
+
+impl InFlight {
+    fn new(activity: Arc<Activity>) -> Self {
+        activity.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(activity)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        // the idle time starts over once the request is handled
+        self.0.touch();
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Records when data last went through the stream.
+struct ActivityTrackedStream {
+    stream: UnixStream,
+    activity: Arc<Activity>,
+}
+
+impl ActivityTrackedStream {
+    fn track<T>(&self, poll: Poll<io::Result<T>>) -> Poll<io::Result<T>> {
+        if let Poll::Ready(Ok(_)) = poll {
+            self.activity.touch();
+        }
+        poll
+    }
+}
+
+impl AsyncRead for ActivityTrackedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.stream).poll_read(cx, buf);
+        self.track(poll)
+    }
+}
+
+impl AsyncWrite for ActivityTrackedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.stream).poll_write(cx, buf);
+        self.track(poll)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(path: &Path) -> UnixSocketConfig {
+        UnixSocketConfig {
+            path: path.to_str().unwrap().to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_bind_replaces_stale_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run").join("agent.sock");
+        let mut config = config(&path);
+        config.mode = Some(0o660);
+
+        let listener = bind(&config).unwrap();
+        assert_eq!(
+            0o660,
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777
+        );
+        // a live socket is not taken over
+        assert_eq!(io::ErrorKind::AddrInUse, bind(&config).unwrap_err().kind());
+
+        drop(listener);
+        assert!(path.exists());
+        bind(&config).unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_bind_keeps_other_files() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        assert_eq!(
+            io::ErrorKind::AlreadyExists,
+            bind(&config(file.path())).unwrap_err().kind()
+        );
+        assert!(file.path().exists());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_connection_limit_and_idle_timeout() {
+        use tokio::io::AsyncReadExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config(&dir.path().join("agent.sock"));
+        config.max_connections = Some(1);
+        config.idle_timeout = Some(Duration::from_millis(1500));
+        let health = Arc::new(HealthState::default());
+        let listener = UnixListenerTracked::new(bind(&config).unwrap(), &config, health.clone());
+        tokio::spawn(
+            listener.serve(|_: Request<Body>| async { Response::builder().body(Body::empty()) }),
+        );
+
+        let mut first = UnixStream::connect(&config.path).await.unwrap();
+        let mut second = UnixStream::connect(&config.path).await.unwrap();
+        let mut buf = [0; 1];
+        // no slot frees up within the backpressure timeout, the second connection is turned away
+        assert_eq!(0, second.read(&mut buf).await.unwrap());
+        assert_eq!(1, health.rejected_connections());
+        // the first one is closed once idle
+        assert_eq!(0, first.read(&mut buf).await.unwrap());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_idle_timeout_spares_requests_in_flight() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config(&dir.path().join("agent.sock"));
+        config.idle_timeout = Some(Duration::from_millis(200));
+        let listener = UnixListenerTracked::new(
+            bind(&config).unwrap(),
+            &config,
+            Arc::new(HealthState::default()),
+        );
+        tokio::spawn(listener.serve(|_: Request<Body>| async {
+            // takes longer than the idle timeout
+            tokio::time::sleep(Duration::from_millis(600)).await;
+            Response::builder().body(Body::empty())
+        }));
+
+        let mut stream = UnixStream::connect(&config.path).await.unwrap();
+        stream
+            .write_all(b"GET /info HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = [0; 12];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(b"HTTP/1.1 200", &buf);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_bind_abstract_socket() {
+        let name = format!("@datadog-mini-agent-test-{}", std::process::id());
+        let config = UnixSocketConfig {
+            path: name.clone(),
+            ..Default::default()
+        };
+        let _listener = bind(&config).unwrap();
+        assert!(!Path::new(&name).exists());
+        assert!(bind(&config).is_err());
+    }
+}
@@ -7,7 +7,7 @@ use std::time::SystemTime;
 pub(crate) const MAX_TYPE_LEN: usize = 100;
 /// an arbitrary cutoff to spot weird-looking values
 /// nanoseconds since epoch on Jan 1, 2000
-const YEAR_2000_NANOSEC_TS: i64 = 946684800000000000;
+pub(crate) const YEAR_2000_NANOSEC_TS: i64 = 946684800000000000;
 /// DEFAULT_SPAN_NAME is the default name we assign a span if it's missing and we have no reasonable
 /// fallback
 pub(crate) const DEFAULT_SPAN_NAME: &str = "unnamed_operation";
@@ -17,7 +17,7 @@ pub(crate) const DEFAULT_SERVICE_NAME: &str = "unnamed-service";
 /// MAX_NAME_LEN the maximum length a name can have
 pub(crate) const MAX_NAME_LEN: usize = 100;
 /// MAX_SERVICE_LEN the maximum length a service can have
-const MAX_SERVICE_LEN: usize = 100;
+pub(crate) const MAX_SERVICE_LEN: usize = 100;
 /// MAX_SERVICE_LEN the maximum length a tag can have
 const MAX_TAG_LEN: usize = 200;
 
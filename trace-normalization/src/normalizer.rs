@@ -6,6 +6,9 @@ use datadog_trace_protobuf::pb;
 
 const TAG_SAMPLING_PRIORITY: &str = "_sampling_priority_v1";
 const TAG_ORIGIN: &str = "_dd.origin";
+/// The sampling mechanism which made the sampling decision of the trace, propagated in the chunk
+/// tags.
+const TAG_DECISION_MAKER: &str = "_dd.p.dm";
 
 #[allow(dead_code)]
 #[derive(Debug, Eq, PartialEq)]
@@ -16,18 +19,147 @@ pub enum SamplerPriority {
     None = i8::MIN as isize,
 }
 
+/// Counts of the fix-ups applied by the normalizer to malformed spans, and of the traces it
+/// rejected, by reason. The reasons are named like the `reason` tag of the agent's
+/// `datadog.trace_agent.normalizer.spans_malformed` and `traces_dropped` metrics.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NormalizationStats {
+    pub service_empty: u64,
+    pub service_truncate: u64,
+    pub service_invalid: u64,
+    pub span_name_empty: u64,
+    pub span_name_truncate: u64,
+    pub span_name_invalid: u64,
+    pub resource_empty: u64,
+    pub type_truncate: u64,
+    pub invalid_start_date: u64,
+    pub invalid_duration: u64,
+    pub invalid_http_status_code: u64,
+    pub empty_trace: u64,
+    pub trace_id_zero: u64,
+    pub span_id_zero: u64,
+    pub foreign_span: u64,
+}
+
+impl NormalizationStats {
+    /// The number of spans which were fixed up, by reason.
+    pub fn spans_malformed(&self) -> [(&'static str, u64); 11] {
+        [
+            ("service_empty", self.service_empty),
+            ("service_truncate", self.service_truncate),
+            ("service_invalid", self.service_invalid),
+            ("span_name_empty", self.span_name_empty),
+            ("span_name_truncate", self.span_name_truncate),
+            ("span_name_invalid", self.span_name_invalid),
+            ("resource_empty", self.resource_empty),
+            ("type_truncate", self.type_truncate),
+            ("invalid_start_date", self.invalid_start_date),
+            ("invalid_duration", self.invalid_duration),
+            ("invalid_http_status_code", self.invalid_http_status_code),
+        ]
+    }
+
+    /// The number of traces which could not be normalized, by reason.
+    pub fn traces_dropped(&self) -> [(&'static str, u64); 4] {
+        [
+            ("empty_trace", self.empty_trace),
+            ("trace_id_zero", self.trace_id_zero),
+            ("span_id_zero", self.span_id_zero),
+            ("foreign_span", self.foreign_span),
+        ]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == NormalizationStats::default()
+    }
+
+    pub fn merge(&mut self, other: &NormalizationStats) {
+        self.service_empty += other.service_empty;
+        self.service_truncate += other.service_truncate;
+        self.service_invalid += other.service_invalid;
+        self.span_name_empty += other.span_name_empty;
+        self.span_name_truncate += other.span_name_truncate;
+        self.span_name_invalid += other.span_name_invalid;
+        self.resource_empty += other.resource_empty;
+        self.type_truncate += other.type_truncate;
+        self.invalid_start_date += other.invalid_start_date;
+        self.invalid_duration += other.invalid_duration;
+        self.invalid_http_status_code += other.invalid_http_status_code;
+        self.empty_trace += other.empty_trace;
+        self.trace_id_zero += other.trace_id_zero;
+        self.span_id_zero += other.span_id_zero;
+        self.foreign_span += other.foreign_span;
+    }
+}
+
+#[cfg(test)]
 fn normalize_span(s: &mut pb::Span) -> anyhow::Result<()> {
-    anyhow::ensure!(s.trace_id != 0, "TraceID is zero (reason:trace_id_zero)");
-    anyhow::ensure!(s.span_id != 0, "SpanID is zero (reason:span_id_zero)");
+    normalize_span_with_stats(s, &mut NormalizationStats::default())
+}
+
+fn normalize_span_with_stats(
+    s: &mut pb::Span,
+    stats: &mut NormalizationStats,
+) -> anyhow::Result<()> {
+    if s.trace_id == 0 {
+        stats.trace_id_zero += 1;
+        anyhow::bail!("TraceID is zero (reason:trace_id_zero)");
+    }
+    if s.span_id == 0 {
+        stats.span_id_zero += 1;
+        anyhow::bail!("SpanID is zero (reason:span_id_zero)");
+    }
 
     // TODO: component2name: check for a feature flag to determine the component tag to become the
     // span name https://github.com/DataDog/datadog-agent/blob/dc88d14851354cada1d15265220a39dce8840dcc/pkg/trace/agent/normalizer.go#L64
 
-    normalize_utils::normalize_service(&mut s.service);
-    normalize_utils::normalize_name(&mut s.name);
+    if s.service.is_empty() {
+        stats.service_empty += 1;
+        normalize_utils::normalize_service(&mut s.service);
+    } else {
+        if s.service.len() > normalize_utils::MAX_SERVICE_LEN {
+            stats.service_truncate += 1;
+        }
+        let mut truncated = s.service.clone();
+        normalize_utils::truncate_utf8(&mut truncated, normalize_utils::MAX_SERVICE_LEN);
+        normalize_utils::normalize_service(&mut s.service);
+        if s.service != truncated {
+            stats.service_invalid += 1;
+        }
+    }
+
+    if s.name.is_empty() {
+        stats.span_name_empty += 1;
+        normalize_utils::normalize_name(&mut s.name);
+    } else {
+        if s.name.len() > normalize_utils::MAX_NAME_LEN {
+            stats.span_name_truncate += 1;
+        }
+        let mut truncated = s.name.clone();
+        normalize_utils::truncate_utf8(&mut truncated, normalize_utils::MAX_NAME_LEN);
+        normalize_utils::normalize_name(&mut s.name);
+        if s.name != truncated {
+            stats.span_name_invalid += 1;
+        }
+    }
+
+    if s.resource.is_empty() {
+        stats.resource_empty += 1;
+    }
     normalize_utils::normalize_resource(&mut s.resource, &s.name);
     normalize_utils::normalize_parent_id(&mut s.parent_id, s.trace_id, s.span_id);
+
+    if s.duration < 0 || s.start.checked_add(s.duration).is_none() {
+        stats.invalid_duration += 1;
+    }
+    if s.start < normalize_utils::YEAR_2000_NANOSEC_TS {
+        stats.invalid_start_date += 1;
+    }
     normalize_utils::normalize_span_start_duration(&mut s.start, &mut s.duration);
+
+    if s.r#type.len() > normalize_utils::MAX_TYPE_LEN {
+        stats.type_truncate += 1;
+    }
     normalize_utils::normalize_span_type(&mut s.r#type);
 
     if let Some(env_tag) = s.meta.get_mut("env") {
@@ -36,6 +168,7 @@ fn normalize_span(s: &mut pb::Span) -> anyhow::Result<()> {
 
     if let Some(code) = s.meta.get("http.status_code") {
         if !is_valid_status_code(code) {
+            stats.invalid_http_status_code += 1;
             s.meta.remove("http.status_code");
         }
     };
@@ -54,19 +187,32 @@ pub(crate) fn is_valid_status_code(sc: &str) -> bool {
 /// * returns an error if there is a trace ID discrepancy between 2 spans
 /// * returns an error if at least one span cannot be normalized
 pub fn normalize_trace(trace: &mut [pb::Span]) -> anyhow::Result<()> {
+    normalize_trace_with_stats(trace, &mut NormalizationStats::default())
+}
+
+/// Like [`normalize_trace`], additionally counting the applied fix-ups in `stats`. A trace which
+/// cannot be normalized is counted once, with the reason of the first error.
+pub fn normalize_trace_with_stats(
+    trace: &mut [pb::Span],
+    stats: &mut NormalizationStats,
+) -> anyhow::Result<()> {
     let first_trace_id = match trace.first() {
         Some(first_span) => first_span.trace_id,
-        None => anyhow::bail!("Normalize Trace Error: Trace is empty"),
+        None => {
+            stats.empty_trace += 1;
+            anyhow::bail!("Normalize Trace Error: Trace is empty")
+        }
     };
 
     for span in trace {
         if span.trace_id != first_trace_id {
+            stats.foreign_span += 1;
             anyhow::bail!(format!(
                 "Normalize Trace Error: Trace has foreign span: {:?}",
                 span
             ));
         }
-        normalize_span(span)?;
+        normalize_span_with_stats(span, stats)?;
     }
     Ok(())
 }
@@ -74,8 +220,11 @@ pub fn normalize_trace(trace: &mut [pb::Span]) -> anyhow::Result<()> {
 /// normalize_chunk takes a trace chunk and
 /// * populates origin field if it wasn't populated
 /// * populates priority field if it wasn't populated
+/// * populates the sampling decision maker (`_dd.p.dm`) tag if it wasn't populated
 /// the root span is used to populate these fields, and it's index in TraceChunk spans vec must be
-/// passed.
+/// passed. Chunks of a partially flushed trace may not hold the span carrying the priority and the
+/// decision maker, so the other spans are looked at as well for those. The origin is only ever
+/// taken from the root span.
 pub fn normalize_chunk(chunk: &mut pb::TraceChunk, root_span_index: usize) -> anyhow::Result<()> {
     // check if priority is not populated
     let root_span = match chunk.spans.get(root_span_index) {
@@ -84,27 +233,29 @@ pub fn normalize_chunk(chunk: &mut pb::TraceChunk, root_span_index: usize) -> an
             anyhow::bail!("Normalize Chunk Error: root_span_index > length of trace chunk spans")
         }
     };
+    // the root span first, then the others in order
+    let spans = || std::iter::once(root_span).chain(chunk.spans.iter());
 
     if chunk.priority == SamplerPriority::None as i32 {
         // Older tracers set sampling priority in the root span.
-        if let Some(root_span_priority) = root_span.metrics.get(TAG_SAMPLING_PRIORITY) {
-            chunk.priority = *root_span_priority as i32;
-        } else {
-            for span in &chunk.spans {
-                if let Some(priority) = span.metrics.get(TAG_SAMPLING_PRIORITY) {
-                    chunk.priority = *priority as i32;
-                    break;
-                }
-            }
+        if let Some(priority) = spans().find_map(|span| span.metrics.get(TAG_SAMPLING_PRIORITY)) {
+            chunk.priority = *priority as i32;
         }
     }
     // check if origin is not populated
     if chunk.origin.is_empty() {
+        // Older tracers set origin in the root span.
         if let Some(origin) = root_span.meta.get(TAG_ORIGIN) {
-            // Older tracers set origin in the root span.
             chunk.origin = origin.to_string();
         }
     }
+    if !chunk.tags.contains_key(TAG_DECISION_MAKER) {
+        if let Some(decision_maker) = spans().find_map(|span| span.meta.get(TAG_DECISION_MAKER)) {
+            chunk
+                .tags
+                .insert(TAG_DECISION_MAKER.to_string(), decision_maker.to_string());
+        }
+    }
     Ok(())
 }
 
@@ -477,6 +628,47 @@ mod tests {
         assert!(normalizer::normalize_trace(&mut trace).is_ok());
     }
 
+    #[test]
+    fn test_normalize_trace_with_stats() {
+        let mut span_1 = new_test_span();
+        span_1.service = "retargeting(api-Staging ".to_string();
+        span_1.resource = "".to_string();
+        let mut span_2 = new_test_span();
+        span_2.name = "CAMEMBERT".repeat(100);
+        span_2.duration = -50;
+        span_2
+            .meta
+            .insert("http.status_code".to_string(), "42".to_string());
+
+        let mut stats = normalizer::NormalizationStats::default();
+        let mut trace = vec![span_1, span_2];
+        assert!(normalizer::normalize_trace_with_stats(&mut trace, &mut stats).is_ok());
+
+        let mut no_span_id = vec![new_test_span()];
+        no_span_id[0].span_id = 0;
+        assert!(normalizer::normalize_trace_with_stats(&mut no_span_id, &mut stats).is_err());
+        assert!(normalizer::normalize_trace_with_stats(&mut [], &mut stats).is_err());
+
+        assert_eq!(
+            normalizer::NormalizationStats {
+                service_invalid: 1,
+                span_name_truncate: 1,
+                resource_empty: 1,
+                invalid_duration: 1,
+                invalid_http_status_code: 1,
+                empty_trace: 1,
+                span_id_zero: 1,
+                ..Default::default()
+            },
+            stats
+        );
+
+        // already normalized spans are not counted again
+        let mut stats = normalizer::NormalizationStats::default();
+        assert!(normalizer::normalize_trace_with_stats(&mut trace, &mut stats).is_ok());
+        assert!(stats.is_empty());
+    }
+
     #[test]
     fn test_is_valid_status_code() {
         assert!(normalizer::is_valid_status_code("100"));
@@ -510,6 +702,39 @@ mod tests {
         assert_eq!("lambda".to_string(), chunk.origin);
     }
 
+    #[test]
+    fn test_normalize_chunk_not_populating_origin_from_other_spans() {
+        let mut chunk = new_test_chunk_with_span(new_test_span());
+        chunk.origin = "".to_string();
+        chunk.spans = vec![new_test_span(), new_test_span()];
+        chunk.spans[1]
+            .meta
+            .insert(normalizer::TAG_ORIGIN.to_string(), "synthetics".to_string());
+        assert!(normalizer::normalize_chunk(&mut chunk, 0).is_ok());
+        assert_eq!("", chunk.origin);
+    }
+
+    #[test]
+    fn test_normalize_chunk_populating_decision_maker() {
+        let mut root = new_test_span();
+        root.meta
+            .insert(normalizer::TAG_DECISION_MAKER.to_string(), "-4".to_string());
+        let mut chunk = new_test_chunk_with_span(root);
+        chunk.spans.push(new_test_span());
+        chunk.spans[1]
+            .meta
+            .insert(normalizer::TAG_DECISION_MAKER.to_string(), "-0".to_string());
+        assert!(normalizer::normalize_chunk(&mut chunk, 0).is_ok());
+        assert_eq!("-4", chunk.tags[normalizer::TAG_DECISION_MAKER]);
+
+        // a decision maker set by the tracer is kept
+        chunk
+            .tags
+            .insert(normalizer::TAG_DECISION_MAKER.to_string(), "-3".to_string());
+        assert!(normalizer::normalize_chunk(&mut chunk, 0).is_ok());
+        assert_eq!("-3", chunk.tags[normalizer::TAG_DECISION_MAKER]);
+    }
+
     #[test]
     fn test_normalize_chunk_populating_sampling_priority() {
         let mut root = new_test_span();
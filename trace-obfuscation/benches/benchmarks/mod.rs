@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod credit_cards_bench;
+pub mod obfuscate_span_bench;
 pub mod redis_obfuscation_bench;
 pub mod replace_trace_tags_bench;
 pub mod sql_obfuscation_bench;
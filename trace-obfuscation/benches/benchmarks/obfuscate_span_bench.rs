@@ -0,0 +1,78 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+
+use criterion::{black_box, criterion_group, BatchSize, Criterion};
+use datadog_trace_obfuscation::obfuscate::obfuscate_span;
+use datadog_trace_obfuscation::obfuscation_config::ObfuscationConfig;
+use datadog_trace_protobuf::pb;
+
+fn span(r#type: &str, resource: &str, meta: &[(&str, &str)]) -> pb::Span {
+    pb::Span {
+        duration: 10000000,
+        resource: resource.to_string(),
+        service: "web".to_string(),
+        name: format!("{type}.query"),
+        span_id: 123,
+        start: 1448466874000000000,
+        trace_id: 424242,
+        meta: meta
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+        metrics: HashMap::new(),
+        parent_id: 1111,
+        r#type: r#type.to_string(),
+        ..Default::default()
+    }
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("obfuscate_span");
+    let config = ObfuscationConfig {
+        http_remove_query_string: true,
+        http_remove_path_digits: true,
+        obfuscation_redis_enabled: true,
+        obfuscate_credit_cards: true,
+        credit_cards_luhn: true,
+        obfuscate_sql: true,
+        ..Default::default()
+    };
+
+    let trace = vec![
+        span(
+            "sql",
+            "SELECT * FROM users WHERE id = 42 AND email = 'user@example.com'",
+            &[("db.system", "postgresql")],
+        ),
+        span(
+            "http",
+            "GET /users/?",
+            &[
+                ("http.url", "http://example.com/users/1234?token=abcdef"),
+                ("http.method", "GET"),
+                ("payment.card", "4111 1111 1111 1111"),
+            ],
+        ),
+        span(
+            "redis",
+            "SET",
+            &[("redis.raw_command", "SET user:1234 some-secret-value")],
+        ),
+    ];
+
+    group.bench_function("mixed_trace", |b| {
+        b.iter_batched_ref(
+            || trace.clone(),
+            |trace| {
+                for span in trace.iter_mut() {
+                    obfuscate_span(black_box(span), black_box(&config));
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(benches, criterion_benchmark);
@@ -7,6 +7,7 @@ mod benchmarks;
 
 criterion_main!(
     benchmarks::credit_cards_bench::benches,
+    benchmarks::obfuscate_span_bench::benches,
     benchmarks::redis_obfuscation_bench::benches,
     benchmarks::replace_trace_tags_bench::benches,
     benchmarks::sql_obfuscation_bench::benches,
@@ -1,6 +1,53 @@
 // Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
 // SPDX-License-Identifier: Apache-2.0
 
+use datadog_trace_protobuf::pb;
+
+/// Meta keys whose values are never credit card numbers, mirroring the agent's allowlist.
+const CREDIT_CARD_SAFE_KEYS: &[&str] = &[
+    "_sample_rate",
+    "_sampling_priority_v1",
+    "account_id",
+    "aws_account",
+    "error",
+    "error.msg",
+    "error.type",
+    "error.stack",
+    "env",
+    "graphql.field",
+    "graphql.query",
+    "graphql.type",
+    "graphql.operation.name",
+    "grpc.code",
+    "grpc.method",
+    "grpc.request",
+    "http.status_code",
+    "http.method",
+    "runtime-id",
+    "out.host",
+    "out.port",
+    "sampling.priority",
+    "span.type",
+    "span.name",
+    "service.name",
+    "service",
+    "sql.query",
+    "version",
+];
+
+/// Replaces the meta values of the span which look like credit card numbers with "?". Internal
+/// tags (starting with `_`) and the tags of [`CREDIT_CARD_SAFE_KEYS`] are kept as is.
+pub fn obfuscate_credit_cards(span: &mut pb::Span, validate_luhn: bool) {
+    for (key, value) in span.meta.iter_mut() {
+        if key.starts_with('_') || CREDIT_CARD_SAFE_KEYS.contains(&key.as_str()) {
+            continue;
+        }
+        if is_card_number(&*value, validate_luhn) {
+            *value = "?".to_string();
+        }
+    }
+}
+
 /// is_card_number checks if b could be a credit card number by checking the digit count and IIN
 /// prefix. If validateLuhn is true, the Luhn checksum is also applied to potential candidates.
 /// Note: This code is based on the code from datadog-agent/pkg/obfuscate/credit_cards.go
@@ -143,7 +190,11 @@ fn valid_card_prefix(n: u32) -> FuzzyBool {
 
 #[cfg(test)]
 mod tests {
-    use crate::credit_cards::{calculate_luhn, is_card_number, valid_card_prefix, FuzzyBool};
+    use crate::credit_cards::{
+        calculate_luhn, is_card_number, obfuscate_credit_cards, valid_card_prefix, FuzzyBool,
+    };
+    use datadog_trace_protobuf::pb;
+    use std::collections::HashMap;
 
     #[test]
     fn test_valid_card_prefix() {
@@ -413,4 +464,25 @@ mod tests {
         let actual = calculate_luhn(&[7, 9, 9, 2, 7, 3, 9, 8, 7, 1]);
         assert_eq!(actual, 3);
     }
+
+    #[test]
+    fn test_obfuscate_credit_cards() {
+        let mut span = pb::Span {
+            meta: HashMap::from([
+                ("card".to_string(), "4111 1111 1111 1111".to_string()),
+                ("account_id".to_string(), "4111 1111 1111 1111".to_string()),
+                (
+                    "_dd.internal".to_string(),
+                    "4111 1111 1111 1111".to_string(),
+                ),
+                ("order".to_string(), "1234".to_string()),
+            ]),
+            ..Default::default()
+        };
+        obfuscate_credit_cards(&mut span, true);
+        assert_eq!("?", span.meta["card"]);
+        assert_eq!("4111 1111 1111 1111", span.meta["account_id"]);
+        assert_eq!("4111 1111 1111 1111", span.meta["_dd.internal"]);
+        assert_eq!("1234", span.meta["order"]);
+    }
 }
@@ -0,0 +1,255 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! Obfuscation of the JSON query bodies sent to Elasticsearch, OpenSearch and MongoDB, mirroring
+//! the agent's JSON obfuscator: every literal value is replaced by `"?"`, while the structure and
+//! the keys of the body are kept.
+
+use std::fmt;
+
+use serde::de::{self, DeserializeSeed, MapAccess, SeqAccess, Visitor};
+use serde::Serialize;
+
+use crate::sql::obfuscate_sql_string;
+
+/// Options of the JSON obfuscator, as configured for elasticsearch, opensearch and mongodb in the
+/// agent.
+#[derive(Clone, Debug, Default)]
+pub struct JsonObfuscationConfig {
+    pub enabled: bool,
+    /// Keys whose values, including nested ones, are kept as is.
+    pub keep_values: Vec<String>,
+    /// Keys whose string values are obfuscated as SQL queries instead of being replaced.
+    pub obfuscate_sql_values: Vec<String>,
+}
+
+/// Obfuscates an Elasticsearch, OpenSearch or MongoDB query body. Newline delimited bodies, as used by the
+/// bulk and multi-search APIs, are obfuscated line by line.
+///
+/// If the body is not valid JSON, what could be obfuscated up to the error is returned, followed
+/// by `...`.
+pub fn obfuscate_elasticsearch_string(body: &str, config: &JsonObfuscationConfig) -> String {
+    let mut out = String::with_capacity(body.len());
+    if obfuscate_json(body, config, &mut out).is_ok() {
+        return out;
+    }
+
+    let lines: Vec<&str> = body
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    if lines.len() > 1 {
+        out.clear();
+        for line in lines {
+            if obfuscate_json(line, config, &mut out).is_err() {
+                out.push_str("...");
+                break;
+            }
+            out.push('\n');
+        }
+    } else {
+        out.push_str("...");
+    }
+    out
+}
+
+fn obfuscate_json(
+    json: &str,
+    config: &JsonObfuscationConfig,
+    out: &mut String,
+) -> serde_json::Result<()> {
+    let mut deserializer = serde_json::Deserializer::from_str(json);
+    JsonObfuscator {
+        out,
+        config,
+        mode: Mode::Obfuscate,
+    }
+    .deserialize(&mut deserializer)?;
+    deserializer.end()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Obfuscate,
+    Keep,
+    Sql,
+}
+
+/// Writes the obfuscated form of the value it deserializes into `out`, so that the order of the
+/// keys is preserved.
+struct JsonObfuscator<'a> {
+    out: &'a mut String,
+    config: &'a JsonObfuscationConfig,
+    mode: Mode,
+}
+
+impl JsonObfuscator<'_> {
+    fn child(&mut self, mode: Mode) -> JsonObfuscator<'_> {
+        JsonObfuscator {
+            out: &mut *self.out,
+            config: self.config,
+            mode,
+        }
+    }
+
+    fn push_json<T: Serialize + ?Sized>(&mut self, value: &T) {
+        if let Ok(json) = serde_json::to_string(value) {
+            self.out.push_str(&json);
+        }
+    }
+
+    fn push_literal<T: Serialize + ?Sized>(&mut self, value: &T) {
+        match self.mode {
+            Mode::Keep => self.push_json(value),
+            Mode::Obfuscate | Mode::Sql => self.out.push_str("\"?\""),
+        }
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for JsonObfuscator<'_> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for JsonObfuscator<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a JSON value")
+    }
+
+    fn visit_bool<E: de::Error>(mut self, value: bool) -> Result<(), E> {
+        self.push_literal(&value);
+        Ok(())
+    }
+
+    fn visit_i64<E: de::Error>(mut self, value: i64) -> Result<(), E> {
+        self.push_literal(&value);
+        Ok(())
+    }
+
+    fn visit_u64<E: de::Error>(mut self, value: u64) -> Result<(), E> {
+        self.push_literal(&value);
+        Ok(())
+    }
+
+    fn visit_f64<E: de::Error>(mut self, value: f64) -> Result<(), E> {
+        self.push_literal(&value);
+        Ok(())
+    }
+
+    fn visit_unit<E: de::Error>(mut self) -> Result<(), E> {
+        self.push_literal(&());
+        Ok(())
+    }
+
+    fn visit_str<E: de::Error>(mut self, value: &str) -> Result<(), E> {
+        if self.mode == Mode::Sql {
+            self.push_json(&obfuscate_sql_string(value));
+        } else {
+            self.push_literal(value);
+        }
+        Ok(())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+        self.out.push('[');
+        let mut first = true;
+        loop {
+            let len = self.out.len();
+            if !first {
+                self.out.push(',');
+            }
+            let mode = self.mode;
+            if seq.next_element_seed(self.child(mode))?.is_none() {
+                // nothing was written by the child, drop the separator
+                self.out.truncate(len);
+                break;
+            }
+            first = false;
+        }
+        self.out.push(']');
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<(), A::Error> {
+        self.out.push('{');
+        let mut first = true;
+        while let Some(key) = map.next_key::<String>()? {
+            if !first {
+                self.out.push(',');
+            }
+            first = false;
+            self.push_json(&key);
+            self.out.push(':');
+
+            let mode = if self.mode == Mode::Keep || self.config.keep_values.contains(&key) {
+                Mode::Keep
+            } else if self.config.obfuscate_sql_values.contains(&key) {
+                Mode::Sql
+            } else {
+                Mode::Obfuscate
+            };
+            map.next_value_seed(self.child(mode))?;
+        }
+        self.out.push('}');
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use duplicate::duplicate_item;
+
+    use super::{obfuscate_elasticsearch_string, JsonObfuscationConfig};
+
+    fn config(keep_values: &[&str], obfuscate_sql_values: &[&str]) -> JsonObfuscationConfig {
+        JsonObfuscationConfig {
+            enabled: true,
+            keep_values: keep_values.iter().map(|key| key.to_string()).collect(),
+            obfuscate_sql_values: obfuscate_sql_values
+                .iter()
+                .map(|key| key.to_string())
+                .collect(),
+        }
+    }
+
+    #[duplicate_item(
+        test_name                           input                                                               expected;
+        [test_obfuscate_es_match]           [r#"{"query": {"match": {"title": "secret"}}}"#]                    [r#"{"query":{"match":{"title":"?"}}}"#];
+        [test_obfuscate_es_literals]        [r#"{"size": 10, "explain": true, "min_score": 0.5, "x": null}"#]   [r#"{"size":"?","explain":"?","min_score":"?","x":"?"}"#];
+        [test_obfuscate_es_arrays]          [r#"{"terms": {"tags": ["a", "b"]}, "sort": [{"age": "asc"}]}"#]    [r#"{"terms":{"tags":["?","?"]},"sort":[{"age":"?"}]}"#];
+        [test_obfuscate_es_empty]           [r#"{"query": {"match_all": {}}, "ids": []}"#]                      [r#"{"query":{"match_all":{}},"ids":[]}"#];
+        [test_obfuscate_es_key_order]       [r#"{"z": 1, "a": 2}"#]                                             [r#"{"z":"?","a":"?"}"#];
+        [test_obfuscate_es_ndjson]          ["{\"index\": \"users\"}\n{\"query\": {\"term\": {\"id\": 5}}}\n"]  ["{\"index\":\"?\"}\n{\"query\":{\"term\":{\"id\":\"?\"}}}\n"];
+        [test_obfuscate_es_invalid]         [r#"{"query": {"match": {"title": "secret"#]                        [r#"{"query":{"match":{"title":..."#];
+    )]
+    #[test]
+    fn test_name() {
+        assert_eq!(
+            obfuscate_elasticsearch_string(input, &config(&[], &[])),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_keep_values() {
+        let body = r#"{"query": {"term": {"user_id": {"value": 42}, "name": "bob"}}}"#;
+        assert_eq!(
+            obfuscate_elasticsearch_string(body, &config(&["user_id"], &[])),
+            r#"{"query":{"term":{"user_id":{"value":42},"name":"?"}}}"#
+        );
+    }
+
+    #[test]
+    fn test_obfuscate_sql_values() {
+        let body = r#"{"query": "SELECT * FROM users WHERE id = 42", "other": "x"}"#;
+        assert_eq!(
+            obfuscate_elasticsearch_string(body, &config(&[], &["query"])),
+            r#"{"query":"SELECT * FROM users WHERE id = ?","other":"?"}"#
+        );
+    }
+}
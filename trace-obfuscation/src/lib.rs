@@ -4,6 +4,7 @@
 #![deny(clippy::all)]
 
 pub mod credit_cards;
+pub mod elasticsearch;
 pub mod http;
 pub mod memcached;
 pub mod obfuscate;
@@ -11,4 +12,5 @@ pub mod obfuscation_config;
 pub mod redis;
 pub mod redis_tokenizer;
 pub mod replacer;
+pub mod resource;
 pub mod sql;
@@ -2,21 +2,47 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use datadog_trace_protobuf::pb;
+use datadog_trace_utils::trace_utils;
 
 use crate::{
+    credit_cards::obfuscate_credit_cards,
+    elasticsearch::obfuscate_elasticsearch_string,
     http::obfuscate_url_string,
     memcached::obfuscate_memcached_string,
     obfuscation_config::ObfuscationConfig,
     redis::{obfuscate_redis_string, remove_all_redis_args},
     replacer::replace_span_tags,
+    resource::obfuscate_resource,
+    sql::obfuscate_sql_string,
 };
 
+/// Span meta holding the obfuscated query of SQL spans, like the agent sets it.
+const SQL_QUERY_KEY: &str = "sql.query";
+
+/// Returns the local root span id and the endpoint of a trace chunk which was not obfuscated yet,
+/// with the obfuscation the resource of its span gets, see [`trace_utils::get_trace_endpoint`].
+pub fn get_trace_endpoint(trace: &[pb::Span], config: &ObfuscationConfig) -> Option<(u64, String)> {
+    let (local_root_span_id, span) = trace_utils::get_trace_endpoint_span(trace)?;
+    // The query string goes first, its "?" would be mistaken for an obfuscated value otherwise.
+    let endpoint = trace_utils::trace_endpoint_from_resource(&span.resource)?;
+    let endpoint = if obfuscates_resource(span, config) {
+        obfuscate_resource(endpoint)
+    } else {
+        endpoint.to_string()
+    };
+    Some((local_root_span_id, endpoint))
+}
+
+fn obfuscates_resource(span: &pb::Span, config: &ObfuscationConfig) -> bool {
+    config.obfuscate_resource_types.contains(&span.r#type)
+}
+
 pub fn obfuscate_span(span: &mut pb::Span, config: &ObfuscationConfig) {
+    if obfuscates_resource(span, config) {
+        span.resource = obfuscate_resource(&span.resource);
+    }
     match span.r#type.as_str() {
         "web" | "http" => {
-            if span.meta.is_empty() {
-                return;
-            }
             if let Some(url) = span.meta.get_mut("http.url") {
                 *url = obfuscate_url_string(
                     url,
@@ -25,15 +51,20 @@ pub fn obfuscate_span(span: &mut pb::Span, config: &ObfuscationConfig) {
                 )
             }
         }
+        "sql" | "cassandra" if config.obfuscate_sql && !span.resource.is_empty() => {
+            let query = obfuscate_sql_string(&span.resource);
+            // A query set by the tracer is kept, the resource may only be a summary of it
+            span.meta
+                .entry(SQL_QUERY_KEY.to_string())
+                .or_insert_with(|| query.clone());
+            span.resource = query;
+        }
         "memcached" if config.obfuscate_memcached => {
             if let Some(cmd) = span.meta.get_mut("memcached.command") {
                 *cmd = obfuscate_memcached_string(cmd)
             }
         }
-        "redis" => {
-            if !config.obfuscation_redis_enabled || span.meta.is_empty() {
-                return;
-            }
+        "redis" if config.obfuscation_redis_enabled => {
             if let Some(redis_cmd) = span.meta.get_mut("redis.raw_command") {
                 if config.obfuscation_redis_remove_all_args {
                     *redis_cmd = remove_all_redis_args(redis_cmd)
@@ -41,8 +72,31 @@ pub fn obfuscate_span(span: &mut pb::Span, config: &ObfuscationConfig) {
                 *redis_cmd = obfuscate_redis_string(redis_cmd)
             }
         }
+        "elasticsearch" if config.obfuscation_elasticsearch.enabled => {
+            if let Some(body) = span.meta.get_mut("elasticsearch.body") {
+                *body = obfuscate_elasticsearch_string(body, &config.obfuscation_elasticsearch)
+            }
+        }
+        "opensearch" if config.obfuscation_opensearch.enabled => {
+            if let Some(body) = span.meta.get_mut("opensearch.body") {
+                *body = obfuscate_elasticsearch_string(body, &config.obfuscation_opensearch)
+            }
+        }
+        "mongodb" if config.obfuscation_mongodb.enabled => {
+            if let Some(query) = span.meta.get_mut("mongodb.query") {
+                *query = obfuscate_elasticsearch_string(query, &config.obfuscation_mongodb)
+            }
+        }
         _ => {}
     }
+    if config.remove_stack_traces {
+        if let Some(stack) = span.meta.get_mut("error.stack") {
+            *stack = "?".to_string();
+        }
+    }
+    if config.obfuscate_credit_cards {
+        obfuscate_credit_cards(span, config.credit_cards_luhn);
+    }
     if let Some(tag_replace_rules) = &config.tag_replace_rules {
         replace_span_tags(span, tag_replace_rules, &mut String::new());
     }
@@ -52,6 +106,7 @@ pub fn obfuscate_span(span: &mut pb::Span, config: &ObfuscationConfig) {
 mod tests {
     use datadog_trace_utils::test_utils;
 
+    use crate::elasticsearch::JsonObfuscationConfig;
     use crate::{obfuscation_config, replacer};
 
     use super::obfuscate_span;
@@ -71,6 +126,14 @@ mod tests {
             obfuscate_memcached: false,
             obfuscation_redis_enabled: false,
             obfuscation_redis_remove_all_args: false,
+            obfuscation_elasticsearch: Default::default(),
+            obfuscation_opensearch: Default::default(),
+            obfuscation_mongodb: Default::default(),
+            remove_stack_traces: false,
+            obfuscate_credit_cards: false,
+            credit_cards_luhn: false,
+            obfuscate_resource_types: vec![],
+            obfuscate_sql: false,
         };
         obfuscate_span(&mut span, &obf_config);
         assert_eq!(
@@ -96,6 +159,14 @@ mod tests {
             obfuscate_memcached: false,
             obfuscation_redis_enabled: false,
             obfuscation_redis_remove_all_args: false,
+            obfuscation_elasticsearch: Default::default(),
+            obfuscation_opensearch: Default::default(),
+            obfuscation_mongodb: Default::default(),
+            remove_stack_traces: false,
+            obfuscate_credit_cards: false,
+            credit_cards_luhn: false,
+            obfuscate_resource_types: vec![],
+            obfuscate_sql: false,
         };
 
         obfuscate_span(&mut span, &obf_config);
@@ -118,6 +189,14 @@ mod tests {
             obfuscation_redis_enabled: true,
             obfuscation_redis_remove_all_args: true,
             obfuscate_memcached: false,
+            obfuscation_elasticsearch: Default::default(),
+            obfuscation_opensearch: Default::default(),
+            obfuscation_mongodb: Default::default(),
+            remove_stack_traces: false,
+            obfuscate_credit_cards: false,
+            credit_cards_luhn: false,
+            obfuscate_resource_types: vec![],
+            obfuscate_sql: false,
         };
         obfuscate_span(&mut span, &obf_config);
         assert_eq!(span.meta.get("redis.raw_command").unwrap(), "GEOADD ?")
@@ -138,6 +217,14 @@ mod tests {
             obfuscation_redis_enabled: true,
             obfuscation_redis_remove_all_args: false,
             obfuscate_memcached: false,
+            obfuscation_elasticsearch: Default::default(),
+            obfuscation_opensearch: Default::default(),
+            obfuscation_mongodb: Default::default(),
+            remove_stack_traces: false,
+            obfuscate_credit_cards: false,
+            credit_cards_luhn: false,
+            obfuscate_resource_types: vec![],
+            obfuscate_sql: false,
         };
         obfuscate_span(&mut span, &obf_config);
         assert_eq!(
@@ -145,4 +232,150 @@ mod tests {
             "GEOADD key longitude latitude ?"
         )
     }
+
+    #[test]
+    fn obfuscate_elasticsearch_body() {
+        let mut span = test_utils::create_test_span(111, 222, 0, 1, true);
+        span.r#type = "elasticsearch".to_string();
+        span.meta.insert(
+            "elasticsearch.body".to_string(),
+            r#"{"query": {"term": {"user_id": 42, "name": "bob"}}}"#.to_string(),
+        );
+        let obf_config = obfuscation_config::ObfuscationConfig {
+            obfuscation_elasticsearch: JsonObfuscationConfig {
+                enabled: true,
+                keep_values: vec!["user_id".to_string()],
+                obfuscate_sql_values: vec![],
+            },
+            ..Default::default()
+        };
+        obfuscate_span(&mut span, &obf_config);
+        assert_eq!(
+            span.meta.get("elasticsearch.body").unwrap(),
+            r#"{"query":{"term":{"user_id":42,"name":"?"}}}"#
+        )
+    }
+
+    #[test]
+    fn obfuscate_mongodb_query() {
+        let mut span = test_utils::create_test_span(111, 222, 0, 1, true);
+        span.r#type = "mongodb".to_string();
+        span.meta.insert(
+            "mongodb.query".to_string(),
+            r#"{"find": "users", "filter": {"name": "bob"}}"#.to_string(),
+        );
+        span.meta
+            .insert("error.stack".to_string(), "at main.rs:42".to_string());
+        let obf_config = obfuscation_config::ObfuscationConfig {
+            obfuscation_mongodb: JsonObfuscationConfig {
+                enabled: true,
+                keep_values: vec!["find".to_string()],
+                obfuscate_sql_values: vec![],
+            },
+            remove_stack_traces: true,
+            ..Default::default()
+        };
+        obfuscate_span(&mut span, &obf_config);
+        assert_eq!(
+            span.meta.get("mongodb.query").unwrap(),
+            r#"{"find":"users","filter":{"name":"?"}}"#
+        );
+        assert_eq!(span.meta.get("error.stack").unwrap(), "?");
+    }
+
+    #[test]
+    fn obfuscate_sql_resource() {
+        let mut span = test_utils::create_test_span(111, 222, 0, 1, true);
+        span.r#type = "sql".to_string();
+        span.resource = "SELECT * FROM users WHERE id = 42".to_string();
+        obfuscate_span(&mut span, &Default::default());
+        assert_eq!("SELECT * FROM users WHERE id = 42", span.resource);
+        assert!(!span.meta.contains_key("sql.query"));
+
+        let obf_config = obfuscation_config::ObfuscationConfig {
+            obfuscate_sql: true,
+            ..Default::default()
+        };
+        let mut span = test_utils::create_test_span(111, 222, 0, 1, true);
+        span.r#type = "sql".to_string();
+        span.resource = "SELECT * FROM users WHERE id = 42".to_string();
+        obfuscate_span(&mut span, &obf_config);
+        assert_eq!("SELECT * FROM users WHERE id = ?", span.resource);
+        assert_eq!("SELECT * FROM users WHERE id = ?", span.meta["sql.query"]);
+
+        let mut span = test_utils::create_test_span(111, 222, 0, 1, true);
+        span.r#type = "sql".to_string();
+        span.resource = "SELECT * FROM users WHERE id = 42".to_string();
+        span.meta.insert(
+            "sql.query".to_string(),
+            "SELECT * FROM users WHERE id = :id".to_string(),
+        );
+        obfuscate_span(&mut span, &obf_config);
+        assert_eq!("SELECT * FROM users WHERE id = ?", span.resource);
+        assert_eq!("SELECT * FROM users WHERE id = :id", span.meta["sql.query"]);
+    }
+
+    #[test]
+    fn obfuscate_resource_of_types() {
+        let mut span = test_utils::create_test_span(111, 222, 0, 1, true);
+        span.r#type = "cache".to_string();
+        span.resource = "GET session:42".to_string();
+        let mut obf_config = obfuscation_config::ObfuscationConfig {
+            obfuscate_resource_types: vec!["queue".to_string()],
+            ..Default::default()
+        };
+        obfuscate_span(&mut span, &obf_config);
+        assert_eq!("GET session:42", span.resource);
+
+        obf_config
+            .obfuscate_resource_types
+            .push("cache".to_string());
+        obfuscate_span(&mut span, &obf_config);
+        assert_eq!("GET session:?", span.resource);
+    }
+
+    #[test]
+    fn obfuscate_credit_cards_in_meta() {
+        let mut span = test_utils::create_test_span(111, 222, 0, 1, true);
+        span.meta
+            .insert("payment.card".to_string(), "4111111111111111".to_string());
+        let mut obf_config = obfuscation_config::ObfuscationConfig::default();
+        obfuscate_span(&mut span.clone(), &obf_config);
+        assert_eq!("4111111111111111", span.meta["payment.card"]);
+
+        obf_config.obfuscate_credit_cards = true;
+        obfuscate_span(&mut span, &obf_config);
+        assert_eq!("?", span.meta["payment.card"]);
+
+        // whatever the type of the span
+        for r#type in ["web", "http", "redis"] {
+            let mut span = test_utils::create_test_span(111, 222, 0, 1, true);
+            span.r#type = r#type.to_string();
+            span.meta
+                .insert("payment.card".to_string(), "4111111111111111".to_string());
+            obfuscate_span(&mut span, &obf_config);
+            assert_eq!("?", span.meta["payment.card"], "{}", r#type);
+        }
+    }
+
+    #[test]
+    fn obfuscated_trace_endpoint() {
+        let root = test_utils::create_test_span(111, 1, 0, 1, true);
+        let mut web = test_utils::create_test_span(111, 2, 1, 2, false);
+        web.r#type = "web".to_string();
+        web.resource = "GET /users/42?page=2".to_string();
+        web.metrics.insert("_dd.top_level".to_string(), 1.0);
+        let trace = [root, web];
+        let mut obf_config = obfuscation_config::ObfuscationConfig::default();
+        assert_eq!(
+            Some((1, "GET /users/42".to_string())),
+            super::get_trace_endpoint(&trace, &obf_config)
+        );
+
+        obf_config.obfuscate_resource_types.push("web".to_string());
+        assert_eq!(
+            Some((1, "GET /users/?".to_string())),
+            super::get_trace_endpoint(&trace, &obf_config)
+        );
+    }
 }
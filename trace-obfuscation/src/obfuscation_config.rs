@@ -2,13 +2,15 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use log::{debug, error};
+use serde::Deserialize;
 use std::env;
 
 use ddcommon::config::parse_env;
 
-use crate::replacer::{self, ReplaceRule};
+use crate::elasticsearch::JsonObfuscationConfig;
+use crate::replacer::{self, RawReplaceRule, ReplaceRule};
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct ObfuscationConfig {
     pub tag_replace_rules: Option<Vec<ReplaceRule>>,
     pub http_remove_query_string: bool,
@@ -16,10 +18,40 @@ pub struct ObfuscationConfig {
     pub obfuscate_memcached: bool,
     pub obfuscation_redis_enabled: bool,
     pub obfuscation_redis_remove_all_args: bool,
+    pub obfuscation_elasticsearch: JsonObfuscationConfig,
+    pub obfuscation_opensearch: JsonObfuscationConfig,
+    pub obfuscation_mongodb: JsonObfuscationConfig,
+    /// Replaces the error stack traces of the spans by "?".
+    pub remove_stack_traces: bool,
+    /// Replaces the meta values looking like credit card numbers.
+    pub obfuscate_credit_cards: bool,
+    /// Only replaces the credit card numbers with a valid Luhn checksum, reducing false positives.
+    pub credit_cards_luhn: bool,
+    /// The span types whose resource has its numbers and UUIDs replaced, e.g. "cache" or "queue".
+    pub obfuscate_resource_types: Vec<String>,
+    /// Replaces the literals of the resource of sql and cassandra spans, like the agent does. The
+    /// query is copied to the "sql.query" meta first, unless the tracer already set it.
+    pub obfuscate_sql: bool,
 }
 
 impl ObfuscationConfig {
+    /// Reads the obfuscation config from the environment.
+    ///
+    /// DD_APM_OBFUSCATION_CONFIG may hold the agent's `apm_config` section as JSON (see
+    /// [`ObfuscationConfig::from_agent_config_json`]), which is used as a base. The individual
+    /// DD_APM_* env vars take precedence over it, like they do in the agent.
     pub fn new() -> Result<ObfuscationConfig, Box<dyn std::error::Error>> {
+        let base = match env::var("DD_APM_OBFUSCATION_CONFIG") {
+            Ok(json) => Self::from_agent_config_json(&json)?,
+            Err(_) => ObfuscationConfig::default(),
+        };
+        for name in UNSUPPORTED_JSON_OBFUSCATIONS {
+            let var = format!("DD_APM_OBFUSCATION_{}_ENABLED", name.to_uppercase());
+            if parse_env::bool(&var).unwrap_or(false) {
+                return Err(unsupported_option(name).into());
+            }
+        }
+
         let tag_replace_rules: Option<Vec<ReplaceRule>> = match env::var("DD_APM_REPLACE_TAGS") {
             Ok(replace_rules_str) => match replacer::parse_rules_from_string(&replace_rules_str) {
                 Ok(res) => {
@@ -31,19 +63,47 @@ impl ObfuscationConfig {
                     None
                 }
             },
-            Err(_) => None,
+            Err(_) => base.tag_replace_rules,
         };
         let http_remove_query_string =
-            parse_env::bool("DD_APM_OBFUSCATION_HTTP_REMOVE_QUERY_STRING").unwrap_or(false);
+            parse_env::bool("DD_APM_OBFUSCATION_HTTP_REMOVE_QUERY_STRING")
+                .unwrap_or(base.http_remove_query_string);
         let http_remove_path_digits =
-            parse_env::bool("DD_APM_OBFUSCATION_HTTP_REMOVE_PATHS_WITH_DIGITS").unwrap_or(false);
-        let obfuscation_redis_enabled =
-            parse_env::bool("DD_APM_OBFUSCATION_REDIS_ENABLED").unwrap_or(false);
+            parse_env::bool("DD_APM_OBFUSCATION_HTTP_REMOVE_PATHS_WITH_DIGITS")
+                .unwrap_or(base.http_remove_path_digits);
+        let obfuscation_redis_enabled = parse_env::bool("DD_APM_OBFUSCATION_REDIS_ENABLED")
+            .unwrap_or(base.obfuscation_redis_enabled);
         let obfuscation_redis_remove_all_args =
-            parse_env::bool("DD_APM_OBFUSCATION_REDIS_REMOVE_ALL_ARGS").unwrap_or(false);
+            parse_env::bool("DD_APM_OBFUSCATION_REDIS_REMOVE_ALL_ARGS")
+                .unwrap_or(base.obfuscation_redis_remove_all_args);
+
+        let obfuscate_memcached = parse_env::bool("DD_APM_OBFUSCATION_MEMCACHED_ENABLED")
+            .unwrap_or(base.obfuscate_memcached);
+
+        let obfuscation_elasticsearch =
+            json_obfuscation_from_env("ELASTICSEARCH", base.obfuscation_elasticsearch);
+        let obfuscation_opensearch =
+            json_obfuscation_from_env("OPENSEARCH", base.obfuscation_opensearch);
+        let obfuscation_mongodb = json_obfuscation_from_env("MONGODB", base.obfuscation_mongodb);
+        let remove_stack_traces = parse_env::bool("DD_APM_OBFUSCATION_REMOVE_STACK_TRACES")
+            .unwrap_or(base.remove_stack_traces);
 
-        let obfuscate_memcached =
-            parse_env::bool("DD_APM_OBFUSCATION_MEMCACHED_ENABLED").unwrap_or(false);
+        let obfuscate_credit_cards = parse_env::bool("DD_APM_OBFUSCATION_CREDIT_CARDS_ENABLED")
+            .unwrap_or(base.obfuscate_credit_cards);
+        let credit_cards_luhn = parse_env::bool("DD_APM_OBFUSCATION_CREDIT_CARDS_LUHN")
+            .unwrap_or(base.credit_cards_luhn);
+
+        let obfuscate_resource_types =
+            match parse_env::str_not_empty("DD_APM_OBFUSCATION_RESOURCE_TYPES") {
+                Some(types) => types
+                    .split([',', ' '])
+                    .filter(|span_type| !span_type.is_empty())
+                    .map(String::from)
+                    .collect(),
+                None => base.obfuscate_resource_types,
+            };
+        let obfuscate_sql =
+            parse_env::bool("DD_APM_OBFUSCATION_SQL_ENABLED").unwrap_or(base.obfuscate_sql);
 
         Ok(ObfuscationConfig {
             tag_replace_rules,
@@ -52,6 +112,270 @@ impl ObfuscationConfig {
             obfuscate_memcached,
             obfuscation_redis_enabled,
             obfuscation_redis_remove_all_args,
+            obfuscation_elasticsearch,
+            obfuscation_opensearch,
+            obfuscation_mongodb,
+            remove_stack_traces,
+            obfuscate_credit_cards,
+            credit_cards_luhn,
+            obfuscate_resource_types,
+            obfuscate_sql,
         })
     }
+
+    /// Builds the config from the JSON representation of the datadog-agent's `apm_config`
+    /// section, i.e. an object holding `replace_tags` and `obfuscation`. The whole agent config
+    /// (with a top-level `apm_config` key) is accepted as well, so that existing agent configs
+    /// can be reused unchanged.
+    ///
+    /// Enabling the obfuscation of SQL execution plans is an error: the trace agent doesn't apply
+    /// it to spans, and this obfuscator doesn't implement it.
+    pub fn from_agent_config_json(json: &str) -> anyhow::Result<ObfuscationConfig> {
+        let mut value: serde_json::Value = serde_json::from_str(json)?;
+        if let Some(apm_config) = value.get_mut("apm_config") {
+            value = apm_config.take();
+        }
+        let apm_config: AgentApmConfig = serde_json::from_value(value)?;
+        apm_config.into_obfuscation_config()
+    }
+}
+
+/// The agent's JSON obfuscation options this obfuscator doesn't implement.
+const UNSUPPORTED_JSON_OBFUSCATIONS: [&str; 2] = ["sql_exec_plan", "sql_exec_plan_normalize"];
+
+fn unsupported_option(option: &str) -> anyhow::Error {
+    anyhow::anyhow!("The {option} obfuscation is not supported")
+}
+
+/// Applies the DD_APM_OBFUSCATION_<NAME>_ENABLED, _KEEP_VALUES and _OBFUSCATE_SQL_VALUES env vars
+/// on top of `base`. Like in the agent, the lists are given as JSON arrays.
+fn json_obfuscation_from_env(name: &str, base: JsonObfuscationConfig) -> JsonObfuscationConfig {
+    let list = |option: &str, base: Vec<String>| {
+        let var = format!("DD_APM_OBFUSCATION_{name}_{option}");
+        match parse_env::str_not_empty(&var).map(|list| serde_json::from_str(&list)) {
+            Some(Ok(list)) => list,
+            Some(Err(e)) => {
+                error!("Failed to parse {var}: {e}");
+                base
+            }
+            None => base,
+        }
+    };
+    JsonObfuscationConfig {
+        enabled: parse_env::bool(&format!("DD_APM_OBFUSCATION_{name}_ENABLED"))
+            .unwrap_or(base.enabled),
+        keep_values: list("KEEP_VALUES", base.keep_values),
+        obfuscate_sql_values: list("OBFUSCATE_SQL_VALUES", base.obfuscate_sql_values),
+    }
+}
+
+/// Mirror of the agent's apm_config.obfuscation.http
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct AgentHttpObfuscationConfig {
+    remove_query_string: bool,
+    remove_paths_with_digits: bool,
+}
+
+/// Mirror of the agent's apm_config.obfuscation.redis
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct AgentRedisObfuscationConfig {
+    enabled: bool,
+    remove_all_args: bool,
+}
+
+/// Mirror of the agent's apm_config.obfuscation.memcached. The command is always kept, only the
+/// values following it are removed, so `keep_command` has no effect.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct AgentMemcachedObfuscationConfig {
+    enabled: bool,
+}
+
+/// Mirror of the agent's JSON obfuscation options (elasticsearch, opensearch, mongodb,
+/// sql_exec_plan, sql_exec_plan_normalize)
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct AgentJsonObfuscationConfig {
+    enabled: bool,
+    keep_values: Vec<String>,
+    obfuscate_sql_values: Vec<String>,
+}
+
+impl From<AgentJsonObfuscationConfig> for JsonObfuscationConfig {
+    fn from(config: AgentJsonObfuscationConfig) -> Self {
+        JsonObfuscationConfig {
+            enabled: config.enabled,
+            keep_values: config.keep_values,
+            obfuscate_sql_values: config.obfuscate_sql_values,
+        }
+    }
+}
+
+/// Mirror of the agent's apm_config.obfuscation.credit_cards
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct AgentCreditCardsObfuscationConfig {
+    enabled: bool,
+    luhn: bool,
+}
+
+/// The agent always obfuscates SQL resources, this lets the caller opt in instead.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct AgentSqlObfuscationConfig {
+    enabled: bool,
+}
+
+/// Mirror of the agent's apm_config.obfuscation
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct AgentObfuscationConfig {
+    elasticsearch: AgentJsonObfuscationConfig,
+    opensearch: AgentJsonObfuscationConfig,
+    mongodb: AgentJsonObfuscationConfig,
+    sql_exec_plan: AgentJsonObfuscationConfig,
+    sql_exec_plan_normalize: AgentJsonObfuscationConfig,
+    http: AgentHttpObfuscationConfig,
+    remove_stack_traces: bool,
+    redis: AgentRedisObfuscationConfig,
+    memcached: AgentMemcachedObfuscationConfig,
+    credit_cards: AgentCreditCardsObfuscationConfig,
+    /// Not an agent option, see [`ObfuscationConfig::obfuscate_resource_types`].
+    resource_types: Vec<String>,
+    /// Not an agent option, see [`ObfuscationConfig::obfuscate_sql`].
+    sql: AgentSqlObfuscationConfig,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct AgentApmConfig {
+    replace_tags: Option<Vec<RawReplaceRule>>,
+    obfuscation: AgentObfuscationConfig,
+}
+
+impl AgentApmConfig {
+    fn into_obfuscation_config(self) -> anyhow::Result<ObfuscationConfig> {
+        let obfuscation = self.obfuscation;
+        let sql_exec_plans = [
+            obfuscation.sql_exec_plan.enabled,
+            obfuscation.sql_exec_plan_normalize.enabled,
+        ];
+        for (option, enabled) in UNSUPPORTED_JSON_OBFUSCATIONS.iter().zip(sql_exec_plans) {
+            if enabled {
+                return Err(unsupported_option(option));
+            }
+        }
+
+        Ok(ObfuscationConfig {
+            tag_replace_rules: self.replace_tags.map(replacer::compile_rules).transpose()?,
+            http_remove_query_string: obfuscation.http.remove_query_string,
+            http_remove_path_digits: obfuscation.http.remove_paths_with_digits,
+            obfuscate_memcached: obfuscation.memcached.enabled,
+            obfuscation_redis_enabled: obfuscation.redis.enabled,
+            obfuscation_redis_remove_all_args: obfuscation.redis.remove_all_args,
+            obfuscation_elasticsearch: obfuscation.elasticsearch.into(),
+            obfuscation_opensearch: obfuscation.opensearch.into(),
+            obfuscation_mongodb: obfuscation.mongodb.into(),
+            remove_stack_traces: obfuscation.remove_stack_traces,
+            obfuscate_credit_cards: obfuscation.credit_cards.enabled,
+            credit_cards_luhn: obfuscation.credit_cards.luhn,
+            obfuscate_resource_types: obfuscation.resource_types,
+            obfuscate_sql: obfuscation.sql.enabled,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ObfuscationConfig;
+
+    #[test]
+    fn test_from_agent_apm_config_json() {
+        let config = ObfuscationConfig::from_agent_config_json(
+            r#"{
+                "replace_tags": [
+                    {"name": "custom.tag", "pattern": "(/foo/bar/).*", "repl": "${1}extra"}
+                ],
+                "obfuscation": {
+                    "elasticsearch": {
+                        "enabled": true,
+                        "keep_values": ["user_id"],
+                        "obfuscate_sql_values": ["val1"]
+                    },
+                    "mongodb": {"enabled": true, "keep_values": ["find"]},
+                    "sql_exec_plan": {"enabled": false},
+                    "http": {"remove_query_string": true, "remove_paths_with_digits": true},
+                    "remove_stack_traces": true,
+                    "redis": {"enabled": true, "remove_all_args": true},
+                    "memcached": {"enabled": true, "keep_command": true},
+                    "credit_cards": {"enabled": true, "luhn": false},
+                    "resource_types": ["cache", "queue"],
+                    "sql": {"enabled": true}
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(1, config.tag_replace_rules.unwrap().len());
+        assert!(config.http_remove_query_string);
+        assert!(config.http_remove_path_digits);
+        assert!(config.obfuscate_memcached);
+        assert!(config.obfuscation_redis_enabled);
+        assert!(config.obfuscation_redis_remove_all_args);
+        assert!(config.obfuscation_elasticsearch.enabled);
+        assert_eq!(
+            vec!["user_id"],
+            config.obfuscation_elasticsearch.keep_values
+        );
+        assert_eq!(
+            vec!["val1"],
+            config.obfuscation_elasticsearch.obfuscate_sql_values
+        );
+        assert!(!config.obfuscation_opensearch.enabled);
+        assert!(config.obfuscation_mongodb.enabled);
+        assert_eq!(vec!["find"], config.obfuscation_mongodb.keep_values);
+        assert!(config.remove_stack_traces);
+        assert!(config.obfuscate_credit_cards);
+        assert!(!config.credit_cards_luhn);
+        assert_eq!(vec!["cache", "queue"], config.obfuscate_resource_types);
+        assert!(config.obfuscate_sql);
+    }
+
+    #[test]
+    fn test_from_full_agent_config_json() {
+        let config = ObfuscationConfig::from_agent_config_json(
+            r#"{"apm_config": {"obfuscation": {"http": {"remove_query_string": true}}}}"#,
+        )
+        .unwrap();
+
+        assert!(config.tag_replace_rules.is_none());
+        assert!(config.http_remove_query_string);
+        assert!(!config.http_remove_path_digits);
+        assert!(!config.obfuscate_memcached);
+        assert!(!config.obfuscation_redis_enabled);
+        assert!(config.obfuscate_resource_types.is_empty());
+        assert!(!config.obfuscate_sql);
+    }
+
+    #[test]
+    fn test_from_agent_config_json_unsupported_obfuscation() {
+        for option in ["sql_exec_plan", "sql_exec_plan_normalize"] {
+            let json = format!(r#"{{"obfuscation": {{"{option}": {{"enabled": true}}}}}}"#);
+            let error = ObfuscationConfig::from_agent_config_json(&json).unwrap_err();
+            assert_eq!(
+                format!("The {option} obfuscation is not supported"),
+                error.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_agent_config_json_invalid_rule() {
+        let config = ObfuscationConfig::from_agent_config_json(
+            r#"{"replace_tags": [{"name": "*", "pattern": "(", "repl": ""}]}"#,
+        );
+        assert!(config.is_err());
+    }
 }
@@ -6,7 +6,7 @@ use regex::Regex;
 use serde::Deserialize;
 
 #[derive(Deserialize)]
-struct RawReplaceRule {
+pub(crate) struct RawReplaceRule {
     name: String,
     pattern: String,
     repl: String,
@@ -80,8 +80,11 @@ pub fn parse_rules_from_string(
     rules: &str,
 ) -> anyhow::Result<Vec<ReplaceRule>> {
     let raw_rules = serde_json::from_str::<Vec<RawReplaceRule>>(rules)?;
+    compile_rules(raw_rules)
+}
 
-    let mut vec: Vec<ReplaceRule> = Vec::with_capacity(rules.len());
+pub(crate) fn compile_rules(raw_rules: Vec<RawReplaceRule>) -> anyhow::Result<Vec<ReplaceRule>> {
+    let mut vec: Vec<ReplaceRule> = Vec::with_capacity(raw_rules.len());
 
     // for [name, pattern, repl] in rules {
     for raw_rule in raw_rules {
@@ -0,0 +1,74 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+const UUID_LEN: usize = 36;
+
+/// Whether `bytes` starts with a UUID in its hyphenated form, which isn't followed by more
+/// alphanumeric characters.
+fn starts_with_uuid(bytes: &[u8]) -> bool {
+    bytes.len() >= UUID_LEN
+        && !bytes
+            .get(UUID_LEN)
+            .is_some_and(|b| b.is_ascii_alphanumeric())
+        && bytes[..UUID_LEN].iter().enumerate().all(|(i, b)| match i {
+            8 | 13 | 18 | 23 => *b == b'-',
+            _ => b.is_ascii_hexdigit(),
+        })
+}
+
+/// Replaces the numbers and UUIDs of a resource name with "?", e.g. for the resources of cache or
+/// queue spans which embed identifiers, to reduce their cardinality. Only whole alphanumeric words
+/// are replaced: "user:42" becomes "user:?", but "shard2" is kept.
+pub fn obfuscate_resource(resource: &str) -> String {
+    let bytes = resource.as_bytes();
+    let mut obfuscated = String::with_capacity(resource.len());
+    // the start of the part of the resource which has not been copied yet
+    let mut copied = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_alphanumeric() {
+            i += 1;
+            continue;
+        }
+        let is_uuid = starts_with_uuid(&bytes[i..]);
+        let word_end = if is_uuid {
+            i + UUID_LEN
+        } else {
+            i + bytes[i..]
+                .iter()
+                .take_while(|b| b.is_ascii_alphanumeric())
+                .count()
+        };
+        if is_uuid || bytes[i..word_end].iter().all(u8::is_ascii_digit) {
+            obfuscated.push_str(&resource[copied..i]);
+            obfuscated.push('?');
+            copied = word_end;
+        }
+        i = word_end;
+    }
+    obfuscated.push_str(&resource[copied..]);
+    obfuscated
+}
+
+#[cfg(test)]
+mod tests {
+    use duplicate::duplicate_item;
+
+    use super::obfuscate_resource;
+
+    #[duplicate_item(
+        test_name                   input                                                       expected;
+        [test_obfuscate_resource_1] ["GET user:42:profile"]                                     ["GET user:?:profile"];
+        [test_obfuscate_resource_2] ["orders.3f2b8c1e-9d4a-4b7e-8f00-1c2d3e4f5a6b.created"]     ["orders.?.created"];
+        [test_obfuscate_resource_3] ["publish shard2"]                                          ["publish shard2"];
+        [test_obfuscate_resource_4] ["job-17 retry 3"]                                          ["job-? retry ?"];
+        [test_obfuscate_resource_5] ["3f2b8c1e-9d4a-4b7e-8f00-1c2d3e4f5a6bc"]                   ["3f2b8c1e-9d4a-4b7e-8f00-1c2d3e4f5a6bc"];
+        [test_obfuscate_resource_6] ["clé 12 émise"]                                            ["clé ? émise"];
+        [test_obfuscate_resource_7] [""]                                                        [""];
+    )]
+    #[test]
+    fn test_name() {
+        let result = obfuscate_resource(input);
+        assert_eq!(result, expected);
+    }
+}
@@ -36,11 +36,43 @@ fn is_quoted(bytes: &[u8], start: usize, end: usize) -> bool {
 /// based off
 /// https://github.com/DataDog/dd-trace-java/blob/36e924eaa/internal-api/src/main/java/datadog/trace/api/normalize/SQLNormalizer.java
 pub fn obfuscate_sql_string(s: &str) -> String {
+    obfuscate(s, |_| {})
+}
+
+/// A literal replaced by `?` in the obfuscated query.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ObfuscatedLiteral {
+    /// The byte offset of the literal in the original query.
+    pub start: usize,
+    /// The byte offset right after the literal in the original query.
+    pub end: usize,
+    /// The byte offset of the `?` in the obfuscated query.
+    pub obfuscated_offset: usize,
+}
+
+/// Same as [`obfuscate_sql_string`], additionally returning the literals which were replaced, so
+/// that the `?`s of the obfuscated query can be mapped back to their position in the original
+/// one.
+pub fn obfuscate_sql_string_with_offsets(s: &str) -> (String, Vec<ObfuscatedLiteral>) {
+    let mut literals = Vec::new();
+    let obfuscated = obfuscate(s, |literal| literals.push(literal));
+    (obfuscated, literals)
+}
+
+fn obfuscate(s: &str, mut on_literal: impl FnMut(ObfuscatedLiteral)) -> String {
     let bytes = s.as_bytes();
     let mut obfuscated = String::new();
     if s.is_empty() {
         return obfuscated;
     }
+    let mut replace = |obfuscated: &mut String, start: usize, end: usize| {
+        on_literal(ObfuscatedLiteral {
+            start,
+            end,
+            obfuscated_offset: obfuscated.len(),
+        });
+        obfuscated.push('?');
+    };
     let mut start = 0;
     loop {
         if start >= s.len() {
@@ -51,7 +83,7 @@ pub fn obfuscate_sql_string(s: &str) -> String {
         if start + 1 == end {
             // if the gap is 1 character it can only be a number
             if bytes[start].is_ascii_digit() {
-                obfuscated.push('?');
+                replace(&mut obfuscated, start, end);
             } else {
                 obfuscated.push_str(&s[start..end]);
             }
@@ -60,7 +92,7 @@ pub fn obfuscate_sql_string(s: &str) -> String {
                 || is_quoted(bytes, start, end)
                 || is_hex_litteral_prefix(bytes, start, end)
             {
-                obfuscated.push('?');
+                replace(&mut obfuscated, start, end);
             } else {
                 obfuscated.push_str(&s[start..end]);
             }
@@ -73,16 +105,33 @@ pub fn obfuscate_sql_string(s: &str) -> String {
     obfuscated
 }
 
+/// Finds the next splitter, skipping over string litterals and quoted identifiers.
+///
+/// Identifiers may be quoted with double quotes (ANSI / Postgres) or backticks (MySQL), and may
+/// contain any character including splitters and single quotes. A quote character is escaped by
+/// doubling it, which naturally ends and restarts the identifier.
 fn next_splitter(s: &[u8], at: usize) -> Option<usize> {
     let mut quoted = false;
     let mut escaped = false;
+    let mut identifier_quote = None;
     for (pos, b) in s.iter().copied().enumerate().skip(at) {
+        if let Some(quote) = identifier_quote {
+            if b == quote {
+                identifier_quote = None;
+            }
+            continue;
+        }
         if b == b'\'' && !escaped {
             quoted = !quoted;
             continue;
         }
         escaped = (b == b'\\') && !escaped;
-        if !quoted && is_splitter(b) {
+        if quoted {
+            continue;
+        }
+        if b == b'"' || b == b'`' {
+            identifier_quote = Some(b);
+        } else if is_splitter(b) {
             return Some(pos);
         }
     }
@@ -124,6 +173,19 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_sql_obfuscation_offsets() {
+        let query = "SELECT * FROM users WHERE id = 42 AND name = 'bob'";
+        let (obfuscated, literals) = super::obfuscate_sql_string_with_offsets(query);
+        assert_eq!("SELECT * FROM users WHERE id = ? AND name = ?", obfuscated);
+        assert_eq!(2, literals.len());
+        assert_eq!("42", &query[literals[0].start..literals[0].end]);
+        assert_eq!("'bob'", &query[literals[1].start..literals[1].end]);
+        for literal in literals {
+            assert_eq!("?", &obfuscated[literal.obfuscated_offset..][..1]);
+        }
+    }
+
     fn test_sql_obfuscation_case(input: &str, output: &str) -> anyhow::Result<()> {
         let got = super::obfuscate_sql_string(input);
         if output != got {
@@ -258,6 +320,13 @@ INSERT INTO country (country_name, country_name_eng, country_code) VALUES (?, ?,
         ),
         ("SELECT * FROM TABLE WHERE userId = ',' and foo=foo.bar", "SELECT * FROM TABLE WHERE userId = ? and foo=foo.bar"),
         ("SELECT * FROM TABLE WHERE userId =     ','||foo.bar", "SELECT * FROM TABLE WHERE userId =     ?||foo.bar"),
+        ("SELECT * FROM 用户表 WHERE 名字 = '张三'", "SELECT * FROM 用户表 WHERE 名字 = ?"),
+        ("SELECT \"prénom\", \"nom de famille\" FROM \"utilisateurs\" WHERE \"âge\" > 18", "SELECT \"prénom\", \"nom de famille\" FROM \"utilisateurs\" WHERE \"âge\" > ?"),
+        ("SELECT \"l'été\" FROM \"ma table\" WHERE a = 'x'", "SELECT \"l'été\" FROM \"ma table\" WHERE a = ?"),
+        ("SELECT \"a\"\"b, c\" FROM t WHERE x IN ('y', 1)", "SELECT \"a\"\"b, c\" FROM t WHERE x IN (?, ?)"),
+        ("SELECT `prénom`, `nom de famille` FROM `用户` WHERE id = 1", "SELECT `prénom`, `nom de famille` FROM `用户` WHERE id = ?"),
+        ("SELECT `a``b (c)` FROM `l'été` WHERE `ü` = 'ö'", "SELECT `a``b (c)` FROM `l'été` WHERE `ü` = ?"),
+        ("SELECT * FROM \"Ünïcödé\" WHERE \"名前\" = 'it\\'s' AND n = 3", "SELECT * FROM \"Ünïcödé\" WHERE \"名前\" = ? AND n = ?"),
         (
             concat!(
             "SELECT count(*) AS totcount FROM (SELECT \"c1\", \"c2\",\"c3\",\"c4\",\"c5\",\"c6\",\"c7\",\"c8\", \"c9\", \"c10\",\"c11\",\"c12\",\"c13\",\"c14\", \"c15\",\"c16\",\"c17\",\"c18\", \"c19\",\"c20\",\"c21\",\"c22\",\"c23\", \"c24\",\"c25\",\"c26\", \"c27\" FROM (SELECT bar.y AS \"c2\", foo.x AS \"c3\", foo.z AS \"c4\", DECODE(foo.a, NULL,NULL, foo.a ||', '|| foo.b) AS \"c5\" , foo.c AS \"c6\", bar.d AS \"c1\", bar.e AS \"c7\", bar.f AS \"c8\", bar.g AS \"c9\", TO_DATE(TO_CHAR(TO_DATE(bar.h,'YYYYMMDD'),'DD-MON-YYYY'),'DD-MON-YYYY') AS \"c10\", TO_DATE(TO_CHAR(TO_DATE(bar.i,'YYYYMMDD'),'DD-MON-YYYY'),'DD-MON-YYYY') AS \"c11\", CASE WHEN DECODE(bar.j, NULL, TRUNC(SYSDATE) - TRUNC(TO_DATE(bar.h,'YYYYMMDD'))) > 150 THEN '>150 Days' WHEN DECODE(bar.j, NULL, TRUNC(SYSDATE) - TRUNC(TO_DATE(bar.h,'YYYYMMDD'))) > 120 THEN '121 to 150 Days' WHEN DECODE(bar.j, NULL, TRUNC(SYSDATE) - TRUNC(TO_DATE(bar.h,'YYYYMMDD'))) > 90 THEN '91 to 120 Days' WHEN DECODE(bar.j, NULL, TRUNC(SYSDATE) - TRUNC(TO_DATE(bar.h,'YYYYMMDD'))) > 60 THEN '61 to 90 Days' WHEN DECODE(bar.j, NULL, TRUNC(SYSDATE) - TRUNC(TO_DATE(bar.h,'YYYYMMDD'))) > 30 THEN '31 to 60 Days' WHEN DECODE(bar.j, NULL, TRUNC(SYSDATE) - TRUNC(TO_DATE(bar.h,'YYYYMMDD'))) > 0 THEN '1 to 30 Days' ELSE NULL END AS \"c12\", DECODE(bar.j, NULL, TRUNC(SYSDATE) - TRUNC(TO_DATE(bar.h,'YYYYMMDD')),NULL) as \"c13\", bar.k AS \"c14\", bar.l ||', '||bar.m AS \"c15\", DECODE(bar.n, NULL, NULL,bar.n ||', '||bar.o) AS \"c16\", bar.p AS \"c17\", bar.q AS \"c18\", bar.r AS \"c19\", bar.s AS \"c20\", qux.a AS \"c21\", TO_CHAR(TO_DATE(qux.b,'YYYYMMDD'),'DD-MON-YYYY') AS \"c22\", DECODE(qux.l,NULL,NULL, qux.l ||', '||qux.m) AS \"c23\", bar.a AS \"c24\", TO_CHAR(TO_DATE(bar.j,'YYYYMMDD'),'DD-MON-YYYY') AS \"c25\", DECODE(bar.c , 1,'N',0, 'Y', bar.c ) AS \"c26\", bar.y AS y, bar.d, bar.d AS \"c27\" FROM blort.bar , ( SELECT * FROM (SELECT a,a,l,m,b,c, RANK() OVER (PARTITION BY c ORDER BY b DESC) RNK FROM blort.d WHERE y IN (:protocols)) WHERE RNK = 1) qux, blort.foo WHERE bar.c = qux.c(+) AND bar.x = foo.x AND bar.y IN (:protocols) and bar.x IN (:sites)) ) ",
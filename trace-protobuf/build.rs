@@ -45,15 +45,42 @@ fn generate_protobuf() {
     // - handle edge case struct field names that the trace stats intake expects. example: the trace
     //   intake expects the name ContainerID rather than the PascalCase ContainerId
 
+    config.type_attribute("AgentPayload", "#[derive(Deserialize, Serialize)]");
     config.type_attribute("TracerPayload", "#[derive(Deserialize, Serialize)]");
     config.type_attribute("TraceChunk", "#[derive(Deserialize, Serialize)]");
 
     config.type_attribute("SpanLink", "#[derive(Deserialize, Serialize)]");
 
     config.type_attribute("Span", "#[derive(Deserialize, Serialize)]");
+    for field in [
+        "service",
+        "name",
+        "resource",
+        "traceID",
+        "spanID",
+        "parentID",
+        "start",
+        "duration",
+        "error",
+        "meta",
+        "metrics",
+        "type",
+        "spanLinks",
+    ] {
+        config.field_attribute(
+            format!(".pb.Span.{field}"),
+            "#[serde(default)] #[serde(deserialize_with = \"deserialize_null_into_default\")]",
+        );
+    }
+    // meta_struct values are msgpack payloads: they must be (de)serialized as bytes rather than as
+    // a sequence of integers, so that they reach the agent unchanged.
     config.field_attribute(
-        ".pb.Span",
-        "#[serde(default)] #[serde(deserialize_with = \"deserialize_null_into_default\")]",
+        ".pb.Span.meta_struct",
+        "#[serde(default)] #[serde(deserialize_with = \"deserialize_meta_struct\")]",
+    );
+    config.field_attribute(
+        ".pb.Span.meta_struct",
+        "#[serde(serialize_with = \"serialize_meta_struct\")]",
     );
     config.field_attribute(
         ".pb.Span.meta_struct",
@@ -136,7 +163,8 @@ fn generate_protobuf() {
     let add_to_top = "// Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
 // SPDX-License-Identifier: Apache-2.0
 
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
 
 fn deserialize_null_into_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
 where
@@ -147,6 +175,25 @@ where
     Ok(opt.unwrap_or_default())
 }
 
+fn deserialize_meta_struct<'de, D>(deserializer: D) -> Result<HashMap<String, Vec<u8>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let opt: Option<HashMap<String, serde_bytes::ByteBuf>> = Option::deserialize(deserializer)?;
+    Ok(opt
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(k, v)| (k, v.into_vec()))
+        .collect())
+}
+
+fn serialize_meta_struct<S>(meta_struct: &HashMap<String, Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_map(meta_struct.iter().map(|(k, v)| (k, serde_bytes::Bytes::new(v))))
+}
+
 pub fn is_default<T: Default + PartialEq>(t: &T) -> bool {
     t == &T::default()
 }
@@ -0,0 +1,44 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! JSON rendering of the payloads, for debugging.
+
+use serde::Serialize;
+
+/// Renders a span, chunk or payload as pretty printed JSON, with the field names of the msgpack
+/// encoding. The `meta_struct` values, which are msgpack encoded, are rendered as arrays of bytes.
+pub fn to_debug_json<T: Serialize + ?Sized>(value: &T) -> String {
+    serde_json::to_string_pretty(value)
+        .unwrap_or_else(|e| format!("<unable to render as JSON: {e}>"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pb;
+
+    #[test]
+    fn test_to_debug_json() {
+        let payload = pb::AgentPayload {
+            env: "prod".to_string(),
+            tracer_payloads: vec![pb::TracerPayload {
+                chunks: vec![pb::TraceChunk {
+                    spans: vec![pb::Span {
+                        name: "test".to_string(),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let json = to_debug_json(&payload);
+        assert!(json.contains("\"env\": \"prod\""));
+        assert!(json.contains("\"name\": \"test\""));
+
+        let decoded: pb::AgentPayload = serde_json::from_str(&json).unwrap();
+        assert_eq!(payload, decoded);
+    }
+}
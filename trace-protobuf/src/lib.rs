@@ -4,5 +4,8 @@
 #[rustfmt::skip]
 pub mod pb;
 
+#[cfg(feature = "json")]
+pub mod json;
+
 #[cfg(test)]
 mod pb_test;
@@ -1,7 +1,8 @@
 // Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
 // SPDX-License-Identifier: Apache-2.0
 
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
 
 fn deserialize_null_into_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
 where
@@ -12,6 +13,25 @@ where
     Ok(opt.unwrap_or_default())
 }
 
+fn deserialize_meta_struct<'de, D>(deserializer: D) -> Result<HashMap<String, Vec<u8>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let opt: Option<HashMap<String, serde_bytes::ByteBuf>> = Option::deserialize(deserializer)?;
+    Ok(opt
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(k, v)| (k, v.into_vec()))
+        .collect())
+}
+
+fn serialize_meta_struct<S>(meta_struct: &HashMap<String, Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_map(meta_struct.iter().map(|(k, v)| (k, serde_bytes::Bytes::new(v))))
+}
+
 pub fn is_default<T: Default + PartialEq>(t: &T) -> bool {
     t == &T::default()
 }
@@ -138,7 +158,8 @@ pub struct Span {
     /// @gotags: json:"meta_struct,omitempty" msg:"meta_struct,omitempty"
     #[prost(map = "string, bytes", tag = "13")]
     #[serde(default)]
-    #[serde(deserialize_with = "deserialize_null_into_default")]
+    #[serde(deserialize_with = "deserialize_meta_struct")]
+    #[serde(serialize_with = "serialize_meta_struct")]
     #[serde(skip_serializing_if = "::std::collections::HashMap::is_empty")]
     pub meta_struct: ::std::collections::HashMap<
         ::prost::alloc::string::String,
@@ -231,6 +252,7 @@ pub struct TracerPayload {
     pub app_version: ::prost::alloc::string::String,
 }
 /// AgentPayload represents payload the agent sends to the intake.
+#[derive(Deserialize, Serialize)]
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct AgentPayload {
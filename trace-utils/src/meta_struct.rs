@@ -0,0 +1,110 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! Helpers for span `meta_struct` values.
+//!
+//! Each `meta_struct` entry holds a msgpack encoded structure, stored as raw bytes on the span.
+//! These helpers take care of the encoding so that callers can work with typed values instead.
+
+use datadog_trace_protobuf::pb;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Key of the AppSec (WAF) events in `meta_struct`.
+pub const APPSEC_KEY: &str = "appsec";
+
+/// AppSec events attached to a span by the WAF.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AppSecMetaStruct {
+    /// The triggered rules with their matches, left opaque as their schema is owned by the WAF.
+    #[serde(default)]
+    pub triggers: Vec<serde_json::Value>,
+}
+
+/// Encodes a value to the msgpack representation used for `meta_struct` entries.
+pub fn encode<T: Serialize + ?Sized>(value: &T) -> anyhow::Result<Vec<u8>> {
+    Ok(rmp_serde::to_vec_named(value)?)
+}
+
+/// Decodes a `meta_struct` entry.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> anyhow::Result<T> {
+    Ok(rmp_serde::from_slice(bytes)?)
+}
+
+/// Returns the decoded `meta_struct` entry for `key`, or None if the span does not have one.
+pub fn get<T: DeserializeOwned>(span: &pb::Span, key: &str) -> Option<anyhow::Result<T>> {
+    span.meta_struct.get(key).map(|bytes| decode(bytes))
+}
+
+/// Encodes `value` and stores it as the `meta_struct` entry for `key`.
+pub fn set<T: Serialize + ?Sized>(span: &mut pb::Span, key: &str, value: &T) -> anyhow::Result<()> {
+    span.meta_struct.insert(key.to_string(), encode(value)?);
+    Ok(())
+}
+
+pub fn get_appsec(span: &pb::Span) -> Option<anyhow::Result<AppSecMetaStruct>> {
+    get(span, APPSEC_KEY)
+}
+
+pub fn set_appsec(span: &mut pb::Span, appsec: &AppSecMetaStruct) -> anyhow::Result<()> {
+    set(span, APPSEC_KEY, appsec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn appsec() -> AppSecMetaStruct {
+        AppSecMetaStruct {
+            triggers: vec![json!({
+                "rule": {"id": "crs-942-100", "name": "SQL Injection Attack"},
+                "rule_matches": [{
+                    "operator": "is_sqli",
+                    "parameters": [{"address": "server.request.query", "value": "1' OR '1'='1"}]
+                }]
+            })],
+        }
+    }
+
+    #[test]
+    fn test_appsec_roundtrip() {
+        let mut span = pb::Span::default();
+        assert!(get_appsec(&span).is_none());
+
+        set_appsec(&mut span, &appsec()).unwrap();
+        assert_eq!(appsec(), get_appsec(&span).unwrap().unwrap());
+
+        span.meta_struct
+            .insert(APPSEC_KEY.to_string(), b"not msgpack".to_vec());
+        assert!(get_appsec(&span).unwrap().is_err());
+    }
+
+    #[test]
+    fn test_meta_struct_survives_msgpack_traces() {
+        let mut span = pb::Span {
+            name: "test".to_string(),
+            ..Default::default()
+        };
+        set_appsec(&mut span, &appsec()).unwrap();
+        let traces = vec![vec![span]];
+
+        let payload = rmp_serde::to_vec_named(&traces).unwrap();
+
+        // meta_struct entries must be encoded as msgpack bin, like the tracers and the agent do
+        let value = rmpv::decode::read_value(&mut payload.as_slice()).unwrap();
+        let span = &value.as_array().unwrap()[0].as_array().unwrap()[0];
+        let meta_struct = span
+            .as_map()
+            .unwrap()
+            .iter()
+            .find(|(k, _)| k.as_str() == Some("meta_struct"))
+            .map(|(_, v)| v)
+            .unwrap();
+        assert!(meta_struct.as_map().unwrap()[0].1.is_bin());
+
+        let decoded: Vec<Vec<pb::Span>> = rmp_serde::from_slice(&payload).unwrap();
+        assert_eq!(traces, decoded);
+        assert_eq!(appsec(), get_appsec(&decoded[0][0]).unwrap().unwrap());
+    }
+}
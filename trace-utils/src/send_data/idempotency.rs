@@ -0,0 +1,112 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+
+const FNV1A_128_OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+const FNV1A_128_PRIME: u128 = 0x0000000001000000000000000000013b;
+
+/// The idempotency key of a serialized payload: the 128 bits FNV-1a hash of its content. It is
+/// sent along with every attempt of the payload, so that the intake can discard the duplicates of
+/// a payload whose response was lost, e.g. to a timeout.
+///
+/// The hash is fully specified, so that the key of a payload doesn't depend on the Rust version
+/// or the process, unlike the one of the std hashers.
+pub fn idempotency_key(payload: &[u8]) -> String {
+    let hash = payload.iter().fold(FNV1A_128_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u128::from(*byte)).wrapping_mul(FNV1A_128_PRIME)
+    });
+    format!("{hash:032x}")
+}
+
+/// Remembers the idempotency keys of the payloads recently accepted by the intake, so that a
+/// payload which is submitted again is not sent twice, e.g. when a caller retries a `SendData`
+/// after a failure which happened after some of its payloads were accepted.
+///
+/// Shared between the `SendData` of an uploader, see `SendData::set_sent_payloads`.
+#[derive(Debug)]
+pub struct SentPayloads {
+    capacity: usize,
+    keys: Mutex<RecentKeys>,
+}
+
+#[derive(Debug, Default)]
+struct RecentKeys {
+    set: HashSet<String>,
+    /// The keys in the order they were inserted, the oldest are forgotten first.
+    order: VecDeque<String>,
+}
+
+impl Default for SentPayloads {
+    fn default() -> Self {
+        SentPayloads::new(1024)
+    }
+}
+
+impl SentPayloads {
+    /// Remembers the `capacity` most recently accepted payloads.
+    pub fn new(capacity: usize) -> Self {
+        SentPayloads {
+            capacity,
+            keys: Mutex::new(RecentKeys::default()),
+        }
+    }
+
+    /// Whether the payload with the given idempotency key was accepted recently.
+    pub fn contains(&self, key: &str) -> bool {
+        self.keys.lock().unwrap().set.contains(key)
+    }
+
+    /// Records that the payload with the given idempotency key was accepted.
+    pub fn insert(&self, key: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut keys = self.keys.lock().unwrap();
+        if keys.set.insert(key.clone()) {
+            keys.order.push_back(key);
+            if keys.order.len() > self.capacity {
+                if let Some(oldest) = keys.order.pop_front() {
+                    keys.set.remove(&oldest);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idempotency_key() {
+        let key = idempotency_key(b"payload");
+        assert_eq!(32, key.len());
+        assert_eq!(key, idempotency_key(b"payload"));
+        assert_ne!(key, idempotency_key(b"payload2"));
+        assert_ne!(idempotency_key(b""), idempotency_key(b"\0"));
+        // the reference values of FNV-1a, the keys must not change between releases
+        assert_eq!("6c62272e07bb014262b821756295c58d", idempotency_key(b""));
+        assert_eq!("d228cb696f1a8caf78912b704e4a8964", idempotency_key(b"a"));
+    }
+
+    #[test]
+    fn test_sent_payloads() {
+        let sent = SentPayloads::new(2);
+        sent.insert("a".to_string());
+        sent.insert("b".to_string());
+        sent.insert("a".to_string());
+        assert!(sent.contains("a"));
+        assert!(sent.contains("b"));
+
+        sent.insert("c".to_string());
+        assert!(!sent.contains("a"));
+        assert!(sent.contains("b"));
+        assert!(sent.contains("c"));
+
+        let disabled = SentPayloads::new(0);
+        disabled.insert("a".to_string());
+        assert!(!disabled.contains("a"));
+    }
+}
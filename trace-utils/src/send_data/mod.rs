@@ -1,10 +1,14 @@
 // Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
 // SPDX-License-Identifier: Apache-2.0
 
+pub mod idempotency;
 pub mod retry_strategy;
 pub mod send_data_result;
+pub mod timeouts;
 
+pub use crate::send_data::idempotency::SentPayloads;
 pub use crate::send_data::retry_strategy::{RetryBackoffType, RetryStrategy};
+pub use crate::send_data::timeouts::SendTimeouts;
 
 use crate::trace_utils::{SendDataResult, TracerHeaderTags};
 use crate::tracer_payload::TracerPayloadCollection;
@@ -17,10 +21,13 @@ use futures::StreamExt;
 use hyper::header::HeaderValue;
 use hyper::{Body, Client, HeaderMap, Method, Response};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
 
 const DD_API_KEY: &str = "DD-API-KEY";
 
 const HEADER_DD_TRACE_COUNT: &str = "X-Datadog-Trace-Count";
+const HEADER_DD_IDEMPOTENCY_KEY: &str = "X-Datadog-Idempotency-Key";
 
 const HEADER_HTTP_CTYPE: &str = "Content-Type";
 const HEADER_CTYPE_MSGPACK: &str = "application/msgpack";
@@ -61,12 +68,14 @@ pub(crate) enum RequestResult {
     NetworkError((Attempts, ChunksDropped)),
     /// Treats errors coming from building the request
     BuildError((Attempts, ChunksDropped)),
+    /// The payload was already accepted, it was not sent again.
+    Duplicate(ChunksSent),
 }
 
 #[derive(Debug, Clone)]
 /// `SendData` is a structure that holds the data to be sent to a target endpoint.
 /// It includes the payloads to be sent, the size of the data, the target endpoint,
-/// headers for the request, and a retry strategy for sending the data.
+/// headers for the request, and a retry strategy and timeouts for sending the data.
 ///
 /// # Example
 ///
@@ -105,7 +114,10 @@ pub struct SendData {
     pub(crate) size: usize, // have a rough size estimate to force flushing if it's large
     target: Endpoint,
     headers: HashMap<&'static str, String>,
+    pub(crate) agent_payload_tags: HashMap<String, String>,
     retry_strategy: RetryStrategy,
+    timeouts: SendTimeouts,
+    sent_payloads: Option<Arc<SentPayloads>>,
 }
 
 impl SendData {
@@ -138,7 +150,10 @@ impl SendData {
             size,
             target: target.clone(),
             headers,
+            agent_payload_tags: HashMap::new(),
             retry_strategy: RetryStrategy::default(),
+            timeouts: SendTimeouts::default(),
+            sent_payloads: None,
         }
     }
 
@@ -187,6 +202,36 @@ impl SendData {
         self.retry_strategy = retry_strategy;
     }
 
+    /// Overrides the default SendTimeouts with user-defined values.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeouts`: The new timeouts to be used.
+    pub fn set_timeouts(&mut self, timeouts: SendTimeouts) {
+        self.timeouts = timeouts;
+    }
+
+    /// Sets the tags of the `AgentPayload` wrapping the tracer payloads. They are only sent with
+    /// protobuf (agentless) payloads, as the agent adds its own host tags otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `tags`: The tags, like the host tags of the tracer.
+    pub fn set_agent_payload_tags(&mut self, tags: HashMap<String, String>) {
+        self.agent_payload_tags = tags;
+    }
+
+    /// Skips the payloads which were already accepted, according to their idempotency key. The
+    /// accepted payloads are recorded in `sent_payloads`, which is meant to be shared by all the
+    /// `SendData` of an uploader, so that data submitted again is not sent twice.
+    ///
+    /// # Arguments
+    ///
+    /// * `sent_payloads`: The payloads recently accepted by the intake.
+    pub fn set_sent_payloads(&mut self, sent_payloads: Arc<SentPayloads>) {
+        self.sent_payloads = Some(sent_payloads);
+    }
+
     /// Sends the data to the target endpoint.
     ///
     /// # Returns
@@ -210,11 +255,20 @@ impl SendData {
             Err(_) => return Err(RequestError::Build),
         };
 
-        match Client::builder()
-            .build(connector::Connector::default())
-            .request(req)
-            .await
-        {
+        let connector = match self.timeouts.connect {
+            Some(timeout) => connector::Connector::with_connect_timeout(false, timeout),
+            None => connector::Connector::default(),
+        };
+        let response = Client::builder().build(connector).request(req);
+        let response = match self.timeouts.request {
+            Some(timeout) => match tokio::time::timeout(timeout, response).await {
+                Ok(response) => response,
+                Err(_) => return Err(RequestError::Timeout),
+            },
+            None => response.await,
+        };
+
+        match response {
             Ok(resp) => Ok(resp),
             Err(e) => {
                 if e.is_timeout() {
@@ -230,23 +284,63 @@ impl SendData {
         self.target.api_key.is_some()
     }
 
+    // Sends the payload with retries, giving up once the deadline is reached.
+    async fn send_payload(
+        &self,
+        content_type: &'static str,
+        payload: Vec<u8>,
+        payload_chunks: u64,
+        // For payload specific headers that need to be added to the request like trace count.
+        additional_payload_headers: Option<HashMap<&'static str, String>>,
+    ) -> RequestResult {
+        let request_attempt = AtomicU32::new(0);
+        let send = self.send_payload_with_retries(
+            content_type,
+            payload,
+            payload_chunks,
+            additional_payload_headers,
+            &request_attempt,
+        );
+        match self.timeouts.deadline {
+            Some(deadline) => match tokio::time::timeout(deadline, send).await {
+                Ok(result) => result,
+                Err(_) => RequestResult::TimeoutError((
+                    request_attempt.load(Ordering::Relaxed),
+                    payload_chunks,
+                )),
+            },
+            None => send.await,
+        }
+    }
+
     // This function wraps send_data with a retry strategy and the building of the request.
     // Hyper doesn't allow you to send a ref to a request, and you can't clone it. So we have to
     // build a new one for every send attempt. Being of type Bytes, the payload.clone() is not doing
     // a deep clone.
-    async fn send_payload(
+    async fn send_payload_with_retries(
         &self,
         content_type: &'static str,
         payload: Vec<u8>,
         payload_chunks: u64,
-        // For payload specific headers that need to be added to the request like trace count.
         additional_payload_headers: Option<HashMap<&'static str, String>>,
+        attempts: &AtomicU32,
     ) -> RequestResult {
         let mut request_attempt = 0;
+        // The same key for all the attempts, the intake may have received the payload even though
+        // an attempt failed.
+        let idempotency_key = idempotency::idempotency_key(&payload);
+        if let Some(sent_payloads) = &self.sent_payloads {
+            if sent_payloads.contains(&idempotency_key) {
+                return RequestResult::Duplicate(payload_chunks);
+            }
+        }
         let payload = Bytes::from(payload);
 
         let mut headers = HeaderMap::new();
         headers.insert(HEADER_HTTP_CTYPE, HeaderValue::from_static(content_type));
+        if let Ok(value) = HeaderValue::from_str(&idempotency_key) {
+            headers.insert(HEADER_DD_IDEMPOTENCY_KEY, value);
+        }
 
         if let Some(additional_payload_headers) = &additional_payload_headers {
             for (key, value) in additional_payload_headers {
@@ -256,6 +350,7 @@ impl SendData {
 
         loop {
             request_attempt += 1;
+            attempts.store(request_attempt, Ordering::Relaxed);
             let mut req = self.create_request_builder();
             req.headers_mut()
                 .expect("HttpRequestBuilder unable to get headers for request")
@@ -278,6 +373,12 @@ impl SendData {
                             self.retry_strategy.delay(request_attempt).await;
                             continue;
                         }
+                        RequestResult::Success(_) => {
+                            if let Some(sent_payloads) = &self.sent_payloads {
+                                sent_payloads.insert(idempotency_key);
+                            }
+                            return request_result;
+                        }
                         _ => return request_result,
                     }
                 }
@@ -329,7 +430,7 @@ impl SendData {
             .uri(self.target.url.clone())
             .header(
                 hyper::header::USER_AGENT,
-                concat!("Tracer/", env!("CARGO_PKG_VERSION")),
+                ddcommon::user_agent::user_agent(concat!("Tracer/", env!("CARGO_PKG_VERSION"))),
             )
             .method(Method::POST);
 
@@ -337,7 +438,7 @@ impl SendData {
             req = req.header(*key, value);
         }
 
-        req
+        self.target.apply_headers(req)
     }
 
     async fn send_with_protobuf(&self) -> SendDataResult {
@@ -346,7 +447,8 @@ impl SendData {
 
         match &self.tracer_payloads {
             TracerPayloadCollection::V07(payloads) => {
-                let agent_payload = construct_agent_payload(payloads.to_vec());
+                let agent_payload =
+                    construct_agent_payload(payloads.to_vec(), self.agent_payload_tags.clone());
                 let serialized_trace_payload = match serialize_proto_payload(&agent_payload)
                     .context("Failed to serialize trace agent payload, dropping traces")
                 {
@@ -411,8 +513,11 @@ impl SendData {
         loop {
             match futures.next().await {
                 Some(response) => {
+                    // Duplicates leave the last result alone, which is an error until a request
+                    // was made.
+                    let sent = !matches!(response, RequestResult::Duplicate(_));
                     result.update(response).await;
-                    if result.last_result.is_err() {
+                    if sent && result.last_result.is_err() {
                         return result;
                     }
                 }
@@ -422,14 +527,17 @@ impl SendData {
     }
 }
 
-fn construct_agent_payload(tracer_payloads: Vec<TracerPayload>) -> AgentPayload {
+fn construct_agent_payload(
+    tracer_payloads: Vec<TracerPayload>,
+    tags: HashMap<String, String>,
+) -> AgentPayload {
     AgentPayload {
         host_name: "".to_string(),
         env: "".to_string(),
         agent_version: "".to_string(),
         error_tps: 60.0,
         target_tps: 60.0,
-        tags: HashMap::new(),
+        tags,
         tracer_payloads,
         rare_sampler_enabled: false,
     }
@@ -500,7 +608,7 @@ mod tests {
     fn compute_payload_len(collection: &TracerPayloadCollection) -> usize {
         match collection {
             TracerPayloadCollection::V07(payloads) => {
-                let agent_payload = construct_agent_payload(payloads.to_vec());
+                let agent_payload = construct_agent_payload(payloads.to_vec(), HashMap::new());
                 let serialized_trace_payload = serialize_proto_payload(&agent_payload).unwrap();
                 serialized_trace_payload.len()
             }
@@ -535,6 +643,7 @@ mod tests {
             &Endpoint {
                 api_key: Some(std::borrow::Cow::Borrowed("TEST-KEY")),
                 url: "/foo/bar?baz".parse::<hyper::Uri>().unwrap(),
+                ..Default::default()
             },
         );
 
@@ -558,6 +667,7 @@ mod tests {
             &Endpoint {
                 api_key: None,
                 url: "/foo/bar?baz".parse::<hyper::Uri>().unwrap(),
+                ..Default::default()
             },
         );
 
@@ -594,6 +704,7 @@ mod tests {
             &Endpoint {
                 api_key: Some(std::borrow::Cow::Borrowed("TEST-KEY")),
                 url: server.url("/").parse::<hyper::Uri>().unwrap(),
+                ..Default::default()
             },
         );
 
@@ -602,6 +713,7 @@ mod tests {
 
         mock.assert_async().await;
 
+        assert_eq!(res.last_status, Some(202));
         assert_eq!(res.last_result.unwrap().status(), 202);
         assert_eq!(res.errors_timeout, 0);
         assert_eq!(res.errors_network, 0);
@@ -636,6 +748,7 @@ mod tests {
             &Endpoint {
                 api_key: Some(std::borrow::Cow::Borrowed("TEST-KEY")),
                 url: server.url("/").parse::<hyper::Uri>().unwrap(),
+                ..Default::default()
             },
         );
 
@@ -689,6 +802,7 @@ mod tests {
             &Endpoint {
                 api_key: None,
                 url: server.url("/").parse::<hyper::Uri>().unwrap(),
+                ..Default::default()
             },
         );
 
@@ -742,6 +856,7 @@ mod tests {
             &Endpoint {
                 api_key: None,
                 url: server.url("/").parse::<hyper::Uri>().unwrap(),
+                ..Default::default()
             },
         );
 
@@ -784,6 +899,7 @@ mod tests {
             &Endpoint {
                 api_key: None,
                 url: server.url("/").parse::<hyper::Uri>().unwrap(),
+                ..Default::default()
             },
         );
 
@@ -824,6 +940,7 @@ mod tests {
             &Endpoint {
                 api_key: None,
                 url: server.url("/").parse::<hyper::Uri>().unwrap(),
+                ..Default::default()
             },
         );
 
@@ -832,6 +949,7 @@ mod tests {
         mock.assert_hits_async(5).await;
 
         assert!(res.last_result.is_err());
+        assert_eq!(res.last_status, Some(500));
         assert_eq!(res.errors_timeout, 0);
         assert_eq!(res.errors_network, 0);
         assert_eq!(res.errors_status_code, 1);
@@ -853,6 +971,7 @@ mod tests {
             &Endpoint {
                 api_key: None,
                 url: "http://127.0.0.1:4321/".parse::<hyper::Uri>().unwrap(),
+                ..Default::default()
             },
         );
 
@@ -895,6 +1014,7 @@ mod tests {
         let target_endpoint = Endpoint {
             url: server.url("").to_owned().parse().unwrap(),
             api_key: Some("test-key".into()),
+            ..Default::default()
         };
 
         let size = 512;
@@ -934,6 +1054,7 @@ mod tests {
         let target_endpoint = Endpoint {
             url: server.url("").to_owned().parse().unwrap(),
             api_key: Some("test-key".into()),
+            ..Default::default()
         };
 
         let size = 512;
@@ -978,6 +1099,7 @@ mod tests {
         let target_endpoint = Endpoint {
             url: server.url("").to_owned().parse().unwrap(),
             api_key: None,
+            ..Default::default()
         };
 
         let size = 512;
@@ -1012,6 +1134,7 @@ mod tests {
         let target_endpoint = Endpoint {
             url: server.url("").to_owned().parse().unwrap(),
             api_key: Some("test-key".into()),
+            ..Default::default()
         };
 
         let size = 512;
@@ -1056,6 +1179,7 @@ mod tests {
         let target_endpoint = Endpoint {
             url: server.url("").to_owned().parse().unwrap(),
             api_key: Some("test-key".into()),
+            ..Default::default()
         };
 
         let size = 512;
@@ -1072,4 +1196,129 @@ mod tests {
             "Expected only one request attempt"
         );
     }
+
+    #[cfg_attr(miri, ignore)]
+    #[tokio::test]
+    async fn test_request_timeout() {
+        let server = MockServer::start();
+        let _mock = server
+            .mock_async(|_when, then| {
+                then.status(202)
+                    .delay(std::time::Duration::from_millis(500))
+                    .body(r#"{"status":"Ok"}"#);
+            })
+            .await;
+
+        let target_endpoint = Endpoint {
+            url: server.url("").to_owned().parse().unwrap(),
+            api_key: Some("test-key".into()),
+            ..Default::default()
+        };
+
+        let mut send_data = create_send_data(512, &target_endpoint);
+        send_data.set_retry_strategy(RetryStrategy::new(2, 10, RetryBackoffType::Constant, None));
+        send_data.set_timeouts(SendTimeouts {
+            request: Some(std::time::Duration::from_millis(50)),
+            ..Default::default()
+        });
+
+        let res = send_data.send().await;
+        assert_eq!(res.errors_timeout, 1);
+        assert_eq!(res.requests_count, 2);
+    }
+
+    #[cfg_attr(miri, ignore)]
+    #[tokio::test]
+    async fn test_deadline_includes_retries() {
+        let server = MockServer::start();
+        let _mock = server
+            .mock_async(|_when, then| {
+                then.status(503).body(r#"{"status":"error"}"#);
+            })
+            .await;
+
+        let target_endpoint = Endpoint {
+            url: server.url("").to_owned().parse().unwrap(),
+            api_key: Some("test-key".into()),
+            ..Default::default()
+        };
+
+        let mut send_data = create_send_data(512, &target_endpoint);
+        send_data.set_retry_strategy(RetryStrategy::new(
+            10,
+            100,
+            RetryBackoffType::Constant,
+            None,
+        ));
+        send_data.set_timeouts(SendTimeouts {
+            deadline: Some(std::time::Duration::from_millis(250)),
+            ..Default::default()
+        });
+
+        let res = send_data.send().await;
+        assert_eq!(res.errors_timeout, 1);
+        assert!(res.requests_count < 10);
+    }
+
+    #[cfg_attr(miri, ignore)]
+    #[tokio::test]
+    async fn test_skips_sent_payloads() {
+        let server = MockServer::start();
+        let mock = server
+            .mock_async(|when, then| {
+                when.header_exists(HEADER_DD_IDEMPOTENCY_KEY);
+                then.status(202).body(r#"{"status":"Ok"}"#);
+            })
+            .await;
+
+        let target_endpoint = Endpoint {
+            url: server.url("").to_owned().parse().unwrap(),
+            api_key: Some("test-key".into()),
+            ..Default::default()
+        };
+
+        let sent_payloads = Arc::new(SentPayloads::default());
+        let mut send_data = create_send_data(512, &target_endpoint);
+        send_data.set_sent_payloads(sent_payloads.clone());
+
+        let res = send_data.send().await;
+        assert!(res.last_result.is_ok());
+        assert_eq!(res.requests_count, 1);
+        mock.assert_hits_async(1).await;
+
+        // The same payload, submitted again by another SendData of the uploader.
+        let mut send_data = create_send_data(512, &target_endpoint);
+        send_data.set_sent_payloads(sent_payloads);
+
+        let res = send_data.send().await;
+        assert!(res.last_result.is_ok());
+        assert_eq!(res.requests_count, 0);
+        mock.assert_hits_async(1).await;
+    }
+
+    #[cfg_attr(miri, ignore)]
+    #[tokio::test]
+    async fn test_endpoint_headers() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.header("authorization", "Bearer token")
+                    .header("x-proxy-tenant", "tenant");
+                then.status(202).body(r#"{"status":"Ok"}"#);
+            })
+            .await;
+
+        let mut target_endpoint = Endpoint {
+            url: server.url("").to_owned().parse().unwrap(),
+            ..Default::default()
+        };
+        target_endpoint.set_bearer_token("token").unwrap();
+        target_endpoint
+            .set_header("X-Proxy-Tenant", "tenant")
+            .unwrap();
+
+        let res = create_send_data(512, &target_endpoint).send().await;
+        assert!(res.last_result.is_ok());
+        mock.assert_async().await;
+    }
 }
@@ -10,6 +10,8 @@ use std::collections::HashMap;
 pub struct SendDataResult {
     // Keeps track of the last request result.
     pub last_result: anyhow::Result<Response<Body>>,
+    // The HTTP status of the last response received, if any.
+    pub last_status: Option<u16>,
     // Count metric for 'trace_api.requests'.
     pub requests_count: u64,
     // Count metric for 'trace_api.responses'. Each key maps  a different HTTP status code.
@@ -26,12 +28,18 @@ pub struct SendDataResult {
     pub chunks_sent: u64,
     // Count metric for 'trace_chunks_dropped'
     pub chunks_dropped: u64,
+    // Payloads which were not sent, as they were already accepted. They leave `last_result` and
+    // `last_status` alone, no request was made for them.
+    pub payloads_deduplicated: u64,
+    // Chunks which were not sent, as they were already accepted.
+    pub chunks_deduplicated: u64,
 }
 
 impl Default for SendDataResult {
     fn default() -> Self {
         SendDataResult {
             last_result: Err(anyhow!("No requests sent")),
+            last_status: None,
             requests_count: 0,
             responses_count_per_code: Default::default(),
             errors_timeout: 0,
@@ -40,6 +48,8 @@ impl Default for SendDataResult {
             bytes_sent: 0,
             chunks_sent: 0,
             chunks_dropped: 0,
+            payloads_deduplicated: 0,
+            chunks_deduplicated: 0,
         }
     }
 }
@@ -60,11 +70,13 @@ impl SendDataResult {
                     .or_default() += 1;
                 self.bytes_sent += bytes;
                 self.chunks_sent += chunks;
+                self.last_status = Some(response.status().as_u16());
                 self.last_result = Ok(response);
                 self.requests_count += u64::from(attempts);
             }
             RequestResult::Error((response, attempts, chunks)) => {
                 let status_code = response.status().as_u16();
+                self.last_status = Some(status_code);
                 self.errors_status_code += 1;
                 *self
                     .responses_count_per_code
@@ -96,6 +108,10 @@ impl SendDataResult {
                 self.chunks_dropped += chunks;
                 self.requests_count += u64::from(attempts);
             }
+            RequestResult::Duplicate(chunks) => {
+                self.payloads_deduplicated += 1;
+                self.chunks_deduplicated += chunks;
+            }
         }
     }
 
@@ -0,0 +1,20 @@
+// Copyright 2024-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use std::time::Duration;
+
+/// Struct representing the time limits of sending data.
+///
+/// `None` disables the corresponding limit. The default sets no limit at all, callers opt in with
+/// [`SendData::set_timeouts`].
+///
+/// [`SendData::set_timeouts`]: crate::send_data::SendData::set_timeouts
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SendTimeouts {
+    /// The maximum time to establish a TCP connection.
+    pub connect: Option<Duration>,
+    /// The maximum time of a single attempt, until the response headers are received.
+    pub request: Option<Duration>,
+    /// The maximum time of sending a payload, retries and the delays between them included.
+    pub deadline: Option<Duration>,
+}
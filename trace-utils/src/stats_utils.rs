@@ -58,8 +58,8 @@ pub async fn send_stats_payload(
         .uri(target.url.clone())
         .header("Content-Type", "application/msgpack")
         .header("Content-Encoding", "gzip")
-        .header("DD-API-KEY", api_key)
-        .body(Body::from(data.clone()))?;
+        .header("DD-API-KEY", api_key);
+    let req = target.apply_headers(req).body(Body::from(data.clone()))?;
 
     let https = HttpsConnectorBuilder::new()
         .with_native_roots()
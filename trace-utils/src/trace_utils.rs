@@ -23,6 +23,12 @@ use ddcommon::azure_app_services;
 const TOP_LEVEL_KEY: &str = "_top_level";
 /// Span metric the tracer sets to denote a top level span
 const TRACER_TOP_LEVEL_KEY: &str = "_dd.top_level";
+/// Span metric marking a span stats are computed for, even if it's not top level
+const MEASURED_KEY: &str = "_dd.measured";
+/// Span metric set on partial snapshots of long running spans, which are not counted in stats
+const PARTIAL_VERSION_KEY: &str = "_dd.partial_version";
+/// Span meta holding the status code of an HTTP request
+const HTTP_STATUS_CODE_KEY: &str = "http.status_code";
 
 const MAX_PAYLOAD_SIZE: usize = 50 * 1024 * 1024;
 const MAX_STRING_DICT_SIZE: u32 = 25_000_000;
@@ -300,7 +306,13 @@ pub(crate) fn cmp_send_data_payloads(a: &pb::TracerPayload, b: &pb::TracerPayloa
         .then(a.app_version.cmp(&b.app_version))
 }
 
-pub fn coalesce_send_data(mut data: Vec<SendData>) -> Vec<SendData> {
+pub fn coalesce_send_data(data: Vec<SendData>) -> Vec<SendData> {
+    coalesce_send_data_with_max_size(data, MAX_PAYLOAD_SIZE / 2)
+}
+
+/// Like [`coalesce_send_data`], but payloads are only merged as long as their combined size stays
+/// below `max_size`, e.g. to adapt to an intake rejecting large payloads.
+pub fn coalesce_send_data_with_max_size(mut data: Vec<SendData>, max_size: usize) -> Vec<SendData> {
     // TODO trace payloads with identical data except for chunk could be merged?
 
     data.sort_unstable_by(|a, b| {
@@ -310,12 +322,12 @@ pub fn coalesce_send_data(mut data: Vec<SendData>) -> Vec<SendData> {
             .cmp(&b.get_target().url.to_string())
     });
     data.dedup_by(|a, b| {
-        if a.get_target().url == b.get_target().url {
+        if a.get_target().url == b.get_target().url && a.agent_payload_tags == b.agent_payload_tags
+        {
             // Size is only an approximation. In practice it won't vary much, but be safe here.
             // We also don't care about the exact maximum size, like two 25 MB or one 50 MB request
             // has similar results. The primary goal here is avoiding many small requests.
-            // TODO: maybe make the MAX_PAYLOAD_SIZE configurable?
-            if a.size + b.size < MAX_PAYLOAD_SIZE / 2 {
+            if a.size + b.size < max_size {
                 // Note: dedup_by drops a, and retains b.
                 b.tracer_payloads.append(&mut a.tracer_payloads);
                 b.size += a.size;
@@ -416,6 +428,48 @@ fn set_top_level_span(span: &mut Span, is_top_level: bool) {
     span.metrics.insert(TOP_LEVEL_KEY.to_string(), 1.0);
 }
 
+/// Marks the span as measured, so that stats are computed for it even if it's not top level.
+pub fn set_measured(span: &mut Span, measured: bool) {
+    if measured {
+        span.metrics.insert(MEASURED_KEY.to_string(), 1.0);
+    } else {
+        span.metrics.remove(MEASURED_KEY);
+    }
+}
+
+pub fn is_measured(span: &Span) -> bool {
+    span.metrics.get(MEASURED_KEY) == Some(&1.0)
+}
+
+/// Whether the span is top level, as computed by [`compute_top_level_span`] or by the tracer.
+pub fn is_top_level(span: &Span) -> bool {
+    span.metrics.get(TOP_LEVEL_KEY) == Some(&1.0)
+        || span.metrics.get(TRACER_TOP_LEVEL_KEY) == Some(&1.0)
+}
+
+/// Whether stats are computed for the span: it must be top level or measured, and not a partial
+/// snapshot of a span which is still running.
+pub fn is_stats_eligible(span: &Span) -> bool {
+    (is_top_level(span) || is_measured(span))
+        && !matches!(span.metrics.get(PARTIAL_VERSION_KEY), Some(version) if *version >= 0.0)
+}
+
+/// Counts the hits of the stats eligible spans of a trace, by service, name and resource. Top
+/// level spans must have been computed beforehand, see [`compute_top_level_span`].
+pub fn compute_span_hits(trace: &[Span]) -> HashMap<(&str, &str, &str), u64> {
+    let mut hits = HashMap::new();
+    for span in trace.iter().filter(|span| is_stats_eligible(span)) {
+        *hits
+            .entry((
+                span.service.as_str(),
+                span.name.as_str(),
+                span.resource.as_str(),
+            ))
+            .or_insert(0) += 1;
+    }
+    hits
+}
+
 pub fn set_serverless_root_span_tags(
     span: &mut Span,
     function_name: Option<String>,
@@ -505,6 +559,91 @@ pub fn enrich_span_with_azure_metadata(span: &mut Span, mini_agent_version: &str
     }
 }
 
+/// Error and status summary of a trace, see [`compute_trace_rollup`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TraceRollup<'a> {
+    /// Number of spans flagged as errors.
+    pub error_count: usize,
+    /// The `http.status_code` of the top level spans, in the order of the spans.
+    pub top_level_status_codes: Vec<&'a str>,
+}
+
+impl TraceRollup<'_> {
+    pub fn has_error(&self) -> bool {
+        self.error_count > 0
+    }
+}
+
+/// Summarizes the errors and the HTTP statuses of a trace. Top level spans must have been
+/// computed beforehand, see [`compute_top_level_span`].
+pub fn compute_trace_rollup(trace: &[Span]) -> TraceRollup {
+    TraceRollup {
+        error_count: trace.iter().filter(|span| span.error != 0).count(),
+        top_level_status_codes: trace
+            .iter()
+            .filter(|span| is_top_level(span))
+            .filter_map(|span| span.meta.get(HTTP_STATUS_CODE_KEY))
+            .map(String::as_str)
+            .collect(),
+    }
+}
+
+/// Whether the sampling decision of a trace is to drop it. Traces without a decision are kept.
+pub fn is_sampled_out(priority: i32) -> bool {
+    priority != normalizer::SamplerPriority::None as i32
+        && priority <= normalizer::SamplerPriority::AutoDrop as i32
+}
+
+/// Marks the chunk as dropped when its sampling priority rejects it, so that the backend only
+/// counts it in stats. Like in the agent, traces with errors are kept regardless of the priority.
+pub fn set_chunk_dropped_trace(chunk: &mut TraceChunk) {
+    chunk.dropped_trace = is_sampled_out(chunk.priority) && !has_error(&chunk.spans);
+}
+
+/// Whether any span of the trace is flagged as an error.
+pub fn has_error(trace: &[Span]) -> bool {
+    trace.iter().any(|span| span.error != 0)
+}
+
+/// Returns the local root span id and the endpoint of a trace chunk, as the backend derives the
+/// `trace endpoint` of profiles: the resource of the top level web span, preferring the root span,
+/// see [`trace_endpoint_from_resource`]. The resource is taken as is, so the spans must have been
+/// obfuscated already, otherwise see `datadog_trace_obfuscation::obfuscate::get_trace_endpoint`.
+///
+/// The result is meant for `Profile::add_endpoint`, which is keyed by the local root span id, so
+/// that profiles and traces agree on the endpoint names.
+pub fn get_trace_endpoint(trace: &[Span]) -> Option<(u64, &str)> {
+    let (local_root_span_id, span) = get_trace_endpoint_span(trace)?;
+    Some((
+        local_root_span_id,
+        trace_endpoint_from_resource(&span.resource)?,
+    ))
+}
+
+/// Returns the local root span id of a trace chunk and the span whose resource is the endpoint of
+/// the trace, see [`get_trace_endpoint`].
+pub fn get_trace_endpoint_span(trace: &[Span]) -> Option<(u64, &Span)> {
+    let is_web_entry =
+        |span: &Span| span.r#type == "web" && (span.parent_id == 0 || is_top_level(span));
+    let root = &trace[get_root_span_index(trace).ok()?];
+    let span = Some(root)
+        .filter(|span| is_web_entry(span))
+        .or_else(|| trace.iter().find(|span| is_web_entry(span)))?;
+    Some((root.span_id, span))
+}
+
+/// Returns the endpoint named by the resource of a span: the query string of resources built from
+/// the URL is stripped, like the obfuscation does for `http.url`, so that requests to an endpoint
+/// aren't told apart by their parameters.
+pub fn trace_endpoint_from_resource(resource: &str) -> Option<&str> {
+    let endpoint = match resource.split_once('?') {
+        Some((path, _query)) => path,
+        None => resource,
+    }
+    .trim_end();
+    (!endpoint.is_empty()).then_some(endpoint)
+}
+
 /// Used to populate root_span_tags fields if they exist in the root span's meta tags
 macro_rules! parse_root_span_tags {
     (
@@ -902,4 +1041,126 @@ mod tests {
         );
         assert_eq!(span.r#type, "serverless".to_string())
     }
+
+    #[test]
+    fn test_get_trace_endpoint() {
+        let mut root = create_test_span(1234, 1, 0, 1, false);
+        let mut web = create_test_span(1234, 2, 1, 2, false);
+        web.r#type = "web".to_string();
+        web.resource = "GET /users?id=1".to_string();
+        web.metrics.insert("_dd.top_level".to_string(), 1.0);
+        let mut trace = vec![root.clone(), web.clone()];
+        // keyed by the local root span, like the samples of the profile
+        assert_eq!(
+            Some((1, "GET /users")),
+            trace_utils::get_trace_endpoint(&trace)
+        );
+
+        // the root span wins, even when it's not first
+        root.r#type = "web".to_string();
+        root.resource = "POST /checkout".to_string();
+        trace = vec![web.clone(), root];
+        assert_eq!(
+            Some((1, "POST /checkout")),
+            trace_utils::get_trace_endpoint(&trace)
+        );
+
+        // nested web spans of the same service are not entry points
+        web.metrics.clear();
+        assert_eq!(None, trace_utils::get_trace_endpoint(&[web.clone()]));
+        web.resource = "?".to_string();
+        web.parent_id = 0;
+        assert_eq!(None, trace_utils::get_trace_endpoint(&[web]));
+    }
+
+    #[test]
+    fn test_compute_span_hits() {
+        let mut root = create_test_span(1234, 1, 0, 1, false);
+        let mut measured = create_test_span(1234, 2, 1, 2, false);
+        let unmeasured = create_test_span(1234, 3, 1, 3, false);
+        let mut partial = create_test_span(1234, 4, 1, 4, false);
+        measured.resource = "measured-resource".to_string();
+        trace_utils::set_measured(&mut measured, true);
+        trace_utils::set_measured(&mut partial, true);
+        partial
+            .metrics
+            .insert("_dd.partial_version".to_string(), 1.0);
+        root.metrics.insert("_dd.top_level".to_string(), 1.0);
+
+        assert!(trace_utils::is_measured(&measured));
+        assert!(!trace_utils::is_stats_eligible(&unmeasured));
+        assert!(!trace_utils::is_stats_eligible(&partial));
+
+        let trace = vec![root, measured.clone(), measured, unmeasured, partial];
+        let hits = trace_utils::compute_span_hits(&trace);
+        assert_eq!(
+            hits,
+            HashMap::from([
+                (("test-service", "test_name", "test-resource"), 1),
+                (("test-service", "test_name", "measured-resource"), 2),
+            ])
+        );
+
+        let mut span = trace[1].clone();
+        trace_utils::set_measured(&mut span, false);
+        assert!(!trace_utils::is_measured(&span));
+    }
+
+    #[test]
+    fn test_compute_trace_rollup() {
+        let mut root = create_test_span(1234, 1, 0, 1, true);
+        let mut child = create_test_span(1234, 2, 1, 2, false);
+        let mut downstream = create_test_span(1234, 3, 2, 3, true);
+        root.meta
+            .insert("http.status_code".to_string(), "500".to_string());
+        child
+            .meta
+            .insert("http.status_code".to_string(), "200".to_string());
+        downstream
+            .meta
+            .insert("http.status_code".to_string(), "503".to_string());
+        downstream.error = 1;
+
+        let mut trace = vec![root, child, downstream];
+        let rollup = trace_utils::compute_trace_rollup(&trace);
+        assert!(rollup.has_error());
+        assert_eq!(1, rollup.error_count);
+        assert_eq!(vec!["500", "503"], rollup.top_level_status_codes);
+
+        trace[2].error = 0;
+        assert_eq!(
+            trace_utils::TraceRollup {
+                error_count: 0,
+                top_level_status_codes: vec!["500", "503"],
+            },
+            trace_utils::compute_trace_rollup(&trace)
+        );
+    }
+
+    #[test]
+    fn test_set_chunk_dropped_trace() {
+        let mut chunk = TraceChunk {
+            priority: 0,
+            origin: "".to_string(),
+            spans: vec![create_test_span(1234, 1, 0, 1, true)],
+            tags: Default::default(),
+            dropped_trace: false,
+        };
+        for (priority, dropped) in [(-1, true), (0, true), (1, false), (2, false)] {
+            chunk.priority = priority;
+            trace_utils::set_chunk_dropped_trace(&mut chunk);
+            assert_eq!(dropped, chunk.dropped_trace, "priority {priority}");
+        }
+
+        // no sampling decision
+        chunk.priority = i8::MIN as i32;
+        trace_utils::set_chunk_dropped_trace(&mut chunk);
+        assert!(!chunk.dropped_trace);
+
+        // errors are kept
+        chunk.priority = 0;
+        chunk.spans[0].error = 1;
+        trace_utils::set_chunk_dropped_trace(&mut chunk);
+        assert!(!chunk.dropped_trace);
+    }
 }